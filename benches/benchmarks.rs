@@ -294,7 +294,7 @@ fn bench_select_where_like(c: &mut Criterion) {
             let result = db
                 .query("SELECT * FROM t WHERE name LIKE 'name_5%'")
                 .unwrap();
-            assert!(result.len() > 0);
+            assert!(!result.is_empty());
         });
     });
 }
@@ -581,7 +581,7 @@ fn bench_join_with_where(c: &mut Criterion) {
                     "SELECT c.name, o.amount FROM customers c JOIN orders o ON c.id = o.customer_id WHERE o.amount > 250.0",
                 )
                 .unwrap();
-            assert!(result.len() > 0);
+            assert!(!result.is_empty());
         });
     });
 }
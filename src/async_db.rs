@@ -0,0 +1,93 @@
+//! Async wrapper around [`Database`] for use inside Tokio runtimes.
+//!
+//! Available with the `tokio` Cargo feature. The database state is guarded
+//! by a [`tokio::sync::Mutex`], so waiting for the lock never blocks an
+//! executor thread, and each statement runs on Tokio's blocking pool via
+//! [`tokio::task::spawn_blocking`] so page I/O stays off the async workers.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::error::{HorizonError, Result};
+use crate::{sql, Database, DatabaseInner, QueryResult};
+
+/// An async handle to a Horizon database.
+///
+/// Cloning is cheap; all clones share the same underlying database.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    path: PathBuf,
+    inner: Arc<Mutex<DatabaseInner>>,
+}
+
+impl AsyncDatabase {
+    /// Open or create a database at the given path.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let db = run_blocking(move || Database::open(path)).await?;
+        Self::from_database(db)
+    }
+
+    /// Wrap an already-open [`Database`], which must be its database's
+    /// only connection. A transaction open on it stays open.
+    pub fn from_database(mut db: Database) -> Result<Self> {
+        let path = db.path.clone();
+        let txn_manager = db.txn_manager.get_mut().map(std::mem::take).map_err(|_| {
+            HorizonError::Internal("lock poisoned".into())
+        })?;
+        let inner = Arc::clone(&db.inner);
+        drop(db);
+        let mut inner = Arc::try_unwrap(inner)
+            .map_err(|_| HorizonError::Busy("the database has other open connections".into()))?
+            .into_inner()
+            .map_err(|_| HorizonError::Internal("lock poisoned".into()))?;
+        inner.txn_manager = txn_manager;
+        Ok(AsyncDatabase {
+            path,
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Execute a SQL statement that doesn't return rows.
+    pub async fn execute(&self, sql_text: &str) -> Result<usize> {
+        let stmts = sql::parser::Parser::parse(sql_text)?;
+        if stmts.is_empty() {
+            return Ok(0);
+        }
+        let mut inner = self.inner.clone().lock_owned().await;
+        run_blocking(move || inner.execute_statements(stmts)).await
+    }
+
+    /// Execute a SQL query that returns rows.
+    pub async fn query(&self, sql_text: &str) -> Result<QueryResult> {
+        let stmts = sql::parser::Parser::parse(sql_text)?;
+        let mut inner = self.inner.clone().lock_owned().await;
+        run_blocking(move || inner.query_statements(stmts)).await
+    }
+
+    /// Get the file path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flush all writes to disk.
+    ///
+    /// Other clones of this handle remain usable afterwards.
+    pub async fn close(self) -> Result<()> {
+        let mut inner = self.inner.lock_owned().await;
+        run_blocking(move || inner.flush()).await
+    }
+}
+
+/// Run `f` on Tokio's blocking thread pool and wait for its result.
+async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| HorizonError::Internal(format!("blocking task failed: {}", e)))?
+}
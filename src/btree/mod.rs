@@ -6,7 +6,8 @@
 //!
 //! ## Page Layout
 //!
-//! Every B+Tree node occupies exactly one database page ([`PAGE_SIZE`] bytes).
+//! Every B+Tree node occupies exactly one database page, whose size is
+//! fixed per database (see [`crate::pager::PAGE_SIZE`]).
 //!
 //! **Header** (8 bytes):
 //!
@@ -29,10 +30,39 @@
 //! ### Leaf cell format
 //!
 //! `[key_size: u16 BE][key_data: ...][value_size: u32 BE][value_data: ...]`
+//!
+//! ### Overflow pages
+//!
+//! A value longer than a quarter of a page is not stored in its leaf cell.
+//! It is split across a chain of overflow pages, each holding the next page
+//! of the chain (u32 BE, 0 on the last page) followed by as many bytes of
+//! the value as fit. The cell then sets the top bit of `value_size`, whose
+//! other bits give the value's length, and holds the first page of the
+//! chain (u32 BE) in place of the value. Overflow pages are freed when the
+//! value is replaced or deleted.
+//!
+//! Keys are never moved to overflow pages. An insert whose key is longer
+//! than a quarter of a page is refused with an error, so that a split
+//! always leaves room on both halves.
+//!
+//! ## Deletion
+//!
+//! A delete that leaves a non-root leaf less than 40% full rebalances it
+//! with an adjacent leaf under the same parent: the two are merged if their
+//! entries fit on one page, and the separator between them is dropped from
+//! the parent; otherwise their entries are split evenly between them. A
+//! leaf left empty is unlinked from the leaf chain and its parent, as is a
+//! parent left without children. Internal pages are not merged, but a root
+//! with a single child takes over the child's contents, so the root page of
+//! a tree never changes.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::Arc;
 
 use crate::buffer::BufferPool;
-use crate::error::Result;
-use crate::pager::{PageId, PAGE_SIZE};
+use crate::error::{HorizonError, Result};
+use crate::pager::PageId;
 
 // ---------------------------------------------------------------------------
 // Constants
@@ -50,6 +80,17 @@ const HEADER_SIZE: usize = 8;
 /// Size of each cell pointer in the pointer array (u16 BE).
 const CELL_PTR_SIZE: usize = 2;
 
+/// A non-root leaf filled below this percentage of its page by a delete is
+/// rebalanced with a sibling.
+const MIN_FILL_PERCENT: usize = 40;
+
+/// Set in the `value_size` of a leaf cell whose value is kept in overflow
+/// pages.
+const OVERFLOW_FLAG: u32 = 0x8000_0000;
+
+/// Size of the header of an overflow page: the next page of the chain.
+const OVERFLOW_HEADER_SIZE: usize = 4;
+
 // ---------------------------------------------------------------------------
 // Public types
 // ---------------------------------------------------------------------------
@@ -61,14 +102,53 @@ pub struct BTreeEntry {
     pub value: Vec<u8>,
 }
 
+/// A position in the leaf chain of a [`BTree`], used to scan entries one at
+/// a time via [`BTree::next`] instead of collecting them all up front.
+///
+/// The cursor only records a leaf page and a slot within it, so it does not
+/// borrow the buffer pool between calls. It is invalidated by any write to
+/// the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BTreeCursor {
+    /// Current leaf page, or 0 once the scan is exhausted.
+    leaf: PageId,
+    /// Index of the next cell to return within `leaf`.
+    slot: u16,
+}
+
+/// The outcome of [`BTree::check_integrity`] or [`BTree::check_leaves`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Number of entries found in the leaves that could be read.
+    pub entries: u64,
+    /// One description per problem found, such as `"page 7: keys out of
+    /// order"`. Empty when the tree is sound.
+    pub errors: Vec<String>,
+}
+
+impl IntegrityReport {
+    /// Whether no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A comparison of two whole keys, for a tree whose keys are not in byte
+/// order. It must be a total order, and consider keys equal only when they
+/// have the same bytes.
+pub type KeyOrder = Arc<dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync>;
+
 /// A B+Tree rooted at a specific page.
 ///
 /// The tree stores arbitrary byte-string keys and values. Keys are compared
-/// lexicographically. All values live in leaf nodes, and the leaves are
-/// chained via `next_leaf` pointers so that range scans can be performed
-/// efficiently.
+/// lexicographically, unless the tree is given a [`KeyOrder`] with
+/// [`ordered_by`](Self::ordered_by). All values live in leaf nodes, and the
+/// leaves are chained via `next_leaf` pointers so that range scans can be
+/// performed efficiently.
 pub struct BTree {
     root_page: PageId,
+    /// The order of the keys, or `None` for byte order.
+    order: Option<KeyOrder>,
 }
 
 // ---------------------------------------------------------------------------
@@ -119,7 +199,7 @@ fn write_u32(buf: &mut [u8], off: usize, val: u32) {
 }
 
 // ---------------------------------------------------------------------------
-// Page-level accessors (operate on a `[u8; PAGE_SIZE]` buffer)
+// Page-level accessors (operate on a page-sized buffer)
 // ---------------------------------------------------------------------------
 
 fn page_type(page: &[u8]) -> u8 {
@@ -167,20 +247,17 @@ fn cell_area_start(count: u16) -> usize {
 }
 
 /// Find the lowest cell body offset currently stored (the "content area
-/// start"). Returns `PAGE_SIZE` if there are no cells.
+/// start"). Returns the page size if there are no cells.
 fn content_start(page: &[u8]) -> usize {
     let n = cell_count(page);
-    if n == 0 {
-        return PAGE_SIZE;
-    }
-    let mut min = PAGE_SIZE as u16;
+    let mut min = page.len();
     for i in 0..n {
-        let ptr = cell_ptr(page, i);
+        let ptr = cell_ptr(page, i) as usize;
         if ptr < min {
             min = ptr;
         }
     }
-    min as usize
+    min
 }
 
 // ---------------------------------------------------------------------------
@@ -195,6 +272,16 @@ fn read_internal_cell(page: &[u8], off: usize) -> (PageId, Vec<u8>) {
     (child, key)
 }
 
+/// The child of an internal page at `slot`, where slot `cell_count` is
+/// the rightmost child.
+fn child_at(page: &[u8], slot: u16) -> PageId {
+    if slot == cell_count(page) {
+        trailer(page)
+    } else {
+        read_internal_cell(page, cell_ptr(page, slot) as usize).0
+    }
+}
+
 /// Size in bytes of an internal cell with the given key.
 fn internal_cell_size(key: &[u8]) -> usize {
     4 /* child */ + 2 /* key_size */ + key.len()
@@ -204,19 +291,260 @@ fn internal_cell_size(key: &[u8]) -> usize {
 // Leaf cell helpers
 // ---------------------------------------------------------------------------
 
+/// The value field of a leaf cell.
+#[derive(Debug, Clone)]
+enum CellValue {
+    /// The value itself.
+    Inline(Vec<u8>),
+    /// A value of `len` bytes kept in the overflow chain starting at
+    /// `first_page`.
+    Overflow { first_page: PageId, len: u32 },
+}
+
+impl CellValue {
+    /// Store `value` for a leaf cell: inline if it takes at most a quarter
+    /// of a page, otherwise in a new chain of overflow pages.
+    fn store(pool: &mut BufferPool, value: &[u8]) -> Result<Self> {
+        if value.len() <= pool.page_size() / 4 {
+            return Ok(CellValue::Inline(value.to_vec()));
+        }
+        let len = u32::try_from(value.len())
+            .ok()
+            .filter(|len| len & OVERFLOW_FLAG == 0)
+            .ok_or_else(|| HorizonError::InvalidSql(format!("value of {} bytes is too large", value.len())))?;
+
+        let chunks: Vec<&[u8]> = value.chunks(pool.page_size() - OVERFLOW_HEADER_SIZE).collect();
+        let pages = chunks.iter().map(|_| allocate_unpinned(pool)).collect::<Result<Vec<_>>>()?;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let page = pool.get_page_mut(pages[i])?;
+            write_u32(page, 0, pages.get(i + 1).copied().unwrap_or(0));
+            page[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+            pool.unpin(pages[i]);
+        }
+        Ok(CellValue::Overflow { first_page: pages[0], len })
+    }
+
+    /// The value's bytes, read from its overflow pages if needed.
+    fn load(self, pool: &BufferPool) -> Result<Vec<u8>> {
+        let (mut page_id, len) = match self {
+            CellValue::Inline(value) => return Ok(value),
+            CellValue::Overflow { first_page, len } => (first_page, len as usize),
+        };
+        let mut value = Vec::with_capacity(len);
+        while value.len() < len {
+            if page_id == 0 {
+                return Err(HorizonError::CorruptDatabase("overflow chain ends early".into()));
+            }
+            let page = pool.read_page(page_id)?;
+            let take = (len - value.len()).min(page.len() - OVERFLOW_HEADER_SIZE);
+            value.extend_from_slice(&page[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + take]);
+            page_id = read_u32(&page, 0);
+        }
+        Ok(value)
+    }
+
+    /// Return the overflow pages holding the value, if any, to the free
+    /// list.
+    fn free(&self, pool: &mut BufferPool) -> Result<()> {
+        if let CellValue::Overflow { first_page, len } = *self {
+            let mut page_id = first_page;
+            for _ in 0..overflow_page_count(pool.page_size(), len) {
+                if page_id == 0 {
+                    break;
+                }
+                let next = read_u32(&pool.read_page(page_id)?, 0);
+                pool.free_page(page_id)?;
+                page_id = next;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of overflow pages holding a value of `len` bytes.
+fn overflow_page_count(page_size: usize, len: u32) -> usize {
+    let chunk = page_size - OVERFLOW_HEADER_SIZE;
+    (len as usize + chunk - 1) / chunk
+}
+
 /// Parse a leaf cell at `off` and return `(key, value)`.
-fn read_leaf_cell(page: &[u8], off: usize) -> (Vec<u8>, Vec<u8>) {
+fn read_leaf_cell(page: &[u8], off: usize) -> (Vec<u8>, CellValue) {
     let key_size = read_u16(page, off) as usize;
     let key = page[off + 2..off + 2 + key_size].to_vec();
     let val_off = off + 2 + key_size;
-    let val_size = read_u32(page, val_off) as usize;
-    let value = page[val_off + 4..val_off + 4 + val_size].to_vec();
+    let val_size = read_u32(page, val_off);
+    let value = if val_size & OVERFLOW_FLAG != 0 {
+        CellValue::Overflow { first_page: read_u32(page, val_off + 4), len: val_size & !OVERFLOW_FLAG }
+    } else {
+        CellValue::Inline(page[val_off + 4..val_off + 4 + val_size as usize].to_vec())
+    };
     (key, value)
 }
 
 /// Size in bytes of a leaf cell with the given key and value.
-fn leaf_cell_size(key: &[u8], value: &[u8]) -> usize {
-    2 /* key_size */ + key.len() + 4 /* value_size */ + value.len()
+fn leaf_cell_size(key: &[u8], value: &CellValue) -> usize {
+    let stored = match value {
+        CellValue::Inline(value) => value.len(),
+        CellValue::Overflow { .. } => 4 /* first_page */,
+    };
+    2 /* key_size */ + key.len() + 4 /* value_size */ + stored
+}
+
+// ---------------------------------------------------------------------------
+// Checked parsing, for pages whose contents cannot be trusted
+// ---------------------------------------------------------------------------
+
+/// Parse every cell of `page` with bounds checks, returning `(key, page,
+/// len)` in slot order, or a description of the first malformed part of
+/// the page. On internal pages `page` is the child and `len` is 0; on
+/// leaves they are the first overflow page and length of an overflowing
+/// value, or 0 for a value stored in the cell.
+fn checked_cells(page: &[u8]) -> std::result::Result<Vec<(Vec<u8>, PageId, u32)>, String> {
+    let kind = page_type(page);
+    if kind != PAGE_TYPE_LEAF && kind != PAGE_TYPE_INTERNAL {
+        return Err(format!("invalid page type {:#04x}", kind));
+    }
+    let n = cell_count(page);
+    let start = cell_area_start(n);
+    if start > page.len() {
+        return Err(format!("cell count {} does not fit on the page", n));
+    }
+
+    let slice = |from: usize, len: usize| page.get(from..from.checked_add(len)?);
+    let mut cells = Vec::with_capacity(n as usize);
+    for i in 0..n {
+        let off = cell_ptr(page, i) as usize;
+        let cell = if off < start {
+            None
+        } else if kind == PAGE_TYPE_LEAF {
+            slice(off, 2).and_then(|size| {
+                let key_size = read_u16(size, 0) as usize;
+                let key = slice(off + 2, key_size)?;
+                let val_size = read_u32(slice(off + 2 + key_size, 4)?, 0);
+                if val_size & OVERFLOW_FLAG != 0 {
+                    let first_page = read_u32(slice(off + 6 + key_size, 4)?, 0);
+                    return Some((key.to_vec(), first_page, val_size & !OVERFLOW_FLAG));
+                }
+                slice(off + 6 + key_size, val_size as usize)?;
+                Some((key.to_vec(), 0, 0))
+            })
+        } else {
+            slice(off, 6).and_then(|head| {
+                let key = slice(off + 6, read_u16(head, 4) as usize)?;
+                Some((key.to_vec(), read_u32(head, 0), 0))
+            })
+        };
+        match cell {
+            Some(cell) => cells.push(cell),
+            None => return Err(format!("cell {} extends outside the page", i)),
+        }
+    }
+    Ok(cells)
+}
+
+/// State for the full structural walk done by [`BTree::check_integrity`].
+struct TreeChecker<'a> {
+    tree: &'a BTree,
+    pool: &'a BufferPool,
+    seen: &'a mut HashSet<PageId>,
+    /// `(page, next_leaf)` for every leaf, in key order.
+    leaves: Vec<(PageId, PageId)>,
+    leaf_depth: Option<usize>,
+    report: IntegrityReport,
+}
+
+impl TreeChecker<'_> {
+    /// Check the subtree at `page_id`, whose keys must all be `>= lower`
+    /// and `< upper`.
+    fn check_node(
+        &mut self,
+        page_id: PageId,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+        depth: usize,
+    ) -> Result<()> {
+        let errors = &mut self.report.errors;
+        if page_id == 0 || page_id >= self.pool.pager().page_count() {
+            errors.push(format!("page {} is out of range", page_id));
+            return Ok(());
+        }
+        if !self.seen.insert(page_id) {
+            errors.push(format!("page {} is referenced more than once", page_id));
+            return Ok(());
+        }
+        let page = self.pool.read_page(page_id)?;
+        let cells = match checked_cells(&page) {
+            Ok(cells) => cells,
+            Err(msg) => {
+                errors.push(format!("page {}: {}", page_id, msg));
+                return Ok(());
+            }
+        };
+
+        if cells.windows(2).any(|pair| self.tree.cmp_keys(&pair[0].0, &pair[1].0).is_ge()) {
+            errors.push(format!("page {}: keys out of order", page_id));
+        }
+        if let (Some(lower), Some((first, ..))) = (lower, cells.first()) {
+            if self.tree.cmp_keys(first, lower).is_lt() {
+                errors.push(format!("page {}: key below the parent's separator", page_id));
+            }
+        }
+        if let (Some(upper), Some((last, ..))) = (upper, cells.last()) {
+            if self.tree.cmp_keys(last, upper).is_ge() {
+                errors.push(format!("page {}: key above the parent's separator", page_id));
+            }
+        }
+
+        if page_type(&page) == PAGE_TYPE_LEAF {
+            match self.leaf_depth {
+                Some(d) if d != depth => {
+                    errors.push(format!("page {}: leaf at depth {}, expected {}", page_id, depth, d));
+                }
+                _ => self.leaf_depth = Some(depth),
+            }
+            self.leaves.push((page_id, trailer(&page)));
+            self.report.entries += cells.len() as u64;
+            for (_, first_page, len) in cells {
+                if first_page != 0 {
+                    self.check_overflow(page_id, first_page, len)?;
+                }
+            }
+            return Ok(());
+        }
+
+        // Child i holds the keys in [k(i-1), k(i)); the rightmost child
+        // holds everything from the last separator up.
+        let rightmost = trailer(&page);
+        drop(page);
+        let mut low = lower.map(<[u8]>::to_vec);
+        for (key, child, _) in cells {
+            self.check_node(child, low.as_deref(), Some(&key), depth + 1)?;
+            low = Some(key);
+        }
+        self.check_node(rightmost, low.as_deref(), upper, depth + 1)
+    }
+
+    /// Check the overflow chain of a value of `len` bytes stored on leaf
+    /// `leaf`.
+    fn check_overflow(&mut self, leaf: PageId, first_page: PageId, len: u32) -> Result<()> {
+        let errors = &mut self.report.errors;
+        let mut page_id = first_page;
+        for _ in 0..overflow_page_count(self.pool.page_size(), len) {
+            if page_id == 0 || page_id >= self.pool.pager().page_count() {
+                errors.push(format!("page {}: overflow page {} is out of range", leaf, page_id));
+                return Ok(());
+            }
+            if !self.seen.insert(page_id) {
+                errors.push(format!("page {} is referenced more than once", page_id));
+                return Ok(());
+            }
+            page_id = read_u32(&self.pool.read_page(page_id)?, 0);
+        }
+        if page_id != 0 {
+            errors.push(format!("page {}: overflow chain is longer than its value", leaf));
+        }
+        Ok(())
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -279,15 +607,92 @@ fn build_internal_cell(child: PageId, key: &[u8]) -> Vec<u8> {
 }
 
 /// Build the byte representation of a leaf cell.
-fn build_leaf_cell(key: &[u8], value: &[u8]) -> Vec<u8> {
+fn build_leaf_cell(key: &[u8], value: &CellValue) -> Vec<u8> {
     let mut buf = Vec::with_capacity(leaf_cell_size(key, value));
     buf.extend_from_slice(&(key.len() as u16).to_be_bytes());
     buf.extend_from_slice(key);
-    buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
-    buf.extend_from_slice(value);
+    match value {
+        CellValue::Inline(value) => {
+            buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            buf.extend_from_slice(value);
+        }
+        CellValue::Overflow { first_page, len } => {
+            buf.extend_from_slice(&(len | OVERFLOW_FLAG).to_be_bytes());
+            buf.extend_from_slice(&first_page.to_be_bytes());
+        }
+    }
     buf
 }
 
+/// The cells of the leaf page `page`.
+fn leaf_entries(page: &[u8]) -> Vec<(Vec<u8>, CellValue)> {
+    (0..cell_count(page)).map(|i| read_leaf_cell(page, cell_ptr(page, i) as usize)).collect()
+}
+
+/// The cells of the internal page `page`.
+fn internal_cells(page: &[u8]) -> Vec<(PageId, Vec<u8>)> {
+    (0..cell_count(page)).map(|i| read_internal_cell(page, cell_ptr(page, i) as usize)).collect()
+}
+
+/// The bytes used by a leaf page holding `entries`, header included.
+fn leaf_size(entries: &[(Vec<u8>, CellValue)]) -> usize {
+    HEADER_SIZE + entries.iter().map(|(k, v)| CELL_PTR_SIZE + leaf_cell_size(k, v)).sum::<usize>()
+}
+
+/// The bytes used by an internal page holding `cells`, header included.
+fn internal_size(cells: &[(PageId, Vec<u8>)]) -> usize {
+    HEADER_SIZE + cells.iter().map(|(_, k)| CELL_PTR_SIZE + internal_cell_size(k)).sum::<usize>()
+}
+
+/// Replace the contents of page `page_id` with a leaf holding `entries`.
+fn write_leaf(
+    pool: &mut BufferPool,
+    page_id: PageId,
+    entries: &[(Vec<u8>, CellValue)],
+    next_leaf: PageId,
+) -> Result<()> {
+    let data = pool.get_page_mut(page_id)?;
+    init_leaf_page(data);
+    set_trailer(data, next_leaf);
+    for (i, (k, v)) in entries.iter().enumerate() {
+        write_cell(data, i as u16, &build_leaf_cell(k, v));
+    }
+    pool.unpin(page_id);
+    Ok(())
+}
+
+/// Replace the contents of page `page_id` with an internal page holding
+/// `cells`.
+fn write_internal(
+    pool: &mut BufferPool,
+    page_id: PageId,
+    cells: &[(PageId, Vec<u8>)],
+    rightmost: PageId,
+) -> Result<()> {
+    let data = pool.get_page_mut(page_id)?;
+    init_internal_page(data);
+    set_trailer(data, rightmost);
+    for (i, (child, key)) in cells.iter().enumerate() {
+        write_cell(data, i as u16, &build_internal_cell(*child, key));
+    }
+    pool.unpin(page_id);
+    Ok(())
+}
+
+/// Allocate a page in `pool` without keeping it pinned.
+fn allocate_unpinned(pool: &mut BufferPool) -> Result<PageId> {
+    let page_id = pool.allocate_page()?;
+    pool.unpin(page_id);
+    Ok(page_id)
+}
+
+/// Replace the contents of page `page_id` with `data`.
+fn write_whole_page(pool: &mut BufferPool, page_id: PageId, data: &[u8]) -> Result<()> {
+    pool.get_page_mut(page_id)?.copy_from_slice(data);
+    pool.unpin(page_id);
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // BTree implementation
 // ---------------------------------------------------------------------------
@@ -296,17 +701,33 @@ impl BTree {
     /// Create a brand-new, empty B+Tree. Allocates one leaf page as the root.
     pub fn create(pool: &mut BufferPool) -> Result<Self> {
         let page_id = pool.allocate_page()?;
+        pool.unpin(page_id);
         {
             let data = pool.get_page_mut(page_id)?;
             init_leaf_page(data);
         }
         pool.unpin(page_id);
-        Ok(BTree { root_page: page_id })
+        Ok(BTree { root_page: page_id, order: None })
     }
 
     /// Open an existing B+Tree whose root page is already known.
     pub fn open(root_page: PageId) -> Self {
-        BTree { root_page }
+        BTree { root_page, order: None }
+    }
+
+    /// This tree with its keys ordered by `order`, or in byte order if it
+    /// is `None`. A tree must always be used with the order its keys were
+    /// inserted in.
+    pub fn ordered_by(self, order: Option<KeyOrder>) -> Self {
+        BTree { order, ..self }
+    }
+
+    /// Compare two keys in the order of this tree.
+    fn cmp_keys(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self.order {
+            Some(ref order) => order(a, b),
+            None => a.cmp(b),
+        }
     }
 
     /// Return the root page id of this tree.
@@ -319,28 +740,26 @@ impl BTree {
     // -----------------------------------------------------------------------
 
     /// Look up a single key. Returns `Some(value)` if found, `None` otherwise.
-    pub fn search(&self, pool: &mut BufferPool, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    pub fn search(&self, pool: &BufferPool, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let leaf_id = self.find_leaf(pool, key)?;
-        let page = pool.get_page(leaf_id)?.clone();
-        pool.unpin(leaf_id);
+        let page = pool.read_page(leaf_id)?;
 
         let n = cell_count(&page);
         for i in 0..n {
             let off = cell_ptr(&page, i) as usize;
             let (k, v) = read_leaf_cell(&page, off);
             if k == key {
-                return Ok(Some(v));
+                return v.load(pool).map(Some);
             }
         }
         Ok(None)
     }
 
     /// Walk down the tree to find the leaf page that should contain `key`.
-    fn find_leaf(&self, pool: &mut BufferPool, key: &[u8]) -> Result<PageId> {
+    fn find_leaf(&self, pool: &BufferPool, key: &[u8]) -> Result<PageId> {
         let mut current = self.root_page;
         loop {
-            let page = pool.get_page(current)?.clone();
-            pool.unpin(current);
+            let page = pool.read_page(current)?;
 
             if page_type(&page) == PAGE_TYPE_LEAF {
                 return Ok(current);
@@ -352,7 +771,7 @@ impl BTree {
             for i in 0..n {
                 let off = cell_ptr(&page, i) as usize;
                 let (c, k) = read_internal_cell(&page, off);
-                if key < k.as_slice() {
+                if self.cmp_keys(key, &k).is_lt() {
                     child = c;
                     break;
                 }
@@ -365,13 +784,31 @@ impl BTree {
     // Insert
     // -----------------------------------------------------------------------
 
+    /// Fail if `key` is too long to be inserted into a tree in `pool`, that
+    /// is longer than a quarter of a page.
+    pub fn check_key_size(pool: &BufferPool, key: &[u8]) -> Result<()> {
+        let limit = pool.page_size() / 4;
+        if key.len() > limit {
+            return Err(HorizonError::InvalidSql(format!(
+                "key of {} bytes is too large (at most {} bytes)",
+                key.len(),
+                limit
+            )));
+        }
+        Ok(())
+    }
+
     /// Insert a key/value pair. If the key already exists its value is
-    /// replaced (upsert semantics).
+    /// replaced (upsert semantics). A key longer than a quarter of a page
+    /// is refused.
     pub fn insert(&mut self, pool: &mut BufferPool, key: &[u8], value: &[u8]) -> Result<()> {
-        let result = self.insert_recursive(pool, self.root_page, key, value)?;
+        Self::check_key_size(pool, key)?;
+        let value = CellValue::store(pool, value)?;
+        let result = self.insert_recursive(pool, self.root_page, key, &value)?;
         if let InsertResult::Split { split_key, new_page } = result {
             // The root was split. Create a new root.
             let new_root = pool.allocate_page()?;
+            pool.unpin(new_root);
             {
                 let data = pool.get_page_mut(new_root)?;
                 init_internal_page(data);
@@ -393,9 +830,9 @@ impl BTree {
         pool: &mut BufferPool,
         page_id: PageId,
         key: &[u8],
-        value: &[u8],
+        value: &CellValue,
     ) -> Result<InsertResult> {
-        let page = pool.get_page(page_id)?.clone();
+        let page = pool.get_page(page_id)?.to_vec();
         pool.unpin(page_id);
 
         if page_type(&page) == PAGE_TYPE_LEAF {
@@ -409,7 +846,7 @@ impl BTree {
         for i in 0..n {
             let off = cell_ptr(&page, i) as usize;
             let (c, k) = read_internal_cell(&page, off);
-            if key < k.as_slice() {
+            if self.cmp_keys(key, &k).is_lt() {
                 child_page = c;
                 child_idx = Some(i);
                 break;
@@ -435,11 +872,11 @@ impl BTree {
         pool: &mut BufferPool,
         page_id: PageId,
         key: &[u8],
-        value: &[u8],
+        value: &CellValue,
     ) -> Result<InsertResult> {
         // First check for an existing key (upsert). We work on a clone to
         // determine the slot, then apply the mutation.
-        let page = pool.get_page(page_id)?.clone();
+        let page = pool.get_page(page_id)?.to_vec();
         pool.unpin(page_id);
 
         let n = cell_count(&page);
@@ -450,23 +887,25 @@ impl BTree {
         for i in 0..n {
             let off = cell_ptr(&page, i) as usize;
             let (k, _v) = read_leaf_cell(&page, off);
-            match key.cmp(k.as_slice()) {
-                std::cmp::Ordering::Equal => {
+            match self.cmp_keys(key, &k) {
+                Ordering::Equal => {
                     existing_idx = Some(i);
                     break;
                 }
-                std::cmp::Ordering::Less => {
+                Ordering::Less => {
                     if insert_slot == n {
                         insert_slot = i;
                     }
                 }
-                std::cmp::Ordering::Greater => {}
+                Ordering::Greater => {}
             }
         }
 
         if let Some(idx) = existing_idx {
             // Key exists -- rebuild the page replacing this cell's value.
+            let (_, old_value) = read_leaf_cell(&page, cell_ptr(&page, idx) as usize);
             self.replace_leaf_cell(pool, page_id, idx, key, value)?;
+            old_value.free(pool)?;
             return Ok(InsertResult::Done);
         }
 
@@ -495,21 +934,21 @@ impl BTree {
         page_id: PageId,
         cell_idx: u16,
         key: &[u8],
-        new_value: &[u8],
+        new_value: &CellValue,
     ) -> Result<()> {
-        let old_page = pool.get_page(page_id)?.clone();
+        let old_page = pool.get_page(page_id)?.to_vec();
         pool.unpin(page_id);
 
         let n = cell_count(&old_page);
         let next_leaf = trailer(&old_page);
 
         // Collect all cells, replacing the one at cell_idx.
-        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(n as usize);
+        let mut entries: Vec<(Vec<u8>, CellValue)> = Vec::with_capacity(n as usize);
         for i in 0..n {
             let off = cell_ptr(&old_page, i) as usize;
             let (k, v) = read_leaf_cell(&old_page, off);
             if i == cell_idx {
-                entries.push((key.to_vec(), new_value.to_vec()));
+                entries.push((key.to_vec(), new_value.clone()));
             } else {
                 entries.push((k, v));
             }
@@ -536,21 +975,21 @@ impl BTree {
         pool: &mut BufferPool,
         page_id: PageId,
         key: &[u8],
-        value: &[u8],
+        value: &CellValue,
         insert_slot: u16,
     ) -> Result<InsertResult> {
-        let old_page = pool.get_page(page_id)?.clone();
+        let old_page = pool.get_page(page_id)?.to_vec();
         pool.unpin(page_id);
 
         let n = cell_count(&old_page);
         let old_next_leaf = trailer(&old_page);
 
         // Collect all existing entries plus the new one.
-        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(n as usize + 1);
+        let mut entries: Vec<(Vec<u8>, CellValue)> = Vec::with_capacity(n as usize + 1);
         let mut inserted = false;
         for i in 0..n {
             if i == insert_slot && !inserted {
-                entries.push((key.to_vec(), value.to_vec()));
+                entries.push((key.to_vec(), value.clone()));
                 inserted = true;
             }
             let off = cell_ptr(&old_page, i) as usize;
@@ -558,7 +997,7 @@ impl BTree {
             entries.push((k, v));
         }
         if !inserted {
-            entries.push((key.to_vec(), value.to_vec()));
+            entries.push((key.to_vec(), value.clone()));
         }
 
         let total = entries.len();
@@ -614,7 +1053,7 @@ impl BTree {
         key: &[u8],
         new_child: PageId,
     ) -> Result<InsertResult> {
-        let page = pool.get_page(page_id)?.clone();
+        let page = pool.get_page(page_id)?.to_vec();
         pool.unpin(page_id);
 
         // The new_child is actually the right sibling produced by a split
@@ -788,7 +1227,7 @@ impl BTree {
         let total_ptrs = cells.len() * CELL_PTR_SIZE;
         let needed = HEADER_SIZE + total_ptrs + total_cell_bytes;
 
-        if needed <= PAGE_SIZE {
+        if needed <= pool.page_size() {
             // Fits -- rebuild in place.
             let data = pool.get_page_mut(page_id)?;
             init_internal_page(data);
@@ -853,12 +1292,12 @@ impl BTree {
     /// Delete a key from the tree. Returns `true` if the key was found and
     /// removed, `false` if it was not present.
     ///
-    /// This implementation performs a simple removal from the leaf page
-    /// without any rebalancing. The tree remains correct (all searches and
-    /// scans still work), but pages may become under-utilised.
+    /// A leaf left less than 40% full is merged with a sibling, or takes
+    /// entries from it, and a leaf left empty is unlinked and freed; see
+    /// the [module documentation](self). The root page never changes.
     pub fn delete(&mut self, pool: &mut BufferPool, key: &[u8]) -> Result<bool> {
-        let leaf_id = self.find_leaf(pool, key)?;
-        let page = pool.get_page(leaf_id)?.clone();
+        let (path, leaf_id) = self.find_leaf_path(pool, key)?;
+        let page = pool.get_page(leaf_id)?.to_vec();
         pool.unpin(leaf_id);
 
         let n = cell_count(&page);
@@ -876,10 +1315,11 @@ impl BTree {
             Some(i) => i,
             None => return Ok(false),
         };
+        let (_, deleted) = read_leaf_cell(&page, cell_ptr(&page, idx) as usize);
 
         // Rebuild the page without the deleted cell.
         let next_leaf = trailer(&page);
-        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(n as usize - 1);
+        let mut entries: Vec<(Vec<u8>, CellValue)> = Vec::with_capacity(n as usize - 1);
         for i in 0..n {
             if i == idx {
                 continue;
@@ -888,32 +1328,281 @@ impl BTree {
             let (k, v) = read_leaf_cell(&page, off);
             entries.push((k, v));
         }
+        if entries.is_empty() && !path.is_empty() {
+            deleted.free(pool)?;
+            self.remove_leaf(pool, &path, leaf_id, next_leaf)?;
+            return Ok(true);
+        }
 
-        let data = pool.get_page_mut(leaf_id)?;
-        init_leaf_page(data);
-        set_trailer(data, next_leaf);
-        for (k, v) in &entries {
-            let cell = build_leaf_cell(k, v);
-            let slot = cell_count(data);
-            write_cell(data, slot, &cell);
+        write_leaf(pool, leaf_id, &entries, next_leaf)?;
+        deleted.free(pool)?;
+        if let Some(&(parent, slot)) = path.last() {
+            if leaf_size(&entries) * 100 < pool.page_size() * MIN_FILL_PERCENT {
+                self.rebalance_leaf(pool, parent, slot)?;
+            }
         }
-        pool.unpin(leaf_id);
         Ok(true)
     }
 
+    /// Rebalance the underfull leaf at `slot` of internal page `parent`
+    /// with its right sibling, or its left sibling if it is the rightmost
+    /// child.
+    ///
+    /// If the entries of both leaves fit on one page, they are merged into
+    /// the left one: the right one is unlinked from the leaf chain and
+    /// freed, and the separator between them is dropped from the parent.
+    /// Otherwise the entries are split evenly between the two leaves and
+    /// the separator becomes the first key of the right one, unless the
+    /// new separator does not fit in the parent.
+    fn rebalance_leaf(&self, pool: &mut BufferPool, parent: PageId, slot: u16) -> Result<()> {
+        let page = pool.read_page(parent)?.into_owned();
+        let n = cell_count(&page);
+        if n == 0 {
+            // No sibling under this parent
+            return Ok(());
+        }
+        let left_slot = slot.min(n - 1);
+        let (left, right) = (child_at(&page, left_slot), child_at(&page, left_slot + 1));
+        let mut cells = internal_cells(&page);
+        let mut rightmost = trailer(&page);
+
+        let right_page = pool.read_page(right)?.into_owned();
+        let right_next = trailer(&right_page);
+        let mut entries = leaf_entries(&pool.read_page(left)?);
+        entries.extend(leaf_entries(&right_page));
+
+        let page_size = pool.page_size();
+        if leaf_size(&entries) <= page_size {
+            write_leaf(pool, left, &entries, right_next)?;
+            // The merged leaf takes over the right leaf's key range
+            match cells.get_mut(left_slot as usize + 1) {
+                Some((child, _)) => *child = left,
+                None => rightmost = left,
+            }
+            cells.remove(left_slot as usize);
+            write_internal(pool, parent, &cells, rightmost)?;
+            pool.free_page(right)?;
+            return self.collapse_root(pool);
+        }
+
+        // Split where the left leaf holds at least half of the bytes
+        let half = leaf_size(&entries) / 2;
+        let mut split = 0;
+        let mut size = HEADER_SIZE;
+        while split < entries.len() - 1 && size < half {
+            size += CELL_PTR_SIZE + leaf_cell_size(&entries[split].0, &entries[split].1);
+            split += 1;
+        }
+        let (low, high) = entries.split_at(split.max(1));
+        cells[left_slot as usize].1 = high[0].0.clone();
+        if leaf_size(low) > page_size || leaf_size(high) > page_size || internal_size(&cells) > page_size {
+            return Ok(());
+        }
+        write_leaf(pool, left, low, right)?;
+        write_leaf(pool, right, high, right_next)?;
+        write_internal(pool, parent, &cells, rightmost)
+    }
+
+    /// Walk down to the leaf that should contain `key`, like
+    /// [`find_leaf`](Self::find_leaf), also returning each internal page
+    /// passed with the slot of the child taken (its cell count for the
+    /// rightmost child).
+    fn find_leaf_path(&self, pool: &BufferPool, key: &[u8]) -> Result<(Vec<(PageId, u16)>, PageId)> {
+        let mut path = Vec::new();
+        let mut current = self.root_page;
+        loop {
+            let page = pool.read_page(current)?;
+            if page_type(&page) == PAGE_TYPE_LEAF {
+                return Ok((path, current));
+            }
+
+            let n = cell_count(&page);
+            let mut slot = n;
+            for i in 0..n {
+                let (_, k) = read_internal_cell(&page, cell_ptr(&page, i) as usize);
+                if self.cmp_keys(key, &k).is_lt() {
+                    slot = i;
+                    break;
+                }
+            }
+            path.push((current, slot));
+            current = child_at(&page, slot);
+        }
+    }
+
+    /// Unlink the empty leaf `leaf_id`, reached through `path`, from the
+    /// leaf chain and from its parent, and free it.
+    ///
+    /// Dropping child `i` of a parent leaves its key range to child `i + 1`
+    /// (or, for the rightmost child, to child `i - 1`), which keeps every
+    /// leaf at the same depth. A parent left without children is removed
+    /// from its own parent in the same way, and a root left with a single
+    /// child takes over that child's contents.
+    fn remove_leaf(
+        &self,
+        pool: &mut BufferPool,
+        path: &[(PageId, u16)],
+        leaf_id: PageId,
+        next_leaf: PageId,
+    ) -> Result<()> {
+        // The previous leaf is the rightmost leaf of the nearest subtree to
+        // the left of the path
+        if let Some(&(page_id, slot)) = path.iter().rev().find(|&&(_, slot)| slot > 0) {
+            let mut current = child_at(&pool.read_page(page_id)?, slot - 1);
+            loop {
+                let page = pool.read_page(current)?;
+                if page_type(&page) == PAGE_TYPE_LEAF {
+                    break;
+                }
+                current = trailer(&page);
+            }
+            set_trailer(pool.get_page_mut(current)?, next_leaf);
+            pool.unpin(current);
+        }
+
+        let mut freed = vec![leaf_id];
+        for &(page_id, slot) in path.iter().rev() {
+            let page = pool.read_page(page_id)?.into_owned();
+            let n = cell_count(&page);
+            if n == 0 {
+                if page_id == self.root_page {
+                    // The tree is now empty
+                    init_leaf_page(pool.get_page_mut(page_id)?);
+                    pool.unpin(page_id);
+                    break;
+                }
+                freed.push(page_id);
+                continue;
+            }
+
+            let mut cells = internal_cells(&page);
+            let mut rightmost = trailer(&page);
+            if slot == n {
+                rightmost = cells[n as usize - 1].0;
+                cells.truncate(n as usize - 1);
+            } else {
+                cells.remove(slot as usize);
+            }
+            write_internal(pool, page_id, &cells, rightmost)?;
+            break;
+        }
+        for page_id in freed {
+            pool.free_page(page_id)?;
+        }
+        self.collapse_root(pool)
+    }
+
+    /// While the root is an internal page with a single child, move that
+    /// child's contents into the root page and free the child.
+    fn collapse_root(&self, pool: &mut BufferPool) -> Result<()> {
+        loop {
+            let root = pool.read_page(self.root_page)?;
+            if page_type(&root) == PAGE_TYPE_LEAF || cell_count(&root) > 0 {
+                return Ok(());
+            }
+            let child = trailer(&root);
+            let data = pool.read_page(child)?.into_owned();
+            pool.get_page_mut(self.root_page)?.copy_from_slice(&data);
+            pool.unpin(self.root_page);
+            pool.free_page(child)?;
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Compaction
+    // -----------------------------------------------------------------------
+
+    /// Copy every entry of this tree into a new tree in `dst`, filling each
+    /// page as far as it will go, and return the new tree.
+    ///
+    /// The leaves are allocated first, in key order, followed by each level
+    /// of internal pages up to the root. Copied into a fresh file, the tree
+    /// therefore occupies a contiguous run of pages.
+    pub fn copy_compacted(&self, src: &BufferPool, dst: &mut BufferPool) -> Result<BTree> {
+        // (first key, page id) of each page of the level being built
+        let mut level: Vec<(Vec<u8>, PageId)> = Vec::new();
+        let mut page = vec![0u8; dst.page_size()];
+        init_leaf_page(&mut page);
+        let mut page_id = allocate_unpinned(dst)?;
+        let mut first_key = None;
+
+        let mut cursor = self.cursor(src)?;
+        while let Some(entry) = self.next(src, &mut cursor)? {
+            let cell = build_leaf_cell(&entry.key, &CellValue::store(dst, &entry.value)?);
+            if cell_count(&page) > 0 && !has_space(&page, cell.len()) {
+                let next = allocate_unpinned(dst)?;
+                set_trailer(&mut page, next);
+                write_whole_page(dst, page_id, &page)?;
+                level.push((first_key.take().unwrap_or_default(), page_id));
+                init_leaf_page(&mut page);
+                page_id = next;
+            }
+            first_key.get_or_insert(entry.key);
+            let slot = cell_count(&page);
+            write_cell(&mut page, slot, &cell);
+        }
+        write_whole_page(dst, page_id, &page)?;
+        level.push((first_key.unwrap_or_default(), page_id));
+
+        while level.len() > 1 {
+            level = Self::build_internal_level(dst, level)?;
+        }
+        Ok(BTree { root_page: level[0].1, order: self.order.clone() })
+    }
+
+    /// Write internal pages over `children`, given as (first key, page id)
+    /// pairs in key order, and return the same pairs for the new pages.
+    fn build_internal_level(
+        dst: &mut BufferPool,
+        children: Vec<(Vec<u8>, PageId)>,
+    ) -> Result<Vec<(Vec<u8>, PageId)>> {
+        let mut parents = Vec::new();
+        let mut children = children.into_iter();
+        let (mut first_key, mut last_child) = match children.next() {
+            Some(child) => child,
+            None => return Ok(parents),
+        };
+        let mut page = vec![0u8; dst.page_size()];
+        init_internal_page(&mut page);
+        let mut page_id = allocate_unpinned(dst)?;
+
+        for (key, child) in children {
+            // Keys below `key` belong to the previous child
+            let cell = build_internal_cell(last_child, &key);
+            if has_space(&page, cell.len()) {
+                let slot = cell_count(&page);
+                write_cell(&mut page, slot, &cell);
+            } else {
+                set_trailer(&mut page, last_child);
+                write_whole_page(dst, page_id, &page)?;
+                parents.push((std::mem::replace(&mut first_key, key), page_id));
+                init_internal_page(&mut page);
+                page_id = allocate_unpinned(dst)?;
+            }
+            last_child = child;
+        }
+        set_trailer(&mut page, last_child);
+        write_whole_page(dst, page_id, &page)?;
+        parents.push((first_key, page_id));
+        Ok(parents)
+    }
+
     // -----------------------------------------------------------------------
     // Scan operations
     // -----------------------------------------------------------------------
 
     /// Return all entries in the tree, in key order.
-    pub fn scan_all(&self, pool: &mut BufferPool) -> Result<Vec<BTreeEntry>> {
+    ///
+    /// Range scans check the current query's cancel flag once per leaf
+    /// and fail with [`HorizonError::Cancelled`] once it is set.
+    pub fn scan_all(&self, pool: &BufferPool) -> Result<Vec<BTreeEntry>> {
         // Find the leftmost leaf.
         let leftmost = self.find_leftmost_leaf(pool)?;
         self.scan_leaves_from_page(pool, leftmost, None, None)
     }
 
     /// Return all entries whose key is >= `start_key`, in key order.
-    pub fn scan_from(&self, pool: &mut BufferPool, start_key: &[u8]) -> Result<Vec<BTreeEntry>> {
+    pub fn scan_from(&self, pool: &BufferPool, start_key: &[u8]) -> Result<Vec<BTreeEntry>> {
         let leaf_id = self.find_leaf(pool, start_key)?;
         self.scan_leaves_from_page(pool, leaf_id, Some(start_key), None)
     }
@@ -922,7 +1611,7 @@ impl BTree {
     /// inclusive, end exclusive), in key order.
     pub fn scan_range(
         &self,
-        pool: &mut BufferPool,
+        pool: &BufferPool,
         start_key: &[u8],
         end_key: &[u8],
     ) -> Result<Vec<BTreeEntry>> {
@@ -930,14 +1619,37 @@ impl BTree {
         self.scan_leaves_from_page(pool, leaf_id, Some(start_key), Some(end_key))
     }
 
+    /// Create a cursor positioned before the first entry in the tree.
+    pub fn cursor(&self, pool: &BufferPool) -> Result<BTreeCursor> {
+        let leaf = self.find_leftmost_leaf(pool)?;
+        Ok(BTreeCursor { leaf, slot: 0 })
+    }
+
+    /// Advance `cursor` and return the entry it moved past, or `None` once
+    /// every entry has been returned. Entries come back in key order.
+    pub fn next(&self, pool: &BufferPool, cursor: &mut BTreeCursor) -> Result<Option<BTreeEntry>> {
+        while cursor.leaf != 0 {
+            let leaf_id = cursor.leaf;
+            let page = pool.read_page(leaf_id)?;
+            if cursor.slot < cell_count(&page) {
+                let off = cell_ptr(&page, cursor.slot) as usize;
+                let (key, value) = read_leaf_cell(&page, off);
+                cursor.slot += 1;
+                return Ok(Some(BTreeEntry { key, value: value.load(pool)? }));
+            }
+            cursor.leaf = trailer(&page);
+            cursor.slot = 0;
+        }
+        Ok(None)
+    }
+
     /// Count the total number of entries in the tree.
-    pub fn count(&self, pool: &mut BufferPool) -> Result<u64> {
+    pub fn count(&self, pool: &BufferPool) -> Result<u64> {
         let leftmost = self.find_leftmost_leaf(pool)?;
         let mut total: u64 = 0;
         let mut current = leftmost;
         loop {
-            let page = pool.get_page(current)?.clone();
-            pool.unpin(current);
+            let page = pool.read_page(current)?;
             total += cell_count(&page) as u64;
             let next = trailer(&page);
             if next == 0 {
@@ -948,16 +1660,139 @@ impl BTree {
         Ok(total)
     }
 
+    /// The number of levels in the tree: 1 while the root is a leaf.
+    pub fn height(&self, pool: &BufferPool) -> Result<usize> {
+        let mut height = 1;
+        let mut current = self.root_page;
+        loop {
+            let page = pool.read_page(current)?;
+            if page_type(&page) == PAGE_TYPE_LEAF {
+                return Ok(height);
+            }
+            height += 1;
+            current = trailer(&page);
+        }
+    }
+
+    /// The number of internal and leaf pages in the tree. Overflow pages
+    /// are not counted.
+    pub fn page_count(&self, pool: &BufferPool) -> Result<u64> {
+        let mut total = 0;
+        let mut pending = vec![self.root_page];
+        while let Some(page_id) = pending.pop() {
+            let page = pool.read_page(page_id)?;
+            total += 1;
+            if page_type(&page) == PAGE_TYPE_INTERNAL {
+                pending.extend(internal_cells(&page).into_iter().map(|(child, _)| child));
+                pending.push(trailer(&page));
+            }
+        }
+        Ok(total)
+    }
+
+    // -----------------------------------------------------------------------
+    // Integrity checks
+    // -----------------------------------------------------------------------
+
+    /// Walk every page of the tree and verify its structure: page ids are in
+    /// range and used once, cells lie within their pages, keys ascend within
+    /// each page and respect the separators above them, all leaves are at
+    /// the same depth, and the leaf chain visits the leaves in key order.
+    ///
+    /// `seen` collects the pages visited; share it between trees to catch
+    /// a page claimed by two of them. Corruption is reported in the returned
+    /// [`IntegrityReport`]; only I/O failures are returned as errors.
+    pub fn check_integrity(
+        &self,
+        pool: &BufferPool,
+        seen: &mut HashSet<PageId>,
+    ) -> Result<IntegrityReport> {
+        let mut checker = TreeChecker {
+            tree: self,
+            pool,
+            seen,
+            leaves: Vec::new(),
+            leaf_depth: None,
+            report: IntegrityReport::default(),
+        };
+        checker.check_node(self.root_page, None, None, 0)?;
+
+        let TreeChecker { leaves, mut report, .. } = checker;
+        for (i, &(page_id, next)) in leaves.iter().enumerate() {
+            let expected = leaves.get(i + 1).map_or(0, |&(id, _)| id);
+            if next != expected {
+                report.errors.push(format!(
+                    "page {}: next leaf is {}, expected {}",
+                    page_id, next, expected
+                ));
+            }
+        }
+        Ok(report)
+    }
+
+    /// A lighter version of [`check_integrity`](Self::check_integrity) that
+    /// only looks at the leaf level: it descends to the first leaf, then
+    /// follows the leaf chain checking that every leaf is well formed and
+    /// that keys ascend across the whole chain.
+    pub fn check_leaves(&self, pool: &BufferPool) -> Result<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+        let page_count = pool.pager().page_count();
+        let mut seen = HashSet::new();
+        let mut current = self.root_page;
+        let mut in_chain = false;
+        let mut last_key: Option<Vec<u8>> = None;
+
+        while current != 0 {
+            if current >= page_count {
+                report.errors.push(format!("page {} is out of range", current));
+                break;
+            }
+            if !seen.insert(current) {
+                report.errors.push(format!("page {} is referenced more than once", current));
+                break;
+            }
+            let page = pool.read_page(current)?;
+            let cells = match checked_cells(&page) {
+                Ok(cells) => cells,
+                Err(msg) => {
+                    report.errors.push(format!("page {}: {}", current, msg));
+                    break;
+                }
+            };
+
+            if page_type(&page) == PAGE_TYPE_INTERNAL {
+                if in_chain {
+                    report.errors.push(format!("page {}: internal page in the leaf chain", current));
+                    break;
+                }
+                current = cells.first().map_or_else(|| trailer(&page), |&(_, child, _)| child);
+                continue;
+            }
+
+            in_chain = true;
+            let mut ordered = true;
+            for (key, ..) in cells {
+                ordered &= last_key.as_ref().map_or(true, |last| self.cmp_keys(last, &key).is_lt());
+                last_key = Some(key);
+                report.entries += 1;
+            }
+            if !ordered {
+                report.errors.push(format!("page {}: keys out of order", current));
+            }
+            current = trailer(&page);
+        }
+        Ok(report)
+    }
+
     // -----------------------------------------------------------------------
     // Internal scan helpers
     // -----------------------------------------------------------------------
 
     /// Find the leftmost leaf by always descending to the first child.
-    fn find_leftmost_leaf(&self, pool: &mut BufferPool) -> Result<PageId> {
+    fn find_leftmost_leaf(&self, pool: &BufferPool) -> Result<PageId> {
         let mut current = self.root_page;
         loop {
-            let page = pool.get_page(current)?.clone();
-            pool.unpin(current);
+            let page = pool.read_page(current)?;
 
             if page_type(&page) == PAGE_TYPE_LEAF {
                 return Ok(current);
@@ -981,7 +1816,7 @@ impl BTree {
     /// If `end_key` is given, stop at the first entry with key >= end_key.
     fn scan_leaves_from_page(
         &self,
-        pool: &mut BufferPool,
+        pool: &BufferPool,
         start_page: PageId,
         start_key: Option<&[u8]>,
         end_key: Option<&[u8]>,
@@ -990,24 +1825,24 @@ impl BTree {
         let mut current = start_page;
 
         loop {
-            let page = pool.get_page(current)?.clone();
-            pool.unpin(current);
+            crate::cancel::check()?;
+            let page = pool.read_page(current)?;
 
             let n = cell_count(&page);
             for i in 0..n {
                 let off = cell_ptr(&page, i) as usize;
                 let (k, v) = read_leaf_cell(&page, off);
                 if let Some(sk) = start_key {
-                    if k.as_slice() < sk {
+                    if self.cmp_keys(&k, sk).is_lt() {
                         continue;
                     }
                 }
                 if let Some(ek) = end_key {
-                    if k.as_slice() >= ek {
+                    if self.cmp_keys(&k, ek).is_ge() {
                         return Ok(result);
                     }
                 }
-                result.push(BTreeEntry { key: k, value: v });
+                result.push(BTreeEntry { key: k, value: v.load(pool)? });
             }
 
             let next = trailer(&page);
@@ -1054,7 +1889,7 @@ mod tests {
     fn search_empty_tree_returns_none() {
         let (_dir, mut pool) = test_pool();
         let tree = BTree::create(&mut pool).unwrap();
-        let result = tree.search(&mut pool, b"hello").unwrap();
+        let result = tree.search(&pool, b"hello").unwrap();
         assert!(result.is_none());
     }
 
@@ -1062,7 +1897,7 @@ mod tests {
     fn scan_empty_tree_returns_empty() {
         let (_dir, mut pool) = test_pool();
         let tree = BTree::create(&mut pool).unwrap();
-        let entries = tree.scan_all(&mut pool).unwrap();
+        let entries = tree.scan_all(&pool).unwrap();
         assert!(entries.is_empty());
     }
 
@@ -1070,7 +1905,7 @@ mod tests {
     fn count_empty_tree_returns_zero() {
         let (_dir, mut pool) = test_pool();
         let tree = BTree::create(&mut pool).unwrap();
-        assert_eq!(tree.count(&mut pool).unwrap(), 0);
+        assert_eq!(tree.count(&pool).unwrap(), 0);
     }
 
     // -----------------------------------------------------------------------
@@ -1083,7 +1918,7 @@ mod tests {
         let mut tree = BTree::create(&mut pool).unwrap();
         tree.insert(&mut pool, b"key1", b"value1").unwrap();
 
-        let val = tree.search(&mut pool, b"key1").unwrap();
+        let val = tree.search(&pool, b"key1").unwrap();
         assert_eq!(val, Some(b"value1".to_vec()));
     }
 
@@ -1093,7 +1928,7 @@ mod tests {
         let mut tree = BTree::create(&mut pool).unwrap();
         tree.insert(&mut pool, b"key1", b"value1").unwrap();
 
-        let val = tree.search(&mut pool, b"key2").unwrap();
+        let val = tree.search(&pool, b"key2").unwrap();
         assert!(val.is_none());
     }
 
@@ -1111,18 +1946,18 @@ mod tests {
         tree.insert(&mut pool, b"cherry", b"dark red").unwrap();
 
         assert_eq!(
-            tree.search(&mut pool, b"apple").unwrap(),
+            tree.search(&pool, b"apple").unwrap(),
             Some(b"red".to_vec())
         );
         assert_eq!(
-            tree.search(&mut pool, b"banana").unwrap(),
+            tree.search(&pool, b"banana").unwrap(),
             Some(b"yellow".to_vec())
         );
         assert_eq!(
-            tree.search(&mut pool, b"cherry").unwrap(),
+            tree.search(&pool, b"cherry").unwrap(),
             Some(b"dark red".to_vec())
         );
-        assert!(tree.search(&mut pool, b"date").unwrap().is_none());
+        assert!(tree.search(&pool, b"date").unwrap().is_none());
     }
 
     // -----------------------------------------------------------------------
@@ -1136,18 +1971,18 @@ mod tests {
 
         tree.insert(&mut pool, b"key1", b"v1").unwrap();
         assert_eq!(
-            tree.search(&mut pool, b"key1").unwrap(),
+            tree.search(&pool, b"key1").unwrap(),
             Some(b"v1".to_vec())
         );
 
         tree.insert(&mut pool, b"key1", b"v2").unwrap();
         assert_eq!(
-            tree.search(&mut pool, b"key1").unwrap(),
+            tree.search(&pool, b"key1").unwrap(),
             Some(b"v2".to_vec())
         );
 
         // Only one entry should exist.
-        assert_eq!(tree.count(&mut pool).unwrap(), 1);
+        assert_eq!(tree.count(&pool).unwrap(), 1);
     }
 
     #[test]
@@ -1161,7 +1996,7 @@ mod tests {
             tree.insert(&mut pool, key.as_bytes(), val.as_bytes())
                 .unwrap();
         }
-        assert_eq!(tree.count(&mut pool).unwrap(), 10);
+        assert_eq!(tree.count(&pool).unwrap(), 10);
 
         // Update all values.
         for i in 0u32..10 {
@@ -1170,13 +2005,13 @@ mod tests {
             tree.insert(&mut pool, key.as_bytes(), val.as_bytes())
                 .unwrap();
         }
-        assert_eq!(tree.count(&mut pool).unwrap(), 10);
+        assert_eq!(tree.count(&pool).unwrap(), 10);
 
         // Verify updated values.
         for i in 0u32..10 {
             let key = format!("key{:04}", i);
             let expected = format!("val_v2_{}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap().unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap().unwrap();
             assert_eq!(found, expected.as_bytes());
         }
     }
@@ -1201,7 +2036,7 @@ mod tests {
         for i in 0u32..100 {
             let key = format!("key{:04}", i);
             let expected = format!("value{:04}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap();
             assert_eq!(
                 found,
                 Some(expected.as_bytes().to_vec()),
@@ -1210,7 +2045,7 @@ mod tests {
             );
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 100);
+        assert_eq!(tree.count(&pool).unwrap(), 100);
     }
 
     #[test]
@@ -1229,7 +2064,7 @@ mod tests {
         for i in 0u32..200 {
             let key = format!("k{:05}", i);
             let expected = format!("v{:05}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap();
             assert_eq!(
                 found,
                 Some(expected.as_bytes().to_vec()),
@@ -1238,7 +2073,7 @@ mod tests {
             );
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 200);
+        assert_eq!(tree.count(&pool).unwrap(), 200);
     }
 
     #[test]
@@ -1267,11 +2102,11 @@ mod tests {
         for i in 0u32..500 {
             let key = format!("key{:06}", i);
             let expected = format!("val{:06}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap();
             assert_eq!(found, Some(expected.as_bytes().to_vec()), "missing {}", key);
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 500);
+        assert_eq!(tree.count(&pool).unwrap(), 500);
     }
 
     // -----------------------------------------------------------------------
@@ -1289,7 +2124,7 @@ mod tests {
             tree.insert(&mut pool, k.as_bytes(), k.as_bytes()).unwrap();
         }
 
-        let entries = tree.scan_all(&mut pool).unwrap();
+        let entries = tree.scan_all(&pool).unwrap();
         let result_keys: Vec<&[u8]> = entries.iter().map(|e| e.key.as_slice()).collect();
         assert_eq!(
             result_keys,
@@ -1315,7 +2150,7 @@ mod tests {
                 .unwrap();
         }
 
-        let entries = tree.scan_all(&mut pool).unwrap();
+        let entries = tree.scan_all(&pool).unwrap();
         assert_eq!(entries.len(), 150);
 
         // Verify sorted order.
@@ -1332,6 +2167,35 @@ mod tests {
         assert_eq!(entries[149].key, b"k0149");
     }
 
+    #[test]
+    fn cursor_matches_scan_all() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+
+        for i in (0u32..300).rev() {
+            let key = format!("k{:04}", i);
+            tree.insert(&mut pool, key.as_bytes(), &[0xAB; 64]).unwrap();
+        }
+
+        let mut cursor = tree.cursor(&pool).unwrap();
+        let mut streamed = Vec::new();
+        while let Some(entry) = tree.next(&pool, &mut cursor).unwrap() {
+            streamed.push(entry);
+        }
+        assert_eq!(streamed, tree.scan_all(&pool).unwrap());
+
+        // An exhausted cursor stays exhausted.
+        assert!(tree.next(&pool, &mut cursor).unwrap().is_none());
+    }
+
+    #[test]
+    fn cursor_on_empty_tree() {
+        let (_dir, mut pool) = test_pool();
+        let tree = BTree::create(&mut pool).unwrap();
+        let mut cursor = tree.cursor(&pool).unwrap();
+        assert!(tree.next(&pool, &mut cursor).unwrap().is_none());
+    }
+
     // -----------------------------------------------------------------------
     // Scan from (range start)
     // -----------------------------------------------------------------------
@@ -1346,7 +2210,7 @@ mod tests {
             tree.insert(&mut pool, key.as_bytes(), b"v").unwrap();
         }
 
-        let entries = tree.scan_from(&mut pool, b"k0025").unwrap();
+        let entries = tree.scan_from(&pool, b"k0025").unwrap();
         assert_eq!(entries.len(), 25);
         assert_eq!(entries[0].key, b"k0025");
         assert_eq!(entries[24].key, b"k0049");
@@ -1362,7 +2226,7 @@ mod tests {
         tree.insert(&mut pool, b"f", b"6").unwrap();
 
         // Start from "c" which does not exist; should return "d" and "f".
-        let entries = tree.scan_from(&mut pool, b"c").unwrap();
+        let entries = tree.scan_from(&pool, b"c").unwrap();
         assert_eq!(entries.len(), 2);
         assert_eq!(entries[0].key, b"d");
         assert_eq!(entries[1].key, b"f");
@@ -1386,7 +2250,7 @@ mod tests {
 
         // Range [k0010, k0020) should return 10 entries.
         let entries = tree
-            .scan_range(&mut pool, b"k0010", b"k0020")
+            .scan_range(&pool, b"k0010", b"k0020")
             .unwrap();
         assert_eq!(entries.len(), 10);
         assert_eq!(entries[0].key, b"k0010");
@@ -1401,7 +2265,7 @@ mod tests {
         tree.insert(&mut pool, b"a", b"1").unwrap();
         tree.insert(&mut pool, b"z", b"2").unwrap();
 
-        let entries = tree.scan_range(&mut pool, b"m", b"n").unwrap();
+        let entries = tree.scan_range(&pool, b"m", b"n").unwrap();
         assert!(entries.is_empty());
     }
 
@@ -1415,12 +2279,12 @@ mod tests {
         let mut tree = BTree::create(&mut pool).unwrap();
 
         tree.insert(&mut pool, b"key1", b"val1").unwrap();
-        assert!(tree.search(&mut pool, b"key1").unwrap().is_some());
+        assert!(tree.search(&pool, b"key1").unwrap().is_some());
 
         let removed = tree.delete(&mut pool, b"key1").unwrap();
         assert!(removed);
-        assert!(tree.search(&mut pool, b"key1").unwrap().is_none());
-        assert_eq!(tree.count(&mut pool).unwrap(), 0);
+        assert!(tree.search(&pool, b"key1").unwrap().is_none());
+        assert_eq!(tree.count(&pool).unwrap(), 0);
     }
 
     #[test]
@@ -1431,7 +2295,7 @@ mod tests {
         tree.insert(&mut pool, b"key1", b"val1").unwrap();
         let removed = tree.delete(&mut pool, b"key_missing").unwrap();
         assert!(!removed);
-        assert_eq!(tree.count(&mut pool).unwrap(), 1);
+        assert_eq!(tree.count(&pool).unwrap(), 1);
     }
 
     #[test]
@@ -1451,7 +2315,7 @@ mod tests {
             let key = format!("k{:04}", i);
             tree.insert(&mut pool, key.as_bytes(), b"v").unwrap();
         }
-        assert_eq!(tree.count(&mut pool).unwrap(), 20);
+        assert_eq!(tree.count(&pool).unwrap(), 20);
 
         // Delete every other key.
         for i in (0u32..20).step_by(2) {
@@ -1460,12 +2324,12 @@ mod tests {
             assert!(removed, "should have deleted {}", key);
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 10);
+        assert_eq!(tree.count(&pool).unwrap(), 10);
 
         // Verify remaining keys.
         for i in 0u32..20 {
             let key = format!("k{:04}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap();
             if i % 2 == 0 {
                 assert!(found.is_none(), "{} should be deleted", key);
             } else {
@@ -1481,11 +2345,11 @@ mod tests {
 
         tree.insert(&mut pool, b"key1", b"v1").unwrap();
         tree.delete(&mut pool, b"key1").unwrap();
-        assert!(tree.search(&mut pool, b"key1").unwrap().is_none());
+        assert!(tree.search(&pool, b"key1").unwrap().is_none());
 
         tree.insert(&mut pool, b"key1", b"v2").unwrap();
         assert_eq!(
-            tree.search(&mut pool, b"key1").unwrap(),
+            tree.search(&pool, b"key1").unwrap(),
             Some(b"v2".to_vec())
         );
     }
@@ -1513,17 +2377,146 @@ mod tests {
             assert!(removed);
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 100);
+        assert_eq!(tree.count(&pool).unwrap(), 100);
 
         // The remaining 100 should still be searchable.
         for i in 100u32..200 {
             let key = format!("key{:05}", i);
             let expected = format!("val{:05}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap();
             assert_eq!(found, Some(expected.as_bytes().to_vec()));
         }
     }
 
+    #[test]
+    fn emptied_leaves_are_freed_and_reused() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        let key = |i: u32| format!("{:040}", i);
+        for i in 0..3000 {
+            tree.insert(&mut pool, key(i).as_bytes(), &[7; 200]).unwrap();
+        }
+        let root = tree.root_page();
+        let page_count = pool.pager().page_count();
+
+        // Delete in an order that empties leaves all over the tree
+        for (n, i) in (0..3000).map(|n| (n * 7) % 3000).enumerate() {
+            assert!(tree.delete(&mut pool, key(i).as_bytes()).unwrap());
+            if n % 250 == 0 {
+                let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+                assert!(report.is_ok(), "{:?}", report.errors);
+                assert_eq!(report.entries, 2999 - n as u64);
+            }
+        }
+        assert_eq!(tree.root_page(), root);
+        assert_eq!(tree.count(&pool).unwrap(), 0);
+        // Everything but the header and the root is back on the free list
+        assert_eq!(pool.pager().free_list().len(), page_count - 2);
+
+        for i in 3000..6000 {
+            tree.insert(&mut pool, key(i).as_bytes(), &[8; 200]).unwrap();
+        }
+        assert_eq!(pool.pager().page_count(), page_count);
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.entries, 3000);
+    }
+
+    #[test]
+    fn underfull_leaves_are_merged() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        let key = |i: u32| format!("key{:05}", i);
+        for i in 0..1000 {
+            tree.insert(&mut pool, key(i).as_bytes(), &[1; 100]).unwrap();
+        }
+        let pages = tree.page_count(&pool).unwrap();
+        assert_eq!(tree.height(&pool).unwrap(), 2);
+
+        // Keep every tenth row
+        for i in (0..1000).filter(|i| i % 10 != 0) {
+            assert!(tree.delete(&mut pool, key(i).as_bytes()).unwrap());
+        }
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.entries, 100);
+        assert!(tree.page_count(&pool).unwrap() * 4 < pages);
+        for i in (0..1000).step_by(10) {
+            assert_eq!(tree.search(&pool, key(i).as_bytes()).unwrap(), Some(vec![1; 100]));
+        }
+    }
+
+    #[test]
+    fn underfull_leaf_takes_entries_from_a_full_sibling() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        let key = |i: u32| format!("key{:05}", i);
+        let leaf_sizes = |pool: &BufferPool, tree: &BTree| {
+            let mut sizes = Vec::new();
+            let mut current = tree.find_leftmost_leaf(pool).unwrap();
+            while current != 0 {
+                let page = pool.read_page(current).unwrap();
+                sizes.push(cell_count(&page));
+                current = trailer(&page);
+            }
+            sizes
+        };
+        // 35 of these entries fit on a leaf; ascending inserts leave 18 on
+        // each leaf but the last
+        for i in 0..200 {
+            tree.insert(&mut pool, key(i).as_bytes(), &[2; 100]).unwrap();
+        }
+        assert_eq!(leaf_sizes(&pool, &tree)[..3], [18, 18, 18]);
+        for i in 36..53 {
+            tree.insert(&mut pool, format!("{}a", key(i)).as_bytes(), &[3; 100]).unwrap();
+        }
+        let pages = tree.page_count(&pool).unwrap();
+
+        // Down to 13 entries, the second leaf is below 40% full, and does
+        // not fit on one page with the third
+        for i in 18..23 {
+            assert!(tree.delete(&mut pool, key(i).as_bytes()).unwrap());
+        }
+        assert_eq!(leaf_sizes(&pool, &tree)[..3], [18, 24, 24]);
+        assert_eq!(tree.page_count(&pool).unwrap(), pages);
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.entries, 212);
+        assert_eq!(tree.search(&pool, b"key00040a").unwrap(), Some(vec![3; 100]));
+    }
+
+    // -----------------------------------------------------------------------
+    // Compaction
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn copy_compacted_packs_the_remaining_entries() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        for i in 0u32..3000 {
+            tree.insert(&mut pool, format!("key{:05}", i).as_bytes(), &[7u8; 24]).unwrap();
+        }
+        for i in (0u32..3000).filter(|i| i % 3 != 0) {
+            tree.delete(&mut pool, format!("key{:05}", i).as_bytes()).unwrap();
+        }
+
+        let (_dst_dir, mut dst) = test_pool();
+        let before = dst.pager().page_count();
+        let copy = tree.copy_compacted(&pool, &mut dst).unwrap();
+
+        let report = copy.check_integrity(&dst, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(copy.scan_all(&dst).unwrap(), tree.scan_all(&pool).unwrap());
+        assert_eq!(copy.search(&dst, b"key02997").unwrap(), Some(vec![7u8; 24]));
+        assert_eq!(copy.search(&dst, b"key02998").unwrap(), None);
+
+        let used = dst.pager().page_count() - before;
+        assert!(used * 2 < pool.pager().page_count(), "copy used {} pages", used);
+
+        let empty = BTree::create(&mut pool).unwrap().copy_compacted(&pool, &mut dst).unwrap();
+        assert_eq!(empty.count(&dst).unwrap(), 0);
+    }
+
     // -----------------------------------------------------------------------
     // Count
     // -----------------------------------------------------------------------
@@ -1533,17 +2526,17 @@ mod tests {
         let (_dir, mut pool) = test_pool();
         let mut tree = BTree::create(&mut pool).unwrap();
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 0);
+        assert_eq!(tree.count(&pool).unwrap(), 0);
 
         tree.insert(&mut pool, b"a", b"1").unwrap();
-        assert_eq!(tree.count(&mut pool).unwrap(), 1);
+        assert_eq!(tree.count(&pool).unwrap(), 1);
 
         tree.insert(&mut pool, b"b", b"2").unwrap();
         tree.insert(&mut pool, b"c", b"3").unwrap();
-        assert_eq!(tree.count(&mut pool).unwrap(), 3);
+        assert_eq!(tree.count(&pool).unwrap(), 3);
 
         tree.delete(&mut pool, b"b").unwrap();
-        assert_eq!(tree.count(&mut pool).unwrap(), 2);
+        assert_eq!(tree.count(&pool).unwrap(), 2);
     }
 
     // -----------------------------------------------------------------------
@@ -1561,7 +2554,7 @@ mod tests {
         // "Re-open" the tree by its root page.
         let tree2 = BTree::open(root);
         assert_eq!(tree2.root_page(), root);
-        let val = tree2.search(&mut pool, b"hello").unwrap();
+        let val = tree2.search(&pool, b"hello").unwrap();
         assert_eq!(val, Some(b"world".to_vec()));
     }
 
@@ -1584,12 +2577,85 @@ mod tests {
 
         for i in 0u32..50 {
             let key = format!("k{:04}", i);
-            let val = tree.search(&mut pool, key.as_bytes()).unwrap().unwrap();
+            let val = tree.search(&pool, key.as_bytes()).unwrap().unwrap();
             assert_eq!(val.len(), 200);
             assert!(val.iter().all(|&b| b == b'x'));
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 50);
+        assert_eq!(tree.count(&pool).unwrap(), 50);
+    }
+
+    // -----------------------------------------------------------------------
+    // Overflow pages
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn values_larger_than_a_page_use_overflow_pages() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        let big: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for i in 0u32..20 {
+            tree.insert(&mut pool, format!("k{:03}", i).as_bytes(), &big[..5000 + i as usize]).unwrap();
+        }
+        tree.insert(&mut pool, b"small", b"inline").unwrap();
+
+        let value = tree.search(&pool, b"k007").unwrap().unwrap();
+        assert_eq!(value, &big[..5007]);
+        let entries = tree.scan_all(&pool).unwrap();
+        assert_eq!(entries.len(), 21);
+        assert_eq!(entries[19].value, &big[..5019]);
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+
+        // Replacing and deleting values free their overflow pages for reuse
+        tree.insert(&mut pool, b"k000", &big).unwrap();
+        let pages = pool.pager().page_count();
+        tree.insert(&mut pool, b"k000", b"short").unwrap();
+        for i in 1u32..20 {
+            tree.delete(&mut pool, format!("k{:03}", i).as_bytes()).unwrap();
+        }
+        assert_eq!(tree.search(&pool, b"k000").unwrap(), Some(b"short".to_vec()));
+        tree.insert(&mut pool, b"again", &big).unwrap();
+        assert_eq!(pool.pager().page_count(), pages);
+        assert_eq!(tree.search(&pool, b"again").unwrap().unwrap(), big);
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+    }
+
+    #[test]
+    fn keys_larger_than_a_quarter_page_are_refused() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        let limit = pool.page_size() / 4;
+        for i in 0u8..20 {
+            let mut key = vec![b'k'; limit];
+            key[limit - 1] = i;
+            tree.insert(&mut pool, &key, b"v").unwrap();
+        }
+        let err = tree.insert(&mut pool, &vec![b'k'; limit + 1], b"v").unwrap_err();
+        assert!(err.to_string().contains("too large"), "{err}");
+        assert_eq!(tree.count(&pool).unwrap(), 20);
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+    }
+
+    #[test]
+    fn integrity_check_reports_a_shared_overflow_page() {
+        let (_dir, mut pool) = test_pool();
+        let mut tree = BTree::create(&mut pool).unwrap();
+        tree.insert(&mut pool, b"a", &[1u8; 3000]).unwrap();
+        tree.insert(&mut pool, b"b", &[2u8; 3000]).unwrap();
+
+        // Point the second value at the first value's chain
+        let root = tree.root_page();
+        let page = pool.get_page_mut(root).unwrap();
+        let (a, b) = (cell_ptr(page, 0) as usize, cell_ptr(page, 1) as usize);
+        let first_page = read_u32(page, a + 2 + 1 + 4);
+        write_u32(page, b + 2 + 1 + 4, first_page);
+        pool.unpin(root);
+
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.errors.iter().any(|e| e.contains("referenced more than once")), "{:?}", report.errors);
     }
 
     // -----------------------------------------------------------------------
@@ -1607,8 +2673,8 @@ mod tests {
                 .unwrap();
         }
 
-        assert_eq!(tree.count(&mut pool).unwrap(), 1);
-        let val = tree.search(&mut pool, b"only_key").unwrap().unwrap();
+        assert_eq!(tree.count(&pool).unwrap(), 1);
+        let val = tree.search(&pool, b"only_key").unwrap().unwrap();
         assert_eq!(val, b"version_99");
     }
 
@@ -1629,7 +2695,7 @@ mod tests {
                 .unwrap();
         }
 
-        let entries = tree.scan_all(&mut pool).unwrap();
+        let entries = tree.scan_all(&pool).unwrap();
         assert_eq!(entries.len(), n as usize);
 
         // Verify sorted.
@@ -1644,6 +2710,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn ordered_tree_keeps_keys_in_its_order() {
+        let (_dir, mut pool) = test_pool();
+        let reversed: KeyOrder = Arc::new(|a: &[u8], b: &[u8]| b.cmp(a));
+        let mut tree = BTree::create(&mut pool).unwrap().ordered_by(Some(reversed));
+
+        for i in 0..300u32 {
+            let key = format!("{:06}", i);
+            tree.insert(&mut pool, key.as_bytes(), &[0u8; 40]).unwrap();
+        }
+        for i in (0..300u32).step_by(3) {
+            assert!(tree.delete(&mut pool, format!("{:06}", i).as_bytes()).unwrap());
+        }
+
+        let keys: Vec<Vec<u8>> = tree.scan_all(&pool).unwrap().into_iter().map(|e| e.key).collect();
+        let expected: Vec<Vec<u8>> = (0..300u32).rev().filter(|i| i % 3 != 0).map(|i| format!("{:06}", i).into_bytes()).collect();
+        assert_eq!(keys, expected);
+        assert!(tree.search(&pool, b"000200").unwrap().is_some());
+        // Ranges run in the tree's order too
+        assert_eq!(tree.scan_range(&pool, b"000010", b"000005").unwrap().len(), 3);
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        // In byte order the same keys are out of order
+        assert!(!BTree::open(tree.root_page()).check_leaves(&pool).unwrap().is_ok());
+    }
+
     // -----------------------------------------------------------------------
     // Integration: mixed inserts, deletes, updates, scans
     // -----------------------------------------------------------------------
@@ -1666,7 +2758,7 @@ mod tests {
             let key = format!("item{:04}", i);
             tree.delete(&mut pool, key.as_bytes()).unwrap();
         }
-        assert_eq!(tree.count(&mut pool).unwrap(), 40);
+        assert_eq!(tree.count(&pool).unwrap(), 40);
 
         // Update keys 30..40.
         for i in 30u32..40 {
@@ -1675,10 +2767,10 @@ mod tests {
             tree.insert(&mut pool, key.as_bytes(), val.as_bytes())
                 .unwrap();
         }
-        assert_eq!(tree.count(&mut pool).unwrap(), 40);
+        assert_eq!(tree.count(&pool).unwrap(), 40);
 
         // Scan all -- should have 40 entries, sorted.
-        let entries = tree.scan_all(&mut pool).unwrap();
+        let entries = tree.scan_all(&pool).unwrap();
         assert_eq!(entries.len(), 40);
         for i in 1..entries.len() {
             assert!(entries[i - 1].key < entries[i].key);
@@ -1688,14 +2780,82 @@ mod tests {
         for i in 30u32..40 {
             let key = format!("item{:04}", i);
             let expected = format!("updated_{}", i);
-            let found = tree.search(&mut pool, key.as_bytes()).unwrap().unwrap();
+            let found = tree.search(&pool, key.as_bytes()).unwrap().unwrap();
             assert_eq!(found, expected.as_bytes());
         }
 
         // Verify deleted keys are gone.
         for i in 10u32..20 {
             let key = format!("item{:04}", i);
-            assert!(tree.search(&mut pool, key.as_bytes()).unwrap().is_none());
+            assert!(tree.search(&pool, key.as_bytes()).unwrap().is_none());
         }
     }
+
+    // -----------------------------------------------------------------------
+    // Integrity checks
+    // -----------------------------------------------------------------------
+
+    fn tree_with_splits(pool: &mut BufferPool) -> BTree {
+        let mut tree = BTree::create(pool).unwrap();
+        for i in 0..500u32 {
+            let key = format!("{:06}", i);
+            tree.insert(pool, key.as_bytes(), &[0u8; 40]).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn integrity_check_passes_on_a_sound_tree() {
+        let (_dir, mut pool) = test_pool();
+        let tree = tree_with_splits(&mut pool);
+
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.entries, 500);
+        let report = tree.check_leaves(&pool).unwrap();
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.entries, 500);
+    }
+
+    #[test]
+    fn integrity_check_reports_keys_out_of_order() {
+        let (_dir, mut pool) = test_pool();
+        let tree = tree_with_splits(&mut pool);
+
+        // Swap the first two cell pointers of the first leaf
+        let leaf = tree.find_leftmost_leaf(&pool).unwrap();
+        let page = pool.get_page_mut(leaf).unwrap();
+        let (first, second) = (cell_ptr(page, 0), cell_ptr(page, 1));
+        set_cell_ptr(page, 0, second);
+        set_cell_ptr(page, 1, first);
+        pool.mark_dirty(leaf);
+        pool.unpin(leaf);
+
+        let expected = vec![format!("page {}: keys out of order", leaf)];
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert_eq!(report.errors, expected);
+        assert_eq!(tree.check_leaves(&pool).unwrap().errors, expected);
+    }
+
+    #[test]
+    fn integrity_check_reports_bad_child_pointers() {
+        let (_dir, mut pool) = test_pool();
+        let tree = tree_with_splits(&mut pool);
+        let root = tree.root_page();
+        assert_eq!(page_type(&pool.read_page(root).unwrap()), PAGE_TYPE_INTERNAL);
+
+        let page = pool.get_page_mut(root).unwrap();
+        set_trailer(page, 9999);
+        pool.mark_dirty(root);
+        pool.unpin(root);
+
+        let report = tree.check_integrity(&pool, &mut HashSet::new()).unwrap();
+        assert!(report.errors.contains(&"page 9999 is out of range".to_string()));
+
+        // A second tree sharing pages with the first is caught too
+        let mut seen = HashSet::new();
+        tree.check_integrity(&pool, &mut seen).unwrap();
+        let report = BTree::open(root).check_integrity(&pool, &mut seen).unwrap();
+        assert_eq!(report.errors, vec![format!("page {} is referenced more than once", root)]);
+    }
 }
@@ -2,28 +2,32 @@
 //!
 //! The buffer pool is an in-memory page cache that sits between the
 //! B+Tree layer and the Pager/WAL layer. It caches frequently accessed
-//! pages and uses LRU eviction when the pool is full.
+//! pages and uses LRU eviction when the pool is full: the least recently
+//! used unpinned page is dropped, after writing it to the WAL (or the
+//! database file) if it is dirty.
 //!
 //! All page access in the system goes through the buffer pool.
 
 use crate::error::{HorizonError, Result};
-use crate::pager::{PageId, Pager, PAGE_SIZE};
-use crate::wal::WalManager;
-use std::collections::HashMap;
+use crate::pager::{FreeListPages, PageId, Pager};
+use crate::wal::{
+    CheckpointMode, CheckpointResult, JournalMode, RollbackJournal, SynchronousMode, WalManager,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// A page frame in the buffer pool.
 #[derive(Debug)]
 struct Frame {
     /// The page data.
-    data: Box<[u8; PAGE_SIZE]>,
+    data: Box<[u8]>,
     /// The page ID this frame holds.
     page_id: PageId,
     /// Whether this page has been modified.
     dirty: bool,
     /// Reference count (number of active pins).
     pin_count: u32,
-    /// LRU timestamp (higher = more recently used).
-    last_accessed: u64,
 }
 
 /// A handle to a pinned page in the buffer pool.
@@ -36,7 +40,7 @@ pub struct PageHandle<'a> {
 
 impl<'a> PageHandle<'a> {
     /// Get a read-only reference to the page data.
-    pub fn data(&self) -> &[u8; PAGE_SIZE] {
+    pub fn data(&self) -> &[u8] {
         &self.pool.frames[self.frame_index].data
     }
 
@@ -63,14 +67,14 @@ pub struct PageHandleMut<'a> {
 
 impl<'a> PageHandleMut<'a> {
     /// Get a read-only reference to the page data.
-    pub fn data(&self) -> &[u8; PAGE_SIZE] {
+    pub fn data(&self) -> &[u8] {
         &self.pool.frames[self.frame_index].data
     }
 
     /// Get a mutable reference to the page data.
     /// Automatically marks the page as dirty.
-    pub fn data_mut(&mut self) -> &mut [u8; PAGE_SIZE] {
-        self.pool.frames[self.frame_index].dirty = true;
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.pool.frames[self.frame_index].dirty = !self.pool.pager.is_read_only();
         &mut self.pool.frames[self.frame_index].data
     }
 
@@ -92,8 +96,15 @@ pub struct BufferPool {
     capacity: usize,
     /// Mapping from page_id to frame index.
     page_table: HashMap<PageId, usize>,
-    /// Monotonic counter for LRU ordering.
-    access_counter: u64,
+    /// The cached pages, from least to most recently used.
+    lru_order: VecDeque<PageId>,
+    /// Frames released by [`free_page`](Self::free_page), reused before
+    /// any page is evicted.
+    free_frames: Vec<usize>,
+    /// When the WAL or rollback journal is synced.
+    synchronous: SynchronousMode,
+    /// The number of page reads served, cached or not.
+    page_accesses: AtomicU64,
 }
 
 impl BufferPool {
@@ -105,17 +116,20 @@ impl BufferPool {
             frames: Vec::with_capacity(capacity),
             capacity,
             page_table: HashMap::with_capacity(capacity),
-            access_counter: 0,
+            lru_order: VecDeque::with_capacity(capacity),
+            free_frames: Vec::new(),
+            synchronous: SynchronousMode::default(),
+            page_accesses: AtomicU64::new(0),
         })
     }
 
     /// Fetch a page into the buffer pool, returning its frame index.
     /// If the page is already cached, returns the existing frame.
     fn fetch_page(&mut self, page_id: PageId) -> Result<usize> {
+        *self.page_accesses.get_mut() += 1;
         // Check if already in buffer pool
         if let Some(&frame_idx) = self.page_table.get(&page_id) {
-            self.access_counter += 1;
-            self.frames[frame_idx].last_accessed = self.access_counter;
+            self.touch(page_id);
             self.frames[frame_idx].pin_count += 1;
             return Ok(frame_idx);
         }
@@ -131,55 +145,76 @@ impl BufferPool {
             self.pager.read_page(page_id)?
         };
 
-        // Find a frame to use
-        let frame_idx = if self.frames.len() < self.capacity {
-            // Pool not full, allocate a new frame
-            let idx = self.frames.len();
-            self.frames.push(Frame {
-                data: Box::new(data),
-                page_id,
-                dirty: false,
-                pin_count: 1,
-                last_accessed: 0,
-            });
+        self.install(Frame {
+            data: data.into_boxed_slice(),
+            page_id,
+            dirty: false,
+            pin_count: 1,
+        })
+    }
+
+    /// Put a frame for a page that is not cached yet into the pool,
+    /// evicting another page if the pool is full, and return its index.
+    fn install(&mut self, frame: Frame) -> Result<usize> {
+        let page_id = frame.page_id;
+        let frame_idx = if let Some(idx) = self.free_frames.pop() {
+            self.frames[idx] = frame;
             idx
+        } else if self.frames.len() < self.capacity {
+            self.frames.push(frame);
+            self.frames.len() - 1
         } else {
-            // Evict LRU unpinned frame
-            self.evict_one()?
+            let idx = self.evict_one()?;
+            self.frames[idx] = frame;
+            idx
         };
-
-        // Update frame
-        if frame_idx < self.frames.len() {
-            // Remove old mapping if we're reusing a frame
-            let old_page_id = self.frames[frame_idx].page_id;
-            if self.page_table.get(&old_page_id) == Some(&frame_idx) {
-                self.page_table.remove(&old_page_id);
-            }
-
-            self.frames[frame_idx].data = Box::new(data);
-            self.frames[frame_idx].page_id = page_id;
-            self.frames[frame_idx].dirty = false;
-            self.frames[frame_idx].pin_count = 1;
-        }
-
-        self.access_counter += 1;
-        self.frames[frame_idx].last_accessed = self.access_counter;
         self.page_table.insert(page_id, frame_idx);
-
+        self.lru_order.push_back(page_id);
         Ok(frame_idx)
     }
 
+    /// Move a cached page to the most recently used end of the LRU queue.
+    fn touch(&mut self, page_id: PageId) {
+        if self.lru_order.back() == Some(&page_id) {
+            return;
+        }
+        if let Some(pos) = self.lru_order.iter().position(|&id| id == page_id) {
+            self.lru_order.remove(pos);
+        }
+        self.lru_order.push_back(page_id);
+    }
+
     /// Get a page for reading.
-    pub fn get_page(&mut self, page_id: PageId) -> Result<&[u8; PAGE_SIZE]> {
+    pub fn get_page(&mut self, page_id: PageId) -> Result<&[u8]> {
         let frame_idx = self.fetch_page(page_id)?;
         Ok(&self.frames[frame_idx].data)
     }
 
+    /// Read a page through a shared borrow.
+    ///
+    /// A cached page is returned in place; otherwise it is read from the WAL
+    /// or the database file without being brought into the pool. This is
+    /// the read path used by concurrent readers, which cannot pin or evict
+    /// frames.
+    pub fn read_page(&self, page_id: PageId) -> Result<Cow<'_, [u8]>> {
+        self.page_accesses.fetch_add(1, Ordering::Relaxed);
+        if let Some(&frame_idx) = self.page_table.get(&page_id) {
+            return Ok(Cow::Borrowed(&self.frames[frame_idx].data));
+        }
+        if let Some(ref wal) = self.wal {
+            if let Some(wal_data) = wal.read_page(page_id)? {
+                return Ok(Cow::Owned(wal_data));
+            }
+        }
+        Ok(Cow::Owned(self.pager.read_page(page_id)?))
+    }
+
     /// Get a mutable reference to a page for writing.
-    /// The page is automatically marked as dirty.
-    pub fn get_page_mut(&mut self, page_id: PageId) -> Result<&mut [u8; PAGE_SIZE]> {
+    /// The page is automatically marked as dirty, unless the pager is
+    /// read-only: then changes stay in the cache and are never written.
+    pub fn get_page_mut(&mut self, page_id: PageId) -> Result<&mut [u8]> {
         let frame_idx = self.fetch_page(page_id)?;
-        self.frames[frame_idx].dirty = true;
+        self.frames[frame_idx].dirty = !self.pager.is_read_only();
         Ok(&mut self.frames[frame_idx].data)
     }
 
@@ -192,65 +227,51 @@ impl BufferPool {
         }
     }
 
-    /// Allocate a new page through the pager and bring it into the pool.
+    /// Allocate a new page and bring it into the pool. A page from the
+    /// pager's free list is reused if there is one; otherwise the file is
+    /// extended.
     pub fn allocate_page(&mut self) -> Result<PageId> {
-        let page_id = self.pager.allocate_page()?;
-
-        // Bring the new (zeroed) page into the buffer pool
-        let data = [0u8; PAGE_SIZE];
-
-        let frame_idx = if self.frames.len() < self.capacity {
-            let idx = self.frames.len();
-            self.frames.push(Frame {
-                data: Box::new(data),
-                page_id,
-                dirty: true,
-                pin_count: 1,
-                last_accessed: 0,
-            });
-            idx
-        } else {
-            let idx = self.evict_one()?;
-            let old_page_id = self.frames[idx].page_id;
-            if self.page_table.get(&old_page_id) == Some(&idx) {
-                self.page_table.remove(&old_page_id);
+        let mut free_list = self.pager.free_list();
+        let page_id = match free_list.pop(self)? {
+            Some(page_id) => {
+                self.pager.set_free_list(free_list)?;
+                // A trunk page may still be cached
+                self.discard(page_id);
+                page_id
             }
-            self.frames[idx] = Frame {
-                data: Box::new(data),
-                page_id,
-                dirty: true,
-                pin_count: 1,
-                last_accessed: 0,
-            };
-            idx
+            None => self.pager.allocate_page()?,
         };
 
-        self.access_counter += 1;
-        self.frames[frame_idx].last_accessed = self.access_counter;
-        self.page_table.insert(page_id, frame_idx);
+        // Bring the new (zeroed) page into the buffer pool
+        let data = vec![0u8; self.pager.page_size()].into_boxed_slice();
+        self.install(Frame {
+            data,
+            page_id,
+            dirty: true,
+            pin_count: 1,
+        })?;
 
         Ok(page_id)
     }
 
-    /// Evict one unpinned frame using LRU policy.
-    /// Returns the frame index that was evicted.
+    /// Evict the least recently used unpinned page, writing it out first
+    /// if it is dirty. Returns the index of the frame it occupied.
     fn evict_one(&mut self) -> Result<usize> {
-        // Find the LRU unpinned frame
-        let victim = self
-            .frames
+        let pos = self
+            .lru_order
             .iter()
-            .enumerate()
-            .filter(|(_, f)| f.pin_count == 0)
-            .min_by_key(|(_, f)| f.last_accessed)
-            .map(|(idx, _)| idx);
-
-        let victim_idx = victim.ok_or(HorizonError::BufferPoolFull)?;
+            .position(|id| self.frames[self.page_table[id]].pin_count == 0)
+            .ok_or(HorizonError::BufferPoolFull)?;
+        let page_id = self.lru_order[pos];
+        let victim_idx = self.page_table[&page_id];
 
         // If dirty, flush to WAL/disk
         if self.frames[victim_idx].dirty {
             self.flush_frame(victim_idx)?;
         }
 
+        self.lru_order.remove(pos);
+        self.page_table.remove(&page_id);
         Ok(victim_idx)
     }
 
@@ -279,18 +300,126 @@ impl BufferPool {
 
     /// Flush all dirty pages to disk.
     pub fn flush_all(&mut self) -> Result<()> {
+        self.checkpoint(CheckpointMode::Restart)?;
+        Ok(())
+    }
+
+    /// Write all dirty pages to the WAL, then checkpoint it into the
+    /// database file. Without a WAL, pages are written directly and the
+    /// result reports no frames.
+    ///
+    /// If writing a page to the database file fails, the pager rolls the
+    /// file back from its rollback journal (when it has one) and the cached
+    /// pages are dropped, so the pool again shows the last commit.
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> Result<CheckpointResult> {
         for i in 0..self.frames.len() {
             if self.frames[i].dirty {
-                self.flush_frame(i)?;
+                if let Err(e) = self.flush_frame(i) {
+                    // The error that stopped the flush is the one reported
+                    if let Ok(true) = self.pager.rollback() {
+                        self.drop_cached_pages();
+                    }
+                    return Err(e);
+                }
             }
         }
 
+        let result = match self.wal {
+            Some(ref mut wal) => {
+                wal.commit()?;
+                wal.checkpoint(&mut self.pager, mode)?
+            }
+            None => CheckpointResult::default(),
+        };
+
+        if let Err(e) = self.pager.sync() {
+            // The pager has rolled the file back from its journal
+            if self.pager.journal_mode().is_some() {
+                self.drop_cached_pages();
+            }
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    /// Whether another handle has changed the database file or its WAL
+    /// since this pool's pager and WAL last saw them, so that cached pages
+    /// may be out of date.
+    pub fn is_stale(&self) -> Result<bool> {
+        if self.pager.is_stale()? {
+            return Ok(true);
+        }
+        match self.wal {
+            Some(ref wal) => wal.is_stale(),
+            None => Ok(false),
+        }
+    }
+
+    /// How changes reach the database file. In-memory databases report
+    /// `Memory`.
+    pub fn journal_mode(&self) -> JournalMode {
+        if self.wal.is_some() {
+            JournalMode::Wal
+        } else if self.pager.path().is_none() {
+            JournalMode::Memory
+        } else {
+            self.pager.journal_mode().unwrap_or(JournalMode::Off)
+        }
+    }
+
+    /// Switch to journal mode `mode` and return the mode now in use.
+    ///
+    /// Every change is committed first: dirty pages are written and the
+    /// WAL is checkpointed. Leaving WAL mode deletes the WAL file, and
+    /// entering it creates a new one. An in-memory database keeps its
+    /// `Memory` mode.
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> Result<JournalMode> {
+        let db_path = match self.pager.path() {
+            Some(path) => path.to_path_buf(),
+            None => return Ok(JournalMode::Memory),
+        };
+        if mode == self.journal_mode() {
+            return Ok(mode);
+        }
+        self.checkpoint(CheckpointMode::Truncate)?;
+
+        let wal_path = db_path.with_extension("hdb-wal");
+        if let Some(wal) = self.wal.take() {
+            drop(wal);
+            std::fs::remove_file(&wal_path)?;
+        }
+        let journal = match mode {
+            JournalMode::Delete => Some(RollbackJournal::file(&db_path)),
+            JournalMode::Memory => Some(RollbackJournal::memory()),
+            JournalMode::Wal | JournalMode::Off => None,
+        };
+        self.pager.set_journal(journal)?;
+        if mode == JournalMode::Wal {
+            self.wal = Some(WalManager::open_with_page_size(&wal_path, self.page_size())?);
+        }
+        self.set_synchronous(self.synchronous);
+        Ok(mode)
+    }
+
+    /// When the WAL, the rollback journal, and the database file are
+    /// synced.
+    pub fn synchronous(&self) -> SynchronousMode {
+        self.synchronous
+    }
+
+    /// Set when the WAL, the rollback journal, and the database file are
+    /// synced.
+    pub fn set_synchronous(&mut self, mode: SynchronousMode) {
+        self.synchronous = mode;
         if let Some(ref mut wal) = self.wal {
-            wal.checkpoint(&mut self.pager)?;
+            wal.set_synchronous(mode);
         }
+        self.pager.set_synchronous(mode);
+    }
 
-        self.pager.sync()?;
-        Ok(())
+    /// Size of every page in bytes.
+    pub fn page_size(&self) -> usize {
+        self.pager.page_size()
     }
 
     /// Get a reference to the underlying pager.
@@ -323,23 +452,143 @@ impl BufferPool {
         self.capacity
     }
 
-    /// Mark a page as dirty (modified).
+    /// The number of times a page has been read or fetched for writing
+    /// through this pool, whether or not it was cached.
+    pub fn page_accesses(&self) -> u64 {
+        self.page_accesses.load(Ordering::Relaxed)
+    }
+
+    /// Change the capacity of the buffer pool to `capacity` pages.
+    ///
+    /// Growing only raises the limit. Shrinking evicts unpinned frames,
+    /// least recently used first, until the pool fits: clean frames go
+    /// first, and dirty ones are flushed before they are evicted. Pinned
+    /// frames stay, and are evicted by later fetches once unpinned.
+    pub fn resize(&mut self, capacity: usize) -> Result<()> {
+        if capacity == 0 {
+            return Err(HorizonError::InvalidSql("cache size must be at least 1 page".into()));
+        }
+        self.capacity = capacity;
+        let excess = self.frames.len().saturating_sub(capacity);
+        if excess == 0 {
+            return Ok(());
+        }
+
+        // Freed frames are dropped first, then pages in LRU order
+        let free: HashSet<usize> = self.free_frames.drain(..).collect();
+        let rank: HashMap<PageId, usize> =
+            self.lru_order.iter().enumerate().map(|(pos, &id)| (id, pos)).collect();
+        let mut victims: Vec<usize> = (0..self.frames.len())
+            .filter(|&i| self.frames[i].pin_count == 0)
+            .collect();
+        victims.sort_by_key(|&i| {
+            let frame = &self.frames[i];
+            match free.contains(&i) {
+                true => (false, false, 0),
+                false => (true, frame.dirty, rank[&frame.page_id]),
+            }
+        });
+        victims.truncate(excess);
+        for &i in &victims {
+            self.flush_frame(i)?;
+        }
+
+        let mut evicted = vec![false; self.frames.len()];
+        for &i in &victims {
+            evicted[i] = true;
+        }
+        let old_frames = std::mem::take(&mut self.frames);
+        self.page_table.clear();
+        for (i, frame) in old_frames.into_iter().enumerate() {
+            if evicted[i] {
+                continue;
+            }
+            if free.contains(&i) {
+                self.free_frames.push(self.frames.len());
+            } else {
+                self.page_table.insert(frame.page_id, self.frames.len());
+            }
+            self.frames.push(frame);
+        }
+        let page_table = &self.page_table;
+        self.lru_order.retain(|id| page_table.contains_key(id));
+        Ok(())
+    }
+
+    /// Mark a page as dirty (modified). Does nothing if the pager is
+    /// read-only.
     pub fn mark_dirty(&mut self, page_id: PageId) {
+        if self.pager.is_read_only() {
+            return;
+        }
         if let Some(&frame_idx) = self.page_table.get(&page_id) {
             self.frames[frame_idx].dirty = true;
         }
     }
 
     /// Free a page (return it to the pager's free list).
+    ///
+    /// The free list's trunk pages are updated through the pool, so in WAL
+    /// mode they are logged like any other page.
     pub fn free_page(&mut self, page_id: PageId) -> Result<()> {
-        // Remove from buffer pool if cached
-        if let Some(&frame_idx) = self.page_table.get(&page_id) {
+        if self.pager.is_read_only() {
+            return Err(HorizonError::ReadOnly("cannot mutate a read-only database".into()));
+        }
+        self.discard(page_id);
+        let mut free_list = self.pager.free_list();
+        free_list.push(page_id, self)?;
+        self.pager.set_free_list(free_list)
+    }
+
+    /// Write out all dirty pages, then shrink the database to its first
+    /// `page_count` pages and empty the free list; see
+    /// [`Pager::truncate`].
+    pub fn truncate(&mut self, page_count: PageId) -> Result<()> {
+        self.flush_all()?;
+        let dropped: Vec<PageId> = self.page_table.keys().copied().filter(|&id| id >= page_count).collect();
+        for page_id in dropped {
+            self.discard(page_id);
+        }
+        self.pager.truncate(page_count)?;
+        self.pager.sync()
+    }
+
+    /// Drop every cached page, after the database file was rolled back
+    /// to a state they are newer than.
+    fn drop_cached_pages(&mut self) {
+        let cached: Vec<PageId> = self.page_table.keys().copied().collect();
+        for page_id in cached {
+            self.discard(page_id);
+        }
+    }
+
+    /// Drop a page from the pool without writing it.
+    fn discard(&mut self, page_id: PageId) {
+        if let Some(frame_idx) = self.page_table.remove(&page_id) {
             self.frames[frame_idx].dirty = false;
             self.frames[frame_idx].pin_count = 0;
-            self.page_table.remove(&page_id);
+            self.lru_order.retain(|&id| id != page_id);
+            self.free_frames.push(frame_idx);
         }
+    }
+}
 
-        self.pager.free_page(page_id)?;
+impl FreeListPages for BufferPool {
+    fn page_size(&self) -> usize {
+        self.pager.page_size()
+    }
+
+    fn page_count(&self) -> u32 {
+        self.pager.page_count()
+    }
+
+    fn read_trunk(&mut self, page_id: PageId) -> Result<Vec<u8>> {
+        Ok(self.read_page(page_id)?.into_owned())
+    }
+
+    fn write_trunk(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.get_page_mut(page_id)?.copy_from_slice(data);
+        self.unpin(page_id);
         Ok(())
     }
 }
@@ -407,6 +656,54 @@ mod tests {
         assert_eq!(pool.size(), 3);
     }
 
+    #[test]
+    fn test_eviction_follows_lru_order() {
+        let (_dir, mut pool) = test_pool(3);
+        let pages: Vec<PageId> = (0..3)
+            .map(|_| {
+                let p = pool.allocate_page().unwrap();
+                pool.unpin(p);
+                p
+            })
+            .collect();
+
+        // Using the oldest page makes the second one the eviction victim
+        pool.get_page(pages[0]).unwrap();
+        pool.unpin(pages[0]);
+        let p4 = pool.allocate_page().unwrap();
+        pool.unpin(p4);
+        assert!(pool.page_table.contains_key(&pages[0]));
+        assert!(!pool.page_table.contains_key(&pages[1]));
+        assert_eq!(pool.lru_order, VecDeque::from(vec![pages[2], pages[0], p4]));
+    }
+
+    #[test]
+    fn test_eviction_writes_dirty_pages_to_wal() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.hdb");
+        let pager = Pager::open(&db_path, false).unwrap();
+        let wal = WalManager::open(&dir.path().join("test.hdb-wal")).unwrap();
+        let mut pool = BufferPool::new(pager, Some(wal), 4).unwrap();
+
+        let mut pages = Vec::new();
+        for i in 0..8u8 {
+            let p = pool.allocate_page().unwrap();
+            pool.get_page_mut(p).unwrap()[0] = i;
+            pool.unpin(p);
+            pool.unpin(p);
+            pages.push(p);
+        }
+        assert_eq!(pool.size(), 4);
+        assert_eq!(pool.wal().unwrap().frame_count(), 4);
+
+        for _ in 0..3 {
+            for (i, &p) in pages.iter().enumerate() {
+                assert_eq!(pool.get_page(p).unwrap()[0], i as u8);
+                pool.unpin(p);
+            }
+        }
+    }
+
     #[test]
     fn test_flush_all() {
         let (_dir, mut pool) = test_pool(10);
@@ -421,6 +718,27 @@ mod tests {
         pool.flush_all().unwrap();
     }
 
+    #[test]
+    fn test_read_page_shared() {
+        let (_dir, mut pool) = test_pool(2);
+
+        let p1 = pool.allocate_page().unwrap();
+        pool.get_page_mut(p1).unwrap()[0] = 7;
+        pool.unpin(p1);
+        pool.unpin(p1);
+
+        // Push p1 out of the pool so it must be read back from disk.
+        for _ in 0..2 {
+            let p = pool.allocate_page().unwrap();
+            pool.unpin(p);
+        }
+        assert!(!pool.page_table.contains_key(&p1));
+
+        let pool = &pool;
+        assert_eq!(pool.read_page(p1).unwrap()[0], 7);
+        assert_eq!(pool.size(), 2);
+    }
+
     #[test]
     fn test_buffer_pool_full_error() {
         let (_dir, mut pool) = test_pool(2);
@@ -434,4 +752,58 @@ mod tests {
         let result = pool.allocate_page();
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_resize() {
+        let (_dir, mut pool) = test_pool(10);
+        let mut pages = Vec::new();
+        for i in 0..10u8 {
+            let p = pool.allocate_page().unwrap();
+            pool.get_page_mut(p).unwrap()[0] = i;
+            pool.unpin(p);
+            pool.unpin(p);
+            pages.push(p);
+        }
+        // Keep the oldest page pinned; it survives the shrink
+        pool.get_page(pages[0]).unwrap();
+
+        pool.resize(5).unwrap();
+        assert_eq!(pool.capacity(), 5);
+        assert_eq!(pool.size(), 5);
+        assert!(pool.page_table.contains_key(&pages[0]));
+        assert!(pool.page_table.contains_key(&pages[9]));
+        assert!(!pool.page_table.contains_key(&pages[1]));
+        for (i, &p) in pages.iter().enumerate() {
+            assert_eq!(pool.get_page(p).unwrap()[0], i as u8);
+            pool.unpin(p);
+            assert!(pool.size() <= 5);
+        }
+
+        pool.resize(100).unwrap();
+        for _ in 0..50 {
+            let p = pool.allocate_page().unwrap();
+            pool.unpin(p);
+        }
+        assert_eq!(pool.size(), 55);
+        assert!(pool.resize(0).is_err());
+    }
+
+    #[test]
+    fn test_read_only_pool_never_writes() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.hdb");
+        let mut pager = Pager::open(&db_path, false).unwrap();
+        let page_id = pager.allocate_page().unwrap();
+        pager.write_page(page_id, &vec![5u8; pager.page_size()]).unwrap();
+        drop(pager);
+
+        let pager = Pager::open(&db_path, true).unwrap();
+        let mut pool = BufferPool::new(pager, None, 2).unwrap();
+        pool.get_page_mut(page_id).unwrap()[0] = 9;
+        pool.unpin(page_id);
+        pool.mark_dirty(page_id);
+        assert!(pool.frames.iter().all(|f| !f.dirty));
+        pool.flush_all().unwrap();
+        assert_eq!(pool.pager().read_page(page_id).unwrap()[0], 5);
+    }
 }
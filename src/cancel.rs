@@ -0,0 +1,116 @@
+//! Cancelling long-running queries.
+//!
+//! A query started with [`Database::query_cancellable`](crate::Database::query_cancellable)
+//! runs with its cancel flag installed for the current thread. Scan loops
+//! call `check` as they go (every `CHECK_INTERVAL` rows, or once per
+//! B+Tree leaf) and stop with [`HorizonError::Cancelled`] once the flag is
+//! set, so the flag can be raised from any other thread.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{HorizonError, Result};
+
+/// How many rows a loop processes between two checks of the cancel flag.
+pub(crate) const CHECK_INTERVAL: usize = 1000;
+
+thread_local! {
+    static CANCEL_FLAG: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// A handle for cancelling a query from another thread.
+///
+/// Clones share the same flag. Pass [`QueryHandle::flag`] to
+/// [`Database::query_cancellable`](crate::Database::query_cancellable) and
+/// call [`QueryHandle::cancel`] to stop the query.
+#[derive(Debug, Clone, Default)]
+pub struct QueryHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl QueryHandle {
+    /// Create a handle whose query has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the query to stop. It returns [`HorizonError::Cancelled`] at
+    /// its next check of the flag.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`cancel`](Self::cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// The shared flag that [`cancel`](Self::cancel) sets.
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.flag.clone()
+    }
+}
+
+/// Run `f` with `flag` as the current thread's cancel flag.
+pub(crate) fn with_cancel_flag<T>(flag: Arc<AtomicBool>, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous flag even if `f` panics.
+    struct Restore(Option<Arc<AtomicBool>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CANCEL_FLAG.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let previous = CANCEL_FLAG.with(|current| current.borrow_mut().replace(flag));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Fail with [`HorizonError::Cancelled`] if the current thread's query has
+/// been cancelled.
+pub(crate) fn check() -> Result<()> {
+    let cancelled = CANCEL_FLAG.with(|current| {
+        current.borrow().as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    });
+    if cancelled {
+        Err(HorizonError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// [`check`] on every [`CHECK_INTERVAL`]-th value of the loop counter `i`.
+#[inline]
+pub(crate) fn check_every(i: usize) -> Result<()> {
+    if i % CHECK_INTERVAL == 0 {
+        check()
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_applies_only_inside_the_closure() {
+        let handle = QueryHandle::new();
+        handle.cancel();
+        assert!(check().is_ok());
+        let result = with_cancel_flag(handle.flag(), check);
+        assert!(matches!(result, Err(HorizonError::Cancelled)));
+        assert!(check().is_ok());
+
+        let clear = QueryHandle::new();
+        with_cancel_flag(clear.flag(), || {
+            assert!(check().is_ok());
+            assert!(check_every(1).is_ok());
+            clear.cancel();
+            assert!(check_every(1).is_ok());
+            assert!(check_every(CHECK_INTERVAL).is_err());
+        });
+    }
+}
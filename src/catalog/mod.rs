@@ -19,13 +19,24 @@
 //!   root page, next rowid).
 //! - [`ColumnInfo`]: Schema description of a single column within a table.
 //! - [`IndexInfo`]: Schema description of a secondary index.
+//! - [`stats::ColumnStats`]: Column statistics gathered by `ANALYZE`.
+//! - [`stats::QueryCounters`]: How often each table is scanned.
+
+pub mod stats;
 
 use std::collections::HashMap;
 use crate::btree::BTree;
 use crate::buffer::BufferPool;
 use crate::error::{HorizonError, Result};
 use crate::pager::PageId;
-use crate::sql::ast::Expr;
+use crate::execution::collation::{CollationFn, Collations};
+use crate::execution::fts5::SharedTokenizer;
+use crate::execution::dump::sql_literal;
+use crate::execution::join::DEFAULT_JOIN_SPILL_THRESHOLD;
+use crate::execution::sort::DEFAULT_SORT_SPILL_THRESHOLD;
+use crate::execution::{eval_const_expr, expr_to_sql};
+use crate::sql::ast::{Expr, FkAction};
+use crate::sql::parser::Parser;
 use crate::types::{DataType, Value, determine_affinity};
 
 /// Metadata for a single column in a table.
@@ -55,6 +66,8 @@ pub struct ColumnInfo {
     /// Whether the generated column is STORED (true) or VIRTUAL (false).
     /// Only meaningful when `generated_expr` is `Some`.
     pub is_stored: bool,
+    /// Column-level `CHECK` constraint expression.
+    pub check_expr: Option<Expr>,
 }
 
 /// Metadata for a table.
@@ -69,8 +82,30 @@ pub struct TableInfo {
     /// The next rowid to assign for an `INSERT` without an explicit rowid.
     pub next_rowid: i64,
     /// Index of the primary key column within [`columns`](Self::columns),
-    /// if any.
+    /// if the table has a single-column primary key.
     pub pk_column: Option<usize>,
+    /// Indices of all primary key columns, in key order. Holding more than
+    /// one entry means a composite key, which is then also the B+Tree key.
+    pub pk_columns: Vec<usize>,
+    /// Table-level `CHECK` constraint expressions.
+    pub checks: Vec<Expr>,
+    /// Foreign keys declared on this (child) table.
+    pub foreign_keys: Vec<ForeignKeyInfo>,
+}
+
+/// A foreign key from columns of a child table to a parent table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyInfo {
+    /// Referencing columns in the child table.
+    pub columns: Vec<String>,
+    /// The referenced (parent) table.
+    pub ref_table: String,
+    /// Referenced parent columns; empty means the parent's primary key.
+    pub ref_columns: Vec<String>,
+    /// Action taken on child rows when the parent row is deleted.
+    pub on_delete: FkAction,
+    /// Action taken on child rows when the parent key changes.
+    pub on_update: FkAction,
 }
 
 impl TableInfo {
@@ -83,6 +118,11 @@ impl TableInfo {
     pub fn find_column_index(&self, name: &str) -> Option<usize> {
         self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
     }
+
+    /// Whether the primary key spans more than one column.
+    pub fn has_composite_pk(&self) -> bool {
+        self.pk_columns.len() > 1
+    }
 }
 
 /// Metadata for an index.
@@ -92,12 +132,25 @@ pub struct IndexInfo {
     pub name: String,
     /// Name of the table this index belongs to.
     pub table_name: String,
-    /// Ordered list of column names that form the index key.
+    /// Ordered list of column names that form the index key. For an
+    /// expression key part this holds the expression's SQL text.
     pub columns: Vec<String>,
     /// Whether this is a unique index.
     pub unique: bool,
     /// Root page of the index's B+Tree.
     pub root_page: PageId,
+    /// `WHERE` predicate of a partial index; only matching rows are indexed.
+    pub predicate: Option<Expr>,
+    /// The indexed expression of each key part that is not a plain column.
+    /// May be shorter than `columns`; missing entries are plain columns.
+    pub expressions: Vec<Option<Expr>>,
+}
+
+impl IndexInfo {
+    /// The expression indexed by key part `i`, or `None` for a plain column.
+    pub fn key_expr(&self, i: usize) -> Option<&Expr> {
+        self.expressions.get(i).and_then(Option::as_ref)
+    }
 }
 
 /// Metadata for a view.
@@ -124,6 +177,9 @@ pub struct TriggerInfo {
     pub table: String,
     /// Whether the trigger fires for each row.
     pub for_each_row: bool,
+    /// The `WHEN` condition, which may refer to the `NEW` and `OLD` rows;
+    /// the trigger only fires for rows where it is true.
+    pub when: Option<Expr>,
     /// The SQL text of the trigger body statements (stored as raw SQL).
     pub body_sql: Vec<String>,
 }
@@ -166,6 +222,21 @@ pub struct RTreeInfo {
     pub root_page: PageId,
 }
 
+/// Metadata for a full-text index on a table, created with
+/// `Database::create_fulltext_index`.
+#[derive(Debug, Clone)]
+pub struct FulltextIndexInfo {
+    /// The indexed table.
+    pub table: String,
+    /// The FTS5 shadow table holding the indexed text, keyed by the
+    /// table's INTEGER PRIMARY KEY.
+    pub fts_table: String,
+    /// The table's INTEGER PRIMARY KEY column.
+    pub rowid_column: String,
+    /// The indexed columns, which are also the columns of the shadow table.
+    pub columns: Vec<String>,
+}
+
 /// The schema catalog -- tracks all tables and indexes in the database.
 pub struct Catalog {
     tables: HashMap<String, TableInfo>,
@@ -176,6 +247,44 @@ pub struct Catalog {
     pub attached_databases: HashMap<String, AttachedDatabase>,
     /// R-tree virtual tables keyed by name.
     rtrees: HashMap<String, RTreeInfo>,
+    /// Full-text indexes keyed by the name of the indexed table
+    /// (in-memory only).
+    fulltext_indexes: HashMap<String, FulltextIndexInfo>,
+    /// Whether foreign keys are enforced (`PRAGMA foreign_keys`). Like
+    /// SQLite this is a per-connection setting that defaults to off.
+    foreign_keys_enabled: bool,
+    /// How many rows an ORDER BY sorts in memory before spilling sorted
+    /// runs to disk (`PRAGMA sort_spill_threshold`).
+    sort_spill_threshold: usize,
+    /// How many rows a hash join builds its hash table over in memory
+    /// before partitioning its inputs on disk (`PRAGMA join_spill_threshold`).
+    join_spill_threshold: usize,
+    /// Collations registered with `Database::create_collation`, a
+    /// per-connection setting like the spill thresholds.
+    collations: Collations,
+    /// FTS5 tokenizers registered with `Database::register_fts5_tokenizer`,
+    /// keyed by lower-cased name; per-connection like the collations.
+    fts5_tokenizers: HashMap<String, SharedTokenizer>,
+    /// Statistics from `ANALYZE`, keyed by table name and lower-cased
+    /// column name (in-memory only).
+    column_stats: HashMap<(String, String), stats::ColumnStats>,
+    /// How often each table has been scanned, for `PRAGMA optimize`
+    /// (in-memory only).
+    query_counters: stats::QueryCounters,
+    /// The write generation of each table written since the catalog was
+    /// loaded, keyed by lower-cased table name (in-memory only).
+    write_generations: HashMap<String, u64>,
+    /// The last write generation handed out.
+    last_write_generation: u64,
+    /// Bumped by every change to the tables, indexes, views, triggers, or
+    /// R-trees of the catalog (in-memory only).
+    schema_generation: u64,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Catalog {
@@ -188,6 +297,17 @@ impl Catalog {
             triggers: HashMap::new(),
             attached_databases: HashMap::new(),
             rtrees: HashMap::new(),
+            fulltext_indexes: HashMap::new(),
+            foreign_keys_enabled: false,
+            sort_spill_threshold: DEFAULT_SORT_SPILL_THRESHOLD,
+            join_spill_threshold: DEFAULT_JOIN_SPILL_THRESHOLD,
+            collations: Collations::new(),
+            fts5_tokenizers: HashMap::new(),
+            column_stats: HashMap::new(),
+            query_counters: stats::QueryCounters::default(),
+            write_generations: HashMap::new(),
+            last_write_generation: 0,
+            schema_generation: 0,
         }
     }
 
@@ -284,6 +404,7 @@ impl Catalog {
         }
 
         self.tables.insert(table.name.clone(), table);
+        self.schema_generation += 1;
         Ok(())
     }
 
@@ -309,6 +430,10 @@ impl Catalog {
             }
         }
 
+        self.column_stats.retain(|(table, _), _| table != name);
+        self.query_counters.remove(name);
+        self.fulltext_indexes.remove(name);
+
         // Also remove indexes for this table
         let index_names: Vec<String> = self.indexes.iter()
             .filter(|(_, idx)| idx.table_name == name)
@@ -318,6 +443,7 @@ impl Catalog {
             self.indexes.remove(&idx_name);
         }
 
+        self.schema_generation += 1;
         Ok(table)
     }
 
@@ -351,6 +477,125 @@ impl Catalog {
         self.tables.keys().map(|s| s.as_str()).collect()
     }
 
+    /// Whether foreign key constraints are currently enforced.
+    pub fn foreign_keys_enabled(&self) -> bool {
+        self.foreign_keys_enabled
+    }
+
+    /// Turn foreign key enforcement on or off.
+    pub fn set_foreign_keys_enabled(&mut self, enabled: bool) {
+        self.foreign_keys_enabled = enabled;
+    }
+
+    /// How many rows an ORDER BY sorts in memory before spilling to disk.
+    pub fn sort_spill_threshold(&self) -> usize {
+        self.sort_spill_threshold
+    }
+
+    /// Set how many rows an ORDER BY sorts in memory.
+    pub fn set_sort_spill_threshold(&mut self, rows: usize) {
+        self.sort_spill_threshold = rows;
+    }
+
+    /// How many rows a hash join builds its hash table over in memory
+    /// before partitioning its inputs on disk.
+    pub fn join_spill_threshold(&self) -> usize {
+        self.join_spill_threshold
+    }
+
+    /// Set how many rows a hash join builds its hash table over in memory.
+    pub fn set_join_spill_threshold(&mut self, rows: usize) {
+        self.join_spill_threshold = rows;
+    }
+
+    /// The collations ORDER BY can use.
+    pub fn collations(&self) -> &Collations {
+        &self.collations
+    }
+
+    /// Register `compare` as the collation `name`.
+    pub fn create_collation(&mut self, name: &str, compare: CollationFn) {
+        self.collations.register(name, compare);
+    }
+
+    /// Replace the registered collations with `collations`.
+    pub fn set_collations(&mut self, collations: Collations) {
+        self.collations = collations;
+    }
+
+    /// Register `tokenizer` as the FTS5 tokenizer `name`, replacing any
+    /// tokenizer of that name. Names are case-insensitive.
+    pub fn register_fts5_tokenizer(&mut self, name: &str, tokenizer: SharedTokenizer) {
+        self.fts5_tokenizers.insert(name.to_lowercase(), tokenizer);
+    }
+
+    /// The registered FTS5 tokenizer `name`, if any.
+    pub fn fts5_tokenizer(&self, name: &str) -> Option<SharedTokenizer> {
+        self.fts5_tokenizers.get(&name.to_lowercase()).cloned()
+    }
+
+    /// The registered FTS5 tokenizers, keyed by lower-cased name.
+    pub fn fts5_tokenizers(&self) -> &HashMap<String, SharedTokenizer> {
+        &self.fts5_tokenizers
+    }
+
+    /// Replace the registered FTS5 tokenizers with `tokenizers`.
+    pub fn set_fts5_tokenizers(&mut self, tokenizers: HashMap<String, SharedTokenizer>) {
+        self.fts5_tokenizers = tokenizers;
+    }
+
+    /// The write generation of `table`: 0 if it has not been written since
+    /// the catalog was loaded, and otherwise a number that grows with every
+    /// write to any table, so that no two writes share a generation.
+    pub fn write_generation(&self, table: &str) -> u64 {
+        self.write_generations.get(&table.to_lowercase()).copied().unwrap_or(0)
+    }
+
+    /// Record a write to the rows of `table`, giving it a new write
+    /// generation.
+    pub fn bump_write_generation(&mut self, table: &str) {
+        self.last_write_generation += 1;
+        self.write_generations.insert(table.to_lowercase(), self.last_write_generation);
+    }
+
+    /// The schema generation: a number that grows with every change to the
+    /// schema, so that anything derived from the schema can tell whether it
+    /// is still current.
+    pub fn schema_generation(&self) -> u64 {
+        self.schema_generation
+    }
+
+    /// Set the schema generation, for a catalog reloaded in place of one
+    /// whose generation must not be repeated.
+    pub fn set_schema_generation(&mut self, generation: u64) {
+        self.schema_generation = generation;
+    }
+
+    /// All foreign keys that reference `parent`, paired with the name of
+    /// the child table that declares them.
+    pub fn foreign_keys_referencing(&self, parent: &str) -> Vec<(String, ForeignKeyInfo)> {
+        let mut refs = Vec::new();
+        for table in self.tables.values() {
+            for fk in &table.foreign_keys {
+                if fk.ref_table.eq_ignore_ascii_case(parent) {
+                    refs.push((table.name.clone(), fk.clone()));
+                }
+            }
+        }
+        refs.sort_by(|a, b| a.0.cmp(&b.0));
+        refs
+    }
+
+    /// The root page of every B+Tree the catalog refers to: those of the
+    /// tables, indexes, and R-tree tables.
+    pub fn tree_roots_mut(&mut self) -> impl Iterator<Item = &mut PageId> {
+        self.tables
+            .values_mut()
+            .map(|table| &mut table.root_page)
+            .chain(self.indexes.values_mut().map(|index| &mut index.root_page))
+            .chain(self.rtrees.values_mut().map(|rtree| &mut rtree.root_page))
+    }
+
     /// Add an index to the catalog.
     ///
     /// # Errors
@@ -362,6 +607,7 @@ impl Catalog {
             return Err(HorizonError::DuplicateIndex(index.name.clone()));
         }
         self.indexes.insert(index.name.clone(), index);
+        self.schema_generation += 1;
         Ok(())
     }
 
@@ -373,6 +619,7 @@ impl Catalog {
     pub fn drop_index(&mut self, _pool: &mut BufferPool, name: &str) -> Result<IndexInfo> {
         let index = self.indexes.remove(name)
             .ok_or_else(|| HorizonError::IndexNotFound(name.into()))?;
+        self.schema_generation += 1;
         Ok(index)
     }
 
@@ -393,6 +640,33 @@ impl Catalog {
             .collect()
     }
 
+    // =================================================================
+    // Statistics
+    // =================================================================
+
+    /// Record statistics for a column, replacing any earlier ones.
+    pub fn set_column_stats(&mut self, table_name: &str, column: &str, stats: stats::ColumnStats) {
+        self.column_stats.insert((table_name.to_string(), column.to_lowercase()), stats);
+    }
+
+    /// The statistics recorded for a column by `ANALYZE`, if any.
+    pub fn get_column_stats(&self, table_name: &str, column: &str) -> Option<&stats::ColumnStats> {
+        self.column_stats.get(&(table_name.to_string(), column.to_lowercase()))
+    }
+
+    /// The row count of a table as of its last `ANALYZE`, if it has been
+    /// analyzed.
+    pub fn analyzed_row_count(&self, table_name: &str) -> Option<u64> {
+        self.column_stats.iter()
+            .find(|((table, _), _)| table == table_name)
+            .map(|(_, stats)| stats.row_count)
+    }
+
+    /// How often each table has been scanned since the catalog was loaded.
+    pub fn query_counters(&self) -> &stats::QueryCounters {
+        &self.query_counters
+    }
+
     // =================================================================
     // View operations
     // =================================================================
@@ -403,13 +677,16 @@ impl Catalog {
             return Err(HorizonError::DuplicateTable(view.name.clone()));
         }
         self.views.insert(view.name.clone(), view);
+        self.schema_generation += 1;
         Ok(())
     }
 
     /// Drop a view from the catalog.
     pub fn drop_view(&mut self, name: &str) -> Result<ViewInfo> {
-        self.views.remove(name)
-            .ok_or_else(|| HorizonError::TableNotFound(format!("view: {}", name)))
+        let view = self.views.remove(name)
+            .ok_or_else(|| HorizonError::TableNotFound(format!("view: {}", name)))?;
+        self.schema_generation += 1;
+        Ok(view)
     }
 
     /// Check whether a view with the given name exists.
@@ -422,6 +699,11 @@ impl Catalog {
         self.views.get(name)
     }
 
+    /// Return all views currently in the catalog.
+    pub fn list_views(&self) -> Vec<&ViewInfo> {
+        self.views.values().collect()
+    }
+
     // =================================================================
     // Trigger operations
     // =================================================================
@@ -432,13 +714,16 @@ impl Catalog {
             return Err(HorizonError::DuplicateTable(trigger.name.clone()));
         }
         self.triggers.insert(trigger.name.clone(), trigger);
+        self.schema_generation += 1;
         Ok(())
     }
 
     /// Drop a trigger from the catalog.
     pub fn drop_trigger(&mut self, name: &str) -> Result<TriggerInfo> {
-        self.triggers.remove(name)
-            .ok_or_else(|| HorizonError::TableNotFound(format!("trigger: {}", name)))
+        let trigger = self.triggers.remove(name)
+            .ok_or_else(|| HorizonError::TableNotFound(format!("trigger: {}", name)))?;
+        self.schema_generation += 1;
+        Ok(trigger)
     }
 
     /// Check whether a trigger with the given name exists.
@@ -446,6 +731,11 @@ impl Catalog {
         self.triggers.contains_key(name)
     }
 
+    /// Return all triggers currently in the catalog.
+    pub fn list_triggers(&self) -> Vec<&TriggerInfo> {
+        self.triggers.values().collect()
+    }
+
     /// Return all triggers for a given table, event, and timing.
     pub fn get_triggers_for_table(
         &self,
@@ -472,13 +762,16 @@ impl Catalog {
             return Err(HorizonError::DuplicateTable(rtree.name.clone()));
         }
         self.rtrees.insert(rtree.name.clone(), rtree);
+        self.schema_generation += 1;
         Ok(())
     }
 
     /// Drop an R-tree virtual table from the catalog.
     pub fn drop_rtree(&mut self, name: &str) -> Result<RTreeInfo> {
-        self.rtrees.remove(name)
-            .ok_or_else(|| HorizonError::TableNotFound(name.into()))
+        let rtree = self.rtrees.remove(name)
+            .ok_or_else(|| HorizonError::TableNotFound(name.into()))?;
+        self.schema_generation += 1;
+        Ok(rtree)
     }
 
     /// Check whether an R-tree virtual table with the given name exists.
@@ -496,6 +789,25 @@ impl Catalog {
         self.rtrees.get_mut(name)
     }
 
+    // =================================================================
+    // Full-text index operations
+    // =================================================================
+
+    /// Record a full-text index on a table (in-memory only).
+    pub fn create_fulltext_index(&mut self, index: FulltextIndexInfo) -> Result<()> {
+        if self.fulltext_indexes.contains_key(&index.table) {
+            return Err(HorizonError::DuplicateIndex(format!("full-text index on {}", index.table)));
+        }
+        self.fulltext_indexes.insert(index.table.clone(), index);
+        self.schema_generation += 1;
+        Ok(())
+    }
+
+    /// The full-text index on a table, if it has one.
+    pub fn get_fulltext_index(&self, table_name: &str) -> Option<&FulltextIndexInfo> {
+        self.fulltext_indexes.get(table_name)
+    }
+
     /// Rename a table in the catalog.
     ///
     /// Updates the in-memory catalog and persists the change to the schema
@@ -535,6 +847,15 @@ impl Catalog {
                 idx.table_name = new_name.to_string();
             }
         }
+        let stats: Vec<_> = self.column_stats.keys()
+            .filter(|(table, _)| table == old_name)
+            .cloned()
+            .collect();
+        for key in stats {
+            let value = self.column_stats.remove(&key).unwrap();
+            self.column_stats.insert((new_name.to_string(), key.1), value);
+        }
+        self.query_counters.rename(old_name, new_name);
 
         // Persist the renamed table
         let schema_root = pool.pager().schema_root();
@@ -549,6 +870,7 @@ impl Catalog {
         }
 
         self.tables.insert(new_name.to_string(), table);
+        self.schema_generation += 1;
         Ok(())
     }
 
@@ -586,6 +908,7 @@ impl Catalog {
             }
         }
 
+        self.schema_generation += 1;
         Ok(())
     }
 
@@ -635,13 +958,14 @@ impl Catalog {
             }
         }
 
+        self.schema_generation += 1;
         Ok(())
     }
 
-    /// Drop a column from an existing table (metadata-only).
+    /// Drop a column from an existing table's metadata.
     ///
-    /// Existing rows are not rewritten; the column position simply becomes
-    /// inaccessible. This is a "lazy" approach.
+    /// Existing rows are not rewritten here; `ALTER TABLE ... DROP COLUMN`
+    /// rewrites them after checking that nothing depends on the column.
     ///
     /// # Errors
     ///
@@ -678,6 +1002,11 @@ impl Catalog {
                 table.pk_column = None;
             }
         }
+        for pk in &mut table.pk_columns {
+            if col_idx < *pk {
+                *pk -= 1;
+            }
+        }
 
         // Remove any indexes that reference the dropped column
         let to_remove: Vec<String> = self.indexes.iter()
@@ -689,6 +1018,7 @@ impl Catalog {
         for idx_name in to_remove {
             self.indexes.remove(&idx_name);
         }
+        self.column_stats.remove(&(table_name.to_string(), col_name.to_lowercase()));
 
         // Persist
         let updated = table.clone();
@@ -703,6 +1033,7 @@ impl Catalog {
             }
         }
 
+        self.schema_generation += 1;
         Ok(())
     }
 
@@ -732,7 +1063,12 @@ impl Catalog {
     /// Serialize a [`TableInfo`] into a simple text-based format.
     ///
     /// Each line is a `key=value` pair. Columns are encoded as
-    /// `colN=name:type=TYPE:pk:autoinc:notnull:unique`.
+    /// `colN=name:type=TYPE:pk:autoinc:notnull:unique:stored`, and a
+    /// composite primary key as `pk_columns=i,j,...`; defaults, generated
+    /// column expressions, and CHECK constraints are stored as SQL text in
+    /// `default_colN=`, `generated_colN=`, `check_colN=`, and `check=`
+    /// lines (escaped with [`escape_line`] so that they stay on one line),
+    /// and foreign keys as `fk=cols;parent;parent_cols;on_delete;on_update`.
     fn serialize_table(table: &TableInfo) -> Vec<u8> {
         let mut parts = Vec::new();
         parts.push(format!("name={}", table.name));
@@ -741,6 +1077,10 @@ impl Catalog {
         if let Some(pk) = table.pk_column {
             parts.push(format!("pk_column={}", pk));
         }
+        if table.has_composite_pk() {
+            let cols: Vec<String> = table.pk_columns.iter().map(|i| i.to_string()).collect();
+            parts.push(format!("pk_columns={}", cols.join(",")));
+        }
         for (i, col) in table.columns.iter().enumerate() {
             let mut col_str = format!("col{}={}", i, col.name);
             if !col.type_name.is_empty() {
@@ -750,8 +1090,33 @@ impl Catalog {
             if col.autoincrement { col_str.push_str(":autoinc"); }
             if col.not_null { col_str.push_str(":notnull"); }
             if col.unique { col_str.push_str(":unique"); }
+            if col.generated_expr.is_some() && col.is_stored { col_str.push_str(":stored"); }
             parts.push(col_str);
         }
+        for (i, col) in table.columns.iter().enumerate() {
+            if let Some(ref default) = col.default_value {
+                parts.push(format!("default_col{}={}", i, escape_line(&sql_literal(default))));
+            }
+            if let Some(ref expr) = col.generated_expr {
+                parts.push(format!("generated_col{}={}", i, escape_line(&expr_to_sql(expr))));
+            }
+            if let Some(ref check) = col.check_expr {
+                parts.push(format!("check_col{}={}", i, escape_line(&expr_to_sql(check))));
+            }
+        }
+        for check in &table.checks {
+            parts.push(format!("check={}", escape_line(&expr_to_sql(check))));
+        }
+        for fk in &table.foreign_keys {
+            parts.push(format!(
+                "fk={};{};{};{};{}",
+                fk.columns.join(","),
+                fk.ref_table,
+                fk.ref_columns.join(","),
+                fk.on_delete.as_sql(),
+                fk.on_update.as_sql(),
+            ));
+        }
         parts.join("\n").into_bytes()
     }
 
@@ -765,15 +1130,40 @@ impl Catalog {
         let mut root_page: PageId = 0;
         let mut next_rowid: i64 = 1;
         let mut pk_column: Option<usize> = None;
+        let mut pk_columns = Vec::new();
         let mut columns = Vec::new();
+        let mut column_checks = Vec::new();
+        let mut column_defaults = Vec::new();
+        let mut generated = Vec::new();
+        let mut checks = Vec::new();
+        let mut foreign_keys = Vec::new();
 
         for line in text.lines() {
-            if let Some(val) = line.strip_prefix("name=") {
+            if let Some(val) = line.strip_prefix("fk=") {
+                foreign_keys.push(Self::deserialize_foreign_key(val)?);
+            } else if let Some(val) = line.strip_prefix("check=") {
+                checks.push(Parser::parse_expression(&unescape_line(val))?);
+            } else if let Some(rest) = line.strip_prefix("check_col") {
+                let (idx, sql) = Self::split_column_line(rest)?;
+                column_checks.push((idx, Parser::parse_expression(&unescape_line(sql))?));
+            } else if let Some(rest) = line.strip_prefix("default_col") {
+                let (idx, sql) = Self::split_column_line(rest)?;
+                column_defaults.push((idx, eval_const_expr(&Parser::parse_expression(&unescape_line(sql))?)));
+            } else if let Some(rest) = line.strip_prefix("generated_col") {
+                let (idx, sql) = Self::split_column_line(rest)?;
+                generated.push((idx, Parser::parse_expression(&unescape_line(sql))?));
+            } else if let Some(val) = line.strip_prefix("name=") {
                 name = val.to_string();
             } else if let Some(val) = line.strip_prefix("root_page=") {
                 root_page = val.parse().unwrap_or(0);
             } else if let Some(val) = line.strip_prefix("next_rowid=") {
                 next_rowid = val.parse().unwrap_or(1);
+            } else if let Some(val) = line.strip_prefix("pk_columns=") {
+                pk_columns = val
+                    .split(',')
+                    .map(|i| i.parse())
+                    .collect::<std::result::Result<Vec<usize>, _>>()
+                    .map_err(|_| HorizonError::CorruptDatabase("invalid primary key columns".into()))?;
             } else if let Some(val) = line.strip_prefix("pk_column=") {
                 pk_column = val.parse().ok();
             } else if line.starts_with("col") {
@@ -787,6 +1177,7 @@ impl Catalog {
                     let mut autoincrement = false;
                     let mut not_null = false;
                     let mut unique = false;
+                    let mut is_stored = false;
 
                     for part in &parts[1..] {
                         if let Some(tn) = part.strip_prefix("type=") {
@@ -799,6 +1190,8 @@ impl Catalog {
                             not_null = true;
                         } else if *part == "unique" {
                             unique = true;
+                        } else if *part == "stored" {
+                            is_stored = true;
                         }
                     }
 
@@ -807,13 +1200,61 @@ impl Catalog {
                     columns.push(ColumnInfo {
                         name: col_name, type_name, affinity, primary_key,
                         autoincrement, not_null, unique, default_value: None, position,
-                        generated_expr: None, is_stored: false,
+                        generated_expr: None, is_stored, check_expr: None,
                     });
                 }
             }
         }
 
-        Ok(TableInfo { name, columns, root_page, next_rowid, pk_column })
+        for (idx, default) in column_defaults {
+            if let Some(col) = columns.get_mut(idx) {
+                col.default_value = Some(default);
+            }
+        }
+        for (idx, expr) in generated {
+            if let Some(col) = columns.get_mut(idx) {
+                col.generated_expr = Some(expr);
+            }
+        }
+
+        for (idx, check) in column_checks {
+            if let Some(col) = columns.get_mut(idx) {
+                col.check_expr = Some(check);
+            }
+        }
+
+        if pk_columns.is_empty() {
+            pk_columns.extend(pk_column);
+        }
+
+        Ok(TableInfo { name, columns, root_page, next_rowid, pk_column, pk_columns, checks, foreign_keys })
+    }
+
+    /// Split the `N=sql` rest of a per-column line such as `check_colN=`
+    /// into the column index and the SQL text.
+    fn split_column_line(rest: &str) -> Result<(usize, &str)> {
+        rest.split_once('=')
+            .and_then(|(idx, sql)| Some((idx.parse().ok()?, sql)))
+            .ok_or_else(|| HorizonError::CorruptDatabase("invalid column metadata line".into()))
+    }
+
+    /// Decode the value of an `fk=` line written by [`Self::serialize_table`].
+    fn deserialize_foreign_key(val: &str) -> Result<ForeignKeyInfo> {
+        let corrupt = || HorizonError::CorruptDatabase("invalid foreign key metadata".into());
+        let parts: Vec<&str> = val.split(';').collect();
+        if parts.len() != 5 {
+            return Err(corrupt());
+        }
+        let list = |s: &str| -> Vec<String> {
+            s.split(',').filter(|c| !c.is_empty()).map(str::to_string).collect()
+        };
+        Ok(ForeignKeyInfo {
+            columns: list(parts[0]),
+            ref_table: parts[1].to_string(),
+            ref_columns: list(parts[2]),
+            on_delete: FkAction::from_sql(parts[3]).ok_or_else(corrupt)?,
+            on_update: FkAction::from_sql(parts[4]).ok_or_else(corrupt)?,
+        })
     }
 
     /// Serialize an [`IndexInfo`] into a simple text-based format.
@@ -824,7 +1265,20 @@ impl Catalog {
         parts.push(format!("table={}", index.table_name));
         parts.push(format!("root_page={}", index.root_page));
         parts.push(format!("unique={}", index.unique));
-        parts.push(format!("columns={}", index.columns.join(",")));
+        // Expression SQL may contain commas, so expression parts are left
+        // blank in `columns=` and stored on their own `exprN=` lines.
+        let columns: Vec<&str> = index.columns.iter().enumerate()
+            .map(|(i, c)| if index.key_expr(i).is_some() { "" } else { c.as_str() })
+            .collect();
+        parts.push(format!("columns={}", columns.join(",")));
+        for (i, expr) in index.expressions.iter().enumerate() {
+            if let Some(expr) = expr {
+                parts.push(format!("expr{}={}", i, escape_line(&expr_to_sql(expr))));
+            }
+        }
+        if let Some(predicate) = &index.predicate {
+            parts.push(format!("where={}", escape_line(&expr_to_sql(predicate))));
+        }
         parts.join("\n").into_bytes()
     }
 
@@ -838,17 +1292,73 @@ impl Catalog {
         let mut root_page: PageId = 0;
         let mut unique = false;
         let mut columns = Vec::new();
+        let mut predicate = None;
+        let mut expressions = Vec::new();
 
         for line in text.lines() {
-            if let Some(val) = line.strip_prefix("name=") { name = val.to_string(); }
+            if let Some(val) = line.strip_prefix("where=") {
+                predicate = Some(Parser::parse_expression(&unescape_line(val))?);
+            } else if let Some(rest) = line.strip_prefix("expr") {
+                if let Some((idx, sql)) = rest.split_once('=') {
+                    let idx: usize = idx.parse().map_err(|_| {
+                        HorizonError::CorruptDatabase("invalid index expression position".into())
+                    })?;
+                    if expressions.len() <= idx {
+                        expressions.resize(idx + 1, None);
+                    }
+                    expressions[idx] = Some(Parser::parse_expression(&unescape_line(sql))?);
+                }
+            } else if let Some(val) = line.strip_prefix("name=") { name = val.to_string(); }
             else if let Some(val) = line.strip_prefix("table=") { table_name = val.to_string(); }
             else if let Some(val) = line.strip_prefix("root_page=") { root_page = val.parse().unwrap_or(0); }
             else if let Some(val) = line.strip_prefix("unique=") { unique = val == "true"; }
             else if let Some(val) = line.strip_prefix("columns=") { columns = val.split(',').map(|s| s.to_string()).collect(); }
         }
 
-        Ok(IndexInfo { name, table_name, columns, unique, root_page })
+        for (i, expr) in expressions.iter().enumerate() {
+            if let (Some(expr), Some(col)) = (expr, columns.get_mut(i)) {
+                *col = expr_to_sql(expr);
+            }
+        }
+
+        Ok(IndexInfo { name, table_name, columns, unique, root_page, predicate, expressions })
+    }
+}
+
+/// Escape SQL text stored on a single line of table or index metadata:
+/// backslashes, line feeds, and carriage returns (which may appear in string
+/// literals) become `\\`, `\n`, and `\r`.
+fn escape_line(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    for c in sql.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Undo [`escape_line`]. Any other backslash is kept as it is, so metadata
+/// written before escaping was introduced still reads back unchanged unless
+/// it happens to contain one of the escape sequences.
+fn unescape_line(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.peek() {
+                Some('\\') => { chars.next(); out.push('\\'); continue; }
+                Some('n') => { chars.next(); out.push('\n'); continue; }
+                Some('r') => { chars.next(); out.push('\r'); continue; }
+                _ => {}
+            }
+        }
+        out.push(c);
     }
+    out
 }
 
 // ---------------------------------------------------------------------------
@@ -880,6 +1390,7 @@ mod tests {
                     position: 0,
                     generated_expr: None,
                     is_stored: false,
+                    check_expr: None,
                 },
                 ColumnInfo {
                     name: "email".to_string(),
@@ -893,6 +1404,7 @@ mod tests {
                     position: 1,
                     generated_expr: None,
                     is_stored: false,
+                    check_expr: None,
                 },
                 ColumnInfo {
                     name: "score".to_string(),
@@ -906,11 +1418,15 @@ mod tests {
                     position: 2,
                     generated_expr: None,
                     is_stored: false,
+                    check_expr: None,
                 },
             ],
             root_page: 7,
             next_rowid: 42,
             pk_column: Some(0),
+            pk_columns: vec![0],
+            checks: vec![],
+            foreign_keys: vec![],
         }
     }
 
@@ -921,6 +1437,8 @@ mod tests {
             columns: vec!["email".to_string()],
             unique: true,
             root_page: 12,
+            predicate: None,
+            expressions: Vec::new(),
         }
     }
 
@@ -1052,6 +1570,81 @@ mod tests {
         assert_eq!(decoded.columns[2].position, 2);
     }
 
+    #[test]
+    fn table_serialization_check_constraints() {
+        let mut table = sample_table("checked");
+        table.columns[2].check_expr = Some(Parser::parse_expression("score >= 0").unwrap());
+        table.checks = vec![Parser::parse_expression("score < id * 100 OR email = 'x:y'").unwrap()];
+
+        let bytes = Catalog::serialize_table(&table);
+        let decoded = Catalog::deserialize_table(&bytes).unwrap();
+        assert!(decoded.columns[0].check_expr.is_none());
+        assert_eq!(decoded.columns[2].check_expr, table.columns[2].check_expr);
+        assert_eq!(decoded.checks, table.checks);
+    }
+
+    #[test]
+    fn table_serialization_defaults_and_generated_columns() {
+        let mut table = sample_table("people");
+        table.columns[1].default_value = Some(Value::Text("it's: none".into()));
+        table.columns[2].default_value = Some(Value::Real(-1.5));
+        table.columns[2].generated_expr = Some(Parser::parse_expression("id * 2").unwrap());
+        table.columns[2].is_stored = true;
+
+        let bytes = Catalog::serialize_table(&table);
+        let decoded = Catalog::deserialize_table(&bytes).unwrap();
+        assert_eq!(decoded.columns[0].default_value, None);
+        assert_eq!(decoded.columns[1].default_value, table.columns[1].default_value);
+        assert_eq!(decoded.columns[2].default_value, table.columns[2].default_value);
+        assert!(decoded.columns[1].generated_expr.is_none());
+        assert_eq!(decoded.columns[2].generated_expr, table.columns[2].generated_expr);
+        assert!(decoded.columns[2].is_stored);
+    }
+
+    #[test]
+    fn table_serialization_foreign_keys() {
+        let mut table = sample_table("orders");
+        table.foreign_keys = vec![
+            ForeignKeyInfo {
+                columns: vec!["id".into()],
+                ref_table: "customers".into(),
+                ref_columns: vec![],
+                on_delete: FkAction::Cascade,
+                on_update: FkAction::NoAction,
+            },
+            ForeignKeyInfo {
+                columns: vec!["email".into(), "score".into()],
+                ref_table: "accounts".into(),
+                ref_columns: vec!["mail".into(), "points".into()],
+                on_delete: FkAction::SetNull,
+                on_update: FkAction::SetDefault,
+            },
+        ];
+
+        let bytes = Catalog::serialize_table(&table);
+        let decoded = Catalog::deserialize_table(&bytes).unwrap();
+        assert_eq!(decoded.foreign_keys, table.foreign_keys);
+    }
+
+    #[test]
+    fn table_serialization_composite_primary_key() {
+        let mut table = sample_table("scores");
+        table.pk_column = None;
+        table.pk_columns = vec![1, 2];
+
+        let bytes = Catalog::serialize_table(&table);
+        let decoded = Catalog::deserialize_table(&bytes).unwrap();
+        assert_eq!(decoded.pk_column, None);
+        assert_eq!(decoded.pk_columns, vec![1, 2]);
+        assert!(decoded.has_composite_pk());
+
+        // Tables written before composite keys existed derive the list
+        // from the single key column.
+        let single = Catalog::deserialize_table(&Catalog::serialize_table(&sample_table("t"))).unwrap();
+        assert_eq!(single.pk_columns, vec![0]);
+        assert!(!single.has_composite_pk());
+    }
+
     #[test]
     fn table_serialization_no_pk_column() {
         let table = TableInfo {
@@ -1069,11 +1662,15 @@ mod tests {
                     position: 0,
                     generated_expr: None,
                     is_stored: false,
+                    check_expr: None,
                 },
             ],
             root_page: 3,
             next_rowid: 1,
             pk_column: None,
+            pk_columns: vec![],
+            checks: vec![],
+            foreign_keys: vec![],
         };
 
         let bytes = Catalog::serialize_table(&table);
@@ -1091,6 +1688,9 @@ mod tests {
             root_page: 1,
             next_rowid: 1,
             pk_column: None,
+            pk_columns: vec![],
+            checks: vec![],
+            foreign_keys: vec![],
         };
 
         let bytes = Catalog::serialize_table(&table);
@@ -1116,11 +1716,15 @@ mod tests {
                     position: 0,
                     generated_expr: None,
                     is_stored: false,
+                    check_expr: None,
                 },
             ],
             root_page: 5,
             next_rowid: 1,
             pk_column: None,
+            pk_columns: vec![],
+            checks: vec![],
+            foreign_keys: vec![],
         };
 
         let bytes = Catalog::serialize_table(&table);
@@ -1162,6 +1766,8 @@ mod tests {
             columns: vec!["user_id".to_string(), "created_at".to_string(), "status".to_string()],
             unique: false,
             root_page: 25,
+            predicate: None,
+            expressions: Vec::new(),
         };
 
         let bytes = Catalog::serialize_index(&index);
@@ -1185,6 +1791,8 @@ mod tests {
             columns: vec!["status".to_string()],
             unique: false,
             root_page: 10,
+            predicate: None,
+            expressions: Vec::new(),
         };
 
         let bytes = Catalog::serialize_index(&index);
@@ -1192,6 +1800,43 @@ mod tests {
         assert!(!decoded.unique);
     }
 
+    #[test]
+    fn index_serialization_partial_predicate() {
+        let index = IndexInfo {
+            name: "idx_active".to_string(),
+            table_name: "orders".to_string(),
+            columns: vec!["user_id".to_string()],
+            unique: false,
+            root_page: 10,
+            predicate: Some(Parser::parse_expression("status = 'active'").unwrap()),
+            expressions: Vec::new(),
+        };
+
+        let bytes = Catalog::serialize_index(&index);
+        let decoded = Catalog::deserialize_index(&bytes).unwrap();
+        assert_eq!(decoded.predicate, index.predicate);
+    }
+
+    #[test]
+    fn index_serialization_expression_parts() {
+        let index = IndexInfo {
+            name: "idx_expr".to_string(),
+            table_name: "users".to_string(),
+            columns: vec!["substr(email, 1, 3)".to_string(), "name".to_string()],
+            unique: false,
+            root_page: 10,
+            predicate: None,
+            expressions: vec![Some(Parser::parse_expression("substr(email, 1, 3)").unwrap())],
+        };
+
+        let bytes = Catalog::serialize_index(&index);
+        let decoded = Catalog::deserialize_index(&bytes).unwrap();
+        assert_eq!(decoded.key_expr(0), index.key_expr(0));
+        assert!(decoded.key_expr(1).is_none());
+        assert_eq!(decoded.columns[1], "name");
+        assert_eq!(decoded.columns[0], expr_to_sql(index.key_expr(0).unwrap()));
+    }
+
     #[test]
     fn index_deserialization_rejects_invalid_utf8() {
         let data: Vec<u8> = vec![0xFF, 0xFE, 0xFD];
@@ -1303,6 +1948,8 @@ mod tests {
             columns: vec!["email".to_string()],
             unique: true,
             root_page: 10,
+            predicate: None,
+            expressions: Vec::new(),
         });
 
         catalog.indexes.insert("idx_users_name".to_string(), IndexInfo {
@@ -1311,6 +1958,8 @@ mod tests {
             columns: vec!["name".to_string()],
             unique: false,
             root_page: 11,
+            predicate: None,
+            expressions: Vec::new(),
         });
 
         catalog.indexes.insert("idx_orders_date".to_string(), IndexInfo {
@@ -1319,6 +1968,8 @@ mod tests {
             columns: vec!["date".to_string()],
             unique: false,
             root_page: 12,
+            predicate: None,
+            expressions: Vec::new(),
         });
 
         let user_indexes = catalog.get_indexes_for_table("users");
@@ -1357,6 +2008,8 @@ mod tests {
             columns: vec!["email".to_string()],
             unique: true,
             root_page: 10,
+            predicate: None,
+            expressions: Vec::new(),
         });
 
         catalog.indexes.insert("idx_orders_date".to_string(), IndexInfo {
@@ -1365,6 +2018,8 @@ mod tests {
             columns: vec!["date".to_string()],
             unique: false,
             root_page: 11,
+            predicate: None,
+            expressions: Vec::new(),
         });
 
         // Manually remove the table and its indexes (simulating drop_table
@@ -1401,6 +2056,7 @@ mod tests {
             position: 0,
             generated_expr: None,
             is_stored: false,
+            check_expr: None,
         };
         assert_eq!(col.affinity, DataType::Text);
     }
@@ -1419,6 +2075,7 @@ mod tests {
             position: 0,
             generated_expr: None,
             is_stored: false,
+            check_expr: None,
         };
         let debug = format!("{:?}", col);
         assert!(debug.contains("id"));
@@ -1437,6 +2094,9 @@ mod tests {
             root_page: u32::MAX,
             next_rowid: i64::MAX,
             pk_column: None,
+            pk_columns: vec![],
+            checks: vec![],
+            foreign_keys: vec![],
         };
 
         let bytes = Catalog::serialize_table(&table);
@@ -1453,6 +2113,9 @@ mod tests {
             root_page: 1,
             next_rowid: -100,
             pk_column: None,
+            pk_columns: vec![],
+            checks: vec![],
+            foreign_keys: vec![],
         };
 
         let bytes = Catalog::serialize_table(&table);
@@ -1468,6 +2131,8 @@ mod tests {
             columns: vec!["col1".to_string()],
             unique: true,
             root_page: 1,
+            predicate: None,
+            expressions: Vec::new(),
         };
 
         let bytes = Catalog::serialize_index(&index);
@@ -1491,6 +2156,9 @@ mod tests {
                 root_page: i as u32 + 1,
                 next_rowid: 1,
                 pk_column: None,
+                pk_columns: vec![],
+                checks: vec![],
+                foreign_keys: vec![],
             });
         }
 
@@ -0,0 +1,177 @@
+//! Column statistics gathered by `ANALYZE`.
+//!
+//! The planner uses these to estimate how many rows an index scan will
+//! visit, so that it can pick the most selective of several usable indexes.
+//! Statistics are kept in memory only; they are a snapshot taken when
+//! `ANALYZE` ran and are not updated by later writes.
+//!
+//! [`QueryCounters`] count how often each table is scanned, so that
+//! `PRAGMA optimize` can analyze again the tables queried much more often
+//! than when their statistics were gathered.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::btree::BTree;
+use crate::buffer::BufferPool;
+use crate::error::Result;
+use crate::pager::PageId;
+use crate::types::Value;
+
+/// Statistics for one column of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnStats {
+    /// Number of rows in the table.
+    pub row_count: u64,
+    /// Number of distinct values in the column (NULL counts as one value).
+    pub distinct_values: u64,
+}
+
+impl ColumnStats {
+    /// The average number of rows sharing one value of the column, which
+    /// is the expected result size of an equality lookup.
+    pub fn rows_per_value(&self) -> f64 {
+        self.row_count as f64 / self.distinct_values.max(1) as f64
+    }
+}
+
+/// Scan the index rooted at `root_page` and count its entries and the
+/// distinct values of its first `key_columns` key parts.
+///
+/// Element `i` of the returned vector is the number of distinct key
+/// prefixes made of the first `i + 1` key parts. Index keys are sorted, so
+/// each new prefix shows up as a change from the previous entry.
+pub fn index_prefix_counts(
+    pool: &BufferPool,
+    root_page: PageId,
+    key_columns: usize,
+) -> Result<(u64, Vec<u64>)> {
+    let mut entries = 0u64;
+    let mut distinct = vec![0u64; key_columns];
+    let mut previous: Vec<Vec<u8>> = Vec::new();
+
+    let tree = BTree::open(root_page);
+    let mut cursor = tree.cursor(pool)?;
+    while let Some(entry) = tree.next(pool, &mut cursor)? {
+        entries += 1;
+        // The key is the encoded key parts followed by the row key
+        let mut prefixes = Vec::with_capacity(key_columns);
+        let mut end = 0;
+        for _ in 0..key_columns {
+            match Value::sortable_len(&entry.key[end..]) {
+                Some(len) => end += len,
+                None => break,
+            }
+            prefixes.push(entry.key[..end].to_vec());
+        }
+        for (i, prefix) in prefixes.iter().enumerate() {
+            if previous.get(i) != Some(prefix) {
+                distinct[i] += 1;
+            }
+        }
+        previous = prefixes;
+    }
+    Ok((entries, distinct))
+}
+
+/// `PRAGMA optimize` analyzes a table scanned more than this many times as
+/// often as when it was last analyzed.
+pub const STALE_SCAN_FACTOR: u64 = 2;
+
+/// How often each table has been scanned, keyed by lower-cased table name
+/// (in-memory only).
+///
+/// Queries share the catalog, so the counters have a lock of their own.
+#[derive(Debug, Default)]
+pub struct QueryCounters {
+    tables: Mutex<HashMap<String, ScanCount>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ScanCount {
+    scans: u64,
+    /// The value of `scans` when the table was last analyzed.
+    scans_at_analyze: u64,
+}
+
+impl QueryCounters {
+    /// Count a scan of `table_name`.
+    pub fn record_scan(&self, table_name: &str) {
+        if let Ok(mut tables) = self.tables.lock() {
+            tables.entry(table_name.to_lowercase()).or_default().scans += 1;
+        }
+    }
+
+    /// The number of times `table_name` has been scanned.
+    pub fn scans(&self, table_name: &str) -> u64 {
+        self.count(table_name).scans
+    }
+
+    /// Note that `table_name` has just been analyzed.
+    pub fn record_analyze(&self, table_name: &str) {
+        if let Ok(mut tables) = self.tables.lock() {
+            let count = tables.entry(table_name.to_lowercase()).or_default();
+            count.scans_at_analyze = count.scans;
+        }
+    }
+
+    /// Whether `table_name` has been scanned more than
+    /// [`STALE_SCAN_FACTOR`] times as often as when it was last analyzed,
+    /// or at all if it never was.
+    pub fn is_stale(&self, table_name: &str) -> bool {
+        let count = self.count(table_name);
+        count.scans > count.scans_at_analyze.saturating_mul(STALE_SCAN_FACTOR)
+    }
+
+    /// Forget the counts of `table_name`, which has been dropped.
+    pub fn remove(&self, table_name: &str) {
+        if let Ok(mut tables) = self.tables.lock() {
+            tables.remove(&table_name.to_lowercase());
+        }
+    }
+
+    /// Move the counts of `old_name` to `new_name`, which it was renamed to.
+    pub fn rename(&self, old_name: &str, new_name: &str) {
+        if let Ok(mut tables) = self.tables.lock() {
+            if let Some(count) = tables.remove(&old_name.to_lowercase()) {
+                tables.insert(new_name.to_lowercase(), count);
+            }
+        }
+    }
+
+    fn count(&self, table_name: &str) -> ScanCount {
+        self.tables.lock()
+            .ok()
+            .and_then(|tables| tables.get(&table_name.to_lowercase()).copied())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_stale_once_scanned_more_often_than_when_analyzed() {
+        let counters = QueryCounters::default();
+        assert!(!counters.is_stale("t"));
+        counters.record_scan("t");
+        assert!(counters.is_stale("T"));
+
+        for _ in 0..9 {
+            counters.record_scan("t");
+        }
+        counters.record_analyze("t");
+        assert_eq!(counters.scans("t"), 10);
+        assert!(!counters.is_stale("t"));
+        for _ in 0..10 {
+            counters.record_scan("t");
+        }
+        assert!(!counters.is_stale("t"));
+        counters.record_scan("t");
+        assert!(counters.is_stale("t"));
+
+        counters.remove("t");
+        assert!(!counters.is_stale("t"));
+    }
+}
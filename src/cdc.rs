@@ -0,0 +1,164 @@
+//! Change data capture.
+//!
+//! [`Database::enable_cdc`](crate::Database::enable_cdc) records every row
+//! inserted into, updated in, or deleted from a table in the `__cdc_log`
+//! table. It creates the log if needed and installs AFTER INSERT, AFTER
+//! UPDATE, and AFTER DELETE triggers on the table that append an entry to
+//! it. Each entry holds the rows before and after the change as JSON
+//! objects keyed by column name:
+//!
+//! ```sql
+//! CREATE TABLE __cdc_log (
+//!     id INTEGER PRIMARY KEY, table_name TEXT, operation TEXT,
+//!     old_row_json TEXT, new_row_json TEXT, changed_at TEXT
+//! )
+//! ```
+//!
+//! Entries are numbered in the order of the changes, so an application
+//! polls with [`Database::cdc_events_since`](crate::Database::cdc_events_since),
+//! passing the id of the last event it has seen. The log is an ordinary
+//! table: the application deletes the entries it no longer needs.
+//!
+//! The triggers list the table's columns when they are created. After
+//! columns are added, renamed, or dropped, enabling CDC on the table again
+//! replaces them.
+
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+use crate::{QueryResult, Row};
+
+/// The table change events are written to.
+pub const CDC_LOG_TABLE: &str = "__cdc_log";
+
+/// The kind of change a [`CdcEvent`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CdcOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl CdcOperation {
+    const ALL: [CdcOperation; 3] = [CdcOperation::Insert, CdcOperation::Update, CdcOperation::Delete];
+
+    /// The name of the operation as stored in the log: `INSERT`, `UPDATE`,
+    /// or `DELETE`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CdcOperation::Insert => "INSERT",
+            CdcOperation::Update => "UPDATE",
+            CdcOperation::Delete => "DELETE",
+        }
+    }
+}
+
+/// A row change recorded in the CDC log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CdcEvent {
+    /// The position of the event in the log; later changes have larger ids.
+    pub id: i64,
+    pub table_name: String,
+    pub operation: CdcOperation,
+    /// The row before the change as a JSON object, or `None` for an insert.
+    pub old_row_json: Option<String>,
+    /// The row after the change as a JSON object, or `None` for a delete.
+    pub new_row_json: Option<String>,
+    /// When the change was made, as `YYYY-MM-DD HH:MM:SS` in UTC.
+    pub changed_at: String,
+}
+
+/// The SQL that creates the CDC log if needed and (re)creates the triggers
+/// recording the changes to `table`, whose columns are `columns`.
+pub(crate) fn enable_sql(table: &str, columns: &[String]) -> Result<String> {
+    if table.eq_ignore_ascii_case(CDC_LOG_TABLE) {
+        return Err(HorizonError::InvalidSql(format!("cannot enable CDC on {}", CDC_LOG_TABLE)));
+    }
+    let mut sql = format!(
+        "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, table_name TEXT, operation TEXT, \
+         old_row_json TEXT, new_row_json TEXT, changed_at TEXT);\n",
+        CDC_LOG_TABLE
+    );
+    for operation in CdcOperation::ALL {
+        let trigger = quote_identifier(&format!("{}_{}_{}", CDC_LOG_TABLE, table, operation.as_str().to_lowercase()));
+        let (old, new) = match operation {
+            CdcOperation::Insert => ("NULL".to_string(), row_json("NEW", columns)),
+            CdcOperation::Update => (row_json("OLD", columns), row_json("NEW", columns)),
+            CdcOperation::Delete => (row_json("OLD", columns), "NULL".to_string()),
+        };
+        sql.push_str(&format!("DROP TRIGGER IF EXISTS {};\n", trigger));
+        sql.push_str(&format!(
+            "CREATE TRIGGER {} AFTER {} ON {} FOR EACH ROW BEGIN \
+             INSERT INTO {} (table_name, operation, old_row_json, new_row_json, changed_at) \
+             VALUES ({}, '{}', {}, {}, DATETIME('now')); END;\n",
+            trigger,
+            operation.as_str(),
+            quote_identifier(table),
+            CDC_LOG_TABLE,
+            quote_string(table),
+            operation.as_str(),
+            old,
+            new,
+        ));
+    }
+    Ok(sql)
+}
+
+/// The query for the events after `cursor`, in order.
+pub(crate) fn events_since_sql(cursor: i64) -> String {
+    format!(
+        "SELECT id, table_name, operation, old_row_json, new_row_json, changed_at FROM {} WHERE id > {} ORDER BY id",
+        CDC_LOG_TABLE, cursor
+    )
+}
+
+/// The events in the result of [`events_since_sql`].
+pub(crate) fn events(result: QueryResult) -> Result<Vec<CdcEvent>> {
+    result.rows.iter().map(event).collect()
+}
+
+fn event(row: &Row) -> Result<CdcEvent> {
+    let operation = row.get_typed::<String>("operation")?;
+    let operation = CdcOperation::ALL
+        .into_iter()
+        .find(|op| op.as_str() == operation)
+        .ok_or_else(|| HorizonError::CorruptDatabase(format!("unknown CDC operation: {}", operation)))?;
+    Ok(CdcEvent {
+        id: row.get_typed("id")?,
+        table_name: row.get_typed("table_name")?,
+        operation,
+        old_row_json: row.get_typed("old_row_json")?,
+        new_row_json: row.get_typed("new_row_json")?,
+        changed_at: row.get_typed("changed_at")?,
+    })
+}
+
+/// `JSON_OBJECT(...)` over the columns of the trigger row `qualifier`
+/// (`NEW` or `OLD`).
+fn row_json(qualifier: &str, columns: &[String]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|c| format!("{}, {}.{}", quote_string(c), qualifier, quote_identifier(c)))
+        .collect();
+    format!("JSON_OBJECT({})", fields.join(", "))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_string(s: &str) -> String {
+    crate::execution::dump::sql_literal(&Value::Text(s.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triggers_list_the_columns_of_the_table() {
+        let sql = enable_sql("t", &["id".to_string(), "it's".to_string()]).unwrap();
+        assert!(sql.contains(r#"CREATE TRIGGER "__cdc_log_t_update" AFTER UPDATE ON "t""#));
+        assert!(sql.contains(r#"JSON_OBJECT('id', OLD."id", 'it''s', OLD."it's")"#));
+        assert!(enable_sql("__CDC_LOG", &[]).is_err());
+    }
+}
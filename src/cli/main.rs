@@ -6,7 +6,7 @@ use std::env;
 use std::io::{self, BufRead, Write};
 use std::time::Instant;
 
-use horizon::Database;
+use horizon::{Database, Row};
 
 /// Output formatting mode.
 #[derive(Clone, Copy, PartialEq)]
@@ -491,11 +491,8 @@ fn handle_dot_command(cmd: &str, db: &Database, config: &mut Config) {
         }
 
         ".tables" => {
-            match db.query("PRAGMA database_list") {
-                Ok(_) => {
-                    // Use PRAGMA table_info approach - list from catalog
-                }
-                Err(_) => {}
+            if db.query("PRAGMA database_list").is_ok() {
+                // Use PRAGMA table_info approach - list from catalog
             }
             // Fallback: try to get tables from catalog
             // The cleanest way is to query all table names
@@ -645,6 +642,25 @@ fn handle_dot_command(cmd: &str, db: &Database, config: &mut Config) {
     }
 }
 
+/// The `PRIMARY KEY (a, b, ...)` table constraint for a `PRAGMA table_info`
+/// result whose key spans several columns.
+fn composite_primary_key(rows: &[Row]) -> Option<String> {
+    let mut keys: Vec<(i64, &str)> = rows
+        .iter()
+        .filter_map(|row| {
+            let pk = row.get("pk").and_then(|v| v.as_integer()).unwrap_or(0);
+            let name = row.get("name").and_then(|v| v.as_text())?;
+            (pk > 0).then_some((pk, name))
+        })
+        .collect();
+    if keys.len() < 2 {
+        return None;
+    }
+    keys.sort();
+    let names: Vec<&str> = keys.into_iter().map(|(_, name)| name).collect();
+    Some(format!("PRIMARY KEY ({})", names.join(", ")))
+}
+
 fn show_schema_for_table(db: &Database, table: &str, _config: &Config) {
     match db.query(&format!("PRAGMA table_info({})", table)) {
         Ok(result) => {
@@ -654,6 +670,7 @@ fn show_schema_for_table(db: &Database, table: &str, _config: &Config) {
             }
 
             print!("CREATE TABLE {} (", table);
+            let composite_pk = composite_primary_key(&result.rows);
             let mut first = true;
             for row in &result.rows {
                 if !first {
@@ -670,13 +687,16 @@ fn show_schema_for_table(db: &Database, table: &str, _config: &Config) {
                 if !type_name.is_empty() {
                     print!(" {}", type_name);
                 }
-                if pk == 1 {
+                if pk == 1 && composite_pk.is_none() {
                     print!(" PRIMARY KEY");
                 }
                 if notnull == 1 {
                     print!(" NOT NULL");
                 }
             }
+            if let Some(ref key) = composite_pk {
+                print!(", {}", key);
+            }
             println!(");");
         }
         Err(e) => eprintln!("Error: {}", e),
@@ -684,83 +704,10 @@ fn show_schema_for_table(db: &Database, table: &str, _config: &Config) {
 }
 
 fn dump_database(db: &Database) {
-    println!("BEGIN TRANSACTION;");
-
-    match db.query("SELECT name FROM __schema_tables") {
-        Ok(tables) => {
-            for trow in &tables.rows {
-                let table_name = match trow.values.first().and_then(|v| v.as_text()) {
-                    Some(n) => n.to_string(),
-                    None => continue,
-                };
-
-                // Get schema
-                match db.query(&format!("PRAGMA table_info({})", table_name)) {
-                    Ok(info) => {
-                        if info.is_empty() {
-                            continue;
-                        }
-
-                        print!("CREATE TABLE {} (", table_name);
-                        let mut cols = Vec::new();
-                        let mut col_names = Vec::new();
-                        for (i, row) in info.rows.iter().enumerate() {
-                            let name =
-                                row.get("name").and_then(|v| v.as_text()).unwrap_or("?");
-                            let type_name =
-                                row.get("type").and_then(|v| v.as_text()).unwrap_or("");
-                            let notnull =
-                                row.get("notnull").and_then(|v| v.as_integer()).unwrap_or(0);
-                            let pk = row.get("pk").and_then(|v| v.as_integer()).unwrap_or(0);
-
-                            let mut def = name.to_string();
-                            if !type_name.is_empty() {
-                                def.push(' ');
-                                def.push_str(type_name);
-                            }
-                            if pk == 1 {
-                                def.push_str(" PRIMARY KEY");
-                            }
-                            if notnull == 1 {
-                                def.push_str(" NOT NULL");
-                            }
-                            if i > 0 {
-                                print!(", ");
-                            }
-                            print!("{}", def);
-                            cols.push(def);
-                            col_names.push(name.to_string());
-                        }
-                        println!(");");
-
-                        // Dump data
-                        let col_list = col_names.join(", ");
-                        match db.query(&format!("SELECT {} FROM {}", col_list, table_name)) {
-                            Ok(data) => {
-                                for row in &data.rows {
-                                    let vals: Vec<String> = row
-                                        .values
-                                        .iter()
-                                        .map(|v| sql_literal(v))
-                                        .collect();
-                                    println!(
-                                        "INSERT INTO {} VALUES({});",
-                                        table_name,
-                                        vals.join(", ")
-                                    );
-                                }
-                            }
-                            Err(e) => eprintln!("-- Error dumping {}: {}", table_name, e),
-                        }
-                    }
-                    Err(e) => eprintln!("-- Error getting schema for {}: {}", table_name, e),
-                }
-            }
-        }
-        Err(_) => {}
+    match db.dump_sql() {
+        Ok(sql) => print!("{}", sql),
+        Err(e) => eprintln!("Error: {}", e),
     }
-
-    println!("COMMIT;");
 }
 
 fn read_sql_file(db: &Database, path: &str, config: &Config) {
@@ -870,26 +817,6 @@ fn parse_csv_line(line: &str) -> Vec<String> {
 
 // --- Utility functions ---
 
-fn sql_literal(v: &horizon::Value) -> String {
-    if v.is_null() {
-        "NULL".to_string()
-    } else if let Some(n) = v.as_integer() {
-        n.to_string()
-    } else if let Some(f) = v.as_real() {
-        format!("{}", f)
-    } else if let Some(s) = v.as_text() {
-        format!("'{}'", s.replace('\'', "''"))
-    } else if let Some(b) = v.as_blob() {
-        format!("X'{}'", hex_encode(b))
-    } else {
-        "NULL".to_string()
-    }
-}
-
-fn hex_encode(bytes: &[u8]) -> String {
-    bytes.iter().map(|b| format!("{:02X}", b)).collect()
-}
-
 fn csv_escape(s: &str) -> String {
     if s.contains(',') || s.contains('"') || s.contains('\n') {
         format!("\"{}\"", s.replace('"', "\"\""))
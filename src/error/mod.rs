@@ -36,8 +36,8 @@ pub enum HorizonError {
     /// `NOT NULL`, `FOREIGN KEY`, and `CHECK` constraints.
     ConstraintViolation(String),
 
-    /// A transaction-level error such as a commit or rollback failure,
-    /// a serialization conflict under MVCC, or a deadlock.
+    /// A transaction-level error such as a commit or rollback failure or
+    /// a serialization conflict under MVCC.
     TransactionError(String),
 
     /// The referenced table does not exist in the catalog.
@@ -83,6 +83,30 @@ pub enum HorizonError {
     /// An error occurred during deserialization of a value or data structure
     /// from its binary representation.
     Deserialization(String),
+
+    /// A query expected to return at most one row returned several.
+    TooManyRows,
+
+    /// The query was cancelled through its
+    /// [`QueryHandle`](crate::cancel::QueryHandle) or cancel flag.
+    Cancelled,
+
+    /// Another connection held the database's write lock for longer than
+    /// the busy timeout.
+    Busy(String),
+
+    /// A row was locked by another transaction for longer than the lock
+    /// timeout.
+    LockConflict(String),
+
+    /// The transaction was chosen as the victim of a deadlock between
+    /// transactions waiting for each other's row locks, and has been
+    /// rolled back.
+    Deadlock(String),
+
+    /// A schema migration could not be applied or rolled back, for example
+    /// because one already applied has been changed since.
+    Migration(String),
 }
 
 impl fmt::Display for HorizonError {
@@ -117,6 +141,46 @@ impl fmt::Display for HorizonError {
             HorizonError::Deserialization(msg) => {
                 write!(f, "deserialization error: {msg}")
             }
+            HorizonError::TooManyRows => write!(f, "query returned more than one row"),
+            HorizonError::Cancelled => write!(f, "query cancelled"),
+            HorizonError::Busy(msg) => write!(f, "database is busy: {msg}"),
+            HorizonError::LockConflict(msg) => write!(f, "lock conflict: {msg}"),
+            HorizonError::Deadlock(msg) => write!(f, "deadlock: {msg}"),
+            HorizonError::Migration(msg) => write!(f, "migration error: {msg}"),
+        }
+    }
+}
+
+/// `io::Error` is not `Clone`, so a cloned [`HorizonError::Io`] carries a
+/// new error with the same kind and message.
+impl Clone for HorizonError {
+    fn clone(&self) -> Self {
+        match self {
+            HorizonError::Io(err) => HorizonError::Io(io::Error::new(err.kind(), err.to_string())),
+            HorizonError::CorruptDatabase(msg) => HorizonError::CorruptDatabase(msg.clone()),
+            HorizonError::InvalidSql(msg) => HorizonError::InvalidSql(msg.clone()),
+            HorizonError::TypeError(msg) => HorizonError::TypeError(msg.clone()),
+            HorizonError::ConstraintViolation(msg) => HorizonError::ConstraintViolation(msg.clone()),
+            HorizonError::TransactionError(msg) => HorizonError::TransactionError(msg.clone()),
+            HorizonError::TableNotFound(name) => HorizonError::TableNotFound(name.clone()),
+            HorizonError::ColumnNotFound(name) => HorizonError::ColumnNotFound(name.clone()),
+            HorizonError::IndexNotFound(name) => HorizonError::IndexNotFound(name.clone()),
+            HorizonError::DuplicateTable(name) => HorizonError::DuplicateTable(name.clone()),
+            HorizonError::DuplicateColumn(name) => HorizonError::DuplicateColumn(name.clone()),
+            HorizonError::DuplicateIndex(name) => HorizonError::DuplicateIndex(name.clone()),
+            HorizonError::BufferPoolFull => HorizonError::BufferPoolFull,
+            HorizonError::PageNotFound(id) => HorizonError::PageNotFound(*id),
+            HorizonError::Overflow(msg) => HorizonError::Overflow(msg.clone()),
+            HorizonError::Internal(msg) => HorizonError::Internal(msg.clone()),
+            HorizonError::NotImplemented(msg) => HorizonError::NotImplemented(msg.clone()),
+            HorizonError::ReadOnly(msg) => HorizonError::ReadOnly(msg.clone()),
+            HorizonError::Deserialization(msg) => HorizonError::Deserialization(msg.clone()),
+            HorizonError::TooManyRows => HorizonError::TooManyRows,
+            HorizonError::Cancelled => HorizonError::Cancelled,
+            HorizonError::Busy(msg) => HorizonError::Busy(msg.clone()),
+            HorizonError::LockConflict(msg) => HorizonError::LockConflict(msg.clone()),
+            HorizonError::Deadlock(msg) => HorizonError::Deadlock(msg.clone()),
+            HorizonError::Migration(msg) => HorizonError::Migration(msg.clone()),
         }
     }
 }
@@ -166,6 +230,18 @@ mod tests {
         assert!(matches!(err, HorizonError::Io(_)));
     }
 
+    #[test]
+    fn cloned_io_error_keeps_kind_and_message() {
+        let err = HorizonError::from(io::Error::new(io::ErrorKind::NotFound, "file missing"));
+        match err.clone() {
+            HorizonError::Io(io_err) => {
+                assert_eq!(io_err.kind(), io::ErrorKind::NotFound);
+                assert_eq!(io_err.to_string(), "file missing");
+            }
+            other => panic!("expected an I/O error, got {other:?}"),
+        }
+    }
+
     #[test]
     fn display_messages_are_human_readable() {
         let cases: Vec<(HorizonError, &str)> = vec![
@@ -234,6 +310,24 @@ mod tests {
                 HorizonError::ReadOnly("cannot INSERT in read-only mode".into()),
                 "read-only: cannot INSERT in read-only mode",
             ),
+            (HorizonError::TooManyRows, "query returned more than one row"),
+            (HorizonError::Cancelled, "query cancelled"),
+            (
+                HorizonError::Busy("test.hdb is locked".into()),
+                "database is busy: test.hdb is locked",
+            ),
+            (
+                HorizonError::LockConflict("row in table t is locked by transaction 3".into()),
+                "lock conflict: row in table t is locked by transaction 3",
+            ),
+            (
+                HorizonError::Deadlock("transaction 4 was rolled back".into()),
+                "deadlock: transaction 4 was rolled back",
+            ),
+            (
+                HorizonError::Migration("001_init has changed since it was applied".into()),
+                "migration error: 001_init has changed since it was applied",
+            ),
         ];
 
         for (error, expected) in cases {
@@ -0,0 +1,178 @@
+//! Schema-qualified table names for attached databases.
+//!
+//! Statements may name the tables of a database attached with
+//! `ATTACH DATABASE 'file' AS schema` as `schema.table`, and the tables of
+//! the main database as `main.table`. Before such a statement runs,
+//! [`resolve_schemas`] rewrites it into one the engine of a single database
+//! can execute:
+//!
+//! - `main.` qualifiers are dropped.
+//! - Each table read from an attached database is replaced by a `VALUES`
+//!   list holding its rows, under the table's alias or name.
+//! - A CREATE TABLE, DROP TABLE, INSERT, UPDATE, or DELETE of an attached
+//!   table loses its qualifier and is reported so the caller runs it
+//!   against that database. The rows an `INSERT ... SELECT` inserts are
+//!   read from the main database beforehand.
+//!
+//! Each change to an attached database commits on its own, so the caller
+//! refuses them while a transaction is open on the main database.
+
+use crate::buffer::BufferPool;
+use crate::catalog::Catalog;
+use crate::error::{HorizonError, Result};
+use crate::sql::ast::*;
+use crate::QueryResult;
+use super::params;
+
+/// Looks up the buffer pool and catalog of an attached database by its
+/// lowercased schema name.
+pub type SchemaLookup<'a> = dyn Fn(&str) -> Option<(&'a BufferPool, &'a Catalog)> + 'a;
+
+pub(super) type TableVisitor<'a> = dyn FnMut(&mut FromClause) -> Result<()> + 'a;
+
+/// Rewrite the schema-qualified table names of `stmt` as described in the
+/// [module documentation](self).
+///
+/// Returns the lowercased schema of the attached database `stmt` writes
+/// to, or `None` if it runs against the main database.
+///
+/// # Errors
+///
+/// Returns [`HorizonError::TableNotFound`] for a table of a schema that is
+/// not attached, and any error reading the attached tables.
+pub fn resolve_schemas(
+    stmt: &mut Statement,
+    main: (&BufferPool, &Catalog),
+    attached: &SchemaLookup,
+) -> Result<Option<String>> {
+    let target = match stmt {
+        Statement::Insert(ins) => resolve_target(&mut ins.table, attached)?,
+        Statement::Update(upd) => resolve_target(&mut upd.table, attached)?,
+        Statement::Delete(del) => resolve_target(&mut del.table, attached)?,
+        Statement::CreateTable(ct) => resolve_target(&mut ct.name, attached)?,
+        Statement::DropTable(dt) => resolve_target(&mut dt.name, attached)?,
+        _ => None,
+    };
+    walk_tables(stmt, &mut |from| resolve_table(from, attached))?;
+
+    if let (Some(_), Statement::Insert(ins)) = (&target, &mut *stmt) {
+        if let InsertSource::Select(select) = &ins.source {
+            let result = super::execute_read_query(&Statement::Select((**select).clone()), main.0, main.1)?;
+            ins.source = InsertSource::Values(literal_rows(&result));
+        }
+    }
+    Ok(target)
+}
+
+/// Drop the schema from the name of the table a statement writes to,
+/// returning the schema if it is an attached one.
+fn resolve_target(name: &mut String, attached: &SchemaLookup) -> Result<Option<String>> {
+    let (schema, table) = match name.split_once('.') {
+        Some((schema, table)) => (schema.to_lowercase(), table.to_string()),
+        None => return Ok(None),
+    };
+    if schema == "main" {
+        *name = table;
+        return Ok(None);
+    }
+    if attached(&schema).is_none() {
+        return Err(HorizonError::TableNotFound(name.clone()));
+    }
+    *name = table;
+    Ok(Some(schema))
+}
+
+/// Resolve a schema-qualified table in a FROM clause.
+fn resolve_table(from: &mut FromClause, attached: &SchemaLookup) -> Result<()> {
+    let (name, alias) = match from {
+        FromClause::Table { name, alias } if name.contains('.') => (name, alias),
+        _ => return Ok(()),
+    };
+    let (schema, table) = name.split_once('.').unwrap_or_default();
+    let (schema, table) = (schema.to_lowercase(), table.to_string());
+    if schema == "main" {
+        *name = table;
+        return Ok(());
+    }
+    let (pool, catalog) = attached(&schema).ok_or_else(|| HorizonError::TableNotFound(name.clone()))?;
+    let result = super::execute_read_query(&select_all(&table), pool, catalog)?;
+    *from = FromClause::Values {
+        rows: literal_rows(&result),
+        alias: Some(alias.take().unwrap_or(table)),
+        columns: Some(result.columns.as_ref().clone()),
+    };
+    Ok(())
+}
+
+/// The rows of `result` as rows of literals.
+fn literal_rows(result: &QueryResult) -> Vec<Vec<Expr>> {
+    result
+        .rows
+        .iter()
+        .map(|row| row.values.iter().map(|v| Expr::Literal(params::value_to_literal(v))).collect())
+        .collect()
+}
+
+/// `SELECT * FROM table`.
+fn select_all(table: &str) -> Statement {
+    Statement::Select(SelectStatement {
+        ctes: vec![],
+        distinct: false,
+        columns: vec![SelectColumn::AllColumns],
+        from: Some(FromClause::Table { name: table.to_string(), alias: None }),
+        where_clause: None,
+        group_by: vec![],
+        group_by_modifier: None,
+        having: None,
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        compound: vec![],
+        windows: vec![],
+    })
+}
+
+/// Visit every table named in a FROM clause of `stmt`, including those of
+/// its CTEs and subqueries.
+pub(super) fn walk_tables(stmt: &mut Statement, f: &mut TableVisitor) -> Result<()> {
+    match stmt {
+        Statement::Select(select) => walk_select_from(select, f)?,
+        Statement::Insert(InsertStatement { source: InsertSource::Select(select), .. }) => {
+            walk_select_from(select, f)?
+        }
+        Statement::Explain(inner) | Statement::ExplainQueryPlan(inner) => return walk_tables(inner, f),
+        _ => {}
+    }
+    params::walk_statement(stmt, &mut |expr| match expr {
+        Expr::Subquery(select) | Expr::Exists(select) => walk_select_from(select, f),
+        _ => Ok(()),
+    })
+}
+
+/// Visit the tables in the FROM clauses of `select`. Subqueries in its
+/// expressions are left to the caller.
+fn walk_select_from(select: &mut SelectStatement, f: &mut TableVisitor) -> Result<()> {
+    for cte in &mut select.ctes {
+        walk_select_from(&mut cte.query, f)?;
+    }
+    if let Some(from) = &mut select.from {
+        walk_from(from, f)?;
+    }
+    for compound in &mut select.compound {
+        if let Some(from) = &mut compound.select.from {
+            walk_from(from, f)?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_from(from: &mut FromClause, f: &mut TableVisitor) -> Result<()> {
+    match from {
+        FromClause::Join { left, right, .. } => {
+            walk_from(left, f)?;
+            walk_from(right, f)
+        }
+        FromClause::Subquery { query, .. } => walk_select_from(query, f),
+        _ => f(from),
+    }
+}
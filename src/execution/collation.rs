@@ -0,0 +1,262 @@
+//! Collation sequences.
+//!
+//! A collation decides how ORDER BY compares two text values:
+//! `ORDER BY name COLLATE NOCASE` orders names ignoring case. These are
+//! built in:
+//!
+//! - BINARY (the default) compares code points.
+//! - NOCASE ignores case.
+//! - RTRIM ignores trailing spaces.
+//! - UNICODE ignores case, using Unicode case folding, and sorts accented
+//!   letters right after their base letters: `e < é < f`.
+//! - UNICODENOACCENT ignores accents as well, so `'café' = 'cafe'`.
+//!
+//! Accents are combining diacritical marks, and the marks of precomposed
+//! Latin letters such as `é` or `ṩ`.
+//!
+//! Further collations are registered on a connection with
+//! [`Database::create_collation`](crate::Database::create_collation) and
+//! kept in its [`Collations`], which the catalog holds like the other
+//! per-connection settings. A registered collation replaces a built-in one
+//! of the same name. A comparison such as `a = b COLLATE UNICODE` uses the
+//! built-in collations only.
+//!
+//! Collations only apply to text: other values, and text compared with a
+//! value of another type, compare as they do without one. ORDER BY and
+//! index keys fail on an unknown collation name with a "no such collation
+//! sequence" error; a comparison treats one like BINARY.
+//!
+//! An index whose key uses a collation keeps its entries in the order of
+//! that collation (see [`index_key_order`]), so it can read rows in the
+//! order of an ORDER BY using the same collation. Its collation must be
+//! registered whenever the index is read or written.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::extract_collation;
+use crate::btree::KeyOrder;
+use crate::catalog::IndexInfo;
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+
+/// The names of the built-in collations.
+const BUILT_IN: [&str; 5] = ["BINARY", "NOCASE", "RTRIM", "UNICODE", "UNICODENOACCENT"];
+
+/// A comparison function registered as a collation.
+pub type CollationFn = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+
+/// The collations registered on a connection, keyed by upper-cased name.
+#[derive(Clone, Default)]
+pub struct Collations {
+    registered: HashMap<String, CollationFn>,
+}
+
+impl Collations {
+    /// A registry holding only the built-in collations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `compare` as the collation `name`, replacing any collation
+    /// of that name. Names are case-insensitive.
+    pub fn register(&mut self, name: &str, compare: CollationFn) {
+        self.registered.insert(name.to_uppercase(), compare);
+    }
+
+    /// Fail unless `name` is a registered or built-in collation.
+    pub fn check(&self, name: &str) -> Result<()> {
+        let upper = name.to_uppercase();
+        if self.registered.contains_key(&upper) || BUILT_IN.contains(&upper.as_str()) {
+            Ok(())
+        } else {
+            Err(HorizonError::InvalidSql(format!("no such collation sequence: {}", name)))
+        }
+    }
+
+    /// Compare two values using the collation `name`.
+    pub fn compare(&self, a: &Value, b: &Value, name: &str) -> Ordering {
+        let (sa, sb) = match (a, b) {
+            (Value::Text(sa), Value::Text(sb)) => (sa, sb),
+            _ => return a.cmp(b),
+        };
+        let upper = name.to_uppercase();
+        if let Some(compare) = self.registered.get(&upper) {
+            return compare(sa, sb);
+        }
+        match upper.as_str() {
+            "NOCASE" => sa.to_lowercase().cmp(&sb.to_lowercase()),
+            "RTRIM" => sa.trim_end().cmp(sb.trim_end()),
+            "UNICODE" => {
+                let (fa, fb) = (fold_case(sa), fold_case(sb));
+                strip_accents(&fa).cmp(&strip_accents(&fb)).then_with(|| fa.cmp(&fb))
+            }
+            "UNICODENOACCENT" => strip_accents(&fold_case(sa)).cmp(&strip_accents(&fold_case(sb))),
+            // BINARY, or a name `check` rejects
+            _ => a.cmp(b),
+        }
+    }
+}
+
+/// The order of the keys of `index` if a key part uses a collation other
+/// than BINARY, or `None` if its keys are in byte order.
+///
+/// An index key is the [`Value::serialize_sortable`] encoding of each key
+/// part followed by the row key. The parts are compared in turn: two text
+/// values of a collated part by its collation, anything else by its
+/// encoding. Keys that compare equal are ordered by their bytes. A key
+/// holding only the first few parts, such as the bound of an index scan,
+/// sorts before the keys it is a prefix of.
+///
+/// # Errors
+///
+/// Fails if a key part uses a collation `collations` does not know.
+pub fn index_key_order(index: &IndexInfo, collations: &Collations) -> Result<Option<KeyOrder>> {
+    let mut parts = Vec::with_capacity(index.columns.len());
+    for i in 0..index.columns.len() {
+        let collation = index.key_expr(i).and_then(extract_collation).filter(|c| !c.eq_ignore_ascii_case("BINARY"));
+        if let Some(name) = collation {
+            collations.check(name)?;
+        }
+        parts.push(collation.map(str::to_string));
+    }
+    if parts.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+    let collations = collations.clone();
+    Ok(Some(Arc::new(move |a: &[u8], b: &[u8]| compare_index_keys(&parts, &collations, a, b))))
+}
+
+/// Compare two index keys whose parts use the collations `parts`, as
+/// described for [`index_key_order`].
+fn compare_index_keys(parts: &[Option<String>], collations: &Collations, a: &[u8], b: &[u8]) -> Ordering {
+    let text = |part: &[u8]| match Value::deserialize_sortable(part) {
+        Some((text @ Value::Text(_), _)) => Some(text),
+        _ => None,
+    };
+    let (mut rest_a, mut rest_b) = (a, b);
+    for collation in parts {
+        let (Some(len_a), Some(len_b)) = (Value::sortable_len(rest_a), Value::sortable_len(rest_b)) else {
+            break;
+        };
+        let (part_a, part_b) = (&rest_a[..len_a], &rest_b[..len_b]);
+        let collated = collation.as_ref().and_then(|name| Some((name, text(part_a)?, text(part_b)?)));
+        let ordering = match collated {
+            Some((name, text_a, text_b)) => collations.compare(&text_a, &text_b, name),
+            None => part_a.cmp(part_b),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+        rest_a = &rest_a[len_a..];
+        rest_b = &rest_b[len_b..];
+    }
+    rest_a.cmp(rest_b).then_with(|| a.cmp(b))
+}
+
+/// The base letter of each code point from U+00C0 to U+023F (Latin-1
+/// Supplement to Latin Extended-B), or `.` for one that is not a letter
+/// with diacritics.
+const LATIN_BASE_LETTERS: &str = concat!(
+    "AAAAAA.CEEEEIIII.NOOOOO..UUUUY..aaaaaa.ceeeeiiii.nooooo..uuuuy.y", // U+00C0
+    "AaAaAaCcCcCcCcDd..EeEeEeEeEeGgGgGgGgHh..IiIiIiIiI...JjKk.LlLlLl.", // U+0100
+    "...NnNnNn...OoOoOo..RrRrRrSsSsSsSsTtTt..UuUuUuUuUuUuWwYyYZzZzZz.", // U+0140
+    "................................Oo.............Uu...............", // U+0180
+    ".............AaIiOoUuUuUuUuUu.AaAa....GgKkOoOo..j...Gg..NnAa....", // U+01C0
+    "AaAaEeEeIiIiOoOoRrRrUuUuSsTt..Hh......AaEeOoOoOoOoYy............", // U+0200
+);
+
+/// The base letter of each code point from U+1E00 to U+1EFF (Latin
+/// Extended Additional), as in [`LATIN_BASE_LETTERS`].
+const LATIN_EXTENDED_ADDITIONAL_BASE_LETTERS: &str = concat!(
+    "AaBbBbBbCcDdDdDdDdDdEeEeEeEeEeFfGgHhHhHhHhHhIiIiKkKkKkLlLlLlLlMm", // U+1E00
+    "MmMmNnNnNnNnOoOoOoOoPpPpRrRrRrRrSsSsSsSsSsTtTtTtTtUuUuUuUuUuVvVv", // U+1E40
+    "WwWwWwWwWwXxXxYyZzZzZzhtwy......AaAaAaAaAaAaAaAaAaAaAaAaEeEeEeEe", // U+1E80
+    "EeEeEeEeIiIiOoOoOoOoOoOoOoOoOoOoOoOoUuUuUuUuUuUuUuYyYyYyYy......", // U+1EC0
+);
+
+/// `s` with its case folded: lower-cased, with `ß` written `ss` and final
+/// sigma as sigma.
+fn fold_case(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// `s` without its combining diacritical marks, and with precomposed Latin
+/// letters replaced by their base letters.
+fn strip_accents(s: &str) -> String {
+    s.chars().filter(|&c| !is_combining_mark(c)).map(base_letter).collect()
+}
+
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}' | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+/// The letter `c` is written with diacritics on, or `c` itself.
+fn base_letter(c: char) -> char {
+    let (table, first) = match c {
+        '\u{00C0}'..='\u{023F}' => (LATIN_BASE_LETTERS, 0xC0),
+        '\u{1E00}'..='\u{1EFF}' => (LATIN_EXTENDED_ADDITIONAL_BASE_LETTERS, 0x1E00),
+        _ => return c,
+    };
+    match table.as_bytes()[c as usize - first] {
+        b'.' => c,
+        base => base as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    #[test]
+    fn registered_collations_compare_text_only() {
+        let mut collations = Collations::new();
+        assert_eq!(collations.compare(&text("a"), &text("B"), "nocase"), Ordering::Less);
+        assert_eq!(collations.compare(&text("a "), &text("a"), "RTRIM"), Ordering::Equal);
+        assert_eq!(collations.compare(&text("a"), &text("B"), "BINARY"), Ordering::Greater);
+
+        collations.register("Reverse", Arc::new(|a: &str, b: &str| b.cmp(a)));
+        assert_eq!(collations.compare(&text("a"), &text("b"), "reverse"), Ordering::Greater);
+        assert_eq!(collations.compare(&Value::Integer(1), &Value::Integer(2), "reverse"), Ordering::Less);
+
+        // A registered collation replaces a built-in one
+        collations.register("NOCASE", Arc::new(|a: &str, b: &str| a.len().cmp(&b.len())));
+        assert_eq!(collations.compare(&text("b"), &text("AA"), "nocase"), Ordering::Less);
+    }
+
+    #[test]
+    fn unicode_collations_fold_case_and_accents() {
+        let collations = Collations::new();
+        let unicode = |a: &str, b: &str| collations.compare(&text(a), &text(b), "UNICODE");
+        let no_accent = |a: &str, b: &str| collations.compare(&text(a), &text(b), "UNICODENOACCENT");
+
+        assert_eq!(unicode("Ö", "ö"), Ordering::Equal);
+        assert_eq!(unicode("ΣΟΦΟΣ", "σοφος"), Ordering::Equal);
+        assert_eq!(unicode("STRASSE", "straße"), Ordering::Equal);
+        assert_eq!(unicode("é", "e"), Ordering::Greater);
+        assert_eq!(unicode("é", "f"), Ordering::Less);
+        assert_eq!(unicode("École", "ecole"), Ordering::Greater);
+
+        assert_eq!(no_accent("café", "CAFE"), Ordering::Equal);
+        assert_eq!(no_accent("Ṩ", "s"), Ordering::Equal);
+        // The same letter written with a combining mark
+        assert_eq!(no_accent("cafe\u{0301}", "café"), Ordering::Equal);
+        assert_eq!(no_accent("Øl", "ol"), Ordering::Greater);
+    }
+}
@@ -0,0 +1,102 @@
+//! Cryptographic hash functions for Horizon DB (feature `crypto`).
+//!
+//! The caller evaluates the arguments; this module only sees their values.
+//! Blobs are hashed as their raw bytes, text as its UTF-8 bytes, and
+//! numbers as their decimal text. A NULL argument yields NULL, and digests
+//! are returned as upper-case hex text, the same form `HEX()` produces.
+
+use super::check_arity;
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+
+/// Whether `name` (upper case) is a function handled by [`eval_hash_function`].
+pub(super) fn is_hash_function(name: &str) -> bool {
+    matches!(name, "SHA256" | "MD5" | "HMAC_SHA256")
+}
+
+/// The bytes to hash for an argument, or None for NULL.
+fn hash_input(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Null => None,
+        Value::Blob(b) => Some(b.clone()),
+        Value::Text(s) => Some(s.as_bytes().to_vec()),
+        Value::Integer(_) | Value::Real(_) => Some(value.to_string().into_bytes()),
+    }
+}
+
+/// Evaluate the hash function `name` (upper case) on `args`.
+pub(super) fn eval_hash_function(name: &str, args: &[Value]) -> Result<Value> {
+    let arity = if name == "HMAC_SHA256" { 2..=2 } else { 1..=1 };
+    check_arity(name, args, arity)?;
+    let inputs: Option<Vec<Vec<u8>>> = args.iter().map(hash_input).collect();
+    match inputs {
+        Some(inputs) => digest(name, &inputs).map(|d| Value::Text(to_hex(&d))),
+        None => Ok(Value::Null),
+    }
+}
+
+#[cfg(feature = "crypto")]
+fn digest(name: &str, inputs: &[Vec<u8>]) -> Result<Vec<u8>> {
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+
+    Ok(match name {
+        "SHA256" => Sha256::digest(&inputs[0]).to_vec(),
+        "MD5" => md5::compute(&inputs[0]).0.to_vec(),
+        "HMAC_SHA256" => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(&inputs[0])
+                .expect("HMAC accepts keys of any length");
+            mac.update(&inputs[1]);
+            mac.finalize().into_bytes().to_vec()
+        }
+        _ => return Err(HorizonError::NotImplemented(format!("function: {}", name))),
+    })
+}
+
+#[cfg(not(feature = "crypto"))]
+fn digest(name: &str, _inputs: &[Vec<u8>]) -> Result<Vec<u8>> {
+    Err(HorizonError::NotImplemented(format!(
+        "{}() requires the `crypto` feature",
+        name.to_lowercase()
+    )))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(all(test, feature = "crypto"))]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        eval_hash_function(name, args).unwrap()
+    }
+
+    fn t(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(
+            call("SHA256", &[t("")]),
+            t("E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855")
+        );
+        assert_eq!(call("MD5", &[t("hello")]), t("5D41402ABC4B2A76B9719D911017C592"));
+        // RFC 4231, test case 2
+        assert_eq!(
+            call("HMAC_SHA256", &[t("Jefe"), t("what do ya want for nothing?")]),
+            t("5BDCC146BF60754E6A042426089575C75A003F089D2739839DEC58B964EC3843")
+        );
+    }
+
+    #[test]
+    fn inputs_are_hashed_as_bytes() {
+        assert_eq!(call("MD5", &[Value::Blob(b"hello".to_vec())]), call("MD5", &[t("hello")]));
+        assert_eq!(call("SHA256", &[Value::Integer(42)]), call("SHA256", &[t("42")]));
+        assert_eq!(call("SHA256", &[Value::Null]), Value::Null);
+        assert_eq!(call("HMAC_SHA256", &[t("k"), Value::Null]), Value::Null);
+        assert!(eval_hash_function("MD5", &[]).is_err());
+    }
+}
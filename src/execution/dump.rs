@@ -0,0 +1,264 @@
+//! SQL text dumps of a whole database.
+//!
+//! [`dump_sql`] renders the schema and contents of a database as a script
+//! of SQL statements that recreates it when executed against an empty
+//! database, in the spirit of the SQLite shell's `.dump` command.
+
+use super::views_triggers::expr_to_sql;
+use super::deserialize_row;
+use crate::btree::BTree;
+use crate::buffer::BufferPool;
+use crate::catalog::{
+    Catalog, ColumnInfo, ForeignKeyInfo, IndexInfo, TableInfo, TriggerEventKind, TriggerInfo,
+    TriggerTimingKind,
+};
+use crate::error::Result;
+use crate::sql::ast::FkAction;
+use crate::types::Value;
+
+/// Render the database as a SQL script.
+///
+/// The script is wrapped in a single transaction and holds, in order, the
+/// `PRAGMA user_version` (unless it is 0), a `CREATE TABLE` and the `INSERT`
+/// statements for each table, then the `CREATE INDEX` statements, then the
+/// `CREATE VIEW` statements, then the `CREATE TRIGGER` statements; each
+/// group is sorted by name so the same database always dumps the same way.
+/// Identifiers are double-quoted, text is escaped, and blobs are written as
+/// hex literals. Generated columns are recreated by their definitions
+/// rather than dumped as data.
+pub fn dump_sql(pool: &BufferPool, catalog: &Catalog) -> Result<String> {
+    let mut out = String::from("BEGIN TRANSACTION;\n");
+    let user_version = pool.pager().get_user_version();
+    if user_version != 0 {
+        out.push_str(&format!("PRAGMA user_version = {};\n", user_version));
+    }
+
+    let mut tables: Vec<&TableInfo> = catalog
+        .list_tables()
+        .into_iter()
+        .map(|name| catalog.get_table(name))
+        .collect::<Result<_>>()?;
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut indexes = Vec::new();
+    for table in &tables {
+        out.push_str(&create_table_sql(table));
+        out.push_str(";\n");
+        dump_rows(pool, table, &mut out)?;
+        indexes.extend(catalog.get_indexes_for_table(&table.name));
+    }
+
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+    for index in indexes {
+        out.push_str(&create_index_sql(index));
+        out.push_str(";\n");
+    }
+
+    let mut views = catalog.list_views();
+    views.sort_by(|a, b| a.name.cmp(&b.name));
+    for view in views {
+        out.push_str("CREATE VIEW ");
+        out.push_str(&quote_identifier(&view.name));
+        if let Some(ref columns) = view.columns {
+            out.push_str(&format!(" ({})", quote_identifiers(columns)));
+        }
+        out.push_str(" AS ");
+        out.push_str(&view.sql);
+        out.push_str(";\n");
+    }
+
+    let mut triggers = catalog.list_triggers();
+    triggers.sort_by(|a, b| a.name.cmp(&b.name));
+    for trigger in triggers {
+        out.push_str(&create_trigger_sql(trigger));
+        out.push_str(";\n");
+    }
+
+    out.push_str("COMMIT;\n");
+    Ok(out)
+}
+
+/// Quote an identifier with double quotes, doubling any it contains.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_identifiers(names: &[String]) -> String {
+    names.iter().map(|n| quote_identifier(n)).collect::<Vec<_>>().join(", ")
+}
+
+/// A SQL literal that reads back as `value`.
+pub(crate) fn sql_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        // Debug formatting keeps a decimal point, so 1.0 stays a real
+        Value::Real(r) if r.is_finite() => format!("{:?}", r),
+        Value::Real(r) if r.is_nan() => "NULL".to_string(),
+        Value::Real(r) => if *r > 0.0 { "9e999" } else { "-9e999" }.to_string(),
+        Value::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Blob(b) => {
+            format!("X'{}'", b.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())
+        }
+    }
+}
+
+fn create_table_sql(table: &TableInfo) -> String {
+    let mut defs: Vec<String> = table
+        .columns
+        .iter()
+        .map(|col| column_def_sql(table, col))
+        .collect();
+    if table.has_composite_pk() {
+        let key: Vec<String> = table
+            .pk_columns
+            .iter()
+            .map(|&i| table.columns[i].name.clone())
+            .collect();
+        defs.push(format!("PRIMARY KEY ({})", quote_identifiers(&key)));
+    }
+    for check in &table.checks {
+        defs.push(format!("CHECK ({})", expr_to_sql(check)));
+    }
+    for fk in &table.foreign_keys {
+        defs.push(foreign_key_sql(fk));
+    }
+    format!("CREATE TABLE {} ({})", quote_identifier(&table.name), defs.join(", "))
+}
+
+fn create_trigger_sql(trigger: &TriggerInfo) -> String {
+    let timing = match trigger.timing {
+        TriggerTimingKind::Before => "BEFORE",
+        TriggerTimingKind::After => "AFTER",
+        TriggerTimingKind::InsteadOf => "INSTEAD OF",
+    };
+    let event = match trigger.event {
+        TriggerEventKind::Insert => "INSERT",
+        TriggerEventKind::Update => "UPDATE",
+        TriggerEventKind::Delete => "DELETE",
+    };
+    let mut sql = format!(
+        "CREATE TRIGGER {} {} {} ON {}",
+        quote_identifier(&trigger.name),
+        timing,
+        event,
+        quote_identifier(&trigger.table)
+    );
+    if trigger.for_each_row {
+        sql.push_str(" FOR EACH ROW");
+    }
+    if let Some(ref when) = trigger.when {
+        sql.push_str(&format!(" WHEN {}", expr_to_sql(when)));
+    }
+    sql.push_str(" BEGIN");
+    for statement in &trigger.body_sql {
+        sql.push_str(&format!(" {};", statement));
+    }
+    sql.push_str(" END");
+    sql
+}
+
+fn column_def_sql(table: &TableInfo, col: &ColumnInfo) -> String {
+    let mut def = quote_identifier(&col.name);
+    if !col.type_name.is_empty() {
+        def.push(' ');
+        def.push_str(&col.type_name);
+    }
+    let single_pk = col.primary_key && !table.has_composite_pk();
+    if single_pk {
+        def.push_str(" PRIMARY KEY");
+        if col.autoincrement {
+            def.push_str(" AUTOINCREMENT");
+        }
+    }
+    // Key columns are implicitly NOT NULL, and a single key column UNIQUE
+    if col.not_null && !col.primary_key {
+        def.push_str(" NOT NULL");
+    }
+    if col.unique && !single_pk {
+        def.push_str(" UNIQUE");
+    }
+    if let Some(ref default) = col.default_value {
+        def.push_str(&format!(" DEFAULT ({})", sql_literal(default)));
+    }
+    if let Some(ref check) = col.check_expr {
+        def.push_str(&format!(" CHECK ({})", expr_to_sql(check)));
+    }
+    if let Some(ref expr) = col.generated_expr {
+        let kind = if col.is_stored { "STORED" } else { "VIRTUAL" };
+        def.push_str(&format!(" GENERATED ALWAYS AS ({}) {}", expr_to_sql(expr), kind));
+    }
+    def
+}
+
+fn foreign_key_sql(fk: &ForeignKeyInfo) -> String {
+    let mut sql = format!(
+        "FOREIGN KEY ({}) REFERENCES {}",
+        quote_identifiers(&fk.columns),
+        quote_identifier(&fk.ref_table)
+    );
+    if !fk.ref_columns.is_empty() {
+        sql.push_str(&format!(" ({})", quote_identifiers(&fk.ref_columns)));
+    }
+    for (event, action) in [("DELETE", fk.on_delete), ("UPDATE", fk.on_update)] {
+        let action = match action {
+            FkAction::NoAction => continue,
+            FkAction::Restrict => "RESTRICT",
+            FkAction::Cascade => "CASCADE",
+            FkAction::SetNull => "SET NULL",
+            FkAction::SetDefault => "SET DEFAULT",
+        };
+        sql.push_str(&format!(" ON {} {}", event, action));
+    }
+    sql
+}
+
+/// Append an `INSERT` for every row of `table`, naming only the columns
+/// that hold data (generated columns are computed again on restore).
+fn dump_rows(pool: &BufferPool, table: &TableInfo, out: &mut String) -> Result<()> {
+    let stored: Vec<usize> = (0..table.columns.len())
+        .filter(|&i| table.columns[i].generated_expr.is_none())
+        .collect();
+    let names: Vec<String> = stored.iter().map(|&i| table.columns[i].name.clone()).collect();
+    let prefix = format!(
+        "INSERT INTO {} ({}) VALUES (",
+        quote_identifier(&table.name),
+        quote_identifiers(&names)
+    );
+
+    for entry in BTree::open(table.root_page).scan_all(pool)? {
+        let row = deserialize_row(&entry.value, table.columns.len())?;
+        let values: Vec<String> = stored
+            .iter()
+            .map(|&i| sql_literal(row.get(i).unwrap_or(&Value::Null)))
+            .collect();
+        out.push_str(&prefix);
+        out.push_str(&values.join(", "));
+        out.push_str(");\n");
+    }
+    Ok(())
+}
+
+fn create_index_sql(index: &IndexInfo) -> String {
+    let keys: Vec<String> = index
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, col)| match index.key_expr(i) {
+            Some(expr) => expr_to_sql(expr),
+            None => quote_identifier(col),
+        })
+        .collect();
+    let mut sql = format!(
+        "CREATE {}INDEX {} ON {} ({})",
+        if index.unique { "UNIQUE " } else { "" },
+        quote_identifier(&index.name),
+        quote_identifier(&index.table_name),
+        keys.join(", ")
+    );
+    if let Some(ref predicate) = index.predicate {
+        sql.push_str(" WHERE ");
+        sql.push_str(&expr_to_sql(predicate));
+    }
+    sql
+}
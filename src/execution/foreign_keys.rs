@@ -0,0 +1,286 @@
+//! Foreign key enforcement for Horizon DB.
+//!
+//! Foreign keys are declared with `REFERENCES` clauses in `CREATE TABLE`
+//! and stored on the child table's [`TableInfo`]. As in SQLite, enforcement
+//! is off until `PRAGMA foreign_keys = ON`. While it is on:
+//!
+//! - INSERT and UPDATE on a child table require a parent row with a
+//!   matching key. A child key containing NULL is not checked.
+//! - DELETE on a parent table, and UPDATE of a parent key, apply the
+//!   `ON DELETE` / `ON UPDATE` action of every foreign key referencing it.
+
+use super::params::value_to_literal;
+use super::{deserialize_row, eval_expr, execute_delete, execute_update, scan_with_index};
+use crate::btree::BTree;
+use crate::buffer::BufferPool;
+use crate::catalog::{Catalog, ForeignKeyInfo, TableInfo};
+use crate::error::{HorizonError, Result};
+use crate::mvcc::TransactionManager;
+use crate::sql::ast::*;
+use crate::types::Value;
+
+/// Build the catalog entry for a foreign key declared on table `child`.
+pub(super) fn foreign_key_info(
+    child: &TableInfo,
+    columns: Vec<String>,
+    clause: &ForeignKeyClause,
+) -> Result<ForeignKeyInfo> {
+    if let Some(missing) = columns.iter().find(|c| child.find_column(c).is_none()) {
+        return Err(HorizonError::ColumnNotFound(format!("{}.{}", child.name, missing)));
+    }
+    if !clause.columns.is_empty() && clause.columns.len() != columns.len() {
+        return Err(HorizonError::InvalidSql(format!(
+            "foreign key on {} has {} columns but references {}",
+            child.name,
+            columns.len(),
+            clause.columns.len()
+        )));
+    }
+    Ok(ForeignKeyInfo {
+        columns,
+        ref_table: clause.table.clone(),
+        ref_columns: clause.columns.clone(),
+        on_delete: clause.on_delete,
+        on_update: clause.on_update,
+    })
+}
+
+/// Verify that every foreign key of `table` whose key is set in `row`
+/// points at an existing parent row. When `old_row` is given (UPDATE),
+/// keys that did not change are skipped.
+pub(super) fn check_parent_keys(
+    table: &TableInfo,
+    row: &[Value],
+    old_row: Option<&[Value]>,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<()> {
+    if !catalog.foreign_keys_enabled() {
+        return Ok(());
+    }
+    for fk in &table.foreign_keys {
+        let key = column_values(table, &fk.columns, row)?;
+        if key.iter().any(Value::is_null) {
+            continue;
+        }
+        if let Some(old) = old_row {
+            if column_values(table, &fk.columns, old)? == key {
+                continue;
+            }
+        }
+        let parent = catalog.get_table(&fk.ref_table)?;
+        let parent_cols = parent_columns(fk, parent)?;
+        if !has_matching_row(parent, &parent_cols, &key, pool, catalog)? {
+            return Err(HorizonError::ConstraintViolation(format!(
+                "FOREIGN KEY constraint failed: {}({}) references {}",
+                table.name,
+                fk.columns.join(", "),
+                fk.ref_table
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A change to a parent row's key, as seen by one foreign key referencing
+/// its table.
+struct KeyChange {
+    child: String,
+    fk: ForeignKeyInfo,
+    old_key: Vec<Value>,
+    /// The new key, or `None` when the parent row is deleted.
+    new_key: Option<Vec<Value>>,
+}
+
+/// The changes that deleting the parent row `old_row` of `table`
+/// (`new_row` is `None`) or updating it to `new_row` makes to the keys of
+/// foreign keys referencing it. Keys that are NULL or unchanged are left out.
+fn key_changes(
+    table: &TableInfo,
+    old_row: &[Value],
+    new_row: Option<&[Value]>,
+    catalog: &Catalog,
+) -> Result<Vec<KeyChange>> {
+    let mut changes = Vec::new();
+    for (child, fk) in catalog.foreign_keys_referencing(&table.name) {
+        let parent_cols = parent_columns(&fk, table)?;
+        let old_key = column_values(table, &parent_cols, old_row)?;
+        if old_key.iter().any(Value::is_null) {
+            continue;
+        }
+        let new_key = match new_row {
+            Some(row) => {
+                let key = column_values(table, &parent_cols, row)?;
+                if key == old_key {
+                    continue;
+                }
+                Some(key)
+            }
+            None => None,
+        };
+        changes.push(KeyChange { child, fk, old_key, new_key });
+    }
+    Ok(changes)
+}
+
+/// Fail if deleting or updating the parent row `old_row` of `table` (as
+/// for [`on_parent_change`]) would leave a child row of a RESTRICT or
+/// NO ACTION foreign key without its parent. This runs before the parent
+/// row is written, so a statement it rejects leaves the row unchanged.
+pub(super) fn check_parent_change(
+    table: &TableInfo,
+    old_row: &[Value],
+    new_row: Option<&[Value]>,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<()> {
+    if !catalog.foreign_keys_enabled() {
+        return Ok(());
+    }
+    for change in key_changes(table, old_row, new_row, catalog)? {
+        let action = if change.new_key.is_some() { change.fk.on_update } else { change.fk.on_delete };
+        if !matches!(action, FkAction::NoAction | FkAction::Restrict) {
+            continue;
+        }
+        let child = catalog.get_table(&change.child)?;
+        if has_matching_row(child, &change.fk.columns, &change.old_key, pool, catalog)? {
+            return Err(HorizonError::ConstraintViolation(format!(
+                "FOREIGN KEY constraint failed: {} is still referenced by {}",
+                table.name, change.child
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Apply the actions of foreign keys that reference `table` when the
+/// parent row `old_row` is deleted (`new_row` is `None`) or updated.
+/// RESTRICT and NO ACTION foreign keys are checked beforehand by
+/// [`check_parent_change`].
+pub(super) fn on_parent_change(
+    table: &TableInfo,
+    old_row: &[Value],
+    new_row: Option<&[Value]>,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+) -> Result<()> {
+    if !catalog.foreign_keys_enabled() {
+        return Ok(());
+    }
+    for KeyChange { child: child_name, fk, old_key, new_key } in key_changes(table, old_row, new_row, catalog)? {
+        let child = catalog.get_table(&child_name)?.clone();
+        let predicate = key_predicate(&fk.columns, &old_key);
+        let action = if new_key.is_some() { fk.on_update } else { fk.on_delete };
+        let assignments: Vec<(String, Expr)> = match action {
+            FkAction::NoAction | FkAction::Restrict => continue,
+            FkAction::Cascade => match new_key {
+                None => {
+                    let del = DeleteStatement {
+                        table: child_name,
+                        where_clause: Some(predicate),
+                        returning: None,
+                    };
+                    execute_delete(&del, pool, catalog, txn_mgr)?;
+                    continue;
+                }
+                Some(key) => fk.columns.iter().cloned().zip(key.iter().map(literal)).collect(),
+            },
+            FkAction::SetNull => fk
+                .columns
+                .iter()
+                .map(|c| (c.clone(), Expr::Literal(LiteralValue::Null)))
+                .collect(),
+            FkAction::SetDefault => fk
+                .columns
+                .iter()
+                .map(|c| {
+                    let default = child.find_column(c).and_then(|col| col.default_value.clone());
+                    (c.clone(), literal(&default.unwrap_or(Value::Null)))
+                })
+                .collect(),
+        };
+        let upd = UpdateStatement {
+            table: child_name,
+            assignments,
+            from: None,
+            where_clause: Some(predicate),
+            returning: None,
+        };
+        execute_update(&upd, pool, catalog, txn_mgr)?;
+    }
+    Ok(())
+}
+
+/// The parent columns a foreign key refers to: its explicit column list,
+/// or else the parent's primary key.
+fn parent_columns(fk: &ForeignKeyInfo, parent: &TableInfo) -> Result<Vec<String>> {
+    if !fk.ref_columns.is_empty() {
+        return Ok(fk.ref_columns.clone());
+    }
+    if !parent.pk_columns.is_empty() && parent.pk_columns.len() == fk.columns.len() {
+        return Ok(parent.pk_columns.iter().map(|&i| parent.columns[i].name.clone()).collect());
+    }
+    match parent.pk_column {
+        Some(idx) if fk.columns.len() == 1 => Ok(vec![parent.columns[idx].name.clone()]),
+        _ => Err(HorizonError::InvalidSql(format!(
+            "foreign key mismatch: {} has no primary key matching ({})",
+            parent.name,
+            fk.columns.join(", ")
+        ))),
+    }
+}
+
+/// The values of the named columns in `row`.
+fn column_values(table: &TableInfo, columns: &[String], row: &[Value]) -> Result<Vec<Value>> {
+    columns
+        .iter()
+        .map(|name| {
+            table
+                .find_column_index(name)
+                .map(|idx| row[idx].clone())
+                .ok_or_else(|| HorizonError::ColumnNotFound(format!("{}.{}", table.name, name)))
+        })
+        .collect()
+}
+
+fn literal(value: &Value) -> Expr {
+    Expr::Literal(value_to_literal(value))
+}
+
+/// Build `c1 = v1 AND c2 = v2 ...`.
+fn key_predicate(columns: &[String], values: &[Value]) -> Expr {
+    columns
+        .iter()
+        .zip(values)
+        .map(|(name, value)| Expr::BinaryOp {
+            left: Box::new(Expr::Column { table: None, name: name.clone() }),
+            op: BinaryOp::Eq,
+            right: Box::new(literal(value)),
+        })
+        .reduce(|acc, eq| Expr::BinaryOp {
+            left: Box::new(acc),
+            op: BinaryOp::And,
+            right: Box::new(eq),
+        })
+        .unwrap_or(Expr::Literal(LiteralValue::True))
+}
+
+/// Whether `table` holds a row whose `columns` equal `values`.
+fn has_matching_row(
+    table: &TableInfo,
+    columns: &[String],
+    values: &[Value],
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<bool> {
+    let predicate = key_predicate(columns, values);
+    let tree = BTree::open(table.root_page);
+    for entry in scan_with_index(Some(&predicate), None, &table.name, table, &tree, pool, catalog)? {
+        let row = deserialize_row(&entry.value, table.columns.len())?;
+        if eval_expr(&predicate, &row, &table.columns, table)?.to_bool() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -2,14 +2,21 @@
 //!
 //! Provides a simplified but functional FTS5 implementation:
 //! - Inverted index mapping terms to document positions
-//! - Simple whitespace + punctuation tokenizer
-//! - AND semantics for multi-term queries
+//! - Pluggable tokenizers ([`Fts5Tokenizer`]): the default `simple` one,
+//!   which splits on whitespace and punctuation, `porter`, which also
+//!   stems words with the Porter algorithm, and those registered with
+//!   [`Database::register_fts5_tokenizer`](crate::Database::register_fts5_tokenizer).
+//!   A table picks one with `USING fts5(content, tokenize = 'porter')`
+//! - Queries of terms, phrases (`"quick brown fox"`), and proximity groups
+//!   (`NEAR(quick fox, 5)`), all of which a document must match
 //! - BM25 relevance scoring
 //! - highlight() and snippet() auxiliary functions
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use crate::error::{HorizonError, Result};
 use crate::types::Value;
+use super::porter;
 
 /// Metadata for an FTS5 virtual table.
 #[derive(Debug, Clone)]
@@ -44,49 +51,149 @@ pub struct Fts5Index {
     pub doc_lengths: HashMap<i64, usize>,
     /// Total number of tokens across all documents.
     pub total_tokens: usize,
+    /// Splits documents and queries into terms.
+    pub tokenizer: SharedTokenizer,
 }
 
 impl Fts5Index {
-    /// Create a new, empty FTS5 index.
+    /// Create a new, empty FTS5 index using the `simple` tokenizer.
     pub fn new(info: Fts5TableInfo) -> Self {
+        Self::with_tokenizer(info, Arc::new(SimpleTokenizer))
+    }
+
+    /// Create a new, empty FTS5 index using `tokenizer`.
+    pub fn with_tokenizer(info: Fts5TableInfo, tokenizer: SharedTokenizer) -> Self {
         Fts5Index {
             info,
             documents: HashMap::new(),
             inverted: HashMap::new(),
             doc_lengths: HashMap::new(),
             total_tokens: 0,
+            tokenizer,
         }
     }
 
-    /// Insert a document into the index.
-    /// `col_values` should contain the text for each column in the FTS table.
-    /// Returns the assigned rowid.
-    pub fn insert(&mut self, col_values: Vec<String>) -> i64 {
-        let rowid = self.info.next_rowid;
-        self.info.next_rowid += 1;
+    /// The terms of `text`, as the index's tokenizer splits it.
+    fn terms(&self, text: &str) -> Vec<String> {
+        self.tokenizer.tokenize(text).into_iter().map(|t| t.text).collect()
+    }
 
-        let mut total_doc_tokens = 0;
+    /// Every term of `query`, in order.
+    fn query_terms(&self, query: &str) -> Vec<String> {
+        self.parse_query(query).iter().flat_map(|part| part.terms().to_vec()).collect()
+    }
 
-        for (col_idx, text) in col_values.iter().enumerate() {
-            let tokens = tokenize(text);
-            for (position, token) in tokens.iter().enumerate() {
-                let posting = Posting {
-                    rowid,
-                    col_idx,
-                    position,
-                };
-                self.inverted
-                    .entry(token.clone())
-                    .or_default()
-                    .push(posting);
+    /// Parse a full-text query: a sequence of `"phrases"`, `NEAR(terms,
+    /// distance)` groups, and bare text, which is a phrase of one term for
+    /// each of its terms.
+    fn parse_query(&self, query: &str) -> Vec<QueryPart> {
+        let mut parts = Vec::new();
+        let bare = |text: &str, parts: &mut Vec<QueryPart>| {
+            parts.extend(self.terms(text).into_iter().map(|term| QueryPart::Phrase(vec![term])));
+        };
+        let mut rest = query;
+        loop {
+            let quote = rest.find('"');
+            let near = rest.find("NEAR(").filter(|&i| {
+                // NEAR must start a word
+                !rest[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+            });
+            match (quote, near) {
+                (Some(q), n) if n.map_or(true, |n| q < n) => {
+                    bare(&rest[..q], &mut parts);
+                    let inner = &rest[q + 1..];
+                    let end = inner.find('"').unwrap_or(inner.len());
+                    let terms = self.terms(&inner[..end]);
+                    if !terms.is_empty() {
+                        parts.push(QueryPart::Phrase(terms));
+                    }
+                    rest = inner.get(end + 1..).unwrap_or("");
+                }
+                (_, Some(n)) => {
+                    bare(&rest[..n], &mut parts);
+                    let inner = &rest[n + "NEAR(".len()..];
+                    let end = inner.find(')').unwrap_or(inner.len());
+                    let group = &inner[..end];
+                    let (terms, distance) = match group.rsplit_once(',') {
+                        Some((terms, distance)) => match distance.trim().parse() {
+                            Ok(distance) => (terms, distance),
+                            Err(_) => (group, DEFAULT_NEAR_DISTANCE),
+                        },
+                        None => (group, DEFAULT_NEAR_DISTANCE),
+                    };
+                    let terms = self.terms(terms);
+                    if !terms.is_empty() {
+                        parts.push(QueryPart::Near { terms, distance });
+                    }
+                    rest = inner.get(end + 1..).unwrap_or("");
+                }
+                _ => {
+                    bare(rest, &mut parts);
+                    return parts;
+                }
             }
-            total_doc_tokens += tokens.len();
         }
+    }
 
-        self.doc_lengths.insert(rowid, total_doc_tokens);
-        self.total_tokens += total_doc_tokens;
-        self.documents.insert(rowid, col_values);
+    /// The positions of `term` in each column of each document, keyed by
+    /// `(rowid, column)`, in increasing order; only in the column `column`
+    /// if it is given.
+    fn occurrences(&self, term: &str, column: Option<usize>) -> HashMap<(i64, usize), Vec<usize>> {
+        let mut occurrences: HashMap<(i64, usize), Vec<usize>> = HashMap::new();
+        for posting in self.inverted.get(term).into_iter().flatten() {
+            if column.map_or(true, |c| posting.col_idx == c) {
+                occurrences.entry((posting.rowid, posting.col_idx)).or_default().push(posting.position);
+            }
+        }
+        for positions in occurrences.values_mut() {
+            positions.sort_unstable();
+        }
+        occurrences
+    }
+
+    /// The documents matching `part` in the column `column`, or in any
+    /// column if `None`, in rowid order.
+    fn part_rowids(&self, part: &QueryPart, column: Option<usize>) -> Vec<i64> {
+        let occurrences: Vec<_> = part.terms().iter().map(|term| self.occurrences(term, column)).collect();
+        let mut rowids: Vec<i64> = occurrences[0]
+            .keys()
+            .filter(|key| {
+                let positions: Option<Vec<&Vec<usize>>> = occurrences.iter().map(|o| o.get(key)).collect();
+                positions.is_some_and(|positions| part.matches(&positions))
+            })
+            .map(|&(rowid, _)| rowid)
+            .collect();
+        rowids.sort_unstable();
+        rowids.dedup();
+        rowids
+    }
+
+    /// Search for documents containing the terms of `phrase` one after
+    /// another, in the column `column` or any column.
+    pub fn search_phrase(&self, phrase: &str, column: Option<usize>) -> Vec<i64> {
+        match self.terms(phrase) {
+            terms if terms.is_empty() => vec![],
+            terms => self.part_rowids(&QueryPart::Phrase(terms), column),
+        }
+    }
 
+    /// Search for documents containing each of `terms` within `distance`
+    /// positions of each other in one column (the column `column`, if it
+    /// is given).
+    pub fn search_near(&self, terms: &[&str], distance: usize, column: Option<usize>) -> Vec<i64> {
+        let terms: Vec<String> = terms.iter().flat_map(|term| self.terms(term)).collect();
+        if terms.is_empty() {
+            return vec![];
+        }
+        self.part_rowids(&QueryPart::Near { terms, distance }, column)
+    }
+
+    /// Insert a document into the index.
+    /// `col_values` should contain the text for each column in the FTS table.
+    /// Returns the assigned rowid.
+    pub fn insert(&mut self, col_values: Vec<String>) -> i64 {
+        let rowid = self.info.next_rowid;
+        self.insert_with_rowid(rowid, col_values);
         rowid
     }
 
@@ -99,19 +206,19 @@ impl Fts5Index {
         let mut total_doc_tokens = 0;
 
         for (col_idx, text) in col_values.iter().enumerate() {
-            let tokens = tokenize(text);
-            for (position, token) in tokens.iter().enumerate() {
+            let tokens = self.tokenizer.tokenize(text);
+            total_doc_tokens += tokens.len();
+            for token in tokens {
                 let posting = Posting {
                     rowid,
                     col_idx,
-                    position,
+                    position: token.position,
                 };
                 self.inverted
-                    .entry(token.clone())
+                    .entry(token.text)
                     .or_default()
                     .push(posting);
             }
-            total_doc_tokens += tokens.len();
         }
 
         self.doc_lengths.insert(rowid, total_doc_tokens);
@@ -144,29 +251,29 @@ impl Fts5Index {
     /// Search for documents matching all terms (AND semantics).
     /// Returns a list of matching rowids.
     pub fn search(&self, query: &str) -> Vec<i64> {
-        let terms = tokenize(query);
-        if terms.is_empty() {
+        self.search_in(query, None)
+    }
+
+    /// Search for documents whose column `col_idx` matches all terms.
+    pub fn search_column(&self, col_idx: usize, query: &str) -> Vec<i64> {
+        self.search_in(query, Some(col_idx))
+    }
+
+    /// Search the column `column`, or every column if `None`.
+    fn search_in(&self, query: &str, column: Option<usize>) -> Vec<i64> {
+        let parts = self.parse_query(query);
+        let Some((first, rest)) = parts.split_first() else {
             return vec![];
-        }
+        };
 
-        // For each term, get the set of matching rowids.
-        let mut term_rowid_sets: Vec<Vec<i64>> = Vec::new();
-        for term in &terms {
-            if let Some(postings) = self.inverted.get(term) {
-                let mut rowids: Vec<i64> = postings.iter().map(|p| p.rowid).collect();
-                rowids.sort();
-                rowids.dedup();
-                term_rowid_sets.push(rowids);
-            } else {
-                // Term not found -> no results (AND semantics)
-                return vec![];
+        // Intersect the documents matching each part (AND semantics)
+        let mut result = self.part_rowids(first, column);
+        for part in rest {
+            if result.is_empty() {
+                break;
             }
-        }
-
-        // Intersect all sets
-        let mut result = term_rowid_sets[0].clone();
-        for set in &term_rowid_sets[1..] {
-            result.retain(|rid| set.contains(rid));
+            let matching = self.part_rowids(part, column);
+            result.retain(|rid| matching.binary_search(rid).is_ok());
         }
 
         result
@@ -175,7 +282,7 @@ impl Fts5Index {
     /// Compute BM25 score for a document given a query.
     /// Uses standard BM25 parameters: k1=1.2, b=0.75.
     pub fn bm25(&self, rowid: i64, query: &str) -> f64 {
-        let terms = tokenize(query);
+        let terms = self.query_terms(query);
         if terms.is_empty() || self.documents.is_empty() {
             return 0.0;
         }
@@ -226,7 +333,7 @@ impl Fts5Index {
         after_tag: &str,
         query: &str,
     ) -> String {
-        let query_terms = tokenize(query);
+        let query_terms = self.query_terms(query);
         let doc = match self.documents.get(&rowid) {
             Some(d) => d,
             None => return String::new(),
@@ -236,11 +343,13 @@ impl Fts5Index {
             None => return String::new(),
         };
 
-        highlight_text(text, &query_terms, before_tag, after_tag)
+        let tokens = self.tokenizer.tokenize(text);
+        highlight_text(text, &tokens, 0..text.len(), &query_terms, before_tag, after_tag)
     }
 
     /// Generate a snippet with highlighted terms.
     /// Returns a fragment of the text around matching terms.
+    #[allow(clippy::too_many_arguments)]
     pub fn snippet(
         &self,
         rowid: i64,
@@ -251,7 +360,7 @@ impl Fts5Index {
         max_tokens: usize,
         query: &str,
     ) -> String {
-        let query_terms = tokenize(query);
+        let query_terms = self.query_terms(query);
         let doc = match self.documents.get(&rowid) {
             Some(d) => d,
             None => return String::new(),
@@ -261,7 +370,8 @@ impl Fts5Index {
             None => return String::new(),
         };
 
-        snippet_text(text, &query_terms, before_tag, after_tag, ellipsis, max_tokens)
+        let tokens = self.tokenizer.tokenize(text);
+        snippet_text(text, &tokens, &query_terms, before_tag, after_tag, ellipsis, max_tokens)
     }
 
     /// Get the column values for a document.
@@ -274,7 +384,17 @@ impl Fts5Index {
         &self,
         query: &str,
     ) -> Vec<(i64, Vec<Value>, f64)> {
-        let rowids = self.search(query);
+        self.rank_rows(self.search(query), query)
+    }
+
+    /// Like [`query_rows`](Self::query_rows), but only the column `col_idx`
+    /// has to match the query.
+    pub fn query_column_rows(&self, col_idx: usize, query: &str) -> Vec<(i64, Vec<Value>, f64)> {
+        self.rank_rows(self.search_column(col_idx, query), query)
+    }
+
+    /// The documents `rowids` with their BM25 scores for `query`, best first.
+    fn rank_rows(&self, rowids: Vec<i64>, query: &str) -> Vec<(i64, Vec<Value>, f64)> {
         let mut results: Vec<(i64, Vec<Value>, f64)> = Vec::new();
 
         for rowid in rowids {
@@ -293,109 +413,235 @@ impl Fts5Index {
 }
 
 // ---------------------------------------------------------------------------
-// Tokenizer
+// Queries
 // ---------------------------------------------------------------------------
 
-/// Tokenize text into lowercase terms.
-/// Splits on whitespace and punctuation, strips common punctuation.
-pub fn tokenize(text: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut current = String::new();
+/// The distance of a `NEAR` group that does not give one.
+pub const DEFAULT_NEAR_DISTANCE: usize = 10;
+
+/// A part of a full-text query; a document matches a query if it matches
+/// every part.
+#[derive(Debug, Clone, PartialEq)]
+enum QueryPart {
+    /// Terms that must occur one after another in a column: `"quick brown
+    /// fox"`. A bare term is a phrase of one term.
+    Phrase(Vec<String>),
+    /// Terms that must all occur within `distance` positions of each other
+    /// in a column: `NEAR(quick fox, 5)`.
+    Near { terms: Vec<String>, distance: usize },
+}
 
-    for ch in text.chars() {
-        if ch.is_alphanumeric() || ch == '_' || ch == '\'' {
-            // Keep apostrophes within words (e.g., "don't")
-            // but strip them from the beginning/end during finalization
-            current.push(ch);
-        } else {
-            if !current.is_empty() {
-                let token = normalize_token(&current);
-                if !token.is_empty() {
-                    tokens.push(token);
-                }
-                current.clear();
-            }
+impl QueryPart {
+    fn terms(&self) -> &[String] {
+        match self {
+            QueryPart::Phrase(terms) | QueryPart::Near { terms, .. } => terms,
         }
     }
 
-    // Don't forget the last token
-    if !current.is_empty() {
-        let token = normalize_token(&current);
-        if !token.is_empty() {
-            tokens.push(token);
+    /// Whether a column in which the terms occur at `positions` (one list
+    /// per term, in increasing order) matches.
+    fn matches(&self, positions: &[&Vec<usize>]) -> bool {
+        match self {
+            QueryPart::Phrase(_) => phrase_at(positions),
+            QueryPart::Near { distance, .. } => within(positions, *distance),
         }
     }
+}
 
-    tokens
+/// Whether the terms whose positions are `positions` occur one after
+/// another, the first at one of its positions.
+fn phrase_at(positions: &[&Vec<usize>]) -> bool {
+    positions[0].iter().any(|&start| {
+        positions[1..]
+            .iter()
+            .enumerate()
+            .all(|(i, p)| p.binary_search(&(start + i + 1)).is_ok())
+    })
 }
 
-/// Normalize a token: lowercase and strip leading/trailing punctuation.
-fn normalize_token(token: &str) -> String {
-    let trimmed = token.trim_matches(|c: char| !c.is_alphanumeric());
-    trimmed.to_lowercase()
+/// Whether an occurrence of each of the terms whose positions are
+/// `positions` can be picked so that they are all within `distance` of
+/// each other.
+fn within(positions: &[&Vec<usize>], distance: usize) -> bool {
+    // Slide a window over all occurrences in order, looking for one that
+    // holds each term and spans at most `distance` positions
+    let mut occurrences: Vec<(usize, usize)> = positions
+        .iter()
+        .enumerate()
+        .flat_map(|(term, p)| p.iter().map(move |&position| (position, term)))
+        .collect();
+    occurrences.sort_unstable();
+    let mut counts = vec![0usize; positions.len()];
+    let mut covered = 0;
+    let mut start = 0;
+    for &(position, term) in &occurrences {
+        if counts[term] == 0 {
+            covered += 1;
+        }
+        counts[term] += 1;
+        while covered == positions.len() {
+            let (first, first_term) = occurrences[start];
+            if position - first <= distance {
+                return true;
+            }
+            counts[first_term] -= 1;
+            if counts[first_term] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+    false
 }
 
 // ---------------------------------------------------------------------------
-// Highlight and Snippet helpers
+// Tokenizer
 // ---------------------------------------------------------------------------
 
-/// Highlight matching terms in text.
-fn highlight_text(text: &str, query_terms: &[String], before: &str, after: &str) -> String {
-    let words: Vec<&str> = split_preserving_whitespace(text);
-    let mut result = String::new();
+/// A term of a document or query, as a tokenizer produces it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// The term indexed or searched for.
+    pub text: String,
+    /// The byte offset in the text where the term starts.
+    pub start: usize,
+    /// The byte offset in the text just after the term.
+    pub end: usize,
+    /// The position of the term among the terms of the text, counting
+    /// from 0.
+    pub position: usize,
+}
 
-    for part in &words {
-        let normalized = normalize_token(part);
-        if !normalized.is_empty() && query_terms.contains(&normalized) {
-            // Reconstruct with highlighting
-            result.push_str(before);
-            result.push_str(part);
-            result.push_str(after);
-        } else {
-            result.push_str(part);
+/// Splits the text of documents and queries into terms. A query matches
+/// a document if the terms of the query are among the terms of the
+/// document, so a tokenizer that folds case, say, makes searches
+/// case-insensitive.
+pub trait Fts5Tokenizer {
+    /// The terms of `text`, in order.
+    fn tokenize(&self, text: &str) -> Vec<Token>;
+}
+
+/// A tokenizer shared by the FTS5 tables using it.
+pub type SharedTokenizer = Arc<dyn Fts5Tokenizer + Send + Sync>;
+
+/// The `simple` tokenizer, the default: a term is a run of letters,
+/// digits, underscores, and apostrophes, without the apostrophes and
+/// underscores at its ends, in lowercase.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleTokenizer;
+
+impl Fts5Tokenizer for SimpleTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut word_start = None;
+        // A space after the text ends its last word
+        for (i, ch) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            // Keep apostrophes within words (e.g., "don't")
+            let in_word = ch.is_alphanumeric() || ch == '_' || ch == '\'';
+            match (word_start, in_word) {
+                (None, true) => word_start = Some(i),
+                (Some(start), false) => {
+                    push_word(&mut tokens, text, start, i);
+                    word_start = None;
+                }
+                _ => {}
+            }
         }
+        tokens
     }
+}
 
-    result
+/// Add the word `text[start..end]`, less the punctuation at its ends, to
+/// `tokens` unless nothing is left of it.
+fn push_word(tokens: &mut Vec<Token>, text: &str, start: usize, end: usize) {
+    let word = &text[start..end];
+    let not_alphanumeric = |c: char| !c.is_alphanumeric();
+    let leading = word.len() - word.trim_start_matches(not_alphanumeric).len();
+    let trimmed = word.trim_matches(not_alphanumeric);
+    if trimmed.is_empty() {
+        return;
+    }
+    tokens.push(Token {
+        text: trimmed.to_lowercase(),
+        start: start + leading,
+        end: start + leading + trimmed.len(),
+        position: tokens.len(),
+    });
 }
 
-/// Split text into words while preserving whitespace as separate items.
-fn split_preserving_whitespace(text: &str) -> Vec<&str> {
-    let mut parts: Vec<&str> = Vec::new();
-    let mut start = 0;
-    let mut in_word = false;
-    let bytes = text.as_bytes();
-
-    for (i, &b) in bytes.iter().enumerate() {
-        let is_space = b == b' ' || b == b'\t' || b == b'\n' || b == b'\r';
-        if in_word && is_space {
-            parts.push(&text[start..i]);
-            start = i;
-            in_word = false;
-        } else if !in_word && !is_space {
-            if i > start {
-                parts.push(&text[start..i]);
-            }
-            start = i;
-            in_word = true;
+/// The `porter` tokenizer: the terms of the `simple` tokenizer, stemmed
+/// with the Porter algorithm, so that `connected` and `connections` both
+/// match `connect`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PorterTokenizer;
+
+impl Fts5Tokenizer for PorterTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = SimpleTokenizer.tokenize(text);
+        for token in &mut tokens {
+            token.text = porter::stem(&token.text);
         }
+        tokens
     }
-    if start < text.len() {
-        parts.push(&text[start..]);
+}
+
+/// The built-in tokenizer `name` (`simple` or `porter`, ignoring case).
+pub fn builtin_tokenizer(name: &str) -> Option<SharedTokenizer> {
+    match name.to_lowercase().as_str() {
+        "simple" => Some(Arc::new(SimpleTokenizer)),
+        "porter" => Some(Arc::new(PorterTokenizer)),
+        _ => None,
     }
-    parts
 }
 
-/// Generate a snippet of text around matching terms.
+/// Tokenize text into lowercase terms with the `simple` tokenizer.
+/// Splits on whitespace and punctuation, strips common punctuation.
+pub fn tokenize(text: &str) -> Vec<String> {
+    SimpleTokenizer.tokenize(text).into_iter().map(|t| t.text).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Highlight and Snippet helpers
+// ---------------------------------------------------------------------------
+
+/// The part `range` of `text` with the `tokens` in it that are among
+/// `query_terms` wrapped in `before` and `after`.
+fn highlight_text(
+    text: &str,
+    tokens: &[Token],
+    range: std::ops::Range<usize>,
+    query_terms: &[String],
+    before: &str,
+    after: &str,
+) -> String {
+    let mut result = String::new();
+    let mut copied = range.start;
+    for token in tokens {
+        if token.start < range.start || token.end > range.end || !query_terms.contains(&token.text) {
+            continue;
+        }
+        result.push_str(&text[copied..token.start]);
+        result.push_str(before);
+        result.push_str(&text[token.start..token.end]);
+        result.push_str(after);
+        copied = token.end;
+    }
+    result.push_str(&text[copied..range.end]);
+    result
+}
+
+/// Generate a snippet of at most `max_tokens` tokens (all of them if 0)
+/// of `text` around the first matching term, with `ellipsis` marking the
+/// text left out.
 fn snippet_text(
     text: &str,
+    tokens: &[Token],
     query_terms: &[String],
     before: &str,
     after: &str,
     ellipsis: &str,
     max_tokens: usize,
 ) -> String {
-    let tokens = tokenize(text);
     if tokens.is_empty() {
         return String::new();
     }
@@ -403,44 +649,29 @@ fn snippet_text(
     // Find the first matching token position
     let first_match = tokens
         .iter()
-        .position(|t| query_terms.contains(t))
+        .position(|t| query_terms.contains(&t.text))
         .unwrap_or(0);
 
     // Determine the window around the first match
     let max_tokens = if max_tokens == 0 { tokens.len() } else { max_tokens };
     let half = max_tokens / 2;
-    let start = if first_match > half {
-        first_match - half
-    } else {
-        0
-    };
+    let start = first_match.saturating_sub(half);
     let end = (start + max_tokens).min(tokens.len());
 
-    // Rebuild the snippet from the original text using word boundaries
-    // We'll use a simpler approach: split the original text into words and rebuild
-    let words: Vec<&str> = text.split_whitespace().collect();
-    let w_start = start.min(words.len());
-    let w_end = end.min(words.len());
+    // The window reaches the ends of the text if it holds the first or
+    // last token
+    let from = if start == 0 { 0 } else { tokens[start].start };
+    let to = if end == tokens.len() { text.len() } else { tokens[end - 1].end };
 
-    let mut parts: Vec<String> = Vec::new();
-    if w_start > 0 {
-        parts.push(ellipsis.to_string());
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str(ellipsis);
     }
-
-    for word in &words[w_start..w_end] {
-        let normalized = normalize_token(word);
-        if !normalized.is_empty() && query_terms.contains(&normalized) {
-            parts.push(format!("{}{}{}", before, word, after));
-        } else {
-            parts.push(word.to_string());
-        }
-    }
-
-    if w_end < words.len() {
-        parts.push(ellipsis.to_string());
+    snippet.push_str(&highlight_text(text, tokens, from..to, query_terms, before, after));
+    if end < tokens.len() {
+        snippet.push_str(ellipsis);
     }
-
-    parts.join(" ")
+    snippet
 }
 
 // ---------------------------------------------------------------------------
@@ -451,12 +682,21 @@ use std::sync::Mutex;
 
 // We use a simple module-level HashMap guarded by a Mutex for the FTS5 indexes.
 // In a production system, this would be integrated into the Database struct.
-static FTS5_INDEXES: std::sync::LazyLock<Mutex<HashMap<String, Fts5Index>>> =
-    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+static FTS5_INDEXES: std::sync::OnceLock<Mutex<HashMap<String, Fts5Index>>> =
+    std::sync::OnceLock::new();
+
+fn fts5_indexes() -> &'static Mutex<HashMap<String, Fts5Index>> {
+    FTS5_INDEXES.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-/// Register a new FTS5 virtual table.
+/// Register a new FTS5 virtual table using the `simple` tokenizer.
 pub fn create_fts5_table(name: &str, columns: Vec<String>) -> Result<()> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    create_fts5_table_with_tokenizer(name, columns, Arc::new(SimpleTokenizer))
+}
+
+/// Register a new FTS5 virtual table using `tokenizer`.
+pub fn create_fts5_table_with_tokenizer(name: &str, columns: Vec<String>, tokenizer: SharedTokenizer) -> Result<()> {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
 
@@ -469,18 +709,18 @@ pub fn create_fts5_table(name: &str, columns: Vec<String>) -> Result<()> {
         columns,
         next_rowid: 1,
     };
-    indexes.insert(name.to_string(), Fts5Index::new(info));
+    indexes.insert(name.to_string(), Fts5Index::with_tokenizer(info, tokenizer));
     Ok(())
 }
 
 /// Check if an FTS5 table exists.
 pub fn fts5_table_exists(name: &str) -> bool {
-    FTS5_INDEXES.lock().map(|i| i.contains_key(name)).unwrap_or(false)
+    fts5_indexes().lock().map(|i| i.contains_key(name)).unwrap_or(false)
 }
 
 /// Get the column names for an FTS5 table.
 pub fn fts5_get_columns(name: &str) -> Result<Vec<String>> {
-    let indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     indexes
@@ -491,7 +731,7 @@ pub fn fts5_get_columns(name: &str) -> Result<Vec<String>> {
 
 /// Insert a row into an FTS5 table.
 pub fn fts5_insert(name: &str, col_values: Vec<String>) -> Result<i64> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get_mut(name).ok_or_else(|| {
@@ -502,7 +742,7 @@ pub fn fts5_insert(name: &str, col_values: Vec<String>) -> Result<i64> {
 
 /// Insert a row into an FTS5 table with a specific rowid.
 pub fn fts5_insert_with_rowid(name: &str, rowid: i64, col_values: Vec<String>) -> Result<()> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get_mut(name).ok_or_else(|| {
@@ -514,7 +754,7 @@ pub fn fts5_insert_with_rowid(name: &str, rowid: i64, col_values: Vec<String>) -
 
 /// Delete a row from an FTS5 table.
 pub fn fts5_delete(name: &str, rowid: i64) -> Result<bool> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get_mut(name).ok_or_else(|| {
@@ -525,7 +765,7 @@ pub fn fts5_delete(name: &str, rowid: i64) -> Result<bool> {
 
 /// Query an FTS5 table. Returns (rowid, column_values, bm25_score) tuples.
 pub fn fts5_query(name: &str, query: &str) -> Result<Vec<(i64, Vec<Value>, f64)>> {
-    let indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get(name).ok_or_else(|| {
@@ -534,9 +774,46 @@ pub fn fts5_query(name: &str, query: &str) -> Result<Vec<(i64, Vec<Value>, f64)>
     Ok(index.query_rows(query))
 }
 
+/// Query the column `col_idx` of an FTS5 table, as [`fts5_query`] does
+/// for every column.
+pub fn fts5_query_column(name: &str, col_idx: usize, query: &str) -> Result<Vec<(i64, Vec<Value>, f64)>> {
+    let indexes = fts5_indexes().lock().map_err(|_| {
+        HorizonError::Internal("FTS5 mutex poisoned".into())
+    })?;
+    let index = indexes.get(name).ok_or_else(|| {
+        HorizonError::TableNotFound(name.to_string())
+    })?;
+    Ok(index.query_column_rows(col_idx, query))
+}
+
+/// Query an FTS5 table for the rows containing the terms of `phrase` one
+/// after another, as the query `"phrase"` does.
+pub fn fts5_query_phrase(name: &str, phrase: &str) -> Result<Vec<(i64, Vec<Value>, f64)>> {
+    let indexes = fts5_indexes().lock().map_err(|_| {
+        HorizonError::Internal("FTS5 mutex poisoned".into())
+    })?;
+    let index = indexes.get(name).ok_or_else(|| {
+        HorizonError::TableNotFound(name.to_string())
+    })?;
+    Ok(index.rank_rows(index.search_phrase(phrase, None), phrase))
+}
+
+/// Query an FTS5 table for the rows containing each of `terms` within
+/// `distance` positions of each other, as the query `NEAR(terms, distance)`
+/// does.
+pub fn fts5_query_near(name: &str, terms: &[&str], distance: usize) -> Result<Vec<(i64, Vec<Value>, f64)>> {
+    let indexes = fts5_indexes().lock().map_err(|_| {
+        HorizonError::Internal("FTS5 mutex poisoned".into())
+    })?;
+    let index = indexes.get(name).ok_or_else(|| {
+        HorizonError::TableNotFound(name.to_string())
+    })?;
+    Ok(index.rank_rows(index.search_near(terms, distance, None), &terms.join(" ")))
+}
+
 /// Get all rows from an FTS5 table (full scan).
 pub fn fts5_scan_all(name: &str) -> Result<Vec<(i64, Vec<Value>)>> {
-    let indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get(name).ok_or_else(|| {
@@ -556,7 +833,7 @@ pub fn fts5_scan_all(name: &str) -> Result<Vec<(i64, Vec<Value>)>> {
 
 /// Delete rows from an FTS5 table matching a MATCH query.
 pub fn fts5_delete_matching(name: &str, query: &str) -> Result<usize> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get_mut(name).ok_or_else(|| {
@@ -572,7 +849,7 @@ pub fn fts5_delete_matching(name: &str, query: &str) -> Result<usize> {
 
 /// Delete all rows from an FTS5 table (DELETE without WHERE).
 pub fn fts5_delete_all(name: &str) -> Result<usize> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get_mut(name).ok_or_else(|| {
@@ -595,7 +872,7 @@ pub fn fts5_highlight(
     after_tag: &str,
     query: &str,
 ) -> Result<String> {
-    let indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get(name).ok_or_else(|| {
@@ -605,6 +882,7 @@ pub fn fts5_highlight(
 }
 
 /// Compute the snippet() function for a given FTS5 table.
+#[allow(clippy::too_many_arguments)]
 pub fn fts5_snippet(
     name: &str,
     rowid: i64,
@@ -615,7 +893,7 @@ pub fn fts5_snippet(
     max_tokens: usize,
     query: &str,
 ) -> Result<String> {
-    let indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get(name).ok_or_else(|| {
@@ -626,7 +904,7 @@ pub fn fts5_snippet(
 
 /// Compute the bm25() function for a given FTS5 table.
 pub fn fts5_bm25(name: &str, rowid: i64, query: &str) -> Result<f64> {
-    let indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     let index = indexes.get(name).ok_or_else(|| {
@@ -637,7 +915,7 @@ pub fn fts5_bm25(name: &str, rowid: i64, query: &str) -> Result<f64> {
 
 /// Drop an FTS5 table.
 pub fn fts5_drop_table(name: &str) -> Result<()> {
-    let mut indexes = FTS5_INDEXES.lock().map_err(|_| {
+    let mut indexes = fts5_indexes().lock().map_err(|_| {
         HorizonError::Internal("FTS5 mutex poisoned".into())
     })?;
     indexes.remove(name).ok_or_else(|| {
@@ -677,6 +955,55 @@ mod tests {
         assert_eq!(tokens, vec!["don't", "stop", "hello"]);
     }
 
+    #[test]
+    fn test_simple_tokenizer_offsets() {
+        let text = "Don't  stop, 'Hello'!";
+        let tokens = SimpleTokenizer.tokenize(text);
+        let terms: Vec<(&str, &str, usize)> =
+            tokens.iter().map(|t| (t.text.as_str(), &text[t.start..t.end], t.position)).collect();
+        assert_eq!(terms, vec![("don't", "Don't", 0), ("stop", "stop", 1), ("hello", "Hello", 2)]);
+    }
+
+    #[test]
+    fn test_porter_tokenizer_matches_inflected_forms() {
+        let info = Fts5TableInfo {
+            name: "test".into(),
+            columns: vec!["content".into()],
+            next_rowid: 1,
+        };
+        let mut idx = Fts5Index::with_tokenizer(info, Arc::new(PorterTokenizer));
+        let rid1 = idx.insert(vec!["The runners kept running".into()]);
+        let rid2 = idx.insert(vec!["She runs daily".into()]);
+        idx.insert(vec!["A quiet walk".into()]);
+
+        assert_eq!(idx.search("run"), vec![rid1, rid2]);
+        assert_eq!(idx.search("RUNNING"), vec![rid1, rid2]);
+        assert_eq!(idx.highlight(rid1, 0, "[", "]", "run"), "The runners kept [running]");
+    }
+
+    #[test]
+    fn test_phrase_and_near_queries() {
+        let info = Fts5TableInfo {
+            name: "test".into(),
+            columns: vec!["content".into()],
+            next_rowid: 1,
+        };
+        let mut idx = Fts5Index::new(info);
+        let rid1 = idx.insert(vec!["the quick brown fox jumps".into()]);
+        let rid2 = idx.insert(vec!["the brown quick fox".into()]);
+        let rid3 = idx.insert(vec!["quick thinking saves the brown dog and the fox".into()]);
+
+        assert_eq!(idx.search("\"quick brown fox\""), vec![rid1]);
+        assert_eq!(idx.search("\"brown quick\" fox"), vec![rid2]);
+        assert_eq!(idx.search_phrase("Quick, brown!", None), vec![rid1]);
+        assert_eq!(idx.search("NEAR(quick fox, 2)"), vec![rid1, rid2]);
+        assert_eq!(idx.search("NEAR(fox quick, 8)"), vec![rid1, rid2, rid3]);
+        assert_eq!(idx.search_near(&["quick", "brown", "fox"], 2, None), vec![rid1, rid2]);
+        // The default distance is 10
+        assert_eq!(idx.search("NEAR(quick fox) jumps"), vec![rid1]);
+        assert_eq!(idx.query_terms("\"quick brown\" NEAR(fox dog, 3) jumps"), vec!["quick", "brown", "fox", "dog", "jumps"]);
+    }
+
     #[test]
     fn test_fts5_index_insert_and_search() {
         let info = Fts5TableInfo {
@@ -716,6 +1043,23 @@ mod tests {
         assert_eq!(idx.search("world").len(), 1);
     }
 
+    #[test]
+    fn test_fts5_index_search_column() {
+        let info = Fts5TableInfo {
+            name: "test".into(),
+            columns: vec!["title".into(), "body".into()],
+            next_rowid: 1,
+        };
+        let mut idx = Fts5Index::new(info);
+        let rid1 = idx.insert(vec!["learning rust".into(), "a systems language".into()]);
+        let rid2 = idx.insert(vec!["learning go".into(), "rust is mentioned here".into()]);
+
+        assert_eq!(idx.search("rust"), vec![rid1, rid2]);
+        assert_eq!(idx.search_column(0, "rust"), vec![rid1]);
+        assert_eq!(idx.search_column(1, "rust"), vec![rid2]);
+        assert!(idx.search_column(0, "learning systems").is_empty());
+    }
+
     #[test]
     fn test_bm25_scoring() {
         let info = Fts5TableInfo {
@@ -0,0 +1,141 @@
+//! Grouping sets of a GROUP BY clause.
+//!
+//! `GROUP BY ROLLUP(a, b, c)` groups the rows by `(a, b, c)`, then by
+//! `(a, b)`, then by `(a)`, and finally into a single group, returning the
+//! rows of each grouping in turn. In the rows of a shorter grouping, the
+//! GROUP BY expressions it leaves out are NULL. `GROUPING(a)` tells such a
+//! NULL apart from a NULL in the data: it is 1 in the rows of a grouping
+//! that leaves `a` out, and 0 otherwise.
+//!
+//! [`grouping_sets`] lists the groupings of a clause, and [`rolled_up`]
+//! rewrites a select column or HAVING clause for one of them.
+
+use crate::sql::ast::*;
+use super::expr_has_aggregate_fn;
+
+/// The groupings of `group_by` under `modifier`, each as the number of
+/// leading GROUP BY expressions it groups by, from the longest down.
+pub(super) fn grouping_sets(group_by: &[Expr], modifier: Option<GroupByModifier>) -> Vec<usize> {
+    match modifier {
+        None => vec![group_by.len()],
+        Some(GroupByModifier::Rollup) => (0..=group_by.len()).rev().collect(),
+    }
+}
+
+/// `expr` as evaluated for the grouping by the first `grouped` expressions
+/// of `group_by`: the expressions it leaves out become NULL, except inside
+/// aggregate calls, and each `GROUPING(...)` call becomes its value.
+///
+/// With several arguments, `GROUPING` returns a bit mask with one bit per
+/// argument, the first argument's bit the most significant.
+pub(super) fn rolled_up(expr: &Expr, group_by: &[Expr], grouped: usize) -> Expr {
+    let (kept, left_out) = group_by.split_at(grouped);
+    let matches = |exprs: &[Expr], e: &Expr| exprs.iter().any(|g| same_expr(g, e));
+    let boxed = |e: &Expr| Box::new(rolled_up(e, group_by, grouped));
+    match expr {
+        Expr::Function { name, args, .. } if name.eq_ignore_ascii_case("GROUPING") => {
+            let mask = args
+                .iter()
+                .fold(0, |mask, arg| mask << 1 | i64::from(!matches(kept, arg) && matches(left_out, arg)));
+            Expr::Literal(LiteralValue::Integer(mask))
+        }
+        _ if !matches(kept, expr) && matches(left_out, expr) => Expr::Literal(LiteralValue::Null),
+        Expr::Function { name, args, distinct, filter } => {
+            let scalar_min_max = args.len() >= 2 && (name.eq_ignore_ascii_case("MIN") || name.eq_ignore_ascii_case("MAX"));
+            if expr_has_aggregate_fn(expr) && !scalar_min_max {
+                return expr.clone();
+            }
+            Expr::Function {
+                name: name.clone(),
+                args: args.iter().map(|a| rolled_up(a, group_by, grouped)).collect(),
+                distinct: *distinct,
+                filter: filter.clone(),
+            }
+        }
+        Expr::BinaryOp { left, op, right } => Expr::BinaryOp { left: boxed(left), op: op.clone(), right: boxed(right) },
+        Expr::UnaryOp { op, expr } => Expr::UnaryOp { op: op.clone(), expr: boxed(expr) },
+        Expr::IsNull { expr, negated } => Expr::IsNull { expr: boxed(expr), negated: *negated },
+        Expr::Cast { expr, type_name } => Expr::Cast { expr: boxed(expr), type_name: type_name.clone() },
+        Expr::Between { expr, low, high, negated } => Expr::Between {
+            expr: boxed(expr),
+            low: boxed(low),
+            high: boxed(high),
+            negated: *negated,
+        },
+        Expr::InList { expr, list, negated } => Expr::InList {
+            expr: boxed(expr),
+            list: list.iter().map(|e| rolled_up(e, group_by, grouped)).collect(),
+            negated: *negated,
+        },
+        Expr::Like { expr, pattern, negated } => Expr::Like {
+            expr: boxed(expr),
+            pattern: boxed(pattern),
+            negated: *negated,
+        },
+        Expr::Case { operand, when_clauses, else_clause } => Expr::Case {
+            operand: operand.as_deref().map(boxed),
+            when_clauses: when_clauses
+                .iter()
+                .map(|(w, t)| (rolled_up(w, group_by, grouped), rolled_up(t, group_by, grouped)))
+                .collect(),
+            else_clause: else_clause.as_deref().map(boxed),
+        },
+        other => other.clone(),
+    }
+}
+
+/// Whether `a` and `b` are the same GROUP BY expression. A column matches
+/// a column of the same name unless both name different tables.
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Column { table: ta, name: na }, Expr::Column { table: tb, name: nb }) => {
+            na.eq_ignore_ascii_case(nb)
+                && match (ta, tb) {
+                    (Some(ta), Some(tb)) => ta.eq_ignore_ascii_case(tb),
+                    _ => true,
+                }
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+
+    fn expr(sql: &str) -> Expr {
+        match Parser::parse(&format!("SELECT {}", sql)).unwrap().remove(0) {
+            Statement::Select(select) => match &select.columns[0] {
+                SelectColumn::Expr { expr, .. } => expr.clone(),
+                other => panic!("expected an expression, got {:?}", other),
+            },
+            other => panic!("expected SELECT, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn left_out_expressions_become_null_outside_aggregates() {
+        let group_by = [expr("a"), expr("t.b"), expr("c + 1")];
+        assert_eq!(grouping_sets(&group_by, None), vec![3]);
+        assert_eq!(grouping_sets(&group_by, Some(GroupByModifier::Rollup)), vec![3, 2, 1, 0]);
+
+        let rewrite = |sql: &str, grouped| rolled_up(&expr(sql), &group_by, grouped);
+        assert_eq!(rewrite("a || b", 3), expr("a || b"));
+        assert_eq!(rewrite("a || b", 1), expr("a || NULL"));
+        assert_eq!(rewrite("coalesce(t.b, 'all') || sum(b)", 1), expr("coalesce(NULL, 'all') || sum(b)"));
+        assert_eq!(rewrite("c + 1 > max(c, 0)", 2), expr("NULL > max(c, 0)"));
+        assert_eq!(rewrite("count(c + 1)", 0), expr("count(c + 1)"));
+    }
+
+    #[test]
+    fn grouping_is_set_for_left_out_expressions() {
+        let group_by = [expr("a"), expr("b")];
+        let grouping = |sql: &str, grouped| rolled_up(&expr(sql), &group_by, grouped);
+        assert_eq!(grouping("grouping(b)", 2), expr("0"));
+        assert_eq!(grouping("grouping(b)", 1), expr("1"));
+        assert_eq!(grouping("grouping(a, b)", 1), expr("1"));
+        assert_eq!(grouping("grouping(a, b)", 0), expr("3"));
+        assert_eq!(grouping("grouping(b, a)", 1), expr("2"));
+    }
+}
@@ -0,0 +1,196 @@
+//! `PRAGMA integrity_check` and `PRAGMA quick_check`.
+//!
+//! [`check_database`] walks the schema tree and the B-tree of every table
+//! and index in the catalog and describes each problem it finds in one
+//! line of text. The full check verifies the structure of every tree (see
+//! [`BTree::check_integrity`]), that no page belongs to two trees, that
+//! each row decodes under a rowid below the table's `next_rowid`, and that
+//! every full index has one entry per row. The quick check only walks the
+//! leaf level of each tree (see [`BTree::check_leaves`]).
+
+use std::collections::HashSet;
+
+use super::{deserialize_row, index_tree};
+use crate::btree::{BTree, IntegrityReport};
+use crate::buffer::BufferPool;
+use crate::catalog::{Catalog, TableInfo};
+use crate::error::Result;
+
+/// The number of problems reported when the PRAGMA gives no limit, as in
+/// SQLite.
+pub(super) const DEFAULT_MAX_ERRORS: usize = 100;
+
+/// Check the database and return up to `max_errors` problem descriptions,
+/// or a single `"ok"` when there are none.
+pub(super) fn check_database(
+    pool: &BufferPool,
+    catalog: &Catalog,
+    quick: bool,
+    max_errors: usize,
+) -> Result<Vec<String>> {
+    // Page 0 holds the file header
+    let mut seen = HashSet::from([0]);
+    let mut errors = Vec::new();
+    let mut check_tree = |label: &str, tree: BTree| -> Result<IntegrityReport> {
+        let report = if quick {
+            tree.check_leaves(pool)?
+        } else {
+            tree.check_integrity(pool, &mut seen)?
+        };
+        errors.extend(report.errors.iter().map(|e| format!("{}: {}", label, e)));
+        Ok(report)
+    };
+
+    let schema_root = pool.pager().schema_root();
+    if schema_root != 0 {
+        check_tree("schema", BTree::open(schema_root))?;
+    }
+
+    let mut tables: Vec<&TableInfo> = catalog
+        .list_tables()
+        .into_iter()
+        .map(|name| catalog.get_table(name))
+        .collect::<Result<_>>()?;
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut row_errors = Vec::new();
+    for table in tables {
+        let label = format!("table {}", table.name);
+        let report = check_tree(&label, BTree::open(table.root_page))?;
+        // Reading rows is only safe once the tree itself is sound
+        let rows = if !quick && report.is_ok() {
+            row_errors.extend(check_rows(pool, table)?);
+            Some(report.entries)
+        } else {
+            None
+        };
+
+        let mut indexes = catalog.get_indexes_for_table(&table.name);
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        for index in indexes {
+            let report = check_tree(&format!("index {}", index.name), index_tree(index, catalog)?)?;
+            if let (Some(rows), true, None) = (rows, report.is_ok(), &index.predicate) {
+                if report.entries != rows {
+                    row_errors.push(format!(
+                        "index {}: {} entries, but table {} has {} rows",
+                        index.name, report.entries, table.name, rows
+                    ));
+                }
+            }
+        }
+    }
+    errors.append(&mut row_errors);
+
+    if errors.is_empty() {
+        errors.push("ok".to_string());
+    }
+    errors.truncate(max_errors.max(1));
+    Ok(errors)
+}
+
+/// Check that every row of `table` decodes and, for rowid tables, that its
+/// key is a rowid below `next_rowid`.
+fn check_rows(pool: &BufferPool, table: &TableInfo) -> Result<Vec<String>> {
+    let mut errors = Vec::new();
+    for entry in BTree::open(table.root_page).scan_all(pool)? {
+        if !table.has_composite_pk() {
+            match <[u8; 8]>::try_from(entry.key.as_slice()) {
+                Ok(bytes) => {
+                    let rowid = i64::from_be_bytes(bytes);
+                    if rowid >= table.next_rowid {
+                        errors.push(format!(
+                            "table {}: rowid {} is not below next_rowid {}",
+                            table.name, rowid, table.next_rowid
+                        ));
+                    }
+                }
+                Err(_) => {
+                    errors.push(format!("table {}: malformed rowid key", table.name));
+                    continue;
+                }
+            }
+        }
+        if deserialize_row(&entry.value, table.columns.len()).is_err() {
+            errors.push(format!("table {}: malformed row", table.name));
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::execute_statement;
+    use crate::pager::Pager;
+    use crate::sql::parser::Parser;
+    use crate::mvcc::TransactionManager;
+    use tempfile::TempDir;
+
+    /// A table of 200 rows with an index on `name`.
+    fn setup() -> (TempDir, BufferPool, Catalog) {
+        let dir = TempDir::new().unwrap();
+        let pager = Pager::open(&dir.path().join("test.hdb"), false).unwrap();
+        let mut pool = BufferPool::new(pager, None, 100).unwrap();
+        let mut catalog = Catalog::new();
+        let mut txn_mgr = TransactionManager::new();
+        let mut sql = vec![
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)".to_string(),
+            "CREATE INDEX idx_name ON t (name)".to_string(),
+        ];
+        sql.extend((0..200).map(|i| format!("INSERT INTO t VALUES ({}, 'name {}')", i, i)));
+        for sql in sql {
+            for stmt in Parser::parse(&sql).unwrap() {
+                execute_statement(&stmt, &mut pool, &mut catalog, &mut txn_mgr).unwrap();
+            }
+        }
+        (dir, pool, catalog)
+    }
+
+    fn check(pool: &BufferPool, catalog: &Catalog, quick: bool) -> Vec<String> {
+        check_database(pool, catalog, quick, DEFAULT_MAX_ERRORS).unwrap()
+    }
+
+    #[test]
+    fn sound_database_is_ok() {
+        let (_dir, pool, catalog) = setup();
+        assert_eq!(check(&pool, &catalog, false), vec!["ok"]);
+        assert_eq!(check(&pool, &catalog, true), vec!["ok"]);
+    }
+
+    #[test]
+    fn wrong_root_page_is_caught() {
+        let (_dir, pool, mut catalog) = setup();
+        let index_root = catalog.get_index("idx_name").unwrap().root_page;
+        catalog.get_table_mut("t").unwrap().root_page = index_root;
+
+        let errors = check(&pool, &catalog, false);
+        assert!(
+            errors.iter().any(|e| e.starts_with("index idx_name: page") && e.ends_with("referenced more than once")),
+            "{:?}",
+            errors
+        );
+
+        catalog.get_table_mut("t").unwrap().root_page = 9999;
+        let expected = vec!["table t: page 9999 is out of range".to_string()];
+        assert_eq!(check(&pool, &catalog, false), expected);
+        assert_eq!(check(&pool, &catalog, true), expected);
+    }
+
+    #[test]
+    fn row_and_index_mismatches_are_caught() {
+        let (_dir, mut pool, mut catalog) = setup();
+        catalog.get_table_mut("t").unwrap().next_rowid = 150;
+        let index_root = catalog.get_index("idx_name").unwrap().root_page;
+        let mut index = BTree::open(index_root);
+        let key = index.scan_all(&pool).unwrap().remove(0).key;
+        index.delete(&mut pool, &key).unwrap();
+
+        let errors = check(&pool, &catalog, false);
+        assert_eq!(errors.len(), 51);
+        assert_eq!(errors[0], "table t: rowid 150 is not below next_rowid 150");
+        assert_eq!(errors[50], "index idx_name: 199 entries, but table t has 200 rows");
+        assert_eq!(check_database(&pool, &catalog, false, 3).unwrap().len(), 3);
+        // The quick check does not look at rows
+        assert_eq!(check(&pool, &catalog, true), vec!["ok"]);
+    }
+}
@@ -0,0 +1,429 @@
+//! Equi-joins whose hash table does not fit in memory.
+//!
+//! A hash join builds a hash table over one input (the right one, or the
+//! left one for a RIGHT JOIN) and probes it with the other. When the build
+//! input holds more rows than the connection's join spill threshold (see
+//! `PRAGMA join_spill_threshold`), [`GraceHashJoin`] first splits both
+//! inputs into partitions by a hash of their join keys and writes them to
+//! a temporary file. Rows with equal keys land in the same partition, so
+//! joining each pair of partitions in memory joins the whole inputs, with
+//! a hash table over one partition at a time.
+//!
+//! Every row is written along with its position in its input, and the
+//! joined rows are put back in the order the in-memory join produces.
+//!
+//! In memory, the build rows go into a [`JoinHashTable`], which puts a
+//! [`BloomFilter`] of the build keys in front of its hash table: a probe
+//! key the filter rules out is known not to match without a lookup.
+//!
+//! Inputs that are already in join key order, such as two tables read in
+//! the order of the INTEGER PRIMARY KEYs they are joined on, need no hash
+//! table at all: [`merge_join`] advances a cursor over each input and
+//! pairs up the runs of rows with equal keys.
+
+use std::collections::hash_map::DefaultHasher;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::cancel;
+use crate::error::Result;
+use crate::sql::ast::JoinType;
+use crate::types::Value;
+use super::execute_hash_join;
+use super::spill::{Run, RunReader, RunWriter, SpillFile};
+
+/// The number of build rows joined in memory unless configured otherwise.
+pub const DEFAULT_JOIN_SPILL_THRESHOLD: usize = 1_000_000;
+
+/// The most partitions an input is split into.
+const MAX_PARTITIONS: usize = 256;
+
+/// The number of bits a [`BloomFilter`] sets for each value.
+const BLOOM_HASHES: u64 = 8;
+
+/// The bits of a [`BloomFilter`] per expected value. With 8 hashes, this
+/// gives a false positive rate of about 0.3%.
+const BLOOM_BITS_PER_VALUE: usize = 12;
+
+/// A set of values that can tell that a value was never inserted, but may
+/// wrongly report a value as inserted.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// An empty filter sized for `values` values.
+    pub fn new(values: usize) -> Self {
+        let words = (values.max(1) * BLOOM_BITS_PER_VALUE + 63) / 64;
+        BloomFilter { bits: vec![0; words] }
+    }
+
+    /// Add `value` to the set.
+    pub fn insert(&mut self, value: &Value) {
+        for bit in self.bit_positions(value) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether `value` may have been inserted. `false` means it never was.
+    pub fn may_contain(&self, value: &Value) -> bool {
+        self.bit_positions(value).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// The bits of `value`, derived from a single hash by double hashing.
+    fn bit_positions(&self, value: &Value) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let step = hash.rotate_left(32) | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..BLOOM_HASHES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+    }
+}
+
+/// The build side of an in-memory hash join: the positions of the build
+/// rows by join key, with a [`BloomFilter`] of the keys in front.
+#[derive(Debug)]
+pub struct JoinHashTable {
+    rows: HashMap<Value, Vec<usize>>,
+    filter: BloomFilter,
+    lookups: usize,
+}
+
+impl JoinHashTable {
+    /// Build the table over column `key` of `rows`. Rows with a NULL key
+    /// never match, so they are left out.
+    pub fn build(rows: &[Vec<Value>], key: usize) -> Self {
+        let mut table = JoinHashTable { rows: HashMap::new(), filter: BloomFilter::new(rows.len()), lookups: 0 };
+        for (i, row) in rows.iter().enumerate() {
+            let key = &row[key];
+            if !key.is_null() {
+                table.filter.insert(key);
+                table.rows.entry(key.clone()).or_default().push(i);
+            }
+        }
+        table
+    }
+
+    /// The positions of the build rows whose key equals `key`, or `None`
+    /// if there are none.
+    pub fn probe(&mut self, key: &Value) -> Option<&[usize]> {
+        if key.is_null() || !self.filter.may_contain(key) {
+            return None;
+        }
+        self.lookups += 1;
+        self.rows.get(key).map(Vec::as_slice)
+    }
+
+    /// The number of probes that went on to look up the hash table.
+    pub fn lookups(&self) -> usize {
+        self.lookups
+    }
+}
+
+/// A hash join on one column of each input that partitions its inputs on
+/// disk when the build input has more rows than a threshold.
+#[derive(Debug, Clone)]
+pub struct GraceHashJoin {
+    join_type: JoinType,
+    left_key: usize,
+    right_key: usize,
+    threshold: usize,
+}
+
+impl GraceHashJoin {
+    /// A join of type `join_type` (not CROSS) on equality of column
+    /// `left_key` of the left rows and column `right_key` of the right
+    /// rows, joining up to `threshold` build rows in memory.
+    pub fn new(join_type: JoinType, left_key: usize, right_key: usize, threshold: usize) -> Self {
+        GraceHashJoin { join_type, left_key, right_key, threshold: threshold.max(1) }
+    }
+
+    /// The number of partitions the inputs are split into for a left input
+    /// of `left_rows` rows and a right input of `right_rows` rows, or 1 if
+    /// they are joined in memory.
+    pub fn partitions(&self, left_rows: usize, right_rows: usize) -> usize {
+        let build_rows = match self.join_type {
+            JoinType::Right => left_rows,
+            _ => right_rows,
+        };
+        if build_rows <= self.threshold {
+            return 1;
+        }
+        ((build_rows + self.threshold - 1) / self.threshold).clamp(2, MAX_PARTITIONS)
+    }
+
+    /// Join `left`, whose rows have `num_left` columns, with `right`, whose
+    /// rows have `num_right` columns. Each joined row is a left row followed
+    /// by a right row, either of which is NULL-padded where the join type
+    /// calls for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing or reading the temporary file, and
+    /// [`HorizonError::Cancelled`](crate::error::HorizonError::Cancelled)
+    /// if the query is cancelled.
+    pub fn join(
+        &self,
+        left: Vec<Vec<Value>>,
+        num_left: usize,
+        right: Vec<Vec<Value>>,
+        num_right: usize,
+    ) -> Result<Vec<Vec<Value>>> {
+        let partitions = self.partitions(left.len(), right.len());
+        if partitions == 1 {
+            return execute_hash_join(&left, &right, self.left_key, self.right_key, num_left, num_right, &self.join_type);
+        }
+
+        let mut spill = SpillFile::create("join")?;
+        let left_runs = partition(&mut spill, left, self.left_key, partitions)?;
+        let right_runs = partition(&mut spill, right, self.right_key, partitions)?;
+
+        // Each input row carries its position as an extra last column, so
+        // a joined row is [left.., left position, right.., right position]
+        let mut joined: Vec<((u64, u64), Vec<Value>)> = Vec::new();
+        for (left_run, right_run) in left_runs.iter().zip(&right_runs) {
+            cancel::check()?;
+            let left = read_partition(&spill, left_run)?;
+            let right = read_partition(&spill, right_run)?;
+            let rows = execute_hash_join(
+                &left, &right, self.left_key, self.right_key,
+                num_left + 1, num_right + 1, &self.join_type,
+            )?;
+            for mut row in rows {
+                let right_pos = position(row.pop());
+                let left_pos = position(Some(row.remove(num_left)));
+                joined.push((self.output_order(left_pos, right_pos), row));
+            }
+        }
+        joined.sort_unstable_by_key(|(order, _)| *order);
+        Ok(joined.into_iter().map(|(_, row)| row).collect())
+    }
+
+    /// Where a row joined from the given input positions (`None` for the
+    /// NULL-padded side) goes in the output of the in-memory join: in probe
+    /// order, matches in build order, then unmatched build rows of a FULL
+    /// JOIN.
+    fn output_order(&self, left_pos: Option<u64>, right_pos: Option<u64>) -> (u64, u64) {
+        match self.join_type {
+            JoinType::Right => (right_pos.unwrap_or(0), left_pos.unwrap_or(0)),
+            _ => (left_pos.unwrap_or(u64::MAX), right_pos.unwrap_or(0)),
+        }
+    }
+}
+
+/// Write `rows` to `partitions` runs of `spill` by the hash of column
+/// `key`, each row with its position in `rows`.
+fn partition(spill: &mut SpillFile, rows: Vec<Vec<Value>>, key: usize, partitions: usize) -> Result<Vec<Run>> {
+    let mut writers: Vec<RunWriter> = (0..partitions).map(|_| RunWriter::default()).collect();
+    for (pos, row) in rows.into_iter().enumerate() {
+        let writer = &mut writers[partition_of(&row[key], partitions)];
+        writer.push(spill, &[Value::Integer(pos as i64)], &row)?;
+    }
+    writers.into_iter().map(|writer| writer.finish(spill)).collect()
+}
+
+/// The partition of a join key. NULL keys never match, so they all go to
+/// the first partition.
+fn partition_of(key: &Value, partitions: usize) -> usize {
+    if key.is_null() {
+        return 0;
+    }
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % partitions as u64) as usize
+}
+
+/// Read back a partition, appending each row's position to it.
+fn read_partition(spill: &SpillFile, run: &Run) -> Result<Vec<Vec<Value>>> {
+    let mut rows = Vec::with_capacity(run.len());
+    let mut reader = RunReader::new(run);
+    while let Some((mut pos, mut row)) = reader.next(spill)? {
+        row.append(&mut pos);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn position(value: Option<Value>) -> Option<u64> {
+    match value {
+        Some(Value::Integer(pos)) => Some(pos as u64),
+        _ => None,
+    }
+}
+
+/// Join `left`, whose rows have `num_left` columns, with `right`, whose
+/// rows have `num_right` columns, on equality of column `left_key` of the
+/// left rows and column `right_key` of the right rows, by advancing a
+/// cursor over each input in key order. `join_type` must not be CROSS.
+///
+/// The inputs are expected to be in key order already; one that is not
+/// (a table with negative keys, which it stores after the others) is
+/// sorted first. The joined rows come out in key order, NULL-padded where
+/// the join type calls for it.
+///
+/// # Errors
+///
+/// Returns [`HorizonError::Cancelled`](crate::error::HorizonError::Cancelled)
+/// if the query is cancelled.
+pub fn merge_join(
+    mut left: Vec<Vec<Value>>,
+    num_left: usize,
+    mut right: Vec<Vec<Value>>,
+    num_right: usize,
+    left_key: usize,
+    right_key: usize,
+    join_type: &JoinType,
+) -> Result<Vec<Vec<Value>>> {
+    sort_by_key(&mut left, left_key);
+    sort_by_key(&mut right, right_key);
+    let pad_left = matches!(join_type, JoinType::Left | JoinType::Full);
+    let pad_right = matches!(join_type, JoinType::Right | JoinType::Full);
+    let joined = |l: Option<&Vec<Value>>, r: Option<&Vec<Value>>| {
+        let mut row = l.cloned().unwrap_or_else(|| vec![Value::Null; num_left]);
+        row.extend(r.cloned().unwrap_or_else(|| vec![Value::Null; num_right]));
+        row
+    };
+
+    let mut result = Vec::new();
+    let (mut l, mut r) = (0, 0);
+    while l < left.len() && r < right.len() {
+        cancel::check()?;
+        let (left_value, right_value) = (&left[l][left_key], &right[r][right_key]);
+        // NULL keys sort first and never match
+        let order = match (left_value.is_null(), right_value.is_null()) {
+            (true, _) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => left_value.cmp(right_value),
+        };
+        match order {
+            Ordering::Less => {
+                if pad_left {
+                    result.push(joined(Some(&left[l]), None));
+                }
+                l += 1;
+            }
+            Ordering::Greater => {
+                if pad_right {
+                    result.push(joined(None, Some(&right[r])));
+                }
+                r += 1;
+            }
+            Ordering::Equal => {
+                let left_end = run_end(&left, l, left_key);
+                let right_end = run_end(&right, r, right_key);
+                for left_row in &left[l..left_end] {
+                    for right_row in &right[r..right_end] {
+                        result.push(joined(Some(left_row), Some(right_row)));
+                    }
+                }
+                (l, r) = (left_end, right_end);
+            }
+        }
+    }
+    if pad_left {
+        result.extend(left[l..].iter().map(|row| joined(Some(row), None)));
+    }
+    if pad_right {
+        result.extend(right[r..].iter().map(|row| joined(None, Some(row))));
+    }
+    Ok(result)
+}
+
+/// Sort `rows` by column `key`, unless they are in that order already.
+fn sort_by_key(rows: &mut [Vec<Value>], key: usize) {
+    if rows.windows(2).any(|pair| pair[0][key] > pair[1][key]) {
+        rows.sort_by(|a, b| a[key].cmp(&b[key]));
+    }
+}
+
+/// The end of the run of rows starting at `start` whose column `key` equals
+/// that of the row at `start`.
+fn run_end(rows: &[Vec<Value>], start: usize, key: usize) -> usize {
+    let value = &rows[start][key];
+    start + rows[start..].iter().take_while(|row| row[key].cmp(value) == Ordering::Equal).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows `[key, n]` for `n` in `0..count`, with `key` cycling through
+    /// `distinct` values and NULL for every tenth row.
+    fn rows(count: i64, distinct: i64) -> Vec<Vec<Value>> {
+        (0..count)
+            .map(|n| {
+                let key = if n % 10 == 9 { Value::Null } else { Value::Integer(n * 7919 % distinct) };
+                vec![key, Value::Integer(n)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partitioned_join_matches_the_in_memory_join() {
+        let (left, right) = (rows(5000, 3000), rows(4000, 2000));
+        for join_type in [JoinType::Inner, JoinType::Left, JoinType::Right, JoinType::Full] {
+            let spilled = GraceHashJoin::new(join_type.clone(), 0, 0, 100);
+            assert!(spilled.partitions(left.len(), right.len()) > 1);
+            let expected = execute_hash_join(&left, &right, 0, 0, 2, 2, &join_type).unwrap();
+            let joined = spilled.join(left.clone(), 2, right.clone(), 2).unwrap();
+            assert_eq!(joined, expected, "{:?}", join_type);
+        }
+    }
+
+    #[test]
+    fn merge_join_matches_the_hash_join() {
+        // Unsorted inputs with repeated and NULL keys
+        let (left, right) = (rows(3000, 1000), rows(2000, 1500));
+        for join_type in [JoinType::Inner, JoinType::Left, JoinType::Right, JoinType::Full] {
+            let mut expected = execute_hash_join(&left, &right, 0, 0, 2, 2, &join_type).unwrap();
+            let mut joined = merge_join(left.clone(), 2, right.clone(), 2, 0, 0, &join_type).unwrap();
+            expected.sort();
+            joined.sort();
+            assert_eq!(joined, expected, "{:?}", join_type);
+        }
+
+        // Inputs in key order come out in key order
+        let left: Vec<Vec<Value>> = (0..100).map(|n| vec![Value::Integer(n * 2)]).collect();
+        let right: Vec<Vec<Value>> = (0..100).map(|n| vec![Value::Integer(n * 3)]).collect();
+        let joined = merge_join(left, 1, right, 1, 0, 0, &JoinType::Inner).unwrap();
+        let keys: Vec<Value> = joined.iter().map(|row| row[0].clone()).collect();
+        assert_eq!(keys, (0..34).map(|n| Value::Integer(n * 6)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn bloom_filter_has_no_false_negatives_and_few_false_positives() {
+        let mut filter = BloomFilter::new(10_000);
+        for n in 0..10_000 {
+            filter.insert(&Value::Integer(n));
+        }
+        assert!((0..10_000).all(|n| filter.may_contain(&Value::Integer(n))));
+        let false_positives = (10_000..110_000).filter(|&n| filter.may_contain(&Value::Integer(n))).count();
+        assert!(false_positives < 1000, "{} false positives in 100000", false_positives);
+    }
+
+    #[test]
+    fn bloom_filter_skips_lookups_for_unmatched_probe_keys() {
+        // One in ten probe keys matches a build key
+        let build: Vec<Vec<Value>> = (0..10_000).map(|n| vec![Value::Integer(n * 10)]).collect();
+        let probe: Vec<Vec<Value>> = (0..100_000).map(|n| vec![Value::Integer(n)]).collect();
+        let mut table = JoinHashTable::build(&build, 0);
+        let matched = probe.iter().filter(|row| table.probe(&row[0]).is_some()).count();
+        assert_eq!(matched, 10_000);
+        assert!(table.lookups() < 11_000, "{} lookups for 100000 probes", table.lookups());
+
+        let joined = execute_hash_join(&probe, &build, 0, 0, 1, 1, &JoinType::Inner).unwrap();
+        assert_eq!(joined.len(), 10_000);
+        assert!(joined.iter().all(|row| row[0] == row[1]));
+    }
+
+    #[test]
+    fn partitions_are_capped() {
+        let join = GraceHashJoin::new(JoinType::Inner, 0, 0, 10);
+        assert_eq!(join.partitions(1_000_000, 10), 1);
+        assert_eq!(join.partitions(10, 11), 2);
+        assert_eq!(join.partitions(10, 1_000_000), MAX_PARTITIONS);
+    }
+}
@@ -2,7 +2,15 @@
 //!
 //! Provides a minimal JSON implementation with no external dependencies.
 //! JSON values are stored as TEXT in the database, and this module handles
-//! parsing, serialization, path extraction, and type inspection.
+//! parsing, serialization, path extraction, type inspection, and in-place
+//! modification, along with the `json_each` and `json_tree` table-valued
+//! functions. `JSON_EXTRACT` also takes JSONPath queries, with wildcards,
+//! recursive descent, slices, and filters.
+
+use super::{check_arity, RowSet};
+use crate::error::{HorizonError, Result};
+use crate::sql::ast::BinaryOp;
+use crate::types::Value;
 
 /// A JSON value representation.
 #[derive(Debug, Clone, PartialEq)]
@@ -99,37 +107,32 @@ impl JsonValue {
 
     /// Extract a value at the given JSON path (e.g., "$.key", "$[0]", "$.a.b").
     pub fn extract_path(&self, path: &str) -> Option<&JsonValue> {
-        let segments = parse_json_path(path)?;
+        self.follow(&parse_json_path(path)?)
+    }
+
+    /// The value reached by following `segments` from this one.
+    fn follow(&self, segments: &[PathSegment]) -> Option<&JsonValue> {
         let mut current = self;
         for seg in segments {
-            match seg {
-                PathSegment::Key(key) => {
-                    if let JsonValue::Object(pairs) = current {
-                        let found = pairs.iter().find(|(k, _)| k == &key);
-                        match found {
-                            Some((_, v)) => current = v,
-                            None => return None,
-                        }
-                    } else {
-                        return None;
-                    }
-                }
-                PathSegment::Index(idx) => {
-                    if let JsonValue::Array(items) = current {
-                        if idx < items.len() {
-                            current = &items[idx];
-                        } else {
-                            return None;
-                        }
-                    } else {
-                        return None;
-                    }
-                }
-            }
+            current = match (seg, current) {
+                (PathSegment::Key(key), JsonValue::Object(pairs)) => &pairs.iter().find(|(k, _)| k == key)?.1,
+                (PathSegment::Index(idx), JsonValue::Array(items)) => items.get(*idx)?,
+                _ => return None,
+            };
         }
         Some(current)
     }
 
+    /// All the values the JSONPath query `path` selects, in document
+    /// order, or None if `path` is malformed. Besides the keys and indexes
+    /// [`JsonValue::extract_path`] follows, a query can use `*` for every
+    /// element or member, `..` for recursive descent, `[start:end]` slices,
+    /// negative indexes, and `[?(...)]` filters such as `[?(@.age > 18)]`.
+    pub fn query_path(&self, path: &str) -> Option<Vec<&JsonValue>> {
+        let steps = JsonPathParser::parse(path)?;
+        Some(steps.iter().fold(vec![self], |nodes, step| step.apply(nodes)))
+    }
+
     /// Return the array length if this is an array, None otherwise.
     pub fn array_length(&self) -> Option<usize> {
         if let JsonValue::Array(items) = self {
@@ -138,9 +141,172 @@ impl JsonValue {
             None
         }
     }
+
+    /// Iterate over the elements if this is an array, None otherwise.
+    pub fn iter_array(&self) -> Option<std::slice::Iter<'_, JsonValue>> {
+        if let JsonValue::Array(items) = self {
+            Some(items.iter())
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the key/value pairs if this is an object, None otherwise.
+    pub fn iter_object(&self) -> Option<std::slice::Iter<'_, (String, JsonValue)>> {
+        if let JsonValue::Object(pairs) = self {
+            Some(pairs.iter())
+        } else {
+            None
+        }
+    }
+
+    /// Store `value` at the given JSON path, creating and/or overwriting
+    /// as `mode` allows. Missing object keys along the path are created as
+    /// nested objects, and an array index one past the end appends.
+    /// Returns false if `path` is malformed.
+    pub fn set_path(&mut self, path: &str, value: JsonValue, mode: JsonSetMode) -> bool {
+        match parse_json_path(path) {
+            Some(segments) => {
+                self.set_segments(&segments, value, mode);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_segments(&mut self, segments: &[PathSegment], value: JsonValue, mode: JsonSetMode) {
+        let (seg, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => {
+                if mode != JsonSetMode::Insert {
+                    *self = value;
+                }
+                return;
+            }
+        };
+        match (seg, self) {
+            (PathSegment::Key(key), JsonValue::Object(pairs)) => {
+                match pairs.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, child)) => child.set_segments(rest, value, mode),
+                    None if mode != JsonSetMode::Replace => {
+                        if let Some(child) = new_path_value(rest, value) {
+                            pairs.push((key.clone(), child));
+                        }
+                    }
+                    None => {}
+                }
+            }
+            (PathSegment::Index(idx), JsonValue::Array(items)) => {
+                if *idx < items.len() {
+                    items[*idx].set_segments(rest, value, mode);
+                } else if *idx == items.len() && mode != JsonSetMode::Replace {
+                    if let Some(child) = new_path_value(rest, value) {
+                        items.push(child);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Remove the value at the given JSON path, if there is one. Removing
+    /// the root (`$`) leaves null. Returns false if `path` is malformed.
+    pub fn remove_path(&mut self, path: &str) -> bool {
+        let segments = match parse_json_path(path) {
+            Some(segments) => segments,
+            None => return false,
+        };
+        let (last, parents) = match segments.split_last() {
+            Some(split) => split,
+            None => {
+                *self = JsonValue::Null;
+                return true;
+            }
+        };
+        let mut current = self;
+        for seg in parents {
+            current = match (seg, current) {
+                (PathSegment::Key(key), JsonValue::Object(pairs)) => {
+                    match pairs.iter_mut().find(|(k, _)| k == key) {
+                        Some((_, child)) => child,
+                        None => return true,
+                    }
+                }
+                (PathSegment::Index(idx), JsonValue::Array(items)) => match items.get_mut(*idx) {
+                    Some(child) => child,
+                    None => return true,
+                },
+                _ => return true,
+            };
+        }
+        match (last, current) {
+            (PathSegment::Key(key), JsonValue::Object(pairs)) => pairs.retain(|(k, _)| k != key),
+            (PathSegment::Index(idx), JsonValue::Array(items)) if *idx < items.len() => {
+                items.remove(*idx);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Apply `patch` as an RFC 7396 merge patch: object members are merged
+    /// recursively, members whose patch value is null are removed, and any
+    /// other patch value replaces this one outright.
+    pub fn merge_patch(&mut self, patch: &JsonValue) {
+        let patch_pairs = match patch {
+            JsonValue::Object(pairs) => pairs,
+            _ => {
+                *self = patch.clone();
+                return;
+            }
+        };
+        if !matches!(self, JsonValue::Object(_)) {
+            *self = JsonValue::Object(Vec::new());
+        }
+        if let JsonValue::Object(pairs) = self {
+            for (key, value) in patch_pairs {
+                let existing = pairs.iter().position(|(k, _)| k == key);
+                match (existing, value) {
+                    (Some(i), JsonValue::Null) => {
+                        pairs.remove(i);
+                    }
+                    (None, JsonValue::Null) => {}
+                    (Some(i), value) => pairs[i].1.merge_patch(value),
+                    (None, value) => {
+                        let mut member = JsonValue::Null;
+                        member.merge_patch(value);
+                        pairs.push((key.clone(), member));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether [`JsonValue::set_path`] may create new values, overwrite
+/// existing ones, or both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonSetMode {
+    /// Create or overwrite (`JSON_SET`).
+    Set,
+    /// Only create (`JSON_INSERT`).
+    Insert,
+    /// Only overwrite (`JSON_REPLACE`).
+    Replace,
+}
+
+/// The value to create for the `segments` left of a path that ran past the
+/// end of the document: `value` nested in a new object for each remaining
+/// key. None if an array index remains, since there is no array to index.
+fn new_path_value(segments: &[PathSegment], value: JsonValue) -> Option<JsonValue> {
+    segments.iter().rev().try_fold(value, |value, seg| match seg {
+        PathSegment::Key(key) => Some(JsonValue::Object(vec![(key.clone(), value)])),
+        PathSegment::Index(_) => None,
+    })
 }
 
 /// A segment in a JSON path.
+#[derive(Debug, Clone, PartialEq)]
 enum PathSegment {
     Key(String),
     Index(usize),
@@ -209,6 +375,606 @@ fn parse_json_path(path: &str) -> Option<Vec<PathSegment>> {
     Some(segments)
 }
 
+// ---- JSONPath ----
+
+/// `JSON_EXTRACT(json, path)`: the value at `path` in the JSON text `json`,
+/// as [`json_value_to_sql`] converts it.
+///
+/// A path that can select more than one value (a wildcard, recursive
+/// descent, slice, or filter) yields a JSON array of everything it
+/// selects, in document order. Malformed JSON or no value at a path that
+/// selects at most one yields NULL.
+pub(super) fn json_extract(json: &str, path: &str) -> Value {
+    let doc = match JsonParser::parse(json) {
+        Some(doc) => doc,
+        None => return Value::Null,
+    };
+    match JsonPathParser::parse(path) {
+        Some(steps) => {
+            let nodes = steps.iter().fold(vec![&doc], |nodes, step| step.apply(nodes));
+            if steps.iter().all(PathStep::is_singular) {
+                nodes.first().map_or(Value::Null, |node| json_value_to_sql(node))
+            } else {
+                Value::Text(JsonValue::Array(nodes.into_iter().cloned().collect()).to_json_string())
+            }
+        }
+        None => doc.extract_path(path).map_or(Value::Null, json_value_to_sql),
+    }
+}
+
+/// A step of a JSONPath query, selecting values from each of the values
+/// the step before it selected.
+#[derive(Debug, Clone, PartialEq)]
+enum PathStep {
+    /// `.key` or `['key']`: the member `key` of an object.
+    Key(String),
+    /// `[n]`: element `n` of an array, counting from the end if negative.
+    Index(i64),
+    /// `.*` or `[*]`: every element of an array or member of an object.
+    Wildcard,
+    /// `[start:end]`: the elements of an array from `start` up to but not
+    /// including `end`, either counting from the end if negative.
+    Slice(Option<i64>, Option<i64>),
+    /// `[?(filter)]`: the elements or members matching `filter`.
+    Filter(PathFilter),
+    /// `..`: the value itself and everything nested in it, for the next
+    /// step to select from.
+    Descendants,
+}
+
+impl PathStep {
+    /// Whether the step selects at most one value from each value.
+    fn is_singular(&self) -> bool {
+        matches!(self, PathStep::Key(_) | PathStep::Index(_))
+    }
+
+    /// The values the step selects from `nodes`.
+    fn apply<'a>(&self, nodes: Vec<&'a JsonValue>) -> Vec<&'a JsonValue> {
+        let mut selected = Vec::new();
+        for node in nodes {
+            match self {
+                PathStep::Key(key) => {
+                    if let Some(mut pairs) = node.iter_object() {
+                        if let Some((_, v)) = pairs.find(|(k, _)| k == key) {
+                            selected.push(v);
+                        }
+                    }
+                }
+                PathStep::Index(idx) => {
+                    if let JsonValue::Array(items) = node {
+                        let idx = if *idx < 0 { items.len() as i64 + idx } else { *idx };
+                        if let Some(item) = usize::try_from(idx).ok().and_then(|i| items.get(i)) {
+                            selected.push(item);
+                        }
+                    }
+                }
+                PathStep::Wildcard => selected.extend(children(node)),
+                PathStep::Slice(start, end) => {
+                    if let JsonValue::Array(items) = node {
+                        let len = items.len() as i64;
+                        let clamp = |bound: i64| (if bound < 0 { len + bound } else { bound }).clamp(0, len) as usize;
+                        let start = start.map_or(0, clamp);
+                        let end = end.map_or(items.len(), clamp);
+                        if start < end {
+                            selected.extend(&items[start..end]);
+                        }
+                    }
+                }
+                PathStep::Filter(filter) => selected.extend(children(node).filter(|child| filter.matches(child))),
+                PathStep::Descendants => push_descendants(node, &mut selected),
+            }
+        }
+        selected
+    }
+}
+
+/// The elements of `node` if it is an array, or its member values if it
+/// is an object.
+fn children(node: &JsonValue) -> impl Iterator<Item = &JsonValue> {
+    let items = node.iter_array().into_iter().flatten();
+    let values = node.iter_object().into_iter().flatten().map(|(_, v)| v);
+    items.chain(values)
+}
+
+/// Push `node` and everything nested in it onto `out`, parents first.
+fn push_descendants<'a>(node: &'a JsonValue, out: &mut Vec<&'a JsonValue>) {
+    out.push(node);
+    for child in children(node) {
+        push_descendants(child, out);
+    }
+}
+
+/// The condition of a `[?(...)]` JSONPath filter, tested against each
+/// element or member (`@`) in turn.
+#[derive(Debug, Clone, PartialEq)]
+enum PathFilter {
+    /// `@.path`: the path leads to a value.
+    Exists(Vec<PathSegment>),
+    /// `@.path op literal`: the value at the path compares with the literal.
+    Compare(Vec<PathSegment>, BinaryOp, JsonValue),
+    And(Box<PathFilter>, Box<PathFilter>),
+    Or(Box<PathFilter>, Box<PathFilter>),
+    Not(Box<PathFilter>),
+}
+
+impl PathFilter {
+    fn matches(&self, node: &JsonValue) -> bool {
+        match self {
+            PathFilter::Exists(path) => node.follow(path).is_some(),
+            PathFilter::Compare(path, op, literal) => {
+                let value = match node.follow(path) {
+                    Some(value) => value,
+                    None => return false,
+                };
+                let ordering = match (value, literal) {
+                    (JsonValue::Number(a), JsonValue::Number(b)) => a.partial_cmp(b),
+                    (JsonValue::String(a), JsonValue::String(b)) => Some(a.cmp(b)),
+                    _ => None,
+                };
+                match op {
+                    BinaryOp::Eq => value == literal,
+                    BinaryOp::NotEq => value != literal,
+                    BinaryOp::Lt => ordering.is_some_and(|o| o.is_lt()),
+                    BinaryOp::LtEq => ordering.is_some_and(|o| o.is_le()),
+                    BinaryOp::Gt => ordering.is_some_and(|o| o.is_gt()),
+                    BinaryOp::GtEq => ordering.is_some_and(|o| o.is_ge()),
+                    _ => false,
+                }
+            }
+            PathFilter::And(a, b) => a.matches(node) && b.matches(node),
+            PathFilter::Or(a, b) => a.matches(node) || b.matches(node),
+            PathFilter::Not(inner) => !inner.matches(node),
+        }
+    }
+}
+
+/// A parser for JSONPath queries such as `$.store..price` or
+/// `$.people[?(@.age >= 18 && @.name != 'Bob')].name`.
+struct JsonPathParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonPathParser {
+    /// Parse `path` into its steps, or None if it is malformed.
+    fn parse(path: &str) -> Option<Vec<PathStep>> {
+        let mut parser = JsonPathParser { chars: path.trim().chars().collect(), pos: 0 };
+        if !parser.eat('$') {
+            return None;
+        }
+        let mut steps = Vec::new();
+        while let Some(c) = parser.peek() {
+            match c {
+                '.' if parser.chars.get(parser.pos + 1) == Some(&'.') => {
+                    parser.pos += 2;
+                    steps.push(PathStep::Descendants);
+                    if parser.peek() != Some('[') {
+                        steps.push(parser.parse_member()?);
+                    }
+                }
+                '.' => {
+                    parser.pos += 1;
+                    steps.push(parser.parse_member()?);
+                }
+                '[' => {
+                    parser.pos += 1;
+                    steps.push(parser.parse_bracket()?);
+                }
+                _ => return None,
+            }
+        }
+        Some(steps)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `c` if it comes next, after any whitespace.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume `s` if it comes next, after any whitespace.
+    fn eat_str(&mut self, s: &str) -> bool {
+        self.skip_whitespace();
+        let matched = s.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c));
+        if matched {
+            self.pos += s.chars().count();
+        }
+        matched
+    }
+
+    /// The key or `*` after a `.`, which runs up to the next `.` or `[`.
+    fn parse_member(&mut self) -> Option<PathStep> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c != '.' && c != '[') {
+            self.pos += 1;
+        }
+        let key: String = self.chars[start..self.pos].iter().collect();
+        match key.as_str() {
+            "" => None,
+            "*" => Some(PathStep::Wildcard),
+            _ => Some(PathStep::Key(key)),
+        }
+    }
+
+    /// The step inside `[...]`, the `[` already consumed.
+    fn parse_bracket(&mut self) -> Option<PathStep> {
+        let step = if self.eat('*') {
+            PathStep::Wildcard
+        } else if self.eat('?') {
+            if !self.eat('(') {
+                return None;
+            }
+            let filter = self.parse_or()?;
+            if !self.eat(')') {
+                return None;
+            }
+            PathStep::Filter(filter)
+        } else if let Some(key) = self.parse_quoted() {
+            PathStep::Key(key)
+        } else {
+            let start = self.parse_integer();
+            if self.eat(':') {
+                PathStep::Slice(start, self.parse_integer())
+            } else {
+                PathStep::Index(start?)
+            }
+        };
+        if self.eat(']') {
+            Some(step)
+        } else {
+            None
+        }
+    }
+
+    /// A `'...'` or `"..."` string, without escapes.
+    fn parse_quoted(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let quote = self.peek().filter(|&c| c == '\'' || c == '"')?;
+        let start = self.pos + 1;
+        let len = self.chars[start..].iter().position(|&c| c == quote)?;
+        self.pos = start + len + 1;
+        Some(self.chars[start..start + len].iter().collect())
+    }
+
+    /// An optionally negative integer, if one comes next.
+    fn parse_integer(&mut self) -> Option<i64> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    fn parse_or(&mut self) -> Option<PathFilter> {
+        let mut filter = self.parse_and()?;
+        while self.eat_str("||") {
+            filter = PathFilter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        Some(filter)
+    }
+
+    fn parse_and(&mut self) -> Option<PathFilter> {
+        let mut filter = self.parse_condition()?;
+        while self.eat_str("&&") {
+            filter = PathFilter::And(Box::new(filter), Box::new(self.parse_condition()?));
+        }
+        Some(filter)
+    }
+
+    /// A parenthesised or negated filter, or a test of a path from `@`.
+    fn parse_condition(&mut self) -> Option<PathFilter> {
+        if self.eat('(') {
+            let filter = self.parse_or()?;
+            return if self.eat(')') { Some(filter) } else { None };
+        }
+        if self.eat('!') {
+            return Some(PathFilter::Not(Box::new(self.parse_condition()?)));
+        }
+        if !self.eat('@') {
+            return None;
+        }
+        let mut path = Vec::new();
+        loop {
+            if self.peek() == Some('.') {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return None;
+                }
+                path.push(PathSegment::Key(self.chars[start..self.pos].iter().collect()));
+            } else if self.peek() == Some('[') {
+                self.pos += 1;
+                let segment = match self.parse_quoted() {
+                    Some(key) => PathSegment::Key(key),
+                    None => PathSegment::Index(usize::try_from(self.parse_integer()?).ok()?),
+                };
+                if !self.eat(']') {
+                    return None;
+                }
+                path.push(segment);
+            } else {
+                break;
+            }
+        }
+        let ops = [
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::NotEq),
+            ("<=", BinaryOp::LtEq),
+            (">=", BinaryOp::GtEq),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ];
+        match ops.into_iter().find(|(text, _)| self.eat_str(text)) {
+            Some((_, op)) => Some(PathFilter::Compare(path, op, self.parse_literal()?)),
+            None => Some(PathFilter::Exists(path)),
+        }
+    }
+
+    /// A string, number, `true`, `false`, or `null` to compare with.
+    fn parse_literal(&mut self) -> Option<JsonValue> {
+        if let Some(s) = self.parse_quoted() {
+            return Some(JsonValue::String(s));
+        }
+        for (word, value) in [("true", JsonValue::Bool(true)), ("false", JsonValue::Bool(false)), ("null", JsonValue::Null)] {
+            if self.eat_str(word) {
+                return Some(value);
+            }
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse().ok().map(JsonValue::Number)
+    }
+}
+
+// ---- JSON_SET / JSON_INSERT / JSON_REPLACE / JSON_REMOVE / JSON_PATCH ----
+
+/// Whether `name` (upper case) is a function handled by [`eval_json_mutation`].
+pub(super) fn is_json_mutation(name: &str) -> bool {
+    matches!(name, "JSON_SET" | "JSON_INSERT" | "JSON_REPLACE" | "JSON_REMOVE" | "JSON_PATCH")
+}
+
+/// Parse a JSON document argument, or None for NULL.
+fn json_document(value: &Value) -> Result<Option<JsonValue>> {
+    let text = match value {
+        Value::Null => return Ok(None),
+        Value::Text(s) => s.clone(),
+        Value::Integer(_) | Value::Real(_) => value.to_string(),
+        Value::Blob(_) => return Err(HorizonError::InvalidSql("malformed JSON".into())),
+    };
+    JsonParser::parse(&text)
+        .map(Some)
+        .ok_or_else(|| HorizonError::InvalidSql("malformed JSON".into()))
+}
+
+fn path_error(path: &Value) -> HorizonError {
+    HorizonError::InvalidSql(format!("JSON path error near '{}'", path))
+}
+
+/// Evaluate the JSON mutation function `name` (upper case) on `args` and
+/// return the modified document as text.
+///
+/// `JSON_SET`, `JSON_INSERT` and `JSON_REPLACE` take the document followed
+/// by path/value pairs, applied left to right; `JSON_REMOVE` takes the
+/// document followed by paths; `JSON_PATCH(base, patch)` applies a merge
+/// patch. Values are stored the way `JSON_ARRAY` stores them, so text
+/// becomes a JSON string. A NULL document or path yields NULL.
+pub(super) fn eval_json_mutation(name: &str, args: &[Value]) -> Result<Value> {
+    if name == "JSON_PATCH" {
+        check_arity(name, args, 2..=2)?;
+        return Ok(match (json_document(&args[0])?, json_document(&args[1])?) {
+            (Some(mut base), Some(patch)) => {
+                base.merge_patch(&patch);
+                Value::Text(base.to_json_string())
+            }
+            _ => Value::Null,
+        });
+    }
+
+    if args.is_empty() || (name != "JSON_REMOVE" && args.len() % 2 == 0) {
+        let expected = if name == "JSON_REMOVE" { "at least 1" } else { "an odd number of" };
+        return Err(HorizonError::InvalidSql(format!(
+            "{}() takes {} arguments",
+            name.to_lowercase(),
+            expected
+        )));
+    }
+    let mut doc = match json_document(&args[0])? {
+        Some(doc) => doc,
+        None => return Ok(Value::Null),
+    };
+
+    if name == "JSON_REMOVE" {
+        for path in &args[1..] {
+            match path {
+                Value::Null => return Ok(Value::Null),
+                Value::Text(p) if p.trim() == "$" => return Ok(Value::Null),
+                Value::Text(p) if doc.remove_path(p) => {}
+                _ => return Err(path_error(path)),
+            }
+        }
+    } else {
+        let mode = match name {
+            "JSON_INSERT" => JsonSetMode::Insert,
+            "JSON_REPLACE" => JsonSetMode::Replace,
+            _ => JsonSetMode::Set,
+        };
+        for pair in args[1..].chunks(2) {
+            let value = sql_value_to_json(&pair[1]);
+            match &pair[0] {
+                Value::Null => return Ok(Value::Null),
+                Value::Text(p) if doc.set_path(p, value, mode) => {}
+                path => return Err(path_error(path)),
+            }
+        }
+    }
+    Ok(Value::Text(doc.to_json_string()))
+}
+
+// ---- json_each / json_tree ----
+
+/// Column names of the rows produced by `json_each` and `json_tree`.
+const JSON_EACH_COLUMNS: [&str; 8] =
+    ["key", "value", "type", "atom", "id", "parent", "fullkey", "path"];
+
+/// `json_each(json[, path])` and, when `recursive`, `json_tree(json[, path])`.
+///
+/// `json_each` yields one row per element of the array or member of the
+/// object at `path` (or a single row when that value is a scalar), while
+/// `json_tree` walks the whole value and yields one row per node, the
+/// starting node included. `id` numbers the nodes in walk order and
+/// `parent` holds the id of the enclosing node (NULL for `json_each`).
+/// A NULL document or a path that matches nothing produces no rows.
+pub(super) fn json_each_rows(args: &[Value], recursive: bool) -> Result<RowSet> {
+    let name = if recursive { "json_tree" } else { "json_each" };
+    if args.is_empty() || args.len() > 2 {
+        return Err(HorizonError::InvalidSql(format!("{} takes 1 or 2 arguments", name)));
+    }
+    let columns = JSON_EACH_COLUMNS.iter().map(|c| c.to_string()).collect();
+    let text = match &args[0] {
+        Value::Null => return Ok((columns, Vec::new())),
+        Value::Text(s) => s,
+        other => return Err(HorizonError::TypeError(format!("{} expects JSON text, got {}", name, other))),
+    };
+    let root = JsonParser::parse(text)
+        .ok_or_else(|| HorizonError::InvalidSql(format!("malformed JSON passed to {}", name)))?;
+    let path = match args.get(1) {
+        None => "$".to_string(),
+        Some(Value::Text(p)) => p.trim().to_string(),
+        Some(other) => {
+            return Err(HorizonError::TypeError(format!("{} path must be text, got {}", name, other)))
+        }
+    };
+    let start = match root.extract_path(&path) {
+        Some(v) => v,
+        None if parse_json_path(&path).is_none() => {
+            return Err(HorizonError::InvalidSql(format!("bad JSON path: {}", path)))
+        }
+        None => return Ok((columns, Vec::new())),
+    };
+
+    let mut walker = JsonWalker { rows: Vec::new(), next_id: 0, recursive };
+    if recursive {
+        let (parent_path, key) = split_json_path(&path);
+        walker.visit(start, key, Value::Null, &path, &parent_path);
+    } else if start.iter_array().is_some() || start.iter_object().is_some() {
+        walker.next_id = 1;
+        walker.visit_children(start, Value::Null, &path);
+    } else {
+        walker.visit(start, Value::Null, Value::Null, &path, &path);
+    }
+    Ok((columns, walker.rows))
+}
+
+/// Accumulates `json_each` / `json_tree` rows while walking a JSON value.
+struct JsonWalker {
+    rows: Vec<Vec<Value>>,
+    next_id: i64,
+    recursive: bool,
+}
+
+impl JsonWalker {
+    /// Emit the row for `node`, then its descendants when walking a tree.
+    fn visit(&mut self, node: &JsonValue, key: Value, parent: Value, fullkey: &str, path: &str) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let atom = match node {
+            JsonValue::Array(_) | JsonValue::Object(_) => Value::Null,
+            _ => json_value_to_sql(node),
+        };
+        self.rows.push(vec![
+            key,
+            json_value_to_sql(node),
+            Value::Text(node.json_type_name().to_string()),
+            atom,
+            Value::Integer(id),
+            parent,
+            Value::Text(fullkey.to_string()),
+            Value::Text(path.to_string()),
+        ]);
+        if self.recursive {
+            self.visit_children(node, Value::Integer(id), fullkey);
+        }
+    }
+
+    /// Emit a row for each element or member of the container `node`.
+    fn visit_children(&mut self, node: &JsonValue, parent: Value, fullkey: &str) {
+        if let Some(items) = node.iter_array() {
+            for (i, item) in items.enumerate() {
+                let child_key = format!("{}[{}]", fullkey, i);
+                self.visit(item, Value::Integer(i as i64), parent.clone(), &child_key, fullkey);
+            }
+        } else if let Some(pairs) = node.iter_object() {
+            for (k, v) in pairs {
+                let child_key = format!("{}{}", fullkey, key_path_segment(k));
+                self.visit(v, Value::Text(k.clone()), parent.clone(), &child_key, fullkey);
+            }
+        }
+    }
+}
+
+/// The path segment that selects member `key`: `.key` for plain
+/// identifiers, `["key"]` otherwise.
+fn key_path_segment(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        format!(".{}", key)
+    } else {
+        format!("[\"{}\"]", key)
+    }
+}
+
+/// Split a JSON path into the path of its parent container and the key of
+/// its last segment (NULL for the root `$`).
+fn split_json_path(path: &str) -> (String, Value) {
+    match parse_json_path(path).and_then(|mut segs| segs.pop()) {
+        Some(PathSegment::Index(i)) => {
+            let cut = path.rfind('[').unwrap_or(path.len());
+            (path[..cut].to_string(), Value::Integer(i as i64))
+        }
+        Some(PathSegment::Key(k)) => {
+            let suffix = key_path_segment(&k);
+            let cut = if path.ends_with(&suffix) {
+                path.len() - suffix.len()
+            } else {
+                path.rfind(['.', '[']).unwrap_or(path.len())
+            };
+            (path[..cut].to_string(), Value::Text(k))
+        }
+        None => (path.to_string(), Value::Null),
+    }
+}
+
 /// A simple hand-written JSON parser.
 pub struct JsonParser {
     chars: Vec<char>,
@@ -545,6 +1311,38 @@ mod tests {
         assert_eq!(json.extract_path("$.c"), None);
     }
 
+    #[test]
+    fn test_query_path() {
+        let json = JsonParser::parse(
+            r#"{"people": [{"name": "Ann", "age": 34}, {"name": "Bob", "age": 12}, {"name": "Cy", "age": 19}]}"#,
+        )
+        .unwrap();
+        let names = |path: &str| -> Vec<String> {
+            json.query_path(path).unwrap().into_iter().map(JsonValue::to_json_string).collect()
+        };
+        assert_eq!(names("$.people[*].name"), [r#""Ann""#, r#""Bob""#, r#""Cy""#]);
+        assert_eq!(names("$..age"), ["34", "12", "19"]);
+        assert_eq!(names("$.people[0:2].age"), ["34", "12"]);
+        assert_eq!(names("$.people[-1:].name"), [r#""Cy""#]);
+        assert_eq!(names("$.people[-2].name"), [r#""Bob""#]);
+        assert_eq!(names("$.people[?(@.age > 18)].name"), [r#""Ann""#, r#""Cy""#]);
+        assert_eq!(names("$.people[?(@.age > 18 && @.name != 'Ann')].age"), ["19"]);
+        assert_eq!(names("$.people[?(@.name == \"Bob\" || !(@.age < 30))].name"), [r#""Ann""#, r#""Bob""#]);
+        assert_eq!(names("$.people[?(@.missing)]"), Vec::<String>::new());
+        assert!(json.query_path("$.people[?(@.age >)]").is_none());
+        assert!(json.query_path("people").is_none());
+    }
+
+    #[test]
+    fn test_json_extract_wraps_multiple_matches_in_an_array() {
+        assert_eq!(json_extract("[1,2,3]", "$[*]"), Value::Text("[1,2,3]".into()));
+        assert_eq!(json_extract(r#"{"a":{"b":1}}"#, "$..b"), Value::Text("[1]".into()));
+        assert_eq!(json_extract(r#"{"a":{"b":1}}"#, "$.a.b"), Value::Integer(1));
+        assert_eq!(json_extract("[1,2,3]", "$[-1]"), Value::Integer(3));
+        assert_eq!(json_extract("[1,2,3]", "$[5]"), Value::Null);
+        assert_eq!(json_extract("[]", "$[*]"), Value::Text("[]".into()));
+    }
+
     #[test]
     fn test_json_type_name() {
         assert_eq!(JsonValue::Null.json_type_name(), "null");
@@ -585,6 +1383,72 @@ mod tests {
         assert_eq!(JsonParser::parse("{foo: 1}"), None);
     }
 
+    #[test]
+    fn test_json_each_rows() {
+        let doc = Value::Text(r#"{"a": [1, 2], "b c": "x"}"#.into());
+        let (columns, rows) = json_each_rows(std::slice::from_ref(&doc), false).unwrap();
+        assert_eq!(columns, JSON_EACH_COLUMNS);
+        let fullkeys: Vec<&Value> = rows.iter().map(|r| &r[6]).collect();
+        assert_eq!(fullkeys, [&Value::Text("$.a".into()), &Value::Text(r#"$["b c"]"#.into())]);
+
+        // json_tree starting below the root keeps the full path
+        let (_, rows) = json_each_rows(&[doc, Value::Text("$.a".into())], true).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0][0], Value::Text("a".into()));
+        assert_eq!(rows[0][7], Value::Text("$".into()));
+        assert_eq!(rows[2][5], Value::Integer(0));
+        assert_eq!(rows[2][6], Value::Text("$.a[1]".into()));
+
+        // Scalars produce a single row; NULL produces none
+        let (_, rows) = json_each_rows(&[Value::Text("7".into())], false).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][0], Value::Null);
+        assert!(json_each_rows(&[Value::Null], false).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn test_set_path_modes() {
+        let mut doc = JsonParser::parse(r#"{"a": 1, "b": [1, 2]}"#).unwrap();
+        assert!(doc.set_path("$.a", JsonValue::Number(2.0), JsonSetMode::Insert));
+        assert!(doc.set_path("$.c", JsonValue::Number(3.0), JsonSetMode::Replace));
+        assert_eq!(doc.to_json_string(), r#"{"a":1,"b":[1,2]}"#);
+
+        assert!(doc.set_path("$.a", JsonValue::Number(2.0), JsonSetMode::Replace));
+        assert!(doc.set_path("$.b[2]", JsonValue::Number(3.0), JsonSetMode::Insert));
+        assert!(doc.set_path("$.x.y", JsonValue::Bool(true), JsonSetMode::Set));
+        assert!(doc.set_path("$.z[0]", JsonValue::Null, JsonSetMode::Set));
+        assert_eq!(doc.to_json_string(), r#"{"a":2,"b":[1,2,3],"x":{"y":true}}"#);
+        assert!(!doc.set_path("a", JsonValue::Null, JsonSetMode::Set));
+    }
+
+    #[test]
+    fn test_remove_path() {
+        let mut doc = JsonParser::parse(r#"{"a": [1, 2, 3], "b": {"c": 1}}"#).unwrap();
+        assert!(doc.remove_path("$.a[1]"));
+        assert!(doc.remove_path("$.b.c"));
+        assert!(doc.remove_path("$.missing.key"));
+        assert_eq!(doc.to_json_string(), r#"{"a":[1,3],"b":{}}"#);
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // Examples from RFC 7396, appendix A
+        let cases = [
+            (r#"{"a":"b"}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+            (r#"{"a":"b"}"#, r#"{"a":null}"#, r#"{}"#),
+            (r#"{"a":{"b":"c"}}"#, r#"{"a":{"b":"d","c":null}}"#, r#"{"a":{"b":"d"}}"#),
+            (r#"{"a":[{"b":"c"}]}"#, r#"{"a":[1]}"#, r#"{"a":[1]}"#),
+            (r#"["a","b"]"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+            (r#"{}"#, r#"{"a":{"bb":{"ccc":null}}}"#, r#"{"a":{"bb":{}}}"#),
+            (r#"{"a":"foo"}"#, "null", "null"),
+        ];
+        for (base, patch, expected) in cases {
+            let mut doc = JsonParser::parse(base).unwrap();
+            doc.merge_patch(&JsonParser::parse(patch).unwrap());
+            assert_eq!(doc.to_json_string(), expected, "{} patched with {}", base, patch);
+        }
+    }
+
     #[test]
     fn test_array_length() {
         let json = JsonParser::parse("[1, 2, 3, 4]").unwrap();
@@ -0,0 +1,184 @@
+//! Numeric scalar functions for Horizon DB.
+//!
+//! The caller evaluates the arguments; this module only sees their values.
+//! Integers (and numeric text) are accepted wherever a real is expected,
+//! a NULL or non-numeric argument yields NULL, and so does an input outside
+//! a function's domain, such as `SQRT(-1)` or `LOG(0)`. Angles are in
+//! radians.
+
+use super::check_arity;
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+
+/// Whether `name` (upper case) is a function handled by [`eval_math_function`].
+pub(super) fn is_math_function(name: &str) -> bool {
+    matches!(
+        name,
+        "CEIL" | "CEILING" | "FLOOR" | "TRUNC" | "SIGN" | "SQRT" | "POW" | "POWER"
+            | "LN" | "LOG" | "LOG2" | "LOG10" | "MOD" | "SIN" | "COS" | "TAN" | "ASIN"
+            | "ACOS" | "ATAN" | "ATAN2" | "RADIANS" | "DEGREES" | "PI"
+    )
+}
+
+/// A numeric argument, keeping integers exact.
+#[derive(Clone, Copy)]
+enum Num {
+    Int(i64),
+    Real(f64),
+}
+
+impl Num {
+    fn from_value(value: &Value) -> Option<Num> {
+        match value {
+            Value::Integer(i) => Some(Num::Int(*i)),
+            Value::Real(r) => Some(Num::Real(*r)),
+            Value::Text(s) => {
+                let s = s.trim();
+                s.parse::<i64>()
+                    .map(Num::Int)
+                    .or_else(|_| s.parse::<f64>().map(Num::Real))
+                    .ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Num::Int(i) => i as f64,
+            Num::Real(r) => r,
+        }
+    }
+}
+
+/// A real result, with NaN (an undefined result) reported as NULL.
+fn real(r: f64) -> Value {
+    if r.is_nan() {
+        Value::Null
+    } else {
+        Value::Real(r)
+    }
+}
+
+/// Evaluate the math function `name` (upper case) on `args`.
+pub(super) fn eval_math_function(name: &str, args: &[Value]) -> Result<Value> {
+    let arity = match name {
+        "POW" | "POWER" | "MOD" | "ATAN2" => 2..=2,
+        "LOG" => 1..=2,
+        "PI" => 0..=0,
+        _ => 1..=1,
+    };
+    check_arity(name, args, arity)?;
+    if name == "PI" {
+        return Ok(Value::Real(std::f64::consts::PI));
+    }
+    let nums: Option<Vec<Num>> = args.iter().map(Num::from_value).collect();
+    let nums = match nums {
+        Some(nums) => nums,
+        None => return Ok(Value::Null),
+    };
+    let x = nums[0];
+
+    Ok(match name {
+        "CEIL" | "CEILING" => match x {
+            Num::Int(i) => Value::Integer(i),
+            Num::Real(r) => Value::Real(r.ceil()),
+        },
+        "FLOOR" => match x {
+            Num::Int(i) => Value::Integer(i),
+            Num::Real(r) => Value::Real(r.floor()),
+        },
+        "TRUNC" => match x {
+            Num::Int(i) => Value::Integer(i),
+            Num::Real(r) => Value::Real(r.trunc()),
+        },
+        "SIGN" => match x {
+            Num::Int(i) => Value::Integer(i.signum()),
+            Num::Real(r) if r.is_nan() => Value::Null,
+            Num::Real(r) => Value::Integer(if r > 0.0 { 1 } else if r < 0.0 { -1 } else { 0 }),
+        },
+        "SQRT" => real(x.as_f64().sqrt()),
+        "POW" | "POWER" => real(x.as_f64().powf(nums[1].as_f64())),
+        "LN" | "LOG" | "LOG2" | "LOG10" => {
+            // LOG(b, x) is the base-b logarithm of x
+            let (base, x) = match nums.get(1) {
+                Some(arg) => (Some(x.as_f64()), arg.as_f64()),
+                None => (None, x.as_f64()),
+            };
+            if x <= 0.0 {
+                return Ok(Value::Null);
+            }
+            match (name, base) {
+                (_, Some(b)) if b <= 0.0 || b == 1.0 => Value::Null,
+                (_, Some(b)) => real(x.ln() / b.ln()),
+                ("LOG2", _) => real(x.log2()),
+                ("LOG10", _) => real(x.log10()),
+                _ => real(x.ln()),
+            }
+        }
+        "MOD" => match (x, nums[1]) {
+            (Num::Int(a), Num::Int(b)) if b != 0 => Value::Integer(a.wrapping_rem(b)),
+            (_, b) if b.as_f64() == 0.0 => Value::Null,
+            (a, b) => real(a.as_f64() % b.as_f64()),
+        },
+        "SIN" => real(x.as_f64().sin()),
+        "COS" => real(x.as_f64().cos()),
+        "TAN" => real(x.as_f64().tan()),
+        "ASIN" => real(x.as_f64().asin()),
+        "ACOS" => real(x.as_f64().acos()),
+        "ATAN" => real(x.as_f64().atan()),
+        // ATAN2(y, x)
+        "ATAN2" => real(x.as_f64().atan2(nums[1].as_f64())),
+        "RADIANS" => real(x.as_f64().to_radians()),
+        "DEGREES" => real(x.as_f64().to_degrees()),
+        _ => return Err(HorizonError::NotImplemented(format!("function: {}", name))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        eval_math_function(name, args).unwrap()
+    }
+
+    #[test]
+    fn rounding_keeps_integers_exact() {
+        assert_eq!(call("CEIL", &[Value::Real(1.1)]), Value::Real(2.0));
+        assert_eq!(call("FLOOR", &[Value::Real(-1.1)]), Value::Real(-2.0));
+        assert_eq!(call("TRUNC", &[Value::Real(-1.9)]), Value::Real(-1.0));
+        assert_eq!(call("CEILING", &[Value::Integer(7)]), Value::Integer(7));
+        assert_eq!(call("SIGN", &[Value::Real(-0.5)]), Value::Integer(-1));
+        assert_eq!(call("SIGN", &[Value::Text("12".into())]), Value::Integer(1));
+    }
+
+    #[test]
+    fn domain_errors_are_null() {
+        assert_eq!(call("SQRT", &[Value::Integer(-1)]), Value::Null);
+        assert_eq!(call("LOG", &[Value::Integer(0)]), Value::Null);
+        assert_eq!(call("LOG", &[Value::Integer(1), Value::Integer(8)]), Value::Null);
+        assert_eq!(call("MOD", &[Value::Integer(5), Value::Integer(0)]), Value::Null);
+        assert_eq!(call("POW", &[Value::Integer(-8), Value::Real(0.5)]), Value::Null);
+        assert_eq!(call("SQRT", &[Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn trigonometry() {
+        use std::f64::consts::PI;
+        assert_eq!(call("SIN", &[Value::Integer(0)]), Value::Real(0.0));
+        assert_eq!(call("COS", &[Value::Integer(0)]), Value::Real(1.0));
+        assert_eq!(call("ATAN2", &[Value::Integer(1), Value::Integer(1)]), Value::Real(PI / 4.0));
+        assert_eq!(call("DEGREES", &[call("PI", &[])]), Value::Real(180.0));
+        assert_eq!(call("RADIANS", &[Value::Integer(180)]), Value::Real(PI));
+        assert_eq!(call("ASIN", &[Value::Real(2.0)]), Value::Null);
+        assert_eq!(call("ACOS", &[Value::Integer(-2)]), Value::Null);
+    }
+
+    #[test]
+    fn wrong_argument_count_is_an_error() {
+        assert!(eval_math_function("POW", &[Value::Integer(2)]).is_err());
+        assert!(eval_math_function("SQRT", &[]).is_err());
+        assert!(eval_math_function("PI", &[Value::Integer(1)]).is_err());
+    }
+}
@@ -3,26 +3,55 @@
 //! Executes SQL statements against the storage layer. This module bridges
 //! the SQL parser/planner with the B+Tree storage, catalog, and MVCC layers.
 
+pub mod attach;
+pub mod collation;
 pub mod json;
+mod crypto;
+pub mod dump;
+mod math;
+mod porter;
 pub mod rtree;
+pub mod join;
+pub mod sort;
+mod spill;
 pub mod fts5;
+mod grouping;
+mod foreign_keys;
+mod integrity;
+pub mod params;
+pub mod query_cache;
+pub mod stream;
+mod strings;
+mod table_functions;
+mod vacuum;
 mod views_triggers;
 
-use std::collections::HashMap;
+pub(crate) use views_triggers::expr_to_sql;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
 use crate::btree::BTree;
 use crate::buffer::BufferPool;
+use crate::cancel;
 use crate::catalog::{Catalog, ColumnInfo, TableInfo, ViewInfo, TriggerInfo, TriggerTimingKind, TriggerEventKind};
 use crate::error::{HorizonError, Result};
-use crate::mvcc::{TransactionManager, UndoEntry};
-use crate::planner::{LogicalPlan, plan_statement};
+use crate::mvcc::{snapshot, TransactionManager, UndoEntry};
+use crate::planner::{fold_constants, literal_truth, LogicalPlan, plan_from, plan_statement, rewrite_fulltext_matches, ScanMethod};
 use crate::sql::ast::*;
 use crate::sql::parser::Parser;
 use crate::types::{DataType, Value, determine_affinity};
 use crate::{QueryResult, Row};
 
 /// Stored CTE data: column names and row values.
-type CteStore = HashMap<String, (Vec<String>, Vec<Vec<Value>>)>;
+type CteStore = HashMap<String, RowSet>;
+
+/// Column names paired with the materialized rows of an intermediate result.
+type RowSet = (Vec<String>, Vec<Vec<Value>>);
+
+/// A grouping key paired with the rows that share it.
+type RowGroup = (Vec<Value>, Vec<Vec<Value>>);
 
 /// Execute a non-query statement (DDL, INSERT, UPDATE, DELETE).
 /// Returns the number of affected rows.
@@ -39,14 +68,28 @@ pub fn execute_statement(
             if catalog.rtree_exists(&ins.table) {
                 return rtree::execute_rtree_insert(ins, pool, catalog);
             }
-            execute_insert(ins, pool, catalog, txn_mgr)
+            if let Some(view) = catalog.get_view(&ins.table).cloned() {
+                return views_triggers::execute_view_write(stmt, &view, pool, catalog, txn_mgr);
+            }
+            let result = execute_insert(ins, pool, catalog, txn_mgr);
+            roll_back_deadlock_victim(result, pool, catalog, txn_mgr)
+        }
+        Statement::Update(upd) => {
+            if let Some(view) = catalog.get_view(&upd.table).cloned() {
+                return views_triggers::execute_view_write(stmt, &view, pool, catalog, txn_mgr);
+            }
+            let result = execute_update(upd, pool, catalog, txn_mgr);
+            roll_back_deadlock_victim(result, pool, catalog, txn_mgr)
         }
-        Statement::Update(upd) => execute_update(upd, pool, catalog, txn_mgr),
         Statement::Delete(del) => {
             if catalog.rtree_exists(&del.table) {
                 return rtree::execute_rtree_delete(del, pool, catalog);
             }
-            execute_delete(del, pool, catalog, txn_mgr)
+            if let Some(view) = catalog.get_view(&del.table).cloned() {
+                return views_triggers::execute_view_write(stmt, &view, pool, catalog, txn_mgr);
+            }
+            let result = execute_delete(del, pool, catalog, txn_mgr);
+            roll_back_deadlock_victim(result, pool, catalog, txn_mgr)
         }
         Statement::CreateIndex(ci) => execute_create_index(ci, pool, catalog),
         Statement::DropIndex(di) => execute_drop_index(di, pool, catalog),
@@ -61,14 +104,28 @@ pub fn execute_statement(
         Statement::Rollback => {
             execute_rollback(pool, catalog, txn_mgr)
         }
+        Statement::Savepoint(name) => {
+            txn_mgr.savepoint(name)?;
+            Ok(0)
+        }
+        Statement::ReleaseSavepoint(name) => {
+            txn_mgr.release_savepoint(name)?;
+            Ok(0)
+        }
+        Statement::RollbackToSavepoint(name) => {
+            let undo_entries = txn_mgr.rollback_to_savepoint(name)?;
+            apply_undo(undo_entries, pool, catalog)?;
+            Ok(0)
+        }
         Statement::AlterTable(alter) => execute_alter_table(alter, pool, catalog),
         Statement::Explain(_) => {
             // EXPLAIN returns rows; handled in Database::query()
             Err(HorizonError::Internal("use query() for EXPLAIN statements".into()))
         }
-        Statement::Pragma(_) => {
-            // PRAGMA returns rows; handled in Database::query()
-            Err(HorizonError::Internal("use query() for PRAGMA statements".into()))
+        Statement::Pragma(pragma) => {
+            // Setting a PRAGMA is allowed here; its result rows are dropped.
+            execute_pragma(pragma, pool, catalog)?;
+            Ok(0)
         }
         Statement::CreateView(cv) => views_triggers::execute_create_view(cv, catalog),
         Statement::DropView(dv) => views_triggers::execute_drop_view(dv, catalog),
@@ -85,14 +142,16 @@ pub fn execute_statement(
             catalog.detach_database(&detach.schema_name)?;
             Ok(0)
         }
-        Statement::Vacuum => execute_vacuum(pool, catalog),
+        Statement::Vacuum(None) => vacuum::vacuum(pool, catalog).map(|()| 0),
+        Statement::Vacuum(Some(path)) => vacuum::vacuum_into(Path::new(path), pool, catalog).map(|()| 0),
+        Statement::Analyze(target) => execute_analyze(target.as_deref(), pool, catalog),
         Statement::ExplainQueryPlan(_) => {
             // EXPLAIN QUERY PLAN returns rows; handled in Database::query()
             Err(HorizonError::Internal("use query() for EXPLAIN QUERY PLAN statements".into()))
         }
         Statement::CreateVirtualTable(cvt) => {
             match cvt.module_name.to_lowercase().as_str() {
-                "fts5" => execute_create_fts5_table(cvt),
+                "fts5" => execute_create_fts5_table(cvt, catalog),
                 "rtree" => rtree::execute_create_virtual_table_rtree(cvt, pool, catalog),
                 _ => Err(HorizonError::InvalidSql(format!(
                     "unknown virtual table module: {}",
@@ -111,23 +170,73 @@ pub fn execute_query(
     txn_mgr: &mut TransactionManager,
 ) -> Result<QueryResult> {
     match stmt {
-        Statement::Select(select) => execute_select(select, pool, catalog),
+        stmt if is_read_only(stmt) => execute_read_query(stmt, pool, catalog),
         Statement::Pragma(pragma) => execute_pragma(pragma, pool, catalog),
-        Statement::Explain(inner) => execute_explain(inner, catalog),
-        Statement::ExplainQueryPlan(inner) => execute_explain_query_plan(inner, catalog),
         Statement::Insert(ins) if ins.returning.is_some() => {
-            execute_insert_returning(ins, pool, catalog, txn_mgr)
+            let result = execute_insert_returning(ins, pool, catalog, txn_mgr);
+            roll_back_deadlock_victim(result, pool, catalog, txn_mgr)
         }
         Statement::Update(upd) if upd.returning.is_some() => {
-            execute_update_returning(upd, pool, catalog, txn_mgr)
+            let result = execute_update_returning(upd, pool, catalog, txn_mgr);
+            roll_back_deadlock_victim(result, pool, catalog, txn_mgr)
         }
         Statement::Delete(del) if del.returning.is_some() => {
-            execute_delete_returning(del, pool, catalog, txn_mgr)
+            let result = execute_delete_returning(del, pool, catalog, txn_mgr);
+            roll_back_deadlock_victim(result, pool, catalog, txn_mgr)
         }
         _ => Err(HorizonError::Internal("execute_query requires a SELECT, PRAGMA, EXPLAIN, or RETURNING statement".into())),
     }
 }
 
+/// Whether a statement only reads the database, so it can be executed
+/// through shared borrows by [`execute_read_query`].
+pub fn is_read_only(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::Select(_) | Statement::Explain(_) | Statement::ExplainQueryPlan(_)
+    )
+}
+
+/// Whether executing `stmt` can change the database file.
+///
+/// Queries, transaction control, and PRAGMAs that only read or change
+/// per-connection settings do not.
+pub fn modifies_database(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Select(_)
+        | Statement::Explain(_)
+        | Statement::ExplainQueryPlan(_)
+        | Statement::Begin
+        | Statement::Commit
+        | Statement::Rollback
+        | Statement::Savepoint(_)
+        | Statement::ReleaseSavepoint(_)
+        | Statement::RollbackToSavepoint(_)
+        | Statement::AttachDatabase(_)
+        | Statement::DetachDatabase(_)
+        | Statement::Vacuum(Some(_)) => false,
+        Statement::Pragma(pragma) => match pragma.name.to_lowercase().as_str() {
+            "user_version" | "journal_mode" => pragma.value.is_some(),
+            "wal_checkpoint" => true,
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+/// Execute a read-only query (see [`is_read_only`]).
+///
+/// Only shared borrows of the buffer pool and catalog are needed, so any
+/// number of these may run at once.
+pub fn execute_read_query(stmt: &Statement, pool: &BufferPool, catalog: &Catalog) -> Result<QueryResult> {
+    match stmt {
+        Statement::Select(select) => execute_select(select, pool, catalog),
+        Statement::Explain(inner) => execute_explain(inner, catalog),
+        Statement::ExplainQueryPlan(inner) => execute_explain_query_plan(inner, catalog),
+        _ => Err(HorizonError::Internal("execute_read_query requires a SELECT or EXPLAIN statement".into())),
+    }
+}
+
 /// Check whether a statement has a RETURNING clause.
 pub fn has_returning(stmt: &Statement) -> bool {
     match stmt {
@@ -179,21 +288,65 @@ fn execute_create_table(
             autoincrement: col_def.autoincrement,
             not_null: col_def.not_null || col_def.primary_key,
             unique: col_def.unique || col_def.primary_key,
-            default_value: col_def.default.as_ref().map(|e| eval_const_expr(e)),
+            default_value: col_def.default.as_ref().map(eval_const_expr),
             position: i,
             generated_expr: gen_expr,
             is_stored: gen_stored,
+            check_expr: col_def.check.clone(),
         });
     }
 
-    let table = TableInfo {
+    // A table-level PRIMARY KEY (a, b, ...) marks its columns as key columns
+    let mut pk_columns: Vec<usize> = pk_column.into_iter().collect();
+    for constraint in &ct.constraints {
+        if let TableConstraint::PrimaryKey(names) = constraint {
+            if !pk_columns.is_empty() {
+                return Err(HorizonError::InvalidSql(format!(
+                    "table {} has more than one primary key", ct.name
+                )));
+            }
+            for name in names {
+                let idx = columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| HorizonError::ColumnNotFound(format!("{}.{}", ct.name, name)))?;
+                columns[idx].primary_key = true;
+                columns[idx].not_null = true;
+                pk_columns.push(idx);
+            }
+            if let [idx] = pk_columns[..] {
+                columns[idx].unique = true;
+                pk_column = Some(idx);
+            }
+        }
+    }
+
+    let mut table = TableInfo {
         name: ct.name.clone(),
         columns,
         root_page,
         next_rowid: 1,
         pk_column,
+        pk_columns,
+        checks: Vec::new(),
+        foreign_keys: Vec::new(),
     };
 
+    for col_def in &ct.columns {
+        if let Some(ref references) = col_def.references {
+            let fk = foreign_keys::foreign_key_info(&table, vec![col_def.name.clone()], references)?;
+            table.foreign_keys.push(fk);
+        }
+    }
+    for constraint in &ct.constraints {
+        match constraint {
+            TableConstraint::PrimaryKey(_) => {}
+            TableConstraint::Check(expr) => table.checks.push(expr.clone()),
+            TableConstraint::ForeignKey { columns, references } => {
+                let fk = foreign_keys::foreign_key_info(&table, columns.clone(), references)?;
+                table.foreign_keys.push(fk);
+            }
+        }
+    }
+
     catalog.create_table(pool, table)?;
     Ok(0)
 }
@@ -202,11 +355,30 @@ fn execute_create_table(
 
 fn execute_create_fts5_table(
     cvt: &CreateVirtualTableStatement,
+    catalog: &Catalog,
 ) -> Result<usize> {
     if cvt.if_not_exists && fts5::fts5_table_exists(&cvt.name) {
         return Ok(0);
     }
-    fts5::create_fts5_table(&cvt.name, cvt.module_args.clone())?;
+    // The arguments are the columns, and the option `tokenize = name`
+    let mut columns = Vec::new();
+    let mut tokenizer: fts5::SharedTokenizer = Arc::new(fts5::SimpleTokenizer);
+    for arg in &cvt.module_args {
+        match arg.split_once('=') {
+            None => columns.push(arg.clone()),
+            Some((option, name)) if option.eq_ignore_ascii_case("tokenize") => {
+                let name = name.trim();
+                tokenizer = catalog
+                    .fts5_tokenizer(name)
+                    .or_else(|| fts5::builtin_tokenizer(name))
+                    .ok_or_else(|| HorizonError::InvalidSql(format!("no such FTS5 tokenizer: {}", name)))?;
+            }
+            Some((option, _)) => {
+                return Err(HorizonError::InvalidSql(format!("unknown FTS5 option: {}", option)));
+            }
+        }
+    }
+    fts5::create_fts5_table_with_tokenizer(&cvt.name, columns, tokenizer)?;
     Ok(0)
 }
 
@@ -225,7 +397,12 @@ fn execute_drop_table(
     if dt.if_exists && !catalog.table_exists(&dt.name) {
         return Ok(0);
     }
+    let fulltext = catalog.get_fulltext_index(&dt.name).map(|f| f.fts_table.clone());
     catalog.drop_table(pool, &dt.name)?;
+    // The shadow table of a full-text index goes with its table
+    if let Some(fts_table) = fulltext {
+        fts5::fts5_drop_table(&fts_table)?;
+    }
     Ok(0)
 }
 
@@ -235,7 +412,7 @@ fn execute_drop_table(
 /// expressions. This must be called after deserializing a row from disk
 /// and before the row is used in expressions or projections.
 fn fill_virtual_columns(
-    row: &mut Vec<Value>,
+    row: &mut [Value],
     table: &TableInfo,
 ) -> Result<()> {
     for (i, col) in table.columns.iter().enumerate() {
@@ -257,23 +434,120 @@ fn table_has_virtual_columns(table: &TableInfo) -> bool {
     table.columns.iter().any(|c| c.generated_expr.is_some() && !c.is_stored)
 }
 
-// ---- VACUUM ----
+// ---- CHECK Constraints ----
+
+/// Evaluate the column- and table-level CHECK constraints of `table`
+/// against a row about to be written. A check that evaluates to NULL is
+/// satisfied, as in standard SQL.
+fn enforce_check_constraints(table: &TableInfo, row: &[Value]) -> Result<()> {
+    let mut checks = table
+        .columns
+        .iter()
+        .filter_map(|c| c.check_expr.as_ref())
+        .chain(&table.checks)
+        .peekable();
+    if checks.peek().is_none() {
+        return Ok(());
+    }
+    let mut row = row.to_vec();
+    if table_has_virtual_columns(table) {
+        fill_virtual_columns(&mut row, table)?;
+    }
+    for check in checks {
+        let result = eval_expr(check, &row, &table.columns, table)?;
+        if !result.is_null() && !result.to_bool() {
+            return Err(HorizonError::ConstraintViolation(format!(
+                "CHECK constraint failed: {}: {}",
+                table.name,
+                expr_to_sql(check)
+            )));
+        }
+    }
+    Ok(())
+}
 
-/// Execute a VACUUM command.
-///
-/// This implementation flushes all dirty pages to disk, effectively
-/// compacting the in-memory representation. A full page-level
-/// compaction would require rewriting the entire database file, which
-/// is deferred to a future release.
-fn execute_vacuum(
+/// Record `tree`'s current root page in the catalog entry for `table_name`
+/// if a split moved it, so nested statements (foreign key actions) see the
+/// rows written so far.
+fn sync_table_root(
+    table_name: &str,
+    tree: &BTree,
     pool: &mut BufferPool,
-    _catalog: &mut Catalog,
-) -> Result<usize> {
-    pool.flush_all()?;
+    catalog: &mut Catalog,
+) -> Result<()> {
+    let current = catalog.get_table(table_name)?;
+    if current.root_page != tree.root_page() {
+        let mut updated = current.clone();
+        updated.root_page = tree.root_page();
+        catalog.update_table_meta(pool, table_name, &updated)?;
+    }
+    Ok(())
+}
+
+// ---- ANALYZE ----
+
+/// Execute `ANALYZE [name]`: gather column statistics from the indexes of
+/// every table, of the named table, or from the named index alone.
+///
+/// Each index that keys on plain columns contributes statistics for its
+/// leading column: the number of rows and of distinct values. Partial
+/// indexes are skipped, since they do not cover every row.
+fn execute_analyze(target: Option<&str>, pool: &BufferPool, catalog: &mut Catalog) -> Result<usize> {
+    let tables: Vec<String> = match target {
+        None => catalog.list_tables().into_iter().map(str::to_string).collect(),
+        Some(name) if catalog.table_exists(name) => vec![name.to_string()],
+        Some(name) => catalog.get_index(name).map(|idx| vec![idx.table_name.clone()]).unwrap_or_default(),
+    };
+    for table in &tables {
+        catalog.query_counters().record_analyze(table);
+    }
+    let indexes: Vec<crate::catalog::IndexInfo> = match target {
+        None => catalog.list_tables().into_iter()
+            .flat_map(|t| catalog.get_indexes_for_table(t))
+            .cloned()
+            .collect(),
+        Some(name) if catalog.table_exists(name) => {
+            catalog.get_indexes_for_table(name).into_iter().cloned().collect()
+        }
+        Some(name) => vec![catalog.get_index(name)
+            .map_err(|_| HorizonError::TableNotFound(name.into()))?
+            .clone()],
+    };
+
+    for index in indexes {
+        if index.predicate.is_some() || index.key_expr(0).is_some() {
+            continue;
+        }
+        let (row_count, distinct) = crate::catalog::stats::index_prefix_counts(pool, index.root_page, 1)?;
+        let stats = crate::catalog::stats::ColumnStats { row_count, distinct_values: distinct[0] };
+        catalog.set_column_stats(&index.table_name, &index.columns[0], stats);
+    }
     Ok(0)
 }
 
 
+/// Execute `PRAGMA optimize`: analyze each table with an index that has
+/// been scanned more than [`STALE_SCAN_FACTOR`](crate::catalog::stats::STALE_SCAN_FACTOR)
+/// times as often as when it was last analyzed, or at all if it never was,
+/// returning a row naming each table analyzed.
+///
+/// Tables without indexes are left alone, since `ANALYZE` gathers its
+/// statistics from indexes.
+fn execute_optimize(pool: &BufferPool, catalog: &mut Catalog) -> Result<QueryResult> {
+    let mut stale: Vec<String> = catalog.list_tables().into_iter()
+        .filter(|t| !catalog.get_indexes_for_table(t).is_empty() && catalog.query_counters().is_stale(t))
+        .map(str::to_string)
+        .collect();
+    stale.sort();
+    for table in &stale {
+        execute_analyze(Some(table), pool, catalog)?;
+    }
+    let columns = Arc::new(vec!["optimize".to_string()]);
+    let rows = stale.into_iter()
+        .map(|table| Row { columns: columns.clone(), values: vec![Value::Text(table)] })
+        .collect();
+    Ok(QueryResult { columns, rows })
+}
 
 // ---- FTS5 SELECT ----
 
@@ -281,26 +555,22 @@ fn execute_fts5_select(
     select: &SelectStatement,
     table_name: &str,
     table_fn_args: Option<&Vec<Expr>>,
-    _pool: &mut BufferPool,
-    _catalog: &mut Catalog,
+    _pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let fts_columns = fts5::fts5_get_columns(table_name)?;
 
     // Determine the search query
-    let query_text: Option<String> = if let Some(args) = table_fn_args {
+    let search: Option<Fts5Match> = if let Some(args) = table_fn_args {
         // Table function syntax: FROM table('query')
-        if let Some(first_arg) = args.first() {
-            if let Expr::Literal(LiteralValue::String(s)) = first_arg {
-                Some(s.clone())
-            } else {
-                None
-            }
+        if let Some(Expr::Literal(LiteralValue::String(s))) = args.first() {
+            Some(Fts5Match { column: None, query: s.clone() })
         } else {
             None
         }
     } else if let Some(ref where_clause) = select.where_clause {
-        // MATCH syntax: WHERE table MATCH 'query'
-        extract_fts5_match_query(where_clause, table_name)
+        // MATCH syntax: WHERE table MATCH 'query' or WHERE column MATCH 'query'
+        extract_fts5_match_query(where_clause, table_name, &fts_columns)
     } else {
         None
     };
@@ -324,10 +594,10 @@ fn execute_fts5_select(
         }
     });
 
-    let query_for_fns = query_text.clone().unwrap_or_default();
+    let query_for_fns = search.as_ref().map(|s| s.query.clone()).unwrap_or_default();
 
-    let rows_data: Vec<(i64, Vec<Value>, f64)> = if let Some(ref q) = query_text {
-        fts5::fts5_query(table_name, q)?
+    let rows_data: Vec<(i64, Vec<Value>, f64)> = if let Some(ref search) = search {
+        search.rows(table_name)?
     } else {
         // Full scan
         let all = fts5::fts5_scan_all(table_name)?;
@@ -405,7 +675,7 @@ fn execute_fts5_select(
 
     // Apply ORDER BY
     if !select.order_by.is_empty() {
-        sort_rows_dynamic(&mut rows, &select.order_by, &col_names)?;
+        sort_rows_dynamic(&mut rows, &select.order_by, &col_names, catalog)?;
     }
 
     // Apply DISTINCT
@@ -537,7 +807,7 @@ fn eval_fts5_expr(
         Expr::BinaryOp { left, op, right } => {
             let l = eval_fts5_expr(left, table_name, rowid, doc_values, fts_columns, bm25_score, query)?;
             let r = eval_fts5_expr(right, table_name, rowid, doc_values, fts_columns, bm25_score, query)?;
-            Ok(eval_binary_op(&l, op, &r))
+            eval_binary_op(&l, op, &r)
         }
         _ => Ok(Value::Null),
     }
@@ -545,11 +815,11 @@ fn eval_fts5_expr(
 
 // ---- FTS5 INSERT ----
 
-fn execute_fts5_insert(ins: &InsertStatement) -> Result<usize> {
+fn execute_fts5_insert(ins: &InsertStatement, source_rows: &[Vec<Expr>]) -> Result<usize> {
     let columns = fts5::fts5_get_columns(&ins.table)?;
     let mut inserted = 0;
 
-    for value_row in &ins.values {
+    for value_row in source_rows {
         // Evaluate expressions to get values
         let dummy_table = TableInfo {
             name: ins.table.clone(),
@@ -557,15 +827,27 @@ fn execute_fts5_insert(ins: &InsertStatement) -> Result<usize> {
             root_page: 0,
             next_rowid: 0,
             pk_column: None,
+            pk_columns: vec![],
+            checks: vec![],
+            foreign_keys: vec![],
         };
 
         let mut col_values: Vec<String> = Vec::new();
+        let mut rowid = None;
 
         if let Some(ref col_names) = ins.columns {
             // Named columns: map values to column positions
             let mut vals = vec![String::new(); columns.len()];
             for (i, col_name) in col_names.iter().enumerate() {
-                if let Some(pos) = columns.iter().position(|c| c.eq_ignore_ascii_case(col_name)) {
+                if col_name.eq_ignore_ascii_case("rowid") {
+                    rowid = match eval_expr(&value_row[i], &[], &[], &dummy_table)? {
+                        Value::Integer(n) => Some(n),
+                        Value::Null => None,
+                        other => {
+                            return Err(HorizonError::TypeError(format!("FTS5 rowid must be an integer, got {}", other)))
+                        }
+                    };
+                } else if let Some(pos) = columns.iter().position(|c| c.eq_ignore_ascii_case(col_name)) {
                     let val = eval_expr(&value_row[i], &[], &[], &dummy_table)?;
                     vals[pos] = match val {
                         Value::Text(s) => s,
@@ -596,7 +878,12 @@ fn execute_fts5_insert(ins: &InsertStatement) -> Result<usize> {
             }
         }
 
-        fts5::fts5_insert(&ins.table, col_values)?;
+        match rowid {
+            Some(rowid) => fts5::fts5_insert_with_rowid(&ins.table, rowid, col_values)?,
+            None => {
+                fts5::fts5_insert(&ins.table, col_values)?;
+            }
+        }
         inserted += 1;
     }
 
@@ -605,15 +892,51 @@ fn execute_fts5_insert(ins: &InsertStatement) -> Result<usize> {
 
 // ---- INSERT ----
 
+/// The rows an INSERT writes, as expressions: its `VALUES` rows, or the
+/// rows of its `SELECT` turned into literals. The query runs to completion
+/// first, so it never sees the rows being inserted.
+pub(super) fn insert_source_rows<'a>(
+    ins: &'a InsertStatement,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<Cow<'a, [Vec<Expr>]>> {
+    match &ins.source {
+        InsertSource::Values(rows) => Ok(Cow::Borrowed(rows)),
+        InsertSource::Select(select) => {
+            let result = execute_select(select, pool, catalog)?;
+            let literal = |v: &Value| Expr::Literal(params::value_to_literal(v));
+            Ok(Cow::Owned(
+                result.rows.iter().map(|row| row.values.iter().map(literal).collect()).collect(),
+            ))
+        }
+    }
+}
+
 fn execute_insert(
     ins: &InsertStatement,
     pool: &mut BufferPool,
     catalog: &mut Catalog,
     txn_mgr: &mut TransactionManager,
 ) -> Result<usize> {
+    insert_rows(ins, pool, catalog, txn_mgr, |_| Ok(()))
+}
+
+/// Insert the rows of `ins`, calling `on_row` with the values of each one
+/// once it is in the table, its AFTER INSERT triggers have fired, and its
+/// rowid and stored generated columns hold their final values. Returns the
+/// number of rows inserted.
+fn insert_rows(
+    ins: &InsertStatement,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+    mut on_row: impl FnMut(&[Value]) -> Result<()>,
+) -> Result<usize> {
+    catalog.bump_write_generation(&ins.table);
     // Check if this is an FTS5 virtual table
+    let source_rows = insert_source_rows(ins, pool, catalog)?;
     if fts5::fts5_table_exists(&ins.table) {
-        return execute_fts5_insert(ins);
+        return execute_fts5_insert(ins, &source_rows);
     }
 
     let table = catalog.get_table(&ins.table)?.clone();
@@ -622,10 +945,7 @@ fn execute_insert(
     let _txn_id = txn_mgr.auto_commit();
     let mut inserted = 0;
 
-    // Fire BEFORE INSERT triggers
-    views_triggers::fire_triggers(&ins.table, &TriggerEventKind::Insert, &TriggerTimingKind::Before, pool, catalog, txn_mgr)?;
-
-    for value_row in &ins.values {
+    for value_row in source_rows.iter() {
         // Determine column ordering, filtering out generated columns
         let col_order: Vec<usize> = if let Some(ref col_names) = ins.columns {
             let mut order = Vec::new();
@@ -727,6 +1047,11 @@ fn execute_insert(
             id
         };
 
+        views_triggers::fire_table_row_triggers(
+            &table, TriggerEventKind::Insert, TriggerTimingKind::Before,
+            Some(&row_values), None, &mut tree, pool, catalog, txn_mgr,
+        )?;
+
         // Check NOT NULL constraints (skip virtual generated columns)
         for (i, col) in table.columns.iter().enumerate() {
             // Virtual generated columns are not stored; their value is NULL on disk
@@ -740,12 +1065,17 @@ fn execute_insert(
                 )));
             }
         }
+        enforce_check_constraints(&table, &row_values)?;
+        foreign_keys::check_parent_keys(&table, &row_values, None, pool, catalog)?;
 
         // Check for duplicate primary key / handle OR REPLACE
-        let key = rowid.to_be_bytes();
+        let key = row_key(&table, &row_values, rowid);
+        check_index_key_sizes(&table, &row_values, &key, pool, catalog)?;
+        resolve_unique_conflicts(ins, &table, &row_values, &key, &mut tree, pool, catalog, txn_mgr)?;
         let existing = tree.search(pool, &key)?;
         if let Some(ref old_value) = existing {
             if ins.or_replace {
+                txn_mgr.lock_row(&ins.table, &key)?;
                 // Record undo for the row we are about to overwrite
                 txn_mgr.record_undo(UndoEntry::Update {
                     table: ins.table.clone(),
@@ -753,13 +1083,16 @@ fn execute_insert(
                     key: key.to_vec(),
                     old_value: old_value.clone(),
                 });
+                let old_values = deserialize_row(old_value, table.columns.len())?;
+                delete_index_entries(&table, &old_values, &key, pool, catalog)?;
             } else {
                 return Err(HorizonError::ConstraintViolation(format!(
-                    "UNIQUE constraint failed: {}.rowid",
-                    ins.table
+                    "UNIQUE constraint failed: {}",
+                    primary_key_label(&table)
                 )));
             }
         } else {
+            txn_mgr.lock_row(&ins.table, &key)?;
             // Record undo for a fresh insert
             txn_mgr.record_undo(UndoEntry::Insert {
                 table: ins.table.clone(),
@@ -775,26 +1108,7 @@ fn execute_insert(
         tree.insert(pool, &key, &row_data)?;
 
         // Maintain indexes: insert index entries for this row
-        let indexes = catalog.get_indexes_for_table(&ins.table)
-            .iter().map(|idx| (*idx).clone()).collect::<Vec<_>>();
-        for idx_info in &indexes {
-            let col_indices: Vec<usize> = idx_info.columns.iter()
-                .filter_map(|col_name| table.find_column_index(col_name))
-                .collect();
-            if col_indices.len() == idx_info.columns.len() {
-                let index_key = build_index_key(&row_values, &col_indices, &key);
-                let mut index_tree = BTree::open(idx_info.root_page);
-                index_tree.insert(pool, &index_key, &key)?;
-                // Update index root page if it changed due to splits
-                if index_tree.root_page() != idx_info.root_page {
-                    let mut updated_idx = idx_info.clone();
-                    updated_idx.root_page = index_tree.root_page();
-                    // We need to update the index metadata in catalog
-                    let _ = catalog.drop_index(pool, &idx_info.name);
-                    let _ = catalog.create_index(pool, updated_idx);
-                }
-            }
-        }
+        insert_index_entries(&table, &row_values, &key, pool, catalog)?;
 
         // Update root page if it changed due to splits
         if tree.root_page() != table.root_page {
@@ -804,6 +1118,11 @@ fn execute_insert(
             catalog.update_table_meta(pool, &ins.table, &updated_table)?;
         }
 
+        views_triggers::fire_table_row_triggers(
+            &table, TriggerEventKind::Insert, TriggerTimingKind::After,
+            Some(&row_values), None, &mut tree, pool, catalog, txn_mgr,
+        )?;
+        on_row(&row_values)?;
         inserted += 1;
     }
 
@@ -815,9 +1134,6 @@ fn execute_insert(
     }
     catalog.update_table_meta(pool, &ins.table, &updated_table)?;
 
-    // Fire AFTER INSERT triggers
-    views_triggers::fire_triggers(&ins.table, &TriggerEventKind::Insert, &TriggerTimingKind::After, pool, catalog, txn_mgr)?;
-
     Ok(inserted)
 }
 
@@ -825,24 +1141,43 @@ fn execute_insert(
 
 fn execute_select(
     select: &SelectStatement,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<QueryResult> {
+    execute_select_in_scope(select, pool, catalog, &CteStore::new())
+}
+
+/// Execute a SELECT that can see the CTEs in `outer` (and its own).
+fn execute_select_in_scope(
+    select: &SelectStatement,
+    pool: &BufferPool,
+    catalog: &Catalog,
+    outer: &CteStore,
 ) -> Result<QueryResult> {
     // --- Phase 1: Process CTEs ---
+    let inlined;
+    let select = if select.ctes.iter().any(|cte| cte.materialized == Some(false)) {
+        inlined = inline_ctes(select)?;
+        &inlined
+    } else {
+        select
+    };
+    let own_ctes;
     let cte_store = if select.ctes.is_empty() {
-        CteStore::new()
+        outer
     } else {
-        execute_ctes(&select.ctes, pool, catalog)?
+        own_ctes = execute_ctes(&select.ctes, pool, catalog, outer)?;
+        &own_ctes
     };
 
     // --- Phase 2: Execute base SELECT body ---
-    let (col_names, mut rows) = execute_select_body_inner(select, pool, catalog, &cte_store)?;
+    let (col_names, mut rows) = execute_select_body_inner(select, pool, catalog, cte_store)?;
 
     // --- Phase 3: Handle compound operators (UNION/INTERSECT/EXCEPT) ---
     if !select.compound.is_empty() {
         for compound_op in &select.compound {
             let rhs_stmt = select_body_to_statement(&compound_op.select);
-            let (_rhs_cols, rhs_rows) = execute_select_body_inner(&rhs_stmt, pool, catalog, &cte_store)?;
+            let (_rhs_cols, rhs_rows) = execute_select_body_inner(&rhs_stmt, pool, catalog, cte_store)?;
 
             match compound_op.op {
                 CompoundType::UnionAll => {
@@ -888,7 +1223,7 @@ fn execute_select(
             .collect();
 
         if !select.order_by.is_empty() {
-            sort_rows_by_index(&mut result_rows, &select.order_by, &columns)?;
+            sort_rows_by_index(&mut result_rows, &select.order_by, &columns, catalog)?;
         }
         if let Some(ref offset_expr) = select.offset {
             let offset = eval_const_expr(offset_expr).as_integer().unwrap_or(0) as usize;
@@ -915,13 +1250,76 @@ fn select_body_to_statement(body: &SelectBody) -> SelectStatement {
     SelectStatement {
         ctes: vec![], distinct: body.distinct, columns: body.columns.clone(),
         from: body.from.clone(), where_clause: body.where_clause.clone(),
-        group_by: body.group_by.clone(), having: body.having.clone(),
-        order_by: vec![], limit: None, offset: None, compound: vec![],
+        group_by: body.group_by.clone(), group_by_modifier: body.group_by_modifier, having: body.having.clone(),
+        order_by: vec![], limit: None, offset: None, compound: vec![], windows: vec![],
+    }
+}
+
+/// `select` with each of its `NOT MATERIALIZED` CTEs replaced by a
+/// subquery wherever it is read, so that its query runs once per use.
+///
+/// A recursive CTE, or one whose column list cannot be applied as aliases
+/// to its query's select list, is materialized regardless.
+fn inline_ctes(select: &SelectStatement) -> Result<SelectStatement> {
+    let mut select = select.clone();
+    let mut inlined: Vec<(String, SelectStatement)> = Vec::new();
+    let mut materialized = Vec::new();
+    for mut cte in std::mem::take(&mut select.ctes) {
+        cte.query = substitute_ctes(cte.query, &inlined)?;
+        match inlinable_cte_query(&cte) {
+            Some(query) => inlined.push((cte.name, query)),
+            None => materialized.push(cte),
+        }
+    }
+    select.ctes = materialized;
+    substitute_ctes(select, &inlined)
+}
+
+/// The query to inline for a `NOT MATERIALIZED` CTE, with the CTE's column
+/// names as the aliases of its columns, if it can be inlined.
+fn inlinable_cte_query(cte: &Cte) -> Option<SelectStatement> {
+    if cte.materialized != Some(false) || cte.recursive {
+        return None;
+    }
+    let mut query = cte.query.clone();
+    if let Some(ref names) = cte.columns {
+        if names.len() != query.columns.len() {
+            return None;
+        }
+        for (column, name) in query.columns.iter_mut().zip(names) {
+            match column {
+                SelectColumn::Expr { alias, .. } => *alias = Some(name.clone()),
+                _ => return None,
+            }
+        }
+    }
+    Some(query)
+}
+
+/// `select` with every table named after one of the `inlined` CTEs
+/// replaced by a subquery running the CTE's query.
+fn substitute_ctes(select: SelectStatement, inlined: &[(String, SelectStatement)]) -> Result<SelectStatement> {
+    if inlined.is_empty() {
+        return Ok(select);
+    }
+    let mut stmt = Statement::Select(select);
+    attach::walk_tables(&mut stmt, &mut |from| {
+        if let FromClause::Table { name, alias } = from {
+            if let Some((_, query)) = inlined.iter().find(|(cte, _)| cte.eq_ignore_ascii_case(name)) {
+                let alias = alias.clone().unwrap_or_else(|| name.clone());
+                *from = FromClause::Subquery { query: Box::new(query.clone()), alias };
+            }
+        }
+        Ok(())
+    })?;
+    match stmt {
+        Statement::Select(select) => Ok(select),
+        _ => unreachable!("substituting CTEs keeps the statement a SELECT"),
     }
 }
 
-fn execute_ctes(ctes: &[Cte], pool: &mut BufferPool, catalog: &mut Catalog) -> Result<CteStore> {
-    let mut store = CteStore::new();
+fn execute_ctes(ctes: &[Cte], pool: &BufferPool, catalog: &Catalog, outer: &CteStore) -> Result<CteStore> {
+    let mut store = outer.clone();
     for cte in ctes {
         if cte.recursive {
             execute_recursive_cte(cte, pool, catalog, &mut store)?;
@@ -934,16 +1332,25 @@ fn execute_ctes(ctes: &[Cte], pool: &mut BufferPool, catalog: &mut Catalog) -> R
     Ok(store)
 }
 
-fn execute_recursive_cte(cte: &Cte, pool: &mut BufferPool, catalog: &mut Catalog, store: &mut CteStore) -> Result<()> {
+fn execute_recursive_cte(cte: &Cte, pool: &BufferPool, catalog: &Catalog, store: &mut CteStore) -> Result<()> {
     let anchor_stmt = &cte.query;
     let anchor_only = SelectStatement {
         ctes: vec![], distinct: anchor_stmt.distinct, columns: anchor_stmt.columns.clone(),
         from: anchor_stmt.from.clone(), where_clause: anchor_stmt.where_clause.clone(),
-        group_by: anchor_stmt.group_by.clone(), having: anchor_stmt.having.clone(),
-        order_by: vec![], limit: None, offset: None, compound: vec![],
+        group_by: anchor_stmt.group_by.clone(), group_by_modifier: anchor_stmt.group_by_modifier,
+        having: anchor_stmt.having.clone(),
+        order_by: vec![], limit: None, offset: None, compound: vec![], windows: anchor_stmt.windows.clone(),
     };
-    let (anchor_cols, anchor_rows) = execute_cte_query(&anchor_only, pool, catalog, store)?;
-    let col_names = if let Some(ref cte_cols) = cte.columns { cte_cols.clone() } else { anchor_cols };
+    let (anchor_cols, mut anchor_rows) = execute_cte_query(&anchor_only, pool, catalog, store)?;
+    let mut col_names = if let Some(ref cte_cols) = cte.columns { cte_cols.clone() } else { anchor_cols };
+    let cycle = cte.cycle.as_ref().map(|clause| CycleDetection::new(clause, &col_names)).transpose()?;
+    if let Some(ref cycle) = cycle {
+        for row in &mut anchor_rows {
+            cycle.extend_row(row, None);
+        }
+        col_names.push(cycle.clause.set_column.clone());
+        col_names.push(cycle.clause.path_column.clone());
+    }
     let mut all_rows = anchor_rows.clone();
     let mut working_table = anchor_rows;
     const MAX_RECURSION_DEPTH: usize = 10_000;
@@ -953,11 +1360,29 @@ fn execute_recursive_cte(cte: &Cte, pool: &mut BufferPool, catalog: &mut Catalog
         store.insert(cte.name.to_lowercase(), (col_names.clone(), working_table.clone()));
         let mut new_rows = Vec::new();
         for compound_op in &anchor_stmt.compound {
-            let rhs_stmt = select_body_to_statement(&compound_op.select);
+            let mut rhs_stmt = select_body_to_statement(&compound_op.select);
+            if let Some(ref cycle) = cycle {
+                // Carry the path of the row each new row was reached from
+                let table = rhs_stmt.from.as_ref().and_then(|from| cte_reference(from, &cte.name));
+                let path = Expr::Column { table, name: cycle.clause.path_column.clone() };
+                rhs_stmt.columns.push(SelectColumn::Expr { expr: path, alias: None });
+            }
             let (_rhs_cols, rhs_rows) = execute_cte_query(&rhs_stmt, pool, catalog, store)?;
             new_rows.extend(rhs_rows);
         }
         if new_rows.is_empty() { break; }
+        if let Some(ref cycle) = cycle {
+            // Rows on a cycle are returned, but not recursed from
+            working_table = Vec::new();
+            for mut row in new_rows {
+                let path = row.pop();
+                if !cycle.extend_row(&mut row, path.as_ref()) {
+                    working_table.push(row.clone());
+                }
+                all_rows.push(row);
+            }
+            continue;
+        }
         all_rows.extend(new_rows.clone());
         working_table = new_rows;
     }
@@ -965,14 +1390,85 @@ fn execute_recursive_cte(cte: &Cte, pool: &mut BufferPool, catalog: &mut Catalog
     Ok(())
 }
 
-fn execute_cte_query(select: &SelectStatement, pool: &mut BufferPool, catalog: &mut Catalog, cte_store: &CteStore) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+/// The name under which `from` reads the CTE `cte_name`: its alias, or
+/// its name if it has none.
+fn cte_reference(from: &FromClause, cte_name: &str) -> Option<String> {
+    match from {
+        FromClause::Table { name, alias } if name.eq_ignore_ascii_case(cte_name) => {
+            Some(alias.clone().unwrap_or_else(|| name.clone()))
+        }
+        FromClause::Join { left, right, .. } => {
+            cte_reference(left, cte_name).or_else(|| cte_reference(right, cte_name))
+        }
+        _ => None,
+    }
+}
+
+/// The cycle detection of a recursive CTE's `CYCLE` clause.
+///
+/// A path is the JSON array of the `CYCLE` column values of the rows on
+/// it: each entry is the value itself for a single column, or the array
+/// of the values for several.
+struct CycleDetection<'a> {
+    clause: &'a CycleClause,
+    /// The positions of the `CYCLE` columns among the CTE's columns.
+    keys: Vec<usize>,
+    mark: Value,
+    default: Value,
+}
+
+impl<'a> CycleDetection<'a> {
+    fn new(clause: &'a CycleClause, col_names: &[String]) -> Result<Self> {
+        let keys = clause
+            .columns
+            .iter()
+            .map(|name| find_col_index(name, col_names).ok_or_else(|| HorizonError::ColumnNotFound(name.clone())))
+            .collect::<Result<_>>()?;
+        Ok(CycleDetection {
+            clause,
+            keys,
+            mark: eval_const_expr(&clause.mark),
+            default: eval_const_expr(&clause.default),
+        })
+    }
+
+    /// Append the cycle mark and the path to `row`, which was reached from
+    /// a row with path `parent` (none for an anchor row), returning whether
+    /// the row is on a cycle.
+    fn extend_row(&self, row: &mut Vec<Value>, parent: Option<&Value>) -> bool {
+        let key = match &self.keys[..] {
+            [key] => json::sql_value_to_json(&row[*key]),
+            keys => json::JsonValue::Array(keys.iter().map(|&k| json::sql_value_to_json(&row[k])).collect()),
+        };
+        let mut path = match parent {
+            Some(Value::Text(text)) => match json::JsonParser::parse(text) {
+                Some(json::JsonValue::Array(path)) => path,
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        let on_cycle = path.contains(&key);
+        path.push(key);
+        row.push(if on_cycle { self.mark.clone() } else { self.default.clone() });
+        row.push(Value::Text(json::JsonValue::Array(path).to_json_string()));
+        on_cycle
+    }
+}
+
+fn execute_cte_query(select: &SelectStatement, pool: &BufferPool, catalog: &Catalog, cte_store: &CteStore) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     execute_select_body_inner(select, pool, catalog, cte_store)
 }
 
 fn execute_select_body_inner(
-    select: &SelectStatement, pool: &mut BufferPool, catalog: &mut Catalog, cte_store: &CteStore,
+    select: &SelectStatement, pool: &BufferPool, catalog: &Catalog, cte_store: &CteStore,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    if let Some(rewritten) = rewrite_fulltext_matches(select, catalog) {
+        return execute_select_body_inner(&rewritten, pool, catalog, cte_store);
+    }
     if let Some(ref from) = select.from {
+        if from_contains_derived_table(from) {
+            return execute_select_with_derived_tables(select, from, pool, catalog, cte_store);
+        }
         if let Some(cte_result) = try_resolve_cte_from(from, cte_store) {
             return execute_select_from_cte(select, cte_result, pool, catalog);
         }
@@ -1008,13 +1504,13 @@ fn execute_select_body_inner(
         }
     }
 
-    let (table_name, _alias) = match &select.from {
+    let (table_name, alias) = match &select.from {
         Some(FromClause::Table { name, alias }) => (name.clone(), alias.clone()),
         Some(FromClause::Join { .. }) => { unreachable!(); }
-        Some(FromClause::Subquery { .. }) => { return Err(HorizonError::NotImplemented("subqueries in FROM".into())); }
+        Some(FromClause::Subquery { .. }) | Some(FromClause::Values { .. }) => { unreachable!(); }
         Some(FromClause::TableFunction { .. }) => { return Err(HorizonError::NotImplemented("table functions in FROM".into())); }
         None => {
-            let result = execute_select_no_from(select)?;
+            let result = execute_select_no_from(select, pool, catalog)?;
             let col_names = result.columns.as_ref().clone();
             let rows = result.rows.into_iter().map(|r| r.values).collect();
             return Ok((col_names, rows));
@@ -1039,29 +1535,47 @@ fn execute_select_body_inner(
     }
     let table = catalog.get_table(&table_name)?.clone();
     let data_tree = BTree::open(table.root_page);
-    let entries = scan_with_index(
-        select.where_clause.as_ref(), &table_name, &table, &data_tree, pool, catalog,
-    )?;
+    // A WHERE clause that is constant is decided before the scan
+    let where_clause = select.where_clause.as_ref().map(fold_constants);
+    // Rows read through an index in ORDER BY order need no sorting
+    let visible = alias.as_deref().unwrap_or(&table_name);
+    let order_index = order_by_index(where_clause.as_ref(), &select.order_by, visible, &table, catalog);
+    let scan = |where_clause: Option<&Expr>, read_columns: Option<&[usize]>| match order_index {
+        Some(index) => scan_in_index_order(index, &table, pool, catalog),
+        None => scan_with_index(where_clause, read_columns, &table_name, &table, &data_tree, pool, catalog),
+    };
+    let (where_clause, entries) = match where_clause.as_ref().and_then(literal_truth) {
+        Some(false) => (None, Vec::new()),
+        Some(true) => (None, scan(None, None)?),
+        None => {
+            let read_columns = select_read_columns(select, &table);
+            let entries = scan(where_clause.as_ref(), read_columns.as_deref())?;
+            (where_clause, entries)
+        }
+    };
+    let sort = !select.order_by.is_empty() && order_index.is_none();
     let column_names = resolve_column_names(&select.columns, &table)?;
     let has_virtual = table_has_virtual_columns(&table);
     let mut rows = Vec::new();
-    for entry in &entries {
+    // The table rows the ORDER BY terms are evaluated against
+    let mut table_rows = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        cancel::check_every(i)?;
         let mut row_values = deserialize_row(&entry.value, table.columns.len())?;
         if has_virtual {
             fill_virtual_columns(&mut row_values, &table)?;
         }
-        if let Some(ref where_clause) = select.where_clause {
+        if let Some(ref where_clause) = where_clause {
             let result = eval_expr_with_ctx(where_clause, &row_values, &table.columns, &table, pool, catalog)?;
             if !result.to_bool() { continue; }
         }
         rows.push(project_row_with_ctx(&select.columns, &row_values, &table, pool, catalog)?);
+        if sort {
+            table_rows.push(row_values);
+        }
     }
-    if !select.order_by.is_empty() {
-        let columns_arc = Arc::new(column_names.clone());
-        let mut result_rows: Vec<Row> = rows.into_iter()
-            .map(|values| Row { columns: columns_arc.clone(), values }).collect();
-        sort_rows(&mut result_rows, &select.order_by, &table)?;
-        rows = result_rows.into_iter().map(|r| r.values).collect();
+    if sort {
+        sort_projected_rows(&mut rows, table_rows, &column_names, &select.order_by, &table, catalog)?;
     }
     if select.distinct {
         let mut seen: Vec<Vec<Value>> = Vec::new();
@@ -1080,9 +1594,9 @@ fn execute_select_body_inner(
     Ok((column_names, rows))
 }
 
-fn execute_view_select(
-    outer_select: &SelectStatement, view: &ViewInfo, pool: &mut BufferPool, catalog: &mut Catalog,
-) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+/// Run the query defining `view`, returning its column names (with the
+/// view's column aliases applied) and rows.
+fn view_rows(view: &ViewInfo, pool: &BufferPool, catalog: &Catalog) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let view_stmts = Parser::parse(&view.sql)?;
     let view_select = match view_stmts.into_iter().next() {
         Some(Statement::Select(sel)) => sel,
@@ -1095,6 +1609,13 @@ fn execute_view_select(
             if i < view_col_names.len() { view_col_names[i] = alias.clone(); }
         }
     }
+    Ok((view_col_names, view_rows))
+}
+
+fn execute_view_select(
+    outer_select: &SelectStatement, view: &ViewInfo, pool: &BufferPool, catalog: &Catalog,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let (view_col_names, view_rows) = view_rows(view, pool, catalog)?;
     let is_star = outer_select.columns.len() == 1 && matches!(outer_select.columns[0], SelectColumn::AllColumns);
     if is_star && outer_select.where_clause.is_none() && outer_select.order_by.is_empty()
         && outer_select.limit.is_none() && outer_select.offset.is_none() {
@@ -1135,7 +1656,7 @@ fn execute_view_select(
     if !outer_select.order_by.is_empty() {
         let ca = Arc::new(col_names.clone());
         let mut rr: Vec<Row> = proj_rows.into_iter().map(|v| Row { columns: ca.clone(), values: v }).collect();
-        sort_rows_by_index(&mut rr, &outer_select.order_by, &col_names)?;
+        sort_rows_by_index(&mut rr, &outer_select.order_by, &col_names, catalog)?;
         proj_rows = rr.into_iter().map(|r| r.values).collect();
     }
     if let Some(ref oe) = outer_select.offset {
@@ -1149,6 +1670,171 @@ fn execute_view_select(
     Ok((col_names, proj_rows))
 }
 
+/// Whether `from` contains a derived table: a subquery, a VALUES list, or
+/// a built-in table-valued function.
+fn from_contains_derived_table(from: &FromClause) -> bool {
+    match from {
+        FromClause::Subquery { .. } | FromClause::Values { .. } => true,
+        FromClause::TableFunction { name, .. } => table_functions::is_table_function(name),
+        FromClause::Join { left, right, .. } => from_contains_derived_table(left) || from_contains_derived_table(right),
+        FromClause::Table { .. } => false,
+    }
+}
+
+/// Execute a SELECT whose FROM clause contains derived tables. Each one is
+/// computed once and its rows are then read like a CTE named after its alias.
+fn execute_select_with_derived_tables(
+    select: &SelectStatement, from: &FromClause, pool: &BufferPool, catalog: &Catalog, cte_store: &CteStore,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let mut store = cte_store.clone();
+    let from = materialize_derived_tables(from, pool, catalog, &mut store)?;
+    let select = SelectStatement { from: Some(from), ..select.clone() };
+    execute_select_with_cte_join(&select, pool, catalog, &store)
+}
+
+/// Compute the derived tables in `from`, storing their rows under their
+/// aliases, and return `from` with each one replaced by a reference to them.
+fn materialize_derived_tables(
+    from: &FromClause, pool: &BufferPool, catalog: &Catalog, store: &mut CteStore,
+) -> Result<FromClause> {
+    match from {
+        FromClause::Subquery { query, alias } => {
+            if let Some(outer) = outer_references(query).first() {
+                return Err(HorizonError::InvalidSql(format!(
+                    "subquery {} refers to {}, which is not in its FROM clause (correlated subqueries are not supported in FROM)",
+                    alias, outer
+                )));
+            }
+            let result = execute_select_in_scope(query, pool, catalog, store)?;
+            let rows = result.rows.into_iter().map(|r| r.values).collect();
+            store.insert(alias.to_lowercase(), (result.columns.as_ref().clone(), rows));
+            Ok(FromClause::Table { name: alias.clone(), alias: None })
+        }
+        FromClause::TableFunction { name, args, alias } if table_functions::is_table_function(name) => {
+            let args = args
+                .iter()
+                .map(|arg| eval_expr_dynamic_with_ctx(arg, &[], &[], pool, catalog))
+                .collect::<Result<Vec<_>>>()?;
+            let key = alias.clone().unwrap_or_else(|| name.clone());
+            store.insert(key.to_lowercase(), table_functions::table_function_rows(name, &args)?);
+            Ok(FromClause::Table { name: key, alias: None })
+        }
+        FromClause::Values { rows, alias, columns } => {
+            // A list without rows takes its width from the column names
+            let width = rows.first().map_or_else(|| columns.as_ref().map_or(0, Vec::len), Vec::len);
+            if rows.iter().any(|row| row.len() != width) {
+                return Err(HorizonError::InvalidSql(
+                    "all VALUES rows must have the same number of values".into(),
+                ));
+            }
+            let col_names = match columns {
+                Some(cols) if cols.len() != width => {
+                    return Err(HorizonError::InvalidSql(format!(
+                        "VALUES has {} columns but {} column names were given",
+                        width,
+                        cols.len()
+                    )))
+                }
+                Some(cols) => cols.clone(),
+                None => (1..=width).map(|i| format!("column{}", i)).collect(),
+            };
+            let values = rows
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|expr| eval_expr_dynamic_with_ctx(expr, &[], &[], pool, catalog))
+                        .collect::<Result<Vec<_>>>()
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let key = alias.clone().unwrap_or_else(|| "values".to_string());
+            store.insert(key.to_lowercase(), (col_names, values));
+            Ok(FromClause::Table { name: key, alias: None })
+        }
+        FromClause::Join { left, join_type, right, constraint } => Ok(FromClause::Join {
+            left: Box::new(materialize_derived_tables(left, pool, catalog, store)?),
+            join_type: join_type.clone(),
+            right: Box::new(materialize_derived_tables(right, pool, catalog, store)?),
+            constraint: constraint.clone(),
+        }),
+        FromClause::Table { .. } | FromClause::TableFunction { .. } => Ok(from.clone()),
+    }
+}
+
+/// The qualified column references in `select` whose qualifier names no
+/// table or alias in any FROM clause inside it, i.e. references to an
+/// enclosing query.
+fn outer_references(select: &SelectStatement) -> Vec<String> {
+    fn from_names(from: &FromClause, names: &mut Vec<String>) {
+        match from {
+            FromClause::Table { name, alias } | FromClause::TableFunction { name, alias, .. } => {
+                // An alias hides the table name, so `FROM t AS x` only binds `x`.
+                names.push(alias.clone().unwrap_or_else(|| name.clone()));
+            }
+            FromClause::Join { left, right, .. } => {
+                from_names(left, names);
+                from_names(right, names);
+            }
+            FromClause::Subquery { query, alias } => {
+                names.push(alias.clone());
+                if let Some(from) = &query.from {
+                    from_names(from, names);
+                }
+            }
+            FromClause::Values { alias, .. } => names.extend(alias.clone()),
+        }
+    }
+
+    let mut names = Vec::new();
+    if let Some(from) = &select.from {
+        from_names(from, &mut names);
+    }
+    let mut qualified = Vec::new();
+    let _ = params::walk_select(&mut select.clone(), &mut |expr| {
+        match expr {
+            Expr::Column { table: Some(t), name } => qualified.push((t.clone(), name.clone())),
+            Expr::Subquery(inner) | Expr::Exists(inner) => {
+                if let Some(from) = &inner.from {
+                    from_names(from, &mut names);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    });
+    qualified
+        .into_iter()
+        .filter(|(t, _)| !names.iter().any(|n| n.eq_ignore_ascii_case(t)))
+        .map(|(t, name)| format!("{}.{}", t, name))
+        .collect()
+}
+
+/// Bind the references to an enclosing query in `select` (see
+/// [`outer_references`]) to the values of the current outer row, so the
+/// subquery can run on its own. `lookup` resolves a `(table, column)`
+/// reference against the outer row; references it cannot resolve are left
+/// alone. Uncorrelated subqueries are returned unchanged.
+fn correlate<'a>(
+    select: &'a SelectStatement, lookup: &dyn Fn(&str, &str) -> Option<Value>,
+) -> Cow<'a, SelectStatement> {
+    let outer = outer_references(select);
+    if outer.is_empty() {
+        return Cow::Borrowed(select);
+    }
+    let mut bound = select.clone();
+    let _ = params::walk_select(&mut bound, &mut |expr| {
+        if let Expr::Column { table: Some(t), name } = expr {
+            let qualified = format!("{}.{}", t, name);
+            if outer.iter().any(|o| o.eq_ignore_ascii_case(&qualified)) {
+                if let Some(value) = lookup(t, name) {
+                    *expr = Expr::Literal(params::value_to_literal(&value));
+                }
+            }
+        }
+        Ok(())
+    });
+    Cow::Owned(bound)
+}
+
 fn try_resolve_cte_from<'a>(from: &FromClause, cte_store: &'a CteStore) -> Option<&'a (Vec<String>, Vec<Vec<Value>>)> {
     match from { FromClause::Table { name, .. } => cte_store.get(&name.to_lowercase()), _ => None }
 }
@@ -1157,12 +1843,12 @@ fn from_contains_cte(from: &FromClause, cte_store: &CteStore) -> bool {
     match from {
         FromClause::Table { name, .. } => cte_store.contains_key(&name.to_lowercase()),
         FromClause::Join { left, right, .. } => from_contains_cte(left, cte_store) || from_contains_cte(right, cte_store),
-        FromClause::Subquery { .. } | FromClause::TableFunction { .. } => false,
+        FromClause::Subquery { .. } | FromClause::TableFunction { .. } | FromClause::Values { .. } => false,
     }
 }
 
 fn execute_select_from_cte(
-    select: &SelectStatement, cte_data: &(Vec<String>, Vec<Vec<Value>>), pool: &mut BufferPool, catalog: &mut Catalog,
+    select: &SelectStatement, cte_data: &(Vec<String>, Vec<Vec<Value>>), pool: &BufferPool, catalog: &Catalog,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let (cte_col_names, cte_rows) = cte_data;
     if !select.group_by.is_empty() || select.having.is_some() || select_has_aggregate(&select.columns) {
@@ -1175,9 +1861,9 @@ fn execute_select_from_cte(
             let result = eval_expr_dynamic_with_ctx(where_clause, row_values, cte_col_names, pool, catalog)?;
             if !result.to_bool() { continue; }
         }
-        rows.push(project_row_dynamic(&select.columns, row_values, cte_col_names)?);
+        rows.push(project_row_dynamic(&select.columns, row_values, cte_col_names, pool, catalog)?);
     }
-    if !select.order_by.is_empty() { sort_rows_dynamic(&mut rows, &select.order_by, &out_col_names)?; }
+    if !select.order_by.is_empty() { sort_rows_dynamic(&mut rows, &select.order_by, &out_col_names, catalog)?; }
     if select.distinct {
         let mut seen: Vec<Vec<Value>> = Vec::new(); let mut unique = Vec::new();
         for row in rows { if !seen.contains(&row) { seen.push(row.clone()); unique.push(row); } }
@@ -1194,7 +1880,7 @@ fn execute_select_from_cte(
 }
 
 fn execute_cte_with_aggregates(
-    select: &SelectStatement, cte_col_names: &[String], cte_rows: &[Vec<Value>], pool: &mut BufferPool, catalog: &mut Catalog,
+    select: &SelectStatement, cte_col_names: &[String], cte_rows: &[Vec<Value>], pool: &BufferPool, catalog: &Catalog,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let mut filtered_rows = Vec::new();
     for row_values in cte_rows {
@@ -1204,60 +1890,33 @@ fn execute_cte_with_aggregates(
         }
         filtered_rows.push(row_values.clone());
     }
-    let groups = group_rows(&filtered_rows, &select.group_by, cte_col_names)?;
     let out_col_names = resolve_column_names_dynamic(&select.columns, cte_col_names)?;
-    let mut result = Vec::new();
-    for (_key, group) in &groups {
-        let representative = if group.is_empty() { vec![Value::Null; cte_col_names.len()] } else { group[0].clone() };
-        if let Some(ref having_expr) = select.having {
-            if !eval_aggregate_expr(having_expr, &representative, cte_col_names, group)?.to_bool() { continue; }
-        }
-        let mut out_row = Vec::new();
-        for col in &select.columns {
-            match col {
-                SelectColumn::AllColumns => out_row.extend(representative.iter().cloned()),
-                SelectColumn::TableAllColumns(_) => out_row.extend(representative.iter().cloned()),
-                SelectColumn::Expr { expr, .. } => { out_row.push(eval_aggregate_expr(expr, &representative, cte_col_names, group)?); }
-            }
-        }
-        result.push(out_row);
-    }
+    let result = aggregate_rows(
+        &filtered_rows, cte_col_names, &select.group_by, select.group_by_modifier, &select.having, &select.columns,
+    )?;
     Ok((out_col_names, result))
 }
 
 fn execute_select_with_cte_join(
-    select: &SelectStatement, pool: &mut BufferPool, catalog: &mut Catalog, cte_store: &CteStore,
+    select: &SelectStatement, pool: &BufferPool, catalog: &Catalog, cte_store: &CteStore,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let from = select.from.as_ref().unwrap();
     let (merged_cols, mut result_rows) = execute_from_with_ctes(from, pool, catalog, cte_store)?;
     if let Some(ref where_clause) = select.where_clause {
         let mut filtered = Vec::new();
-        for row in result_rows { if eval_expr_dynamic(where_clause, &row, &merged_cols)?.to_bool() { filtered.push(row); } }
+        for row in result_rows { if eval_expr_dynamic_with_ctx(where_clause, &row, &merged_cols, pool, catalog)?.to_bool() { filtered.push(row); } }
         result_rows = filtered;
     }
-    if !select.group_by.is_empty() || select.having.is_some() || select_has_aggregate(&select.columns) {
-        let groups = group_rows(&result_rows, &select.group_by, &merged_cols)?;
-        let out_col_names = resolve_column_names_dynamic(&select.columns, &merged_cols)?;
-        let mut result = Vec::new();
-        for (_key, group) in &groups {
-            let representative = if group.is_empty() { vec![Value::Null; merged_cols.len()] } else { group[0].clone() };
-            if let Some(ref having_expr) = select.having {
-                if !eval_aggregate_expr(having_expr, &representative, &merged_cols, group)?.to_bool() { continue; }
-            }
-            let mut out_row = Vec::new();
-            for col in &select.columns { match col {
-                SelectColumn::AllColumns => out_row.extend(representative.iter().cloned()),
-                SelectColumn::TableAllColumns(_) => out_row.extend(representative.iter().cloned()),
-                SelectColumn::Expr { expr, .. } => { out_row.push(eval_aggregate_expr(expr, &representative, &merged_cols, group)?); }
-            }}
-            result.push(out_row);
-        }
-        return Ok((out_col_names, result));
-    }
     let out_col_names = resolve_column_names_dynamic(&select.columns, &merged_cols)?;
     let mut out_rows = Vec::new();
-    for row in &result_rows { out_rows.push(project_row_dynamic(&select.columns, row, &merged_cols)?); }
-    if !select.order_by.is_empty() { sort_rows_dynamic(&mut out_rows, &select.order_by, &out_col_names)?; }
+    if !select.group_by.is_empty() || select.having.is_some() || select_has_aggregate(&select.columns) {
+        out_rows = aggregate_rows(
+            &result_rows, &merged_cols, &select.group_by, select.group_by_modifier, &select.having, &select.columns,
+        )?;
+    } else {
+        for row in &result_rows { out_rows.push(project_row_dynamic(&select.columns, row, &merged_cols, pool, catalog)?); }
+    }
+    if !select.order_by.is_empty() { sort_rows_dynamic(&mut out_rows, &select.order_by, &out_col_names, catalog)?; }
     if select.distinct {
         let mut seen: Vec<Vec<Value>> = Vec::new(); let mut unique = Vec::new();
         for row in out_rows { if !seen.contains(&row) { seen.push(row.clone()); unique.push(row); } }
@@ -1273,7 +1932,7 @@ fn execute_select_with_cte_join(
     Ok((out_col_names, out_rows))
 }
 
-fn execute_from_with_ctes(from: &FromClause, pool: &mut BufferPool, catalog: &mut Catalog, cte_store: &CteStore) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+fn execute_from_with_ctes(from: &FromClause, pool: &BufferPool, catalog: &Catalog, cte_store: &CteStore) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     match from {
         FromClause::Table { name, alias } => {
             if let Some((cte_cols, cte_rows)) = cte_store.get(&name.to_lowercase()) {
@@ -1282,7 +1941,7 @@ fn execute_from_with_ctes(from: &FromClause, pool: &mut BufferPool, catalog: &mu
             } else {
                 let table_info = catalog.get_table(name)?.clone();
                 let data_tree = BTree::open(table_info.root_page);
-                let entries = data_tree.scan_all(pool)?;
+                let entries = snapshot::visible_entries(&table_info.name, data_tree.scan_all(pool)?);
                 let prefix = alias.as_deref().unwrap_or(name);
                 let col_names: Vec<String> = table_info.columns.iter().map(|c| format!("{}.{}", prefix, c.name)).collect();
                 let mut rows = Vec::new();
@@ -1290,37 +1949,30 @@ fn execute_from_with_ctes(from: &FromClause, pool: &mut BufferPool, catalog: &mu
                 Ok((col_names, rows))
             }
         }
-        FromClause::Join { left, right, join_type, on } => {
+        FromClause::Join { left, right, join_type, constraint } => {
             let (left_cols, left_rows) = execute_from_with_ctes(left, pool, catalog, cte_store)?;
             let (right_cols, right_rows) = execute_from_with_ctes(right, pool, catalog, cte_store)?;
-            let num_right = right_cols.len(); let num_left = left_cols.len();
-            let mut merged_cols = left_cols.clone(); merged_cols.extend(right_cols.clone());
-            let null_right: Vec<Value> = vec![Value::Null; num_right];
-            let null_left: Vec<Value> = vec![Value::Null; num_left];
-            let mut result = Vec::new();
-            match join_type {
-                JoinType::Inner => { for l in &left_rows { for r in &right_rows {
-                    let mut m = l.clone(); m.extend(r.iter().cloned());
-                    if let Some(ref e) = on { if !eval_expr_dynamic(e, &m, &merged_cols)?.to_bool() { continue; } }
-                    result.push(m);
-                }}}
-                JoinType::Left => { for l in &left_rows { let mut matched = false; for r in &right_rows {
-                    let mut m = l.clone(); m.extend(r.iter().cloned());
-                    if let Some(ref e) = on { if !eval_expr_dynamic(e, &m, &merged_cols)?.to_bool() { continue; } }
-                    matched = true; result.push(m);
-                } if !matched { let mut m = l.clone(); m.extend(null_right.iter().cloned()); result.push(m); } }}
-                JoinType::Right => { for r in &right_rows { let mut matched = false; for l in &left_rows {
-                    let mut m = l.clone(); m.extend(r.iter().cloned());
-                    if let Some(ref e) = on { if !eval_expr_dynamic(e, &m, &merged_cols)?.to_bool() { continue; } }
-                    matched = true; result.push(m);
-                } if !matched { let mut m = null_left.clone(); m.extend(r.iter().cloned()); result.push(m); } }}
-                JoinType::Cross => { for l in &left_rows { for r in &right_rows {
-                    let mut m = l.clone(); m.extend(r.iter().cloned()); result.push(m);
-                }}}
-            }
-            Ok((merged_cols, result))
-        }
-        FromClause::Subquery { .. } => Err(HorizonError::NotImplemented("subquery in FROM with CTEs".into())),
+            match constraint {
+                None => join_rows(left_cols, left_rows, right_cols, right_rows, join_type, &None, catalog),
+                Some(JoinSpec::On(e)) => join_rows(left_cols, left_rows, right_cols, right_rows, join_type, &Some(e.clone()), catalog),
+                Some(JoinSpec::Using(using)) => join_rows_using(left_cols, left_rows, right_cols, right_rows, join_type, using, catalog),
+                Some(JoinSpec::Natural) => {
+                    let unqualified = |c: &String| c.rsplit('.').next().unwrap_or(c).to_string();
+                    let mut shared: Vec<String> = Vec::new();
+                    for name in left_cols.iter().map(unqualified) {
+                        if right_cols.iter().any(|r| unqualified(r).eq_ignore_ascii_case(&name))
+                            && !shared.iter().any(|s| s.eq_ignore_ascii_case(&name))
+                        {
+                            shared.push(name);
+                        }
+                    }
+                    join_rows_using(left_cols, left_rows, right_cols, right_rows, join_type, &shared, catalog)
+                }
+            }
+        }
+        FromClause::Subquery { .. } | FromClause::Values { .. } => {
+            Err(HorizonError::Internal("FROM subquery was not materialized".into()))
+        }
         FromClause::TableFunction { .. } => Err(HorizonError::NotImplemented("table function in FROM with CTEs".into())),
     }
 }
@@ -1346,32 +1998,30 @@ fn resolve_column_names_from_cte(select_cols: &[SelectColumn], cte_col_names: &[
     Ok(names)
 }
 
-fn sort_rows_by_index(rows: &mut [Row], order_by: &[OrderByItem], col_names: &[String]) -> Result<()> {
-    rows.sort_by(|a, b| {
-        for item in order_by {
-            let va = eval_expr_dynamic(&item.expr, &a.values, col_names).unwrap_or(Value::Null);
-            let vb = eval_expr_dynamic(&item.expr, &b.values, col_names).unwrap_or(Value::Null);
-            let cmp = if let Some(coll) = extract_collation(&item.expr) {
-                compare_with_collation(&va, &vb, coll)
-            } else {
-                va.cmp(&vb)
-            };
-            let cmp = if item.desc { cmp.reverse() } else { cmp };
-            if cmp != std::cmp::Ordering::Equal { return cmp; }
-        }
-        std::cmp::Ordering::Equal
-    });
-    Ok(())
+fn sort_rows_by_index(rows: &mut Vec<Row>, order_by: &[OrderByItem], col_names: &[String], catalog: &Catalog) -> Result<()> {
+    sort_row_values(rows, |values| sort_rows_dynamic(values, order_by, col_names, catalog))
 }
 
-/// Fast path for `SELECT COUNT(*) FROM table` with no WHERE, GROUP BY, HAVING,
+/// Sort `rows` by sorting their values with `sort`.
+fn sort_row_values(rows: &mut Vec<Row>, sort: impl FnOnce(&mut Vec<Vec<Value>>) -> Result<()>) -> Result<()> {
+    let columns = match rows.first() {
+        Some(row) => row.columns.clone(),
+        None => return Ok(()),
+    };
+    let mut values: Vec<Vec<Value>> = rows.drain(..).map(|row| row.values).collect();
+    sort(&mut values)?;
+    rows.extend(values.into_iter().map(|values| Row { columns: columns.clone(), values }));
+    Ok(())
+}
+
+/// Fast path for `SELECT COUNT(*) FROM table` with no WHERE, GROUP BY, HAVING,
 /// DISTINCT, JOINs, or compound queries. Uses BTree::count() which traverses
 /// leaf pages counting entries without deserializing any row data.
 fn try_count_star_fast_path(
     select: &SelectStatement,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
-) -> Result<Option<(Vec<String>, Vec<Vec<Value>>)>> {
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<Option<RowSet>> {
     // Must be a single table, no WHERE, no GROUP BY, no HAVING, no DISTINCT, no compound
     if select.where_clause.is_some() || !select.group_by.is_empty()
         || select.having.is_some() || select.distinct || !select.compound.is_empty() {
@@ -1382,14 +2032,11 @@ fn try_count_star_fast_path(
         return Ok(None);
     }
     let is_count_star = match &select.columns[0] {
-        SelectColumn::Expr { expr, .. } => match expr {
-            Expr::Function { name, args, .. } => {
-                name.eq_ignore_ascii_case("count")
-                    && args.len() == 1
-                    && matches!(&args[0], Expr::Column { name: col_name, .. } if col_name == "*")
-            }
-            _ => false,
-        },
+        SelectColumn::Expr { expr: Expr::Function { name, args, filter: None, .. }, .. } => {
+            name.eq_ignore_ascii_case("count")
+                && args.len() == 1
+                && matches!(&args[0], Expr::Column { name: col_name, .. } if col_name == "*")
+        }
         _ => false,
     };
     if !is_count_star {
@@ -1404,8 +2051,8 @@ fn try_count_star_fast_path(
     if fts5::fts5_table_exists(&table_name) || catalog.rtree_exists(&table_name) {
         return Ok(None);
     }
-    // Skip views
-    if !catalog.table_exists(&table_name) {
+    // Skip views, and snapshot reads, which must look at every row
+    if !catalog.table_exists(&table_name) || snapshot::is_active() {
         return Ok(None);
     }
     let table = catalog.get_table(&table_name)?;
@@ -1439,11 +2086,12 @@ fn expr_has_aggregate_fn(expr: &Expr) -> bool {
             // MIN and MAX with 2+ args are scalar functions, not aggregates
             if (upper == "MIN" || upper == "MAX") && args.len() >= 2 {
                 // Check if any arguments contain aggregate functions
-                return args.iter().any(|a| expr_has_aggregate_fn(a));
+                return args.iter().any(expr_has_aggregate_fn);
             }
             matches!(
                 upper.as_str(),
-                "COUNT" | "SUM" | "AVG" | "MIN" | "MAX" | "GROUP_CONCAT" | "TOTAL"
+                "COUNT" | "SUM" | "AVG" | "MIN" | "MAX" | "GROUP_CONCAT" | "JSON_AGG" | "TOTAL"
+                    | "STDDEV" | "STDDEV_POP" | "STDDEV_SAMP" | "VARIANCE" | "VAR_POP" | "VAR_SAMP"
             )
         }
         Expr::BinaryOp { left, right, .. } => {
@@ -1476,18 +2124,18 @@ fn expr_has_window_fn(expr: &Expr) -> bool {
 // ---- Window Function Execution ----
 
 fn execute_select_with_window_functions(
-    select: &SelectStatement, pool: &mut BufferPool, catalog: &mut Catalog,
+    select: &SelectStatement, pool: &BufferPool, catalog: &Catalog,
 ) -> Result<QueryResult> {
     let (table_name, _alias) = match &select.from {
         Some(FromClause::Table { name, alias }) => (name.clone(), alias.clone()),
         Some(FromClause::Join { .. }) => return Err(HorizonError::NotImplemented("window functions with JOINs".into())),
-        Some(FromClause::Subquery { .. }) => return Err(HorizonError::NotImplemented("window functions with subqueries".into())),
+        Some(FromClause::Subquery { .. }) | Some(FromClause::Values { .. }) => return Err(HorizonError::NotImplemented("window functions with subqueries".into())),
         Some(FromClause::TableFunction { .. }) => return Err(HorizonError::NotImplemented("window functions with table functions".into())),
         None => return Err(HorizonError::InvalidSql("window functions require a FROM clause".into())),
     };
     let table = catalog.get_table(&table_name)?.clone();
     let data_tree = BTree::open(table.root_page);
-    let entries = data_tree.scan_all(pool)?;
+    let entries = snapshot::visible_entries(&table.name, data_tree.scan_all(pool)?);
     let mut base_rows: Vec<Vec<Value>> = Vec::new();
     for entry in &entries {
         let row_values = deserialize_row(&entry.value, table.columns.len())?;
@@ -1507,7 +2155,8 @@ fn execute_select_with_window_functions(
             }
             SelectColumn::Expr { expr, .. } => {
                 if expr_has_window_fn(expr) {
-                    let wv = evaluate_window_expr(expr, &base_rows, &table)?;
+                    let expr = resolve_named_window(expr, &select.windows)?;
+                    let wv = evaluate_window_expr(&expr, &base_rows, &table)?;
                     for (i, val) in wv.into_iter().enumerate() { result_rows[i].push(val); }
                 } else {
                     for (i, row) in base_rows.iter().enumerate() {
@@ -1517,9 +2166,11 @@ fn execute_select_with_window_functions(
             }
         }
     }
+    if !select.order_by.is_empty() {
+        sort_projected_rows(&mut result_rows, base_rows, &columns_arc, &select.order_by, &table, catalog)?;
+    }
     let mut rows: Vec<Row> = result_rows.into_iter()
         .map(|values| Row { columns: columns_arc.clone(), values }).collect();
-    if !select.order_by.is_empty() { sort_rows(&mut rows, &select.order_by, &table)?; }
     if select.distinct { rows.dedup_by(|a, b| a.values == b.values); }
     if let Some(ref oe) = select.offset {
         let o = eval_const_expr(oe).as_integer().unwrap_or(0) as usize;
@@ -1552,8 +2203,51 @@ fn resolve_column_names_for_window(cols: &[SelectColumn], table: &TableInfo) ->
     Ok(names)
 }
 
+/// Expand a window function's reference to a window from the `WINDOW`
+/// clause into a complete inline specification.
+fn resolve_named_window<'a>(expr: &'a Expr, windows: &[NamedWindow]) -> Result<Cow<'a, Expr>> {
+    match expr {
+        Expr::WindowFunction { function, partition_by, order_by, frame, window: Some(name) } => {
+            let def = WindowDef {
+                base: Some(name.clone()), partition_by: partition_by.clone(),
+                order_by: order_by.clone(), frame: frame.clone(),
+            };
+            let def = resolve_window_def(&def, windows, windows.len())?;
+            Ok(Cow::Owned(Expr::WindowFunction {
+                function: function.clone(), partition_by: def.partition_by,
+                order_by: def.order_by, frame: def.frame, window: None,
+            }))
+        }
+        _ => Ok(Cow::Borrowed(expr)),
+    }
+}
+
+/// Merge `def` into the window it extends. A named window may only extend
+/// one defined before it, so the lookup is limited to `windows[..visible]`.
+fn resolve_window_def(def: &WindowDef, windows: &[NamedWindow], visible: usize) -> Result<WindowDef> {
+    let name = match &def.base {
+        Some(name) => name,
+        None => return Ok(def.clone()),
+    };
+    let pos = windows[..visible].iter().position(|w| w.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| HorizonError::InvalidSql(format!("no such window: {}", name)))?;
+    let base = resolve_window_def(&windows[pos].def, windows, pos)?;
+    if !def.partition_by.is_empty() {
+        return Err(HorizonError::InvalidSql(format!("cannot override PARTITION BY of window {}", name)));
+    }
+    if !def.order_by.is_empty() && !base.order_by.is_empty() {
+        return Err(HorizonError::InvalidSql(format!("cannot override ORDER BY of window {}", name)));
+    }
+    Ok(WindowDef {
+        base: None,
+        partition_by: base.partition_by,
+        order_by: if def.order_by.is_empty() { base.order_by } else { def.order_by.clone() },
+        frame: def.frame.clone().or(base.frame),
+    })
+}
+
 fn evaluate_window_expr(expr: &Expr, all_rows: &[Vec<Value>], table: &TableInfo) -> Result<Vec<Value>> {
-    if let Expr::WindowFunction { function, partition_by, order_by, frame } = expr {
+    if let Expr::WindowFunction { function, partition_by, order_by, frame, .. } = expr {
         compute_window_function(function, partition_by, order_by, frame, all_rows, table)
     } else {
         all_rows.iter().map(|row| eval_expr(expr, row, &table.columns, table)).collect()
@@ -1575,6 +2269,7 @@ fn compute_window_function(
         } else { partition_map.push((key, vec![i])); }
     }
     let (func_name, func_args) = match function {
+        Expr::Function { filter: Some(_), .. } => return Err(HorizonError::NotImplemented("FILTER on window functions".into())),
         Expr::Function { name, args, .. } => (name.to_uppercase(), args.clone()),
         _ => return Err(HorizonError::InvalidSql("expected function call in window function".into())),
     };
@@ -1609,6 +2304,34 @@ fn compute_window_function(
                     results[indices[pos]] = Value::Integer(rank);
                 }
             }
+            "NTILE" => {
+                let n = func_args.first().map(eval_const_expr).and_then(|v| v.as_integer()).unwrap_or(0);
+                if n <= 0 { return Err(HorizonError::InvalidSql("argument of NTILE must be a positive integer".into())); }
+                // The first `pl % n` buckets hold one extra row.
+                let n = n as usize;
+                let (size, extra) = (pl / n, pl % n);
+                for (pos, &oi) in indices.iter().enumerate() {
+                    let bucket = if pos < extra * (size + 1) { pos / (size + 1) } else { extra + (pos - extra * (size + 1)) / size };
+                    results[oi] = Value::Integer(bucket as i64 + 1);
+                }
+            }
+            "PERCENT_RANK" => {
+                let mut rank = 1usize;
+                for pos in 0..pl {
+                    if pos > 0 && !ob_eq(order_by, &all_rows[indices[pos]], &all_rows[indices[pos-1]], table) { rank = pos + 1; }
+                    results[indices[pos]] = Value::Real(if pl > 1 { (rank - 1) as f64 / (pl - 1) as f64 } else { 0.0 });
+                }
+            }
+            "CUME_DIST" => {
+                // Each row counts every row up to the last of its peers.
+                let mut pos = 0;
+                while pos < pl {
+                    let mut end = pos + 1;
+                    while end < pl && ob_eq(order_by, &all_rows[indices[end]], &all_rows[indices[pos]], table) { end += 1; }
+                    for &oi in &indices[pos..end] { results[oi] = Value::Real(end as f64 / pl as f64); }
+                    pos = end;
+                }
+            }
             "LAG" => {
                 let off = if func_args.len() > 1 { eval_const_expr(&func_args[1]).as_integer().unwrap_or(1) as usize } else { 1 };
                 let def = if func_args.len() > 2 { eval_const_expr(&func_args[2]) } else { Value::Null };
@@ -1639,6 +2362,15 @@ fn compute_window_function(
                     results[indices[pos]] = if !func_args.is_empty() { eval_expr(&func_args[0], &all_rows[indices[fe]], &table.columns, table)? } else { Value::Null };
                 }
             }
+            "NTH_VALUE" => {
+                let n = func_args.get(1).map(eval_const_expr).and_then(|v| v.as_integer()).unwrap_or(0);
+                if n <= 0 { return Err(HorizonError::InvalidSql("second argument of NTH_VALUE must be a positive integer".into())); }
+                for pos in 0..pl {
+                    let (fs, fe) = wf_frame(frame, pos, pl, !order_by.is_empty());
+                    let nth = fs + n as usize - 1;
+                    results[indices[pos]] = if nth <= fe { eval_expr(&func_args[0], &all_rows[indices[nth]], &table.columns, table)? } else { Value::Null };
+                }
+            }
             "SUM" => {
                 for pos in 0..pl {
                     let (fs, fe) = wf_frame(frame, pos, pl, !order_by.is_empty());
@@ -1741,8 +2473,8 @@ fn wf_bound(bound: &WindowFrameBound, pos: usize, pl: usize) -> usize {
 /// Execute a SELECT via the logical plan (used for JOINs and aggregates).
 fn execute_plan_select(
     plan: &LogicalPlan,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<QueryResult> {
     let (col_names, rows) = execute_plan_rows(plan, pool, catalog)?;
     let columns = Arc::new(col_names);
@@ -1760,14 +2492,15 @@ fn execute_plan_select(
 /// Each row is a Vec<Value>.
 fn execute_plan_rows(
     plan: &LogicalPlan,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     match plan {
-        LogicalPlan::SeqScan { table, alias } => {
+        LogicalPlan::SeqScan { table, alias, .. } => {
             let table_info = catalog.get_table(table)?.clone();
+            catalog.query_counters().record_scan(&table_info.name);
             let data_tree = BTree::open(table_info.root_page);
-            let entries = data_tree.scan_all(pool)?;
+            let entries = snapshot::visible_entries(&table_info.name, data_tree.scan_all(pool)?);
 
             let prefix = alias.as_deref().unwrap_or(table);
             let col_names: Vec<String> = table_info
@@ -1784,11 +2517,26 @@ fn execute_plan_rows(
             Ok((col_names, rows))
         }
 
+        LogicalPlan::Filter { input, predicate } if literal_truth(predicate) == Some(false) => {
+            Ok((execute_plan_columns(input, pool, catalog)?, Vec::new()))
+        }
+
         LogicalPlan::Filter { input, predicate } => {
-            let (col_names, rows) = execute_plan_rows(input, pool, catalog)?;
+            let (col_names, rows) = match input.as_ref() {
+                LogicalPlan::SeqScan { table, method, .. } if *method != ScanMethod::FullScan => {
+                    let table_info = catalog.get_table(table)?;
+                    let data_tree = BTree::open(table_info.root_page);
+                    let entries = scan_with_index(Some(predicate), None, table, table_info, &data_tree, pool, catalog)?;
+                    let rows = entries.iter()
+                        .map(|entry| deserialize_row(&entry.value, table_info.columns.len()))
+                        .collect::<Result<Vec<_>>>()?;
+                    (execute_plan_columns(input, pool, catalog)?, rows)
+                }
+                _ => execute_plan_rows(input, pool, catalog)?,
+            };
             let mut filtered = Vec::new();
             for row in rows {
-                let val = eval_expr_dynamic(predicate, &row, &col_names)?;
+                let val = eval_expr_dynamic_with_ctx(predicate, &row, &col_names, pool, catalog)?;
                 if val.to_bool() {
                     filtered.push(row);
                 }
@@ -1801,11 +2549,12 @@ fn execute_plan_rows(
             if let LogicalPlan::Aggregate {
                 input: agg_input,
                 group_by,
+                modifier,
                 having,
             } = input.as_ref()
             {
                 return execute_aggregate_project(
-                    agg_input, group_by, having, columns, pool, catalog,
+                    agg_input, group_by, *modifier, having, columns, pool, catalog,
                 );
             }
 
@@ -1818,7 +2567,7 @@ fn execute_plan_rows(
             let mut out_rows = Vec::new();
             for row in &input_rows {
                 let projected =
-                    project_row_dynamic(columns, row, &input_col_names)?;
+                    project_row_dynamic(columns, row, &input_col_names, pool, catalog)?;
                 out_rows.push(projected);
             }
             Ok((out_col_names, out_rows))
@@ -1826,7 +2575,7 @@ fn execute_plan_rows(
 
         LogicalPlan::Sort { input, order_by } => {
             let (col_names, mut rows) = execute_plan_rows(input, pool, catalog)?;
-            sort_rows_dynamic(&mut rows, order_by, &col_names)?;
+            sort_rows_dynamic(&mut rows, order_by, &col_names, catalog)?;
             Ok((col_names, rows))
         }
 
@@ -1866,12 +2615,22 @@ fn execute_plan_rows(
             right,
             join_type,
             on,
-        } => execute_join(left, right, join_type, on, pool, catalog),
+            using,
+        } => execute_join(left, right, join_type, on, using, pool, catalog),
+
+        LogicalPlan::MergeJoin {
+            left,
+            right,
+            join_type,
+            left_key,
+            right_key,
+        } => execute_merge_join(left, right, join_type, *left_key, *right_key, pool, catalog),
 
         LogicalPlan::Aggregate {
             input,
             group_by,
             having,
+            ..
         } => {
             // Standalone aggregate without Project on top.
             let (col_names, rows) = execute_plan_rows(input, pool, catalog)?;
@@ -1953,17 +2712,168 @@ fn find_col_index(name: &str, cols: &[String]) -> Option<usize> {
     })
 }
 
+/// The column names of the rows `plan` produces, reading no rows of the
+/// tables it scans.
+fn execute_plan_columns(plan: &LogicalPlan, pool: &BufferPool, catalog: &Catalog) -> Result<Vec<String>> {
+    match plan {
+        LogicalPlan::SeqScan { table, alias, .. } => {
+            let prefix = alias.as_deref().unwrap_or(table);
+            let table_info = catalog.get_table(table)?;
+            Ok(table_info.columns.iter().map(|c| format!("{}.{}", prefix, c.name)).collect())
+        }
+        LogicalPlan::Filter { input, .. } => execute_plan_columns(input, pool, catalog),
+        LogicalPlan::Join { left, right, join_type, on, using } => {
+            let left_cols = execute_plan_columns(left, pool, catalog)?;
+            let right_cols = execute_plan_columns(right, pool, catalog)?;
+            let (cols, _) = if using.is_empty() {
+                join_rows(left_cols, Vec::new(), right_cols, Vec::new(), join_type, on, catalog)?
+            } else {
+                join_rows_using(left_cols, Vec::new(), right_cols, Vec::new(), join_type, using, catalog)?
+            };
+            Ok(cols)
+        }
+        LogicalPlan::MergeJoin { left, right, .. } => {
+            let mut cols = execute_plan_columns(left, pool, catalog)?;
+            cols.extend(execute_plan_columns(right, pool, catalog)?);
+            Ok(cols)
+        }
+        other => Ok(execute_plan_rows(other, pool, catalog)?.0),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn execute_join(
     left: &LogicalPlan,
     right: &LogicalPlan,
     join_type: &JoinType,
     on: &Option<Expr>,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    using: &[String],
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let (left_cols, left_rows) = execute_plan_rows(left, pool, catalog)?;
     let (right_cols, right_rows) = execute_plan_rows(right, pool, catalog)?;
 
+    if using.is_empty() {
+        return join_rows(left_cols, left_rows, right_cols, right_rows, join_type, on, catalog);
+    }
+    join_rows_using(left_cols, left_rows, right_cols, right_rows, join_type, using, catalog)
+}
+
+/// Join two inputs that come in the order of their join keys, the columns
+/// at `left_key` and `right_key`, with [`join::merge_join`].
+#[allow(clippy::too_many_arguments)]
+fn execute_merge_join(
+    left: &LogicalPlan,
+    right: &LogicalPlan,
+    join_type: &JoinType,
+    left_key: usize,
+    right_key: usize,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let (mut cols, left_rows) = execute_plan_rows(left, pool, catalog)?;
+    let (right_cols, right_rows) = execute_plan_rows(right, pool, catalog)?;
+    let (num_left, num_right) = (cols.len(), right_cols.len());
+    cols.extend(right_cols);
+    let rows = join::merge_join(left_rows, num_left, right_rows, num_right, left_key, right_key, join_type)?;
+    Ok((cols, rows))
+}
+
+/// Join two materialized inputs on equality of the named columns, emitting
+/// each of those columns once.
+fn join_rows_using(
+    left_cols: Vec<String>,
+    left_rows: Vec<Vec<Value>>,
+    right_cols: Vec<String>,
+    right_rows: Vec<Vec<Value>>,
+    join_type: &JoinType,
+    using: &[String],
+    catalog: &Catalog,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let pairs = using_column_pairs(using, &left_cols, &right_cols)?;
+    let on = using_join_condition(&pairs, &left_cols, &right_cols);
+    let num_left = left_cols.len();
+    let (cols, rows) = join_rows(left_cols, left_rows, right_cols, right_rows, join_type, &on, catalog)?;
+    Ok(merge_using_columns(&pairs, num_left, cols, rows))
+}
+
+/// Resolve each `USING` column to its index on the left and right inputs.
+fn using_column_pairs(
+    using: &[String],
+    left_cols: &[String],
+    right_cols: &[String],
+) -> Result<Vec<(usize, usize)>> {
+    using
+        .iter()
+        .map(|name| {
+            let l = find_col_index(name, left_cols);
+            let r = find_col_index(name, right_cols);
+            l.zip(r).ok_or_else(|| HorizonError::ColumnNotFound(name.clone()))
+        })
+        .collect()
+}
+
+/// Build `l.a = r.a AND l.b = r.b ...` for the resolved `USING` columns.
+fn using_join_condition(
+    pairs: &[(usize, usize)],
+    left_cols: &[String],
+    right_cols: &[String],
+) -> Option<Expr> {
+    pairs
+        .iter()
+        .map(|&(l, r)| Expr::BinaryOp {
+            left: Box::new(Expr::Column { table: None, name: left_cols[l].clone() }),
+            op: BinaryOp::Eq,
+            right: Box::new(Expr::Column { table: None, name: right_cols[r].clone() }),
+        })
+        .reduce(|acc, eq| Expr::BinaryOp {
+            left: Box::new(acc),
+            op: BinaryOp::And,
+            right: Box::new(eq),
+        })
+}
+
+/// Collapse each `USING` column pair into a single output column.
+///
+/// The left copy survives and takes the right value when the left side was
+/// NULL-padded (RIGHT/FULL joins); the right copy is dropped.
+fn merge_using_columns(
+    pairs: &[(usize, usize)],
+    num_left: usize,
+    mut cols: Vec<String>,
+    mut rows: Vec<Vec<Value>>,
+) -> RowSet {
+    let mut drop: Vec<usize> = pairs.iter().map(|&(_, r)| num_left + r).collect();
+    for row in &mut rows {
+        for &(l, r) in pairs {
+            if row[l].is_null() {
+                row[l] = row[num_left + r].clone();
+            }
+        }
+    }
+    drop.sort_unstable();
+    drop.dedup();
+    for &idx in drop.iter().rev() {
+        cols.remove(idx);
+        for row in &mut rows {
+            row.remove(idx);
+        }
+    }
+    (cols, rows)
+}
+
+/// Join two materialized inputs, using a hash join when `on` is a simple
+/// equi-join and a nested loop otherwise.
+fn join_rows(
+    left_cols: Vec<String>,
+    left_rows: Vec<Vec<Value>>,
+    right_cols: Vec<String>,
+    right_rows: Vec<Vec<Value>>,
+    join_type: &JoinType,
+    on: &Option<Expr>,
+    catalog: &Catalog,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let num_right = right_cols.len();
     let num_left = left_cols.len();
 
@@ -1974,10 +2884,10 @@ fn execute_join(
     if !matches!(join_type, JoinType::Cross) {
         if let Some(ref on_expr) = on {
             if let Some((left_key_idx, right_key_idx)) = extract_equi_join_keys(on_expr, &left_cols, &right_cols) {
-                return execute_hash_join(
-                    &left_rows, &right_rows, left_key_idx, right_key_idx,
-                    num_left, num_right, join_type, &merged_cols,
+                let join = join::GraceHashJoin::new(
+                    join_type.clone(), left_key_idx, right_key_idx, catalog.join_spill_threshold(),
                 );
+                return Ok((merged_cols, join.join(left_rows, num_left, right_rows, num_right)?));
             }
         }
     }
@@ -1990,6 +2900,7 @@ fn execute_join(
     match join_type {
         JoinType::Inner => {
             for l_row in &left_rows {
+                cancel::check()?;
                 for r_row in &right_rows {
                     let mut merged = l_row.clone();
                     merged.extend(r_row.iter().cloned());
@@ -2003,6 +2914,7 @@ fn execute_join(
         }
         JoinType::Left => {
             for l_row in &left_rows {
+                cancel::check()?;
                 let mut matched = false;
                 for r_row in &right_rows {
                     let mut merged = l_row.clone();
@@ -2023,6 +2935,7 @@ fn execute_join(
         }
         JoinType::Right => {
             for r_row in &right_rows {
+                cancel::check()?;
                 let mut matched = false;
                 for l_row in &left_rows {
                     let mut merged = l_row.clone();
@@ -2041,8 +2954,39 @@ fn execute_join(
                 }
             }
         }
+        JoinType::Full => {
+            // LEFT JOIN pass, remembering which right rows found a partner
+            let mut right_matched = vec![false; right_rows.len()];
+            for l_row in &left_rows {
+                cancel::check()?;
+                let mut matched = false;
+                for (ri, r_row) in right_rows.iter().enumerate() {
+                    let mut merged = l_row.clone();
+                    merged.extend(r_row.iter().cloned());
+                    if let Some(ref on_expr) = on {
+                        let val = eval_expr_dynamic(on_expr, &merged, &merged_cols)?;
+                        if !val.to_bool() { continue; }
+                    }
+                    matched = true;
+                    right_matched[ri] = true;
+                    result.push(merged);
+                }
+                if !matched {
+                    let mut merged = l_row.clone();
+                    merged.extend(null_right.iter().cloned());
+                    result.push(merged);
+                }
+            }
+            // Then the right rows that never matched, NULL-padded on the left
+            for (r_row, _) in right_rows.iter().zip(&right_matched).filter(|(_, &hit)| !hit) {
+                let mut merged = null_left.clone();
+                merged.extend(r_row.iter().cloned());
+                result.push(merged);
+            }
+        }
         JoinType::Cross => {
             for l_row in &left_rows {
+                cancel::check()?;
                 for r_row in &right_rows {
                     let mut merged = l_row.clone();
                     merged.extend(r_row.iter().cloned());
@@ -2057,6 +3001,7 @@ fn execute_join(
 
 /// Hash join implementation for equi-join conditions.
 /// Builds a HashMap on the smaller side for O(n+m) join instead of O(n*m).
+/// Inputs too large for this are joined by [`join::GraceHashJoin`].
 fn execute_hash_join(
     left_rows: &[Vec<Value>],
     right_rows: &[Vec<Value>],
@@ -2065,27 +3010,17 @@ fn execute_hash_join(
     num_left: usize,
     num_right: usize,
     join_type: &JoinType,
-    _merged_cols: &[String],
-) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+) -> Result<Vec<Vec<Value>>> {
     let null_right: Vec<Value> = vec![Value::Null; num_right];
     let null_left: Vec<Value> = vec![Value::Null; num_left];
     let mut result = Vec::new();
 
     match join_type {
         JoinType::Inner => {
-            // Build hash table on right side
-            let mut hash_map: HashMap<Value, Vec<usize>> = HashMap::new();
-            for (i, row) in right_rows.iter().enumerate() {
-                let key = &row[right_key_idx];
-                if !key.is_null() {
-                    hash_map.entry(key.clone()).or_default().push(i);
-                }
-            }
-            // Probe with left side
+            // Build hash table on right side, probe with left
+            let mut table = join::JoinHashTable::build(right_rows, right_key_idx);
             for l_row in left_rows {
-                let key = &l_row[left_key_idx];
-                if key.is_null() { continue; }
-                if let Some(matches) = hash_map.get(key) {
+                if let Some(matches) = table.probe(&l_row[left_key_idx]) {
                     for &ri in matches {
                         let mut merged = l_row.clone();
                         merged.extend(right_rows[ri].iter().cloned());
@@ -2095,19 +3030,10 @@ fn execute_hash_join(
             }
         }
         JoinType::Left => {
-            // Build hash table on right side
-            let mut hash_map: HashMap<Value, Vec<usize>> = HashMap::new();
-            for (i, row) in right_rows.iter().enumerate() {
-                let key = &row[right_key_idx];
-                if !key.is_null() {
-                    hash_map.entry(key.clone()).or_default().push(i);
-                }
-            }
-            // Probe with left side
+            // Build hash table on right side, probe with left
+            let mut table = join::JoinHashTable::build(right_rows, right_key_idx);
             for l_row in left_rows {
-                let key = &l_row[left_key_idx];
-                let matches = if key.is_null() { None } else { hash_map.get(key) };
-                if let Some(matches) = matches {
+                if let Some(matches) = table.probe(&l_row[left_key_idx]) {
                     for &ri in matches {
                         let mut merged = l_row.clone();
                         merged.extend(right_rows[ri].iter().cloned());
@@ -2122,17 +3048,9 @@ fn execute_hash_join(
         }
         JoinType::Right => {
             // Build hash table on left side, probe with right
-            let mut hash_map: HashMap<Value, Vec<usize>> = HashMap::new();
-            for (i, row) in left_rows.iter().enumerate() {
-                let key = &row[left_key_idx];
-                if !key.is_null() {
-                    hash_map.entry(key.clone()).or_default().push(i);
-                }
-            }
+            let mut table = join::JoinHashTable::build(left_rows, left_key_idx);
             for r_row in right_rows {
-                let key = &r_row[right_key_idx];
-                let matches = if key.is_null() { None } else { hash_map.get(key) };
-                if let Some(matches) = matches {
+                if let Some(matches) = table.probe(&r_row[right_key_idx]) {
                     for &li in matches {
                         let mut merged = left_rows[li].clone();
                         merged.extend(r_row.iter().cloned());
@@ -2145,12 +3063,36 @@ fn execute_hash_join(
                 }
             }
         }
+        JoinType::Full => {
+            // Build hash table on right side, tracking which right rows match
+            let mut table = join::JoinHashTable::build(right_rows, right_key_idx);
+            let mut right_matched = vec![false; right_rows.len()];
+            for l_row in left_rows {
+                if let Some(matches) = table.probe(&l_row[left_key_idx]) {
+                    for &ri in matches {
+                        right_matched[ri] = true;
+                        let mut merged = l_row.clone();
+                        merged.extend(right_rows[ri].iter().cloned());
+                        result.push(merged);
+                    }
+                } else {
+                    let mut merged = l_row.clone();
+                    merged.extend(null_right.iter().cloned());
+                    result.push(merged);
+                }
+            }
+            for (r_row, _) in right_rows.iter().zip(&right_matched).filter(|(_, &hit)| !hit) {
+                let mut merged = null_left.clone();
+                merged.extend(r_row.iter().cloned());
+                result.push(merged);
+            }
+        }
         JoinType::Cross => {
             unreachable!("CROSS JOIN should not reach hash join path");
         }
     }
 
-    Ok((_merged_cols.to_vec(), result))
+    Ok(result)
 }
 
 // ---- Aggregate + Project Execution ----
@@ -2160,60 +3102,76 @@ fn execute_hash_join(
 fn execute_aggregate_project(
     agg_input: &LogicalPlan,
     group_by: &[Expr],
+    modifier: Option<GroupByModifier>,
     having: &Option<Expr>,
     select_columns: &[SelectColumn],
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
     let (input_col_names, input_rows) = execute_plan_rows(agg_input, pool, catalog)?;
 
-    let groups = group_rows(&input_rows, group_by, &input_col_names)?;
-
     // Resolve output column names
     let out_col_names = resolve_column_names_dynamic(select_columns, &input_col_names)?;
 
-    let mut result = Vec::new();
+    let result = aggregate_rows(&input_rows, &input_col_names, group_by, modifier, having, select_columns)?;
+    Ok((out_col_names, result))
+}
 
-    for (_key, group) in &groups {
-        // Skip empty groups (shouldn't normally happen, but guard against it)
-        // However, for aggregates without GROUP BY on an empty table, we have
-        // one group with zero rows -- we still need to produce a result row.
-        let representative: Vec<Value> = if group.is_empty() {
-            vec![Value::Null; input_col_names.len()]
-        } else {
-            group[0].clone()
-        };
+/// Group `rows` by `group_by`, once for each of its grouping sets under
+/// `modifier`, and project each group that satisfies `having` into an
+/// output row of `select_columns`.
+fn aggregate_rows(
+    rows: &[Vec<Value>],
+    col_names: &[String],
+    group_by: &[Expr],
+    modifier: Option<GroupByModifier>,
+    having: &Option<Expr>,
+    select_columns: &[SelectColumn],
+) -> Result<Vec<Vec<Value>>> {
+    let mut result = Vec::new();
 
-        // Apply HAVING filter
-        if let Some(ref having_expr) = having {
-            let val = eval_aggregate_expr(having_expr, &representative, &input_col_names, group)?;
-            if !val.to_bool() {
-                continue;
-            }
-        }
+    for grouped in grouping::grouping_sets(group_by, modifier) {
+        let groups = group_rows(rows, &group_by[..grouped], col_names)?;
+        let having = having.as_ref().map(|h| grouping::rolled_up(h, group_by, grouped));
+        let exprs: Vec<Option<Expr>> = select_columns
+            .iter()
+            .map(|col| match col {
+                SelectColumn::Expr { expr, .. } => Some(grouping::rolled_up(expr, group_by, grouped)),
+                _ => None,
+            })
+            .collect();
 
-        // Project each group into an output row
-        let mut out_row = Vec::new();
+        for (_key, group) in &groups {
+            // For aggregates without GROUP BY on an empty table, we have
+            // one group with zero rows -- we still need to produce a result row.
+            let representative: Vec<Value> = if group.is_empty() {
+                vec![Value::Null; col_names.len()]
+            } else {
+                group[0].clone()
+            };
 
-        for col in select_columns {
-            match col {
-                SelectColumn::AllColumns => {
-                    out_row.extend(representative.iter().cloned());
+            // Apply HAVING filter
+            if let Some(ref having_expr) = having {
+                let val = eval_aggregate_expr(having_expr, &representative, col_names, group)?;
+                if !val.to_bool() {
+                    continue;
                 }
-                SelectColumn::TableAllColumns(_) => {
-                    out_row.extend(representative.iter().cloned());
-                }
-                SelectColumn::Expr { expr, .. } => {
-                    let val = eval_aggregate_expr(expr, &representative, &input_col_names, group)?;
-                    out_row.push(val);
+            }
+
+            // Project each group into an output row
+            let mut out_row = Vec::new();
+            for expr in &exprs {
+                match expr {
+                    Some(expr) => out_row.push(eval_aggregate_expr(expr, &representative, col_names, group)?),
+                    // `*` and `table.*`
+                    None => out_row.extend(representative.iter().cloned()),
                 }
             }
+            result.push(out_row);
         }
-
-        result.push(out_row);
     }
 
-    Ok((out_col_names, result))
+    Ok(result)
 }
 
 /// Group rows by GROUP BY expressions. Returns an ordered list of (key, group_rows).
@@ -2222,13 +3180,13 @@ fn group_rows(
     rows: &[Vec<Value>],
     group_by: &[Expr],
     col_names: &[String],
-) -> Result<Vec<(Vec<Value>, Vec<Vec<Value>>)>> {
+) -> Result<Vec<RowGroup>> {
     if group_by.is_empty() {
         // Entire input is one group
         return Ok(vec![(vec![], rows.to_vec())]);
     }
 
-    let mut map: Vec<(Vec<Value>, Vec<Vec<Value>>)> = Vec::new();
+    let mut map: Vec<RowGroup> = Vec::new();
 
     for row in rows {
         let mut key = Vec::new();
@@ -2272,7 +3230,7 @@ fn eval_expr_dynamic(
         Expr::BinaryOp { left, op, right } => {
             let l = eval_expr_dynamic(left, row, col_names)?;
             let r = eval_expr_dynamic(right, row, col_names)?;
-            Ok(eval_binary_op(&l, op, &r))
+            eval_collated_binary_op(left, &l, op, right, &r)
         }
 
         Expr::UnaryOp { op, expr: inner } => {
@@ -2316,7 +3274,7 @@ fn eval_expr_dynamic(
             Ok(Value::Integer(if matches != *negated { 1 } else { 0 }))
         }
 
-        Expr::Function { name, args, distinct: _ } => {
+        Expr::Function { name, args, .. } => {
             eval_function_dynamic(name, args, row, col_names)
         }
 
@@ -2350,7 +3308,7 @@ fn eval_expr_dynamic(
             }
         }
 
-        Expr::Placeholder(_) => Ok(Value::Null),
+        Expr::Placeholder(_) | Expr::NamedPlaceholder(_) => Ok(Value::Null),
         Expr::Subquery(_) | Expr::Exists(_) => {
             Err(HorizonError::NotImplemented("subqueries in expressions".into()))
         }
@@ -2415,6 +3373,59 @@ fn find_column_dynamic(
     Err(HorizonError::ColumnNotFound(col_ref.to_string()))
 }
 
+/// Evaluate `ROW_TO_JSON(*)` or `ROW_TO_JSON(t.*)`: the columns of `row`
+/// named `col_names`, or those of table `t`, as a JSON object keyed by
+/// column name. An unqualified column name belongs to any table. A name
+/// several of the columns share, as in `*` over a join, is kept qualified
+/// (`a.id`) so that no key is repeated.
+fn row_to_json(args: &[Expr], row: &[Value], col_names: &[String]) -> Result<Value> {
+    let table = match args {
+        [Expr::Column { table, name }] if name == "*" => table.as_deref(),
+        _ => return Err(HorizonError::InvalidSql("row_to_json() takes * or table.* as its argument".into())),
+    };
+    let columns: Vec<(&str, &str, &Value)> = col_names
+        .iter()
+        .zip(row)
+        .filter_map(|(col, value)| {
+            let (qualifier, name) = match col.rsplit_once('.') {
+                Some((qualifier, name)) => (Some(qualifier), name),
+                None => (None, col.as_str()),
+            };
+            match (table, qualifier) {
+                (Some(table), Some(qualifier)) if !table.eq_ignore_ascii_case(qualifier) => None,
+                _ => Some((col.as_str(), name, value)),
+            }
+        })
+        .collect();
+    let shared = |name: &str| columns.iter().filter(|(_, other, _)| other.eq_ignore_ascii_case(name)).count() > 1;
+    let fields = columns
+        .iter()
+        .map(|&(col, name, value)| {
+            let key = if shared(name) { col } else { name };
+            (key.to_string(), json::sql_value_to_json(value))
+        })
+        .collect();
+    Ok(Value::Text(json::JsonValue::Object(fields).to_json_string()))
+}
+
+/// Evaluate `RAISE(action, message)` from a trigger body. `ABORT`, `FAIL`,
+/// and `ROLLBACK` fail the statement that fired the trigger with `message`.
+fn eval_raise(args: &[Expr], eval_message: impl FnOnce(&Expr) -> Result<Value>) -> Result<Value> {
+    let action = match args.first() {
+        Some(Expr::Column { table: None, name }) => name.to_uppercase(),
+        _ => String::new(),
+    };
+    match (action.as_str(), args.get(1)) {
+        ("ABORT" | "FAIL" | "ROLLBACK", Some(message)) if args.len() == 2 => {
+            Err(HorizonError::ConstraintViolation(eval_message(message)?.to_string()))
+        }
+        ("IGNORE", None) => Err(HorizonError::NotImplemented("RAISE(IGNORE)".into())),
+        _ => Err(HorizonError::InvalidSql(
+            "RAISE expects ABORT, FAIL, or ROLLBACK with a message, or IGNORE".into(),
+        )),
+    }
+}
+
 /// Evaluate a scalar function in dynamic (column-name) context.
 fn eval_function_dynamic(
     name: &str,
@@ -2424,6 +3435,7 @@ fn eval_function_dynamic(
 ) -> Result<Value> {
     let upper = name.to_uppercase();
     match upper.as_str() {
+        "RAISE" => eval_raise(args, |message| eval_expr_dynamic(message, row, col_names)),
         "ABS" => {
             let val = eval_expr_dynamic(&args[0], row, col_names)?;
             match val {
@@ -2432,15 +3444,6 @@ fn eval_function_dynamic(
                 _ => Ok(Value::Null),
             }
         }
-        "LENGTH" | "LEN" => {
-            let val = eval_expr_dynamic(&args[0], row, col_names)?;
-            match val {
-                Value::Text(s) => Ok(Value::Integer(s.len() as i64)),
-                Value::Blob(b) => Ok(Value::Integer(b.len() as i64)),
-                Value::Null => Ok(Value::Null),
-                _ => Ok(Value::Integer(format!("{}", val).len() as i64)),
-            }
-        }
         "UPPER" => {
             let val = eval_expr_dynamic(&args[0], row, col_names)?;
             match val {
@@ -2521,7 +3524,8 @@ fn eval_function_dynamic(
             Ok(min_val)
         }
         // Aggregate functions in per-row context just evaluate the argument
-        "MAX" | "MIN" | "COUNT" | "SUM" | "AVG" | "TOTAL" | "GROUP_CONCAT" => {
+        "MAX" | "MIN" | "COUNT" | "SUM" | "AVG" | "TOTAL" | "GROUP_CONCAT" | "JSON_AGG"
+        | "STDDEV" | "STDDEV_POP" | "STDDEV_SAMP" | "VARIANCE" | "VAR_POP" | "VAR_SAMP" => {
             let is_star = args.len() == 1 && matches!(&args[0], Expr::Column { table: None, name } if name == "*");
             if is_star || args.is_empty() {
                 // COUNT(*) in per-row context: return 1 for each row
@@ -2530,37 +3534,6 @@ fn eval_function_dynamic(
                 eval_expr_dynamic(&args[0], row, col_names)
             }
         }
-        "SUBSTR" | "SUBSTRING" => {
-            if args.len() < 2 {
-                return Ok(Value::Null);
-            }
-            let val = eval_expr_dynamic(&args[0], row, col_names)?;
-            let start = eval_expr_dynamic(&args[1], row, col_names)?;
-            let len = if args.len() > 2 {
-                eval_expr_dynamic(&args[2], row, col_names)?.as_integer()
-            } else {
-                None
-            };
-            match (val, start.as_integer()) {
-                (Value::Text(s), Some(start_pos)) => {
-                    let start_idx = if start_pos > 0 { (start_pos - 1) as usize } else { 0 };
-                    let substr_result = if let Some(l) = len {
-                        s.chars().skip(start_idx).take(l as usize).collect::<String>()
-                    } else {
-                        s.chars().skip(start_idx).collect::<String>()
-                    };
-                    Ok(Value::Text(substr_result))
-                }
-                _ => Ok(Value::Null),
-            }
-        }
-        "TRIM" => {
-            let val = eval_expr_dynamic(&args[0], row, col_names)?;
-            match val {
-                Value::Text(s) => Ok(Value::Text(s.trim().to_string())),
-                _ => Ok(val),
-            }
-        }
         "REPLACE" => {
             if args.len() < 3 {
                 return Ok(Value::Null);
@@ -2575,35 +3548,6 @@ fn eval_function_dynamic(
                 _ => Ok(Value::Null),
             }
         }
-        "INSTR" => {
-            if args.len() < 2 {
-                return Ok(Value::Null);
-            }
-            let val = eval_expr_dynamic(&args[0], row, col_names)?;
-            let search = eval_expr_dynamic(&args[1], row, col_names)?;
-            match (val, search) {
-                (Value::Text(s), Value::Text(needle)) => {
-                    Ok(Value::Integer(s.find(&needle).map(|i| i as i64 + 1).unwrap_or(0)))
-                }
-                _ => Ok(Value::Integer(0)),
-            }
-        }
-        "LTRIM" => {
-            if args.is_empty() { return Ok(Value::Null); }
-            let val = eval_expr_dynamic(&args[0], row, col_names)?;
-            match val {
-                Value::Text(s) => Ok(Value::Text(s.trim_start().to_string())),
-                _ => Ok(val),
-            }
-        }
-        "RTRIM" => {
-            if args.is_empty() { return Ok(Value::Null); }
-            let val = eval_expr_dynamic(&args[0], row, col_names)?;
-            match val {
-                Value::Text(s) => Ok(Value::Text(s.trim_end().to_string())),
-                _ => Ok(val),
-            }
-        }
         "HEX" => {
             if args.is_empty() { return Ok(Value::Null); }
             let val = eval_expr_dynamic(&args[0], row, col_names)?;
@@ -2652,6 +3596,7 @@ fn eval_function_dynamic(
                 _ => Ok(Value::Null),
             }
         }
+        "UUID" | "UUID_BLOB" => eval_uuid_function(&upper, args.len()),
         "RANDOM" => {
             // Simple pseudo-random integer using system time
             let now = std::time::SystemTime::now()
@@ -2702,17 +3647,7 @@ fn eval_function_dynamic(
             let json_val = eval_expr_dynamic(&args[0], row, col_names)?;
             let path_val = eval_expr_dynamic(&args[1], row, col_names)?;
             match (json_val, path_val) {
-                (Value::Text(s), Value::Text(path)) => {
-                    match json::JsonParser::parse(&s) {
-                        Some(jv) => {
-                            match jv.extract_path(&path) {
-                                Some(extracted) => Ok(json::json_value_to_sql(extracted)),
-                                None => Ok(Value::Null),
-                            }
-                        }
-                        None => Ok(Value::Null),
-                    }
-                }
+                (Value::Text(s), Value::Text(path)) => Ok(json::json_extract(&s, &path)),
                 _ => Ok(Value::Null),
             }
         }
@@ -2744,6 +3679,7 @@ fn eval_function_dynamic(
                 _ => Ok(Value::Null),
             }
         }
+        "ROW_TO_JSON" => row_to_json(args, row, col_names),
         "JSON_ARRAY" => {
             let mut items = Vec::new();
             for arg in args {
@@ -2820,6 +3756,13 @@ fn eval_function_dynamic(
                 _ => Ok(Value::Integer(0)),
             }
         }
+        _ if json::is_json_mutation(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr_dynamic(arg, row, col_names)?);
+            }
+            json::eval_json_mutation(&upper, &arg_values)
+        }
         // -- Additional utility functions --
         "PRINTF" => {
             if args.is_empty() { return Ok(Value::Null); }
@@ -2845,7 +3788,7 @@ fn eval_function_dynamic(
                                 match v {
                                     Value::Integer(i) => result.push_str(&i.to_string()),
                                     Value::Real(r) => result.push_str(&(r as i64).to_string()),
-                                    _ => result.push_str("0"),
+                                    _ => result.push('0'),
                                 }
                             }
                             ci += 1;
@@ -2920,21 +3863,53 @@ fn eval_function_dynamic(
             }
             Ok(Value::Text(result))
         }
+        "REGEXP" => {
+            if args.len() != 2 {
+                return Err(HorizonError::InvalidSql("regexp() takes 2 arguments".into()));
+            }
+            let pattern = eval_expr_dynamic(&args[0], row, col_names)?;
+            let text = eval_expr_dynamic(&args[1], row, col_names)?;
+            regexp_match(&pattern, &text)
+        }
         // -- Date/Time functions --
-        "DATE" | "TIME" | "DATETIME" | "STRFTIME" | "JULIANDAY" => {
+        "DATE" | "TIME" | "DATETIME" | "STRFTIME" | "JULIANDAY" | "UNIXEPOCH" => {
             let mut arg_values = Vec::new();
             for arg in args {
                 arg_values.push(eval_expr_dynamic(arg, row, col_names)?);
             }
             Ok(eval_datetime_function(&upper, &arg_values))
         }
-        _ => Err(HorizonError::NotImplemented(format!("function: {}", name))),
-    }
-}
-
-// =========================================================================
-// Date/Time helpers (no external dependencies -- pure manual parsing)
-// =========================================================================
+        // -- Hash functions --
+        _ if crypto::is_hash_function(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr_dynamic(arg, row, col_names)?);
+            }
+            crypto::eval_hash_function(&upper, &arg_values)
+        }
+        // -- Math functions --
+        _ if math::is_math_function(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr_dynamic(arg, row, col_names)?);
+            }
+            math::eval_math_function(&upper, &arg_values)
+        }
+        // -- String functions --
+        _ if strings::is_string_function(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr_dynamic(arg, row, col_names)?);
+            }
+            strings::eval_string_function(&upper, &arg_values)
+        }
+        _ => Err(HorizonError::NotImplemented(format!("function: {}", name))),
+    }
+}
+
+// =========================================================================
+// Date/Time helpers (no external dependencies -- pure manual parsing)
+// =========================================================================
 
 /// A parsed date-time value used internally by date/time functions.
 #[derive(Debug, Clone)]
@@ -2946,9 +3921,41 @@ struct DateTime {
     minute: u32, // 0..=59
     second: u32, // 0..=59
     millisecond: u32, // 0..=999
+    /// Seconds the value has been shifted from UTC by a 'localtime'
+    /// modifier, so that a later 'utc' modifier can undo it.
+    utc_offset: i64,
 }
 
 impl DateTime {
+    /// The date-time `secs` seconds after 1970-01-01 00:00:00, or None if
+    /// that is out of range.
+    fn from_unix_epoch(secs: f64) -> Option<DateTime> {
+        let millis = (secs * 1000.0).round();
+        if !millis.is_finite() || millis.abs() > 1e17 {
+            return None;
+        }
+        let millis = millis as i64;
+        let (days, ms_of_day) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+        let (year, month, day) = civil_from_days(days);
+        let secs_of_day = (ms_of_day / 1000) as u32;
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day % 3600 / 60,
+            second: secs_of_day % 60,
+            millisecond: (ms_of_day % 1000) as u32,
+            utc_offset: 0,
+        })
+    }
+
+    /// Whole seconds since 1970-01-01 00:00:00.
+    fn to_unix_epoch(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86400
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+    }
+
     /// Format as 'YYYY-MM-DD'.
     fn format_date(&self) -> String {
         format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
@@ -3040,13 +4047,7 @@ impl DateTime {
                     }
                     's' => {
                         // Seconds since 1970-01-01 00:00:00 UTC
-                        let epoch = DateTime {
-                            year: 1970, month: 1, day: 1,
-                            hour: 0, minute: 0, second: 0, millisecond: 0,
-                        };
-                        let diff_jd = self.to_julian_day() - epoch.to_julian_day();
-                        let secs = (diff_jd * 86400.0) as i64;
-                        result.push_str(&format!("{}", secs));
+                        result.push_str(&format!("{}", self.to_unix_epoch()));
                     }
                     '%' => result.push('%'),
                     c => {
@@ -3075,6 +4076,13 @@ impl DateTime {
         }
     }
 
+    /// Add N seconds (can be negative).
+    fn add_seconds(&mut self, n: i64) {
+        if let Some(shifted) = DateTime::from_unix_epoch((self.to_unix_epoch() + n) as f64) {
+            *self = DateTime { millisecond: self.millisecond, utc_offset: self.utc_offset, ..shifted };
+        }
+    }
+
     /// Add N months (can be negative).
     fn add_months(&mut self, n: i64) {
         let total_months = self.year * 12 + (self.month as i64 - 1) + n;
@@ -3173,24 +4181,7 @@ fn day_of_week(year: i64, month: u32, day: u32) -> u32 {
 fn parse_timestring(s: &str) -> Option<DateTime> {
     let s = s.trim();
     if s.eq_ignore_ascii_case("now") {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default();
-        let total_secs = now.as_secs();
-        let millis = now.subsec_millis();
-
-        let total_days = (total_secs / 86400) as i64;
-        let time_of_day = total_secs % 86400;
-        let hour = (time_of_day / 3600) as u32;
-        let minute = ((time_of_day % 3600) / 60) as u32;
-        let second = (time_of_day % 60) as u32;
-
-        let (year, month, day) = civil_from_days(total_days);
-
-        return Some(DateTime {
-            year, month, day, hour, minute, second,
-            millisecond: millis,
-        });
+        return DateTime::from_unix_epoch(unix_now().as_secs_f64());
     }
 
     if s.len() < 10 {
@@ -3203,12 +4194,13 @@ fn parse_timestring(s: &str) -> Option<DateTime> {
     if s.as_bytes().get(7) != Some(&b'-') { return None; }
     let day = dt_parse_u32(&s[8..10])?;
 
-    if month < 1 || month > 12 { return None; }
+    if !(1..=12).contains(&month) { return None; }
     if day < 1 || day > days_in_month(year, month) { return None; }
 
     let mut dt = DateTime {
         year, month, day,
         hour: 0, minute: 0, second: 0, millisecond: 0,
+        utc_offset: 0,
     };
 
     if s.len() == 10 {
@@ -3276,6 +4268,31 @@ fn apply_modifier(dt: &mut DateTime, modifier: &str) -> bool {
         return true;
     }
 
+    // Convert from UTC to local time, and back
+    if s.eq_ignore_ascii_case("localtime") {
+        let offset = local_utc_offset(dt.to_unix_epoch());
+        dt.add_seconds(offset);
+        dt.utc_offset += offset;
+        return true;
+    }
+
+    if s.eq_ignore_ascii_case("utc") {
+        let offset = if dt.utc_offset != 0 {
+            dt.utc_offset
+        } else {
+            local_utc_offset(dt.to_unix_epoch())
+        };
+        dt.add_seconds(-offset);
+        dt.utc_offset = 0;
+        return true;
+    }
+
+    // '+HH:MM', '-HH:MM', '+HH:MM:SS'
+    if let Some(secs) = dt_parse_offset(s) {
+        dt.add_seconds(secs);
+        return true;
+    }
+
     // '+N days', '-N days', '+N months', '-N months', '+N years', '-N years'
     let parts: Vec<&str> = s.splitn(2, ' ').collect();
     if parts.len() == 2 {
@@ -3306,15 +4323,39 @@ fn apply_modifier(dt: &mut DateTime, modifier: &str) -> bool {
 fn dt_parse_modifier_number(s: &str) -> Option<i64> {
     let s = s.trim();
     if s.is_empty() { return None; }
-    if s.starts_with('+') {
-        dt_parse_i64(&s[1..])
-    } else if s.starts_with('-') {
-        dt_parse_i64(&s[1..]).map(|v| -v)
+    if let Some(rest) = s.strip_prefix('+') {
+        dt_parse_i64(rest)
+    } else if let Some(rest) = s.strip_prefix('-') {
+        dt_parse_i64(rest).map(|v| -v)
     } else {
         dt_parse_i64(s)
     }
 }
 
+/// Parse a time offset modifier like "+05:30", "-01:00" or "+00:00:30"
+/// into seconds.
+fn dt_parse_offset(s: &str) -> Option<i64> {
+    let (sign, rest) = if let Some(rest) = s.strip_prefix('+') {
+        (1, rest)
+    } else {
+        (-1, s.strip_prefix('-')?)
+    };
+    let parts: Vec<&str> = rest.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 || parts.iter().any(|p| p.len() != 2) {
+        return None;
+    }
+    let hours = dt_parse_i64(parts[0])?;
+    let minutes = dt_parse_i64(parts[1])?;
+    let seconds = match parts.get(2) {
+        Some(p) => dt_parse_i64(p)?,
+        None => 0,
+    };
+    if minutes > 59 || seconds > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60 + seconds))
+}
+
 /// Simple integer parser for date/time (no external deps).
 fn dt_parse_i64(s: &str) -> Option<i64> {
     let s = s.trim();
@@ -3334,6 +4375,79 @@ fn dt_parse_u32(s: &str) -> Option<u32> {
     Some(v as u32)
 }
 
+/// The time elapsed since 1970-01-01 00:00:00 UTC.
+fn unix_now() -> std::time::Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// The local time zone's offset from UTC, in seconds, at `secs` seconds
+/// after the Unix epoch. Time zones are only known on Unix; elsewhere local
+/// time is taken to be UTC.
+fn local_utc_offset(secs: i64) -> i64 {
+    #[cfg(unix)]
+    {
+        use std::os::raw::{c_char, c_int, c_long};
+
+        // `struct tm` as laid out by glibc, musl, and the BSDs (macOS
+        // included), which all carry the offset in `tm_gmtoff`.
+        #[repr(C)]
+        struct Tm {
+            tm_sec: c_int,
+            tm_min: c_int,
+            tm_hour: c_int,
+            tm_mday: c_int,
+            tm_mon: c_int,
+            tm_year: c_int,
+            tm_wday: c_int,
+            tm_yday: c_int,
+            tm_isdst: c_int,
+            tm_gmtoff: c_long,
+            tm_zone: *const c_char,
+        }
+        extern "C" {
+            fn tzset();
+            fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+        }
+
+        let time = match c_long::try_from(secs) {
+            Ok(t) => t,
+            Err(_) => return 0,
+        };
+        let mut tm = std::mem::MaybeUninit::<Tm>::zeroed();
+        // SAFETY: `time` and `tm` are valid for the duration of the call,
+        // and `tm` is only read after localtime_r reports success.
+        unsafe {
+            tzset();
+            if localtime_r(&time, tm.as_mut_ptr()).is_null() {
+                return 0;
+            }
+            // `c_long` is only 32 bits wide on some targets
+            #[allow(clippy::unnecessary_cast)]
+            let offset = tm.assume_init().tm_gmtoff as i64;
+            offset
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = secs;
+        0
+    }
+}
+
+/// Convert (year, month, day) to days since the Unix epoch (1970-01-01);
+/// the inverse of [`civil_from_days`].
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
 /// Convert days since Unix epoch (1970-01-01) to (year, month, day).
 /// Civil date algorithm from Howard Hinnant.
 fn civil_from_days(days: i64) -> (i64, u32, u32) {
@@ -3350,129 +4464,85 @@ fn civil_from_days(days: i64) -> (i64, u32, u32) {
     (y_final, m as u32, d as u32)
 }
 
+/// Check that the function `name` was called with an allowed number of
+/// arguments.
+fn check_arity(name: &str, args: &[Value], arity: std::ops::RangeInclusive<usize>) -> Result<()> {
+    if arity.contains(&args.len()) {
+        return Ok(());
+    }
+    let expected = if arity.start() == arity.end() {
+        arity.start().to_string()
+    } else {
+        format!("{} or {}", arity.start(), arity.end())
+    };
+    Err(HorizonError::InvalidSql(format!(
+        "{}() takes {} argument{}",
+        name.to_lowercase(),
+        expected,
+        if expected == "1" { "" } else { "s" }
+    )))
+}
+
+/// Parse a time value and apply the modifiers that follow it, returning
+/// None if either is invalid.
+///
+/// A `'unixepoch'` modifier directly after the time value reads it as a
+/// number of seconds since 1970-01-01 00:00:00 instead of a time string.
+fn eval_timestring(ts: &Value, modifiers: &[Value]) -> Option<DateTime> {
+    let is_unixepoch = |v: &Value| matches!(v, Value::Text(m) if m.trim().eq_ignore_ascii_case("unixepoch"));
+    let (mut dt, modifiers) = match modifiers.first() {
+        Some(first) if is_unixepoch(first) => {
+            let secs = match ts {
+                Value::Integer(i) => *i as f64,
+                Value::Real(r) => *r,
+                Value::Text(s) => s.trim().parse::<f64>().ok()?,
+                _ => return None,
+            };
+            (DateTime::from_unix_epoch(secs)?, &modifiers[1..])
+        }
+        _ => match ts {
+            Value::Text(s) => (parse_timestring(s)?, modifiers),
+            _ => return None,
+        },
+    };
+    for arg in modifiers {
+        match arg {
+            Value::Text(m) if apply_modifier(&mut dt, m) => {}
+            _ => return None,
+        }
+    }
+    Some(dt)
+}
+
 /// Evaluate a date/time function given its name and already-evaluated arguments.
 fn eval_datetime_function(name: &str, arg_values: &[Value]) -> Value {
     let upper = name.to_uppercase();
+    // STRFTIME takes its format first; the time value and modifiers follow.
+    let (fmt, time_args) = match upper.as_str() {
+        "STRFTIME" => match arg_values.split_first() {
+            Some((Value::Text(fmt), rest)) => (Some(fmt.as_str()), rest),
+            _ => return Value::Null,
+        },
+        _ => (None, arg_values),
+    };
+    // UNIXEPOCH() with no arguments is the current time.
+    if upper == "UNIXEPOCH" && time_args.is_empty() {
+        return Value::Integer(unix_now().as_secs() as i64);
+    }
+    let dt = match time_args.split_first() {
+        Some((ts, modifiers)) => match eval_timestring(ts, modifiers) {
+            Some(dt) => dt,
+            None => return Value::Null,
+        },
+        None => return Value::Null,
+    };
     match upper.as_str() {
-        "DATE" => {
-            if arg_values.is_empty() {
-                return Value::Null;
-            }
-            let ts = match &arg_values[0] {
-                Value::Text(s) => s.as_str(),
-                _ => return Value::Null,
-            };
-            let mut dt = match parse_timestring(ts) {
-                Some(d) => d,
-                None => return Value::Null,
-            };
-            for arg in &arg_values[1..] {
-                if let Value::Text(m) = arg {
-                    if !apply_modifier(&mut dt, m) {
-                        return Value::Null;
-                    }
-                } else {
-                    return Value::Null;
-                }
-            }
-            Value::Text(dt.format_date())
-        }
-        "TIME" => {
-            if arg_values.is_empty() {
-                return Value::Null;
-            }
-            let ts = match &arg_values[0] {
-                Value::Text(s) => s.as_str(),
-                _ => return Value::Null,
-            };
-            let mut dt = match parse_timestring(ts) {
-                Some(d) => d,
-                None => return Value::Null,
-            };
-            for arg in &arg_values[1..] {
-                if let Value::Text(m) = arg {
-                    if !apply_modifier(&mut dt, m) {
-                        return Value::Null;
-                    }
-                } else {
-                    return Value::Null;
-                }
-            }
-            Value::Text(dt.format_time())
-        }
-        "DATETIME" => {
-            if arg_values.is_empty() {
-                return Value::Null;
-            }
-            let ts = match &arg_values[0] {
-                Value::Text(s) => s.as_str(),
-                _ => return Value::Null,
-            };
-            let mut dt = match parse_timestring(ts) {
-                Some(d) => d,
-                None => return Value::Null,
-            };
-            for arg in &arg_values[1..] {
-                if let Value::Text(m) = arg {
-                    if !apply_modifier(&mut dt, m) {
-                        return Value::Null;
-                    }
-                } else {
-                    return Value::Null;
-                }
-            }
-            Value::Text(dt.format_datetime())
-        }
-        "STRFTIME" => {
-            if arg_values.len() < 2 {
-                return Value::Null;
-            }
-            let fmt = match &arg_values[0] {
-                Value::Text(s) => s.clone(),
-                _ => return Value::Null,
-            };
-            let ts = match &arg_values[1] {
-                Value::Text(s) => s.as_str(),
-                _ => return Value::Null,
-            };
-            let mut dt = match parse_timestring(ts) {
-                Some(d) => d,
-                None => return Value::Null,
-            };
-            for arg in &arg_values[2..] {
-                if let Value::Text(m) = arg {
-                    if !apply_modifier(&mut dt, m) {
-                        return Value::Null;
-                    }
-                } else {
-                    return Value::Null;
-                }
-            }
-            Value::Text(dt.strftime(&fmt))
-        }
-        "JULIANDAY" => {
-            if arg_values.is_empty() {
-                return Value::Null;
-            }
-            let ts = match &arg_values[0] {
-                Value::Text(s) => s.as_str(),
-                _ => return Value::Null,
-            };
-            let mut dt = match parse_timestring(ts) {
-                Some(d) => d,
-                None => return Value::Null,
-            };
-            for arg in &arg_values[1..] {
-                if let Value::Text(m) = arg {
-                    if !apply_modifier(&mut dt, m) {
-                        return Value::Null;
-                    }
-                } else {
-                    return Value::Null;
-                }
-            }
-            Value::Real(dt.to_julian_day())
-        }
+        "DATE" => Value::Text(dt.format_date()),
+        "TIME" => Value::Text(dt.format_time()),
+        "DATETIME" => Value::Text(dt.format_datetime()),
+        "STRFTIME" => Value::Text(dt.strftime(fmt.unwrap_or_default())),
+        "JULIANDAY" => Value::Real(dt.to_julian_day()),
+        "UNIXEPOCH" => Value::Integer(dt.to_unix_epoch()),
         _ => Value::Null,
     }
 }
@@ -3501,6 +4571,20 @@ fn quote_value(val: &Value) -> String {
     }
 }
 
+/// The population variance of `values`, or the sample variance (with an
+/// `n - 1` denominator) when `sample` is set. Computed in two passes, mean
+/// first, to avoid the cancellation of the naive sum-of-squares formula.
+/// None when there are too few values.
+fn variance(values: &[f64], sample: bool) -> Option<f64> {
+    let n = values.len();
+    if n == 0 || (sample && n == 1) {
+        return None;
+    }
+    let mean = values.iter().sum::<f64>() / n as f64;
+    let squares: f64 = values.iter().map(|v| (v - mean) * (v - mean)).sum();
+    Some(squares / if sample { (n - 1) as f64 } else { n as f64 })
+}
+
 /// Evaluate an expression that may contain aggregate functions, given a group of rows.
 /// For aggregate functions (COUNT, SUM, etc.), compute over the entire group.
 /// For non-aggregate expressions, evaluate against the representative row.
@@ -3511,7 +4595,30 @@ fn eval_aggregate_expr(
     group: &[Vec<Value>],
 ) -> Result<Value> {
     match expr {
-        Expr::Function { name, args, distinct: _ } => {
+        Expr::Function { name, args, distinct, filter: Some(cond) } => {
+            // Aggregate over only the rows that pass the FILTER condition.
+            let mut rows = Vec::new();
+            for row in group {
+                if eval_expr_dynamic(cond, row, col_names)?.to_bool() {
+                    rows.push(row.clone());
+                }
+            }
+            let call = Expr::Function { name: name.clone(), args: args.clone(), distinct: *distinct, filter: None };
+            eval_aggregate_expr(&call, representative, col_names, &rows)
+        }
+        Expr::Function { name, args, distinct: true, filter: None } if !args.is_empty() => {
+            // Keep one row per distinct value of the aggregated expression.
+            let mut seen = HashSet::new();
+            let mut rows = Vec::new();
+            for row in group {
+                if seen.insert(eval_expr_dynamic(&args[0], row, col_names)?) {
+                    rows.push(row.clone());
+                }
+            }
+            let call = Expr::Function { name: name.clone(), args: args.clone(), distinct: false, filter: None };
+            eval_aggregate_expr(&call, representative, col_names, &rows)
+        }
+        Expr::Function { name, args, .. } => {
             let upper = name.to_uppercase();
             match upper.as_str() {
                 "COUNT" => {
@@ -3662,6 +4769,20 @@ fn eval_aggregate_expr(
                         Ok(Value::Text(parts.join(&separator)))
                     }
                 }
+                "JSON_AGG" => {
+                    if args.is_empty() {
+                        return Ok(Value::Null);
+                    }
+                    if group.is_empty() {
+                        return Ok(Value::Null);
+                    }
+                    // Unlike GROUP_CONCAT, NULLs are kept, as JSON nulls
+                    let mut items = Vec::new();
+                    for row in group {
+                        items.push(json::sql_value_to_json(&eval_expr_dynamic(&args[0], row, col_names)?));
+                    }
+                    Ok(Value::Text(json::JsonValue::Array(items).to_json_string()))
+                }
                 "TOTAL" => {
                     if args.is_empty() {
                         return Ok(Value::Real(0.0));
@@ -3677,6 +4798,26 @@ fn eval_aggregate_expr(
                     }
                     Ok(Value::Real(sum))
                 }
+                "STDDEV" | "STDDEV_POP" | "STDDEV_SAMP" | "VARIANCE" | "VAR_POP" | "VAR_SAMP" => {
+                    if args.is_empty() {
+                        return Ok(Value::Null);
+                    }
+                    let mut values = Vec::new();
+                    for row in group {
+                        match eval_expr_dynamic(&args[0], row, col_names)? {
+                            Value::Integer(i) => values.push(i as f64),
+                            Value::Real(r) => values.push(r),
+                            _ => {}
+                        }
+                    }
+                    let upper = name.to_uppercase();
+                    let sample = upper.ends_with("_SAMP");
+                    Ok(match variance(&values, sample) {
+                        Some(var) if upper.starts_with("STDDEV") => Value::Real(var.sqrt()),
+                        Some(var) => Value::Real(var),
+                        None => Value::Null,
+                    })
+                }
                 // Non-aggregate functions: evaluate on representative row
                 _ => eval_function_dynamic(name, args, representative, col_names),
             }
@@ -3686,7 +4827,7 @@ fn eval_aggregate_expr(
         Expr::BinaryOp { left, op, right } => {
             let l = eval_aggregate_expr(left, representative, col_names, group)?;
             let r = eval_aggregate_expr(right, representative, col_names, group)?;
-            Ok(eval_binary_op(&l, op, &r))
+            eval_collated_binary_op(left, &l, op, right, &r)
         }
         Expr::UnaryOp { op, expr: inner } => {
             let val = eval_aggregate_expr(inner, representative, col_names, group)?;
@@ -3783,6 +4924,8 @@ fn project_row_dynamic(
     select_cols: &[SelectColumn],
     row: &[Value],
     col_names: &[String],
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<Vec<Value>> {
     let mut values = Vec::new();
     for col in select_cols {
@@ -3799,7 +4942,7 @@ fn project_row_dynamic(
                 }
             }
             SelectColumn::Expr { expr, .. } => {
-                let val = eval_expr_dynamic(expr, row, col_names)?;
+                let val = eval_expr_dynamic_with_ctx(expr, row, col_names, pool, catalog)?;
                 values.push(val);
             }
         }
@@ -3809,37 +4952,28 @@ fn project_row_dynamic(
 
 /// Sort rows using column-name-based evaluation.
 fn sort_rows_dynamic(
-    rows: &mut [Vec<Value>],
+    rows: &mut Vec<Vec<Value>>,
     order_by: &[OrderByItem],
     col_names: &[String],
+    catalog: &Catalog,
 ) -> Result<()> {
-    rows.sort_by(|a, b| {
-        for item in order_by {
-            let val_a = eval_expr_dynamic(&item.expr, a, col_names).unwrap_or(Value::Null);
-            let val_b = eval_expr_dynamic(&item.expr, b, col_names).unwrap_or(Value::Null);
-            let cmp = if let Some(coll) = extract_collation(&item.expr) {
-                compare_with_collation(&val_a, &val_b, coll)
-            } else {
-                val_a.cmp(&val_b)
-            };
-            let cmp = if item.desc { cmp.reverse() } else { cmp };
-            if cmp != std::cmp::Ordering::Equal {
-                return cmp;
-            }
-        }
-        std::cmp::Ordering::Equal
-    });
-    Ok(())
+    sort::sort_by_terms(rows, order_by, catalog.sort_spill_threshold(), catalog.collations(), |expr, row| {
+        eval_expr_dynamic(expr, row, col_names).unwrap_or(Value::Null)
+    })
 }
 
-fn execute_select_no_from(select: &SelectStatement) -> Result<QueryResult> {
+fn execute_select_no_from(select: &SelectStatement, pool: &BufferPool, catalog: &Catalog) -> Result<QueryResult> {
     let mut column_names = Vec::new();
     let mut values = Vec::new();
 
     for col in &select.columns {
         match col {
             SelectColumn::Expr { expr, alias } => {
-                let val = eval_const_expr(expr);
+                let val = if expr_has_subquery(expr) {
+                    eval_expr_dynamic_with_ctx(expr, &[], &[], pool, catalog)?
+                } else {
+                    eval_expr_dynamic(expr, &[], &[])?
+                };
                 let name = alias.clone().unwrap_or_else(|| format!("{:?}", expr));
                 column_names.push(name);
                 values.push(val);
@@ -3863,25 +4997,51 @@ fn execute_select_no_from(select: &SelectStatement) -> Result<QueryResult> {
 /// 1. Primary key seek (for INTEGER PK equality/range)
 /// 2. Secondary index scan
 /// 3. Full table scan (fallback)
+///
+/// A query reading at a snapshot always scans the whole table, since the
+/// indexes only describe the current rows.
+///
+/// `read_columns` lists the columns the caller reads from the returned rows
+/// besides those in the WHERE clause, or is `None` if it may read any; see
+/// [`try_index_scan`].
 fn scan_with_index(
     where_clause: Option<&Expr>,
+    read_columns: Option<&[usize]>,
     table_name: &str,
     table: &TableInfo,
     tree: &BTree,
-    pool: &mut BufferPool,
+    pool: &BufferPool,
     catalog: &Catalog,
 ) -> Result<Vec<crate::btree::BTreeEntry>> {
+    catalog.query_counters().record_scan(&table.name);
+    if snapshot::is_active() {
+        return Ok(snapshot::visible_entries(&table.name, tree.scan_all(pool)?));
+    }
     if let Some(where_expr) = where_clause {
-        if let Some(pk_entries) = try_pk_seek(where_expr, table, tree, pool)? {
-            return Ok(pk_entries);
-        }
-        if let Some(index_entries) = try_index_scan(where_expr, table_name, table, pool, catalog)? {
-            return Ok(index_entries);
+        if let Some(entries) = try_indexed_scan(where_expr, read_columns, table_name, table, tree, pool, catalog)? {
+            return Ok(entries);
         }
     }
     tree.scan_all(pool)
 }
 
+/// Try to satisfy a WHERE clause with a primary-key seek or a secondary index
+/// scan. Returns `None` when neither applies and a full scan is required.
+fn try_indexed_scan(
+    where_expr: &Expr,
+    read_columns: Option<&[usize]>,
+    table_name: &str,
+    table: &TableInfo,
+    tree: &BTree,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<Option<Vec<crate::btree::BTreeEntry>>> {
+    if let Some(pk_entries) = try_pk_seek(where_expr, table, tree, pool)? {
+        return Ok(Some(pk_entries));
+    }
+    try_index_scan(where_expr, read_columns, table_name, table, pool, catalog)
+}
+
 // ---- PRIMARY KEY SEEK ----
 
 /// Attempt to use the primary key B+Tree directly for point lookups and range scans.
@@ -3891,58 +5051,15 @@ fn try_pk_seek(
     where_clause: &Expr,
     table: &TableInfo,
     tree: &BTree,
-    pool: &mut BufferPool,
+    pool: &BufferPool,
 ) -> Result<Option<Vec<crate::btree::BTreeEntry>>> {
-    let pk_idx = match table.pk_column {
-        Some(idx) => idx,
-        None => return Ok(None),
-    };
-    let pk_col = &table.columns[pk_idx];
-    if pk_col.affinity != DataType::Integer {
-        return Ok(None);
+    if table.has_composite_pk() {
+        return try_composite_pk_seek(where_clause, table, tree, pool);
     }
-    let pk_name = &pk_col.name;
-
-    // Try to extract a PK predicate from the WHERE clause
-    if let Some(entries) = try_pk_predicate(where_clause, pk_name, tree, pool)? {
-        return Ok(Some(entries));
-    }
-
-    // Try to extract PK = N from an AND chain (e.g. `id = 5 AND name = 'foo'`)
-    if let Expr::BinaryOp { left, op: BinaryOp::And, right } = where_clause {
-        if let Some(entries) = try_pk_predicate(left, pk_name, tree, pool)? {
-            return Ok(Some(entries));
-        }
-        if let Some(entries) = try_pk_predicate(right, pk_name, tree, pool)? {
-            return Ok(Some(entries));
-        }
-    }
-
-    Ok(None)
-}
-
-/// Try to match a single expression against `pk_col = N` or range patterns.
-fn try_pk_predicate(
-    expr: &Expr,
-    pk_name: &str,
-    tree: &BTree,
-    pool: &mut BufferPool,
-) -> Result<Option<Vec<crate::btree::BTreeEntry>>> {
-    let (col_name, op, val) = match extract_index_predicate(expr) {
+    let (op, rowid) = match integer_pk_predicate(where_clause, table) {
         Some(p) => p,
         None => return Ok(None),
     };
-    if !col_name.eq_ignore_ascii_case(pk_name) {
-        // Also handle qualified names like "table.id"
-        let unqualified = col_name.rsplit('.').next().unwrap_or(&col_name);
-        if !unqualified.eq_ignore_ascii_case(pk_name) {
-            return Ok(None);
-        }
-    }
-    let rowid = match &val {
-        Value::Integer(n) => *n,
-        _ => return Ok(None),
-    };
 
     match op {
         BinaryOp::Eq => {
@@ -3976,132 +5093,536 @@ fn try_pk_predicate(
     }
 }
 
+/// Find a `pk_col op N` predicate on the INTEGER PRIMARY KEY of `table` in
+/// a WHERE clause, either the whole clause or one side of a top-level AND
+/// (e.g. `id = 5 AND name = 'foo'`).
+fn integer_pk_predicate(where_clause: &Expr, table: &TableInfo) -> Option<(BinaryOp, i64)> {
+    let pk_col = &table.columns[table.pk_column?];
+    if pk_col.affinity != DataType::Integer {
+        return None;
+    }
+    let on_pk = |expr: &Expr| {
+        let (col_name, op, val) = extract_index_predicate(expr)?;
+        // Also handle qualified names like "table.id"
+        let unqualified = col_name.rsplit('.').next().unwrap_or(&col_name);
+        if !unqualified.eq_ignore_ascii_case(&pk_col.name) {
+            return None;
+        }
+        match val {
+            Value::Integer(n) => Some((op.clone(), n)),
+            _ => None,
+        }
+    };
+    on_pk(where_clause).or_else(|| match where_clause {
+        Expr::BinaryOp { left, op: BinaryOp::And, right } => on_pk(left).or_else(|| on_pk(right)),
+        _ => None,
+    })
+}
+
+/// Look up a row of a table with a composite primary key when the WHERE
+/// clause is an AND chain holding an equality for every key column.
+fn try_composite_pk_seek(
+    where_clause: &Expr,
+    table: &TableInfo,
+    tree: &BTree,
+    pool: &BufferPool,
+) -> Result<Option<Vec<crate::btree::BTreeEntry>>> {
+    let values = match composite_pk_values(where_clause, table) {
+        Some(values) => values,
+        None => return Ok(None),
+    };
+    let key = build_index_prefix(&values);
+    match tree.search(pool, &key)? {
+        Some(data) => Ok(Some(vec![crate::btree::BTreeEntry { key, value: data }])),
+        None => Ok(Some(vec![])),
+    }
+}
+
+/// The values an AND chain fixes every composite primary key column of
+/// `table` to, in key order, or `None` if it leaves one unconstrained.
+fn composite_pk_values(where_clause: &Expr, table: &TableInfo) -> Option<Vec<Value>> {
+    let mut conjuncts = vec![where_clause];
+    let mut key_values: Vec<Option<Value>> = vec![None; table.pk_columns.len()];
+    while let Some(expr) = conjuncts.pop() {
+        if let Expr::BinaryOp { left, op: BinaryOp::And, right } = expr {
+            conjuncts.push(left);
+            conjuncts.push(right);
+            continue;
+        }
+        if let Some((col_name, BinaryOp::Eq, val)) = extract_index_predicate(expr) {
+            let unqualified = col_name.rsplit('.').next().unwrap_or(&col_name);
+            let pos = table.pk_columns.iter()
+                .position(|&i| table.columns[i].name.eq_ignore_ascii_case(unqualified));
+            if let Some(pos) = pos {
+                let affinity = table.columns[table.pk_columns[pos]].affinity;
+                key_values[pos] = Some(val.apply_affinity(affinity));
+            }
+        }
+    }
+    key_values.into_iter().collect()
+}
+
 // ---- INDEX SCAN ----
 
 /// Attempt to use an index to satisfy a WHERE clause predicate.
 /// Returns Some(entries) if an index scan was performed, where entries are
 /// the matching rows from the data table (key=rowid, value=serialized row).
 /// Returns None if no suitable index exists or the predicate is too complex.
+///
+/// The WHERE clause may be an AND chain. An index is usable when its leading
+/// columns are constrained by equalities, optionally followed by one column
+/// with range bounds; the index expected to visit the fewest entries is
+/// chosen (see [`estimate_index_rows`]).
+/// Expression key parts are matched by structural equality, so
+/// `lower(name) = 'x'` can use an index on `lower(name)`.
+/// The scan may return a superset of the matching rows (e.g. `>` is scanned
+/// as `>=`), so callers must still apply the full WHERE clause. A partial
+/// index is only considered when the WHERE clause implies its predicate.
+///
+/// When `read_columns` is given and the chosen index holds those columns
+/// and every column of the WHERE clause, the scan is
+/// [covering](IndexScanMode::CoveringIndex): the rows are decoded from the
+/// index keys, with NULL for the columns the index lacks, and the table
+/// itself is not read.
 fn try_index_scan(
     where_clause: &Expr,
+    read_columns: Option<&[usize]>,
     table_name: &str,
     table: &TableInfo,
-    pool: &mut BufferPool,
+    pool: &BufferPool,
     catalog: &Catalog,
 ) -> Result<Option<Vec<crate::btree::BTreeEntry>>> {
-    // Extract a simple predicate: column op literal
-    let pred = match extract_index_predicate(where_clause) {
-        Some(p) => p,
+    let (bounds, idx) = match choose_index(where_clause, table_name, table, catalog) {
+        Some(choice) => choice,
         None => return Ok(None),
     };
+    let covered = read_columns.and_then(|read| covered_key_columns(idx, table, read, where_clause));
+    let mode = match covered {
+        Some(_) => IndexScanMode::CoveringIndex,
+        None => IndexScanMode::RowLookup,
+    };
 
-    let (col_name, op, search_val) = pred;
-
-    // Find an index on this table that has this column as its first (or only) column
-    let indexes = catalog.get_indexes_for_table(table_name);
-    let matching_index = indexes.iter().find(|idx| {
-        !idx.columns.is_empty() && idx.columns[0].eq_ignore_ascii_case(&col_name)
-    });
+    let index_tree = index_tree(idx, catalog)?;
+    let data_tree = BTree::open(table.root_page);
 
-    let index_info = match matching_index {
-        Some(idx) => (*idx).clone(),
-        None => return Ok(None),
+    // Index keys are [sortable column values][rowid]. Every key matching
+    // the equality prefix lies in [prefix, successor(prefix)); range bounds
+    // on the next column narrow that interval.
+    let prefix = build_index_prefix(&bounds.eq_values);
+    let start = match bounds.lower {
+        Some(ref low) => {
+            let mut start = prefix.clone();
+            low.serialize_sortable(&mut start);
+            start
+        }
+        None => prefix.clone(),
+    };
+    let end = match bounds.upper {
+        Some(ref high) => {
+            let mut end = prefix.clone();
+            high.serialize_sortable(&mut end);
+            compute_successor_prefix(&end)
+        }
+        None => compute_successor_prefix(&prefix),
+    };
+    let index_entries = match end {
+        Some(ref end) => index_tree.scan_range(pool, &start, end)?,
+        None => index_tree.scan_from(pool, &start)?,
     };
 
-    // Only use index for single-column indexes to keep it simple
-    if index_info.columns.len() != 1 {
-        return Ok(None);
+    // Look up rows by rowid from the data table, unless the index key
+    // holds every value the query reads
+    let mut result = Vec::with_capacity(index_entries.len());
+    for entry in index_entries {
+        if mode == IndexScanMode::CoveringIndex {
+            let row = covered.as_deref().and_then(|covered| decode_covered_row(&entry.key, covered, table));
+            if let Some(row) = row {
+                result.push(crate::btree::BTreeEntry { key: entry.value, value: serialize_row(&row) });
+                continue;
+            }
+        }
+        if let Some(row_data) = data_tree.search(pool, &entry.value)? {
+            result.push(crate::btree::BTreeEntry {
+                key: entry.value,
+                value: row_data,
+            });
+        }
     }
 
-    // Verify the column exists in the table
-    let _col_idx = match table.find_column_index(&col_name) {
-        Some(idx) => idx,
-        None => return Ok(None),
-    };
+    Ok(Some(result))
+}
 
-    let index_tree = BTree::open(index_info.root_page);
+/// The index [`try_index_scan`] reads to satisfy a WHERE clause, with the
+/// part of its key the clause constrains, or `None` if no index applies.
+fn choose_index<'a>(
+    where_clause: &Expr,
+    table_name: &str,
+    table: &TableInfo,
+    catalog: &'a Catalog,
+) -> Option<(IndexBounds, &'a crate::catalog::IndexInfo)> {
+    let mut predicates = Vec::new();
+    collect_index_predicates(where_clause, &mut predicates);
+    if predicates.is_empty() {
+        return None;
+    }
+
+    let mut best: Option<(f64, IndexBounds, &crate::catalog::IndexInfo)> = None;
+    for idx in catalog.get_indexes_for_table(table_name) {
+        let missing_column = idx.columns.iter().enumerate()
+            .any(|(i, c)| idx.key_expr(i).is_none() && table.find_column_index(c).is_none());
+        if missing_column {
+            continue;
+        }
+        if let Some(ref predicate) = idx.predicate {
+            if !where_implies(where_clause, predicate) {
+                continue;
+            }
+        }
+        let bounds = index_bounds(idx, &predicates);
+        if bounds.eq_values.is_empty() && bounds.lower.is_none() && bounds.upper.is_none() {
+            continue;
+        }
+        let cost = estimate_index_rows(idx, &bounds, table, catalog);
+        // Indexes come in no particular order: break ties in favour of the
+        // narrower index, then by name, so the choice does not vary.
+        let better = best.as_ref().map_or(true, |(c, _, b)| {
+            cost < *c
+                || (cost == *c
+                    && (idx.columns.len(), &idx.name) < (b.columns.len(), &b.name))
+        });
+        if better {
+            best = Some((cost, bounds, idx));
+        }
+    }
+    best.map(|(_, bounds, idx)| (bounds, idx))
+}
+
+/// The [`ScanMethod`] [`scan_with_index`] uses to read the rows of `table`
+/// matching `where_clause`.
+pub(crate) fn scan_method(where_clause: &Expr, table: &TableInfo, catalog: &Catalog) -> ScanMethod {
+    if snapshot::is_active() {
+        return ScanMethod::FullScan;
+    }
+    if table.has_composite_pk() {
+        if composite_pk_values(where_clause, table).is_some() {
+            let cols = table.pk_columns.iter().map(|&i| table.columns[i].name.clone()).collect();
+            return ScanMethod::CompositePkSeek { cols };
+        }
+    } else if let Some((op, _)) = integer_pk_predicate(where_clause, table) {
+        let col = table.columns[table.pk_column.unwrap_or_default()].name.clone();
+        return ScanMethod::PkSeek { col, op };
+    }
+    match choose_index(where_clause, &table.name, table, catalog) {
+        Some((bounds, idx)) => {
+            let range = bounds.lower.is_some() || bounds.upper.is_some();
+            let constrained = bounds.eq_values.len() + usize::from(range);
+            let cols = (0..constrained)
+                .map(|i| match idx.key_expr(i) {
+                    Some(expr) => expr_to_sql(expr),
+                    None => idx.columns[i].clone(),
+                })
+                .collect();
+            ScanMethod::IndexScan {
+                index_name: idx.name.clone(),
+                cols,
+                lower: bounds.lower.is_some(),
+                upper: bounds.upper.is_some(),
+            }
+        }
+        None => ScanMethod::FullScan,
+    }
+}
+
+/// The index to read the rows of `table`, visible as `name`, through for
+/// a SELECT filtered by `where_clause` and sorted by `order_by`, because
+/// its keys are in that order; or `None` if no index is, or the WHERE
+/// clause is better served by a seek.
+///
+/// Every ORDER BY term must be ascending and name the column of the key
+/// part at its position, with the same collation if the key part has one.
+pub(crate) fn order_by_index<'a>(
+    where_clause: Option<&Expr>,
+    order_by: &[OrderByItem],
+    name: &str,
+    table: &TableInfo,
+    catalog: &'a Catalog,
+) -> Option<&'a crate::catalog::IndexInfo> {
+    if snapshot::is_active() || order_by.is_empty() || order_by.iter().any(|item| item.desc) {
+        return None;
+    }
+    if where_clause.is_some_and(|w| scan_method(w, table, catalog) != ScanMethod::FullScan) {
+        return None;
+    }
+    let mut indexes = catalog.get_indexes_for_table(&table.name);
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+    indexes.into_iter().find(|idx| {
+        let holds_rows = match idx.predicate {
+            Some(ref predicate) => where_clause.is_some_and(|w| where_implies(w, predicate)),
+            None => true,
+        };
+        holds_rows
+            && order_by.len() <= idx.columns.len()
+            && order_by.iter().enumerate().all(|(i, item)| orders_like_key_part(&item.expr, idx, i, name, table))
+    })
+}
+
+/// Whether the ORDER BY term `term` orders the rows of `table`, visible as
+/// `name`, like key part `i` of `idx`.
+fn orders_like_key_part(term: &Expr, idx: &crate::catalog::IndexInfo, i: usize, name: &str, table: &TableInfo) -> bool {
+    let column = |expr: &Expr| match expr {
+        Expr::Column { table: qualifier, name: column }
+            if qualifier.as_deref().map_or(true, |q| q.eq_ignore_ascii_case(name)) =>
+        {
+            table.find_column_index(column)
+        }
+        _ => None,
+    };
+    match (idx.key_expr(i), term) {
+        (None, term) => column(term).is_some() && column(term) == table.find_column_index(&idx.columns[i]),
+        (Some(Expr::Collate { expr: key, collation: key_collation }), Expr::Collate { expr, collation }) => {
+            collation.eq_ignore_ascii_case(key_collation) && column(expr).is_some() && column(expr) == column(key)
+        }
+        (Some(_), _) => false,
+    }
+}
+
+/// Read every row of `table` in the order of the keys of `index`.
+fn scan_in_index_order(
+    index: &crate::catalog::IndexInfo,
+    table: &TableInfo,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<Vec<crate::btree::BTreeEntry>> {
+    catalog.query_counters().record_scan(&table.name);
     let data_tree = BTree::open(table.root_page);
+    let mut result = Vec::new();
+    for entry in index_tree(index, catalog)?.scan_all(pool)? {
+        if let Some(row_data) = data_tree.search(pool, &entry.value)? {
+            result.push(crate::btree::BTreeEntry { key: entry.value, value: row_data });
+        }
+    }
+    Ok(result)
+}
 
-    // Build the search prefix from the predicate value (without rowid)
-    let search_prefix = build_index_prefix(&[search_val.clone()]);
+/// How an index scan produces the rows of the index entries it visits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndexScanMode {
+    /// Look each row up in the table by the rowid stored in the entry.
+    RowLookup,
+    /// Decode the row from the entry's key, which holds every column the
+    /// query reads.
+    CoveringIndex,
+}
 
-    // For prefix-based scanning, we need a key that is strictly greater than
-    // any key starting with the prefix. We compute this by incrementing the
-    // last byte of the prefix (with carry).
-    let end_prefix = compute_successor_prefix(&search_prefix);
+/// For an index holding the columns `read` and those of `where_clause`,
+/// the column of `table` each key part fills in a covered row (`None` for
+/// the parts not read). Returns `None` if the index does not cover them.
+///
+/// A column is covered when it is a plain key part whose affinity lets
+/// [`decode_covered_value`] tell the type of its values.
+fn covered_key_columns(
+    idx: &crate::catalog::IndexInfo,
+    table: &TableInfo,
+    read: &[usize],
+    where_clause: &Expr,
+) -> Option<Vec<Option<usize>>> {
+    let mut needed = columns_read(std::iter::once(where_clause), table)?;
+    needed.extend_from_slice(read);
+    let key_columns: Vec<Option<usize>> = idx.columns.iter().enumerate()
+        .map(|(i, col)| match idx.key_expr(i) {
+            Some(_) => None,
+            None => table.find_column_index(col),
+        })
+        .collect();
+    let decodable = |&col: &usize| matches!(table.columns[col].affinity, DataType::Integer | DataType::Real | DataType::Text);
+    if !needed.iter().all(|col| key_columns.contains(&Some(*col)) && decodable(col)) {
+        return None;
+    }
+    Some(key_columns.into_iter().map(|col| col.filter(|col| needed.contains(col))).collect())
+}
 
-    // Get rowids from the index based on the operator.
-    // Index keys are [column_value_serialized][rowid_bytes].
-    // We compare only the prefix (column value) portion for semantic matching,
-    // but since we still apply the WHERE clause for correctness, using a
-    // scan_from/scan_range that returns a superset is fine.
-    let index_entries: Vec<Vec<u8>> = match op {
-        BinaryOp::Eq => {
-            // Scan all index entries whose key starts with search_prefix.
-            // These are in range [search_prefix, end_prefix).
-            let entries = if let Some(ref end) = end_prefix {
-                index_tree.scan_range(pool, &search_prefix, end)?
-            } else {
-                index_tree.scan_from(pool, &search_prefix)?
-            };
-            entries.into_iter().map(|e| e.value).collect()
-        }
-        BinaryOp::Gt | BinaryOp::GtEq => {
-            // For >= val: scan from search_prefix onward
-            // For > val: scan from end_prefix onward (skip all entries with this exact value)
-            if matches!(op, BinaryOp::GtEq) {
-                let entries = index_tree.scan_from(pool, &search_prefix)?;
-                entries.into_iter().map(|e| e.value).collect()
-            } else {
-                // > val: skip everything with this prefix
-                if let Some(ref end) = end_prefix {
-                    let entries = index_tree.scan_from(pool, end)?;
-                    entries.into_iter().map(|e| e.value).collect()
-                } else {
-                    // No successor means this was the maximum possible prefix
-                    vec![]
-                }
-            }
+/// Decode a row of `table` from an index key, filling the columns given by
+/// `covered` for each key part and leaving the others NULL. Returns `None`
+/// if a value's type cannot be told from its key encoding.
+fn decode_covered_row(key: &[u8], covered: &[Option<usize>], table: &TableInfo) -> Option<Vec<Value>> {
+    let mut row = vec![Value::Null; table.columns.len()];
+    let mut rest = key;
+    for col in covered {
+        let (value, len) = Value::deserialize_sortable(rest)?;
+        rest = &rest[len..];
+        if let Some(col) = *col {
+            row[col] = decode_covered_value(value, table.columns[col].affinity)?;
         }
-        BinaryOp::Lt | BinaryOp::LtEq => {
-            // For < val: scan from beginning up to search_prefix (exclusive)
-            // For <= val: scan from beginning up to end_prefix (exclusive)
-            if matches!(op, BinaryOp::Lt) {
-                let entries = index_tree.scan_all(pool)?;
-                entries.into_iter()
-                    .take_while(|e| e.key.as_slice() < search_prefix.as_slice())
-                    .map(|e| e.value)
-                    .collect()
-            } else {
-                // <= val: include everything with this prefix
-                if let Some(ref end) = end_prefix {
-                    let entries = index_tree.scan_all(pool)?;
-                    entries.into_iter()
-                        .take_while(|e| e.key.as_slice() < end.as_slice())
-                        .map(|e| e.value)
-                        .collect()
-                } else {
-                    // No successor means include everything
-                    let entries = index_tree.scan_all(pool)?;
-                    entries.into_iter().map(|e| e.value).collect()
-                }
+    }
+    Some(row)
+}
+
+/// Recover the stored value of a column with `affinity` from its decoded
+/// index key value, in which every number is a REAL. INTEGER affinity
+/// stores integral numbers as integers; beyond 2^53 the key may have
+/// rounded them, and a REAL zero may have been `-0.0`, so these are not
+/// decoded. TEXT affinity stores no numbers.
+fn decode_covered_value(value: Value, affinity: DataType) -> Option<Value> {
+    const EXACT_INTEGERS: f64 = 9_007_199_254_740_992.0;
+    match (value, affinity) {
+        (Value::Real(r), DataType::Integer) if r.trunc() == r && r.abs() <= EXACT_INTEGERS => {
+            Some(Value::Integer(r as i64))
+        }
+        (Value::Real(r), DataType::Integer) if r.trunc() == r => None,
+        (Value::Real(r), DataType::Real) => (r != 0.0).then_some(Value::Real(r)),
+        (Value::Real(_), DataType::Text) => None,
+        (value, _) => Some(value),
+    }
+}
+
+/// The columns of `table` read by `exprs`, or `None` if they may read
+/// anything else: a subquery, a column `table` lacks, or the like.
+fn columns_read<'a>(exprs: impl IntoIterator<Item = &'a Expr>, table: &TableInfo) -> Option<Vec<usize>> {
+    let mut columns = Vec::new();
+    let mut known = true;
+    for expr in exprs {
+        let _ = params::walk_expr(&mut expr.clone(), &mut |e| {
+            match e {
+                Expr::Column { name, .. } => match table.find_column_index(name) {
+                    Some(col) => columns.push(col),
+                    None => known = false,
+                },
+                Expr::Subquery(_) | Expr::Exists(_) | Expr::Match { .. } | Expr::WindowFunction { .. } => known = false,
+                _ => {}
             }
+            Ok(())
+        });
+    }
+    known.then_some(columns)
+}
+
+/// The columns a single-table SELECT reads from its rows besides those in
+/// its WHERE clause, or `None` if it may read any.
+fn select_read_columns(select: &SelectStatement, table: &TableInfo) -> Option<Vec<usize>> {
+    if table_has_virtual_columns(table) {
+        return None;
+    }
+    let mut exprs = Vec::new();
+    for column in &select.columns {
+        match column {
+            SelectColumn::Expr { expr, .. } => exprs.push(expr),
+            SelectColumn::AllColumns | SelectColumn::TableAllColumns(_) => return None,
         }
-        _ => return Ok(None),
+    }
+    exprs.extend(select.order_by.iter().map(|item| &item.expr));
+    columns_read(exprs, table)
+}
+
+/// Estimate how many index entries a scan with `bounds` visits.
+///
+/// An equality on the leading key column is estimated from its `ANALYZE`
+/// statistics when there are any. Otherwise, as in SQLite, the table is
+/// assumed to be large and each equality to keep a tenth of the rows, and a
+/// range to keep a quarter, so that without statistics the index with the
+/// most constrained key wins.
+fn estimate_index_rows(
+    idx: &crate::catalog::IndexInfo,
+    bounds: &IndexBounds,
+    table: &TableInfo,
+    catalog: &Catalog,
+) -> f64 {
+    const UNANALYZED_ROWS: f64 = 1_000_000.0;
+    let leading = match idx.key_expr(0) {
+        Some(_) => None,
+        None => catalog.get_column_stats(&table.name, &idx.columns[0]),
     };
+    let table_rows = catalog.analyzed_row_count(&table.name)
+        .map_or(UNANALYZED_ROWS, |rows| rows as f64);
 
-    // Look up rows by rowid from the data table
-    let mut result = Vec::with_capacity(index_entries.len());
-    for rowid_bytes in &index_entries {
-        if let Some(row_data) = data_tree.search(pool, rowid_bytes)? {
-            result.push(crate::btree::BTreeEntry {
-                key: rowid_bytes.clone(),
-                value: row_data,
-            });
-        }
+    let eq_columns = bounds.eq_values.len() as i32;
+    let mut rows = match leading {
+        Some(stats) if eq_columns > 0 => stats.rows_per_value() / 10f64.powi(eq_columns - 1),
+        _ => table_rows / 10f64.powi(eq_columns),
+    };
+    if bounds.lower.is_some() || bounds.upper.is_some() {
+        rows /= 4.0;
     }
+    rows
+}
 
-    Ok(Some(result))
+/// The part of an index key constrained by a WHERE clause.
+struct IndexBounds {
+    /// Values of the leading index columns fixed by equalities.
+    eq_values: Vec<Value>,
+    /// Inclusive bounds on the column after the equality prefix.
+    lower: Option<Value>,
+    upper: Option<Value>,
+}
+
+/// Collect the `expr op constant` predicates of an AND chain.
+fn collect_index_predicates<'a>(expr: &'a Expr, out: &mut Vec<(&'a Expr, &'a BinaryOp, Value)>) {
+    if let Expr::BinaryOp { left, op: BinaryOp::And, right } = expr {
+        collect_index_predicates(left, out);
+        collect_index_predicates(right, out);
+    } else if let Some(pred) = extract_key_predicate(expr) {
+        out.push(pred);
+    }
+}
+
+/// Split an AND chain into its conjuncts.
+fn conjuncts<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) {
+    if let Expr::BinaryOp { left, op: BinaryOp::And, right } = expr {
+        conjuncts(left, out);
+        conjuncts(right, out);
+    } else {
+        out.push(expr);
+    }
+}
+
+/// Whether `where_clause` implies a partial index `predicate`. This is a
+/// simple subsumption check: every conjunct of the predicate must appear
+/// verbatim among the conjuncts of the WHERE clause.
+fn where_implies(where_clause: &Expr, predicate: &Expr) -> bool {
+    let mut have = Vec::new();
+    conjuncts(where_clause, &mut have);
+    let mut need = Vec::new();
+    conjuncts(predicate, &mut need);
+    need.iter().all(|n| have.contains(n))
+}
+
+/// Work out how far `predicates` constrain the key of `idx`.
+fn index_bounds(
+    idx: &crate::catalog::IndexInfo,
+    predicates: &[(&Expr, &BinaryOp, Value)],
+) -> IndexBounds {
+    let mut bounds = IndexBounds { eq_values: Vec::new(), lower: None, upper: None };
+    for (i, col) in idx.columns.iter().enumerate() {
+        let key_expr = idx.key_expr(i);
+        let on_col = || predicates.iter().filter(move |(lhs, _, _)| match (key_expr, lhs) {
+            // A value's bytes do not bound keys in the order of a collation
+            (Some(Expr::Collate { .. }), _) => false,
+            (Some(expr), lhs) => *lhs == expr,
+            (None, Expr::Column { name, .. }) => name.eq_ignore_ascii_case(col),
+            (None, _) => false,
+        });
+        if let Some((_, _, val)) = on_col().find(|(_, op, _)| **op == BinaryOp::Eq) {
+            bounds.eq_values.push(val.clone());
+            continue;
+        }
+        for (_, op, val) in on_col() {
+            // Keep the tightest bound on each side
+            match op {
+                BinaryOp::Gt | BinaryOp::GtEq
+                    if bounds.lower.as_ref().map_or(true, |low| val > low) =>
+                {
+                    bounds.lower = Some(val.clone());
+                }
+                BinaryOp::Lt | BinaryOp::LtEq
+                    if bounds.upper.as_ref().map_or(true, |high| val < high) =>
+                {
+                    bounds.upper = Some(val.clone());
+                }
+                _ => {}
+            }
+        }
+        break;
+    }
+    bounds
 }
 
 /// Compute the successor of a byte-string prefix for range scanning.
@@ -4129,26 +5650,58 @@ fn execute_update(
     catalog: &mut Catalog,
     txn_mgr: &mut TransactionManager,
 ) -> Result<usize> {
+    update_rows(upd, pool, catalog, txn_mgr, |_| Ok(()))
+}
+
+/// Update the rows `upd` selects, calling `on_row` with the new values of
+/// each one once it has been written and its AFTER UPDATE triggers have
+/// fired. Returns the number of rows updated.
+///
+/// With a FROM clause, a row is updated if the WHERE clause holds for it
+/// joined with some row of the FROM tables, and the assignments are
+/// evaluated against the first such joined row.
+fn update_rows(
+    upd: &UpdateStatement,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+    mut on_row: impl FnMut(&[Value]) -> Result<()>,
+) -> Result<usize> {
+    catalog.bump_write_generation(&upd.table);
     let table = catalog.get_table(&upd.table)?.clone();
     let mut tree = BTree::open(table.root_page);
 
-    // Fire BEFORE UPDATE triggers
-    views_triggers::fire_triggers(&upd.table, &TriggerEventKind::Update, &TriggerTimingKind::Before, pool, catalog, txn_mgr)?;
-
-    let entries = scan_with_index(upd.where_clause.as_ref(), &upd.table, &table, &tree, pool, catalog)?;
+    let source = match &upd.from {
+        Some(from) => Some(UpdateSource::new(from, &table, pool, catalog)?),
+        None => None,
+    };
+    // The WHERE clause of an UPDATE ... FROM constrains the joined row, so
+    // it cannot pick the rows of the table to scan.
+    let scan_filter = if source.is_some() { None } else { upd.where_clause.as_ref() };
+    let entries = scan_with_index(scan_filter, None, &upd.table, &table, &tree, pool, catalog)?;
     let mut updated = 0;
 
     for entry in &entries {
         let mut row_values = deserialize_row(&entry.value, table.columns.len())?;
 
         // Check WHERE
-        if let Some(ref where_clause) = upd.where_clause {
-            let result = eval_expr(where_clause, &row_values, &table.columns, &table)?;
-            if !result.to_bool() {
-                continue;
+        let joined = match &source {
+            Some(source) => match source.join(&row_values, upd.where_clause.as_ref(), pool, catalog)? {
+                Some(joined) => Some(joined),
+                None => continue,
+            },
+            None => {
+                if let Some(ref where_clause) = upd.where_clause {
+                    let result = eval_expr(where_clause, &row_values, &table.columns, &table)?;
+                    if !result.to_bool() {
+                        continue;
+                    }
+                }
+                None
             }
-        }
+        };
 
+        txn_mgr.lock_row(&upd.table, &entry.key)?;
         // Record undo before mutating
         txn_mgr.record_undo(UndoEntry::Update {
             table: upd.table.clone(),
@@ -4158,55 +5711,149 @@ fn execute_update(
         });
 
         // Apply assignments
+        let old_values = row_values.clone();
         for (col_name, expr) in &upd.assignments {
-            let col_idx = table.find_column_index(col_name).ok_or_else(|| {
-                HorizonError::ColumnNotFound(format!("{}.{}", upd.table, col_name))
-            })?;
-            let new_val = eval_expr(expr, &row_values, &table.columns, &table)?;
+            let col_idx = assignment_column(upd, &table, col_name)?;
+            let new_val = match (&source, &joined) {
+                (Some(source), Some(joined)) => {
+                    eval_expr_dynamic_with_ctx(expr, joined, &source.columns, pool, catalog)?
+                }
+                _ => eval_expr(expr, &row_values, &table.columns, &table)?,
+            };
             let affinity = table.columns[col_idx].affinity;
             row_values[col_idx] = new_val.apply_affinity(affinity);
         }
+        views_triggers::fire_table_row_triggers(
+            &table, TriggerEventKind::Update, TriggerTimingKind::Before,
+            Some(&row_values), Some(&old_values), &mut tree, pool, catalog, txn_mgr,
+        )?;
+        enforce_check_constraints(&table, &row_values)?;
+        foreign_keys::check_parent_keys(&table, &row_values, Some(&old_values), pool, catalog)?;
+        check_unique_on_update(&table, &row_values, &entry.key, &tree, pool, catalog)?;
+        foreign_keys::check_parent_change(&table, &old_values, Some(&row_values), pool, catalog)?;
+        check_index_key_sizes(&table, &row_values, &updated_row_key(&table, &entry.key, &row_values), pool, catalog)?;
 
         // Write back
-        let row_data = serialize_row(&row_values);
-        tree.insert(pool, &entry.key, &row_data)?;
+        let key = write_updated_row(&table, &entry.key, &row_values, &mut tree, pool, txn_mgr)?;
+        delete_index_entries(&table, &old_values, &entry.key, pool, catalog)?;
+        insert_index_entries(&table, &row_values, &key, pool, catalog)?;
+        if catalog.foreign_keys_enabled() {
+            sync_table_root(&upd.table, &tree, pool, catalog)?;
+            foreign_keys::on_parent_change(&table, &old_values, Some(&row_values), pool, catalog, txn_mgr)?;
+        }
+        views_triggers::fire_table_row_triggers(
+            &table, TriggerEventKind::Update, TriggerTimingKind::After,
+            Some(&row_values), Some(&old_values), &mut tree, pool, catalog, txn_mgr,
+        )?;
+        on_row(&row_values)?;
         updated += 1;
     }
 
     // Update root page if changed
-    if tree.root_page() != table.root_page {
-        let mut updated_table = table.clone();
-        updated_table.root_page = tree.root_page();
-        catalog.update_table_meta(pool, &upd.table, &updated_table)?;
-    }
-
-    // Fire AFTER UPDATE triggers
-    views_triggers::fire_triggers(&upd.table, &TriggerEventKind::Update, &TriggerTimingKind::After, pool, catalog, txn_mgr)?;
+    sync_table_root(&upd.table, &tree, pool, catalog)?;
 
     Ok(updated)
 }
 
+/// The index in `table` of the column an assignment of `upd` sets, which
+/// may be qualified by the table's name.
+fn assignment_column(upd: &UpdateStatement, table: &TableInfo, col_name: &str) -> Result<usize> {
+    let name = match col_name.split_once('.') {
+        Some((qualifier, name)) if qualifier.eq_ignore_ascii_case(&upd.table) => name,
+        Some(_) => return Err(HorizonError::ColumnNotFound(col_name.to_string())),
+        None => col_name,
+    };
+    table.find_column_index(name).ok_or_else(|| {
+        HorizonError::ColumnNotFound(format!("{}.{}", upd.table, name))
+    })
+}
+
+/// The rows of the FROM clause of an `UPDATE ... FROM`, which each row of
+/// the table being updated is joined with.
+struct UpdateSource {
+    /// The columns of a joined row: the table's, qualified by its name,
+    /// then the FROM clause's, qualified by table name or alias.
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl UpdateSource {
+    fn new(from: &FromClause, table: &TableInfo, pool: &BufferPool, catalog: &Catalog) -> Result<Self> {
+        let (from_columns, rows) = execute_plan_rows(&plan_from(from, catalog)?, pool, catalog)?;
+        let columns = table
+            .columns
+            .iter()
+            .map(|c| format!("{}.{}", table.name, c.name))
+            .chain(from_columns)
+            .collect();
+        Ok(UpdateSource { columns, rows })
+    }
+
+    /// `row` joined with the first row of the FROM clause for which
+    /// `where_clause` holds, if there is one.
+    fn join(
+        &self,
+        row: &[Value],
+        where_clause: Option<&Expr>,
+        pool: &BufferPool,
+        catalog: &Catalog,
+    ) -> Result<Option<Vec<Value>>> {
+        for source_row in &self.rows {
+            let joined: Vec<Value> = row.iter().chain(source_row).cloned().collect();
+            let matches = match where_clause {
+                Some(where_clause) => eval_expr_dynamic_with_ctx(where_clause, &joined, &self.columns, pool, catalog)?.to_bool(),
+                None => true,
+            };
+            if matches {
+                return Ok(Some(joined));
+            }
+        }
+        Ok(None)
+    }
+}
+
 // ---- DELETE ----
 
-/// Extract the search query string from a MATCH expression like:
-/// `table_name MATCH 'query'`
-fn extract_fts5_match_query(expr: &Expr, table_name: &str) -> Option<String> {
+/// A full-text search in the WHERE clause of a statement on an FTS5 table.
+struct Fts5Match {
+    /// The column searched, or `None` for every column.
+    column: Option<usize>,
+    query: String,
+}
+
+impl Fts5Match {
+    /// The rows of the FTS5 table `table_name` matching the search.
+    fn rows(&self, table_name: &str) -> Result<Vec<(i64, Vec<Value>, f64)>> {
+        match self.column {
+            Some(col_idx) => fts5::fts5_query_column(table_name, col_idx, &self.query),
+            None => fts5::fts5_query(table_name, &self.query),
+        }
+    }
+}
+
+/// Extract the search from a MATCH expression like `table_name MATCH 'query'`
+/// or `column MATCH 'query'`.
+fn extract_fts5_match_query(expr: &Expr, table_name: &str, columns: &[String]) -> Option<Fts5Match> {
     match expr {
         Expr::Match { table, pattern } => {
-            // table should be a column reference to the table name
+            // The left side names the table, or one of its columns to search
+            // only that column
             if let Expr::Column { name, .. } = table.as_ref() {
-                if name.eq_ignore_ascii_case(table_name) {
-                    if let Expr::Literal(LiteralValue::String(s)) = pattern.as_ref() {
-                        return Some(s.clone());
-                    }
+                let column = if name.eq_ignore_ascii_case(table_name) {
+                    None
+                } else {
+                    Some(columns.iter().position(|c| c.eq_ignore_ascii_case(name))?)
+                };
+                if let Expr::Literal(LiteralValue::String(s)) = pattern.as_ref() {
+                    return Some(Fts5Match { column, query: s.clone() });
                 }
             }
             None
         }
         Expr::BinaryOp { left, op: BinaryOp::And, right } => {
             // Check both sides for MATCH
-            extract_fts5_match_query(left, table_name)
-                .or_else(|| extract_fts5_match_query(right, table_name))
+            extract_fts5_match_query(left, table_name, columns)
+                .or_else(|| extract_fts5_match_query(right, table_name, columns))
         }
         _ => None,
     }
@@ -4229,8 +5876,16 @@ fn extract_rowid_eq(expr: &Expr) -> Option<i64> {
 fn execute_fts5_delete(del: &DeleteStatement) -> Result<usize> {
     if let Some(ref where_clause) = del.where_clause {
         // Check for MATCH expression in WHERE clause
-        if let Some(query_text) = extract_fts5_match_query(where_clause, &del.table) {
-            return fts5::fts5_delete_matching(&del.table, &query_text);
+        let columns = fts5::fts5_get_columns(&del.table)?;
+        if let Some(search) = extract_fts5_match_query(where_clause, &del.table, &columns) {
+            if search.column.is_none() {
+                return fts5::fts5_delete_matching(&del.table, &search.query);
+            }
+            let matching = search.rows(&del.table)?;
+            for (rowid, _, _) in &matching {
+                fts5::fts5_delete(&del.table, *rowid)?;
+            }
+            return Ok(matching.len());
         }
         // Check for rowid = N
         if let Some(rowid) = extract_rowid_eq(where_clause) {
@@ -4239,7 +5894,6 @@ fn execute_fts5_delete(del: &DeleteStatement) -> Result<usize> {
         }
         // For other WHERE clauses, do a scan and filter
         let all_rows = fts5::fts5_scan_all(&del.table)?;
-        let columns = fts5::fts5_get_columns(&del.table)?;
         let mut deleted = 0;
         for (rowid, values) in &all_rows {
             // Evaluate the WHERE clause using dynamic evaluation
@@ -4264,16 +5918,27 @@ fn execute_delete(
 ) -> Result<usize> {
     // Check if this is an FTS5 virtual table
     if fts5::fts5_table_exists(&del.table) {
+        catalog.bump_write_generation(&del.table);
         return execute_fts5_delete(del);
     }
+    delete_rows(del, pool, catalog, txn_mgr, |_| Ok(()))
+}
 
-    // Fire BEFORE DELETE triggers
-    views_triggers::fire_triggers(&del.table, &TriggerEventKind::Delete, &TriggerTimingKind::Before, pool, catalog, txn_mgr)?;
-
+/// Delete the rows `del` selects, calling `on_row` with the values of each
+/// one once it has been removed and its AFTER DELETE triggers have fired.
+/// Returns the number of rows deleted.
+fn delete_rows(
+    del: &DeleteStatement,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+    mut on_row: impl FnMut(&[Value]) -> Result<()>,
+) -> Result<usize> {
+    catalog.bump_write_generation(&del.table);
     let table = catalog.get_table(&del.table)?.clone();
     let mut tree = BTree::open(table.root_page);
 
-    let entries = scan_with_index(del.where_clause.as_ref(), &del.table, &table, &tree, pool, catalog)?;
+    let entries = scan_with_index(del.where_clause.as_ref(), None, &del.table, &table, &tree, pool, catalog)?;
     let mut to_delete: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
 
     for entry in &entries {
@@ -4290,6 +5955,17 @@ fn execute_delete(
 
     let deleted = to_delete.len();
     for (key, old_value) in &to_delete {
+        let row_values = deserialize_row(old_value, table.columns.len())?;
+        views_triggers::fire_table_row_triggers(
+            &table, TriggerEventKind::Delete, TriggerTimingKind::Before,
+            None, Some(&row_values), &mut tree, pool, catalog, txn_mgr,
+        )?;
+        if catalog.foreign_keys_enabled() {
+            sync_table_root(&del.table, &tree, pool, catalog)?;
+            foreign_keys::check_parent_change(&table, &row_values, None, pool, catalog)?;
+            foreign_keys::on_parent_change(&table, &row_values, None, pool, catalog, txn_mgr)?;
+        }
+        txn_mgr.lock_row(&del.table, key)?;
         // Record undo before deleting
         txn_mgr.record_undo(UndoEntry::Delete {
             table: del.table.clone(),
@@ -4298,17 +5974,16 @@ fn execute_delete(
             old_value: old_value.clone(),
         });
         tree.delete(pool, key)?;
+        delete_index_entries(&table, &row_values, key, pool, catalog)?;
+        views_triggers::fire_table_row_triggers(
+            &table, TriggerEventKind::Delete, TriggerTimingKind::After,
+            None, Some(&row_values), &mut tree, pool, catalog, txn_mgr,
+        )?;
+        on_row(&row_values)?;
     }
 
     // Update root page if changed
-    if tree.root_page() != table.root_page {
-        let mut updated_table = table.clone();
-        updated_table.root_page = tree.root_page();
-        catalog.update_table_meta(pool, &del.table, &updated_table)?;
-    }
-
-    // Fire AFTER DELETE triggers
-    views_triggers::fire_triggers(&del.table, &TriggerEventKind::Delete, &TriggerTimingKind::After, pool, catalog, txn_mgr)?;
+    sync_table_root(&del.table, &tree, pool, catalog)?;
 
     Ok(deleted)
 }
@@ -4316,6 +5991,9 @@ fn execute_delete(
 // ---- INSERT/UPDATE/DELETE with RETURNING ----
 
 /// Execute an INSERT with RETURNING clause, returning the inserted rows.
+/// Whether the rows come from VALUES or a SELECT, each one is projected
+/// once it has been inserted, so the RETURNING clause sees its rowid and
+/// generated columns.
 fn execute_insert_returning(
     ins: &InsertStatement,
     pool: &mut BufferPool,
@@ -4330,128 +6008,9 @@ fn execute_insert_returning(
     let columns = Arc::new(column_names);
 
     let mut rows = Vec::new();
-
-    // Execute the insert normally first (we re-use the insert logic)
-    let mut tree = BTree::open(table.root_page);
-    let mut next_rowid = table.next_rowid;
-    let _txn_id = txn_mgr.auto_commit();
-
-    for value_row in &ins.values {
-        let col_order: Vec<usize> = if let Some(ref col_names) = ins.columns {
-            col_names
-                .iter()
-                .map(|name| {
-                    table.find_column_index(name).ok_or_else(|| {
-                        HorizonError::ColumnNotFound(format!("{}.{}", ins.table, name))
-                    })
-                })
-                .collect::<Result<Vec<_>>>()?
-        } else {
-            (0..table.columns.len()).collect()
-        };
-
-        if value_row.len() != col_order.len() {
-            return Err(HorizonError::InvalidSql(format!(
-                "expected {} values but got {}",
-                col_order.len(),
-                value_row.len()
-            )));
-        }
-
-        let mut row_values = vec![Value::Null; table.columns.len()];
-        for (val_idx, &col_idx) in col_order.iter().enumerate() {
-            let val = eval_expr(&value_row[val_idx], &[], &[], &table)?;
-            let affinity = table.columns[col_idx].affinity;
-            row_values[col_idx] = val.apply_affinity(affinity);
-        }
-
-        for (i, col) in table.columns.iter().enumerate() {
-            if !col_order.contains(&i) && col.generated_expr.is_none() {
-                if let Some(ref default_val) = col.default_value {
-                    row_values[i] = default_val.clone();
-                }
-            }
-        }
-
-        // Evaluate STORED generated columns
-        for (i, col) in table.columns.iter().enumerate() {
-            if let Some(ref gen_expr) = col.generated_expr {
-                if col.is_stored {
-                    let val = eval_expr(gen_expr, &row_values, &table.columns, &table)?;
-                    row_values[i] = val.apply_affinity(col.affinity);
-                }
-            }
-        }
-
-        let rowid = if let Some(pk_idx) = table.pk_column {
-            if table.columns[pk_idx].affinity == DataType::Integer {
-                match &row_values[pk_idx] {
-                    Value::Null => {
-                        let id = next_rowid;
-                        row_values[pk_idx] = Value::Integer(id);
-                        next_rowid = id + 1;
-                        id
-                    }
-                    Value::Integer(id) => {
-                        if *id >= next_rowid {
-                            next_rowid = *id + 1;
-                        }
-                        *id
-                    }
-                    _ => {
-                        let id = next_rowid;
-                        next_rowid = id + 1;
-                        id
-                    }
-                }
-            } else {
-                let id = next_rowid;
-                next_rowid = id + 1;
-                id
-            }
-        } else {
-            let id = next_rowid;
-            next_rowid = id + 1;
-            id
-        };
-
-        for (i, col) in table.columns.iter().enumerate() {
-            if col.not_null && row_values[i].is_null() {
-                return Err(HorizonError::ConstraintViolation(format!(
-                    "NOT NULL constraint failed: {}.{}",
-                    ins.table, col.name
-                )));
-            }
-        }
-
-        let key = rowid.to_be_bytes();
-        let existing = tree.search(pool, &key)?;
-        if let Some(ref old_value) = existing {
-            if ins.or_replace {
-                txn_mgr.record_undo(UndoEntry::Update {
-                    table: ins.table.clone(),
-                    root_page: tree.root_page(),
-                    key: key.to_vec(),
-                    old_value: old_value.clone(),
-                });
-            } else {
-                return Err(HorizonError::ConstraintViolation(format!(
-                    "UNIQUE constraint failed: {}.rowid",
-                    ins.table
-                )));
-            }
-        } else {
-            txn_mgr.record_undo(UndoEntry::Insert {
-                table: ins.table.clone(),
-                root_page: tree.root_page(),
-                key: key.to_vec(),
-            });
-        }
-
-        let row_data = serialize_row(&row_values);
-        tree.insert(pool, &key, &row_data)?;
-
+    insert_rows(ins, pool, catalog, txn_mgr, |row_values| {
         // Fill virtual generated columns for RETURNING
+        let mut row_values = row_values.to_vec();
         if table_has_virtual_columns(&table) {
             fill_virtual_columns(&mut row_values, &table)?;
         }
@@ -4462,15 +6021,8 @@ fn execute_insert_returning(
             columns: columns.clone(),
             values: projected,
         });
-    }
-
-    // Update catalog metadata
-    let mut updated_table = catalog.get_table(&ins.table)?.clone();
-    updated_table.next_rowid = next_rowid;
-    if tree.root_page() != updated_table.root_page {
-        updated_table.root_page = tree.root_page();
-    }
-    catalog.update_table_meta(pool, &ins.table, &updated_table)?;
+        Ok(())
+    })?;
 
     Ok(QueryResult { columns, rows })
 }
@@ -4484,61 +6036,29 @@ fn execute_update_returning(
 ) -> Result<QueryResult> {
     let returning_cols = upd.returning.as_ref().unwrap();
     let table = catalog.get_table(&upd.table)?.clone();
-    let mut tree = BTree::open(table.root_page);
 
     let column_names = resolve_column_names(returning_cols, &table)?;
     let columns = Arc::new(column_names);
 
-    let entries = scan_with_index(upd.where_clause.as_ref(), &upd.table, &table, &tree, pool, catalog)?;
     let mut rows = Vec::new();
-
-    for entry in &entries {
-        let mut row_values = deserialize_row(&entry.value, table.columns.len())?;
-
-        if let Some(ref where_clause) = upd.where_clause {
-            let result = eval_expr(where_clause, &row_values, &table.columns, &table)?;
-            if !result.to_bool() {
-                continue;
-            }
-        }
-
-        txn_mgr.record_undo(UndoEntry::Update {
-            table: upd.table.clone(),
-            root_page: tree.root_page(),
-            key: entry.key.clone(),
-            old_value: entry.value.clone(),
-        });
-
-        for (col_name, expr) in &upd.assignments {
-            let col_idx = table.find_column_index(col_name).ok_or_else(|| {
-                HorizonError::ColumnNotFound(format!("{}.{}", upd.table, col_name))
-            })?;
-            let new_val = eval_expr(expr, &row_values, &table.columns, &table)?;
-            let affinity = table.columns[col_idx].affinity;
-            row_values[col_idx] = new_val.apply_affinity(affinity);
-        }
-
-        let row_data = serialize_row(&row_values);
-        tree.insert(pool, &entry.key, &row_data)?;
-
+    update_rows(upd, pool, catalog, txn_mgr, |row_values| {
         // Project the RETURNING columns from the updated row
-        let projected = project_row_returning(returning_cols, &row_values, &table)?;
+        let projected = project_row_returning(returning_cols, row_values, &table)?;
         rows.push(Row {
             columns: columns.clone(),
             values: projected,
         });
-    }
-
-    if tree.root_page() != table.root_page {
-        let mut updated_table = table.clone();
-        updated_table.root_page = tree.root_page();
-        catalog.update_table_meta(pool, &upd.table, &updated_table)?;
-    }
+        Ok(())
+    })?;
 
     Ok(QueryResult { columns, rows })
 }
 
 /// Execute a DELETE with RETURNING clause, returning the deleted rows.
+///
+/// If the RETURNING clause has aggregates, as in `RETURNING COUNT(*),
+/// SUM(size)`, it is evaluated once over all the deleted rows instead,
+/// giving a single row even when nothing was deleted.
 fn execute_delete_returning(
     del: &DeleteStatement,
     pool: &mut BufferPool,
@@ -4547,49 +6067,42 @@ fn execute_delete_returning(
 ) -> Result<QueryResult> {
     let returning_cols = del.returning.as_ref().unwrap();
     let table = catalog.get_table(&del.table)?.clone();
-    let mut tree = BTree::open(table.root_page);
 
     let column_names = resolve_column_names(returning_cols, &table)?;
     let columns = Arc::new(column_names);
 
-    let entries = scan_with_index(del.where_clause.as_ref(), &del.table, &table, &tree, pool, catalog)?;
-    let mut to_delete: Vec<(Vec<u8>, Vec<Value>)> = Vec::new();
+    let aggregate = select_has_aggregate(returning_cols);
+    let mut deleted = Vec::new();
+    let mut rows = Vec::new();
+    delete_rows(del, pool, catalog, txn_mgr, |row_values| {
+        if aggregate {
+            deleted.push(row_values.to_vec());
+        } else {
+            // Project the RETURNING columns from the deleted row
+            let projected = project_row_returning(returning_cols, row_values, &table)?;
+            rows.push(Row {
+                columns: columns.clone(),
+                values: projected,
+            });
+        }
+        Ok(())
+    })?;
 
-    for entry in &entries {
-        let row_values = deserialize_row(&entry.value, table.columns.len())?;
-        if let Some(ref where_clause) = del.where_clause {
-            let result = eval_expr(where_clause, &row_values, &table.columns, &table)?;
-            if !result.to_bool() {
-                continue;
+    if aggregate {
+        let col_names: Vec<String> = table.columns.iter().map(|c| c.name.clone()).collect();
+        let representative = deleted.first().cloned().unwrap_or_else(|| vec![Value::Null; col_names.len()]);
+        let mut values = Vec::with_capacity(columns.len());
+        for col in returning_cols {
+            match col {
+                SelectColumn::AllColumns | SelectColumn::TableAllColumns(_) => {
+                    values.extend(representative.iter().cloned());
+                }
+                SelectColumn::Expr { expr, .. } => {
+                    values.push(eval_aggregate_expr(expr, &representative, &col_names, &deleted)?);
+                }
             }
         }
-        to_delete.push((entry.key.clone(), row_values));
-    }
-
-    let mut rows = Vec::new();
-    for (key, row_values) in &to_delete {
-        // Project RETURNING columns BEFORE deleting
-        let projected = project_row_returning(returning_cols, row_values, &table)?;
-        rows.push(Row {
-            columns: columns.clone(),
-            values: projected,
-        });
-
-        // Find the serialized value for undo
-        let row_data = serialize_row(row_values);
-        txn_mgr.record_undo(UndoEntry::Delete {
-            table: del.table.clone(),
-            root_page: tree.root_page(),
-            key: key.clone(),
-            old_value: row_data,
-        });
-        tree.delete(pool, key)?;
-    }
-
-    if tree.root_page() != table.root_page {
-        let mut updated_table = table.clone();
-        updated_table.root_page = tree.root_page();
-        catalog.update_table_meta(pool, &del.table, &updated_table)?;
+        rows.push(Row { columns: columns.clone(), values });
     }
 
     Ok(QueryResult { columns, rows })
@@ -4626,33 +6139,52 @@ fn execute_create_index(
     pool: &mut BufferPool,
     catalog: &mut Catalog,
 ) -> Result<usize> {
-    if ci.if_not_exists {
-        if catalog.get_index(&ci.name).is_ok() {
+    if ci.if_not_exists
+        && catalog.get_index(&ci.name).is_ok() {
             return Ok(0);
         }
-    }
 
     // Verify the table exists and get its metadata
     let table = catalog.get_table(&ci.table)?.clone();
 
-    // Resolve the column names from the index definition
-    let index_columns: Vec<String> = ci.columns.iter().map(|c| {
-        if let Expr::Column { name, .. } = &c.expr {
-            name.clone()
-        } else {
-            format!("{:?}", c.expr)
+    // Resolve the key parts: plain columns by name, anything else is an
+    // expression evaluated against each row
+    let mut index_columns = Vec::with_capacity(ci.columns.len());
+    let mut expressions = Vec::with_capacity(ci.columns.len());
+    for item in &ci.columns {
+        match &item.expr {
+            Expr::Column { name, .. } => {
+                if table.find_column_index(name).is_none() {
+                    return Err(HorizonError::ColumnNotFound(format!("{}.{}", ci.table, name)));
+                }
+                index_columns.push(name.clone());
+                expressions.push(None);
+            }
+            expr => {
+                if expr_has_aggregate_fn(expr) {
+                    return Err(HorizonError::InvalidSql(
+                        "aggregate functions are not allowed in index expressions".into(),
+                    ));
+                }
+                index_columns.push(expr_to_sql(expr));
+                expressions.push(Some(expr.clone()));
+            }
         }
-    }).collect();
+    }
 
-    // Verify that all index columns exist in the table
-    let col_indices: Vec<usize> = index_columns.iter().map(|col_name| {
-        table.find_column_index(col_name).ok_or_else(|| {
-            HorizonError::ColumnNotFound(format!("{}.{}", ci.table, col_name))
-        })
-    }).collect::<Result<Vec<_>>>()?;
+    let mut index_info = crate::catalog::IndexInfo {
+        name: ci.name.clone(),
+        table_name: ci.table.clone(),
+        columns: index_columns,
+        unique: ci.unique,
+        root_page: 0,
+        predicate: ci.where_clause.clone(),
+        expressions,
+    };
 
-    // Create a B+Tree for the index
-    let mut index_tree = BTree::create(pool)?;
+    // Create a B+Tree for the index, ordered by the collations its key uses
+    let order = collation::index_key_order(&index_info, catalog.collations())?;
+    let mut index_tree = BTree::create(pool)?.ordered_by(order);
 
     // Scan existing table data and populate the index
     let data_tree = BTree::open(table.root_page);
@@ -4661,21 +6193,20 @@ fn execute_create_index(
     for entry in &entries {
         let row_values = deserialize_row(&entry.value, table.columns.len())?;
 
-        // Build the index key from the indexed column values + rowid
-        let index_key = build_index_key(&row_values, &col_indices, &entry.key);
+        // A partial index only holds rows matching its predicate
+        if !index_covers_row(&index_info, &table, &row_values)? {
+            continue;
+        }
+
+        // Build the index key from the indexed values + rowid
+        let values = index_key_values(&index_info, &table, &row_values)?;
+        let index_key = build_index_key(&values, &entry.key);
 
         // The index value is the rowid (the table B+Tree key)
         index_tree.insert(pool, &index_key, &entry.key)?;
     }
 
-    let index_info = crate::catalog::IndexInfo {
-        name: ci.name.clone(),
-        table_name: ci.table.clone(),
-        columns: index_columns,
-        unique: ci.unique,
-        root_page: index_tree.root_page(),
-    };
-
+    index_info.root_page = index_tree.root_page();
     catalog.create_index(pool, index_info)?;
     Ok(0)
 }
@@ -4687,11 +6218,10 @@ fn execute_drop_index(
     pool: &mut BufferPool,
     catalog: &mut Catalog,
 ) -> Result<usize> {
-    if di.if_exists {
-        if catalog.get_index(&di.name).is_err() {
+    if di.if_exists
+        && catalog.get_index(&di.name).is_err() {
             return Ok(0);
         }
-    }
     catalog.drop_index(pool, &di.name)?;
     Ok(0)
 }
@@ -4714,38 +6244,153 @@ fn execute_alter_table(alter: &AlterTableStatement, pool: &mut BufferPool, catal
                 name: col_def.name.clone(), type_name, affinity,
                 primary_key: false, autoincrement: false,
                 not_null: col_def.not_null, unique: col_def.unique,
-                default_value: col_def.default.as_ref().map(|e| eval_const_expr(e)),
+                default_value: col_def.default.as_ref().map(eval_const_expr),
                 position,
                 generated_expr: gen_expr,
                 is_stored: gen_stored,
+                check_expr: col_def.check.clone(),
             };
             catalog.add_column(pool, &alter.table, col_info)?;
             Ok(0)
         }
         AlterTableAction::RenameTable(new_name) => { catalog.rename_table(pool, &alter.table, new_name)?; Ok(0) }
         AlterTableAction::RenameColumn { old_name, new_name } => { catalog.rename_column(pool, &alter.table, old_name, new_name)?; Ok(0) }
-        AlterTableAction::DropColumn(col_name) => { catalog.drop_column(pool, &alter.table, col_name)?; Ok(0) }
+        AlterTableAction::DropColumn(col_name) => execute_drop_column(&alter.table, col_name, pool, catalog),
     }
 }
 
+/// Execute `ALTER TABLE t DROP COLUMN col`: remove the column from the
+/// schema and rewrite every row without its value.
+///
+/// As in SQLite, a column cannot be dropped while something else depends
+/// on it: the primary key, a UNIQUE constraint, an index (by column,
+/// expression or partial-index predicate), a generated column, a CHECK
+/// constraint on another column or on the table, or a foreign key in either
+/// direction.
+fn execute_drop_column(table_name: &str, col_name: &str, pool: &mut BufferPool, catalog: &mut Catalog) -> Result<usize> {
+    let table = catalog.get_table(table_name)?.clone();
+    let col_idx = table.find_column_index(col_name)
+        .ok_or_else(|| HorizonError::ColumnNotFound(format!("{}.{}", table_name, col_name)))?;
+    let column = &table.columns[col_idx];
+    let reject = |reason: String| {
+        Err(HorizonError::InvalidSql(format!("cannot drop column {}: {}", column.name, reason)))
+    };
+
+    if column.primary_key {
+        return reject("it is part of the PRIMARY KEY".into());
+    }
+    if column.unique {
+        return reject("it has a UNIQUE constraint".into());
+    }
+    for idx in catalog.get_indexes_for_table(table_name) {
+        let in_key = idx.columns.iter().enumerate().any(|(i, c)| match idx.key_expr(i) {
+            Some(expr) => expr_references_column(expr, col_name),
+            None => c.eq_ignore_ascii_case(col_name),
+        });
+        let in_predicate = idx.predicate.as_ref().is_some_and(|p| expr_references_column(p, col_name));
+        if in_key || in_predicate {
+            return reject(format!("it is used by index {}", idx.name));
+        }
+    }
+    for other in table.columns.iter().filter(|c| !c.name.eq_ignore_ascii_case(col_name)) {
+        if other.generated_expr.as_ref().is_some_and(|e| expr_references_column(e, col_name)) {
+            return reject(format!("generated column {} depends on it", other.name));
+        }
+        if other.check_expr.as_ref().is_some_and(|e| expr_references_column(e, col_name)) {
+            return reject(format!("the CHECK constraint on {} depends on it", other.name));
+        }
+    }
+    if table.checks.iter().any(|e| expr_references_column(e, col_name)) {
+        return reject("a CHECK constraint depends on it".into());
+    }
+    if table.foreign_keys.iter().any(|fk| fk.columns.iter().any(|c| c.eq_ignore_ascii_case(col_name))) {
+        return reject("it is part of a FOREIGN KEY".into());
+    }
+    let referenced = catalog.foreign_keys_referencing(table_name).iter().any(|(_, fk)| {
+        fk.ref_columns.iter().any(|c| c.eq_ignore_ascii_case(col_name))
+    });
+    if referenced {
+        return reject("a FOREIGN KEY references it".into());
+    }
+
+    catalog.drop_column(pool, table_name, col_name)?;
+
+    // Rewrite each row without the dropped value. Rows written before a
+    // later ADD COLUMN may be shorter than the table; those are padded by
+    // deserialize_row first.
+    let mut tree = BTree::open(table.root_page);
+    for entry in tree.scan_all(pool)? {
+        let mut values = deserialize_row(&entry.value, table.columns.len())?;
+        values.remove(col_idx);
+        tree.insert(pool, &entry.key, &serialize_row(&values))?;
+    }
+    if tree.root_page() != table.root_page {
+        let mut updated = catalog.get_table(table_name)?.clone();
+        updated.root_page = tree.root_page();
+        catalog.update_table_meta(pool, table_name, &updated)?;
+    }
+    Ok(0)
+}
+
+/// Whether `expr` refers to the column `name` (qualified or not).
+fn expr_references_column(expr: &Expr, name: &str) -> bool {
+    let mut found = false;
+    let _ = params::walk_expr(&mut expr.clone(), &mut |e| {
+        if let Expr::Column { name: col, .. } = e {
+            found |= col.eq_ignore_ascii_case(name);
+        }
+        Ok(())
+    });
+    found
+}
+
 // ---- PRAGMA ----
 
+/// The result of `PRAGMA table_info`, or with `extended` of
+/// `PRAGMA table_xinfo`, which also lists generated columns and adds a
+/// `hidden` column: 1 for VIRTUAL and 2 for STORED generated columns.
+fn pragma_table_info(table: &TableInfo, extended: bool) -> QueryResult {
+    let mut names: Vec<String> = vec!["cid".into(), "name".into(), "type".into(), "notnull".into(), "dflt_value".into(), "pk".into()];
+    if extended {
+        names.push("hidden".into());
+    }
+    let columns = Arc::new(names);
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+    for (i, col) in table.columns.iter().enumerate() {
+        let hidden = match col.generated_expr {
+            Some(_) if col.is_stored => 2,
+            Some(_) => 1,
+            None => 0,
+        };
+        if hidden != 0 && !extended {
+            // As in SQLite, cids count only the listed columns
+            skipped += 1;
+            continue;
+        }
+        // The default is reported as SQL text, so a string default is quoted
+        let dflt = col.default_value.as_ref().map_or(Value::Null, |v| Value::Text(dump::sql_literal(v)));
+        // 1-based position within the primary key, 0 if not a key column
+        let pk = table.pk_columns.iter().position(|&c| c == i).map_or(0, |p| p as i64 + 1);
+        let mut values = vec![
+            Value::Integer((i - skipped) as i64), Value::Text(col.name.clone()), Value::Text(col.type_name.clone()),
+            Value::Integer(if col.not_null { 1 } else { 0 }), dflt, Value::Integer(pk),
+        ];
+        if extended {
+            values.push(Value::Integer(hidden));
+        }
+        rows.push(Row { columns: columns.clone(), values });
+    }
+    QueryResult { columns, rows }
+}
+
 fn execute_pragma(pragma: &PragmaStatement, pool: &mut BufferPool, catalog: &mut Catalog) -> Result<QueryResult> {
     let pragma_name = pragma.name.to_lowercase();
     match pragma_name.as_str() {
-        "table_info" => {
+        "table_info" | "table_xinfo" => {
             let table_name = pragma_extract_table_name(pragma)?;
             let table = catalog.get_table(&table_name)?;
-            let columns = Arc::new(vec!["cid".into(), "name".into(), "type".into(), "notnull".into(), "dflt_value".into(), "pk".into()]);
-            let mut rows = Vec::new();
-            for (i, col) in table.columns.iter().enumerate() {
-                let dflt = col.default_value.clone().unwrap_or(Value::Null);
-                rows.push(Row { columns: columns.clone(), values: vec![
-                    Value::Integer(i as i64), Value::Text(col.name.clone()), Value::Text(col.type_name.clone()),
-                    Value::Integer(if col.not_null { 1 } else { 0 }), dflt, Value::Integer(if col.primary_key { 1 } else { 0 }),
-                ]});
-            }
-            Ok(QueryResult { columns, rows })
+            Ok(pragma_table_info(table, pragma_name == "table_xinfo"))
         }
         "table_list" => {
             let columns = Arc::new(vec![
@@ -4778,7 +6423,8 @@ fn execute_pragma(pragma: &PragmaStatement, pool: &mut BufferPool, catalog: &mut
             for (i, idx) in indexes.iter().enumerate() {
                 rows.push(Row { columns: columns.clone(), values: vec![
                     Value::Integer(i as i64), Value::Text(idx.name.clone()),
-                    Value::Integer(if idx.unique { 1 } else { 0 }), Value::Text("c".into()), Value::Integer(0),
+                    Value::Integer(if idx.unique { 1 } else { 0 }), Value::Text("c".into()),
+                    Value::Integer(if idx.predicate.is_some() { 1 } else { 0 }),
                 ]});
             }
             Ok(QueryResult { columns, rows })
@@ -4790,7 +6436,11 @@ fn execute_pragma(pragma: &PragmaStatement, pool: &mut BufferPool, catalog: &mut
             let columns = Arc::new(vec!["seqno".into(), "cid".into(), "name".into()]);
             let mut rows = Vec::new();
             for (i, col_name) in index.columns.iter().enumerate() {
-                let cid = table.find_column_index(col_name).unwrap_or(0) as i64;
+                // As in SQLite, expression key parts report cid -2
+                let cid = match index.key_expr(i) {
+                    Some(_) => -2,
+                    None => table.find_column_index(col_name).unwrap_or(0) as i64,
+                };
                 rows.push(Row { columns: columns.clone(), values: vec![
                     Value::Integer(i as i64), Value::Integer(cid), Value::Text(col_name.clone()),
                 ]});
@@ -4799,26 +6449,164 @@ fn execute_pragma(pragma: &PragmaStatement, pool: &mut BufferPool, catalog: &mut
         }
         "database_list" => {
             let columns = Arc::new(vec!["seq".into(), "name".into(), "file".into()]);
-            let rows = vec![Row { columns: columns.clone(), values: vec![Value::Integer(0), Value::Text("main".into()), Value::Text(String::new())] }];
+            let mut rows = vec![Row { columns: columns.clone(), values: vec![Value::Integer(0), Value::Text("main".into()), Value::Text(String::new())] }];
+            let mut attached: Vec<_> = catalog.attached_databases.values().collect();
+            attached.sort_by(|a, b| a.schema_name.cmp(&b.schema_name));
+            for (seq, db) in (2..).zip(attached) {
+                let values = vec![Value::Integer(seq), Value::Text(db.schema_name.clone()), Value::Text(db.path.clone())];
+                rows.push(Row { columns: columns.clone(), values });
+            }
             Ok(QueryResult { columns, rows })
         }
         "page_size" => {
             let columns = Arc::new(vec!["page_size".into()]);
-            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(4096)] }] })
+            let page_size = pool.page_size() as i64;
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(page_size)] }] })
         }
         "page_count" => {
             let count = pool.pager().page_count();
             let columns = Arc::new(vec!["page_count".into()]);
             Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(count as i64)] }] })
         }
+        "freelist_count" => {
+            let count = pool.pager().free_list().len();
+            let columns = Arc::new(vec!["freelist_count".into()]);
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(count as i64)] }] })
+        }
+        "cache_size" => {
+            if let Some(ref value) = pragma.value {
+                // As in SQLite, a negative size is a budget in KiB
+                let pages = match eval_const_expr(value) {
+                    Value::Integer(n) if n > 0 => Some(n as usize),
+                    Value::Integer(n) if n < 0 => {
+                        let bytes = n.unsigned_abs().saturating_mul(1024) as usize;
+                        Some(bytes.saturating_add(pool.page_size() - 1) / pool.page_size())
+                    }
+                    _ => None,
+                }
+                .ok_or_else(|| HorizonError::InvalidSql("cache_size must be a non-zero integer".into()))?;
+                pool.resize(pages)?;
+            }
+            let columns = Arc::new(vec!["cache_size".into()]);
+            let pages = pool.capacity() as i64;
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(pages)] }] })
+        }
+        "user_version" => {
+            if let Some(ref value) = pragma.value {
+                let version = match value {
+                    Expr::Literal(LiteralValue::Integer(n)) => u32::try_from(*n).ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    HorizonError::InvalidSql(format!("user_version must be an integer from 0 to {}", u32::MAX))
+                })?;
+                pool.pager_mut().set_user_version(version)?;
+            }
+            let version = pool.pager().get_user_version();
+            let columns = Arc::new(vec!["user_version".into()]);
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(version as i64)] }] })
+        }
         "journal_mode" => {
+            let mode = match pragma.value {
+                Some(ref value) => {
+                    let name = pragma_extract_table_name(pragma).unwrap_or_default();
+                    let mode = crate::wal::JournalMode::from_sql(&name).ok_or_else(|| {
+                        HorizonError::InvalidSql(format!("invalid journal_mode: {:?}", value))
+                    })?;
+                    pool.set_journal_mode(mode)?
+                }
+                None => pool.journal_mode(),
+            };
             let columns = Arc::new(vec!["journal_mode".into()]);
-            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Text("wal".into())] }] })
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Text(mode.as_sql().into())] }] })
+        }
+        "synchronous" => {
+            if let Some(ref value) = pragma.value {
+                let level = match value {
+                    Expr::Literal(LiteralValue::Integer(n)) => n.to_string(),
+                    _ => pragma_extract_table_name(pragma).unwrap_or_default(),
+                };
+                let mode = crate::wal::SynchronousMode::from_sql(&level).ok_or_else(|| {
+                    HorizonError::InvalidSql(format!("invalid synchronous level: {:?}", value))
+                })?;
+                pool.set_synchronous(mode);
+            }
+            let columns = Arc::new(vec!["synchronous".into()]);
+            let level = pool.synchronous().level();
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(level)] }] })
+        }
+        "wal_checkpoint" => {
+            let mode = match pragma.value {
+                Some(ref value) => {
+                    let name = pragma_extract_table_name(pragma).unwrap_or_default();
+                    crate::wal::CheckpointMode::from_sql(&name).ok_or_else(|| {
+                        HorizonError::InvalidSql(format!("invalid wal_checkpoint mode: {:?}", value))
+                    })?
+                }
+                None => crate::wal::CheckpointMode::Passive,
+            };
+            let result = pool.checkpoint(mode)?;
+            let columns = Arc::new(vec!["busy".into(), "log".into(), "checkpointed".into()]);
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![
+                Value::Integer(0),
+                Value::Integer(result.wal_frames as i64),
+                Value::Integer(result.checkpointed_frames as i64),
+            ]}] })
         }
         "encoding" => {
             let columns = Arc::new(vec!["encoding".into()]);
             Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Text("UTF-8".into())] }] })
         }
+        "optimize" => execute_optimize(pool, catalog),
+        "integrity_check" | "quick_check" => {
+            // An optional argument caps the number of problems reported
+            let max_errors = match pragma.value {
+                Some(Expr::Literal(LiteralValue::Integer(n))) if n > 0 => n as usize,
+                Some(ref value) => {
+                    return Err(HorizonError::InvalidSql(format!("invalid {} limit: {:?}", pragma_name, value)));
+                }
+                None => integrity::DEFAULT_MAX_ERRORS,
+            };
+            let quick = pragma_name == "quick_check";
+            let columns = Arc::new(vec![pragma_name.clone()]);
+            let rows = integrity::check_database(pool, catalog, quick, max_errors)?
+                .into_iter()
+                .map(|msg| Row { columns: columns.clone(), values: vec![Value::Text(msg)] })
+                .collect();
+            Ok(QueryResult { columns, rows })
+        }
+        "foreign_keys" => {
+            if let Some(ref value) = pragma.value {
+                catalog.set_foreign_keys_enabled(pragma_bool_value(value)?);
+            }
+            let enabled = catalog.foreign_keys_enabled();
+            let columns = Arc::new(vec!["foreign_keys".into()]);
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(enabled as i64)] }] })
+        }
+        "sort_spill_threshold" => {
+            if let Some(ref value) = pragma.value {
+                let rows = match eval_const_expr(value) {
+                    Value::Integer(n) if n > 0 => n as usize,
+                    _ => return Err(HorizonError::InvalidSql("sort_spill_threshold must be a positive integer".into())),
+                };
+                catalog.set_sort_spill_threshold(rows);
+            }
+            let rows = catalog.sort_spill_threshold() as i64;
+            let columns = Arc::new(vec!["sort_spill_threshold".into()]);
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(rows)] }] })
+        }
+        "join_spill_threshold" => {
+            if let Some(ref value) = pragma.value {
+                let rows = match eval_const_expr(value) {
+                    Value::Integer(n) if n > 0 => n as usize,
+                    _ => return Err(HorizonError::InvalidSql("join_spill_threshold must be a positive integer".into())),
+                };
+                catalog.set_join_spill_threshold(rows);
+            }
+            let rows = catalog.join_spill_threshold() as i64;
+            let columns = Arc::new(vec!["join_spill_threshold".into()]);
+            Ok(QueryResult { columns: columns.clone(), rows: vec![Row { columns, values: vec![Value::Integer(rows)] }] })
+        }
         _ => {
             let columns = Arc::new(vec![pragma.name.clone()]);
             Ok(QueryResult { columns, rows: vec![] })
@@ -4826,6 +6614,23 @@ fn execute_pragma(pragma: &PragmaStatement, pool: &mut BufferPool, catalog: &mut
     }
 }
 
+/// Interpret a boolean PRAGMA argument: ON/OFF, YES/NO, TRUE/FALSE, or 1/0.
+fn pragma_bool_value(value: &Expr) -> Result<bool> {
+    let word = match value {
+        Expr::Column { name, .. } => name.to_ascii_uppercase(),
+        Expr::Literal(LiteralValue::String(s)) => s.to_ascii_uppercase(),
+        Expr::Literal(LiteralValue::Integer(n)) => return Ok(*n != 0),
+        Expr::Literal(LiteralValue::True) => return Ok(true),
+        Expr::Literal(LiteralValue::False) => return Ok(false),
+        _ => String::new(),
+    };
+    match word.as_str() {
+        "ON" | "YES" | "TRUE" => Ok(true),
+        "OFF" | "NO" | "FALSE" => Ok(false),
+        _ => Err(HorizonError::InvalidSql(format!("invalid boolean PRAGMA value: {:?}", value))),
+    }
+}
+
 fn pragma_extract_table_name(pragma: &PragmaStatement) -> Result<String> {
     match &pragma.value {
         Some(Expr::Column { name, .. }) => Ok(name.clone()),
@@ -4856,7 +6661,7 @@ fn explain_plan_opcodes(
     addr: &mut i64,
 ) {
     match plan {
-        LogicalPlan::SeqScan { table, alias } => {
+        LogicalPlan::SeqScan { table, alias, method } => {
             let detail = alias.as_deref()
                 .map(|a| format!("{} AS {}", table, a))
                 .unwrap_or_else(|| table.clone());
@@ -4865,9 +6670,26 @@ fn explain_plan_opcodes(
                 Value::Integer(0), Value::Integer(0), Value::Integer(0), Value::Text(detail),
             ] });
             *addr += 1;
+            let (opcode, p4) = match method {
+                ScanMethod::FullScan => ("Rewind", format!("table {}", table)),
+                ScanMethod::PkSeek { op: BinaryOp::Eq, .. } => ("SeekRowid", format!("table {}", table)),
+                ScanMethod::PkSeek { op: BinaryOp::Gt, .. } => ("SeekGT", format!("table {}", table)),
+                ScanMethod::PkSeek { op: BinaryOp::GtEq, .. } => ("SeekGE", format!("table {}", table)),
+                ScanMethod::PkSeek { .. } => ("Rewind", format!("table {}", table)),
+                ScanMethod::CompositePkSeek { .. } => ("SeekGE", format!("table {}", table)),
+                ScanMethod::IndexScan { index_name, .. } | ScanMethod::IndexOrder { index_name } => {
+                    rows.push(Row { columns: columns.clone(), values: vec![
+                        Value::Integer(*addr), Value::Text("OpenRead".into()),
+                        Value::Integer(1), Value::Integer(0), Value::Integer(0), Value::Text(index_name.clone()),
+                    ] });
+                    *addr += 1;
+                    let opcode = if matches!(method, ScanMethod::IndexOrder { .. }) { "Rewind" } else { "SeekGE" };
+                    (opcode, format!("index {}", index_name))
+                }
+            };
             rows.push(Row { columns: columns.clone(), values: vec![
-                Value::Integer(*addr), Value::Text("Rewind".into()),
-                Value::Integer(0), Value::Integer(0), Value::Integer(0), Value::Text(format!("table {}", table)),
+                Value::Integer(*addr), Value::Text(opcode.into()),
+                Value::Integer(0), Value::Integer(0), Value::Integer(0), Value::Text(p4),
             ] });
             *addr += 1;
         }
@@ -4933,6 +6755,7 @@ fn explain_plan_opcodes(
                 JoinType::Inner => "INNER JOIN",
                 JoinType::Left => "LEFT JOIN",
                 JoinType::Right => "RIGHT JOIN",
+                JoinType::Full => "FULL OUTER JOIN",
                 JoinType::Cross => "CROSS JOIN",
             };
             rows.push(Row { columns: columns.clone(), values: vec![
@@ -4941,6 +6764,22 @@ fn explain_plan_opcodes(
             ] });
             *addr += 1;
         }
+        LogicalPlan::MergeJoin { left, right, join_type, .. } => {
+            explain_plan_opcodes(left, columns, rows, addr);
+            explain_plan_opcodes(right, columns, rows, addr);
+            let jt = match join_type {
+                JoinType::Inner => "INNER JOIN",
+                JoinType::Left => "LEFT JOIN",
+                JoinType::Right => "RIGHT JOIN",
+                JoinType::Full => "FULL OUTER JOIN",
+                JoinType::Cross => "CROSS JOIN",
+            };
+            rows.push(Row { columns: columns.clone(), values: vec![
+                Value::Integer(*addr), Value::Text("MergeJoin".into()),
+                Value::Integer(0), Value::Integer(0), Value::Integer(0), Value::Text(jt.into()),
+            ] });
+            *addr += 1;
+        }
         LogicalPlan::Distinct { input } => {
             explain_plan_opcodes(input, columns, rows, addr);
             rows.push(Row { columns: columns.clone(), values: vec![
@@ -5043,11 +6882,24 @@ fn eqp_walk(
     order: &mut i64,
 ) {
     match plan {
-        LogicalPlan::SeqScan { table, alias } => {
-            let detail = if let Some(a) = alias {
-                format!("SCAN TABLE {} AS {}", table, a)
-            } else {
-                format!("SCAN TABLE {}", table)
+        LogicalPlan::SeqScan { table, alias, method } => {
+            rows.push(Row { columns: columns.clone(), values: vec![
+                Value::Integer(selectid), Value::Integer(*order),
+                Value::Integer(0), Value::Text(eqp_scan_detail(table, alias.as_deref(), method)),
+            ] });
+            *order += 1;
+        }
+        LogicalPlan::Filter { input, predicate } => {
+            // A filter on a scan is applied as the table is read
+            let detail = match input.as_ref() {
+                LogicalPlan::SeqScan { table, alias, method } => {
+                    let scan = eqp_scan_detail(table, alias.as_deref(), method);
+                    format!("{} WHERE {}", scan, expr_to_sql(predicate))
+                }
+                _ => {
+                    eqp_walk(input, columns, rows, selectid, order);
+                    format!("FILTER {}", expr_to_sql(predicate))
+                }
             };
             rows.push(Row { columns: columns.clone(), values: vec![
                 Value::Integer(selectid), Value::Integer(*order),
@@ -5055,9 +6907,6 @@ fn eqp_walk(
             ] });
             *order += 1;
         }
-        LogicalPlan::Filter { input, .. } => {
-            eqp_walk(input, columns, rows, selectid, order);
-        }
         LogicalPlan::Project { input, .. } => {
             eqp_walk(input, columns, rows, selectid, order);
         }
@@ -5087,6 +6936,15 @@ fn eqp_walk(
             eqp_walk(right, columns, rows, selectid, order);
             let _ = join_type; // join type is reflected in scan details
         }
+        LogicalPlan::MergeJoin { left, right, .. } => {
+            eqp_walk(left, columns, rows, selectid, order);
+            eqp_walk(right, columns, rows, selectid, order);
+            rows.push(Row { columns: columns.clone(), values: vec![
+                Value::Integer(selectid), Value::Integer(*order),
+                Value::Integer(0), Value::Text("MERGE JOIN ON INTEGER PRIMARY KEY".into()),
+            ] });
+            *order += 1;
+        }
         LogicalPlan::Distinct { input } => {
             eqp_walk(input, columns, rows, selectid, order);
             rows.push(Row { columns: columns.clone(), values: vec![
@@ -5122,13 +6980,54 @@ fn eqp_walk(
     }
 }
 
+/// How EXPLAIN QUERY PLAN describes a scan: `SCAN TABLE t` when it reads
+/// every row (`SCAN TABLE t USING INDEX i` when through an index in ORDER
+/// BY order), or, as in SQLite, `SEARCH TABLE t USING ...` followed by the
+/// key constraints it seeks with, such as `(rowid=?)` or `(a=? AND b>?)`.
+fn eqp_scan_detail(table: &str, alias: Option<&str>, method: &ScanMethod) -> String {
+    let table = match alias {
+        Some(a) => format!("{} AS {}", table, a),
+        None => table.to_string(),
+    };
+    let op_sql = |op: &BinaryOp| match op {
+        BinaryOp::Lt => "<",
+        BinaryOp::LtEq => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::GtEq => ">=",
+        _ => "=",
+    };
+    match method {
+        ScanMethod::FullScan => format!("SCAN TABLE {}", table),
+        ScanMethod::PkSeek { op, .. } => {
+            format!("SEARCH TABLE {} USING INTEGER PRIMARY KEY (rowid{}?)", table, op_sql(op))
+        }
+        ScanMethod::CompositePkSeek { cols } => {
+            let terms: Vec<String> = cols.iter().map(|c| format!("{}=?", c)).collect();
+            format!("SEARCH TABLE {} USING PRIMARY KEY ({})", table, terms.join(" AND "))
+        }
+        ScanMethod::IndexScan { index_name, cols, lower, upper } => {
+            let eq_cols = if *lower || *upper { cols.len().saturating_sub(1) } else { cols.len() };
+            let mut terms: Vec<String> = cols[..eq_cols].iter().map(|c| format!("{}=?", c)).collect();
+            if let Some(range_col) = cols.get(eq_cols) {
+                if *lower {
+                    terms.push(format!("{}>?", range_col));
+                }
+                if *upper {
+                    terms.push(format!("{}<?", range_col));
+                }
+            }
+            format!("SEARCH TABLE {} USING INDEX {} ({})", table, index_name, terms.join(" AND "))
+        }
+        ScanMethod::IndexOrder { index_name } => format!("SCAN TABLE {} USING INDEX {}", table, index_name),
+    }
+}
+
 #[allow(dead_code)]
 fn format_plan(plan: &LogicalPlan, indent: usize) -> String {
     let pfx = "  ".repeat(indent);
     match plan {
-        LogicalPlan::SeqScan { table, alias } => {
-            let a = alias.as_deref().map(|a| format!(" AS {}", a)).unwrap_or_default();
-            format!("{}SCAN TABLE {}{}", pfx, table, a)
+        LogicalPlan::SeqScan { table, alias, method } => {
+            format!("{}{}", pfx, eqp_scan_detail(table, alias.as_deref(), method))
         }
         LogicalPlan::Filter { input, predicate } => format!("{}FILTER {:?}\n{}", pfx, predicate, format_plan(input, indent + 1)),
         LogicalPlan::Project { input, columns } => {
@@ -5147,16 +7046,25 @@ fn format_plan(plan: &LogicalPlan, indent: usize) -> String {
             let o = offset.as_ref().map(|e| format!(" OFFSET {:?}", e)).unwrap_or_default();
             format!("{}LIMIT {:?}{}\n{}", pfx, limit, o, format_plan(input, indent + 1))
         }
-        LogicalPlan::Aggregate { input, group_by, having } => {
-            let g = if group_by.is_empty() { String::new() } else { format!(" GROUP BY {:?}", group_by) };
+        LogicalPlan::Aggregate { input, group_by, modifier, having } => {
+            let g = match (group_by.is_empty(), modifier) {
+                (true, _) => String::new(),
+                (false, None) => format!(" GROUP BY {:?}", group_by),
+                (false, Some(GroupByModifier::Rollup)) => format!(" GROUP BY ROLLUP {:?}", group_by),
+            };
             let h = having.as_ref().map(|e| format!(" HAVING {:?}", e)).unwrap_or_default();
             format!("{}AGGREGATE{}{}\n{}", pfx, g, h, format_plan(input, indent + 1))
         }
-        LogicalPlan::Join { left, right, join_type, on } => {
-            let jt = match join_type { JoinType::Inner => "INNER JOIN", JoinType::Left => "LEFT JOIN", JoinType::Right => "RIGHT JOIN", JoinType::Cross => "CROSS JOIN" };
-            let o = on.as_ref().map(|e| format!(" ON {:?}", e)).unwrap_or_default();
+        LogicalPlan::Join { left, right, join_type, on, using } => {
+            let jt = match join_type { JoinType::Inner => "INNER JOIN", JoinType::Left => "LEFT JOIN", JoinType::Right => "RIGHT JOIN", JoinType::Full => "FULL OUTER JOIN", JoinType::Cross => "CROSS JOIN" };
+            let mut o = on.as_ref().map(|e| format!(" ON {:?}", e)).unwrap_or_default();
+            if !using.is_empty() { o.push_str(&format!(" USING ({})", using.join(", "))); }
             format!("{}{}{}\n{}\n{}", pfx, jt, o, format_plan(left, indent + 1), format_plan(right, indent + 1))
         }
+        LogicalPlan::MergeJoin { left, right, join_type, left_key, right_key } => {
+            let jt = match join_type { JoinType::Inner => "INNER JOIN", JoinType::Left => "LEFT JOIN", JoinType::Right => "RIGHT JOIN", JoinType::Full => "FULL OUTER JOIN", JoinType::Cross => "CROSS JOIN" };
+            format!("{}MERGE {} ON #{} = #{}\n{}\n{}", pfx, jt, left_key, right_key, format_plan(left, indent + 1), format_plan(right, indent + 1))
+        }
         LogicalPlan::Distinct { input } => format!("{}DISTINCT\n{}", pfx, format_plan(input, indent + 1)),
         LogicalPlan::Insert { table, .. } => format!("{}INSERT INTO {}", pfx, table),
         LogicalPlan::Update { table, .. } => format!("{}UPDATE {}", pfx, table),
@@ -5172,7 +7080,7 @@ fn format_plan(plan: &LogicalPlan, indent: usize) -> String {
     }
 }
 
-// ---- ROLLBACK ----
+// ---- ROLLBACK / SAVEPOINT ----
 
 /// Execute a ROLLBACK by replaying the undo log in reverse order.
 fn execute_rollback(
@@ -5181,13 +7089,52 @@ fn execute_rollback(
     txn_mgr: &mut TransactionManager,
 ) -> Result<usize> {
     let undo_entries = txn_mgr.rollback_user_txn()?;
+    apply_undo(undo_entries, pool, catalog)?;
+    Ok(0)
+}
 
+/// Reverse the changes of the current statement, which stopped at a row
+/// another transaction holds; see
+/// [`TransactionManager::set_defer_lock_waits`].
+pub fn roll_back_statement(
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+) -> Result<()> {
+    apply_undo(txn_mgr.rollback_statement(), pool, catalog)
+}
+
+/// Roll back the user transaction if `result` reports that it was chosen
+/// as a deadlock victim, so its row locks are released.
+fn roll_back_deadlock_victim<T>(
+    result: Result<T>,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+) -> Result<T> {
+    if matches!(result, Err(HorizonError::Deadlock(_))) && txn_mgr.is_user_txn_active() {
+        execute_rollback(pool, catalog, txn_mgr)?;
+    }
+    result
+}
+
+/// Reverse the mutations recorded in `undo_entries`, which must already be
+/// in newest-first order.
+fn apply_undo(
+    undo_entries: Vec<UndoEntry>,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+) -> Result<()> {
     for entry in undo_entries {
         match entry {
             UndoEntry::Insert { table, root_page: _, key } => {
                 // Undo an insert by deleting the key
                 let current_table = catalog.get_table(&table)?.clone();
                 let mut tree = BTree::open(current_table.root_page);
+                if let Some(current) = tree.search(pool, &key)? {
+                    let row_values = deserialize_row(&current, current_table.columns.len())?;
+                    delete_index_entries(&current_table, &row_values, &key, pool, catalog)?;
+                }
                 tree.delete(pool, &key)?;
                 // Update root page if changed
                 if tree.root_page() != current_table.root_page {
@@ -5201,6 +7148,8 @@ fn execute_rollback(
                 let current_table = catalog.get_table(&table)?.clone();
                 let mut tree = BTree::open(current_table.root_page);
                 tree.insert(pool, &key, &old_value)?;
+                let row_values = deserialize_row(&old_value, current_table.columns.len())?;
+                insert_index_entries(&current_table, &row_values, &key, pool, catalog)?;
                 // Update root page if changed
                 if tree.root_page() != current_table.root_page {
                     let mut updated = current_table.clone();
@@ -5212,7 +7161,13 @@ fn execute_rollback(
                 // Undo an update by restoring the old value
                 let current_table = catalog.get_table(&table)?.clone();
                 let mut tree = BTree::open(current_table.root_page);
+                if let Some(current) = tree.search(pool, &key)? {
+                    let row_values = deserialize_row(&current, current_table.columns.len())?;
+                    delete_index_entries(&current_table, &row_values, &key, pool, catalog)?;
+                }
                 tree.insert(pool, &key, &old_value)?;
+                let row_values = deserialize_row(&old_value, current_table.columns.len())?;
+                insert_index_entries(&current_table, &row_values, &key, pool, catalog)?;
                 // Update root page if changed
                 if tree.root_page() != current_table.root_page {
                     let mut updated = current_table.clone();
@@ -5223,7 +7178,7 @@ fn execute_rollback(
         }
     }
 
-    Ok(0)
+    Ok(())
 }
 
 // ---- Helper Functions ----
@@ -5259,29 +7214,298 @@ pub fn deserialize_row(data: &[u8], expected_cols: usize) -> Result<Vec<Value>>
         offset += consumed;
     }
 
-    // Truncate extra columns from rows written before DROP COLUMN
-    values.truncate(expected_cols);
-
-    // NULL-pad any columns added after this row was written (lazy ALTER TABLE ADD COLUMN)
-    while values.len() < expected_cols {
-        values.push(Value::Null);
+    // Truncate extra columns from rows written before DROP COLUMN
+    values.truncate(expected_cols);
+
+    // NULL-pad any columns added after this row was written (lazy ALTER TABLE ADD COLUMN)
+    while values.len() < expected_cols {
+        values.push(Value::Null);
+    }
+
+    Ok(values)
+}
+
+/// Build a composite index key by serializing the indexed values.
+/// The key is: [sortable_values][rowid_bytes]
+/// The rowid suffix ensures uniqueness in the B+Tree even when column values
+/// are duplicated across rows, since the B+Tree uses upsert semantics.
+fn build_index_key(values: &[Value], rowid: &[u8]) -> Vec<u8> {
+    let mut key = build_index_prefix(values);
+    key.extend_from_slice(rowid);
+    key
+}
+
+/// The values of a row for each key part of an index: the column value
+/// for plain columns, or the evaluated expression for expression parts.
+fn index_key_values(
+    idx_info: &crate::catalog::IndexInfo,
+    table: &TableInfo,
+    row_values: &[Value],
+) -> Result<Vec<Value>> {
+    idx_info.columns.iter().enumerate().map(|(i, col_name)| {
+        match idx_info.key_expr(i) {
+            Some(expr) => eval_expr(expr, row_values, &table.columns, table),
+            None => table.find_column_index(col_name)
+                .map(|idx| row_values[idx].clone())
+                .ok_or_else(|| HorizonError::ColumnNotFound(format!("{}.{}", table.name, col_name))),
+        }
+    }).collect()
+}
+
+/// The B+Tree of `index`, ordered by the collations its key uses.
+fn index_tree(index: &crate::catalog::IndexInfo, catalog: &Catalog) -> Result<BTree> {
+    Ok(BTree::open(index.root_page).ordered_by(collation::index_key_order(index, catalog.collations())?))
+}
+
+/// Add the entries for one table row to every index on `table`.
+fn insert_index_entries(
+    table: &TableInfo,
+    row_values: &[Value],
+    key: &[u8],
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+) -> Result<()> {
+    let indexes = catalog.get_indexes_for_table(&table.name)
+        .iter().map(|idx| (*idx).clone()).collect::<Vec<_>>();
+    for idx_info in &indexes {
+        if !index_covers_row(idx_info, table, row_values)? {
+            continue;
+        }
+        let values = index_key_values(idx_info, table, row_values)?;
+        let index_key = build_index_key(&values, key);
+        let mut index_tree = index_tree(idx_info, catalog)?;
+        index_tree.insert(pool, &index_key, key)?;
+        sync_index_root(idx_info, &index_tree, pool, catalog)?;
+    }
+    Ok(())
+}
+
+/// Fail if an index on `table` could not hold the entry for the row
+/// `row_values` stored under `key`, before any of the row is written.
+fn check_index_key_sizes(
+    table: &TableInfo,
+    row_values: &[Value],
+    key: &[u8],
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<()> {
+    for idx_info in catalog.get_indexes_for_table(&table.name) {
+        if index_covers_row(idx_info, table, row_values)? {
+            let values = index_key_values(idx_info, table, row_values)?;
+            BTree::check_key_size(pool, &build_index_key(&values, key))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the entries for one table row from every index on `table`.
+fn delete_index_entries(
+    table: &TableInfo,
+    row_values: &[Value],
+    key: &[u8],
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+) -> Result<()> {
+    let indexes = catalog.get_indexes_for_table(&table.name)
+        .iter().map(|idx| (*idx).clone()).collect::<Vec<_>>();
+    for idx_info in &indexes {
+        if !index_covers_row(idx_info, table, row_values)? {
+            continue;
+        }
+        let values = index_key_values(idx_info, table, row_values)?;
+        let index_key = build_index_key(&values, key);
+        let mut index_tree = index_tree(idx_info, catalog)?;
+        index_tree.delete(pool, &index_key)?;
+        sync_index_root(idx_info, &index_tree, pool, catalog)?;
+    }
+    Ok(())
+}
+
+/// Whether a row belongs in an index: always, unless the index is partial
+/// and the row does not match its predicate.
+fn index_covers_row(
+    idx_info: &crate::catalog::IndexInfo,
+    table: &TableInfo,
+    row_values: &[Value],
+) -> Result<bool> {
+    match idx_info.predicate {
+        Some(ref predicate) => Ok(eval_expr(predicate, row_values, &table.columns, table)?.to_bool()),
+        None => Ok(true),
+    }
+}
+
+/// Record the new root page of an index tree after splits.
+fn sync_index_root(
+    idx_info: &crate::catalog::IndexInfo,
+    index_tree: &BTree,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+) -> Result<()> {
+    if index_tree.root_page() != idx_info.root_page {
+        let mut updated_idx = idx_info.clone();
+        updated_idx.root_page = index_tree.root_page();
+        catalog.drop_index(pool, &idx_info.name)?;
+        catalog.create_index(pool, updated_idx)?;
+    }
+    Ok(())
+}
+
+/// Find a row of `table`, other than the one stored under `self_key`, that
+/// holds the same value as `row_values` in a UNIQUE column.
+///
+/// Returns the conflicting row's entry together with the column name.
+/// NULLs never conflict, and an INTEGER PRIMARY KEY is skipped because it
+/// is the rowid, which the caller checks directly.
+fn find_unique_conflict(
+    table: &TableInfo,
+    row_values: &[Value],
+    self_key: &[u8],
+    tree: &BTree,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<Option<(crate::btree::BTreeEntry, String)>> {
+    for (i, col) in table.columns.iter().enumerate() {
+        if !col.unique || row_values[i].is_null() {
+            continue;
+        }
+        if table.pk_column == Some(i) && col.affinity == DataType::Integer {
+            continue;
+        }
+        let predicate = Expr::BinaryOp {
+            left: Box::new(Expr::Column { table: None, name: col.name.clone() }),
+            op: BinaryOp::Eq,
+            right: Box::new(Expr::Literal(params::value_to_literal(&row_values[i]))),
+        };
+        for entry in scan_with_index(Some(&predicate), None, &table.name, table, tree, pool, catalog)? {
+            if entry.key == self_key {
+                continue;
+            }
+            let existing = deserialize_row(&entry.value, table.columns.len())?;
+            if existing[i] == row_values[i] {
+                return Ok(Some((entry, col.name.clone())));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Check the UNIQUE columns of a row about to be inserted under `key`.
+///
+/// Without OR REPLACE a conflict is an error; with it, every conflicting
+/// row is deleted first so the new row takes its place.
+#[allow(clippy::too_many_arguments)]
+fn resolve_unique_conflicts(
+    ins: &InsertStatement,
+    table: &TableInfo,
+    row_values: &[Value],
+    key: &[u8],
+    tree: &mut BTree,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+) -> Result<()> {
+    while let Some((old, column)) = find_unique_conflict(table, row_values, key, tree, pool, catalog)? {
+        if !ins.or_replace {
+            return Err(HorizonError::ConstraintViolation(format!(
+                "UNIQUE constraint failed: {}.{}",
+                table.name, column
+            )));
+        }
+        txn_mgr.lock_row(&table.name, &old.key)?;
+        tree.delete(pool, &old.key)?;
+        let old_values = deserialize_row(&old.value, table.columns.len())?;
+        delete_index_entries(table, &old_values, &old.key, pool, catalog)?;
+        txn_mgr.record_undo(UndoEntry::Delete {
+            table: table.name.clone(),
+            root_page: tree.root_page(),
+            key: old.key,
+            old_value: old.value,
+        });
+    }
+    Ok(())
+}
+
+/// Fail if the updated row stored under `key` duplicates a UNIQUE value.
+fn check_unique_on_update(
+    table: &TableInfo,
+    row_values: &[Value],
+    key: &[u8],
+    tree: &BTree,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<()> {
+    match find_unique_conflict(table, row_values, key, tree, pool, catalog)? {
+        Some((_, column)) => Err(HorizonError::ConstraintViolation(format!(
+            "UNIQUE constraint failed: {}.{}",
+            table.name, column
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The B+Tree key for a new row: its rowid, or for a table with a composite
+/// primary key the serialized key column values.
+fn row_key(table: &TableInfo, row_values: &[Value], rowid: i64) -> Vec<u8> {
+    if table.has_composite_pk() {
+        let values: Vec<Value> = table.pk_columns.iter().map(|&i| row_values[i].clone()).collect();
+        build_index_prefix(&values)
+    } else {
+        rowid.to_be_bytes().to_vec()
+    }
+}
+
+/// Store an updated row. A row of a table with a composite primary key
+/// moves to a new B+Tree key when one of its key columns changes; the key
+/// the row ends up under is returned.
+fn write_updated_row(
+    table: &TableInfo,
+    key: &[u8],
+    row_values: &[Value],
+    tree: &mut BTree,
+    pool: &mut BufferPool,
+    txn_mgr: &mut TransactionManager,
+) -> Result<Vec<u8>> {
+    let row_data = serialize_row(row_values);
+    let new_key = updated_row_key(table, key, row_values);
+    if new_key != key {
+        if tree.search(pool, &new_key)?.is_some() {
+            return Err(HorizonError::ConstraintViolation(format!(
+                "UNIQUE constraint failed: {}",
+                primary_key_label(table)
+            )));
+        }
+        txn_mgr.lock_row(&table.name, &new_key)?;
+        tree.delete(pool, key)?;
+        txn_mgr.record_undo(UndoEntry::Insert {
+            table: table.name.clone(),
+            root_page: tree.root_page(),
+            key: new_key.clone(),
+        });
+    }
+    tree.insert(pool, &new_key, &row_data)?;
+    Ok(new_key)
+}
+
+/// The key of the row stored under `key` once it holds `row_values`: a
+/// composite primary key follows the row's values, a rowid does not change.
+fn updated_row_key(table: &TableInfo, key: &[u8], row_values: &[Value]) -> Vec<u8> {
+    if table.has_composite_pk() {
+        row_key(table, row_values, 0)
+    } else {
+        key.to_vec()
     }
-
-    Ok(values)
 }
 
-/// Build a composite index key by serializing the indexed column values.
-/// The key is: [serialized_column_values][rowid_bytes]
-/// The rowid suffix ensures uniqueness in the B+Tree even when column values
-/// are duplicated across rows, since the B+Tree uses upsert semantics.
-fn build_index_key(row_values: &[Value], col_indices: &[usize], rowid: &[u8]) -> Vec<u8> {
-    let mut key = Vec::new();
-    for &idx in col_indices {
-        let val = &row_values[idx];
-        key.extend(val.serialize());
+/// Name the primary key in constraint errors, e.g. `t.rowid` or `t.a, t.b`.
+fn primary_key_label(table: &TableInfo) -> String {
+    if table.has_composite_pk() {
+        table.pk_columns.iter()
+            .map(|&i| format!("{}.{}", table.name, table.columns[i].name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    } else {
+        format!("{}.rowid", table.name)
     }
-    key.extend_from_slice(rowid);
-    key
 }
 
 /// Build an index key prefix from column values only (without rowid).
@@ -5289,7 +7513,7 @@ fn build_index_key(row_values: &[Value], col_indices: &[usize], rowid: &[u8]) ->
 fn build_index_prefix(values: &[Value]) -> Vec<u8> {
     let mut key = Vec::new();
     for val in values {
-        key.extend(val.serialize());
+        val.serialize_sortable(&mut key);
     }
     key
 }
@@ -5299,6 +7523,15 @@ fn build_index_prefix(values: &[Value]) -> Vec<u8> {
 ///   column = literal, column > literal, column < literal, etc.
 /// Returns None for complex predicates that cannot use an index.
 fn extract_index_predicate(expr: &Expr) -> Option<(String, &BinaryOp, Value)> {
+    match extract_key_predicate(expr)? {
+        (Expr::Column { name, .. }, op, val) => Some((name.clone(), op, val)),
+        _ => None,
+    }
+}
+
+/// Match `expr op constant` (or `constant op expr`) and return the
+/// non-constant side with the operator oriented towards it.
+fn extract_key_predicate(expr: &Expr) -> Option<(&Expr, &BinaryOp, Value)> {
     match expr {
         Expr::BinaryOp { left, op, right } => {
             match op {
@@ -5307,29 +7540,25 @@ fn extract_index_predicate(expr: &Expr) -> Option<(String, &BinaryOp, Value)> {
                 _ => return None,
             }
 
-            // Pattern: column op literal
-            if let Expr::Column { name, .. } = left.as_ref() {
-                if is_const_expr(right) {
-                    let val = eval_const_expr(right);
-                    return Some((name.clone(), op, val));
-                }
+            // Pattern: expr op literal
+            if is_const_expr(right) && !is_const_expr(left) {
+                let val = eval_const_expr(right);
+                return Some((left.as_ref(), op, val));
             }
 
-            // Pattern: literal op column (reverse the comparison)
-            if let Expr::Column { name, .. } = right.as_ref() {
-                if is_const_expr(left) {
-                    let val = eval_const_expr(left);
-                    // Reverse the operator: literal < column => column > literal
-                    let reversed_op = match op {
-                        BinaryOp::Lt => &BinaryOp::Gt,
-                        BinaryOp::LtEq => &BinaryOp::GtEq,
-                        BinaryOp::Gt => &BinaryOp::Lt,
-                        BinaryOp::GtEq => &BinaryOp::LtEq,
-                        BinaryOp::Eq => &BinaryOp::Eq,
-                        _ => return None,
-                    };
-                    return Some((name.clone(), reversed_op, val));
-                }
+            // Pattern: literal op expr (reverse the comparison)
+            if is_const_expr(left) && !is_const_expr(right) {
+                let val = eval_const_expr(left);
+                // Reverse the operator: literal < column => column > literal
+                let reversed_op = match op {
+                    BinaryOp::Lt => &BinaryOp::Gt,
+                    BinaryOp::LtEq => &BinaryOp::GtEq,
+                    BinaryOp::Gt => &BinaryOp::Lt,
+                    BinaryOp::GtEq => &BinaryOp::LtEq,
+                    BinaryOp::Eq => &BinaryOp::Eq,
+                    _ => return None,
+                };
+                return Some((right.as_ref(), reversed_op, val));
             }
 
             None
@@ -5349,7 +7578,7 @@ fn is_const_expr(expr: &Expr) -> bool {
 }
 
 /// Evaluate a constant expression (no row context needed).
-fn eval_const_expr(expr: &Expr) -> Value {
+pub(crate) fn eval_const_expr(expr: &Expr) -> Value {
     match expr {
         Expr::Literal(lit) => literal_to_value(lit),
         Expr::UnaryOp { op: UnaryOp::Neg, expr } => {
@@ -5368,7 +7597,7 @@ fn eval_const_expr(expr: &Expr) -> Value {
         Expr::BinaryOp { left, op, right } => {
             let l = eval_const_expr(left);
             let r = eval_const_expr(right);
-            eval_binary_op(&l, op, &r)
+            eval_collated_binary_op(left, &l, op, right, &r).unwrap_or(Value::Null)
         }
         Expr::Cast { expr: inner, type_name } => {
             let val = eval_const_expr(inner);
@@ -5413,28 +7642,52 @@ fn eval_const_expr(expr: &Expr) -> Value {
     }
 }
 
-/// Execute a scalar subquery, returning the single value from the first row/column.
-/// If the subquery returns no rows, returns NULL.
+/// Whether `expr` contains a scalar, IN, or EXISTS subquery.
+fn expr_has_subquery(expr: &Expr) -> bool {
+    let mut found = false;
+    let _ = params::walk_expr(&mut expr.clone(), &mut |e| {
+        found |= matches!(e, Expr::Subquery(_) | Expr::Exists(_));
+        Ok(())
+    });
+    found
+}
+
+/// Execute a scalar subquery, returning the value of its single row and column.
+/// If the subquery returns no rows, returns NULL; more than one row is an error.
 fn execute_scalar_subquery(
-    select: &SelectStatement,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    mut select: Cow<'_, SelectStatement>,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<Value> {
-    let result = execute_select(select, pool, catalog)?;
-    if result.rows.is_empty() {
-        Ok(Value::Null)
-    } else {
-        Ok(result.rows[0].values.first().cloned().unwrap_or(Value::Null))
+    // A second row is enough to report the error.
+    if select.limit.is_none() {
+        select.to_mut().limit = Some(Expr::Literal(LiteralValue::Integer(2)));
+    }
+    let result = execute_select(&select, pool, catalog)?;
+    if result.columns.len() != 1 {
+        return Err(HorizonError::InvalidSql(format!(
+            "scalar subquery returns {} columns, expected 1",
+            result.columns.len()
+        )));
+    }
+    match result.rows.len() {
+        0 => Ok(Value::Null),
+        1 => Ok(result.rows[0].values.first().cloned().unwrap_or(Value::Null)),
+        _ => Err(HorizonError::TooManyRows),
     }
 }
 
 /// Execute an EXISTS subquery, returning 1 (true) if the subquery returns any rows, 0 otherwise.
+/// Only the first row matters, so a subquery without a LIMIT is run with `LIMIT 1`.
 fn execute_exists_subquery(
-    select: &SelectStatement,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    mut select: Cow<'_, SelectStatement>,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<Value> {
-    let result = execute_select(select, pool, catalog)?;
+    if select.limit.is_none() {
+        select.to_mut().limit = Some(Expr::Literal(LiteralValue::Integer(1)));
+    }
+    let result = execute_select(&select, pool, catalog)?;
     Ok(Value::Integer(if result.rows.is_empty() { 0 } else { 1 }))
 }
 
@@ -5464,7 +7717,7 @@ fn eval_expr(
         Expr::BinaryOp { left, op, right } => {
             let l = eval_expr(left, row, columns, table)?;
             let r = eval_expr(right, row, columns, table)?;
-            Ok(eval_binary_op(&l, op, &r))
+            eval_collated_binary_op(left, &l, op, right, &r)
         }
         Expr::UnaryOp { op, expr: inner } => {
             let val = eval_expr(inner, row, columns, table)?;
@@ -5503,7 +7756,7 @@ fn eval_expr(
             };
             Ok(Value::Integer(if matches != *negated { 1 } else { 0 }))
         }
-        Expr::Function { name, args, distinct: _ } => {
+        Expr::Function { name, args, .. } => {
             eval_function(name, args, row, columns, table)
         }
         Expr::Cast { expr: inner, type_name } => {
@@ -5534,7 +7787,7 @@ fn eval_expr(
                 Ok(Value::Null)
             }
         }
-        Expr::Placeholder(_) => Ok(Value::Null),
+        Expr::Placeholder(_) | Expr::NamedPlaceholder(_) => Ok(Value::Null),
         Expr::Subquery(_) | Expr::Exists(_) => {
             // Subqueries require pool/catalog context; use eval_expr_with_ctx instead
             Err(HorizonError::NotImplemented("subqueries in expressions (use eval_expr_with_ctx)".into()))
@@ -5562,22 +7815,25 @@ fn eval_expr_with_ctx(
     row: &[Value],
     columns: &[ColumnInfo],
     table: &TableInfo,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<Value> {
+    // The row belongs to a single table, so an outer reference is resolved
+    // by column name whatever its qualifier.
+    let outer = |_: &str, name: &str| table.find_column_index(name).and_then(|i| row.get(i).cloned());
     match expr {
         Expr::Subquery(select) => {
-            execute_scalar_subquery(select, pool, catalog)
+            execute_scalar_subquery(correlate(select, &outer), pool, catalog)
         }
         Expr::Exists(select) => {
-            execute_exists_subquery(select, pool, catalog)
+            execute_exists_subquery(correlate(select, &outer), pool, catalog)
         }
         // For all non-subquery expressions, delegate to eval_expr.
         // But we need to recursively handle subqueries in nested expressions too.
         Expr::BinaryOp { left, op, right } => {
             let l = eval_expr_with_ctx(left, row, columns, table, pool, catalog)?;
             let r = eval_expr_with_ctx(right, row, columns, table, pool, catalog)?;
-            Ok(eval_binary_op(&l, op, &r))
+            eval_collated_binary_op(left, &l, op, right, &r)
         }
         Expr::UnaryOp { op, expr: inner } => {
             let val = eval_expr_with_ctx(inner, row, columns, table, pool, catalog)?;
@@ -5617,7 +7873,7 @@ fn eval_expr_with_ctx(
             // Expr::Subquery item for `IN (SELECT ...)`)
             if list.len() == 1 {
                 if let Expr::Subquery(subquery) = &list[0] {
-                    let result = execute_select(subquery, pool, catalog)?;
+                    let result = execute_select(&correlate(subquery, &outer), pool, catalog)?;
                     let mut found = false;
                     for sub_row in &result.rows {
                         if let Some(sub_val) = sub_row.values.first() {
@@ -5664,25 +7920,29 @@ fn eval_expr_with_ctx(
 }
 
 /// Evaluate a dynamic expression with full subquery support (has pool/catalog context).
-#[allow(dead_code)]
 fn eval_expr_dynamic_with_ctx(
     expr: &Expr,
     row: &[Value],
     col_names: &[String],
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<Value> {
+    let outer = |table: &str, name: &str| {
+        find_column_dynamic(&format!("{}.{}", table, name), row, col_names)
+            .or_else(|_| find_column_dynamic(name, row, col_names))
+            .ok()
+    };
     match expr {
         Expr::Subquery(select) => {
-            execute_scalar_subquery(select, pool, catalog)
+            execute_scalar_subquery(correlate(select, &outer), pool, catalog)
         }
         Expr::Exists(select) => {
-            execute_exists_subquery(select, pool, catalog)
+            execute_exists_subquery(correlate(select, &outer), pool, catalog)
         }
         Expr::BinaryOp { left, op, right } => {
             let l = eval_expr_dynamic_with_ctx(left, row, col_names, pool, catalog)?;
             let r = eval_expr_dynamic_with_ctx(right, row, col_names, pool, catalog)?;
-            Ok(eval_binary_op(&l, op, &r))
+            eval_collated_binary_op(left, &l, op, right, &r)
         }
         Expr::UnaryOp { op, expr: inner } => {
             let val = eval_expr_dynamic_with_ctx(inner, row, col_names, pool, catalog)?;
@@ -5722,7 +7982,7 @@ fn eval_expr_dynamic_with_ctx(
             // Expr::Subquery item for `IN (SELECT ...)`)
             if list.len() == 1 {
                 if let Expr::Subquery(subquery) = &list[0] {
-                    let result = execute_select(subquery, pool, catalog)?;
+                    let result = execute_select(&correlate(subquery, &outer), pool, catalog)?;
                     let mut found = false;
                     for sub_row in &result.rows {
                         if let Some(sub_val) = sub_row.values.first() {
@@ -5778,38 +8038,26 @@ fn literal_to_value(lit: &LiteralValue) -> Value {
     }
 }
 
-/// Compare two values using the specified collation sequence.
-/// Supported collations: BINARY (default), NOCASE (case-insensitive), RTRIM (ignore trailing spaces).
-fn compare_with_collation(a: &Value, b: &Value, collation: &str) -> std::cmp::Ordering {
-    let coll_upper = collation.to_uppercase();
-    match coll_upper.as_str() {
-        "NOCASE" => {
-            // Case-insensitive comparison for text values
-            match (a, b) {
-                (Value::Text(sa), Value::Text(sb)) => {
-                    let la = sa.to_lowercase();
-                    let lb = sb.to_lowercase();
-                    la.cmp(&lb)
-                }
-                _ => a.cmp(b),
-            }
-        }
-        "RTRIM" => {
-            // Ignore trailing spaces for text values
-            match (a, b) {
-                (Value::Text(sa), Value::Text(sb)) => {
-                    let ta = sa.trim_end();
-                    let tb = sb.trim_end();
-                    ta.cmp(tb)
-                }
-                _ => a.cmp(b),
-            }
-        }
-        _ => {
-            // BINARY or unknown — default comparison
-            a.cmp(b)
-        }
-    }
+/// Apply the binary operator `op` to `l` and `r`, the values of `left`
+/// and `right`. A comparison of two text values with a COLLATE operand
+/// compares them with that collation (the left operand's if both have
+/// one), which must be a built-in one.
+fn eval_collated_binary_op(left: &Expr, l: &Value, op: &BinaryOp, right: &Expr, r: &Value) -> Result<Value> {
+    let collation = extract_collation(left).or_else(|| extract_collation(right));
+    let (Some(collation), Value::Text(_), Value::Text(_)) = (collation, l, r) else {
+        return eval_binary_op(l, op, r);
+    };
+    let ordering = collation::Collations::new().compare(l, r, collation);
+    let holds = match op {
+        BinaryOp::Eq => ordering.is_eq(),
+        BinaryOp::NotEq => ordering.is_ne(),
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::LtEq => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        BinaryOp::GtEq => ordering.is_ge(),
+        _ => return eval_binary_op(l, op, r),
+    };
+    Ok(Value::Integer(i64::from(holds)))
 }
 
 /// Extract collation name from an expression if it has one.
@@ -5821,29 +8069,29 @@ fn extract_collation(expr: &Expr) -> Option<&str> {
     }
 }
 
-fn eval_binary_op(left: &Value, op: &BinaryOp, right: &Value) -> Value {
+pub(crate) fn eval_binary_op(left: &Value, op: &BinaryOp, right: &Value) -> Result<Value> {
     // Handle NULL propagation
     if left.is_null() || right.is_null() {
         match op {
             BinaryOp::And => {
                 // FALSE AND NULL = FALSE
-                if let Value::Integer(0) = left { return Value::Integer(0); }
-                if let Value::Integer(0) = right { return Value::Integer(0); }
-                return Value::Null;
+                if let Value::Integer(0) = left { return Ok(Value::Integer(0)); }
+                if let Value::Integer(0) = right { return Ok(Value::Integer(0)); }
+                return Ok(Value::Null);
             }
             BinaryOp::Or => {
                 // TRUE OR NULL = TRUE
-                if left.to_bool() { return Value::Integer(1); }
-                if right.to_bool() { return Value::Integer(1); }
-                return Value::Null;
+                if left.to_bool() { return Ok(Value::Integer(1)); }
+                if right.to_bool() { return Ok(Value::Integer(1)); }
+                return Ok(Value::Null);
             }
             BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::Gt |
-            BinaryOp::LtEq | BinaryOp::GtEq => return Value::Null,
-            _ => return Value::Null,
+            BinaryOp::LtEq | BinaryOp::GtEq => return Ok(Value::Null),
+            _ => return Ok(Value::Null),
         }
     }
 
-    match op {
+    Ok(match op {
         BinaryOp::Add => numeric_op(left, right, |a, b| a + b, |a, b| a + b),
         BinaryOp::Sub => numeric_op(left, right, |a, b| a - b, |a, b| a - b),
         BinaryOp::Mul => numeric_op(left, right, |a, b| a * b, |a, b| a * b),
@@ -5874,13 +8122,13 @@ fn eval_binary_op(left: &Value, op: &BinaryOp, right: &Value) -> Value {
                 Value::Text(s) => s.clone(),
                 Value::Integer(i) => i.to_string(),
                 Value::Real(r) => r.to_string(),
-                _ => return Value::Null,
+                _ => return Ok(Value::Null),
             };
             let r = match right {
                 Value::Text(s) => s.clone(),
                 Value::Integer(i) => i.to_string(),
                 Value::Real(r) => r.to_string(),
-                _ => return Value::Null,
+                _ => return Ok(Value::Null),
             };
             Value::Text(format!("{}{}", l, r))
         }
@@ -5908,10 +8156,17 @@ fn eval_binary_op(left: &Value, op: &BinaryOp, right: &Value) -> Value {
                 _ => Value::Null,
             }
         }
-    }
+        BinaryOp::Regexp => regexp_match(right, left)?,
+        BinaryOp::Glob => match (match_text(left), match_text(right)) {
+            (Some(text), Some(pattern)) => {
+                Value::Integer(if sql_glob_match(&text, &pattern) { 1 } else { 0 })
+            }
+            _ => Value::Integer(0),
+        },
+    })
 }
 
-fn eval_unary_op(op: &UnaryOp, val: &Value) -> Value {
+pub(crate) fn eval_unary_op(op: &UnaryOp, val: &Value) -> Value {
     match op {
         UnaryOp::Neg => match val {
             Value::Integer(i) => Value::Integer(-i),
@@ -5956,6 +8211,7 @@ fn eval_function(
 ) -> Result<Value> {
     let upper = name.to_uppercase();
     match upper.as_str() {
+        "RAISE" => eval_raise(args, |message| eval_expr(message, row, columns, table)),
         "ABS" => {
             let val = eval_expr(&args[0], row, columns, table)?;
             match val {
@@ -5964,15 +8220,6 @@ fn eval_function(
                 _ => Ok(Value::Null),
             }
         }
-        "LENGTH" | "LEN" => {
-            let val = eval_expr(&args[0], row, columns, table)?;
-            match val {
-                Value::Text(s) => Ok(Value::Integer(s.len() as i64)),
-                Value::Blob(b) => Ok(Value::Integer(b.len() as i64)),
-                Value::Null => Ok(Value::Null),
-                _ => Ok(Value::Integer(format!("{}", val).len() as i64)),
-            }
-        }
         "UPPER" => {
             let val = eval_expr(&args[0], row, columns, table)?;
             match val {
@@ -6052,7 +8299,8 @@ fn eval_function(
             }
             Ok(min_val)
         }
-        "MAX" | "MIN" | "COUNT" | "SUM" | "AVG" | "TOTAL" | "GROUP_CONCAT" => {
+        "MAX" | "MIN" | "COUNT" | "SUM" | "AVG" | "TOTAL" | "GROUP_CONCAT" | "JSON_AGG"
+        | "STDDEV" | "STDDEV_POP" | "STDDEV_SAMP" | "VARIANCE" | "VAR_POP" | "VAR_SAMP" => {
             // These are aggregate functions -- evaluated per-row they just return the value
             // Full aggregate support handled at a higher level
             if !args.is_empty() {
@@ -6061,42 +8309,6 @@ fn eval_function(
                 Ok(Value::Null)
             }
         }
-        "SUBSTR" | "SUBSTRING" => {
-            if args.len() < 2 {
-                return Ok(Value::Null);
-            }
-            let val = eval_expr(&args[0], row, columns, table)?;
-            let start = eval_expr(&args[1], row, columns, table)?;
-            let len = if args.len() > 2 {
-                eval_expr(&args[2], row, columns, table)?.as_integer()
-            } else {
-                None
-            };
-
-            match (val, start.as_integer()) {
-                (Value::Text(s), Some(start_pos)) => {
-                    let start_idx = if start_pos > 0 {
-                        (start_pos - 1) as usize
-                    } else {
-                        0
-                    };
-                    let substr_result = if let Some(l) = len {
-                        s.chars().skip(start_idx).take(l as usize).collect::<String>()
-                    } else {
-                        s.chars().skip(start_idx).collect::<String>()
-                    };
-                    Ok(Value::Text(substr_result))
-                }
-                _ => Ok(Value::Null),
-            }
-        }
-        "TRIM" => {
-            let val = eval_expr(&args[0], row, columns, table)?;
-            match val {
-                Value::Text(s) => Ok(Value::Text(s.trim().to_string())),
-                _ => Ok(val),
-            }
-        }
         "REPLACE" => {
             if args.len() < 3 {
                 return Ok(Value::Null);
@@ -6111,35 +8323,6 @@ fn eval_function(
                 _ => Ok(Value::Null),
             }
         }
-        "INSTR" => {
-            if args.len() < 2 {
-                return Ok(Value::Null);
-            }
-            let val = eval_expr(&args[0], row, columns, table)?;
-            let search = eval_expr(&args[1], row, columns, table)?;
-            match (val, search) {
-                (Value::Text(s), Value::Text(needle)) => {
-                    Ok(Value::Integer(s.find(&needle).map(|i| i as i64 + 1).unwrap_or(0)))
-                }
-                _ => Ok(Value::Integer(0)),
-            }
-        }
-        "LTRIM" => {
-            if args.is_empty() { return Ok(Value::Null); }
-            let val = eval_expr(&args[0], row, columns, table)?;
-            match val {
-                Value::Text(s) => Ok(Value::Text(s.trim_start().to_string())),
-                _ => Ok(val),
-            }
-        }
-        "RTRIM" => {
-            if args.is_empty() { return Ok(Value::Null); }
-            let val = eval_expr(&args[0], row, columns, table)?;
-            match val {
-                Value::Text(s) => Ok(Value::Text(s.trim_end().to_string())),
-                _ => Ok(val),
-            }
-        }
         "HEX" => {
             if args.is_empty() { return Ok(Value::Null); }
             let val = eval_expr(&args[0], row, columns, table)?;
@@ -6188,6 +8371,7 @@ fn eval_function(
                 _ => Ok(Value::Null),
             }
         }
+        "UUID" | "UUID_BLOB" => eval_uuid_function(&upper, args.len()),
         "RANDOM" => {
             let now = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -6236,17 +8420,7 @@ fn eval_function(
             let json_val = eval_expr(&args[0], row, columns, table)?;
             let path_val = eval_expr(&args[1], row, columns, table)?;
             match (json_val, path_val) {
-                (Value::Text(s), Value::Text(path)) => {
-                    match json::JsonParser::parse(&s) {
-                        Some(jv) => {
-                            match jv.extract_path(&path) {
-                                Some(extracted) => Ok(json::json_value_to_sql(extracted)),
-                                None => Ok(Value::Null),
-                            }
-                        }
-                        None => Ok(Value::Null),
-                    }
-                }
+                (Value::Text(s), Value::Text(path)) => Ok(json::json_extract(&s, &path)),
                 _ => Ok(Value::Null),
             }
         }
@@ -6278,6 +8452,10 @@ fn eval_function(
                 _ => Ok(Value::Null),
             }
         }
+        "ROW_TO_JSON" => {
+            let col_names: Vec<String> = columns.iter().map(|c| c.name.clone()).collect();
+            row_to_json(args, row, &col_names)
+        }
         "JSON_ARRAY" => {
             let mut items = Vec::new();
             for arg in args {
@@ -6354,6 +8532,13 @@ fn eval_function(
                 _ => Ok(Value::Integer(0)),
             }
         }
+        _ if json::is_json_mutation(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr(arg, row, columns, table)?);
+            }
+            json::eval_json_mutation(&upper, &arg_values)
+        }
         // -- Additional utility functions --
         "PRINTF" => {
             if args.is_empty() { return Ok(Value::Null); }
@@ -6378,7 +8563,7 @@ fn eval_function(
                                 match v {
                                     Value::Integer(i) => result.push_str(&i.to_string()),
                                     Value::Real(r) => result.push_str(&(r as i64).to_string()),
-                                    _ => result.push_str("0"),
+                                    _ => result.push('0'),
                                 }
                             }
                             ci += 1;
@@ -6453,14 +8638,46 @@ fn eval_function(
             }
             Ok(Value::Text(result))
         }
+        "REGEXP" => {
+            if args.len() != 2 {
+                return Err(HorizonError::InvalidSql("regexp() takes 2 arguments".into()));
+            }
+            let pattern = eval_expr(&args[0], row, columns, table)?;
+            let text = eval_expr(&args[1], row, columns, table)?;
+            regexp_match(&pattern, &text)
+        }
         // -- Date/Time functions --
-        "DATE" | "TIME" | "DATETIME" | "STRFTIME" | "JULIANDAY" => {
+        "DATE" | "TIME" | "DATETIME" | "STRFTIME" | "JULIANDAY" | "UNIXEPOCH" => {
             let mut arg_values = Vec::new();
             for arg in args {
                 arg_values.push(eval_expr(arg, row, columns, table)?);
             }
             Ok(eval_datetime_function(&upper, &arg_values))
         }
+        // -- Hash functions --
+        _ if crypto::is_hash_function(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr(arg, row, columns, table)?);
+            }
+            crypto::eval_hash_function(&upper, &arg_values)
+        }
+        // -- Math functions --
+        _ if math::is_math_function(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr(arg, row, columns, table)?);
+            }
+            math::eval_math_function(&upper, &arg_values)
+        }
+        // -- String functions --
+        _ if strings::is_string_function(&upper) => {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(eval_expr(arg, row, columns, table)?);
+            }
+            strings::eval_string_function(&upper, &arg_values)
+        }
         _ => Err(HorizonError::NotImplemented(format!("function: {}", name))),
     }
 }
@@ -6505,6 +8722,200 @@ fn like_match_inner(text: &[char], ti: usize, pattern: &[char], pi: usize) -> bo
     }
 }
 
+/// SQL GLOB pattern matching: case-sensitive, with `*` matching any run of
+/// characters, `?` exactly one, and `[...]` a character class (`[^...]`
+/// negates it; `a-z` ranges are allowed).
+fn sql_glob_match(text: &str, pattern: &str) -> bool {
+    let text_chars: Vec<char> = text.chars().collect();
+    let pattern_chars: Vec<char> = pattern.chars().collect();
+    glob_match_inner(&text_chars, 0, &pattern_chars, 0)
+}
+
+fn glob_match_inner(text: &[char], ti: usize, pattern: &[char], pi: usize) -> bool {
+    if pi == pattern.len() {
+        return ti == text.len();
+    }
+
+    match pattern[pi] {
+        '*' => (ti..=text.len()).any(|i| glob_match_inner(text, i, pattern, pi + 1)),
+        '?' => ti < text.len() && glob_match_inner(text, ti + 1, pattern, pi + 1),
+        '[' => {
+            if ti == text.len() {
+                return false;
+            }
+            match glob_class_match(text[ti], pattern, pi + 1) {
+                Some((true, next)) => glob_match_inner(text, ti + 1, pattern, next),
+                Some((false, _)) => false,
+                // An unterminated class matches a literal '['
+                None => text[ti] == '[' && glob_match_inner(text, ti + 1, pattern, pi + 1),
+            }
+        }
+        c => ti < text.len() && text[ti] == c && glob_match_inner(text, ti + 1, pattern, pi + 1),
+    }
+}
+
+/// Match `c` against the character class starting just after a `[` at
+/// `start`. Returns whether it matched and the pattern index after the
+/// closing `]`, or `None` if the class is never closed.
+fn glob_class_match(c: char, pattern: &[char], start: usize) -> Option<(bool, usize)> {
+    let mut i = start;
+    let negated = pattern.get(i) == Some(&'^');
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        // A ']' right after '[' or '[^' is a literal member of the class
+        if pattern[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            if pattern[i] <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if pattern[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+/// The text a value is matched as by GLOB and REGEXP; BLOBs never match.
+fn match_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Text(s) => Some(s.clone()),
+        Value::Integer(_) | Value::Real(_) => Some(value.to_string()),
+        Value::Null | Value::Blob(_) => None,
+    }
+}
+
+/// Evaluate `text REGEXP pattern`, also available as `regexp(pattern, text)`.
+/// Either argument being NULL gives NULL.
+fn regexp_match(pattern: &Value, text: &Value) -> Result<Value> {
+    if pattern.is_null() || text.is_null() {
+        return Ok(Value::Null);
+    }
+    match (match_text(pattern), match_text(text)) {
+        (Some(pattern), Some(text)) => {
+            Ok(Value::Integer(if regex_is_match(&pattern, &text)? { 1 } else { 0 }))
+        }
+        _ => Ok(Value::Integer(0)),
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_is_match(pattern: &str, text: &str) -> Result<bool> {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    // Compiled patterns are cached per thread, since a REGEXP in a WHERE
+    // clause is evaluated once per row with the same pattern.
+    const CACHE_LIMIT: usize = 64;
+    thread_local! {
+        static CACHE: RefCell<HashMap<String, regex::Regex>> = RefCell::new(HashMap::new());
+    }
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if !cache.contains_key(pattern) {
+            let re = regex::Regex::new(pattern).map_err(|e| {
+                HorizonError::InvalidSql(format!("invalid REGEXP pattern: {}", e))
+            })?;
+            if cache.len() >= CACHE_LIMIT {
+                cache.clear();
+            }
+            cache.insert(pattern.to_string(), re);
+        }
+        Ok(cache[pattern].is_match(text))
+    })
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_is_match(_pattern: &str, _text: &str) -> Result<bool> {
+    Err(HorizonError::NotImplemented(
+        "REGEXP requires the `regex` feature".into(),
+    ))
+}
+
+/// `UUID()`: a random (version 4) UUID as lower-case hyphenated text, and
+/// `UUID_BLOB()`: the same as its 16 raw bytes.
+fn eval_uuid_function(name: &str, arg_count: usize) -> Result<Value> {
+    if arg_count != 0 {
+        return Err(HorizonError::InvalidSql(format!(
+            "{}() takes 0 arguments",
+            name.to_lowercase()
+        )));
+    }
+    let mut bytes = [0u8; 16];
+    fill_random(&mut bytes)?;
+    // RFC 4122: version 4, variant 10xx
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    if name == "UUID_BLOB" {
+        return Ok(Value::Blob(bytes.to_vec()));
+    }
+    let mut text = String::with_capacity(36);
+    for (i, b) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            text.push('-');
+        }
+        text.push_str(&format!("{:02x}", b));
+    }
+    Ok(Value::Text(text))
+}
+
+/// Fill `buf` with random bytes from the operating system's generator.
+#[cfg(target_os = "linux")]
+fn fill_random(buf: &mut [u8]) -> Result<()> {
+    use std::os::raw::{c_uint, c_void};
+    extern "C" {
+        fn getrandom(buf: *mut c_void, buflen: usize, flags: c_uint) -> isize;
+    }
+
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        // SAFETY: `rest` is valid for writes of `rest.len()` bytes.
+        let n = unsafe { getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(HorizonError::Internal(format!("getrandom failed: {}", err)));
+        }
+        filled += n as usize;
+    }
+    Ok(())
+}
+
+/// Fill `buf` with random bytes from the operating system's generator.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn fill_random(buf: &mut [u8]) -> Result<()> {
+    use std::io::Read;
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(buf))
+        .map_err(|e| HorizonError::Internal(format!("cannot read /dev/urandom: {}", e)))
+}
+
+/// Fill `buf` with random bytes. Without a Unix random device this falls
+/// back on the randomly seeded keys of `RandomState`.
+#[cfg(not(unix))]
+fn fill_random(buf: &mut [u8]) -> Result<()> {
+    use std::hash::{BuildHasher, Hasher};
+    for chunk in buf.chunks_mut(8) {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(unix_now().as_nanos());
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+    }
+    Ok(())
+}
+
 fn resolve_column_names(
     select_cols: &[SelectColumn],
     table: &TableInfo,
@@ -6565,8 +8976,8 @@ fn project_row_with_ctx(
     select_cols: &[SelectColumn],
     row: &[Value],
     table: &TableInfo,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<Vec<Value>> {
     let mut values = Vec::new();
     for col in select_cols {
@@ -6586,35 +8997,57 @@ fn project_row_with_ctx(
     Ok(values)
 }
 
-fn sort_rows(
-    rows: &mut [Row],
+/// Sort `rows`, the output rows of a SELECT from `table` named
+/// `column_names`, by `order_by`. `table_rows` holds the row of `table`
+/// each was computed from.
+///
+/// A term that is an output column number, or the name of an output column
+/// that is not a column of the table (such as an alias), sorts by that
+/// output column; any other term is evaluated against the table row.
+fn sort_projected_rows(
+    rows: &mut Vec<Vec<Value>>,
+    table_rows: Vec<Vec<Value>>,
+    column_names: &[String],
     order_by: &[OrderByItem],
     table: &TableInfo,
+    catalog: &Catalog,
 ) -> Result<()> {
-    rows.sort_by(|a, b| {
-        for item in order_by {
-            let val_a = eval_expr(&item.expr, &a.values, &table.columns, table)
-                .unwrap_or(Value::Null);
-            let val_b = eval_expr(&item.expr, &b.values, &table.columns, table)
-                .unwrap_or(Value::Null);
-            let cmp = if let Some(coll) = extract_collation(&item.expr) {
-                compare_with_collation(&val_a, &val_b, coll)
-            } else {
-                val_a.cmp(&val_b)
-            };
-            let cmp = if item.desc { cmp.reverse() } else { cmp };
-            if cmp != std::cmp::Ordering::Equal {
-                return cmp;
-            }
-        }
-        std::cmp::Ordering::Equal
-    });
+    let width = rows.first().map_or(0, Vec::len);
+    let mut combined: Vec<Vec<Value>> = rows
+        .drain(..)
+        .zip(table_rows)
+        .map(|(mut row, table_row)| {
+            row.extend(table_row);
+            row
+        })
+        .collect();
+    sort::sort_by_terms(&mut combined, order_by, catalog.sort_spill_threshold(), catalog.collations(), |expr, row| {
+        let (output, table_row) = row.split_at(width);
+        let term = match expr {
+            Expr::Collate { expr, .. } => expr,
+            expr => expr,
+        };
+        match term {
+            Expr::Literal(LiteralValue::Integer(n)) if *n >= 1 && *n as usize <= width => output[*n as usize - 1].clone(),
+            Expr::Column { table: None, name } if table.find_column_index(name).is_none() => column_names
+                .iter()
+                .position(|c| c.eq_ignore_ascii_case(name))
+                .and_then(|i| output.get(i).cloned())
+                .unwrap_or(Value::Null),
+            _ => eval_expr(expr, table_row, &table.columns, table).unwrap_or(Value::Null),
+        }
+    })?;
+    rows.extend(combined.into_iter().map(|mut row| {
+        row.truncate(width);
+        row
+    }));
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sql::parser::Parser;
 
     #[test]
     fn test_serialize_deserialize_row() {
@@ -6650,4 +9083,203 @@ mod tests {
         assert!(!sql_like_match("hello", "world"));
         assert!(!sql_like_match("hello", "h_lo"));
     }
+
+    #[test]
+    fn test_sql_glob_match() {
+        assert!(sql_glob_match("main.rs", "*.rs"));
+        assert!(!sql_glob_match("main.RS", "*.rs"));
+        assert!(sql_glob_match("a1", "?[0-9]"));
+        assert!(!sql_glob_match("ab", "?[0-9]"));
+        assert!(sql_glob_match("ab", "a[^0-9]"));
+        assert!(sql_glob_match("a]", "a[]]"));
+        assert!(sql_glob_match("[x", "[x"));
+        assert!(!sql_glob_match("hello", "h*z"));
+    }
+
+    #[test]
+    fn test_unix_epoch_round_trip() {
+        for days in [-719_468, -1, 0, 1, 11_016, 19_737, 2_932_896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        let dt = DateTime::from_unix_epoch(-1.5).unwrap();
+        assert_eq!((dt.format_datetime(), dt.millisecond), ("1969-12-31 23:59:58".to_string(), 500));
+        assert_eq!(parse_timestring("2024-01-15 10:20:30").unwrap().to_unix_epoch(), 1_705_314_030);
+        assert!(DateTime::from_unix_epoch(f64::INFINITY).is_none());
+    }
+
+    /// Run `statements` against a fresh database and return its state.
+    fn setup_db(statements: &[&str]) -> (tempfile::TempDir, BufferPool, Catalog) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pager = crate::pager::Pager::open(&dir.path().join("test.hdb"), false).unwrap();
+        let mut pool = BufferPool::new(pager, None, 100).unwrap();
+        let mut catalog = Catalog::new();
+        let mut txn_mgr = TransactionManager::new();
+        for sql in statements {
+            for stmt in Parser::parse(sql).unwrap() {
+                execute_statement(&stmt, &mut pool, &mut catalog, &mut txn_mgr).unwrap();
+            }
+        }
+        (dir, pool, catalog)
+    }
+
+    #[test]
+    fn test_composite_pk_seek() {
+        let (_dir, pool, catalog) = setup_db(&[
+            "CREATE TABLE t (a INTEGER, b TEXT, v INTEGER, PRIMARY KEY (a, b))",
+            "INSERT INTO t VALUES (1, 'x', 10), (1, 'y', 20), (2, 'x', 30)",
+        ]);
+        let table = catalog.get_table("t").unwrap().clone();
+        let tree = BTree::open(table.root_page);
+
+        let both = Parser::parse_expression("b = 'y' AND v > 0 AND a = 1").unwrap();
+        let entries = try_pk_seek(&both, &table, &tree, &pool).unwrap().unwrap();
+        assert_eq!(entries.len(), 1);
+        let row = deserialize_row(&entries[0].value, 3).unwrap();
+        assert_eq!(row[2], Value::Integer(20));
+
+        // A key prefix alone cannot seek
+        let partial = Parser::parse_expression("a = 1").unwrap();
+        assert!(try_pk_seek(&partial, &table, &tree, &pool).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_multi_column_index_scan() {
+        let (_dir, mut pool, catalog) = setup_db(&[
+            "CREATE TABLE t (a TEXT, b INTEGER, c INTEGER)",
+            "INSERT INTO t VALUES ('x', 1, 1), ('x', 2, 2), ('x', -3, 3), ('xx', 1, 4), ('y', 5, 5)",
+            "CREATE INDEX idx_ab ON t (a, b)",
+        ]);
+        let table = catalog.get_table("t").unwrap().clone();
+        let scan = |pool: &mut BufferPool, sql: &str| -> Option<Vec<i64>> {
+            let expr = Parser::parse_expression(sql).unwrap();
+            let entries = try_index_scan(&expr, None, "t", &table, pool, &catalog).unwrap()?;
+            let mut ids: Vec<i64> = entries.iter()
+                .map(|e| deserialize_row(&e.value, 3).unwrap()[2].as_integer().unwrap())
+                .collect();
+            ids.sort();
+            Some(ids)
+        };
+
+        assert_eq!(scan(&mut pool, "a = 'x' AND b = 2"), Some(vec![2]));
+        assert_eq!(scan(&mut pool, "b = 1 AND a = 'x'"), Some(vec![1]));
+        // Strict bounds are scanned inclusively; the WHERE clause drops row 2
+        assert_eq!(scan(&mut pool, "a = 'x' AND b < 2"), Some(vec![1, 2, 3]));
+        assert_eq!(scan(&mut pool, "a = 'x' AND b >= 0 AND b <= 1"), Some(vec![1]));
+        assert_eq!(scan(&mut pool, "a = 'x'"), Some(vec![1, 2, 3]));
+        assert_eq!(scan(&mut pool, "a > 'x'"), Some(vec![1, 2, 3, 4, 5]));
+        assert_eq!(scan(&mut pool, "a < 'xx'"), Some(vec![1, 2, 3, 4]));
+        // The trailing column alone cannot use the index
+        assert_eq!(scan(&mut pool, "b = 1"), None);
+    }
+
+    #[test]
+    fn test_partial_index_scan() {
+        let (_dir, mut pool, mut catalog) = setup_db(&[
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, status TEXT)",
+            "INSERT INTO users VALUES (1, 'a', 'active'), (2, 'b', 'inactive'), (3, 'a', 'inactive')",
+            "CREATE INDEX idx_active ON users (name) WHERE status = 'active'",
+        ]);
+        let index_len = |pool: &mut BufferPool, catalog: &Catalog| {
+            let root = catalog.get_index("idx_active").unwrap().root_page;
+            BTree::open(root).scan_all(pool).unwrap().len()
+        };
+        assert_eq!(index_len(&mut pool, &catalog), 1);
+
+        // Only matching rows are added on INSERT, and UPDATE moves rows out
+        let mut txn_mgr = TransactionManager::new();
+        for sql in [
+            "INSERT INTO users VALUES (4, 'c', 'active'), (5, 'd', 'inactive')",
+            "UPDATE users SET status = 'inactive' WHERE id = 1",
+        ] {
+            let stmt = Parser::parse(sql).unwrap().remove(0);
+            execute_statement(&stmt, &mut pool, &mut catalog, &mut txn_mgr).unwrap();
+        }
+        assert_eq!(index_len(&mut pool, &catalog), 1);
+
+        let table = catalog.get_table("users").unwrap().clone();
+        let implied = Parser::parse_expression("name = 'c' AND status = 'active'").unwrap();
+        let entries = try_index_scan(&implied, None, "users", &table, &pool, &catalog).unwrap();
+        assert_eq!(entries.map(|e| e.len()), Some(1));
+        // Without the index predicate the partial index cannot be used
+        let not_implied = Parser::parse_expression("name = 'c'").unwrap();
+        assert!(try_index_scan(&not_implied, None, "users", &table, &pool, &catalog).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_expression_index_scan() {
+        let (_dir, mut pool, catalog) = setup_db(&[
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT)",
+            "INSERT INTO users VALUES (1, 'Bob@x.com'), (2, 'alice@x.com'), (3, 'bob@X.COM')",
+            "CREATE INDEX idx_email_lower ON users (lower(email))",
+        ]);
+        let table = catalog.get_table("users").unwrap().clone();
+        let scan = |pool: &mut BufferPool, sql: &str| {
+            let expr = Parser::parse_expression(sql).unwrap();
+            try_index_scan(&expr, None, "users", &table, pool, &catalog).unwrap().map(|e| e.len())
+        };
+
+        assert_eq!(scan(&mut pool, "lower(email) = 'bob@x.com'"), Some(2));
+        assert_eq!(scan(&mut pool, "'alice@x.com' = LOWER(email)"), Some(1));
+        // A different expression, or the bare column, cannot use the index
+        assert_eq!(scan(&mut pool, "upper(email) = 'BOB@X.COM'"), None);
+        assert_eq!(scan(&mut pool, "email = 'bob@X.COM'"), None);
+    }
+
+    #[test]
+    fn test_analyze_prefers_selective_index() {
+        let (_dir, mut pool, mut catalog) = setup_db(&[
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, lo INTEGER, hi INTEGER)",
+            "INSERT INTO t (lo, hi) SELECT value % 2, value FROM generate_series(1, 10000)",
+            "CREATE INDEX idx_lo ON t (lo)",
+            "CREATE INDEX idx_lo_id ON t (lo, id)",
+            "CREATE INDEX idx_hi ON t (hi)",
+        ]);
+        assert!(catalog.get_column_stats("t", "lo").is_none());
+
+        let stmt = Parser::parse("ANALYZE").unwrap().remove(0);
+        execute_statement(&stmt, &mut pool, &mut catalog, &mut TransactionManager::new()).unwrap();
+        let stats = |col: &str| catalog.get_column_stats("t", col).copied().unwrap();
+        assert_eq!(stats("LO"), crate::catalog::stats::ColumnStats { row_count: 10000, distinct_values: 2 });
+        assert_eq!(stats("hi").distinct_values, 10000);
+        assert_eq!(stats("hi").rows_per_value(), 1.0);
+
+        // Both indexes match, but only idx_hi narrows the scan to one row
+        let table = catalog.get_table("t").unwrap().clone();
+        for sql in ["lo = 1 AND hi = 77", "hi = 77 AND lo = 1", "lo = 1 AND id = 6 AND hi = 77"] {
+            let expr = Parser::parse_expression(sql).unwrap();
+            let entries = try_index_scan(&expr, None, "t", &table, &pool, &catalog).unwrap().unwrap();
+            assert_eq!(entries.len(), 1, "{}", sql);
+        }
+    }
+    #[test]
+    fn test_covering_index_scan_skips_the_table() {
+        let (_dir, pool, catalog) = setup_db(&[
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER, b REAL, c TEXT)",
+            "INSERT INTO t (a, b, c) SELECT value % 100, value / 4.0, 'row ' || value FROM generate_series(1, 5000)",
+            "CREATE INDEX idx_ab ON t (a, b)",
+        ]);
+        let table = catalog.get_table("t").unwrap().clone();
+        let expr = Parser::parse_expression("a = 5").unwrap();
+        let scan = |read: &[usize]| {
+            let before = pool.page_accesses();
+            let entries = try_index_scan(&expr, Some(read), "t", &table, &pool, &catalog).unwrap().unwrap();
+            let rows: Vec<Vec<Value>> = entries.iter().map(|e| deserialize_row(&e.value, 4).unwrap()).collect();
+            (rows, pool.page_accesses() - before)
+        };
+
+        let (covered, covering_accesses) = scan(&[1, 2]);
+        let (looked_up, lookup_accesses) = scan(&[1, 2, 3]);
+        assert_eq!(covered.len(), 50);
+        for (covered, looked_up) in covered.iter().zip(&looked_up) {
+            assert_eq!(covered[1], Value::Integer(5));
+            assert_eq!(covered[1..3], looked_up[1..3]);
+            assert_eq!(covered[3], Value::Null);
+        }
+        // Only the index tree is read: its inner pages and the leaf with
+        // the matching keys
+        let index_height = BTree::open(catalog.get_index("idx_ab").unwrap().root_page).height(&pool).unwrap();
+        assert!(covering_accesses <= index_height as u64 + 1, "{}", covering_accesses);
+        assert!(lookup_accesses >= covering_accesses + 50, "{}", lookup_accesses);
+    }
 }
@@ -0,0 +1,467 @@
+//! Bind-parameter substitution for Horizon DB.
+//!
+//! Parsed statements may contain positional (`?`, `?N`) and named
+//! (`$name`, `:name`, `@name`) placeholders. Before a statement is executed,
+//! the caller-supplied values are substituted into the AST as literals, so
+//! the rest of the engine only ever sees fully-bound expressions.
+//!
+//! Named placeholders are also addressable by position: the first time a
+//! name appears it is assigned the index one past the largest index seen so
+//! far (the same numbering SQLite uses), and later occurrences of the same
+//! name reuse that index.
+
+use std::collections::HashMap;
+
+use crate::error::{HorizonError, Result};
+use crate::sql::ast::*;
+use crate::types::Value;
+
+/// The placeholder slots referenced by a batch of statements.
+#[derive(Debug, Default)]
+struct ParamSlots {
+    /// Highest parameter index referenced (the number of values required).
+    count: usize,
+    /// Whether any positional `?` / `?N` placeholder appears.
+    has_positional: bool,
+    /// Named placeholders (prefix included) in order of first appearance,
+    /// with the index assigned to each.
+    named: Vec<(String, usize)>,
+}
+
+impl ParamSlots {
+    fn collect(stmts: &mut [Statement]) -> Result<Self> {
+        let mut slots = ParamSlots::default();
+        for stmt in stmts.iter_mut() {
+            walk_statement(stmt, &mut |expr| {
+                match expr {
+                    Expr::Placeholder(n) => {
+                        if *n == 0 {
+                            return Err(HorizonError::InvalidSql(
+                                "placeholder index must be at least 1".into(),
+                            ));
+                        }
+                        slots.has_positional = true;
+                        slots.count = slots.count.max(*n);
+                    }
+                    Expr::NamedPlaceholder(name)
+                        if !slots.named.iter().any(|(n, _)| n == name) =>
+                    {
+                        slots.count += 1;
+                        slots.named.push((name.clone(), slots.count));
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })?;
+        }
+        Ok(slots)
+    }
+}
+
+/// Bind `params` to the placeholders in `stmts` by position.
+///
+/// `?N` binds `params[N - 1]`. A bare `?`, and a named placeholder the
+/// first time it appears, take the index one past the largest before them;
+/// later occurrences of a name reuse its index.
+/// The number of values must match the number of parameter slots exactly.
+pub fn bind_positional(stmts: &mut [Statement], params: &[Value]) -> Result<()> {
+    let slots = ParamSlots::collect(stmts)?;
+    if params.len() < slots.count {
+        return Err(HorizonError::InvalidSql(format!(
+            "not enough parameters: statement expects {}, got {}",
+            slots.count,
+            params.len()
+        )));
+    }
+    if params.len() > slots.count {
+        return Err(HorizonError::InvalidSql(format!(
+            "too many parameters: statement expects {}, got {}",
+            slots.count,
+            params.len()
+        )));
+    }
+
+    let named: HashMap<&str, usize> =
+        slots.named.iter().map(|(n, i)| (n.as_str(), *i)).collect();
+    for stmt in stmts.iter_mut() {
+        walk_statement(stmt, &mut |expr| {
+            let index = match expr {
+                Expr::Placeholder(n) => *n,
+                Expr::NamedPlaceholder(name) => named[name.as_str()],
+                _ => return Ok(()),
+            };
+            *expr = Expr::Literal(value_to_literal(&params[index - 1]));
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Bind `params` to the named placeholders in `stmts`.
+///
+/// A supplied name matches a placeholder either exactly (`":id"`) or without
+/// its prefix (`"id"`). Every placeholder must be supplied, every supplied
+/// name must be used, and positional placeholders are rejected.
+pub fn bind_named(stmts: &mut [Statement], params: &[(&str, Value)]) -> Result<()> {
+    let slots = ParamSlots::collect(stmts)?;
+    if slots.has_positional {
+        return Err(HorizonError::InvalidSql(
+            "positional placeholders cannot be bound by name".into(),
+        ));
+    }
+
+    let find = |placeholder: &str| {
+        params.iter().position(|(key, _)| {
+            *key == placeholder || placeholder.get(1..) == Some(*key)
+        })
+    };
+
+    let mut bound: HashMap<String, usize> = HashMap::new();
+    for (name, _) in &slots.named {
+        match find(name) {
+            Some(pos) => {
+                bound.insert(name.clone(), pos);
+            }
+            None => {
+                return Err(HorizonError::InvalidSql(format!(
+                    "no value supplied for parameter {}",
+                    name
+                )));
+            }
+        }
+    }
+    for (pos, (key, _)) in params.iter().enumerate() {
+        if !bound.values().any(|&p| p == pos) {
+            return Err(HorizonError::InvalidSql(format!(
+                "parameter {} does not appear in the statement",
+                key
+            )));
+        }
+    }
+
+    for stmt in stmts.iter_mut() {
+        walk_statement(stmt, &mut |expr| {
+            if let Expr::NamedPlaceholder(name) = expr {
+                let value = &params[bound[name.as_str()]].1;
+                *expr = Expr::Literal(value_to_literal(value));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Convert a bound value into the literal that replaces its placeholder.
+pub(crate) fn value_to_literal(value: &Value) -> LiteralValue {
+    match value {
+        Value::Null => LiteralValue::Null,
+        Value::Integer(i) => LiteralValue::Integer(*i),
+        Value::Real(r) => LiteralValue::Real(*r),
+        Value::Text(s) => LiteralValue::String(s.clone()),
+        Value::Blob(b) => LiteralValue::Blob(b.clone()),
+    }
+}
+
+// ---- AST walking ----
+
+pub(super) type ExprVisitor<'a> = dyn FnMut(&mut Expr) -> Result<()> + 'a;
+
+/// Visit every expression reachable from a DML or query statement.
+///
+/// DDL statements are left untouched: their expressions are persisted in
+/// the schema and cannot carry bind parameters.
+pub(super) fn walk_statement(stmt: &mut Statement, f: &mut ExprVisitor) -> Result<()> {
+    match stmt {
+        Statement::Select(select) => walk_select(select, f),
+        Statement::Insert(insert) => {
+            match &mut insert.source {
+                InsertSource::Values(rows) => {
+                    for row in rows {
+                        for expr in row {
+                            walk_expr(expr, f)?;
+                        }
+                    }
+                }
+                InsertSource::Select(select) => walk_select(select, f)?,
+            }
+            walk_returning(&mut insert.returning, f)
+        }
+        Statement::Update(update) => {
+            for (_, expr) in &mut update.assignments {
+                walk_expr(expr, f)?;
+            }
+            if let Some(from) = &mut update.from {
+                walk_from(from, f)?;
+            }
+            walk_opt_expr(&mut update.where_clause, f)?;
+            walk_returning(&mut update.returning, f)
+        }
+        Statement::Delete(delete) => {
+            walk_opt_expr(&mut delete.where_clause, f)?;
+            walk_returning(&mut delete.returning, f)
+        }
+        Statement::Explain(inner) | Statement::ExplainQueryPlan(inner) => {
+            walk_statement(inner, f)
+        }
+        Statement::Pragma(pragma) => walk_opt_expr(&mut pragma.value, f),
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn walk_select(select: &mut SelectStatement, f: &mut ExprVisitor) -> Result<()> {
+    for cte in &mut select.ctes {
+        walk_select(&mut cte.query, f)?;
+    }
+    walk_columns(&mut select.columns, f)?;
+    if let Some(from) = &mut select.from {
+        walk_from(from, f)?;
+    }
+    walk_opt_expr(&mut select.where_clause, f)?;
+    for expr in &mut select.group_by {
+        walk_expr(expr, f)?;
+    }
+    walk_opt_expr(&mut select.having, f)?;
+    for window in &mut select.windows {
+        for expr in &mut window.def.partition_by {
+            walk_expr(expr, f)?;
+        }
+        for item in &mut window.def.order_by {
+            walk_expr(&mut item.expr, f)?;
+        }
+    }
+    for compound in &mut select.compound {
+        let body = &mut compound.select;
+        walk_columns(&mut body.columns, f)?;
+        if let Some(from) = &mut body.from {
+            walk_from(from, f)?;
+        }
+        walk_opt_expr(&mut body.where_clause, f)?;
+        for expr in &mut body.group_by {
+            walk_expr(expr, f)?;
+        }
+        walk_opt_expr(&mut body.having, f)?;
+    }
+    for item in &mut select.order_by {
+        walk_expr(&mut item.expr, f)?;
+    }
+    walk_opt_expr(&mut select.limit, f)?;
+    walk_opt_expr(&mut select.offset, f)
+}
+
+fn walk_from(from: &mut FromClause, f: &mut ExprVisitor) -> Result<()> {
+    match from {
+        FromClause::Table { .. } => Ok(()),
+        FromClause::Join { left, right, constraint, .. } => {
+            walk_from(left, f)?;
+            walk_from(right, f)?;
+            match constraint {
+                Some(JoinSpec::On(expr)) => walk_expr(expr, f),
+                _ => Ok(()),
+            }
+        }
+        FromClause::Subquery { query, .. } => walk_select(query, f),
+        FromClause::TableFunction { args, .. } => {
+            for arg in args {
+                walk_expr(arg, f)?;
+            }
+            Ok(())
+        }
+        FromClause::Values { rows, .. } => {
+            for expr in rows.iter_mut().flatten() {
+                walk_expr(expr, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn walk_columns(columns: &mut [SelectColumn], f: &mut ExprVisitor) -> Result<()> {
+    for col in columns {
+        if let SelectColumn::Expr { expr, .. } = col {
+            walk_expr(expr, f)?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_returning(returning: &mut Option<Vec<SelectColumn>>, f: &mut ExprVisitor) -> Result<()> {
+    match returning {
+        Some(columns) => walk_columns(columns, f),
+        None => Ok(()),
+    }
+}
+
+fn walk_opt_expr(expr: &mut Option<Expr>, f: &mut ExprVisitor) -> Result<()> {
+    match expr {
+        Some(e) => walk_expr(e, f),
+        None => Ok(()),
+    }
+}
+
+pub(super) fn walk_expr(expr: &mut Expr, f: &mut ExprVisitor) -> Result<()> {
+    f(expr)?;
+    match expr {
+        Expr::Literal(_)
+        | Expr::Column { .. }
+        | Expr::Placeholder(_)
+        | Expr::NamedPlaceholder(_) => Ok(()),
+        Expr::BinaryOp { left, right, .. } => {
+            walk_expr(left, f)?;
+            walk_expr(right, f)
+        }
+        Expr::UnaryOp { expr: inner, .. }
+        | Expr::IsNull { expr: inner, .. }
+        | Expr::Cast { expr: inner, .. }
+        | Expr::Collate { expr: inner, .. } => walk_expr(inner, f),
+        Expr::Between { expr: inner, low, high, .. } => {
+            walk_expr(inner, f)?;
+            walk_expr(low, f)?;
+            walk_expr(high, f)
+        }
+        Expr::InList { expr: inner, list, .. } => {
+            walk_expr(inner, f)?;
+            for item in list {
+                walk_expr(item, f)?;
+            }
+            Ok(())
+        }
+        Expr::Like { expr: inner, pattern, .. } => {
+            walk_expr(inner, f)?;
+            walk_expr(pattern, f)
+        }
+        Expr::Match { table, pattern } => {
+            walk_expr(table, f)?;
+            walk_expr(pattern, f)
+        }
+        Expr::Function { args, filter, .. } => {
+            for arg in args {
+                walk_expr(arg, f)?;
+            }
+            match filter {
+                Some(filter) => walk_expr(filter, f),
+                None => Ok(()),
+            }
+        }
+        Expr::Case { operand, when_clauses, else_clause } => {
+            if let Some(op) = operand {
+                walk_expr(op, f)?;
+            }
+            for (when_expr, then_expr) in when_clauses {
+                walk_expr(when_expr, f)?;
+                walk_expr(then_expr, f)?;
+            }
+            if let Some(e) = else_clause {
+                walk_expr(e, f)?;
+            }
+            Ok(())
+        }
+        Expr::Subquery(select) | Expr::Exists(select) => walk_select(select, f),
+        Expr::WindowFunction { function, partition_by, order_by, frame, .. } => {
+            walk_expr(function, f)?;
+            for expr in partition_by {
+                walk_expr(expr, f)?;
+            }
+            for item in order_by {
+                walk_expr(&mut item.expr, f)?;
+            }
+            if let Some(frame) = frame {
+                for bound in std::iter::once(&mut frame.start).chain(frame.end.as_mut()) {
+                    if let WindowFrameBound::Preceding(Some(e))
+                    | WindowFrameBound::Following(Some(e)) = bound
+                    {
+                        walk_expr(e, f)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+
+    fn where_rhs(stmts: &[Statement]) -> &Expr {
+        match &stmts[0] {
+            Statement::Select(s) => match s.where_clause.as_ref().unwrap() {
+                Expr::BinaryOp { right, .. } => right,
+                other => panic!("expected BinaryOp, got {:?}", other),
+            },
+            other => panic!("expected SELECT, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn named_placeholders_share_an_index() {
+        let mut stmts = Parser::parse("SELECT ?, :a, ?5, :a, $b").unwrap();
+        let slots = ParamSlots::collect(&mut stmts).unwrap();
+        assert_eq!(slots.count, 6);
+        assert_eq!(slots.named, vec![(":a".to_string(), 2), ("$b".to_string(), 6)]);
+    }
+
+    #[test]
+    fn bare_placeholders_are_numbered_after_named_ones() {
+        let mut stmts = Parser::parse("SELECT :a, ?").unwrap();
+        bind_positional(&mut stmts, &[Value::Integer(1), Value::Integer(2)]).unwrap();
+        let Statement::Select(select) = &stmts[0] else { panic!("expected SELECT") };
+        let values: Vec<&Expr> = select
+            .columns
+            .iter()
+            .map(|c| match c {
+                SelectColumn::Expr { expr, .. } => expr,
+                other => panic!("expected an expression column, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                &Expr::Literal(LiteralValue::Integer(1)),
+                &Expr::Literal(LiteralValue::Integer(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn placeholders_in_aggregate_filters_are_bound() {
+        let mut stmts = Parser::parse("SELECT COUNT(*) FILTER (WHERE x > ?) FROM t").unwrap();
+        bind_positional(&mut stmts, &[Value::Integer(3)]).unwrap();
+        let Statement::Select(select) = &stmts[0] else { panic!("expected SELECT") };
+        match &select.columns[0] {
+            SelectColumn::Expr { expr: Expr::Function { filter: Some(filter), .. }, .. } => {
+                assert!(matches!(&**filter, Expr::BinaryOp { right, .. }
+                    if **right == Expr::Literal(LiteralValue::Integer(3))));
+            }
+            other => panic!("expected a filtered aggregate, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bind_positional_replaces_placeholders() {
+        let mut stmts = Parser::parse("SELECT * FROM t WHERE x = ?").unwrap();
+        bind_positional(&mut stmts, &[Value::Blob(vec![0, 1])]).unwrap();
+        assert_eq!(*where_rhs(&stmts), Expr::Literal(LiteralValue::Blob(vec![0, 1])));
+    }
+
+    #[test]
+    fn bind_named_accepts_bare_names() {
+        let mut stmts = Parser::parse("SELECT * FROM t WHERE x = :x").unwrap();
+        bind_named(&mut stmts, &[("x", Value::Integer(7))]).unwrap();
+        assert_eq!(*where_rhs(&stmts), Expr::Literal(LiteralValue::Integer(7)));
+    }
+
+    #[test]
+    fn parameter_count_mismatch_is_rejected() {
+        let mut stmts = Parser::parse("SELECT ?, ?").unwrap();
+        assert!(matches!(
+            bind_positional(&mut stmts, &[Value::Integer(1)]),
+            Err(HorizonError::InvalidSql(_))
+        ));
+        let mut stmts = Parser::parse("SELECT $a").unwrap();
+        assert!(matches!(
+            bind_named(&mut stmts, &[("b", Value::Integer(1))]),
+            Err(HorizonError::InvalidSql(_))
+        ));
+    }
+}
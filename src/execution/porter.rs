@@ -0,0 +1,294 @@
+//! The Porter stemming algorithm, used by the `porter` FTS5 tokenizer.
+//!
+//! This follows Martin Porter's reference implementation: a word goes
+//! through five steps that strip or replace suffixes, so that `running`,
+//! `runs`, and `run` all stem to `run`. Stems need not be words
+//! (`relational` stems to `relat`); they only have to agree for words of
+//! the same family. Words that are not all lowercase ASCII letters, and
+//! words of one or two letters, are returned unchanged.
+
+/// The stem of the lowercase word `word`.
+pub(crate) fn stem(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+    let mut stemmer = Stemmer { b: word.as_bytes().to_vec(), j: 0 };
+    stemmer.step1ab();
+    if stemmer.b.len() > 1 {
+        stemmer.step1c();
+        stemmer.step2();
+        stemmer.step3();
+        stemmer.step4();
+        stemmer.step5();
+    }
+    String::from_utf8(stemmer.b).expect("stems are ASCII")
+}
+
+/// A word being stemmed: `b` holds what is left of it, and `j` is the
+/// length of the stem before the suffix last found by [`Stemmer::ends`].
+struct Stemmer {
+    b: Vec<u8>,
+    j: usize,
+}
+
+impl Stemmer {
+    /// Whether the letter at `i` is a consonant: `y` is one unless it
+    /// follows a consonant.
+    fn cons(&self, i: usize) -> bool {
+        match self.b[i] {
+            b'a' | b'e' | b'i' | b'o' | b'u' => false,
+            b'y' => i == 0 || !self.cons(i - 1),
+            _ => true,
+        }
+    }
+
+    /// The measure of the stem: the number of vowel-consonant sequences
+    /// in it, `m` in `[C](VC)^m[V]`.
+    fn m(&self) -> usize {
+        let mut n = 0;
+        let mut i = 0;
+        loop {
+            if i >= self.j {
+                return n;
+            }
+            if !self.cons(i) {
+                break;
+            }
+            i += 1;
+        }
+        i += 1;
+        loop {
+            loop {
+                if i >= self.j {
+                    return n;
+                }
+                if self.cons(i) {
+                    break;
+                }
+                i += 1;
+            }
+            i += 1;
+            n += 1;
+            loop {
+                if i >= self.j {
+                    return n;
+                }
+                if !self.cons(i) {
+                    break;
+                }
+                i += 1;
+            }
+            i += 1;
+        }
+    }
+
+    /// Whether the stem contains a vowel.
+    fn vowel_in_stem(&self) -> bool {
+        (0..self.j).any(|i| !self.cons(i))
+    }
+
+    /// Whether the first `len` letters end with a double consonant.
+    fn double_consonant(&self, len: usize) -> bool {
+        len >= 2 && self.b[len - 1] == self.b[len - 2] && self.cons(len - 1)
+    }
+
+    /// Whether the first `len` letters end consonant-vowel-consonant, the
+    /// last consonant not being `w`, `x`, or `y`: `hop`, but not `snow`.
+    fn cvc(&self, len: usize) -> bool {
+        len >= 3
+            && self.cons(len - 1)
+            && !self.cons(len - 2)
+            && self.cons(len - 3)
+            && !matches!(self.b[len - 1], b'w' | b'x' | b'y')
+    }
+
+    /// Whether the word ends with `suffix`, setting the stem to what
+    /// precedes it if so.
+    fn ends(&mut self, suffix: &str) -> bool {
+        if !self.b.ends_with(suffix.as_bytes()) {
+            return false;
+        }
+        self.j = self.b.len() - suffix.len();
+        true
+    }
+
+    /// Replace the suffix after the stem with `replacement`.
+    fn set_to(&mut self, replacement: &str) {
+        self.b.truncate(self.j);
+        self.b.extend_from_slice(replacement.as_bytes());
+    }
+
+    /// Replace the suffix after the stem with `replacement` if the stem's
+    /// measure is positive.
+    fn replace(&mut self, replacement: &str) {
+        if self.m() > 0 {
+            self.set_to(replacement);
+        }
+    }
+
+    /// Replace the first suffix of `rules` the word ends with, if its
+    /// stem's measure is positive.
+    fn replace_first(&mut self, rules: &[(&str, &str)]) {
+        if let Some(&(_, replacement)) = rules.iter().find(|(suffix, _)| self.ends(suffix)) {
+            self.replace(replacement);
+        }
+    }
+
+    /// The letter before the last one.
+    fn penultimate(&self) -> u8 {
+        self.b[self.b.len() - 2]
+    }
+
+    /// Remove plurals and `-ed` or `-ing`: `caresses` to `caress`, `ponies`
+    /// to `poni`, `feed` stays, `agreed` to `agree`, `hopping` to `hop`,
+    /// `filing` to `file`.
+    fn step1ab(&mut self) {
+        if self.b.ends_with(b"s") {
+            if self.ends("sses") {
+                self.b.truncate(self.b.len() - 2);
+            } else if self.ends("ies") {
+                self.set_to("i");
+            } else if self.penultimate() != b's' {
+                self.b.pop();
+            }
+        }
+        if self.ends("eed") {
+            if self.m() > 0 {
+                self.b.pop();
+            }
+        } else if (self.ends("ed") || self.ends("ing")) && self.vowel_in_stem() {
+            self.b.truncate(self.j);
+            let len = self.b.len();
+            if self.ends("at") {
+                self.set_to("ate");
+            } else if self.ends("bl") {
+                self.set_to("ble");
+            } else if self.ends("iz") {
+                self.set_to("ize");
+            } else if self.double_consonant(len) {
+                if !matches!(self.b[len - 1], b'l' | b's' | b'z') {
+                    self.b.pop();
+                }
+            } else {
+                self.j = len;
+                if self.m() == 1 && self.cvc(len) {
+                    self.b.push(b'e');
+                }
+            }
+        }
+    }
+
+    /// Turn a final `y` into `i` if there is another vowel in the stem.
+    fn step1c(&mut self) {
+        if self.ends("y") && self.vowel_in_stem() {
+            let last = self.b.len() - 1;
+            self.b[last] = b'i';
+        }
+    }
+
+    /// Map double suffixes to single ones: `-ization` to `-ize`,
+    /// `-ational` to `-ate`, and so on.
+    fn step2(&mut self) {
+        let rules: &[(&str, &str)] = match self.penultimate() {
+            b'a' => &[("ational", "ate"), ("tional", "tion")],
+            b'c' => &[("enci", "ence"), ("anci", "ance")],
+            b'e' => &[("izer", "ize")],
+            b'l' => &[("bli", "ble"), ("alli", "al"), ("entli", "ent"), ("eli", "e"), ("ousli", "ous")],
+            b'o' => &[("ization", "ize"), ("ation", "ate"), ("ator", "ate")],
+            b's' => &[("alism", "al"), ("iveness", "ive"), ("fulness", "ful"), ("ousness", "ous")],
+            b't' => &[("aliti", "al"), ("iviti", "ive"), ("biliti", "ble")],
+            b'g' => &[("logi", "log")],
+            _ => return,
+        };
+        self.replace_first(rules);
+    }
+
+    /// Deal with `-ic-`, `-full`, `-ness`, and the like.
+    fn step3(&mut self) {
+        let rules: &[(&str, &str)] = match self.b[self.b.len() - 1] {
+            b'e' => &[("icate", "ic"), ("ative", ""), ("alize", "al")],
+            b'i' => &[("iciti", "ic")],
+            b'l' => &[("ical", "ic"), ("ful", "")],
+            b's' => &[("ness", "")],
+            _ => return,
+        };
+        self.replace_first(rules);
+    }
+
+    /// Remove `-ant`, `-ence`, and the like from stems of measure above 1.
+    fn step4(&mut self) {
+        let suffixes: &[&str] = match self.penultimate() {
+            b'a' => &["al"],
+            b'c' => &["ance", "ence"],
+            b'e' => &["er"],
+            b'i' => &["ic"],
+            b'l' => &["able", "ible"],
+            b'n' => &["ant", "ement", "ment", "ent"],
+            b'o' => {
+                let ion = self.ends("ion") && self.j >= 1 && matches!(self.b[self.j - 1], b's' | b't');
+                if !ion && !self.ends("ou") {
+                    return;
+                }
+                &[]
+            }
+            b's' => &["ism"],
+            b't' => &["ate", "iti"],
+            b'u' => &["ous"],
+            b'v' => &["ive"],
+            b'z' => &["ize"],
+            _ => return,
+        };
+        if !suffixes.is_empty() && !suffixes.iter().any(|suffix| self.ends(suffix)) {
+            return;
+        }
+        if self.m() > 1 {
+            self.b.truncate(self.j);
+        }
+    }
+
+    /// Remove a final `-e` and reduce a final `-ll` to `-l` on longer stems.
+    fn step5(&mut self) {
+        self.j = self.b.len();
+        if self.b.ends_with(b"e") {
+            let m = self.m();
+            if m > 1 || (m == 1 && !self.cvc(self.b.len() - 1)) {
+                self.b.pop();
+            }
+        }
+        self.j = self.b.len();
+        if self.b.ends_with(b"l") && self.double_consonant(self.b.len()) && self.m() > 1 {
+            self.b.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stems_the_words_of_the_reference_vocabulary() {
+        let cases = [
+            ("caresses", "caress"), ("ponies", "poni"), ("ties", "ti"), ("caress", "caress"), ("cats", "cat"),
+            ("feed", "feed"), ("agreed", "agre"), ("plastered", "plaster"), ("bled", "bled"),
+            ("motoring", "motor"), ("sing", "sing"), ("conflated", "conflat"), ("troubled", "troubl"),
+            ("sized", "size"), ("hopping", "hop"), ("tanned", "tan"), ("falling", "fall"), ("hissing", "hiss"),
+            ("fizzed", "fizz"), ("failing", "fail"), ("filing", "file"), ("happy", "happi"), ("sky", "sky"),
+            ("relational", "relat"), ("conditional", "condit"), ("rational", "ration"), ("valenci", "valenc"),
+            ("digitizer", "digit"), ("generalization", "gener"), ("electrical", "electr"), ("hopeful", "hope"),
+            ("goodness", "good"), ("revival", "reviv"), ("adoption", "adopt"), ("effective", "effect"),
+            ("probate", "probat"), ("rate", "rate"), ("controll", "control"), ("roll", "roll"),
+            ("running", "run"), ("runs", "run"), ("connections", "connect"), ("connected", "connect"),
+        ];
+        for (word, expected) in cases {
+            assert_eq!(stem(word), expected, "stem of {}", word);
+        }
+    }
+
+    #[test]
+    fn leaves_short_and_non_ascii_words_alone() {
+        assert_eq!(stem("is"), "is");
+        assert_eq!(stem("cafés"), "cafés");
+        assert_eq!(stem("mp3s"), "mp3s");
+    }
+}
@@ -0,0 +1,267 @@
+//! A cache of query results, invalidated by writes to the tables read.
+//!
+//! [`QueryCache`] maps the text of a SELECT statement to its last result.
+//! Every INSERT, UPDATE, or DELETE gives the table it writes a new write
+//! generation (see [`Catalog::write_generation`]), which is larger than any
+//! generation before it. An entry records the tables its query reads and
+//! the largest of their generations when it was stored; it is served as
+//! long as that largest generation is unchanged, that is, as long as none
+//! of the tables has been written since.
+//!
+//! Only queries whose result depends on nothing but the rows of ordinary
+//! tables are cached: see [`cacheable_tables`]. Changes that are not row
+//! writes to a table, such as schema changes or a ROLLBACK, must clear the
+//! cache with [`QueryCache::clear`].
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::catalog::Catalog;
+use crate::sql::ast::*;
+use crate::types::Value;
+use crate::QueryResult;
+use super::{attach, fts5, params};
+
+/// Functions whose result can differ between two calls with the same
+/// arguments.
+const NONDETERMINISTIC_FUNCTIONS: &[&str] = &[
+    "RANDOM", "UUID", "UUID_BLOB", "DATE", "TIME", "DATETIME", "STRFTIME", "JULIANDAY", "UNIXEPOCH",
+];
+
+/// Query results keyed by SQL text, holding at most a given number of
+/// bytes of results. The least recently used results are evicted first.
+pub struct QueryCache {
+    capacity: usize,
+    size: usize,
+    entries: HashMap<String, CachedResult>,
+    /// The cached SQL texts, from least to most recently used.
+    lru: VecDeque<String>,
+}
+
+struct CachedResult {
+    /// The largest write generation of `tables` when the result was stored.
+    generation: u64,
+    tables: Vec<String>,
+    result: QueryResult,
+    size: usize,
+}
+
+impl QueryCache {
+    /// Create an empty cache holding up to `capacity` bytes of results.
+    pub fn new(capacity: usize) -> Self {
+        QueryCache { capacity, size: 0, entries: HashMap::new(), lru: VecDeque::new() }
+    }
+
+    /// The result cached for `sql`, if none of the tables it reads has
+    /// been written since it was stored. A result that is out of date is
+    /// dropped.
+    pub fn get(&mut self, sql: &str, catalog: &Catalog) -> Option<QueryResult> {
+        let entry = self.entries.get(sql)?;
+        if generation(&entry.tables, catalog) != entry.generation {
+            self.remove(sql);
+            return None;
+        }
+        let result = entry.result.clone();
+        self.touch(sql);
+        Some(result)
+    }
+
+    /// Store `result` as the result of `sql`, which reads `tables`,
+    /// evicting older results to make room. A result larger than the whole
+    /// cache is not stored.
+    pub fn insert(&mut self, sql: &str, tables: Vec<String>, result: &QueryResult, catalog: &Catalog) {
+        let size = sql.len() + result_size(result);
+        if size > self.capacity {
+            return;
+        }
+        self.remove(sql);
+        while self.size + size > self.capacity {
+            match self.lru.pop_front() {
+                Some(oldest) => {
+                    if let Some(evicted) = self.entries.remove(&oldest) {
+                        self.size -= evicted.size;
+                    }
+                }
+                None => break,
+            }
+        }
+        let generation = generation(&tables, catalog);
+        self.entries.insert(sql.to_string(), CachedResult { generation, tables, result: result.clone(), size });
+        self.lru.push_back(sql.to_string());
+        self.size += size;
+    }
+
+    /// Drop every cached result.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.size = 0;
+    }
+
+    /// The number of cached results.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no results are cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn remove(&mut self, sql: &str) {
+        if let Some(entry) = self.entries.remove(sql) {
+            self.size -= entry.size;
+            self.lru.retain(|cached| cached != sql);
+        }
+    }
+
+    /// Move `sql` to the most recently used end of the LRU queue.
+    fn touch(&mut self, sql: &str) {
+        if self.lru.back().is_some_and(|last| last == sql) {
+            return;
+        }
+        if let Some(pos) = self.lru.iter().position(|cached| cached == sql) {
+            if let Some(key) = self.lru.remove(pos) {
+                self.lru.push_back(key);
+            }
+        }
+    }
+}
+
+/// The tables `stmt` reads, if its result can be cached: it is a SELECT
+/// that reads only ordinary tables of the main database, and calls no
+/// function whose result varies between calls.
+pub fn cacheable_tables(stmt: &Statement, catalog: &Catalog) -> Option<Vec<String>> {
+    if !matches!(stmt, Statement::Select(_)) {
+        return None;
+    }
+    let mut stmt = stmt.clone();
+    let mut tables = Vec::new();
+    let mut cacheable = true;
+    let _ = attach::walk_tables(&mut stmt, &mut |from| {
+        match from {
+            FromClause::Table { name, .. }
+                if catalog.table_exists(name) && !fts5::fts5_table_exists(name) && !catalog.rtree_exists(name) =>
+            {
+                tables.push(name.to_lowercase());
+            }
+            FromClause::Values { .. } => {}
+            _ => cacheable = false,
+        }
+        Ok(())
+    });
+    let _ = params::walk_statement(&mut stmt, &mut |expr| {
+        match expr {
+            Expr::Function { name, .. } if NONDETERMINISTIC_FUNCTIONS.contains(&name.to_uppercase().as_str()) => {
+                cacheable = false
+            }
+            Expr::Match { .. } | Expr::Placeholder(_) | Expr::NamedPlaceholder(_) => cacheable = false,
+            _ => {}
+        }
+        Ok(())
+    });
+    if !cacheable {
+        return None;
+    }
+    tables.sort();
+    tables.dedup();
+    Some(tables)
+}
+
+/// The largest write generation of `tables`.
+fn generation(tables: &[String], catalog: &Catalog) -> u64 {
+    tables.iter().map(|table| catalog.write_generation(table)).max().unwrap_or(0)
+}
+
+/// The approximate number of bytes `result` takes up.
+fn result_size(result: &QueryResult) -> usize {
+    let columns: usize = result.columns.iter().map(String::len).sum();
+    let values: usize = result
+        .rows
+        .iter()
+        .flat_map(|row| &row.values)
+        .map(|value| {
+            std::mem::size_of::<Value>()
+                + match value {
+                    Value::Text(s) => s.len(),
+                    Value::Blob(b) => b.len(),
+                    _ => 0,
+                }
+        })
+        .sum();
+    columns + values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+    use std::sync::Arc;
+
+    fn result(values: &[i64]) -> QueryResult {
+        let columns = Arc::new(vec!["x".to_string()]);
+        let rows = values
+            .iter()
+            .map(|v| crate::Row { columns: columns.clone(), values: vec![Value::Integer(*v)] })
+            .collect();
+        QueryResult { columns, rows }
+    }
+
+    #[test]
+    fn results_are_dropped_once_a_table_they_read_is_written() {
+        let mut catalog = Catalog::new();
+        let mut cache = QueryCache::new(1 << 20);
+        cache.insert("SELECT 1", vec!["a".into(), "b".into()], &result(&[1]), &catalog);
+        cache.insert("SELECT 2", vec!["c".into()], &result(&[2]), &catalog);
+        assert_eq!(cache.get("SELECT 1", &catalog).unwrap().rows[0].values, vec![Value::Integer(1)]);
+
+        catalog.bump_write_generation("B");
+        assert!(cache.get("SELECT 1", &catalog).is_none());
+        assert!(cache.get("SELECT 2", &catalog).is_some());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn least_recently_used_results_are_evicted() {
+        let catalog = Catalog::new();
+        let one = result(&[1; 10]);
+        let size = "SELECT 1".len() + result_size(&one);
+        let mut cache = QueryCache::new(2 * size);
+        cache.insert("SELECT 1", vec![], &one, &catalog);
+        cache.insert("SELECT 2", vec![], &result(&[2; 10]), &catalog);
+        assert!(cache.get("SELECT 1", &catalog).is_some());
+        cache.insert("SELECT 3", vec![], &result(&[3; 10]), &catalog);
+        assert!(cache.get("SELECT 2", &catalog).is_none());
+        assert!(cache.get("SELECT 1", &catalog).is_some());
+        assert!(cache.get("SELECT 3", &catalog).is_some());
+
+        // A result larger than the cache is not stored
+        cache.insert("SELECT 4", vec![], &result(&[4; 100]), &catalog);
+        assert!(cache.get("SELECT 4", &catalog).is_none());
+    }
+
+    #[test]
+    fn only_deterministic_reads_of_tables_are_cacheable() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pager = crate::pager::Pager::open(&dir.path().join("test.hdb"), false).unwrap();
+        let mut pool = crate::buffer::BufferPool::new(pager, None, 16).unwrap();
+        let mut catalog = Catalog::new();
+        let mut txn_mgr = crate::mvcc::TransactionManager::new();
+        for sql in ["CREATE TABLE t (x INTEGER)", "CREATE TABLE u (y INTEGER)", "CREATE VIEW v AS SELECT x FROM t"] {
+            let stmt = Parser::parse(sql).unwrap().remove(0);
+            crate::execution::execute_statement(&stmt, &mut pool, &mut catalog, &mut txn_mgr).unwrap();
+        }
+        let tables = |sql: &str| cacheable_tables(&Parser::parse(sql).unwrap().remove(0), &catalog);
+
+        assert_eq!(tables("SELECT * FROM t"), Some(vec!["t".into()]));
+        assert_eq!(
+            tables("SELECT x FROM t JOIN u ON x = y WHERE x IN (SELECT y FROM u)"),
+            Some(vec!["t".into(), "u".into()])
+        );
+        assert_eq!(tables("SELECT 1"), Some(vec![]));
+        assert_eq!(tables("SELECT random() FROM t"), None);
+        assert_eq!(tables("SELECT x FROM t WHERE x > date('now')"), None);
+        assert_eq!(tables("SELECT * FROM v"), None);
+        assert_eq!(tables("SELECT * FROM generate_series(1, 3)"), None);
+        assert_eq!(tables("INSERT INTO t VALUES (1)"), None);
+    }
+}
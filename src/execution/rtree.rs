@@ -3,7 +3,13 @@
 //! Provides a simplified R-tree virtual table compatible with SQLite's rtree
 //! extension. Entries are stored in a B+Tree keyed by integer rowid.
 //! Each entry contains the id and coordinate pairs (min/max for each dimension).
+//!
+//! Nearest-neighbour queries, `ORDER BY rtree_distance(minX, minY, maxX,
+//! maxY, x, y) LIMIT n` or [`knn_query`], pack the entries into an
+//! in-memory R-tree and search it best-first instead of sorting them all.
 
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::sync::Arc;
 
 use crate::btree::BTree;
@@ -131,7 +137,7 @@ pub fn execute_rtree_insert(
     let mut tree = BTree::open(rtree.root_page);
     let mut inserted = 0;
 
-    for value_row in &ins.values {
+    for value_row in super::insert_source_rows(ins, pool, catalog)?.iter() {
         if value_row.len() != expected_cols {
             return Err(HorizonError::InvalidSql(format!(
                 "rtree INSERT: expected {} values but got {}",
@@ -258,8 +264,8 @@ pub fn execute_rtree_delete(
 /// Execute a SELECT from an R-tree virtual table.
 pub fn execute_rtree_select(
     select: &SelectStatement,
-    pool: &mut BufferPool,
-    catalog: &mut Catalog,
+    pool: &BufferPool,
+    catalog: &Catalog,
 ) -> Result<QueryResult> {
     let table_name = match &select.from {
         Some(FromClause::Table { name, .. }) => name.clone(),
@@ -281,46 +287,62 @@ pub fn execute_rtree_select(
     let output_col_names = resolve_rtree_columns(&select.columns, &rtree)?;
     let columns = Arc::new(output_col_names);
 
-    let mut rows = Vec::new();
-
+    let mut matches = Vec::new();
     for entry in &entries {
         let rtree_entry = RTreeEntry::deserialize(&entry.value)?;
-        let row_values = rtree_entry_to_values(&rtree_entry, &rtree);
 
         // Apply WHERE filter
         let passes = if let Some(ref where_clause) = select.where_clause {
+            let row_values = rtree_entry_to_values(&rtree_entry, &rtree);
             eval_rtree_where(where_clause, &row_values, &rtree.column_names)?
         } else {
             true
         };
-
         if passes {
-            // Project columns
-            let projected = project_rtree_row(&select.columns, &row_values, &rtree)?;
-            rows.push(Row {
-                columns: columns.clone(),
-                values: projected,
-            });
+            matches.push(rtree_entry);
         }
     }
 
+    // ORDER BY rtree_distance(...) LIMIT n only needs the nearest entries
+    let knn = match (&select.limit, nearest_neighbour_point(select, &rtree)) {
+        (Some(limit_expr), Some(point)) => {
+            let limit = eval_const_i64(limit_expr).unwrap_or(i64::MAX).max(0) as usize;
+            let offset = select.offset.as_ref().and_then(eval_const_i64).unwrap_or(0).max(0) as usize;
+            let found = nearest(&matches, &point, limit.saturating_add(offset));
+            matches = found.into_iter().map(|(_, i)| matches[i].clone()).collect();
+            true
+        }
+        _ => false,
+    };
+
+    let mut rows = Vec::with_capacity(matches.len());
+    let mut sort_keys = Vec::new();
+    for rtree_entry in &matches {
+        let row_values = rtree_entry_to_values(rtree_entry, &rtree);
+        let projected = project_rtree_row(&select.columns, &row_values, &rtree)?;
+        if !knn && !select.order_by.is_empty() {
+            sort_keys.push(rtree_sort_key(&select.order_by, &columns, &projected, &row_values, &rtree)?);
+        }
+        rows.push(Row {
+            columns: columns.clone(),
+            values: projected,
+        });
+    }
+
     // Apply ORDER BY
-    if !select.order_by.is_empty() {
-        rows.sort_by(|a, b| {
-            for item in &select.order_by {
-                if let Expr::Column { ref name, .. } = item.expr {
-                    let col_idx = columns.iter().position(|c| c.eq_ignore_ascii_case(name));
-                    if let Some(idx) = col_idx {
-                        let cmp = a.values[idx].cmp(&b.values[idx]);
-                        let cmp = if item.desc { cmp.reverse() } else { cmp };
-                        if cmp != std::cmp::Ordering::Equal {
-                            return cmp;
-                        }
-                    }
+    if !sort_keys.is_empty() {
+        let mut keyed: Vec<(Vec<Value>, Row)> = sort_keys.into_iter().zip(rows).collect();
+        keyed.sort_by(|(a, _), (b, _)| {
+            for (item, (x, y)) in select.order_by.iter().zip(a.iter().zip(b)) {
+                let cmp = x.cmp(y);
+                let cmp = if item.desc { cmp.reverse() } else { cmp };
+                if cmp != std::cmp::Ordering::Equal {
+                    return cmp;
                 }
             }
             std::cmp::Ordering::Equal
         });
+        rows = keyed.into_iter().map(|(_, row)| row).collect();
     }
 
     // Apply LIMIT / OFFSET
@@ -340,6 +362,245 @@ pub fn execute_rtree_select(
     Ok(QueryResult { columns, rows })
 }
 
+// ---------------------------------------------------------------------------
+// Nearest-neighbour search
+// ---------------------------------------------------------------------------
+
+/// The most children a node of the R-tree [`nearest`] packs entries into.
+const NODE_CAPACITY: usize = 16;
+
+/// Return the `k` entries of the R-tree virtual table `table` nearest to
+/// `point`, nearest first, with all of the table's columns.
+///
+/// `point` has a coordinate per dimension of the table, and an entry's
+/// distance to it is the Euclidean distance to the nearest point of the
+/// entry's box, 0 if the box contains it. Entries at the same distance
+/// come in no particular order.
+pub fn knn_query(
+    table: &str,
+    point: &[f64],
+    k: usize,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<QueryResult> {
+    let rtree = catalog.get_rtree(table)
+        .ok_or_else(|| HorizonError::TableNotFound(table.to_string()))?;
+    if point.len() != rtree.num_dimensions {
+        return Err(HorizonError::InvalidSql(format!(
+            "{} has {} dimensions but the query point has {} coordinates",
+            rtree.name,
+            rtree.num_dimensions,
+            point.len()
+        )));
+    }
+
+    let tree = BTree::open(rtree.root_page);
+    let entries = tree
+        .scan_all(pool)?
+        .iter()
+        .map(|entry| RTreeEntry::deserialize(&entry.value))
+        .collect::<Result<Vec<_>>>()?;
+
+    let columns = Arc::new(rtree.column_names.clone());
+    let rows = nearest(&entries, point, k)
+        .into_iter()
+        .map(|(_, i)| Row {
+            columns: columns.clone(),
+            values: rtree_entry_to_values(&entries[i], rtree),
+        })
+        .collect();
+    Ok(QueryResult { columns, rows })
+}
+
+/// The query point of `ORDER BY rtree_distance(...)` in `select`, if that
+/// is its only ordering and the box it measures from is the entry's own:
+/// the minimum columns of `rtree` in order, then the maximum ones, then a
+/// constant coordinate per dimension.
+fn nearest_neighbour_point(select: &SelectStatement, rtree: &RTreeInfo) -> Option<Vec<f64>> {
+    let [item] = select.order_by.as_slice() else {
+        return None;
+    };
+    let Expr::Function { name, args, .. } = &item.expr else {
+        return None;
+    };
+    let dims = rtree.num_dimensions;
+    if item.desc || !name.eq_ignore_ascii_case("rtree_distance") || args.len() != dims * 3 {
+        return None;
+    }
+    let is_column = |expr: &Expr, column: &String| {
+        matches!(expr, Expr::Column { name, .. } if name.eq_ignore_ascii_case(column))
+    };
+    for d in 0..dims {
+        if !is_column(&args[d], &rtree.column_names[1 + d * 2])
+            || !is_column(&args[dims + d], &rtree.column_names[2 + d * 2])
+        {
+            return None;
+        }
+    }
+    args[dims * 2..]
+        .iter()
+        .map(|arg| match eval_const_expr_rtree(arg).ok()? {
+            Value::Integer(i) => Some(i as f64),
+            Value::Real(r) => Some(r),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The Euclidean distance from `point` to the box `coords`, which holds a
+/// (min, max) pair per dimension like [`RTreeEntry::coords`]. It is 0 for a
+/// point inside the box.
+fn box_distance(coords: &[f64], point: &[f64]) -> f64 {
+    point
+        .iter()
+        .enumerate()
+        .map(|(d, &q)| {
+            let gap = (coords[d * 2] - q).max(q - coords[d * 2 + 1]).max(0.0);
+            gap * gap
+        })
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// A distance, ordered so that it can go in a [`BinaryHeap`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Distance(f64);
+
+impl Eq for Distance {}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A node of an R-tree packed from a slice of entries.
+struct Node {
+    /// The bounding box of the node's children, laid out like
+    /// [`RTreeEntry::coords`].
+    bbox: Vec<f64>,
+    /// Indexes of the node's children: into the entries for a leaf, and
+    /// into the nodes otherwise.
+    children: Vec<usize>,
+    leaf: bool,
+}
+
+/// Pack `entries` into an R-tree with the Sort-Tile-Recursive algorithm,
+/// returning its nodes with the root last, or none if there are no
+/// entries.
+fn pack(entries: &[RTreeEntry]) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let Some(first) = entries.first() else {
+        return nodes;
+    };
+    let dims = first.coords.len() / 2;
+    let mut level: Vec<(Vec<f64>, usize)> =
+        entries.iter().enumerate().map(|(i, e)| (e.coords.clone(), i)).collect();
+    let mut leaf = true;
+    loop {
+        let mut groups = Vec::new();
+        tile(level, 0, dims, &mut groups);
+        level = groups
+            .into_iter()
+            .map(|group| {
+                let mut bbox = group[0].0.clone();
+                for (coords, _) in &group[1..] {
+                    for d in 0..dims {
+                        bbox[d * 2] = bbox[d * 2].min(coords[d * 2]);
+                        bbox[d * 2 + 1] = bbox[d * 2 + 1].max(coords[d * 2 + 1]);
+                    }
+                }
+                let children = group.into_iter().map(|(_, i)| i).collect();
+                nodes.push(Node { bbox: bbox.clone(), children, leaf });
+                (bbox, nodes.len() - 1)
+            })
+            .collect();
+        leaf = false;
+        if level.len() == 1 {
+            return nodes;
+        }
+    }
+}
+
+/// Split `items` into groups of at most [`NODE_CAPACITY`] boxes that lie
+/// close together: sort them by the centre of dimension `dim`, cut them
+/// into slabs, and tile each slab along the next dimension.
+fn tile(mut items: Vec<(Vec<f64>, usize)>, dim: usize, dims: usize, groups: &mut Vec<Vec<(Vec<f64>, usize)>>) {
+    let centre = |coords: &[f64]| coords[dim * 2] + coords[dim * 2 + 1];
+    items.sort_by(|(a, _), (b, _)| centre(a).total_cmp(&centre(b)));
+    let node_count = (items.len() + NODE_CAPACITY - 1) / NODE_CAPACITY;
+    if dim + 1 == dims || node_count <= 1 {
+        while !items.is_empty() {
+            let rest = items.split_off(items.len().min(NODE_CAPACITY));
+            groups.push(std::mem::replace(&mut items, rest));
+        }
+        return;
+    }
+    let slabs = (node_count as f64).powf(1.0 / (dims - dim) as f64).ceil() as usize;
+    let slab_size = (node_count + slabs - 1) / slabs * NODE_CAPACITY;
+    while !items.is_empty() {
+        let rest = items.split_off(items.len().min(slab_size));
+        tile(std::mem::replace(&mut items, rest), dim + 1, dims, groups);
+    }
+}
+
+/// The `k` of `entries` nearest to `point` with their distances, nearest
+/// first.
+///
+/// The entries are stored in a B+Tree by id, so this packs them into an
+/// R-tree and runs a best-first branch-and-bound search over it. A
+/// priority queue holds the nodes and entries still to visit by their
+/// minimum distance to `point`, so the entries come off it nearest first.
+/// A node or entry whose minimum distance is beyond the k-th nearest entry
+/// queued so far cannot hold any of the `k` nearest and is never queued.
+fn nearest(entries: &[RTreeEntry], point: &[f64], k: usize) -> Vec<(f64, usize)> {
+    let nodes = pack(entries);
+    let mut found = Vec::with_capacity(k.min(entries.len()));
+    if k == 0 || nodes.is_empty() {
+        return found;
+    }
+
+    // (distance, whether it is a node, index): entries go before nodes at
+    // the same distance, being results already.
+    let mut queue = BinaryHeap::new();
+    let root = nodes.len() - 1;
+    queue.push(Reverse((Distance(box_distance(&nodes[root].bbox, point)), true, root)));
+    // The distances of the k nearest entries queued so far, farthest on top.
+    let mut bound: BinaryHeap<Distance> = BinaryHeap::with_capacity(k + 1);
+
+    while let Some(Reverse((distance, is_node, index))) = queue.pop() {
+        if !is_node {
+            found.push((distance.0, index));
+            if found.len() == k {
+                break;
+            }
+            continue;
+        }
+        let node = &nodes[index];
+        for &child in &node.children {
+            let bbox = if node.leaf { &entries[child].coords } else { &nodes[child].bbox };
+            let distance = Distance(box_distance(bbox, point));
+            if bound.len() == k && bound.peek().is_some_and(|farthest| distance > *farthest) {
+                continue;
+            }
+            if node.leaf {
+                bound.push(distance);
+                if bound.len() > k {
+                    bound.pop();
+                }
+            }
+            queue.push(Reverse((distance, !node.leaf, child)));
+        }
+    }
+    found
+}
+
 // ---------------------------------------------------------------------------
 // Helper functions
 // ---------------------------------------------------------------------------
@@ -403,6 +664,28 @@ fn project_rtree_row(
     Ok(projected)
 }
 
+/// The values an R-tree row sorts by under `order_by`: an output column
+/// named by an item, or else the item evaluated against the row.
+fn rtree_sort_key(
+    order_by: &[OrderByItem],
+    columns: &[String],
+    projected: &[Value],
+    row_values: &[Value],
+    rtree: &RTreeInfo,
+) -> Result<Vec<Value>> {
+    order_by
+        .iter()
+        .map(|item| {
+            if let Expr::Column { ref name, .. } = item.expr {
+                if let Some(idx) = columns.iter().position(|c| c.eq_ignore_ascii_case(name)) {
+                    return Ok(projected[idx].clone());
+                }
+            }
+            eval_rtree_expr(&item.expr, row_values, &rtree.column_names)
+        })
+        .collect()
+}
+
 /// Evaluate a WHERE clause expression against an R-tree row.
 /// Returns true if the row passes the filter.
 fn eval_rtree_where(
@@ -482,6 +765,24 @@ fn eval_rtree_expr(
                         _ => Ok(Value::Null),
                     }
                 }
+                "RTREE_DISTANCE" => {
+                    let dims = evaluated_args.len() / 3;
+                    if dims == 0 || evaluated_args.len() % 3 != 0 {
+                        return Err(HorizonError::InvalidSql(
+                            "rtree_distance takes the minimum coordinates of a box, then its maximum coordinates, then a point".into(),
+                        ));
+                    }
+                    let mut nums = Vec::with_capacity(evaluated_args.len());
+                    for val in &evaluated_args {
+                        match val {
+                            Value::Integer(i) => nums.push(*i as f64),
+                            Value::Real(r) => nums.push(*r),
+                            _ => return Ok(Value::Null),
+                        }
+                    }
+                    let coords: Vec<f64> = (0..dims).flat_map(|d| [nums[d], nums[dims + d]]).collect();
+                    Ok(Value::Real(box_distance(&coords, &nums[dims * 2..])))
+                }
                 _ => Err(HorizonError::NotImplemented(format!(
                     "function {} in rtree context", name
                 ))),
@@ -675,6 +976,47 @@ mod tests {
         assert_eq!(eval_binary_op(&f, &BinaryOp::Or, &f).unwrap(), Value::Integer(0));
     }
 
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 11) as f64 / (1u64 << 53) as f64 * 100.0
+        };
+        let entries: Vec<RTreeEntry> = (0..500)
+            .map(|id| {
+                let coords = (0..3).flat_map(|_| {
+                    let min = next();
+                    [min, min + next() / 10.0]
+                });
+                RTreeEntry { id, coords: coords.collect() }
+            })
+            .collect();
+        for _ in 0..20 {
+            let point = [next(), next(), next()];
+            let mut expected: Vec<f64> = entries.iter().map(|e| box_distance(&e.coords, &point)).collect();
+            expected.sort_by(f64::total_cmp);
+            let found = nearest(&entries, &point, 7);
+            let distances: Vec<f64> = found.iter().map(|&(d, _)| d).collect();
+            assert_eq!(distances, expected[..7]);
+            for (d, i) in found {
+                assert_eq!(d, box_distance(&entries[i].coords, &point));
+            }
+        }
+        assert!(nearest(&entries, &[0.0, 0.0, 0.0], 0).is_empty());
+        assert_eq!(nearest(&entries[..3], &[0.0, 0.0, 0.0], 10).len(), 3);
+    }
+
+    #[test]
+    fn box_distance_is_zero_inside_the_box() {
+        let coords = [0.0, 10.0, 0.0, 10.0];
+        assert_eq!(box_distance(&coords, &[5.0, 5.0]), 0.0);
+        assert_eq!(box_distance(&coords, &[13.0, 14.0]), 5.0);
+        assert_eq!(box_distance(&coords, &[5.0, -2.0]), 2.0);
+    }
+
     #[test]
     fn eval_comparison_ops() {
         let a = Value::Real(5.0);
@@ -0,0 +1,234 @@
+//! ORDER BY sorting, spilling to disk for large inputs.
+//!
+//! `sort_by_terms` evaluates the ORDER BY terms of every row once and
+//! sorts the rows by these keys. Up to the connection's sort spill
+//! threshold (see `PRAGMA sort_spill_threshold`) the rows are sorted in
+//! memory. Larger inputs go through an [`ExternalSorter`]: each time the
+//! threshold is reached, the buffered rows are sorted and written out as a
+//! *run* in a temporary file, and the runs are merged at the end.
+//! Both sorts are stable, so they order rows identically.
+
+use std::cmp::Ordering;
+
+use crate::error::{HorizonError, Result};
+use crate::sql::ast::{Expr, OrderByItem};
+use crate::types::Value;
+use super::spill::{Keyed, Run, RunReader, RunWriter, SpillFile};
+use super::collation::Collations;
+use super::extract_collation;
+
+/// The number of rows sorted in memory unless configured otherwise.
+pub const DEFAULT_SORT_SPILL_THRESHOLD: usize = 1_000_000;
+
+/// Sort `rows` by the ORDER BY terms `order_by`, using `eval` to evaluate
+/// a term for a row and `collations` to look up the collations of its
+/// COLLATE terms. More than `spill_threshold` rows are sorted with an
+/// [`ExternalSorter`]. Fails if a term uses an unknown collation.
+pub(super) fn sort_by_terms(
+    rows: &mut Vec<Vec<Value>>,
+    order_by: &[OrderByItem],
+    spill_threshold: usize,
+    collations: &Collations,
+    eval: impl Fn(&Expr, &[Value]) -> Value,
+) -> Result<()> {
+    for collation in order_by.iter().filter_map(|item| extract_collation(&item.expr)) {
+        collations.check(collation)?;
+    }
+    let compare = |a: &[Value], b: &[Value]| compare_keys(order_by, collations, a, b);
+    let keys = |row: &[Value]| order_by.iter().map(|item| eval(&item.expr, row)).collect::<Vec<_>>();
+    if rows.len() <= spill_threshold {
+        let mut keyed: Vec<Keyed> = rows.drain(..).map(|row| (keys(&row), row)).collect();
+        keyed.sort_by(|a, b| compare(&a.0, &b.0));
+        rows.extend(keyed.into_iter().map(|(_, row)| row));
+        return Ok(());
+    }
+
+    let mut sorter = ExternalSorter::new(spill_threshold, compare);
+    for row in std::mem::take(rows) {
+        sorter.push(keys(&row), row)?;
+    }
+    *rows = sorter.finish()?;
+    Ok(())
+}
+
+/// Compare the sort keys of two rows, term by term.
+fn compare_keys(order_by: &[OrderByItem], collations: &Collations, a: &[Value], b: &[Value]) -> Ordering {
+    for (item, (a, b)) in order_by.iter().zip(a.iter().zip(b)) {
+        let cmp = match extract_collation(&item.expr) {
+            Some(collation) => collations.compare(a, b, collation),
+            None => a.cmp(b),
+        };
+        let cmp = if item.desc { cmp.reverse() } else { cmp };
+        if cmp != Ordering::Equal {
+            return cmp;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Sorts rows by their keys, holding at most `threshold` rows in memory
+/// while they are pushed. Beyond that, rows are written to a temporary
+/// file in sorted runs, which [`finish`](Self::finish) merges.
+pub struct ExternalSorter<C> {
+    compare: C,
+    threshold: usize,
+    /// The rows pushed since the last run was written.
+    buffer: Vec<Keyed>,
+    /// The temporary file, created when the first run is written.
+    spill: Option<SpillFile>,
+    runs: Vec<Run>,
+}
+
+impl<C: Fn(&[Value], &[Value]) -> Ordering> ExternalSorter<C> {
+    /// Create a sorter that orders rows by comparing their keys with
+    /// `compare` and writes a run every `threshold` rows.
+    pub fn new(threshold: usize, compare: C) -> Self {
+        ExternalSorter { compare, threshold: threshold.max(1), buffer: Vec::new(), spill: None, runs: Vec::new() }
+    }
+
+    /// Add a row with its sort keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error creating or writing the temporary file.
+    pub fn push(&mut self, keys: Vec<Value>, row: Vec<Value>) -> Result<()> {
+        self.buffer.push((keys, row));
+        if self.buffer.len() >= self.threshold {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    /// The number of runs written to the temporary file so far.
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Return all rows pushed, ordered by their keys. Rows with equal keys
+    /// keep the order in which they were pushed.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error reading the temporary file.
+    pub fn finish(mut self) -> Result<Vec<Vec<Value>>> {
+        if self.runs.is_empty() {
+            let compare = &self.compare;
+            self.buffer.sort_by(|a, b| compare(&a.0, &b.0));
+            return Ok(self.buffer.into_iter().map(|(_, row)| row).collect());
+        }
+        if !self.buffer.is_empty() {
+            self.write_run()?;
+        }
+        let spill = self.spill.as_ref().ok_or_else(|| HorizonError::Internal("sort runs without a file".into()))?;
+        let mut readers: Vec<RunReader> = self.runs.iter().map(RunReader::new).collect();
+        let mut heads = Vec::with_capacity(readers.len());
+        for reader in &mut readers {
+            heads.push(reader.next(spill)?);
+        }
+
+        // A min-heap of the runs with rows left, ordered by their next row.
+        // Ties go to the earlier run, which keeps the sort stable.
+        let compare = &self.compare;
+        let less = |heads: &[Option<Keyed>], a: usize, b: usize| match (&heads[a], &heads[b]) {
+            (Some(x), Some(y)) => compare(&x.0, &y.0).then(a.cmp(&b)) == Ordering::Less,
+            _ => false,
+        };
+        let mut heap: Vec<usize> = (0..heads.len()).filter(|&i| heads[i].is_some()).collect();
+        for i in (0..heap.len() / 2).rev() {
+            sift_down(&mut heap, i, |a, b| less(&heads, a, b));
+        }
+
+        let total = self.runs.iter().map(Run::len).sum();
+        let mut rows = Vec::with_capacity(total);
+        while let Some(&run) = heap.first() {
+            if let Some((_, row)) = heads[run].take() {
+                rows.push(row);
+            }
+            heads[run] = readers[run].next(spill)?;
+            if heads[run].is_none() {
+                heap.swap_remove(0);
+            }
+            sift_down(&mut heap, 0, |a, b| less(&heads, a, b));
+        }
+        Ok(rows)
+    }
+
+    /// Sort the buffered rows and write them to the temporary file as a
+    /// new run.
+    fn write_run(&mut self) -> Result<()> {
+        let compare = &self.compare;
+        self.buffer.sort_by(|a, b| compare(&a.0, &b.0));
+        if self.spill.is_none() {
+            self.spill = Some(SpillFile::create("sort")?);
+        }
+        let spill = self.spill.as_mut().ok_or_else(|| HorizonError::Internal("no sort file".into()))?;
+        let mut run = RunWriter::default();
+        for (keys, row) in self.buffer.drain(..) {
+            run.push(spill, &keys, &row)?;
+        }
+        self.runs.push(run.finish(spill)?);
+        Ok(())
+    }
+}
+
+/// Move `heap[i]` down until neither child is less than it.
+fn sift_down(heap: &mut [usize], mut i: usize, less: impl Fn(usize, usize) -> bool) {
+    loop {
+        let mut smallest = i;
+        for child in [2 * i + 1, 2 * i + 2] {
+            if child < heap.len() && less(heap[child], heap[smallest]) {
+                smallest = child;
+            }
+        }
+        if smallest == i {
+            return;
+        }
+        heap.swap(i, smallest);
+        i = smallest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(desc: bool) -> OrderByItem {
+        OrderByItem { expr: Expr::Column { table: None, name: "k".into() }, desc }
+    }
+
+    /// Rows `[key, seq]` with `key` taking `distinct` values.
+    fn rows(count: i64, distinct: i64) -> Vec<Vec<Value>> {
+        (0..count).map(|seq| vec![Value::Integer(seq * 7919 % distinct), Value::Integer(seq)]).collect()
+    }
+
+    #[test]
+    fn external_sort_matches_the_in_memory_sort() {
+        for (distinct, desc) in [(1_000_000, false), (50, false), (50, true)] {
+            let order_by = [term(desc)];
+            let compare = |a: &[Value], b: &[Value]| compare_keys(&order_by, &Collations::new(), a, b);
+            let mut sorter = ExternalSorter::new(1000, compare);
+            for row in rows(100_000, distinct) {
+                sorter.push(vec![row[0].clone()], row).unwrap();
+            }
+            assert_eq!(sorter.run_count(), 100);
+            let sorted = sorter.finish().unwrap();
+
+            let mut expected = rows(100_000, distinct);
+            expected.sort_by(|a, b| compare(&a[..1], &b[..1]));
+            assert_eq!(sorted, expected);
+        }
+    }
+
+    #[test]
+    fn sorter_under_the_threshold_stays_in_memory() {
+        let order_by = [term(false)];
+        let mut sorter = ExternalSorter::new(1000, |a: &[Value], b: &[Value]| compare_keys(&order_by, &Collations::new(), a, b));
+        for row in rows(999, 10) {
+            sorter.push(vec![row[0].clone()], row).unwrap();
+        }
+        assert_eq!(sorter.run_count(), 0);
+        let sorted = sorter.finish().unwrap();
+        assert_eq!(sorted.len(), 999);
+        assert!(sorted.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
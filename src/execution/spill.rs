@@ -0,0 +1,184 @@
+//! Temporary files for operators whose input does not fit in memory.
+//!
+//! A [`SpillFile`] is a scratch file in the system temporary directory,
+//! read and written through its own [`Pager`] and removed when dropped.
+//! Operators write rows to it as *runs*: sequences of records, each
+//! holding a row together with a second list of values (sort keys, say),
+//! packed back to back across the pages of the run. Several runs can be
+//! written at the same time, each buffering one page.
+//!
+//! A record holds its length and the length of its keys (u32 BE each),
+//! followed by the keys and the row as written by [`serialize_row`].
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{HorizonError, Result};
+use crate::pager::{PageId, Pager};
+use crate::types::Value;
+use super::{deserialize_row, serialize_row};
+
+/// A row with the values stored alongside it.
+pub(super) type Keyed = (Vec<Value>, Vec<Value>);
+
+/// A temporary file holding runs, removed when dropped.
+pub(super) struct SpillFile {
+    pager: Pager,
+    path: PathBuf,
+}
+
+impl SpillFile {
+    /// Create an empty file in the system temporary directory, named after
+    /// the operator `kind` (`"sort"`, `"join"`) and the process.
+    pub(super) fn create(kind: &str) -> Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "horizon-{}-{}-{}.tmp",
+            kind,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let pager = Pager::open(&path, false)?;
+        Ok(SpillFile { pager, path })
+    }
+
+    /// Append `data` as a new page at the end of the file.
+    fn append_page(&mut self, data: &[u8]) -> Result<PageId> {
+        let page_id = self.pager.allocate_page()?;
+        self.pager.write_page(page_id, data)?;
+        Ok(page_id)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The pages of a run and the number of records on them.
+#[derive(Default)]
+pub(super) struct Run {
+    pages: Vec<PageId>,
+    rows: usize,
+}
+
+impl Run {
+    /// The number of records in the run.
+    pub(super) fn len(&self) -> usize {
+        self.rows
+    }
+}
+
+/// Appends records to a run, one page at a time.
+#[derive(Default)]
+pub(super) struct RunWriter {
+    run: Run,
+    page: Vec<u8>,
+}
+
+impl RunWriter {
+    /// Append a record holding `keys` and `row`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing a full page to `file`.
+    pub(super) fn push(&mut self, file: &mut SpillFile, keys: &[Value], row: &[Value]) -> Result<()> {
+        let keys = serialize_row(keys);
+        let row = serialize_row(row);
+        let mut record = Vec::with_capacity(8 + keys.len() + row.len());
+        record.extend_from_slice(&((4 + keys.len() + row.len()) as u32).to_be_bytes());
+        record.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+        record.extend_from_slice(&keys);
+        record.extend_from_slice(&row);
+
+        let page_size = file.pager.page_size();
+        let mut rest = record.as_slice();
+        while !rest.is_empty() {
+            let take = rest.len().min(page_size - self.page.len());
+            self.page.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.page.len() == page_size {
+                self.run.pages.push(file.append_page(&self.page)?);
+                self.page.clear();
+            }
+        }
+        self.run.rows += 1;
+        Ok(())
+    }
+
+    /// Write out the last, partly filled page and return the run.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing the page.
+    pub(super) fn finish(mut self, file: &mut SpillFile) -> Result<Run> {
+        if !self.page.is_empty() {
+            self.page.resize(file.pager.page_size(), 0);
+            self.run.pages.push(file.append_page(&self.page)?);
+        }
+        Ok(self.run)
+    }
+}
+
+/// Reads the records of a [`Run`] back in order.
+pub(super) struct RunReader<'a> {
+    pages: std::slice::Iter<'a, PageId>,
+    remaining: usize,
+    page: Vec<u8>,
+    offset: usize,
+}
+
+impl<'a> RunReader<'a> {
+    pub(super) fn new(run: &'a Run) -> Self {
+        RunReader { pages: run.pages.iter(), remaining: run.rows, page: Vec::new(), offset: 0 }
+    }
+
+    /// The next record of the run, or `None` at its end.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error reading `file`, and [`HorizonError::Internal`] for
+    /// a truncated record.
+    pub(super) fn next(&mut self, file: &SpillFile) -> Result<Option<Keyed>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let len = read_u32(&self.read(file, 4)?, 0) as usize;
+        let record = self.read(file, len)?;
+        let (keys, row) = record[4..].split_at(read_u32(&record, 0) as usize);
+        Ok(Some((read_values(keys)?, read_values(row)?)))
+    }
+
+    /// Read the next `len` bytes of the run, continuing onto the following
+    /// pages as needed.
+    fn read(&mut self, file: &SpillFile, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            if self.offset == self.page.len() {
+                let page_id = self.pages.next().ok_or_else(|| HorizonError::Internal("truncated spill run".into()))?;
+                self.page = file.pager.read_page(*page_id)?;
+                self.offset = 0;
+            }
+            let take = (len - bytes.len()).min(self.page.len() - self.offset);
+            bytes.extend_from_slice(&self.page[self.offset..self.offset + take]);
+            self.offset += take;
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decode values written by [`serialize_row`].
+fn read_values(data: &[u8]) -> Result<Vec<Value>> {
+    let count = match data {
+        [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+        _ => return Err(HorizonError::Internal("truncated spill record".into())),
+    };
+    deserialize_row(data, count)
+}
+
+fn read_u32(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
@@ -0,0 +1,224 @@
+//! Incremental SELECT execution for Horizon DB.
+//!
+//! [`SelectStream`] yields result rows one at a time. Simple single-table
+//! scans (no joins, grouping, aggregates, ordering, DISTINCT, or compound
+//! operators) are evaluated lazily against a [`BTreeCursor`], so only the
+//! current row is held in memory. Every other query falls back to the
+//! materializing executor and streams out of the collected result.
+
+use std::sync::Arc;
+
+use super::{
+    deserialize_row, eval_const_expr, eval_expr_with_ctx, execute_read_query, fill_virtual_columns,
+    project_row_with_ctx, resolve_column_names, select_has_aggregate,
+    select_has_window_function, select_read_columns, table_has_virtual_columns, try_indexed_scan,
+};
+use crate::btree::{BTree, BTreeCursor, BTreeEntry};
+use crate::buffer::BufferPool;
+use crate::catalog::{Catalog, TableInfo};
+use crate::error::Result;
+use crate::execution::fts5;
+use crate::sql::ast::*;
+use crate::{QueryResult, Row};
+
+/// A query result that produces its rows on demand.
+pub struct SelectStream {
+    columns: Arc<Vec<String>>,
+    source: StreamSource,
+}
+
+enum StreamSource {
+    /// Rows already produced by the materializing executor.
+    Materialized(std::vec::IntoIter<Row>),
+    /// A lazily-evaluated single-table scan.
+    Scan(Box<TableScan>),
+}
+
+/// State for a lazily-evaluated single-table scan.
+struct TableScan {
+    select: SelectStatement,
+    table: TableInfo,
+    tree: BTree,
+    entries: ScanEntries,
+    has_virtual: bool,
+    /// Matching rows still to be skipped for OFFSET.
+    skip: usize,
+    /// Rows still to be returned for LIMIT (`None` = unlimited).
+    remaining: Option<usize>,
+}
+
+/// Where a [`TableScan`] gets its raw B+Tree entries from.
+enum ScanEntries {
+    /// Full scan, one leaf entry at a time.
+    Cursor(BTreeCursor),
+    /// Entries pre-selected by a primary-key seek or an index scan.
+    Buffered(std::vec::IntoIter<BTreeEntry>),
+}
+
+impl SelectStream {
+    /// A stream with no columns and no rows.
+    pub fn empty() -> Self {
+        SelectStream {
+            columns: Arc::new(vec![]),
+            source: StreamSource::Materialized(Vec::new().into_iter()),
+        }
+    }
+
+    /// A stream over rows that have already been produced.
+    pub fn materialized(result: QueryResult) -> Self {
+        SelectStream {
+            columns: result.columns,
+            source: StreamSource::Materialized(result.rows.into_iter()),
+        }
+    }
+
+    /// The result column names.
+    pub fn columns(&self) -> &Arc<Vec<String>> {
+        &self.columns
+    }
+
+    /// Produce the next row, or `None` when the result is exhausted.
+    ///
+    /// After an error is returned the stream is finished.
+    pub fn next_row(
+        &mut self,
+        pool: &BufferPool,
+        catalog: &Catalog,
+    ) -> Option<Result<Row>> {
+        match &mut self.source {
+            StreamSource::Materialized(rows) => rows.next().map(Ok),
+            StreamSource::Scan(scan) => {
+                let result = scan.next_values(pool, catalog).transpose()?;
+                if result.is_err() {
+                    scan.remaining = Some(0);
+                }
+                Some(result.map(|values| Row { columns: self.columns.clone(), values }))
+            }
+        }
+    }
+}
+
+impl TableScan {
+    fn next_values(
+        &mut self,
+        pool: &BufferPool,
+        catalog: &Catalog,
+    ) -> Result<Option<Vec<crate::types::Value>>> {
+        loop {
+            if self.remaining == Some(0) {
+                return Ok(None);
+            }
+            let entry = match &mut self.entries {
+                ScanEntries::Cursor(cursor) => self.tree.next(pool, cursor)?,
+                ScanEntries::Buffered(entries) => entries.next(),
+            };
+            let entry = match entry {
+                Some(e) => e,
+                None => return Ok(None),
+            };
+
+            let table = &self.table;
+            let mut row_values = deserialize_row(&entry.value, table.columns.len())?;
+            if self.has_virtual {
+                fill_virtual_columns(&mut row_values, table)?;
+            }
+            if let Some(ref where_clause) = self.select.where_clause {
+                let result = eval_expr_with_ctx(where_clause, &row_values, &table.columns, table, pool, catalog)?;
+                if !result.to_bool() {
+                    continue;
+                }
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            if let Some(ref mut n) = self.remaining {
+                *n -= 1;
+            }
+            return project_row_with_ctx(&self.select.columns, &row_values, table, pool, catalog)
+                .map(Some);
+        }
+    }
+}
+
+/// Open a stream over the rows produced by a read-only query statement.
+///
+/// Accepts the same statements as [`execute_read_query`].
+pub fn open_select_stream(stmt: &Statement, pool: &BufferPool, catalog: &Catalog) -> Result<SelectStream> {
+    if let Statement::Select(select) = stmt {
+        if let Some(table) = streamable_table(select, catalog) {
+            return open_table_scan(select, table, pool, catalog);
+        }
+    }
+    execute_read_query(stmt, pool, catalog).map(SelectStream::materialized)
+}
+
+/// Return the table scanned by `select` if it can be evaluated row by row.
+fn streamable_table(select: &SelectStatement, catalog: &Catalog) -> Option<TableInfo> {
+    let simple = select.ctes.is_empty()
+        && select.compound.is_empty()
+        && !select.distinct
+        && select.group_by.is_empty()
+        && select.having.is_none()
+        && select.order_by.is_empty()
+        && !select_has_aggregate(&select.columns)
+        && !select_has_window_function(&select.columns);
+    if !simple {
+        return None;
+    }
+    let name = match &select.from {
+        Some(FromClause::Table { name, .. }) => name,
+        _ => return None,
+    };
+    if fts5::fts5_table_exists(name) || catalog.rtree_exists(name) {
+        return None;
+    }
+    catalog.get_table(name).ok().cloned()
+}
+
+fn open_table_scan(
+    select: &SelectStatement,
+    table: TableInfo,
+    pool: &BufferPool,
+    catalog: &Catalog,
+) -> Result<SelectStream> {
+    let columns = Arc::new(resolve_column_names(&select.columns, &table)?);
+    let tree = BTree::open(table.root_page);
+
+    let indexed = match &select.where_clause {
+        Some(where_expr) => {
+            let read_columns = select_read_columns(select, &table);
+            try_indexed_scan(where_expr, read_columns.as_deref(), &table.name, &table, &tree, pool, catalog)?
+        }
+        None => None,
+    };
+    let entries = match indexed {
+        Some(entries) => ScanEntries::Buffered(entries.into_iter()),
+        None => ScanEntries::Cursor(tree.cursor(pool)?),
+    };
+
+    let skip = select
+        .offset
+        .as_ref()
+        .map(|e| eval_const_expr(e).as_integer().unwrap_or(0).max(0) as usize)
+        .unwrap_or(0);
+    let remaining = select
+        .limit
+        .as_ref()
+        .and_then(|e| eval_const_expr(e).as_integer())
+        .filter(|n| *n >= 0)
+        .map(|n| n as usize);
+
+    Ok(SelectStream {
+        columns,
+        source: StreamSource::Scan(Box::new(TableScan {
+            select: select.clone(),
+            has_virtual: table_has_virtual_columns(&table),
+            table,
+            tree,
+            entries,
+            skip,
+            remaining,
+        })),
+    })
+}
@@ -0,0 +1,289 @@
+//! String scalar functions for Horizon DB.
+//!
+//! The caller evaluates the arguments; this module only sees their values.
+//! Lengths and positions count characters (Unicode scalar values), not
+//! bytes, so multibyte text is never split inside a character:
+//! `LENGTH('こんにちは')` is 5. `OCTET_LENGTH` gives the length in bytes, and
+//! the length of a blob is always its size in bytes. Numbers passed where
+//! text is expected are used in their text form, and a NULL argument yields
+//! NULL.
+
+use super::check_arity;
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+
+/// The largest string, in bytes, these functions will build (SQLite's
+/// default `SQLITE_MAX_LENGTH`).
+const MAX_STRING_LENGTH: usize = 1_000_000_000;
+
+/// Whether `name` (upper case) is a function handled by [`eval_string_function`].
+pub(super) fn is_string_function(name: &str) -> bool {
+    matches!(
+        name,
+        "LENGTH"
+            | "LEN"
+            | "OCTET_LENGTH"
+            | "SUBSTR"
+            | "SUBSTRING"
+            | "INSTR"
+            | "TRIM"
+            | "LTRIM"
+            | "RTRIM"
+            | "LPAD"
+            | "RPAD"
+            | "REPEAT"
+            | "REVERSE"
+            | "SPLIT_PART"
+    )
+}
+
+/// The text form of a string argument, or None for NULL and blobs.
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Text(s) => Some(s.clone()),
+        Value::Integer(_) | Value::Real(_) => Some(value.to_string()),
+        Value::Null | Value::Blob(_) => None,
+    }
+}
+
+/// A length or count argument; reals are truncated toward zero.
+fn count(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => Some(*i),
+        Value::Real(r) => Some(*r as i64),
+        Value::Text(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn check_length(bytes: usize) -> Result<()> {
+    if bytes > MAX_STRING_LENGTH {
+        return Err(HorizonError::Overflow("string or blob too big".into()));
+    }
+    Ok(())
+}
+
+/// The characters `SUBSTR` takes from a string of `len` characters, as
+/// the index of the first one and their count. As in SQLite, a negative
+/// `start` counts from the end, a `start` of 0 is the position before the
+/// first character, and a negative `length` takes the characters before
+/// `start`. Without a `length` the rest of the string is taken.
+fn substr_range(len: i64, start: i64, length: Option<i64>) -> (usize, usize) {
+    let mut n = length.map_or(i64::MAX, i64::saturating_abs);
+    let mut first = start;
+    if first < 0 {
+        first = first.saturating_add(len);
+        if first < 0 {
+            n = n.saturating_add(first).max(0);
+            first = 0;
+        }
+    } else if first > 0 {
+        first -= 1;
+    } else if n > 0 {
+        n -= 1;
+    }
+    if length.is_some_and(|length| length < 0) {
+        first -= n;
+        if first < 0 {
+            n += first;
+            first = 0;
+        }
+    }
+    let first = first.min(len);
+    (first as usize, n.min(len - first) as usize)
+}
+
+/// Evaluate the string function `name` (upper case) on `args`.
+pub(super) fn eval_string_function(name: &str, args: &[Value]) -> Result<Value> {
+    let arity = match name {
+        "SUBSTR" | "SUBSTRING" | "LPAD" | "RPAD" => 2..=3,
+        "TRIM" | "LTRIM" | "RTRIM" => 1..=2,
+        "INSTR" | "REPEAT" => 2..=2,
+        "SPLIT_PART" => 3..=3,
+        _ => 1..=1,
+    };
+    check_arity(name, args, arity)?;
+    // The length of a blob is its size in bytes
+    if let ("LENGTH" | "LEN" | "OCTET_LENGTH", Value::Blob(b)) = (name, &args[0]) {
+        return Ok(Value::Integer(b.len() as i64));
+    }
+    let s = match text(&args[0]) {
+        Some(s) => s,
+        None => return Ok(Value::Null),
+    };
+
+    match name {
+        "LENGTH" | "LEN" => Ok(Value::Integer(s.chars().count() as i64)),
+        "OCTET_LENGTH" => Ok(Value::Integer(s.len() as i64)),
+        "SUBSTR" | "SUBSTRING" => {
+            let (start, length) = match (count(&args[1]), args.get(2).map(count)) {
+                (Some(start), None) => (start, None),
+                (Some(start), Some(Some(length))) => (start, Some(length)),
+                _ => return Ok(Value::Null),
+            };
+            let (first, n) = substr_range(s.chars().count() as i64, start, length);
+            Ok(Value::Text(s.chars().skip(first).take(n).collect()))
+        }
+        "INSTR" => {
+            let needle = match text(&args[1]) {
+                Some(needle) => needle,
+                None => return Ok(Value::Null),
+            };
+            // The byte offset of the match, as a 1-based character position
+            let position = s.find(&needle).map_or(0, |i| s[..i].chars().count() as i64 + 1);
+            Ok(Value::Integer(position))
+        }
+        "TRIM" | "LTRIM" | "RTRIM" => {
+            // The characters to remove, spaces by default
+            let set: Vec<char> = match args.get(1).map(text) {
+                None => vec![' '],
+                Some(Some(set)) => set.chars().collect(),
+                Some(None) => return Ok(Value::Null),
+            };
+            let trimmed = match name {
+                "LTRIM" => s.trim_start_matches(set.as_slice()),
+                "RTRIM" => s.trim_end_matches(set.as_slice()),
+                _ => s.trim_matches(set.as_slice()),
+            };
+            Ok(Value::Text(trimmed.to_string()))
+        }
+        "LPAD" | "RPAD" => {
+            let len = match count(&args[1]) {
+                Some(n) => n.max(0) as usize,
+                None => return Ok(Value::Null),
+            };
+            let pad = match args.get(2).map(text) {
+                None => " ".to_string(),
+                Some(Some(pad)) => pad,
+                Some(None) => return Ok(Value::Null),
+            };
+            check_length(len)?;
+            let chars = s.chars().count();
+            // Longer strings are cut to `len`; so are strings that cannot
+            // be padded because `pad` is empty.
+            if chars >= len || pad.is_empty() {
+                return Ok(Value::Text(s.chars().take(len).collect()));
+            }
+            let fill: String = pad.chars().cycle().take(len - chars).collect();
+            Ok(Value::Text(if name == "LPAD" { fill + &s } else { s + &fill }))
+        }
+        "REPEAT" => {
+            let n = match count(&args[1]) {
+                Some(n) => n.max(0) as usize,
+                None => return Ok(Value::Null),
+            };
+            check_length(s.len().saturating_mul(n))?;
+            Ok(Value::Text(s.repeat(n)))
+        }
+        "REVERSE" => Ok(Value::Text(s.chars().rev().collect())),
+        "SPLIT_PART" => {
+            let (delimiter, n) = match (text(&args[1]), count(&args[2])) {
+                (Some(d), Some(n)) => (d, n),
+                _ => return Ok(Value::Null),
+            };
+            if delimiter.is_empty() {
+                return Err(HorizonError::InvalidSql("split_part() delimiter must not be empty".into()));
+            }
+            if n == 0 {
+                return Err(HorizonError::InvalidSql("split_part() part number must not be zero".into()));
+            }
+            // Negative part numbers count from the end
+            let part = if n > 0 {
+                s.split(delimiter.as_str()).nth(n as usize - 1)
+            } else {
+                s.rsplit(delimiter.as_str()).nth(n.unsigned_abs() as usize - 1)
+            };
+            Ok(Value::Text(part.unwrap_or("").to_string()))
+        }
+        _ => Err(HorizonError::NotImplemented(format!("function: {}", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        eval_string_function(name, args).unwrap()
+    }
+
+    fn t(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    #[test]
+    fn lengths_and_positions_count_characters() {
+        assert_eq!(call("LENGTH", &[t("こんにちは")]), Value::Integer(5));
+        assert_eq!(call("OCTET_LENGTH", &[t("こんにちは")]), Value::Integer(15));
+        assert_eq!(call("LENGTH", &[t("👍🏽!")]), Value::Integer(3));
+        assert_eq!(call("LENGTH", &[Value::Integer(-12)]), Value::Integer(3));
+        assert_eq!(call("LENGTH", &[Value::Blob(vec![0xC3, 0xA9])]), Value::Integer(2));
+        assert_eq!(call("LENGTH", &[Value::Null]), Value::Null);
+
+        assert_eq!(call("SUBSTR", &[t("café"), Value::Integer(3)]), t("fé"));
+        assert_eq!(call("SUBSTRING", &[t("a😀b"), Value::Integer(2), Value::Integer(1)]), t("😀"));
+        assert_eq!(call("SUBSTR", &[t("abc"), Value::Integer(0), Value::Integer(-1)]), t(""));
+        // A negative start counts characters from the end
+        assert_eq!(call("SUBSTR", &[t("😀abc"), Value::Integer(-2)]), t("bc"));
+        assert_eq!(call("SUBSTR", &[t("😀abc"), Value::Integer(-9), Value::Integer(6)]), t("😀"));
+        // Start 0 is the position before the first character
+        assert_eq!(call("SUBSTR", &[t("abc"), Value::Integer(0), Value::Integer(2)]), t("a"));
+        // A negative length takes the characters before the start
+        assert_eq!(call("SUBSTR", &[t("abc"), Value::Integer(2), Value::Integer(-1)]), t("a"));
+        assert_eq!(call("SUBSTR", &[t("a😀c"), Value::Integer(4), Value::Integer(-2)]), t("😀c"));
+        assert_eq!(call("SUBSTR", &[t("abc"), Value::Integer(-1), Value::Integer(-5)]), t("ab"));
+        assert_eq!(call("SUBSTR", &[t("abc"), Value::Integer(9), Value::Integer(-2)]), t(""));
+
+        assert_eq!(call("INSTR", &[t("naïve"), t("ï")]), Value::Integer(3));
+        assert_eq!(call("INSTR", &[t("😀😃x"), t("x")]), Value::Integer(3));
+        assert_eq!(call("INSTR", &[t("abc"), t("z")]), Value::Integer(0));
+        assert_eq!(call("INSTR", &[t("abc"), Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn trimming() {
+        assert_eq!(call("TRIM", &[t("  hi  ")]), t("hi"));
+        assert_eq!(call("LTRIM", &[t("  hi  ")]), t("hi  "));
+        assert_eq!(call("RTRIM", &[t("  hi  ")]), t("  hi"));
+        assert_eq!(call("TRIM", &[t("ééxé"), t("é")]), t("x"));
+        assert_eq!(call("RTRIM", &[t("ok✨🎉"), t("🎉✨")]), t("ok"));
+        assert_eq!(call("LTRIM", &[t("xyzzy"), t("yx")]), t("zzy"));
+        assert_eq!(call("TRIM", &[t("abc"), Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(call("LPAD", &[t("5"), Value::Integer(3), t("0")]), t("005"));
+        assert_eq!(call("RPAD", &[t("ab"), Value::Integer(7), t("xy")]), t("abxyxyx"));
+        assert_eq!(call("LPAD", &[t("hello"), Value::Integer(2)]), t("he"));
+        assert_eq!(call("RPAD", &[Value::Integer(7), Value::Integer(3)]), t("7  "));
+        assert_eq!(call("LPAD", &[t("é"), Value::Integer(3), t("ü")]), t("üüé"));
+        assert_eq!(call("LPAD", &[t("x"), Value::Integer(-1), t("0")]), t(""));
+        assert_eq!(call("LPAD", &[t("x"), Value::Integer(4), Value::Null]), Value::Null);
+    }
+
+    #[test]
+    fn repeat_and_reverse() {
+        assert_eq!(call("REPEAT", &[t("ab"), Value::Integer(3)]), t("ababab"));
+        assert_eq!(call("REPEAT", &[t("ab"), Value::Integer(0)]), t(""));
+        assert_eq!(call("REVERSE", &[t("こんにちは")]), t("はちにんこ"));
+        assert_eq!(call("REVERSE", &[Value::Null]), Value::Null);
+        assert!(matches!(
+            eval_string_function("REPEAT", &[t("ab"), Value::Integer(i64::MAX)]),
+            Err(HorizonError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn split_part() {
+        let split = |n: i64| call("SPLIT_PART", &[t("a,b,c"), t(","), Value::Integer(n)]);
+        assert_eq!(split(2), t("b"));
+        assert_eq!(split(4), t(""));
+        assert_eq!(split(-1), t("c"));
+        assert_eq!(split(-4), t(""));
+        assert_eq!(call("SPLIT_PART", &[t("a::b"), t("::"), Value::Integer(2)]), t("b"));
+        assert_eq!(call("SPLIT_PART", &[t("abc"), t(";"), Value::Integer(1)]), t("abc"));
+        assert!(eval_string_function("SPLIT_PART", &[t("abc"), t(""), Value::Integer(1)]).is_err());
+        assert!(eval_string_function("SPLIT_PART", &[t("abc"), t(","), Value::Integer(0)]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Built-in table-valued functions for Horizon DB.
+//!
+//! A table-valued function appears in a FROM clause like a table, e.g.
+//! `SELECT value FROM generate_series(1, 10)`. Its rows are produced
+//! without touching storage and are then read like a CTE named after the
+//! function (or its alias), so they can be filtered, aggregated, and joined
+//! with ordinary tables.
+
+use super::{json, parse_timestring, DateTime, RowSet};
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+
+/// Whether `name` is a built-in table-valued function.
+pub(super) fn is_table_function(name: &str) -> bool {
+    ["generate_series", "json_each", "json_tree"]
+        .iter()
+        .any(|f| name.eq_ignore_ascii_case(f))
+}
+
+/// Produce the rows of the table-valued function `name` for `args`.
+pub(super) fn table_function_rows(name: &str, args: &[Value]) -> Result<RowSet> {
+    if name.eq_ignore_ascii_case("generate_series") {
+        generate_series(args)
+    } else if name.eq_ignore_ascii_case("json_each") {
+        json::json_each_rows(args, false)
+    } else if name.eq_ignore_ascii_case("json_tree") {
+        json::json_each_rows(args, true)
+    } else {
+        Err(HorizonError::NotImplemented(format!("table function: {}", name)))
+    }
+}
+
+/// `generate_series(start, stop[, step])`: the integers from `start` to
+/// `stop` inclusive, or, when `start` and `stop` are dates, the dates
+/// between them with `step` counted in days. `step` defaults to 1 and may
+/// be negative to count down.
+fn generate_series(args: &[Value]) -> Result<RowSet> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(HorizonError::InvalidSql(
+            "generate_series takes 2 or 3 arguments".into(),
+        ));
+    }
+    let step = match args.get(2) {
+        None => 1,
+        Some(v) => v.as_integer().ok_or_else(|| {
+            HorizonError::TypeError(format!("generate_series step must be an integer, got {}", v))
+        })?,
+    };
+    if step == 0 {
+        return Err(HorizonError::InvalidSql("generate_series step must not be zero".into()));
+    }
+
+    let mut rows = Vec::new();
+    match (&args[0], &args[1]) {
+        (Value::Text(start), Value::Text(stop)) => {
+            let (mut day, stop) = match (parse_timestring(start), parse_timestring(stop)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => {
+                    return Err(HorizonError::TypeError(format!(
+                        "generate_series expects integers or dates, got '{}' and '{}'",
+                        start, stop
+                    )))
+                }
+            };
+            let key = |d: &DateTime| (d.year, d.month, d.day);
+            while (step > 0 && key(&day) <= key(&stop)) || (step < 0 && key(&day) >= key(&stop)) {
+                rows.push(vec![Value::Text(day.format_date())]);
+                day.add_days(step);
+            }
+        }
+        (start, stop) => {
+            let (mut n, stop) = match (start.as_integer(), stop.as_integer()) {
+                (Some(a), Some(b)) => (a, b),
+                _ => {
+                    return Err(HorizonError::TypeError(format!(
+                        "generate_series expects integers or dates, got {} and {}",
+                        start, stop
+                    )))
+                }
+            };
+            while (step > 0 && n <= stop) || (step < 0 && n >= stop) {
+                rows.push(vec![Value::Integer(n)]);
+                n = match n.checked_add(step) {
+                    Some(next) => next,
+                    None => break,
+                };
+            }
+        }
+    }
+    Ok((vec!["value".to_string()], rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(args: &[Value]) -> Vec<Value> {
+        generate_series(args).unwrap().1.into_iter().map(|mut r| r.remove(0)).collect()
+    }
+
+    #[test]
+    fn integer_series() {
+        assert_eq!(series(&[Value::Integer(1), Value::Integer(3)]), vec![1.into(), 2.into(), 3.into()]);
+        assert_eq!(
+            series(&[Value::Integer(10), Value::Integer(1), Value::Integer(-4)]),
+            vec![10.into(), 6.into(), 2.into()]
+        );
+        assert!(series(&[Value::Integer(5), Value::Integer(1)]).is_empty());
+        assert_eq!(series(&[Value::Integer(i64::MAX - 1), Value::Integer(i64::MAX)]).len(), 2);
+    }
+
+    #[test]
+    fn date_series() {
+        let dates = series(&[Value::Text("2024-02-27".into()), Value::Text("2024-03-01".into())]);
+        let expected: Vec<Value> = ["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01"]
+            .iter()
+            .map(|&d| d.into())
+            .collect();
+        assert_eq!(dates, expected);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(generate_series(&[Value::Integer(1)]).is_err());
+        assert!(generate_series(&[Value::Integer(1), Value::Integer(2), Value::Integer(0)]).is_err());
+        assert!(generate_series(&[Value::Text("x".into()), Value::Text("y".into())]).is_err());
+    }
+}
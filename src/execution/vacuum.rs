@@ -0,0 +1,124 @@
+//! `VACUUM` and `VACUUM INTO`.
+//!
+//! Both rebuild the database as a compacted copy: the B+Tree of every
+//! table is copied with [`BTree::copy_compacted`], which fills each page
+//! and lays the trees out one after another, followed by a schema table
+//! pointing at the new roots. Free-list pages, the pages of dropped
+//! tables, and the room left by deleted rows are not copied.
+//!
+//! `VACUUM INTO 'path'` writes the copy to a new file. Plain `VACUUM` also
+//! copies the indexes and R-tree tables, builds the copy in a scratch file
+//! beside the database (or in memory for an in-memory database), then
+//! writes its pages over the start of the database and truncates the rest.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::btree::BTree;
+use crate::buffer::BufferPool;
+use crate::catalog::Catalog;
+use crate::error::{HorizonError, Result};
+use crate::pager::{PageId, Pager};
+
+/// Write a compacted copy of the database to a new file at `path`.
+///
+/// # Errors
+///
+/// Returns [`HorizonError::InvalidSql`] if a non-empty file already exists
+/// at `path`, and any I/O error; a partly written copy is removed.
+pub fn vacuum_into(path: &Path, pool: &BufferPool, catalog: &Catalog) -> Result<()> {
+    if std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0) {
+        return Err(HorizonError::InvalidSql(format!(
+            "output file already exists: {}",
+            path.display()
+        )));
+    }
+    let result = Pager::open_with_page_size(path, false, Some(pool.page_size())).and_then(|pager| {
+        let mut copy = BufferPool::new(pager, None, pool.capacity())?;
+        let mut roots = catalog
+            .list_tables()
+            .into_iter()
+            .map(|name| Ok(catalog.get_table(name)?.root_page))
+            .collect::<Result<Vec<_>>>()?;
+        roots.sort_unstable();
+        write_compacted(pool, catalog, &roots, &mut copy).map(drop)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// Rewrite the database in place as a compacted copy of itself and point
+/// `catalog` at the new root pages.
+pub fn vacuum(pool: &mut BufferPool, catalog: &mut Catalog) -> Result<()> {
+    let scratch = pool.pager().path().map(|path| path.with_extension("hdb-vacuum"));
+    let pager = match &scratch {
+        Some(path) => {
+            // Left over from an interrupted VACUUM
+            let _ = std::fs::remove_file(path);
+            Pager::open_with_page_size(path, false, Some(pool.page_size()))?
+        }
+        None => Pager::open_memory(Some(pool.page_size()))?,
+    };
+    let result = rewrite_from_copy(pool, catalog, pager);
+    if let Some(path) = scratch {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn rewrite_from_copy(pool: &mut BufferPool, catalog: &mut Catalog, scratch: Pager) -> Result<()> {
+    let mut copy = BufferPool::new(scratch, None, pool.capacity())?;
+    let mut roots: Vec<PageId> = catalog.tree_roots_mut().map(|root| *root).collect();
+    roots.sort_unstable();
+    let moved = write_compacted(pool, catalog, &roots, &mut copy)?;
+
+    let page_count = copy.pager().page_count();
+    if page_count > pool.pager().page_count() {
+        return Err(HorizonError::Internal(
+            "compacted copy is larger than the database".into(),
+        ));
+    }
+    for page_id in 1..page_count {
+        let data = copy.read_page(page_id)?;
+        pool.get_page_mut(page_id)?.copy_from_slice(&data);
+        pool.unpin(page_id);
+    }
+    pool.flush_all()?;
+    pool.pager_mut().set_schema_root(copy.pager().schema_root())?;
+    for root in catalog.tree_roots_mut() {
+        if let Some(&new_root) = moved.get(root) {
+            *root = new_root;
+        }
+    }
+    pool.truncate(page_count)
+}
+
+/// Write the trees rooted at `roots` into the empty database `copy`,
+/// followed by a schema table describing the tables with their new root
+/// pages. Returns the new root of each tree, keyed by its old root.
+fn write_compacted(
+    pool: &BufferPool,
+    catalog: &Catalog,
+    roots: &[PageId],
+    copy: &mut BufferPool,
+) -> Result<HashMap<PageId, PageId>> {
+    let mut moved = HashMap::new();
+    for &root in roots {
+        let tree = BTree::open(root).copy_compacted(pool, copy)?;
+        moved.insert(root, tree.root_page());
+    }
+
+    let schema = BTree::create(copy)?;
+    copy.pager_mut().set_schema_root(schema.root_page())?;
+    copy.pager_mut().set_user_version(pool.pager().get_user_version())?;
+    let mut copied = Catalog::new();
+    for name in catalog.list_tables() {
+        let mut table = catalog.get_table(name)?.clone();
+        table.root_page = moved[&table.root_page];
+        copied.create_table(copy, table)?;
+    }
+    copy.flush_all()?;
+    Ok(moved)
+}
@@ -28,15 +28,26 @@ pub(super) fn execute_create_trigger(ct: &CreateTriggerStatement, catalog: &mut
         TriggerTiming::After => TriggerTimingKind::After,
         TriggerTiming::InsteadOf => TriggerTimingKind::InsteadOf,
     };
+    // INSTEAD OF triggers replace writes to views; tables take BEFORE and AFTER
+    if timing == TriggerTimingKind::InsteadOf && catalog.table_exists(&ct.table) {
+        return Err(HorizonError::InvalidSql(format!(
+            "cannot create INSTEAD OF trigger on table: {}", ct.table
+        )));
+    }
+    if timing != TriggerTimingKind::InsteadOf && catalog.view_exists(&ct.table) {
+        return Err(HorizonError::InvalidSql(format!(
+            "cannot create BEFORE or AFTER trigger on view: {}", ct.table
+        )));
+    }
     let event = match ct.event {
         TriggerEvent::Insert => TriggerEventKind::Insert,
         TriggerEvent::Update(_) => TriggerEventKind::Update,
         TriggerEvent::Delete => TriggerEventKind::Delete,
     };
-    let body_sql: Vec<String> = ct.body.iter().map(|s| statement_to_sql(s)).collect();
+    let body_sql: Vec<String> = ct.body.iter().map(statement_to_sql).collect();
     catalog.create_trigger(TriggerInfo {
         name: ct.name.clone(), timing, event, table: ct.table.clone(),
-        for_each_row: ct.for_each_row, body_sql,
+        for_each_row: ct.for_each_row, when: ct.when.clone(), body_sql,
     })?;
     Ok(0)
 }
@@ -47,23 +58,72 @@ pub(super) fn execute_drop_trigger(dt: &DropTriggerStatement, catalog: &mut Cata
     Ok(0)
 }
 
-pub(super) fn fire_triggers(
+/// A row a trigger body refers to as `NEW` or `OLD`.
+#[derive(Clone, Copy)]
+pub(super) struct TriggerRow<'a> {
+    pub columns: &'a [String],
+    pub values: &'a [Value],
+}
+
+/// Fire the BEFORE or AFTER triggers of `table` for one row written through
+/// `tree`, with `new` and `old` as the row's values after and before the
+/// change. A trigger body may write to `table` itself, so the tree's root is
+/// saved to the catalog first and `tree` is reopened afterwards.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn fire_table_row_triggers(
+    table: &TableInfo,
+    event: TriggerEventKind,
+    timing: TriggerTimingKind,
+    new: Option<&[Value]>,
+    old: Option<&[Value]>,
+    tree: &mut BTree,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+) -> Result<()> {
+    if catalog.get_triggers_for_table(&table.name, &event, &timing).is_empty() {
+        return Ok(());
+    }
+    let columns: Vec<String> = table.columns.iter().map(|c| c.name.clone()).collect();
+    let row = |values| TriggerRow { columns: &columns, values };
+    sync_table_root(&table.name, tree, pool, catalog)?;
+    fire_row_triggers(&table.name, &event, &timing, new.map(row), old.map(row), pool, catalog, txn_mgr)?;
+    *tree = BTree::open(catalog.get_table(&table.name)?.root_page);
+    Ok(())
+}
+
+/// Fire the matching triggers of `table_name` with `new` and `old` bound
+/// as the `NEW` and `OLD` rows of their `WHEN` conditions and bodies.
+/// Returns how many triggers fired.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn fire_row_triggers(
     table_name: &str,
     event: &TriggerEventKind,
     timing: &TriggerTimingKind,
+    new: Option<TriggerRow>,
+    old: Option<TriggerRow>,
     pool: &mut BufferPool,
     catalog: &mut Catalog,
     txn_mgr: &mut TransactionManager,
-) -> Result<()> {
+) -> Result<usize> {
     let triggers: Vec<TriggerInfo> = catalog
         .get_triggers_for_table(table_name, event, timing)
         .into_iter()
         .cloned()
         .collect();
+    let mut fired = 0;
     for trigger in &triggers {
+        if let Some(ref when) = trigger.when {
+            let mut when = when.clone();
+            params::walk_expr(&mut when, &mut |expr| bind_row_reference(expr, new, old))?;
+            if !eval_expr_dynamic(&when, &[], &[])?.to_bool() {
+                continue;
+            }
+        }
         for body_sql in &trigger.body_sql {
-            let stmts = Parser::parse(body_sql)?;
-            for stmt in &stmts {
+            let mut stmts = Parser::parse(body_sql)?;
+            for stmt in &mut stmts {
+                params::walk_statement(stmt, &mut |expr| bind_row_reference(expr, new, old))?;
                 match stmt {
                     Statement::Select(_) => {
                         let _ = super::execute_query(stmt, pool, catalog, txn_mgr)?;
@@ -74,10 +134,134 @@ pub(super) fn fire_triggers(
                 }
             }
         }
+        fired += 1;
+    }
+    Ok(fired)
+}
+
+/// Replace a `NEW.column` or `OLD.column` reference in a trigger's `WHEN`
+/// condition or body with the value of the bound row.
+fn bind_row_reference(expr: &mut Expr, new: Option<TriggerRow>, old: Option<TriggerRow>) -> Result<()> {
+    if let Expr::Column { table: Some(qualifier), name } = expr {
+        let row = if qualifier.eq_ignore_ascii_case("new") {
+            new
+        } else if qualifier.eq_ignore_ascii_case("old") {
+            old
+        } else {
+            return Ok(());
+        };
+        let row = row.ok_or_else(|| HorizonError::InvalidSql(format!(
+            "no {} row is available in this trigger", qualifier.to_uppercase()
+        )))?;
+        let value = find_column_dynamic(name, row.values, row.columns)?;
+        *expr = Expr::Literal(params::value_to_literal(&value));
     }
     Ok(())
 }
 
+// ---- WRITES TO VIEWS ----
+
+/// Execute an INSERT, UPDATE, or DELETE whose target is a view by firing
+/// the view's INSTEAD OF triggers once for each affected row, in place of
+/// the write. Returns the number of rows.
+pub(super) fn execute_view_write(
+    stmt: &Statement,
+    view: &ViewInfo,
+    pool: &mut BufferPool,
+    catalog: &mut Catalog,
+    txn_mgr: &mut TransactionManager,
+) -> Result<usize> {
+    let event = match stmt {
+        Statement::Insert(_) => TriggerEventKind::Insert,
+        Statement::Update(_) => TriggerEventKind::Update,
+        Statement::Delete(_) => TriggerEventKind::Delete,
+        _ => return Err(HorizonError::Internal("execute_view_write requires a DML statement".into())),
+    };
+    if catalog.get_triggers_for_table(&view.name, &event, &TriggerTimingKind::InsteadOf).is_empty() {
+        return Err(HorizonError::InvalidSql(format!(
+            "cannot modify {} because it is a view", view.name
+        )));
+    }
+    let (columns, rows) = view_rows(view, pool, catalog)?;
+    let instead_of = &TriggerTimingKind::InsteadOf;
+
+    let mut count = 0;
+    match stmt {
+        Statement::Insert(ins) => {
+            let targets: Vec<usize> = match ins.columns {
+                Some(ref names) => names
+                    .iter()
+                    .map(|name| view_column_index(&columns, name, &view.name))
+                    .collect::<Result<_>>()?,
+                None => (0..columns.len()).collect(),
+            };
+            for source in insert_source_rows(ins, pool, catalog)?.iter() {
+                if source.len() != targets.len() {
+                    return Err(HorizonError::InvalidSql(format!(
+                        "expected {} values but got {}", targets.len(), source.len()
+                    )));
+                }
+                let mut values = vec![Value::Null; columns.len()];
+                for (expr, &idx) in source.iter().zip(&targets) {
+                    values[idx] = eval_expr_dynamic(expr, &[], &[])?;
+                }
+                let new = TriggerRow { columns: &columns, values: &values };
+                fire_row_triggers(&view.name, &event, instead_of, Some(new), None, pool, catalog, txn_mgr)?;
+                count += 1;
+            }
+        }
+        Statement::Update(upd) => {
+            let targets: Vec<(usize, &Expr)> = upd
+                .assignments
+                .iter()
+                .map(|(name, expr)| Ok((view_column_index(&columns, name, &view.name)?, expr)))
+                .collect::<Result<_>>()?;
+            for row in &rows {
+                if !view_row_matches(upd.where_clause.as_ref(), row, &columns)? {
+                    continue;
+                }
+                let mut values = row.clone();
+                for &(idx, expr) in &targets {
+                    values[idx] = eval_expr_dynamic(expr, row, &columns)?;
+                }
+                let new = TriggerRow { columns: &columns, values: &values };
+                let old = TriggerRow { columns: &columns, values: row };
+                fire_row_triggers(&view.name, &event, instead_of, Some(new), Some(old), pool, catalog, txn_mgr)?;
+                count += 1;
+            }
+        }
+        Statement::Delete(del) => {
+            for row in &rows {
+                if !view_row_matches(del.where_clause.as_ref(), row, &columns)? {
+                    continue;
+                }
+                let old = TriggerRow { columns: &columns, values: row };
+                fire_row_triggers(&view.name, &event, instead_of, None, Some(old), pool, catalog, txn_mgr)?;
+                count += 1;
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(count)
+}
+
+/// Position of column `name` among the columns of a view.
+fn view_column_index(columns: &[String], name: &str, view: &str) -> Result<usize> {
+    let suffix = format!(".{}", name.to_lowercase());
+    columns
+        .iter()
+        .position(|c| c.eq_ignore_ascii_case(name))
+        .or_else(|| columns.iter().position(|c| c.to_lowercase().ends_with(&suffix)))
+        .ok_or_else(|| HorizonError::ColumnNotFound(format!("{}.{}", view, name)))
+}
+
+fn view_row_matches(where_clause: Option<&Expr>, row: &[Value], columns: &[String]) -> Result<bool> {
+    match where_clause {
+        Some(expr) => Ok(eval_expr_dynamic(expr, row, columns)?.to_bool()),
+        None => Ok(true),
+    }
+}
+
 // ---- SQL Reconstruction Helpers ----
 
 pub(super) fn select_to_sql(select: &SelectStatement) -> String {
@@ -85,7 +269,7 @@ pub(super) fn select_to_sql(select: &SelectStatement) -> String {
     if select.distinct {
         sql.push_str("DISTINCT ");
     }
-    let cols: Vec<String> = select.columns.iter().map(|c| select_column_to_sql(c)).collect();
+    let cols: Vec<String> = select.columns.iter().map(select_column_to_sql).collect();
     sql.push_str(&cols.join(", "));
     if let Some(ref from) = select.from {
         sql.push_str(" FROM ");
@@ -97,13 +281,23 @@ pub(super) fn select_to_sql(select: &SelectStatement) -> String {
     }
     if !select.group_by.is_empty() {
         sql.push_str(" GROUP BY ");
-        let g: Vec<String> = select.group_by.iter().map(|e| expr_to_sql(e)).collect();
-        sql.push_str(&g.join(", "));
+        let g: Vec<String> = select.group_by.iter().map(expr_to_sql).collect();
+        match select.group_by_modifier {
+            Some(GroupByModifier::Rollup) => sql.push_str(&format!("ROLLUP({})", g.join(", "))),
+            None => sql.push_str(&g.join(", ")),
+        }
     }
     if let Some(ref having) = select.having {
         sql.push_str(" HAVING ");
         sql.push_str(&expr_to_sql(having));
     }
+    if !select.windows.is_empty() {
+        let w: Vec<String> = select.windows.iter().map(|w| {
+            format!("{} AS ({})", w.name, window_spec_to_sql(&w.def.base, &w.def.partition_by, &w.def.order_by))
+        }).collect();
+        sql.push_str(" WINDOW ");
+        sql.push_str(&w.join(", "));
+    }
     if !select.order_by.is_empty() {
         sql.push_str(" ORDER BY ");
         let items: Vec<String> = select.order_by.iter().map(|o| {
@@ -148,17 +342,25 @@ fn from_clause_to_sql(from: &FromClause) -> String {
                 name.clone()
             }
         }
-        FromClause::Join { left, join_type, right, on } => {
+        FromClause::Join { left, join_type, right, constraint } => {
+            let natural = if constraint == &Some(JoinSpec::Natural) { "NATURAL " } else { "" };
             let jt = match join_type {
                 JoinType::Inner => "JOIN",
                 JoinType::Left => "LEFT JOIN",
                 JoinType::Right => "RIGHT JOIN",
+                JoinType::Full => "FULL OUTER JOIN",
                 JoinType::Cross => "CROSS JOIN",
             };
-            let mut s = format!("{} {} {}", from_clause_to_sql(left), jt, from_clause_to_sql(right));
-            if let Some(ref on_expr) = on {
-                s.push_str(" ON ");
-                s.push_str(&expr_to_sql(on_expr));
+            let mut s = format!("{} {}{} {}", from_clause_to_sql(left), natural, jt, from_clause_to_sql(right));
+            match constraint {
+                Some(JoinSpec::On(on_expr)) => {
+                    s.push_str(" ON ");
+                    s.push_str(&expr_to_sql(on_expr));
+                }
+                Some(JoinSpec::Using(cols)) => {
+                    s.push_str(&format!(" USING ({})", cols.join(", ")));
+                }
+                Some(JoinSpec::Natural) | None => {}
             }
             s
         }
@@ -166,7 +368,7 @@ fn from_clause_to_sql(from: &FromClause) -> String {
             format!("({}) AS {}", select_to_sql(query), alias)
         }
         FromClause::TableFunction { name, args, alias } => {
-            let args_str: Vec<String> = args.iter().map(|a| expr_to_sql(a)).collect();
+            let args_str: Vec<String> = args.iter().map(expr_to_sql).collect();
             let base = format!("{}({})", name, args_str.join(", "));
             if let Some(ref a) = alias {
                 format!("{} AS {}", base, a)
@@ -174,10 +376,24 @@ fn from_clause_to_sql(from: &FromClause) -> String {
                 base
             }
         }
+        FromClause::Values { rows, alias, columns } => {
+            let rows_str: Vec<String> = rows
+                .iter()
+                .map(|row| format!("({})", row.iter().map(expr_to_sql).collect::<Vec<_>>().join(", ")))
+                .collect();
+            let mut s = format!("(VALUES {})", rows_str.join(", "));
+            if let Some(ref a) = alias {
+                s.push_str(&format!(" AS {}", a));
+            }
+            if let Some(ref cols) = columns {
+                s.push_str(&format!("({})", cols.join(", ")));
+            }
+            s
+        }
     }
 }
 
-fn expr_to_sql(expr: &Expr) -> String {
+pub(crate) fn expr_to_sql(expr: &Expr) -> String {
     match expr {
         Expr::Literal(lit) => literal_to_sql_repr(lit),
         Expr::Column { table, name } => {
@@ -195,6 +411,7 @@ fn expr_to_sql(expr: &Expr) -> String {
                 BinaryOp::LtEq => "<=", BinaryOp::GtEq => ">=", BinaryOp::And => "AND",
                 BinaryOp::Or => "OR", BinaryOp::Concat => "||", BinaryOp::BitAnd => "&",
                 BinaryOp::BitOr => "|", BinaryOp::ShiftLeft => "<<", BinaryOp::ShiftRight => ">>",
+                BinaryOp::Regexp => "REGEXP", BinaryOp::Glob => "GLOB",
             };
             format!("({} {} {})", expr_to_sql(left), op_str, expr_to_sql(right))
         }
@@ -213,7 +430,7 @@ fn expr_to_sql(expr: &Expr) -> String {
             else { format!("({} BETWEEN {} AND {})", expr_to_sql(inner), expr_to_sql(low), expr_to_sql(high)) }
         }
         Expr::InList { expr: inner, list, negated } => {
-            let items: Vec<String> = list.iter().map(|e| expr_to_sql(e)).collect();
+            let items: Vec<String> = list.iter().map(expr_to_sql).collect();
             if *negated { format!("({} NOT IN ({}))", expr_to_sql(inner), items.join(", ")) }
             else { format!("({} IN ({}))", expr_to_sql(inner), items.join(", ")) }
         }
@@ -224,10 +441,14 @@ fn expr_to_sql(expr: &Expr) -> String {
         Expr::Match { table, pattern } => {
             format!("({} MATCH {})", expr_to_sql(table), expr_to_sql(pattern))
         }
-        Expr::Function { name, args, distinct } => {
-            let a: Vec<String> = args.iter().map(|x| expr_to_sql(x)).collect();
-            if *distinct { format!("{}(DISTINCT {})", name, a.join(", ")) }
-            else { format!("{}({})", name, a.join(", ")) }
+        Expr::Function { name, args, distinct, filter } => {
+            let a: Vec<String> = args.iter().map(expr_to_sql).collect();
+            let call = if *distinct { format!("{}(DISTINCT {})", name, a.join(", ")) }
+            else { format!("{}({})", name, a.join(", ")) };
+            match filter {
+                Some(cond) => format!("{} FILTER (WHERE {})", call, expr_to_sql(cond)),
+                None => call,
+            }
         }
         Expr::Cast { expr: inner, type_name } => {
             format!("CAST({} AS {})", expr_to_sql(inner), type_name)
@@ -248,33 +469,37 @@ fn expr_to_sql(expr: &Expr) -> String {
         Expr::Subquery(sel) => format!("({})", select_to_sql(sel)),
         Expr::Exists(sel) => format!("EXISTS ({})", select_to_sql(sel)),
         Expr::Placeholder(n) => format!("?{}", n),
+        Expr::NamedPlaceholder(name) => name.clone(),
         Expr::Collate { expr: inner, collation } => {
             format!("{} COLLATE {}", expr_to_sql(inner), collation)
         }
-        Expr::WindowFunction { function, partition_by, order_by, .. } => {
-            let mut s = expr_to_sql(function);
-            s.push_str(" OVER (");
-            if !partition_by.is_empty() {
-                s.push_str("PARTITION BY ");
-                let p: Vec<String> = partition_by.iter().map(|e| expr_to_sql(e)).collect();
-                s.push_str(&p.join(", "));
-            }
-            if !order_by.is_empty() {
-                if !partition_by.is_empty() { s.push(' '); }
-                s.push_str("ORDER BY ");
-                let o: Vec<String> = order_by.iter().map(|x| {
-                    let mut os = expr_to_sql(&x.expr);
-                    if x.desc { os.push_str(" DESC"); }
-                    os
-                }).collect();
-                s.push_str(&o.join(", "));
-            }
-            s.push(')');
-            s
+        Expr::WindowFunction { function, partition_by, order_by, window, .. } => {
+            format!("{} OVER ({})", expr_to_sql(function), window_spec_to_sql(window, partition_by, order_by))
         }
     }
 }
 
+/// The body of a window specification, without its parentheses.
+fn window_spec_to_sql(base: &Option<String>, partition_by: &[Expr], order_by: &[OrderByItem]) -> String {
+    let mut parts = Vec::new();
+    if let Some(name) = base {
+        parts.push(name.clone());
+    }
+    if !partition_by.is_empty() {
+        let p: Vec<String> = partition_by.iter().map(expr_to_sql).collect();
+        parts.push(format!("PARTITION BY {}", p.join(", ")));
+    }
+    if !order_by.is_empty() {
+        let o: Vec<String> = order_by.iter().map(|x| {
+            let mut os = expr_to_sql(&x.expr);
+            if x.desc { os.push_str(" DESC"); }
+            os
+        }).collect();
+        parts.push(format!("ORDER BY {}", o.join(", ")));
+    }
+    parts.join(" ")
+}
+
 fn literal_to_sql_repr(lit: &LiteralValue) -> String {
     match lit {
         LiteralValue::Integer(i) => i.to_string(),
@@ -294,12 +519,20 @@ pub(super) fn statement_to_sql(stmt: &Statement) -> String {
             if let Some(ref cols) = ins.columns {
                 sql.push_str(&format!(" ({})", cols.join(", ")));
             }
-            sql.push_str(" VALUES ");
-            let rows: Vec<String> = ins.values.iter().map(|row| {
-                let vals: Vec<String> = row.iter().map(|e| expr_to_sql(e)).collect();
-                format!("({})", vals.join(", "))
-            }).collect();
-            sql.push_str(&rows.join(", "));
+            match &ins.source {
+                InsertSource::Values(values) => {
+                    sql.push_str(" VALUES ");
+                    let rows: Vec<String> = values.iter().map(|row| {
+                        let vals: Vec<String> = row.iter().map(expr_to_sql).collect();
+                        format!("({})", vals.join(", "))
+                    }).collect();
+                    sql.push_str(&rows.join(", "));
+                }
+                InsertSource::Select(select) => {
+                    sql.push(' ');
+                    sql.push_str(&select_to_sql(select));
+                }
+            }
             sql
         }
         Statement::Update(upd) => {
@@ -308,6 +541,10 @@ pub(super) fn statement_to_sql(stmt: &Statement) -> String {
                 .map(|(c, e)| format!("{} = {}", c, expr_to_sql(e)))
                 .collect();
             sql.push_str(&a.join(", "));
+            if let Some(ref from) = upd.from {
+                sql.push_str(" FROM ");
+                sql.push_str(&from_clause_to_sql(from));
+            }
             if let Some(ref wh) = upd.where_clause {
                 sql.push_str(" WHERE ");
                 sql.push_str(&expr_to_sql(wh));
@@ -0,0 +1,103 @@
+//! Full-text indexes on ordinary tables.
+//!
+//! [`Database::create_fulltext_index`](crate::Database::create_fulltext_index)
+//! indexes text columns of a table in an FTS5 shadow table named after it
+//! (`articles_fts` for `articles`), whose rowids are the table's INTEGER
+//! PRIMARY KEY values. It fills the shadow table from the rows already in
+//! the table and installs AFTER INSERT, AFTER UPDATE, and AFTER DELETE
+//! triggers on the table that keep it in step.
+//!
+//! A `MATCH` on the table then searches the shadow table: the query
+//! planner rewrites
+//!
+//! ```sql
+//! SELECT * FROM articles WHERE title MATCH 'rust'
+//! ```
+//!
+//! into
+//!
+//! ```sql
+//! SELECT * FROM articles WHERE id IN (SELECT rowid FROM articles_fts WHERE title MATCH 'rust')
+//! ```
+//!
+//! and `articles MATCH 'rust'` searches every indexed column. Like FTS5
+//! tables and triggers, full-text indexes are in-memory only.
+
+use crate::catalog::TableInfo;
+use crate::error::{HorizonError, Result};
+use crate::types::DataType;
+
+/// The name of the FTS5 shadow table of a full-text index on `table`.
+pub fn shadow_table_name(table: &str) -> String {
+    format!("{}_fts", table)
+}
+
+/// The INTEGER PRIMARY KEY column of `table`, which a full-text index
+/// needs to tie the rows of its shadow table to the table's rows.
+pub(crate) fn rowid_column(table: &TableInfo) -> Result<String> {
+    match table.pk_column.map(|i| &table.columns[i]) {
+        Some(pk) if pk.affinity == DataType::Integer => Ok(pk.name.clone()),
+        _ => Err(HorizonError::InvalidSql(format!(
+            "a full-text index needs an INTEGER PRIMARY KEY on {}",
+            table.name
+        ))),
+    }
+}
+
+/// The SQL that creates and fills the shadow table of a full-text index on
+/// the `columns` of `table` and installs the triggers keeping it in step.
+/// `rowid_column` is the table's INTEGER PRIMARY KEY.
+pub(crate) fn create_sql(table: &str, rowid_column: &str, columns: &[String]) -> String {
+    let fts_table = quote_identifier(&shadow_table_name(table));
+    let fts_columns = columns.iter().map(|c| quote_identifier(c)).collect::<Vec<_>>().join(", ");
+    let row = |qualifier: &str| {
+        std::iter::once(rowid_column)
+            .chain(columns.iter().map(String::as_str))
+            .map(|c| format!("{}.{}", qualifier, quote_identifier(c)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let insert = format!("INSERT INTO {} (rowid, {}) VALUES ({});", fts_table, fts_columns, row("NEW"));
+    let delete = format!("DELETE FROM {} WHERE rowid = OLD.{};", fts_table, quote_identifier(rowid_column));
+    let trigger = |event: &str, body: &str| {
+        format!(
+            "CREATE TRIGGER {} AFTER {} ON {} FOR EACH ROW BEGIN {} END;\n",
+            quote_identifier(&format!("{}_fts_{}", table, event.to_lowercase())),
+            event,
+            quote_identifier(table),
+            body,
+        )
+    };
+
+    let mut sql = format!("CREATE VIRTUAL TABLE {} USING fts5({});\n", fts_table, fts_columns);
+    sql.push_str(&format!(
+        "INSERT INTO {} (rowid, {}) SELECT {}, {} FROM {};\n",
+        fts_table,
+        fts_columns,
+        quote_identifier(rowid_column),
+        fts_columns,
+        quote_identifier(table),
+    ));
+    sql.push_str(&trigger("INSERT", &insert));
+    sql.push_str(&trigger("UPDATE", &format!("{} {}", delete, insert)));
+    sql.push_str(&trigger("DELETE", &delete));
+    sql
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triggers_copy_the_indexed_columns() {
+        let sql = create_sql("articles", "id", &["title".to_string(), "body".to_string()]);
+        assert!(sql.contains(r#"CREATE VIRTUAL TABLE "articles_fts" USING fts5("title", "body");"#));
+        assert!(sql.contains(
+            r#"CREATE TRIGGER "articles_fts_update" AFTER UPDATE ON "articles" FOR EACH ROW BEGIN DELETE FROM "articles_fts" WHERE rowid = OLD."id"; INSERT INTO "articles_fts" (rowid, "title", "body") VALUES (NEW."id", NEW."title", NEW."body"); END;"#
+        ));
+    }
+}
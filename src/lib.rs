@@ -3,6 +3,9 @@
 //! A highly-performant serverless embedded RDBMS written in Rust
 //! with full SQLite feature parity.
 
+// Test fixtures use literals like 3.14 as plain sample data, not as π.
+#![cfg_attr(test, allow(clippy::approx_constant))]
+
 pub mod error;
 pub mod types;
 pub mod pager;
@@ -14,13 +17,35 @@ pub mod catalog;
 pub mod sql;
 pub mod planner;
 pub mod execution;
+pub mod cancel;
+pub mod cdc;
+pub mod fulltext;
+pub mod migration;
+pub mod prepared;
+pub mod slow_query;
+pub mod sqlite_compat;
+#[cfg(feature = "tokio")]
+pub mod async_db;
+#[cfg(feature = "serde")]
+pub mod serde_horizon;
 
 pub use error::{HorizonError, Result};
-pub use types::Value;
+pub use types::{FromSql, ToSql, Value};
 pub use pager::PageId;
+pub use cancel::QueryHandle;
+pub use cdc::{CdcEvent, CdcOperation};
+pub use execution::fts5::Fts5Tokenizer;
+pub use prepared::PreparedStatement;
+pub use slow_query::SlowQueryEntry;
+pub use mvcc::SnapshotId;
+pub use wal::{CheckpointMode, CheckpointResult, JournalMode, SynchronousMode};
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
 /// A row returned from a query.
 #[derive(Debug, Clone)]
@@ -42,9 +67,21 @@ impl Row {
     pub fn get_index(&self, index: usize) -> Option<&Value> {
         self.values.get(index)
     }
+
+    /// Get a value by column name, converted to `T`.
+    ///
+    /// Fails with [`HorizonError::ColumnNotFound`] if there is no such
+    /// column, or with the conversion error from [`FromSql`].
+    pub fn get_typed<T: FromSql>(&self, name: &str) -> Result<T> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| HorizonError::ColumnNotFound(name.to_string()))?;
+        T::from_sql(value)
+    }
 }
 
 /// Query result set.
+#[derive(Clone)]
 pub struct QueryResult {
     pub columns: Arc<Vec<String>>,
     pub rows: Vec<Row>,
@@ -68,100 +105,1294 @@ impl IntoIterator for QueryResult {
     }
 }
 
+/// A lazily-evaluated query result returned by [`Database::query_iter`].
+///
+/// The iterator holds a read lock on the database until it is dropped.
+/// Other queries can run alongside it, but any statement that modifies the
+/// database blocks (or deadlocks, if made from the same thread) while it is
+/// alive.
+pub struct RowIter<'db> {
+    inner: RwLockReadGuard<'db, DatabaseInner>,
+    stream: execution::stream::SelectStream,
+}
+
+impl RowIter<'_> {
+    /// The result column names.
+    pub fn columns(&self) -> &Arc<Vec<String>> {
+        self.stream.columns()
+    }
+}
+
+impl Iterator for RowIter<'_> {
+    type Item = Result<Row>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stream.next_row(&self.inner.buffer_pool, &self.inner.catalog)
+    }
+}
+
+/// The only row of `result`, if any.
+fn single_row(result: QueryResult) -> Result<Option<Row>> {
+    let mut rows = result.rows.into_iter();
+    match (rows.next(), rows.next()) {
+        (row, None) => Ok(row),
+        _ => Err(HorizonError::TooManyRows),
+    }
+}
+
+/// A hook called with the SQL text of each statement before it runs.
+type PreExecuteHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// A hook called with the SQL text of each statement and its result after
+/// it runs.
+type PostExecuteHook = Arc<dyn Fn(&str, &Result<usize>) + Send + Sync>;
+
+/// The path that opens an in-memory database.
+const MEMORY_PATH: &str = ":memory:";
+
+/// Options for opening a database with [`Database::open_with_config`].
+///
+/// The settings are passed on to the [`pager::Pager`],
+/// [`wal::WalManager`], and [`buffer::BufferPool`] of the database. They
+/// are usually filled in through a [`DatabaseBuilder`].
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    /// Page size in bytes, see [`DatabaseConfig::page_size`].
+    pub page_size: Option<u32>,
+    /// Number of pages the buffer pool caches.
+    pub cache_size: usize,
+    /// Open the database file without write access.
+    pub read_only: bool,
+    /// How changes reach the database file.
+    pub journal_mode: JournalMode,
+    /// How often the WAL is synced to disk.
+    pub synchronous: SynchronousMode,
+    /// How long a writer waits for a lock held by another connection, or
+    /// for a row locked by another transaction.
+    pub busy_timeout: Duration,
+    /// Whether foreign key constraints are enforced, as with
+    /// `PRAGMA foreign_keys`.
+    pub foreign_keys: bool,
+    /// How many rows an ORDER BY sorts in memory before spilling sorted
+    /// runs to a temporary file.
+    pub sort_spill_threshold: usize,
+    /// How many rows a hash join builds its hash table over in memory
+    /// before partitioning its inputs into a temporary file.
+    pub join_spill_threshold: usize,
+    /// How many bytes of query results [`Database::query`] caches, or
+    /// `None` to cache none; see [`DatabaseConfig::enable_query_cache`].
+    pub query_cache_capacity: Option<usize>,
+    /// Called each time a writer has to wait for a lock.
+    pub on_busy: Option<fn()>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            page_size: None,
+            cache_size: 1024,
+            read_only: false,
+            journal_mode: JournalMode::default(),
+            synchronous: SynchronousMode::default(),
+            busy_timeout: Duration::from_secs(5),
+            foreign_keys: false,
+            sort_spill_threshold: execution::sort::DEFAULT_SORT_SPILL_THRESHOLD,
+            join_spill_threshold: execution::join::DEFAULT_JOIN_SPILL_THRESHOLD,
+            query_cache_capacity: None,
+            on_busy: None,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Create a configuration with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the page size in bytes: a power of two from 512 to 65536.
+    ///
+    /// A new database is created with this page size. An existing database
+    /// must already use it, otherwise opening fails. Without this setting,
+    /// new databases use 4096-byte pages and existing ones keep theirs.
+    pub fn page_size(mut self, page_size: u32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Cache up to `capacity_bytes` of query results.
+    ///
+    /// [`Database::query`] then answers a SELECT it has run before from
+    /// the cache, without reading any pages, unless one of the tables the
+    /// query reads has been written since. Queries calling functions such
+    /// as `random()` or `datetime('now')`, and queries over views, virtual
+    /// tables, or attached databases, are never cached. The least recently
+    /// used results are evicted when the cache is full.
+    pub fn enable_query_cache(mut self, capacity_bytes: usize) -> Self {
+        self.query_cache_capacity = Some(capacity_bytes);
+        self
+    }
+}
+
+/// Builds a [`DatabaseConfig`] and opens a database with it.
+///
+/// ```no_run
+/// use horizon::{Database, JournalMode};
+///
+/// let db = Database::builder()
+///     .cache_size(256)
+///     .journal_mode(JournalMode::Wal)
+///     .foreign_keys(true)
+///     .open("app.hdb")?;
+/// # Ok::<(), horizon::HorizonError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseBuilder {
+    config: DatabaseConfig,
+}
+
+impl DatabaseBuilder {
+    /// Start from the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the page size in bytes; see [`DatabaseConfig::page_size`].
+    pub fn page_size(mut self, page_size: u32) -> Self {
+        self.config.page_size = Some(page_size);
+        self
+    }
+
+    /// Set the number of pages the buffer pool keeps in memory (1024 by
+    /// default). Less recently used pages are evicted beyond that.
+    pub fn cache_size(mut self, pages: usize) -> Self {
+        self.config.cache_size = pages;
+        self
+    }
+
+    /// Open the database without write access. Statements that would
+    /// change it fail with [`HorizonError::ReadOnly`], and the file must
+    /// already exist.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.config.read_only = read_only;
+        self
+    }
+
+    /// Set how changes reach the database file ([`JournalMode::Wal`] by
+    /// default).
+    pub fn journal_mode(mut self, mode: JournalMode) -> Self {
+        self.config.journal_mode = mode;
+        self
+    }
+
+    /// Set how often changes are synced to disk
+    /// ([`SynchronousMode::Normal`] by default).
+    pub fn synchronous(mut self, mode: SynchronousMode) -> Self {
+        self.config.synchronous = mode;
+        self
+    }
+
+    /// Set how long a writer waits for a lock held by another connection
+    /// (5 seconds by default).
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.config.busy_timeout = timeout;
+        self
+    }
+
+    /// Enforce foreign key constraints from the start, as if
+    /// `PRAGMA foreign_keys = ON` had been run.
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.config.foreign_keys = enabled;
+        self
+    }
+
+    /// Set how many rows an ORDER BY sorts in memory (1 000 000 by
+    /// default), as with `PRAGMA sort_spill_threshold`. Larger results are
+    /// sorted in runs written to a temporary file and merged.
+    pub fn sort_spill_threshold(mut self, rows: usize) -> Self {
+        self.config.sort_spill_threshold = rows;
+        self
+    }
+
+    /// Set how many rows a hash join builds its hash table over in memory
+    /// (1 000 000 by default), as with `PRAGMA join_spill_threshold`.
+    /// Larger joins are split into partitions written to a temporary file
+    /// and joined one partition at a time.
+    pub fn join_spill_threshold(mut self, rows: usize) -> Self {
+        self.config.join_spill_threshold = rows;
+        self
+    }
+
+    /// Cache up to `capacity_bytes` of query results; see
+    /// [`DatabaseConfig::enable_query_cache`].
+    pub fn query_cache(mut self, capacity_bytes: usize) -> Self {
+        self.config.query_cache_capacity = Some(capacity_bytes);
+        self
+    }
+
+    /// Set a function to call each time a writer has to wait for a lock.
+    pub fn on_busy(mut self, handler: fn()) -> Self {
+        self.config.on_busy = Some(handler);
+        self
+    }
+
+    /// The configuration built so far.
+    pub fn config(&self) -> &DatabaseConfig {
+        &self.config
+    }
+
+    /// Open or create the database at `path` with this configuration.
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<Database> {
+        Database::open_with_config(path, self.config)
+    }
+}
+
 /// The main database handle. Thread-safe via internal locking.
+///
+/// Read-only queries share a read lock, so they can run concurrently from
+/// several threads. Any statement that modifies the database takes the
+/// write lock and runs alone.
+///
+/// A handle is one connection, with one transaction at a time. Further
+/// connections to the same database are opened with
+/// [`Database::connect`].
 pub struct Database {
     path: PathBuf,
-    inner: Mutex<DatabaseInner>,
+    inner: Arc<RwLock<DatabaseInner>>,
+    /// This connection's transactions. The manager is swapped into `inner`
+    /// while the connection holds it for writing; see [`WriteGuard`].
+    txn_manager: Mutex<mvcc::TransactionManager>,
+}
+
+/// The database locked for writing by one connection, with that
+/// connection's transaction manager in place of the one `inner` holds
+/// between statements.
+struct WriteGuard<'db> {
+    inner: RwLockWriteGuard<'db, DatabaseInner>,
+    /// The connection's slot, holding the manager swapped out of `inner`.
+    parked: MutexGuard<'db, mvcc::TransactionManager>,
+}
+
+impl<'db> WriteGuard<'db> {
+    fn new(
+        mut inner: RwLockWriteGuard<'db, DatabaseInner>,
+        mut parked: MutexGuard<'db, mvcc::TransactionManager>,
+    ) -> Self {
+        std::mem::swap(&mut inner.txn_manager, &mut *parked);
+        WriteGuard { inner, parked }
+    }
+}
+
+impl Deref for WriteGuard<'_> {
+    type Target = DatabaseInner;
+
+    fn deref(&self) -> &DatabaseInner {
+        &self.inner
+    }
+}
+
+impl DerefMut for WriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut DatabaseInner {
+        &mut self.inner
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        std::mem::swap(&mut self.inner.txn_manager, &mut *self.parked);
+    }
 }
 
 struct DatabaseInner {
     buffer_pool: buffer::BufferPool,
     catalog: catalog::Catalog,
     txn_manager: mvcc::TransactionManager,
+    path: PathBuf,
+    config: DatabaseConfig,
+    /// Held from this handle's first write until it is closed.
+    write_lock: Option<wal::WriteLock>,
+    /// Databases attached with `ATTACH DATABASE`, keyed by lowercased
+    /// schema name. Each is opened like the main database, with its own
+    /// buffer pool and catalog.
+    attached: HashMap<String, DatabaseInner>,
+    /// Results of earlier queries, if enabled. Queries share the read
+    /// lock, so the cache has a lock of its own.
+    query_cache: Option<Mutex<execution::query_cache::QueryCache>>,
+    /// Hooks set with [`Database::set_pre_execute_hook`] and
+    /// [`Database::set_post_execute_hook`].
+    pre_execute_hook: Option<PreExecuteHook>,
+    post_execute_hook: Option<PostExecuteHook>,
+    /// Statements slower than a threshold, once one has been set with
+    /// [`Database::set_slow_query_threshold`]. Statements share the read
+    /// lock, so the log has a lock of its own.
+    slow_query_log: Option<Mutex<slow_query::SlowQueryLog>>,
+}
+
+impl DatabaseInner {
+    fn open(path: &Path, config: DatabaseConfig) -> Result<Self> {
+        let mut write_lock = None;
+        let (buffer_pool, mut catalog) = open_storage(path, &config, &mut write_lock)?;
+        catalog.set_foreign_keys_enabled(config.foreign_keys);
+        catalog.set_sort_spill_threshold(config.sort_spill_threshold);
+        catalog.set_join_spill_threshold(config.join_spill_threshold);
+        let mut txn_manager = mvcc::TransactionManager::new();
+        txn_manager.set_lock_timeout(config.busy_timeout);
+        let query_cache = config.query_cache_capacity
+            .map(|capacity| Mutex::new(execution::query_cache::QueryCache::new(capacity)));
+        Ok(DatabaseInner {
+            buffer_pool,
+            catalog,
+            txn_manager,
+            path: path.to_path_buf(),
+            config,
+            write_lock,
+            attached: HashMap::new(),
+            query_cache,
+            pre_execute_hook: None,
+            post_execute_hook: None,
+            slow_query_log: None,
+        })
+    }
+
+    /// Whether changes go through the database's write lock, which is the
+    /// case for files opened for writing.
+    fn uses_write_lock(&self) -> bool {
+        !self.config.read_only && self.path.as_os_str() != MEMORY_PATH
+    }
+
+    /// Take the write lock before the first change made through this
+    /// handle. If another handle changed the database since this one read
+    /// it, the cached pages and the catalog are loaded again first.
+    fn begin_write(&mut self) -> Result<()> {
+        if self.write_lock.is_some() || !self.uses_write_lock() {
+            return Ok(());
+        }
+        let lock = wal::WriteLock::acquire(&self.path, self.config.busy_timeout, self.config.on_busy)?;
+        let mut write_lock = Some(lock);
+        if self.buffer_pool.is_stale()? {
+            // Keep settings changed by PRAGMA cache_size, journal_mode,
+            // and synchronous
+            self.config.cache_size = self.buffer_pool.capacity();
+            self.config.journal_mode = self.buffer_pool.journal_mode();
+            self.config.synchronous = self.buffer_pool.synchronous();
+            let (buffer_pool, mut catalog) = open_storage(&self.path, &self.config, &mut write_lock)?;
+            catalog.set_foreign_keys_enabled(self.catalog.foreign_keys_enabled());
+            catalog.set_sort_spill_threshold(self.catalog.sort_spill_threshold());
+            catalog.set_join_spill_threshold(self.catalog.join_spill_threshold());
+            catalog.set_collations(self.catalog.collations().clone());
+            catalog.set_fts5_tokenizers(self.catalog.fts5_tokenizers().clone());
+            // The other handle may have changed the schema
+            catalog.set_schema_generation(self.catalog.schema_generation() + 1);
+            catalog.attached_databases = std::mem::take(&mut self.catalog.attached_databases);
+            self.buffer_pool = buffer_pool;
+            self.catalog = catalog;
+            // The reloaded catalog starts its write generations over
+            self.clear_query_cache();
+        }
+        self.write_lock = write_lock;
+        Ok(())
+    }
+
+    /// Fail with [`HorizonError::ReadOnly`] if `stmt` would change a
+    /// read-only database, and take the write lock if it would change a
+    /// writable one.
+    fn prepare_statement(&mut self, stmt: &sql::ast::Statement) -> Result<()> {
+        self.invalidate_query_cache(stmt);
+        if !execution::modifies_database(stmt) {
+            return Ok(());
+        }
+        if self.config.read_only {
+            return Err(HorizonError::ReadOnly(
+                "attempt to write a read-only database".into(),
+            ));
+        }
+        self.begin_write()
+    }
+
+    /// Drop the cached query results `stmt` may make out of date. The
+    /// catalog tracks the tables that INSERT, UPDATE, and DELETE write, so
+    /// only other changes, and rollbacks, clear the cache.
+    fn invalidate_query_cache(&self, stmt: &sql::ast::Statement) {
+        use sql::ast::Statement;
+        let clears = match stmt {
+            Statement::Insert(_) | Statement::Update(_) | Statement::Delete(_) => false,
+            Statement::Rollback | Statement::RollbackToSavepoint(_) => true,
+            stmt => execution::modifies_database(stmt),
+        };
+        if clears {
+            self.clear_query_cache();
+        }
+    }
+
+    fn clear_query_cache(&self) {
+        if let Some(Ok(mut cache)) = self.query_cache.as_ref().map(Mutex::lock) {
+            cache.clear();
+        }
+    }
+
+    /// The cached result of the query `sql`, if there is one and the
+    /// database file has not been changed by another handle since.
+    fn cached_result(&self, sql: &str) -> Result<Option<QueryResult>> {
+        let cache = match &self.query_cache {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+        let mut cache = cache.lock().map_err(|_| HorizonError::Internal("lock poisoned".into()))?;
+        if self.buffer_pool.is_stale()? {
+            cache.clear();
+            return Ok(None);
+        }
+        Ok(cache.get(sql, &self.catalog))
+    }
+
+    /// Run `stmt`, the query `sql`, and cache its result if it can be
+    /// cached. Returns `Ok(None)` without running it if it cannot.
+    fn query_and_cache(&self, sql: &str, stmt: &sql::ast::Statement) -> Result<Option<QueryResult>> {
+        let cache = match &self.query_cache {
+            Some(cache) if self.catalog.attached_databases.is_empty() => cache,
+            _ => return Ok(None),
+        };
+        let tables = match execution::query_cache::cacheable_tables(stmt, &self.catalog) {
+            Some(tables) => tables,
+            None => return Ok(None),
+        };
+        let result = self.query_read_only(stmt.clone())?;
+        if let Ok(mut cache) = cache.lock() {
+            cache.insert(sql, tables, &result, &self.catalog);
+        }
+        Ok(Some(result))
+    }
+
+    fn execute_statements(&mut self, stmts: Vec<sql::ast::Statement>) -> Result<usize> {
+        let mut total = 0;
+        for stmt in stmts {
+            total += self.execute_statement(stmt)?;
+        }
+        Ok(total)
+    }
+
+    /// Run `stmt`. If it stops at a row another connection's transaction
+    /// holds, its changes are undone before the error is returned.
+    fn execute_statement(&mut self, mut stmt: sql::ast::Statement) -> Result<usize> {
+        match &stmt {
+            sql::ast::Statement::AttachDatabase(attach) => return self.attach(attach).map(|()| 0),
+            sql::ast::Statement::DetachDatabase(detach) => return self.detach(&detach.schema_name).map(|()| 0),
+            _ => {}
+        }
+        let db = self.target_of(&mut stmt)?;
+        db.prepare_statement(&stmt)?;
+        db.txn_manager.begin_statement();
+        let result = execution::execute_statement(&stmt, &mut db.buffer_pool, &mut db.catalog, &mut db.txn_manager);
+        db.undo_if_blocked(result)
+    }
+
+    /// Undo the statement that produced `result` if it stopped at a row
+    /// locked by another transaction; see
+    /// [`mvcc::TransactionManager::set_defer_lock_waits`].
+    fn undo_if_blocked<T>(&mut self, result: Result<T>) -> Result<T> {
+        if matches!(result, Err(HorizonError::LockConflict(_))) {
+            self.clear_query_cache();
+            execution::roll_back_statement(&mut self.buffer_pool, &mut self.catalog, &mut self.txn_manager)?;
+        }
+        result
+    }
+
+    /// Roll back the open transaction, if there is one.
+    fn roll_back(&mut self) -> Result<()> {
+        if !self.txn_manager.is_user_txn_active() {
+            return Ok(());
+        }
+        self.execute_statement(sql::ast::Statement::Rollback).map(drop)
+    }
+
+    /// Open the database file of an `ATTACH DATABASE` statement with this
+    /// handle's settings and record it under its schema name.
+    fn attach(&mut self, attach: &sql::ast::AttachDatabaseStatement) -> Result<()> {
+        self.catalog.attach_database(attach.path.clone(), attach.schema_name.clone())?;
+        match DatabaseInner::open(Path::new(&attach.path), self.config.clone()) {
+            Ok(db) => {
+                self.attached.insert(attach.schema_name.to_lowercase(), db);
+                Ok(())
+            }
+            Err(e) => {
+                self.catalog.detach_database(&attach.schema_name)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Flush and close the attached database `schema_name`.
+    fn detach(&mut self, schema_name: &str) -> Result<()> {
+        self.catalog.detach_database(schema_name)?;
+        match self.attached.remove(&schema_name.to_lowercase()) {
+            Some(mut db) => db.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve the schema-qualified table names of `stmt` (see
+    /// [`execution::attach`]), returning the schema of the attached
+    /// database it writes to, if any.
+    fn resolve_schemas(&self, stmt: &mut sql::ast::Statement) -> Result<Option<String>> {
+        let lookup = |schema: &str| self.attached.get(schema).map(|db| (&db.buffer_pool, &db.catalog));
+        execution::attach::resolve_schemas(stmt, (&self.buffer_pool, &self.catalog), &lookup)
+    }
+
+    /// Resolve the schemas of `stmt` and return the database it runs
+    /// against: this one or an attached one.
+    ///
+    /// An attached database commits each change on its own, so it cannot
+    /// be written inside a transaction, which could not roll it back.
+    fn target_of(&mut self, stmt: &mut sql::ast::Statement) -> Result<&mut DatabaseInner> {
+        match self.resolve_schemas(stmt)? {
+            Some(schema) if self.txn_manager.is_user_txn_active() => Err(HorizonError::TransactionError(
+                format!("cannot write to attached database {} inside a transaction", schema),
+            )),
+            Some(schema) => self
+                .attached
+                .get_mut(&schema)
+                .ok_or(HorizonError::TableNotFound(schema)),
+            None => Ok(self),
+        }
+    }
+
+    fn checkpoint(&mut self, mode: CheckpointMode) -> Result<CheckpointResult> {
+        self.begin_write()?;
+        self.buffer_pool.checkpoint(mode)
+    }
+
+    /// Write every change made through this handle to the database file.
+    fn flush(&mut self) -> Result<()> {
+        // A handle that never wrote has nothing to flush, and its view of
+        // the WAL may be out of date
+        for db in self.attached.values_mut() {
+            db.flush()?;
+        }
+        if self.uses_write_lock() && self.write_lock.is_none() {
+            return Ok(());
+        }
+        self.buffer_pool.flush_all()
+    }
+
+    fn query_read_only(&self, mut stmt: sql::ast::Statement) -> Result<QueryResult> {
+        self.resolve_schemas(&mut stmt)?;
+        execution::execute_read_query(&stmt, &self.buffer_pool, &self.catalog)
+    }
+
+    fn query_statements(&mut self, stmts: Vec<sql::ast::Statement>) -> Result<QueryResult> {
+        let mut first = match stmts.into_iter().next() {
+            Some(stmt) => stmt,
+            None => {
+                return Ok(QueryResult {
+                    columns: Arc::new(vec![]),
+                    rows: vec![],
+                })
+            }
+        };
+
+        let db = self.target_of(&mut first)?;
+        db.prepare_statement(&first)?;
+        db.txn_manager.begin_statement();
+        let DatabaseInner { buffer_pool, catalog, txn_manager, .. } = &mut *db;
+        let first = &first;
+
+        // Route SELECT, PRAGMA, EXPLAIN, and RETURNING through execute_query
+        let result = match first {
+            sql::ast::Statement::Select(_)
+            | sql::ast::Statement::Pragma(_)
+            | sql::ast::Statement::Explain(_)
+            | sql::ast::Statement::ExplainQueryPlan(_) => {
+                execution::execute_query(first, buffer_pool, catalog, txn_manager)
+            }
+            stmt if execution::has_returning(stmt) => {
+                execution::execute_query(stmt, buffer_pool, catalog, txn_manager)
+            }
+            _ => Err(HorizonError::Internal(
+                "query() requires a SELECT, PRAGMA, EXPLAIN, or RETURNING statement".into(),
+            )),
+        };
+        db.undo_if_blocked(result)
+    }
 }
 
 impl Database {
     /// Open or create a database at the given path.
+    ///
+    /// The special path `":memory:"` opens a fresh database held entirely
+    /// in memory, without a WAL. It is destroyed when the handle is dropped.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let path = path.as_ref();
-        let pager = pager::Pager::open(path, false)?;
-        let wal_path = path.with_extension("hdb-wal");
-        let wal = Some(wal::WalManager::open(&wal_path)?);
-        let mut buffer_pool = buffer::BufferPool::new(pager, wal, 1024)?;
-
-        // Initialize catalog from schema table
-        let schema_root = buffer_pool.pager().schema_root();
-        let catalog = if schema_root == 0 {
-            // New database — create schema table
-            let tree = btree::BTree::create(&mut buffer_pool)?;
-            buffer_pool.pager_mut().set_schema_root(tree.root_page())?;
-            catalog::Catalog::new()
-        } else {
-            catalog::Catalog::load(&mut buffer_pool, schema_root)?
-        };
+        Self::open_with_config(path, DatabaseConfig::default())
+    }
 
-        let txn_manager = mvcc::TransactionManager::new();
+    /// Open an existing database without write access.
+    ///
+    /// The file is opened read-only and no WAL file is created; a log left
+    /// by a writer is read so its changes are seen. Queries work as usual,
+    /// while any statement that would change the database fails with
+    /// [`HorizonError::ReadOnly`] before it runs.
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::builder().read_only(true).open(path)
+    }
+
+    /// Start building a database configuration; see [`DatabaseBuilder`].
+    pub fn builder() -> DatabaseBuilder {
+        DatabaseBuilder::new()
+    }
 
+    /// Open or create a database at the given path with custom options.
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: DatabaseConfig) -> Result<Self> {
+        let path = path.as_ref();
+        let mut inner = DatabaseInner::open(path, config)?;
+        // Connections wait for each other's row locks in write_waiting,
+        // without holding the database
+        inner.txn_manager.set_defer_lock_waits(true);
+        let txn_manager = mvcc::TransactionManager::sharing(&inner.txn_manager);
         Ok(Database {
             path: path.to_path_buf(),
-            inner: Mutex::new(DatabaseInner {
-                buffer_pool,
-                catalog,
-                txn_manager,
-            }),
+            inner: Arc::new(RwLock::new(inner)),
+            txn_manager: Mutex::new(txn_manager),
+        })
+    }
+
+    /// Open another connection to this database.
+    ///
+    /// The connection shares this one's pages, schema, settings, and
+    /// attached databases, but has transactions of its own. They lock the
+    /// rows they write in the lock table all connections share: a write
+    /// to a row another connection's open transaction has written waits
+    /// for that transaction to end, up to the busy timeout, and then fails
+    /// with [`HorizonError::LockConflict`]. Transactions that wait for
+    /// each other are detected, and one of them fails with
+    /// [`HorizonError::Deadlock`] and is rolled back.
+    ///
+    /// Reads take no locks and read the shared pages as they are, so the
+    /// connections run at the READ UNCOMMITTED isolation level: a query
+    /// sees rows another connection's open transaction has written, and
+    /// they disappear again if it rolls back. To read only committed rows,
+    /// take a snapshot with [`Database::begin_snapshot`] and query it with
+    /// [`Database::query_at_snapshot`].
+    ///
+    /// ```no_run
+    /// # let db = horizon::Database::open("app.db")?;
+    /// let other = db.connect()?;
+    /// std::thread::spawn(move || other.execute("UPDATE counters SET n = n + 1"));
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    pub fn connect(&self) -> Result<Database> {
+        let txn_manager = mvcc::TransactionManager::sharing(&self.read()?.txn_manager);
+        Ok(Database {
+            path: self.path.clone(),
+            inner: Arc::clone(&self.inner),
+            txn_manager: Mutex::new(txn_manager),
         })
     }
 
     /// Execute a SQL statement that doesn't return rows.
     pub fn execute(&self, sql_text: &str) -> Result<usize> {
+        self.with_hooks(sql_text, |changed| *changed, || {
+            let stmts = sql::parser::Parser::parse(sql_text)?;
+            self.execute_statements(stmts)
+        })
+    }
+
+    /// Execute a SQL statement with positional bind parameters.
+    ///
+    /// `?`, `?N`, `$name`, `:name`, and `@name` placeholders are bound from
+    /// `params` by index; see [`execution::params::bind_positional`].
+    pub fn execute_params(&self, sql_text: &str, params: &[Value]) -> Result<usize> {
+        self.with_hooks(sql_text, |changed| *changed, || {
+            let mut stmts = sql::parser::Parser::parse(sql_text)?;
+            execution::params::bind_positional(&mut stmts, params)?;
+            self.execute_statements(stmts)
+        })
+    }
+
+    /// Execute a SQL statement with named bind parameters.
+    ///
+    /// Names may be given with or without their prefix (`":id"` or `"id"`).
+    pub fn execute_named(&self, sql_text: &str, params: &[(&str, Value)]) -> Result<usize> {
+        self.with_hooks(sql_text, |changed| *changed, || {
+            let mut stmts = sql::parser::Parser::parse(sql_text)?;
+            execution::params::bind_named(&mut stmts, params)?;
+            self.execute_statements(stmts)
+        })
+    }
+
+    /// Parse a SQL statement once, to be executed any number of times with
+    /// [`PreparedStatement::execute`] or [`PreparedStatement::query`].
+    pub fn prepare(&self, sql_text: &str) -> Result<PreparedStatement> {
+        PreparedStatement::new(sql_text, self.schema_generation()?)
+    }
+
+    /// Execute a SQL query that returns rows.
+    ///
+    /// With a query cache (see [`DatabaseConfig::enable_query_cache`]), a
+    /// SELECT whose result is cached and still current is answered from
+    /// the cache.
+    pub fn query(&self, sql_text: &str) -> Result<QueryResult> {
+        self.with_hooks(sql_text, QueryResult::len, || {
+            if let Some(result) = self.read()?.cached_result(sql_text)? {
+                return Ok(result);
+            }
+            let stmts = sql::parser::Parser::parse(sql_text)?;
+            if let [stmt] = stmts.as_slice() {
+                if let Some(result) = self.read()?.query_and_cache(sql_text, stmt)? {
+                    return Ok(result);
+                }
+            }
+            self.query_statements(stmts)
+        })
+    }
+
+    /// Execute a SQL query that stops with [`HorizonError::Cancelled`] once
+    /// `cancel` is set, which another thread may do at any time.
+    ///
+    /// A [`QueryHandle`] wraps such a flag:
+    ///
+    /// ```no_run
+    /// # let db = horizon::Database::open(":memory:")?;
+    /// let handle = horizon::QueryHandle::new();
+    /// let canceller = handle.clone();
+    /// std::thread::spawn(move || canceller.cancel());
+    /// let result = db.query_cancellable("SELECT * FROM big", handle.flag());
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    pub fn query_cancellable(&self, sql_text: &str, cancel: Arc<AtomicBool>) -> Result<QueryResult> {
+        self.with_hooks(sql_text, QueryResult::len, || {
+            let stmts = sql::parser::Parser::parse(sql_text)?;
+            cancel::with_cancel_flag(cancel, || self.query_statements(stmts))
+        })
+    }
+
+    /// Open a snapshot of the database as it is now.
+    ///
+    /// Queries run with [`Database::query_at_snapshot`] keep seeing the
+    /// rows as they were when the snapshot was taken, whatever is written
+    /// afterwards. Changes made by transactions that are still open when
+    /// the snapshot is taken are never part of it, even if those
+    /// transactions commit later. The snapshot stays open, and the row
+    /// versions it needs stay in memory, until it is passed to
+    /// [`Database::release_snapshot`].
+    ///
+    /// ```no_run
+    /// # let db = horizon::Database::open(":memory:")?;
+    /// let snapshot = db.begin_snapshot()?;
+    /// db.execute("UPDATE accounts SET balance = 0")?;
+    /// let before = db.query_at_snapshot(snapshot, "SELECT balance FROM accounts")?;
+    /// db.release_snapshot(snapshot)?;
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    pub fn begin_snapshot(&self) -> Result<SnapshotId> {
+        Ok(self.write()?.txn_manager.begin_snapshot())
+    }
+
+    /// Run a SELECT or EXPLAIN statement against the rows visible to
+    /// `snapshot`.
+    ///
+    /// Returns [`HorizonError::TransactionError`] if the snapshot is not
+    /// open.
+    pub fn query_at_snapshot(&self, snapshot: SnapshotId, sql_text: &str) -> Result<QueryResult> {
         let stmts = sql::parser::Parser::parse(sql_text)?;
-        if stmts.is_empty() {
-            return Ok(0);
+        let stmt = match stmts.into_iter().next() {
+            Some(stmt) if execution::is_read_only(&stmt) => stmt,
+            _ => {
+                return Err(HorizonError::InvalidSql(
+                    "query_at_snapshot requires a SELECT or EXPLAIN statement".into(),
+                ))
+            }
+        };
+        let inner = self.read()?;
+        let view = inner.txn_manager.snapshot_view(snapshot)?;
+        mvcc::snapshot::with_snapshot(view, || inner.query_read_only(stmt))
+    }
+
+    /// Close a snapshot opened by [`Database::begin_snapshot`].
+    pub fn release_snapshot(&self, snapshot: SnapshotId) -> Result<()> {
+        self.write()?.txn_manager.release_snapshot(snapshot)
+    }
+
+    /// Execute a SQL query that is expected to return at most one row.
+    ///
+    /// Returns `Ok(None)` when no row matches and
+    /// [`HorizonError::TooManyRows`] when more than one does.
+    pub fn query_row(&self, sql_text: &str) -> Result<Option<Row>> {
+        single_row(self.query(sql_text)?)
+    }
+
+    /// Execute a single-row query with positional bind parameters.
+    ///
+    /// See [`Database::query_row`].
+    pub fn query_row_params(&self, sql_text: &str, params: &[Value]) -> Result<Option<Row>> {
+        single_row(self.query_params(sql_text, params)?)
+    }
+
+    /// Execute a SQL query, producing rows lazily instead of collecting them.
+    ///
+    /// Simple single-table scans are read from the B+Tree one row at a time;
+    /// other queries are executed up front and then iterated.
+    pub fn query_iter<'db>(&'db self, sql_text: &str) -> Result<RowIter<'db>> {
+        let mut stmts = sql::parser::Parser::parse(sql_text)?;
+
+        let stream = match stmts.first_mut() {
+            Some(stmt) if execution::is_read_only(stmt) => {
+                let inner = self.read()?;
+                inner.resolve_schemas(stmt)?;
+                let stream = execution::stream::open_select_stream(
+                    stmt,
+                    &inner.buffer_pool,
+                    &inner.catalog,
+                )?;
+                return Ok(RowIter { inner, stream });
+            }
+            Some(_) => {
+                let result = self.query_statements(stmts)?;
+                execution::stream::SelectStream::materialized(result)
+            }
+            None => execution::stream::SelectStream::empty(),
+        };
+        Ok(RowIter { inner: self.read()?, stream })
+    }
+
+    /// Execute a SQL query with positional bind parameters.
+    pub fn query_params(&self, sql_text: &str, params: &[Value]) -> Result<QueryResult> {
+        self.with_hooks(sql_text, QueryResult::len, || {
+            let mut stmts = sql::parser::Parser::parse(sql_text)?;
+            execution::params::bind_positional(&mut stmts, params)?;
+            self.query_statements(stmts)
+        })
+    }
+
+    /// Execute a SQL query with named bind parameters.
+    pub fn query_named(&self, sql_text: &str, params: &[(&str, Value)]) -> Result<QueryResult> {
+        self.with_hooks(sql_text, QueryResult::len, || {
+            let mut stmts = sql::parser::Parser::parse(sql_text)?;
+            execution::params::bind_named(&mut stmts, params)?;
+            self.query_statements(stmts)
+        })
+    }
+
+    /// Register `compare` as the collation `name`, for use in
+    /// `ORDER BY expr COLLATE name` and in index definitions. Names are
+    /// case-insensitive, and a registered collation replaces a built-in or
+    /// earlier one of the same name. An index using the collation keeps its
+    /// entries in the collation's order, so replacing the collation once
+    /// the index holds rows leaves them out of order.
+    ///
+    /// The collation belongs to this handle: other handles on the same
+    /// file do not see it.
+    ///
+    /// ```no_run
+    /// # let db = horizon::Database::open(":memory:")?;
+    /// db.create_collation("LENGTH", |a, b| a.len().cmp(&b.len()).then(a.cmp(b)))?;
+    /// let result = db.query("SELECT name FROM users ORDER BY name COLLATE length")?;
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    pub fn create_collation<F>(&self, name: &str, compare: F) -> Result<()>
+    where
+        F: Fn(&str, &str) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        let mut inner = self.write()?;
+        inner.catalog.create_collation(name, Arc::new(compare));
+        // Cached results may have been sorted by an earlier collation
+        inner.clear_query_cache();
+        Ok(())
+    }
+
+    /// Register `tokenizer` as the FTS5 tokenizer `name`, for FTS5 tables
+    /// created with `USING fts5(..., tokenize = 'name')`. Names are
+    /// case-insensitive, and a registered tokenizer replaces the built-in
+    /// (`simple` and `porter`) or earlier one of the same name. Tables
+    /// keep the tokenizer they were created with.
+    ///
+    /// The tokenizer belongs to this handle: other handles on the same
+    /// file do not see it.
+    ///
+    /// ```no_run
+    /// use horizon::execution::fts5::{Fts5Tokenizer, Token};
+    ///
+    /// /// Splits on commas.
+    /// struct Tags;
+    ///
+    /// impl Fts5Tokenizer for Tags {
+    ///     fn tokenize(&self, text: &str) -> Vec<Token> {
+    ///         let mut start = 0;
+    ///         let mut tokens = Vec::new();
+    ///         for (position, tag) in text.split(',').enumerate() {
+    ///             let end = start + tag.len();
+    ///             tokens.push(Token { text: tag.trim().to_lowercase(), start, end, position });
+    ///             start = end + 1;
+    ///         }
+    ///         tokens
+    ///     }
+    /// }
+    ///
+    /// # let db = horizon::Database::open(":memory:")?;
+    /// db.register_fts5_tokenizer("tags", Box::new(Tags))?;
+    /// db.execute("CREATE VIRTUAL TABLE photos USING fts5(tags, tokenize = 'tags')")?;
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    pub fn register_fts5_tokenizer(&self, name: &str, tokenizer: Box<dyn Fts5Tokenizer + Send + Sync>) -> Result<()> {
+        self.write()?.catalog.register_fts5_tokenizer(name, Arc::from(tokenizer));
+        Ok(())
+    }
+
+    /// Call `hook` with the SQL text of each statement before it is parsed
+    /// and run, replacing any earlier pre-execute hook.
+    ///
+    /// Hooks are called by [`Database::execute`], [`Database::query`], their
+    /// variants taking parameters or a cancel flag, and [`PreparedStatement`].
+    /// They make it possible to log, time, or audit statements:
+    ///
+    /// ```no_run
+    /// # let db = horizon::Database::open(":memory:")?;
+    /// db.set_pre_execute_hook(|sql| eprintln!("running {}", sql))?;
+    /// db.set_post_execute_hook(|sql, result| {
+    ///     if let Err(e) = result {
+    ///         eprintln!("{} failed: {}", sql, e);
+    ///     }
+    /// })?;
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    pub fn set_pre_execute_hook<F>(&self, hook: F) -> Result<()>
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.write()?.pre_execute_hook = Some(Arc::new(hook));
+        Ok(())
+    }
+
+    /// Call `hook` with the SQL text and the result of each statement after
+    /// it runs, replacing any earlier post-execute hook. The result is the
+    /// number of rows changed for [`Database::execute`] and returned for
+    /// [`Database::query`], or the error the statement failed with.
+    ///
+    /// See [`Database::set_pre_execute_hook`].
+    pub fn set_post_execute_hook<F>(&self, hook: F) -> Result<()>
+    where
+        F: Fn(&str, &Result<usize>) + Send + Sync + 'static,
+    {
+        self.write()?.post_execute_hook = Some(Arc::new(hook));
+        Ok(())
+    }
+
+    /// Record each statement that takes longer than `threshold` in the
+    /// slow query log, read with [`Database::slow_query_log`]. Changing the
+    /// threshold keeps the entries recorded so far.
+    ///
+    /// Statements are timed by the same methods that call the execute
+    /// hooks (see [`Database::set_pre_execute_hook`]); see [`slow_query`].
+    pub fn set_slow_query_threshold(&self, threshold: Duration) -> Result<()> {
+        let mut inner = self.write()?;
+        match inner.slow_query_log.as_mut() {
+            Some(log) => log
+                .get_mut()
+                .map_err(|_| HorizonError::Internal("lock poisoned".into()))?
+                .set_threshold(threshold),
+            None => inner.slow_query_log = Some(Mutex::new(slow_query::SlowQueryLog::new(threshold))),
         }
+        Ok(())
+    }
 
-        let mut inner = self.inner.lock().map_err(|_| {
-            HorizonError::Internal("mutex poisoned".into())
-        })?;
+    /// The most recent statements that took longer than the slow query
+    /// threshold, oldest first. Empty if no threshold has been set.
+    pub fn slow_query_log(&self) -> Result<Vec<SlowQueryEntry>> {
+        match &self.read()?.slow_query_log {
+            Some(log) => Ok(log.lock().map_err(|_| HorizonError::Internal("lock poisoned".into()))?.entries()),
+            None => Ok(Vec::new()),
+        }
+    }
 
+    /// Record the rows inserted into, updated in, and deleted from the
+    /// table `table_name` in the CDC log, read with
+    /// [`Database::cdc_events_since`]. See [`cdc`] for how changes are
+    /// recorded.
+    ///
+    /// Enabling CDC on a table again replaces its triggers, picking up
+    /// columns added, renamed, or dropped since.
+    pub fn enable_cdc(&self, table_name: &str) -> Result<()> {
+        let columns: Vec<String> = {
+            let inner = self.read()?;
+            inner.catalog.get_table(table_name)?.columns.iter().map(|c| c.name.clone()).collect()
+        };
+        let stmts = sql::parser::Parser::parse(&cdc::enable_sql(table_name, &columns)?)?;
+        self.execute_statements(stmts)?;
+        Ok(())
+    }
+
+    /// The changes recorded in the CDC log after the event with id
+    /// `cursor`, oldest first. Pass 0 for every event, and then the id of
+    /// the last event returned to poll for new ones:
+    ///
+    /// ```no_run
+    /// # let db = horizon::Database::open(":memory:")?;
+    /// db.enable_cdc("users")?;
+    /// let mut cursor = 0;
+    /// for event in db.cdc_events_since(cursor)? {
+    ///     println!("{} {:?}: {:?}", event.table_name, event.operation, event.new_row_json);
+    ///     cursor = event.id;
+    /// }
+    /// # Ok::<(), horizon::HorizonError>(())
+    /// ```
+    ///
+    /// Returns no events if CDC has not been enabled on any table.
+    pub fn cdc_events_since(&self, cursor: i64) -> Result<Vec<CdcEvent>> {
+        if !self.read()?.catalog.table_exists(cdc::CDC_LOG_TABLE) {
+            return Ok(Vec::new());
+        }
+        let stmts = sql::parser::Parser::parse(&cdc::events_since_sql(cursor))?;
+        cdc::events(self.query_statements(stmts)?)
+    }
+
+    /// Create a full-text index on the `columns` of the table `table`, so
+    /// that `WHERE column MATCH 'query'` on the table (or `WHERE table
+    /// MATCH 'query'`, for every indexed column) is answered by an FTS5
+    /// search. See [`fulltext`] for how the index is kept up to date.
+    ///
+    /// The table needs an INTEGER PRIMARY KEY, and can have one full-text
+    /// index.
+    pub fn create_fulltext_index(&self, table: &str, columns: &[&str]) -> Result<()> {
+        let mut inner = self.write()?;
+        let info = inner.catalog.get_table(table)?;
+        let rowid_column = fulltext::rowid_column(info)?;
+        let columns = columns
+            .iter()
+            .map(|&name| match info.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name)) {
+                Some(column) => Ok(column.name.clone()),
+                None => Err(HorizonError::ColumnNotFound(format!("{}.{}", table, name))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if columns.is_empty() {
+            return Err(HorizonError::InvalidSql("a full-text index needs at least one column".into()));
+        }
+        let index = catalog::FulltextIndexInfo {
+            table: info.name.clone(),
+            fts_table: fulltext::shadow_table_name(&info.name),
+            rowid_column,
+            columns,
+        };
+        if inner.catalog.get_fulltext_index(&index.table).is_some() {
+            return Err(HorizonError::DuplicateIndex(format!("full-text index on {}", index.table)));
+        }
+        let stmts = sql::parser::Parser::parse(&fulltext::create_sql(&index.table, &index.rowid_column, &index.columns))?;
+        inner.execute_statements(stmts)?;
+        inner.catalog.create_fulltext_index(index)
+    }
+
+    /// The `k` entries of the R-tree virtual table `table` nearest to
+    /// `point`, nearest first, as `SELECT * FROM table ORDER BY
+    /// rtree_distance(...) LIMIT k` would return them. `point` has a
+    /// coordinate per dimension of the table; see
+    /// [`execution::rtree::knn_query`] for how distances are measured.
+    pub fn knn_query(&self, table: &str, point: &[f64], k: usize) -> Result<QueryResult> {
+        let inner = self.read()?;
+        execution::rtree::knn_query(table, point, k, &inner.buffer_pool, &inner.catalog)
+    }
+
+    /// Run the statement `sql_text` with `run`, calling the execute hooks
+    /// before and after it and recording it in the slow query log if it
+    /// is slow. `count` gives the row count the post-execute hook is passed
+    /// and the log records for a result.
+    ///
+    /// The hooks are called without holding the database lock, so they
+    /// may use the database themselves.
+    fn with_hooks<T>(&self, sql_text: &str, count: impl Fn(&T) -> usize, run: impl FnOnce() -> Result<T>) -> Result<T> {
+        let (pre, post, timed) = {
+            let inner = self.read()?;
+            (inner.pre_execute_hook.clone(), inner.post_execute_hook.clone(), inner.slow_query_log.is_some())
+        };
+        if let Some(pre) = pre {
+            pre(sql_text);
+        }
+        let started = Instant::now();
+        let result = run();
+        if let (true, Ok(value)) = (timed, &result) {
+            self.log_if_slow(sql_text, started.elapsed(), count(value));
+        }
+        let post = match post {
+            Some(post) => post,
+            None => return result,
+        };
+        match result {
+            Ok(value) => {
+                post(sql_text, &Ok(count(&value)));
+                Ok(value)
+            }
+            Err(e) => {
+                post(sql_text, &Err(e.clone()));
+                Err(e)
+            }
+        }
+    }
+
+    /// Record the statement `sql_text`, which took `duration` and changed
+    /// or returned `rows` rows, in the slow query log if it is slow.
+    fn log_if_slow(&self, sql_text: &str, duration: Duration, rows: usize) {
+        if let Ok(inner) = self.read() {
+            if let Some(Ok(mut log)) = inner.slow_query_log.as_ref().map(Mutex::lock) {
+                log.record(sql_text, duration, rows);
+            }
+        }
+    }
+
+    fn execute_statements(&self, stmts: Vec<sql::ast::Statement>) -> Result<usize> {
         let mut total = 0;
         for stmt in stmts {
-            let DatabaseInner { buffer_pool, catalog, txn_manager } = &mut *inner;
-            total += execution::execute_statement(&stmt, buffer_pool, catalog, txn_manager)?;
+            total += self.write_waiting(|inner| inner.execute_statement(stmt.clone()))?;
         }
         Ok(total)
     }
 
-    /// Execute a SQL query that returns rows.
-    pub fn query(&self, sql_text: &str) -> Result<QueryResult> {
-        let stmts = sql::parser::Parser::parse(sql_text)?;
+    fn query_statements(&self, mut stmts: Vec<sql::ast::Statement>) -> Result<QueryResult> {
         if stmts.is_empty() {
             return Ok(QueryResult {
                 columns: Arc::new(vec![]),
                 rows: vec![],
             });
         }
+        if execution::is_read_only(&stmts[0]) {
+            return self.read()?.query_read_only(stmts.swap_remove(0));
+        }
+        self.write_waiting(|inner| inner.query_statements(stmts.clone()))
+    }
+
+    /// Run `run` with the database locked for writing. If it stops at a
+    /// row another connection's transaction holds, wait for that row
+    /// without holding the database, so the other transaction can end,
+    /// and run it again.
+    fn write_waiting<T>(&self, mut run: impl FnMut(&mut DatabaseInner) -> Result<T>) -> Result<T> {
+        loop {
+            let wait = {
+                let mut inner = self.write()?;
+                let result = run(&mut inner);
+                match inner.txn_manager.take_lock_wait() {
+                    Some(wait) if matches!(result, Err(HorizonError::LockConflict(_))) => wait,
+                    _ => return result,
+                }
+            };
+            if let Err(e) = wait.wait() {
+                if matches!(e, HorizonError::Deadlock(_)) {
+                    // Release the rows the other transactions wait for
+                    self.write()?.roll_back()?;
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    fn schema_generation(&self) -> Result<u64> {
+        Ok(self.read()?.catalog.schema_generation())
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, DatabaseInner>> {
+        self.inner.read().map_err(|_| {
+            HorizonError::Internal("lock poisoned".into())
+        })
+    }
 
-        let mut inner = self.inner.lock().map_err(|_| {
-            HorizonError::Internal("mutex poisoned".into())
+    fn write(&self) -> Result<WriteGuard<'_>> {
+        let inner = self.inner.write().map_err(|_| {
+            HorizonError::Internal("lock poisoned".into())
         })?;
+        let txn_manager = self.txn_manager.lock().map_err(|_| {
+            HorizonError::Internal("lock poisoned".into())
+        })?;
+        Ok(WriteGuard::new(inner, txn_manager))
+    }
 
-        let DatabaseInner { buffer_pool, catalog, txn_manager } = &mut *inner;
+    /// Render the whole database as a SQL script: its tables and their
+    /// rows, indexes, and views, wrapped in a transaction.
+    ///
+    /// Executing the script against an empty database, for example with
+    /// [`Database::restore_sql`], recreates this one. See
+    /// [`execution::dump::dump_sql`] for the exact layout.
+    pub fn dump_sql(&self) -> Result<String> {
+        let inner = self.read()?;
+        execution::dump::dump_sql(&inner.buffer_pool, &inner.catalog)
+    }
 
-        // Route SELECT, PRAGMA, EXPLAIN, and RETURNING through execute_query
-        match &stmts[0] {
-            sql::ast::Statement::Select(_)
-            | sql::ast::Statement::Pragma(_)
-            | sql::ast::Statement::Explain(_)
-            | sql::ast::Statement::ExplainQueryPlan(_) => {
-                execution::execute_query(&stmts[0], buffer_pool, catalog, txn_manager)
+    /// Execute a SQL script such as one produced by [`Database::dump_sql`].
+    ///
+    /// If a statement fails, the transaction the script opened is rolled
+    /// back before the error is returned.
+    pub fn restore_sql(&self, sql_text: &str) -> Result<()> {
+        match self.execute(sql_text) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // There may be no open transaction to roll back
+                let _ = self.execute("ROLLBACK");
+                Err(e)
             }
-            stmt if execution::has_returning(stmt) => {
-                execution::execute_query(stmt, buffer_pool, catalog, txn_manager)
+        }
+    }
+
+    /// Copy the tables, rows, indexes, views, and triggers of the SQLite
+    /// database file at `sqlite_path` into this database.
+    ///
+    /// The file is read directly, without SQLite. Its tables must not
+    /// exist here yet. Every schema statement is checked before anything
+    /// is created. If the import fails, the rows copied are rolled back
+    /// and the tables, indexes, views, and triggers created are dropped
+    /// again; see [`sqlite_compat`] for what can be imported.
+    pub fn import_from_sqlite<P: AsRef<Path>>(&self, sqlite_path: P) -> Result<()> {
+        sqlite_compat::import(self, sqlite_path.as_ref())
+    }
+
+    /// Write a consistent copy of the database to a new file at `path`,
+    /// replacing any database already there.
+    ///
+    /// Cached pages are flushed and the write-ahead log is checkpointed
+    /// first, so the copy is a complete database file on its own. The
+    /// database is locked against writes while it is copied; queries on
+    /// other threads wait too. An in-memory database can be backed up to
+    /// a file this way.
+    pub fn backup_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.backup_to_with_progress(path, |_, _| {})
+    }
+
+    /// Like [`Database::backup_to`], calling `progress` after each page is
+    /// copied with the number of pages copied so far and the total.
+    pub fn backup_to_with_progress<P, F>(&self, path: P, progress: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(u32, u32),
+    {
+        let path = path.as_ref();
+        let mut inner = self.write()?;
+        inner.checkpoint(CheckpointMode::Truncate)?;
+        inner.buffer_pool.pager().copy_to_file(path, progress)?;
+
+        // A log or journal left over from a database previously at `path`
+        // would be replayed over the copy when it is opened
+        for leftover in [path.with_extension("hdb-wal"), wal::journal_path(path)] {
+            match std::fs::remove_file(leftover) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
             }
-            _ => Err(HorizonError::Internal(
-                "query() requires a SELECT, PRAGMA, EXPLAIN, or RETURNING statement".into(),
-            )),
         }
+        Ok(())
+    }
+
+    /// Checkpoint the write-ahead log into the database file.
+    ///
+    /// This is the same operation as `PRAGMA wal_checkpoint(mode)`.
+    pub fn checkpoint(&self, mode: CheckpointMode) -> Result<CheckpointResult> {
+        self.write()?.checkpoint(mode)
+    }
+
+    /// Whether the database was opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read().is_ok_and(|inner| inner.config.read_only)
+    }
+
+    /// Whether this is an in-memory (`:memory:`) database.
+    pub fn is_memory(&self) -> bool {
+        self.path.as_os_str() == MEMORY_PATH
+    }
+
+    /// The number of page reads the buffer pool has served, from its cache
+    /// or from disk. The count starts over when the storage is reopened
+    /// after another connection changed the file.
+    pub fn page_accesses(&self) -> u64 {
+        self.read().map_or(0, |inner| inner.buffer_pool.page_accesses())
     }
 
     /// Get the file path.
@@ -169,12 +1400,97 @@ impl Database {
         &self.path
     }
 
-    /// Close the database, flushing all writes.
+    /// Close this connection, rolling back a transaction left open.
+    /// Closing the last connection to the database flushes all writes.
     pub fn close(self) -> Result<()> {
-        let mut inner = self.inner.into_inner().map_err(|_| {
-            HorizonError::Internal("mutex poisoned".into())
-        })?;
-        inner.buffer_pool.flush_all()?;
-        Ok(())
+        let inner = Arc::clone(&self.inner);
+        drop(self);
+        match Arc::try_unwrap(inner) {
+            Ok(inner) => inner
+                .into_inner()
+                .map_err(|_| HorizonError::Internal("lock poisoned".into()))?
+                .flush(),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // Release the rows an open transaction locked, which other
+        // connections may be waiting for
+        let open = self.txn_manager.get_mut().is_ok_and(|mgr| mgr.is_user_txn_active());
+        if open {
+            if let Ok(mut inner) = self.write() {
+                let _ = inner.roll_back();
+            }
+        }
+    }
+}
+
+/// Open the pager, WAL, buffer pool, and catalog of the database at `path`.
+///
+/// The write lock is taken into `write_lock` first if opening has to
+/// write: to create the file and its schema table, or to fold a leftover
+/// WAL into the file when journaling is off.
+fn open_storage(
+    path: &Path,
+    config: &DatabaseConfig,
+    write_lock: &mut Option<wal::WriteLock>,
+) -> Result<(buffer::BufferPool, catalog::Catalog)> {
+    if config.cache_size == 0 {
+        return Err(HorizonError::InvalidSql("cache size must be at least 1 page".into()));
     }
+    let memory = path.as_os_str() == MEMORY_PATH;
+    let lock = |write_lock: &mut Option<wal::WriteLock>| -> Result<()> {
+        if write_lock.is_none() && !memory && !config.read_only {
+            *write_lock = Some(wal::WriteLock::acquire(path, config.busy_timeout, config.on_busy)?);
+        }
+        Ok(())
+    };
+
+    let (pager, wal) = if memory {
+        if config.read_only {
+            return Err(HorizonError::ReadOnly(
+                "cannot create a new database in read-only mode".into(),
+            ));
+        }
+        (pager::Pager::open_memory(config.page_size.map(|size| size as usize))?, None)
+    } else {
+        let new_file = std::fs::metadata(path).map_or(true, |meta| meta.len() == 0);
+        let hot_journal = !config.read_only && wal::journal_path(path).exists();
+        if new_file || hot_journal || config.journal_mode != JournalMode::Wal {
+            lock(write_lock)?;
+        }
+        let mut pager = pager::Pager::open_with_config(path, config)?;
+        let wal_path = path.with_extension("hdb-wal");
+        let mut wal = wal::WalManager::open_with_config(&wal_path, pager.page_size(), config)?;
+        if config.journal_mode != JournalMode::Wal && !config.read_only {
+            // Fold any log left from an earlier session into the file
+            if let Some(mut log) = wal.take() {
+                log.checkpoint(&mut pager, CheckpointMode::Truncate)?;
+                drop(log);
+                std::fs::remove_file(&wal_path)?;
+            }
+        }
+        (pager, wal)
+    };
+    let mut buffer_pool = buffer::BufferPool::new(pager, wal, config.cache_size)?;
+    buffer_pool.set_synchronous(config.synchronous);
+
+    // Initialize catalog from schema table
+    let schema_root = buffer_pool.pager().schema_root();
+    let catalog = if schema_root == 0 {
+        // New database — create schema table
+        lock(write_lock)?;
+        let tree = btree::BTree::create(&mut buffer_pool)?;
+        buffer_pool.pager_mut().set_schema_root(tree.root_page())?;
+        // Write the schema table out at once so other handles can open
+        // the new file
+        buffer_pool.flush_all()?;
+        catalog::Catalog::new()
+    } else {
+        catalog::Catalog::load(&mut buffer_pool, schema_root)?
+    };
+    Ok((buffer_pool, catalog))
 }
@@ -0,0 +1,201 @@
+//! Schema migrations.
+//!
+//! A [`Migrator`] applies a list of [`Migration`]s to a database, each at
+//! most once, and records those it applied in the `__schema_migrations`
+//! table:
+//!
+//! ```sql
+//! CREATE TABLE __schema_migrations (
+//!     id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE, checksum TEXT NOT NULL,
+//!     down TEXT NOT NULL, applied_at TEXT NOT NULL
+//! )
+//! ```
+//!
+//! Migrations are identified by name. The checksum of each one's `up`
+//! script is recorded too, so that a migration changed after it was
+//! applied is reported rather than silently skipped. The `down` script is
+//! recorded so that [`Migrator::rollback`] can undo a migration without
+//! being given the list again.
+//!
+//! Each script runs in a transaction of its own, together with the update
+//! to `__schema_migrations`, so scripts must not begin or commit
+//! transactions themselves. If a script fails, its transaction is rolled
+//! back and the migration is not recorded. Rolling back restores the rows
+//! the script changed, but not the schema: tables or indexes it created,
+//! dropped, or altered before the failing statement stay that way.
+
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+use crate::Database;
+
+/// The table applied migrations are recorded in.
+pub const MIGRATIONS_TABLE: &str = "__schema_migrations";
+
+/// A schema change, as SQL scripts applying and undoing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    /// Identifies the migration among those applied to a database.
+    pub name: String,
+    /// The script applying the change.
+    pub up: String,
+    /// The script undoing the change; it may be empty.
+    pub down: String,
+}
+
+impl Migration {
+    pub fn new(name: impl Into<String>, up: impl Into<String>, down: impl Into<String>) -> Self {
+        Migration { name: name.into(), up: up.into(), down: down.into() }
+    }
+
+    /// The checksum recorded for the migration: the 64-bit FNV-1a hash of
+    /// its `up` script, in hex.
+    fn checksum(&self) -> String {
+        let hash = self.up.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        format!("{:016x}", hash)
+    }
+}
+
+/// Applies and rolls back the [`Migration`]s of a database.
+///
+/// ```no_run
+/// # use horizon::migration::{Migration, Migrator};
+/// # let db = horizon::Database::open("app.hdb")?;
+/// let migrations = [
+///     Migration::new("001_users", "CREATE TABLE users (id INTEGER PRIMARY KEY)", "DROP TABLE users"),
+///     Migration::new("002_users_name", "ALTER TABLE users ADD COLUMN name TEXT", "ALTER TABLE users DROP COLUMN name"),
+/// ];
+/// Migrator::new(&db).run(&migrations)?;
+/// # Ok::<(), horizon::HorizonError>(())
+/// ```
+pub struct Migrator<'db> {
+    db: &'db Database,
+}
+
+impl<'db> Migrator<'db> {
+    pub fn new(db: &'db Database) -> Self {
+        Migrator { db }
+    }
+
+    /// Apply the migrations in `migrations` that have not been applied yet,
+    /// in order, creating the migrations table first if needed.
+    ///
+    /// Fails with [`HorizonError::Migration`], before applying any, if a
+    /// migration that was applied has a different `up` script now. Stops
+    /// at the first migration that fails, with its error; the migrations
+    /// before it stay applied.
+    pub fn run(&self, migrations: &[Migration]) -> Result<()> {
+        self.db.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE, \
+             checksum TEXT NOT NULL, down TEXT NOT NULL, applied_at TEXT NOT NULL)",
+            MIGRATIONS_TABLE
+        ))?;
+        let applied = self.applied_checksums()?;
+        let mut pending = Vec::new();
+        for migration in migrations {
+            match applied.iter().find(|(name, _)| *name == migration.name) {
+                Some((_, checksum)) if *checksum != migration.checksum() => {
+                    return Err(HorizonError::Migration(format!(
+                        "{} has changed since it was applied",
+                        migration.name
+                    )));
+                }
+                Some(_) => {}
+                None => pending.push(migration),
+            }
+        }
+        for migration in pending {
+            self.in_transaction(|| {
+                self.db.execute(&migration.up)?;
+                self.db.execute_params(
+                    &format!(
+                        "INSERT INTO {} (name, checksum, down, applied_at) VALUES (?, ?, ?, DATETIME('now'))",
+                        MIGRATIONS_TABLE
+                    ),
+                    &[
+                        Value::Text(migration.name.clone()),
+                        Value::Text(migration.checksum()),
+                        Value::Text(migration.down.clone()),
+                    ],
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Undo the last `n` migrations applied, most recent first, by running
+    /// their `down` scripts. If fewer than `n` have been applied, all of
+    /// them are undone.
+    pub fn rollback(&self, n: usize) -> Result<()> {
+        if !self.has_migrations_table()? {
+            return Ok(());
+        }
+        let result = self.db.query_params(
+            &format!("SELECT id, down FROM {} ORDER BY id DESC LIMIT ?", MIGRATIONS_TABLE),
+            &[Value::Integer(n.min(i64::MAX as usize) as i64)],
+        )?;
+        for row in &result.rows {
+            let id: i64 = row.get_typed("id")?;
+            let down: String = row.get_typed("down")?;
+            self.in_transaction(|| {
+                if !down.trim().is_empty() {
+                    self.db.execute(&down)?;
+                }
+                self.db.execute_params(
+                    &format!("DELETE FROM {} WHERE id = ?", MIGRATIONS_TABLE),
+                    &[Value::Integer(id)],
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The names of the migrations applied, in the order they were applied.
+    pub fn applied(&self) -> Result<Vec<String>> {
+        Ok(self.applied_checksums()?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// The names and checksums of the applied migrations, in order.
+    fn applied_checksums(&self) -> Result<Vec<(String, String)>> {
+        if !self.has_migrations_table()? {
+            return Ok(Vec::new());
+        }
+        let result = self.db.query(&format!("SELECT name, checksum FROM {} ORDER BY id", MIGRATIONS_TABLE))?;
+        result
+            .rows
+            .iter()
+            .map(|row| Ok((row.get_typed("name")?, row.get_typed("checksum")?)))
+            .collect()
+    }
+
+    fn has_migrations_table(&self) -> Result<bool> {
+        Ok(self.db.read()?.catalog.table_exists(MIGRATIONS_TABLE))
+    }
+
+    /// Run `f` in a transaction, rolling it back if `f` fails.
+    fn in_transaction(&self, f: impl FnOnce() -> Result<usize>) -> Result<()> {
+        self.db.execute("BEGIN")?;
+        match f().and_then(|_| self.db.execute("COMMIT")) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // The transaction may already have ended
+                let _ = self.db.execute("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_are_fnv1a_of_the_up_script() {
+        assert_eq!(Migration::new("m", "", "").checksum(), "cbf29ce484222325");
+        assert_eq!(Migration::new("m", "a", "").checksum(), "af63dc4c8601ec8c");
+        // The down script and the name are not part of it
+        assert_eq!(Migration::new("x", "a", "DROP TABLE t").checksum(), "af63dc4c8601ec8c");
+    }
+}
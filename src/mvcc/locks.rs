@@ -0,0 +1,295 @@
+//! Row-level write locks.
+//!
+//! A [`RowLockTable`] maps each locked row, named by its table and B+Tree
+//! key, to the transaction that owns it. A transaction keeps its locks
+//! until [`RowLockTable::release_all`] is called for it at commit or
+//! rollback. A writer that finds a row locked by another transaction waits
+//! for it to be released, up to a timeout, and then fails with
+//! [`HorizonError::LockConflict`].
+//!
+//! ## Deadlocks
+//!
+//! While a transaction waits, the table records the edge "waiter waits for
+//! holder" in a wait-for graph. Before waiting, the graph is searched from
+//! the holder; reaching the waiter again means the transactions wait for
+//! each other in a cycle. The youngest transaction of the cycle (the one
+//! with the highest ID) is chosen as the victim and its lock request fails
+//! with [`HorizonError::Deadlock`], so it can roll back and release its
+//! locks.
+//!
+//! The table is shared through an `Arc` and may be used from several
+//! threads at once.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::error::{HorizonError, Result};
+use super::TxnId;
+
+/// A locked row: lowercased table name and row key.
+type RowId = (String, Vec<u8>);
+
+/// Row locks and the transactions that own them.
+#[derive(Debug, Default)]
+pub struct RowLockTable {
+    state: Mutex<LockState>,
+    /// Signalled whenever locks are released or a deadlock victim is chosen.
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    owners: HashMap<RowId, TxnId>,
+    /// The wait-for graph: each waiting transaction and the transaction
+    /// holding the row it waits for.
+    waits_for: HashMap<TxnId, TxnId>,
+    /// Waiting transactions chosen to break a deadlock, not yet woken.
+    victims: HashSet<TxnId>,
+}
+
+impl LockState {
+    /// Follow the wait-for graph from `holder`. If it leads back to
+    /// `waiter`, return the transactions of the cycle.
+    fn cycle_through(&self, waiter: TxnId, holder: TxnId) -> Option<Vec<TxnId>> {
+        let mut cycle = vec![waiter];
+        let mut visited = HashSet::new();
+        let mut next = Some(holder);
+        while let Some(txn) = next {
+            if txn == waiter {
+                return Some(cycle);
+            }
+            if !visited.insert(txn) {
+                // A cycle that does not involve the waiter
+                return None;
+            }
+            cycle.push(txn);
+            next = self.waits_for.get(&txn).copied();
+        }
+        None
+    }
+}
+
+impl RowLockTable {
+    /// Create an empty lock table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lock the row `key` of `table` for `txn`, waiting up to `timeout`
+    /// for another transaction to release it. Taking a lock `txn` already
+    /// owns succeeds immediately.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::LockConflict`] if the row is still locked by
+    /// another transaction when the timeout expires, and
+    /// [`HorizonError::Deadlock`] if `txn` was chosen to break a deadlock.
+    pub fn acquire(&self, table: &str, key: &[u8], txn: TxnId, timeout: Duration) -> Result<()> {
+        let row = (table.to_lowercase(), key.to_vec());
+        let mut state = self.wait_for(&row, table, txn, timeout)?;
+        state.owners.insert(row, txn);
+        Ok(())
+    }
+
+    /// Wait up to `timeout` until no transaction other than `txn` holds
+    /// the row, without locking it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::LockConflict`] and
+    /// [`HorizonError::Deadlock`] as [`acquire`](Self::acquire) does.
+    pub fn wait_until_free(&self, table: &str, key: &[u8], txn: TxnId, timeout: Duration) -> Result<()> {
+        let row = (table.to_lowercase(), key.to_vec());
+        self.wait_for(&row, table, txn, timeout).map(drop)
+    }
+
+    /// Block until `row` is unlocked or owned by `txn`, returning the
+    /// guard so the caller can take the lock atomically.
+    fn wait_for(
+        &self,
+        row: &RowId,
+        table: &str,
+        txn: TxnId,
+        timeout: Duration,
+    ) -> Result<MutexGuard<'_, LockState>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock()?;
+        let result = loop {
+            if state.victims.remove(&txn) {
+                break Err(HorizonError::Deadlock(format!(
+                    "transaction {} was rolled back to break a deadlock",
+                    txn
+                )));
+            }
+            let holder = match state.owners.get(row) {
+                Some(&holder) if holder != txn => holder,
+                _ => break Ok(()),
+            };
+            if let Some(cycle) = state.cycle_through(txn, holder) {
+                let victim = cycle.iter().copied().max().unwrap_or(txn);
+                if victim == txn {
+                    break Err(HorizonError::Deadlock(format!(
+                        "transaction {} waits for row in table {} held by transaction {}, \
+                         which waits for it",
+                        txn, table, holder
+                    )));
+                }
+                // Wake the victim; this transaction keeps waiting for the
+                // locks its rollback releases
+                state.victims.insert(victim);
+                self.released.notify_all();
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break Err(HorizonError::LockConflict(format!(
+                    "row in table {} is locked by transaction {}",
+                    table, holder
+                )));
+            }
+            state.waits_for.insert(txn, holder);
+            state = self
+                .released
+                .wait_timeout(state, deadline - now)
+                .map_err(|_| HorizonError::Internal("lock poisoned".into()))?
+                .0;
+        };
+        state.waits_for.remove(&txn);
+        result.map(|()| state)
+    }
+
+    /// Release every lock owned by `txn` and wake the writers waiting for
+    /// them.
+    pub fn release_all(&self, txn: TxnId) {
+        if let Ok(mut state) = self.state.lock() {
+            let before = state.owners.len();
+            state.owners.retain(|_, owner| *owner != txn);
+            state.victims.remove(&txn);
+            if state.owners.len() != before {
+                self.released.notify_all();
+            }
+        }
+    }
+
+    /// The transaction holding the lock on row `key` of `table`, if any.
+    pub fn owner(&self, table: &str, key: &[u8]) -> Option<TxnId> {
+        let state = self.state.lock().ok()?;
+        state.owners.get(&(table.to_lowercase(), key.to_vec())).copied()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, LockState>> {
+        self.state
+            .lock()
+            .map_err(|_| HorizonError::Internal("lock poisoned".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn conflicting_writer_times_out() {
+        let locks = RowLockTable::new();
+        locks.acquire("t", b"1", 1, Duration::ZERO).unwrap();
+        locks.acquire("T", b"1", 1, Duration::ZERO).unwrap();
+        locks.acquire("t", b"2", 2, Duration::ZERO).unwrap();
+        let err = locks.acquire("t", b"1", 2, Duration::from_millis(20)).unwrap_err();
+        assert!(matches!(err, HorizonError::LockConflict(_)));
+        assert_eq!(locks.owner("t", b"1"), Some(1));
+
+        locks.release_all(1);
+        assert_eq!(locks.owner("t", b"1"), None);
+        assert_eq!(locks.owner("t", b"2"), Some(2));
+        locks.wait_until_free("t", b"1", 3, Duration::ZERO).unwrap();
+        assert_eq!(locks.owner("t", b"1"), None);
+    }
+
+    #[test]
+    fn waiting_writer_gets_the_lock_once_released() {
+        let locks = Arc::new(RowLockTable::new());
+        locks.acquire("t", b"1", 1, Duration::ZERO).unwrap();
+        let waiter = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.acquire("t", b"1", 2, Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(20));
+        locks.release_all(1);
+        waiter.join().unwrap().unwrap();
+        assert_eq!(locks.owner("t", b"1"), Some(2));
+    }
+
+    #[test]
+    fn concurrent_updates_of_one_row_serialize() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        let locks = Arc::new(RowLockTable::new());
+        let writing = Arc::new(AtomicBool::new(false));
+        let conflicts = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<_> = (1..=2)
+            .map(|txn| {
+                let (locks, writing, conflicts) = (locks.clone(), writing.clone(), conflicts.clone());
+                thread::spawn(move || {
+                    // A writer that loses the race fails fast, then retries with a wait
+                    if locks.acquire("t", b"row", txn, Duration::ZERO).is_err() {
+                        conflicts.fetch_add(1, Ordering::SeqCst);
+                        locks.acquire("t", b"row", txn, Duration::from_secs(10)).unwrap();
+                    }
+                    assert!(!writing.swap(true, Ordering::SeqCst), "both writers hold the row");
+                    thread::sleep(Duration::from_millis(20));
+                    writing.store(false, Ordering::SeqCst);
+                    locks.release_all(txn);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(conflicts.load(Ordering::SeqCst) <= 1);
+        assert_eq!(locks.owner("t", b"row"), None);
+    }
+
+    #[test]
+    fn deadlock_victim_is_the_youngest_transaction() {
+        // Whichever of the two waits first, transaction 2 is the victim
+        for older_waits_first in [true, false] {
+            let locks = Arc::new(RowLockTable::new());
+            locks.acquire("t", b"a", 1, Duration::ZERO).unwrap();
+            locks.acquire("t", b"b", 2, Duration::ZERO).unwrap();
+            let (first, first_row, second, second_row) = match older_waits_first {
+                true => (1, b"b", 2, b"a"),
+                false => (2, b"a", 1, b"b"),
+            };
+            let waiter = {
+                let locks = Arc::clone(&locks);
+                thread::spawn(move || {
+                    let result = locks.acquire("t", first_row, first, Duration::from_secs(10));
+                    if result.is_err() {
+                        locks.release_all(first);
+                    }
+                    result
+                })
+            };
+            while locks.state.lock().unwrap().waits_for.get(&first) != Some(&second) {
+                thread::yield_now();
+            }
+            let result = locks.acquire("t", second_row, second, Duration::from_secs(10));
+            if result.is_err() {
+                locks.release_all(second);
+            }
+            let waiter = waiter.join().unwrap();
+
+            let (winner, loser) = match older_waits_first {
+                true => (waiter, result),
+                false => (result, waiter),
+            };
+            assert!(winner.is_ok());
+            assert!(matches!(loser, Err(HorizonError::Deadlock(_))));
+            assert_eq!(locks.owner("t", b"a"), Some(1));
+            assert_eq!(locks.owner("t", b"b"), Some(1));
+            assert!(locks.state.lock().unwrap().waits_for.is_empty());
+        }
+    }
+}
@@ -14,6 +14,9 @@
 //!   snapshot of active transactions at start time.
 //! - [`RowVersion`]: Per-row version metadata that is serialized into the
 //!   B+Tree value alongside the actual row data.
+//! - [`SnapshotView`]: The rows an open snapshot sees that have changed
+//!   since it was taken, applied to table scans by [`snapshot`].
+//! - [`RowLockTable`]: Row-level write locks owned by transactions.
 //!
 //! # Visibility rules
 //!
@@ -22,9 +25,24 @@
 //!    committed before T started and was **not** in T's active set.
 //! 2. The row has **not** been deleted, **or** the deleting transaction's
 //!    writes are not visible to T (by the same rules).
-
-use std::collections::{HashMap, HashSet};
+//!
+//! # Snapshots
+//!
+//! [`TransactionManager::begin_snapshot`] opens a snapshot at the latest
+//! transaction ID, noting the explicit transactions still running on any
+//! connection: their writes stay invisible to the snapshot even once they
+//! commit. While a snapshot or an explicit transaction is open, every
+//! change recorded with [`TransactionManager::record_undo`] also keeps the
+//! replaced row as an expired [`RowVersion`], so readers at a snapshot can
+//! still find the version that was committed for them.
+
+pub mod locks;
+pub mod snapshot;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 use crate::error::{HorizonError, Result};
 use crate::pager::PageId;
 
@@ -54,6 +72,12 @@ pub enum TxnState {
 /// A transaction ID.
 pub type TxnId = u64;
 
+/// The ID of an open snapshot: the last transaction whose writes it sees.
+pub type SnapshotId = TxnId;
+
+pub use locks::RowLockTable;
+pub use snapshot::SnapshotView;
+
 /// Represents a single transaction.
 #[derive(Debug)]
 pub struct Transaction {
@@ -151,6 +175,24 @@ impl RowVersion {
         let not_deleted = self.deleted_by == 0 || !txn.can_see(self.deleted_by);
         created_visible && not_deleted
     }
+
+    /// Check if this version is visible to a reader at `snapshot`, taken
+    /// while the transactions in `running` were in progress: it was created
+    /// by a transaction the snapshot sees and not deleted by one.
+    pub fn is_visible_at(&self, snapshot: SnapshotId, running: &HashSet<TxnId>) -> bool {
+        let sees = |writer: TxnId| writer <= snapshot && !running.contains(&writer);
+        sees(self.created_by) && (self.deleted_by == 0 || !sees(self.deleted_by))
+    }
+}
+
+/// The version history of one row key, kept while snapshots are open.
+#[derive(Debug, Clone, Default)]
+struct KeyVersions {
+    /// Transaction that wrote the value currently in the B+Tree (`0` if it
+    /// predates every open snapshot).
+    current_created_by: TxnId,
+    /// Replaced versions, oldest first, each with `deleted_by` set.
+    expired: Vec<RowVersion>,
 }
 
 /// An undo log entry that records what to do to reverse a mutation.
@@ -196,15 +238,82 @@ pub enum UndoEntry {
 /// layers consult it to decide whether a particular row version should be
 /// visible to a given transaction.
 pub struct TransactionManager {
-    /// Monotonically increasing counter used to mint new transaction IDs.
-    next_txn_id: AtomicU64,
+    /// Monotonically increasing counter used to mint new transaction IDs,
+    /// shared with managers created by [`sharing`](Self::sharing).
+    next_txn_id: Arc<AtomicU64>,
     /// Mapping from transaction ID to its current state.
     active_txns: HashMap<TxnId, TxnState>,
     /// Whether there is an explicit user transaction currently in progress.
     user_txn_active: bool,
+    /// ID of the explicit user transaction, which owns its row locks.
+    user_txn_id: Option<TxnId>,
+    /// Row-level write locks, possibly shared with other managers.
+    row_locks: Arc<RowLockTable>,
+    /// How long a writer waits for a row locked by another transaction.
+    lock_timeout: Duration,
+    /// Whether [`lock_row`](Self::lock_row) leaves waiting for a locked
+    /// row to the caller; see [`set_defer_lock_waits`](Self::set_defer_lock_waits).
+    defer_lock_waits: bool,
+    /// The row the current statement failed to lock while lock waits are
+    /// deferred.
+    blocked_on: Option<(String, Vec<u8>)>,
+    /// The changes of the current statement, logged while lock waits are
+    /// deferred so the statement can be undone.
+    statement_undo: Vec<UndoEntry>,
     /// Undo log for the current explicit user transaction.
     /// Entries are appended during mutations and replayed in reverse on ROLLBACK.
     undo_log: Vec<UndoEntry>,
+    /// Open savepoints, innermost last. Each frame holds the undo entries
+    /// recorded since that savepoint was established.
+    savepoints: Vec<(String, Vec<UndoEntry>)>,
+    /// Whether the current user transaction was started implicitly by a
+    /// `SAVEPOINT` outside `BEGIN`, in which case releasing the outermost
+    /// savepoint commits it.
+    savepoint_started_txn: bool,
+    /// Open snapshots and row histories, shared with managers created by
+    /// [`sharing`](Self::sharing) so a snapshot keeps the rows every
+    /// connection replaces.
+    snapshots: Arc<Mutex<Snapshots>>,
+}
+
+#[derive(Debug, Default)]
+struct Snapshots {
+    /// Open snapshots, each with the explicit transactions that were
+    /// running when it was taken.
+    open: BTreeMap<SnapshotId, HashSet<TxnId>>,
+    /// Explicit transactions in progress on any connection.
+    running: HashSet<TxnId>,
+    /// Row histories recorded while snapshots or explicit transactions
+    /// are open, by lowercased table name and row key.
+    versions: HashMap<String, BTreeMap<Vec<u8>, KeyVersions>>,
+}
+
+impl Snapshots {
+    /// Whether a snapshot open now, or one taken later, may not see the
+    /// writes of transaction `writer`.
+    fn hides(&self, writer: TxnId) -> bool {
+        self.running.contains(&writer)
+            || self.open.iter().any(|(&snapshot, running)| writer > snapshot || running.contains(&writer))
+    }
+
+    /// Drop the row versions no snapshot can need any more.
+    fn prune(&mut self) {
+        let mut versions = std::mem::take(&mut self.versions);
+        for keys in versions.values_mut() {
+            keys.retain(|_, history| {
+                history.expired.retain(|version| self.hides(version.deleted_by));
+                !history.expired.is_empty() || self.hides(history.current_created_by)
+            });
+        }
+        versions.retain(|_, keys| !keys.is_empty());
+        self.versions = versions;
+    }
+}
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TransactionManager {
@@ -212,11 +321,43 @@ impl TransactionManager {
     ///
     /// The first transaction ID issued will be `1`.
     pub fn new() -> Self {
+        Self::with_shared(Arc::new(AtomicU64::new(1)), Arc::new(RowLockTable::new()), Arc::default())
+    }
+
+    /// Create a transaction manager for another connection to the same
+    /// database as `other`. Both draw transaction IDs from one counter,
+    /// lock rows in one [`RowLockTable`], and share their snapshots, so
+    /// their transactions' writes to the same row conflict.
+    pub fn sharing(other: &TransactionManager) -> Self {
+        let mut mgr = Self::with_shared(
+            Arc::clone(&other.next_txn_id),
+            Arc::clone(&other.row_locks),
+            Arc::clone(&other.snapshots),
+        );
+        mgr.lock_timeout = other.lock_timeout;
+        mgr.defer_lock_waits = other.defer_lock_waits;
+        mgr
+    }
+
+    fn with_shared(
+        next_txn_id: Arc<AtomicU64>,
+        row_locks: Arc<RowLockTable>,
+        snapshots: Arc<Mutex<Snapshots>>,
+    ) -> Self {
         TransactionManager {
-            next_txn_id: AtomicU64::new(1),
+            next_txn_id,
             active_txns: HashMap::new(),
             user_txn_active: false,
+            user_txn_id: None,
+            row_locks,
+            lock_timeout: Duration::from_secs(5),
+            defer_lock_waits: false,
+            blocked_on: None,
+            statement_undo: Vec::new(),
             undo_log: Vec::new(),
+            savepoints: Vec::new(),
+            savepoint_started_txn: false,
+            snapshots,
         }
     }
 
@@ -301,7 +442,13 @@ impl TransactionManager {
             ));
         }
         self.user_txn_active = true;
+        let id = self.next_txn_id.fetch_add(1, Ordering::SeqCst);
+        self.active_txns.insert(id, TxnState::Active);
+        self.snapshots().running.insert(id);
+        self.user_txn_id = Some(id);
         self.undo_log.clear();
+        self.savepoints.clear();
+        self.savepoint_started_txn = false;
         Ok(())
     }
 
@@ -314,7 +461,9 @@ impl TransactionManager {
             ));
         }
         self.user_txn_active = false;
+        self.finish_user_txn(TxnState::Committed);
         self.undo_log.clear();
+        self.savepoints.clear();
         Ok(())
     }
 
@@ -327,12 +476,84 @@ impl TransactionManager {
             ));
         }
         self.user_txn_active = false;
+        self.finish_user_txn(TxnState::Aborted);
         // Return entries in reverse order for proper undo
         let mut entries = std::mem::take(&mut self.undo_log);
+        for (_, frame) in self.savepoints.drain(..) {
+            entries.extend(frame);
+        }
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Record the end state of the user transaction and release its row
+    /// locks.
+    fn finish_user_txn(&mut self, state: TxnState) {
+        if let Some(id) = self.user_txn_id.take() {
+            self.active_txns.insert(id, state);
+            self.row_locks.release_all(id);
+            let mut snapshots = self.snapshots();
+            snapshots.running.remove(&id);
+            snapshots.prune();
+        }
+    }
+
+    /// Establish a named savepoint. If no user transaction is active, one is
+    /// started and will be committed when this savepoint is released.
+    pub fn savepoint(&mut self, name: &str) -> Result<()> {
+        if !self.user_txn_active {
+            self.begin_user_txn()?;
+            self.savepoint_started_txn = true;
+        }
+        self.savepoints.push((name.to_string(), Vec::new()));
+        Ok(())
+    }
+
+    /// Release the most recent savepoint called `name` and every savepoint
+    /// established after it. Their undo entries are merged into the
+    /// enclosing frame so an outer rollback still reverses them.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        let pos = self.find_savepoint(name)?;
+        let released: Vec<UndoEntry> = self
+            .savepoints
+            .drain(pos..)
+            .flat_map(|(_, frame)| frame)
+            .collect();
+        match self.savepoints.last_mut() {
+            Some((_, parent)) => parent.extend(released),
+            None => self.undo_log.extend(released),
+        }
+        if self.savepoints.is_empty() && self.savepoint_started_txn {
+            self.commit_user_txn()?;
+        }
+        Ok(())
+    }
+
+    /// Roll back to the most recent savepoint called `name`. Savepoints
+    /// established after it are discarded, but it stays open and the
+    /// enclosing transaction continues. Returns the undo entries recorded
+    /// since the savepoint, newest first.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<Vec<UndoEntry>> {
+        let pos = self.find_savepoint(name)?;
+        let mut entries: Vec<UndoEntry> = self
+            .savepoints
+            .drain(pos + 1..)
+            .flat_map(|(_, frame)| frame)
+            .collect();
+        let frame = std::mem::take(&mut self.savepoints[pos].1);
+        entries.splice(0..0, frame);
         entries.reverse();
         Ok(entries)
     }
 
+    /// Index of the innermost savepoint called `name` (case-insensitive).
+    fn find_savepoint(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|(n, _)| n.eq_ignore_ascii_case(name))
+            .ok_or_else(|| HorizonError::TransactionError(format!("no such savepoint: {}", name)))
+    }
+
     /// Check whether an explicit user transaction is currently active.
     pub fn is_user_txn_active(&self) -> bool {
         self.user_txn_active
@@ -340,13 +561,246 @@ impl TransactionManager {
 
     /// Record an undo entry for the current explicit user transaction.
     /// If no user transaction is active this is a no-op (auto-commit mode).
+    ///
+    /// While a snapshot or explicit transaction is open the change is also
+    /// stamped with the writing transaction's ID in the row's version
+    /// history.
     pub fn record_undo(&mut self, entry: UndoEntry) {
+        self.record_version(&entry);
+        if self.defer_lock_waits {
+            self.statement_undo.push(entry.clone());
+        }
         if self.user_txn_active {
-            self.undo_log.push(entry);
+            match self.savepoints.last_mut() {
+                Some((_, frame)) => frame.push(entry),
+                None => self.undo_log.push(entry),
+            }
+        }
+    }
+
+    /// Take the write lock on row `key` of `table` before changing it.
+    ///
+    /// Inside an explicit transaction the lock is kept until the
+    /// transaction commits or rolls back. An auto-commit statement commits
+    /// as soon as it finishes, so it only waits for other transactions to
+    /// release the row.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::LockConflict`] if another transaction still
+    /// holds the row after the lock timeout, and [`HorizonError::Deadlock`]
+    /// if the user transaction was chosen to break a deadlock. The caller
+    /// must then roll it back.
+    pub fn lock_row(&mut self, table: &str, key: &[u8]) -> Result<()> {
+        let timeout = if self.defer_lock_waits { Duration::ZERO } else { self.lock_timeout };
+        let result = match self.user_txn_id {
+            Some(id) => self.row_locks.acquire(table, key, id, timeout),
+            None => self.row_locks.wait_until_free(table, key, self.next_id(), timeout),
+        };
+        if self.defer_lock_waits && matches!(result, Err(HorizonError::LockConflict(_))) {
+            self.blocked_on = Some((table.to_string(), key.to_vec()));
         }
+        result
+    }
+
+    /// Set how long [`lock_row`](Self::lock_row) waits for a row locked by
+    /// another transaction.
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+
+    /// Make [`lock_row`](Self::lock_row) fail at once with
+    /// [`HorizonError::LockConflict`] for a row another transaction holds,
+    /// instead of waiting for it while the caller may be holding up that
+    /// transaction.
+    ///
+    /// The caller then undoes the statement with
+    /// [`rollback_statement`](Self::rollback_statement), lets go of the
+    /// database, waits with [`take_lock_wait`](Self::take_lock_wait), and
+    /// runs the statement again. Each statement must be started with
+    /// [`begin_statement`](Self::begin_statement).
+    pub fn set_defer_lock_waits(&mut self, defer: bool) {
+        self.defer_lock_waits = defer;
+    }
+
+    /// Start a statement: forget the changes and blocked row of the
+    /// previous one.
+    pub fn begin_statement(&mut self) {
+        self.statement_undo.clear();
+        self.blocked_on = None;
+    }
+
+    /// Take back the changes the current statement made while lock waits
+    /// are deferred, dropping them from the user transaction's undo log.
+    /// Returns them newest first, for the caller to reverse.
+    pub fn rollback_statement(&mut self) -> Vec<UndoEntry> {
+        let mut entries = std::mem::take(&mut self.statement_undo);
+        if self.user_txn_active {
+            let log = match self.savepoints.last_mut() {
+                Some((_, frame)) => frame,
+                None => &mut self.undo_log,
+            };
+            log.truncate(log.len().saturating_sub(entries.len()));
+        }
+        entries.reverse();
+        entries
+    }
+
+    /// The wait for the row the current statement failed to lock, if it
+    /// stopped at one while lock waits are deferred.
+    pub fn take_lock_wait(&mut self) -> Option<LockWait> {
+        let (table, key) = self.blocked_on.take()?;
+        Some(LockWait {
+            row_locks: Arc::clone(&self.row_locks),
+            table,
+            key,
+            txn: self.user_txn_id.unwrap_or_else(|| self.next_id()),
+            timeout: self.lock_timeout,
+        })
+    }
+
+    /// The row lock table this manager's transactions lock rows in.
+    pub fn row_locks(&self) -> &Arc<RowLockTable> {
+        &self.row_locks
+    }
+
+    /// The ID of the explicit user transaction, if one is active.
+    pub fn user_txn_id(&self) -> Option<TxnId> {
+        self.user_txn_id
+    }
+
+    /// Open a snapshot that sees every write committed so far and none
+    /// made after it. The writes of explicit transactions still running
+    /// stay hidden from it, even after they commit. It stays open until
+    /// passed to [`release_snapshot`](Self::release_snapshot).
+    pub fn begin_snapshot(&mut self) -> SnapshotId {
+        // Allocating an ID makes every later write newer than the snapshot
+        let id = self.auto_commit();
+        let mut snapshots = self.snapshots();
+        let running = snapshots.running.clone();
+        snapshots.open.insert(id, running);
+        id
+    }
+
+    /// Close a snapshot opened by [`begin_snapshot`](Self::begin_snapshot)
+    /// and drop the row versions no open snapshot can see any more.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::TransactionError`] if the snapshot is not
+    /// open.
+    pub fn release_snapshot(&mut self, id: SnapshotId) -> Result<()> {
+        let mut snapshots = self.snapshots();
+        if snapshots.open.remove(&id).is_none() {
+            return Err(no_such_snapshot(id));
+        }
+        snapshots.prune();
+        Ok(())
+    }
+
+    /// Whether any snapshot is open.
+    pub fn has_open_snapshots(&self) -> bool {
+        !self.snapshots().open.is_empty()
+    }
+
+    /// The rows snapshot `id` sees that differ from the current tables.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::TransactionError`] if the snapshot is not
+    /// open.
+    pub fn snapshot_view(&self, id: SnapshotId) -> Result<SnapshotView> {
+        let snapshots = self.snapshots();
+        let running = snapshots.open.get(&id).ok_or_else(|| no_such_snapshot(id))?;
+        let hidden = |writer: TxnId| writer > id || running.contains(&writer);
+        let mut view = SnapshotView { id, ..Default::default() };
+        for (table, keys) in &snapshots.versions {
+            let changed: BTreeMap<Vec<u8>, Option<Vec<u8>>> = keys
+                .iter()
+                .filter(|(_, history)| hidden(history.current_created_by))
+                .map(|(key, history)| {
+                    let visible = history
+                        .expired
+                        .iter()
+                        .find(|version| version.is_visible_at(id, running))
+                        .map(|version| version.data.clone());
+                    (key.clone(), visible)
+                })
+                .collect();
+            if !changed.is_empty() {
+                view.tables.insert(table.clone(), changed);
+            }
+        }
+        Ok(view)
+    }
+
+    /// Add the change described by `entry` to its row's version history,
+    /// if a snapshot is open or the change belongs to an explicit
+    /// transaction. It is stamped with the ID of the explicit transaction,
+    /// or else with the ID the next transaction will receive.
+    fn record_version(&self, entry: &UndoEntry) {
+        let mut snapshots = self.snapshots();
+        if snapshots.open.is_empty() && self.user_txn_id.is_none() {
+            return;
+        }
+        let writer = self.user_txn_id.unwrap_or_else(|| self.next_id());
+        let (table, key, old_value) = match entry {
+            UndoEntry::Insert { table, key, .. } => (table, key, None),
+            UndoEntry::Delete { table, key, old_value, .. }
+            | UndoEntry::Update { table, key, old_value, .. } => (table, key, Some(old_value)),
+        };
+        let history = snapshots
+            .versions
+            .entry(table.to_lowercase())
+            .or_default()
+            .entry(key.clone())
+            .or_default();
+        if let Some(old_value) = old_value {
+            history.expired.push(RowVersion {
+                created_by: history.current_created_by,
+                deleted_by: writer,
+                data: old_value.clone(),
+            });
+        }
+        history.current_created_by = writer;
+    }
+
+    fn snapshots(&self) -> MutexGuard<'_, Snapshots> {
+        // Each update leaves the histories consistent, so they stay usable
+        // after a panic elsewhere
+        self.snapshots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A wait for a row another transaction holds, made after letting go of
+/// the database; see [`TransactionManager::set_defer_lock_waits`].
+#[derive(Debug)]
+pub struct LockWait {
+    row_locks: Arc<RowLockTable>,
+    table: String,
+    key: Vec<u8>,
+    txn: TxnId,
+    timeout: Duration,
+}
+
+impl LockWait {
+    /// Wait up to the lock timeout until the row is released.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::LockConflict`] if the row is still locked
+    /// when the timeout expires, and [`HorizonError::Deadlock`] if the
+    /// waiting transaction was chosen to break a deadlock. The caller must
+    /// then roll it back.
+    pub fn wait(self) -> Result<()> {
+        self.row_locks.wait_until_free(&self.table, &self.key, self.txn, self.timeout)
     }
 }
 
+fn no_such_snapshot(id: SnapshotId) -> HorizonError {
+    HorizonError::TransactionError(format!("no such snapshot: {}", id))
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -943,8 +1397,8 @@ mod tests {
         // The last transaction should have all previous 9 in its active set.
         let last = &txns[9];
         assert_eq!(last.active_at_start.len(), 9);
-        for i in 0..9 {
-            assert!(last.active_at_start.contains(&txns[i].id));
+        for txn in &txns[..9] {
+            assert!(last.active_at_start.contains(&txn.id));
         }
 
         // Commit all and start a new one — active set should be empty.
@@ -1012,4 +1466,278 @@ mod tests {
         let s = format!("{:?}", TxnState::Aborted);
         assert_eq!(s, "Aborted");
     }
+
+    // =====================================================================
+    // Savepoint tests
+    // =====================================================================
+
+    fn insert_undo(key: u8) -> UndoEntry {
+        UndoEntry::Insert { table: "t".into(), root_page: 1, key: vec![key] }
+    }
+
+    fn undo_keys(entries: &[UndoEntry]) -> Vec<u8> {
+        entries
+            .iter()
+            .map(|e| match e {
+                UndoEntry::Insert { key, .. } => key[0],
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rollback_to_savepoint_returns_only_newer_entries() {
+        let mut mgr = TransactionManager::new();
+        mgr.begin_user_txn().unwrap();
+        mgr.record_undo(insert_undo(1));
+        mgr.savepoint("a").unwrap();
+        mgr.record_undo(insert_undo(2));
+        mgr.savepoint("b").unwrap();
+        mgr.record_undo(insert_undo(3));
+
+        let undone = mgr.rollback_to_savepoint("a").unwrap();
+        assert_eq!(undo_keys(&undone), vec![3, 2]);
+        assert!(mgr.is_user_txn_active());
+
+        // "a" is still open, "b" is gone
+        assert!(mgr.release_savepoint("b").is_err());
+        mgr.record_undo(insert_undo(4));
+        let undone = mgr.rollback_user_txn().unwrap();
+        assert_eq!(undo_keys(&undone), vec![4, 1]);
+    }
+
+    #[test]
+    fn release_savepoint_merges_into_parent() {
+        let mut mgr = TransactionManager::new();
+        mgr.begin_user_txn().unwrap();
+        mgr.savepoint("outer").unwrap();
+        mgr.record_undo(insert_undo(1));
+        mgr.savepoint("inner").unwrap();
+        mgr.record_undo(insert_undo(2));
+        mgr.release_savepoint("inner").unwrap();
+
+        let undone = mgr.rollback_to_savepoint("outer").unwrap();
+        assert_eq!(undo_keys(&undone), vec![2, 1]);
+    }
+
+    #[test]
+    fn savepoint_outside_transaction_commits_on_release() {
+        let mut mgr = TransactionManager::new();
+        mgr.savepoint("sp").unwrap();
+        assert!(mgr.is_user_txn_active());
+        mgr.release_savepoint("sp").unwrap();
+        assert!(!mgr.is_user_txn_active());
+        assert!(mgr.rollback_to_savepoint("sp").is_err());
+    }
+
+    // =====================================================================
+    // Snapshot tests
+    // =====================================================================
+
+    fn update_undo(key: u8, old: u8) -> UndoEntry {
+        UndoEntry::Update { table: "T".into(), root_page: 1, key: vec![key], old_value: vec![old] }
+    }
+
+    fn seen(mgr: &TransactionManager, snapshot: SnapshotId) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let view = mgr.snapshot_view(snapshot).unwrap();
+        view.tables.get("t").map(|keys| keys.clone().into_iter().collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn row_version_visible_at_snapshot() {
+        let none = HashSet::new();
+        let v = RowVersion { created_by: 3, deleted_by: 6, data: vec![] };
+        assert!(!v.is_visible_at(2, &none));
+        assert!(v.is_visible_at(3, &none));
+        assert!(v.is_visible_at(5, &none));
+        assert!(!v.is_visible_at(6, &none));
+        // The writes of transactions running when the snapshot was taken
+        // stay hidden
+        assert!(!v.is_visible_at(5, &HashSet::from([3])));
+        assert!(v.is_visible_at(7, &HashSet::from([6])));
+        let live = RowVersion { created_by: 3, deleted_by: 0, data: vec![] };
+        assert!(live.is_visible_at(100, &none));
+    }
+
+    #[test]
+    fn snapshot_sees_versions_replaced_after_it() {
+        let mut mgr = TransactionManager::new();
+        // Nothing is recorded without an open snapshot
+        mgr.record_undo(update_undo(1, 10));
+        let first = mgr.begin_snapshot();
+        mgr.record_undo(update_undo(1, 11));
+        mgr.record_undo(insert_undo(2));
+        let second = mgr.begin_snapshot();
+        mgr.record_undo(update_undo(1, 12));
+        mgr.record_undo(UndoEntry::Delete {
+            table: "t".into(),
+            root_page: 1,
+            key: vec![2],
+            old_value: vec![20],
+        });
+
+        assert_eq!(seen(&mgr, first), vec![(vec![1], Some(vec![11])), (vec![2], None)]);
+        assert_eq!(seen(&mgr, second), vec![(vec![1], Some(vec![12])), (vec![2], Some(vec![20]))]);
+
+        mgr.release_snapshot(first).unwrap();
+        assert!(mgr.snapshot_view(first).is_err());
+        assert_eq!(seen(&mgr, second).len(), 2);
+        mgr.release_snapshot(second).unwrap();
+        assert!(!mgr.has_open_snapshots());
+        assert!(mgr.snapshots().versions.is_empty());
+        assert!(mgr.release_snapshot(second).is_err());
+    }
+
+    // =====================================================================
+    // Row lock tests
+    // =====================================================================
+
+    #[test]
+    fn user_txn_holds_row_locks_until_it_ends() {
+        let mut mgr = TransactionManager::new();
+        mgr.lock_row("t", &[1]).unwrap();
+        assert_eq!(mgr.row_locks().owner("t", &[1]), None);
+
+        mgr.begin_user_txn().unwrap();
+        let id = mgr.user_txn_id().unwrap();
+        mgr.lock_row("t", &[1]).unwrap();
+        mgr.lock_row("t", &[1]).unwrap();
+        assert_eq!(mgr.row_locks().owner("t", &[1]), Some(id));
+        mgr.rollback_user_txn().unwrap();
+        assert_eq!(mgr.row_locks().owner("t", &[1]), None);
+        assert_eq!(mgr.user_txn_id(), None);
+    }
+
+    #[test]
+    fn concurrent_writers_to_one_row_conflict() {
+        use std::sync::mpsc;
+        use std::thread;
+
+        let mut first = TransactionManager::new();
+        first.set_lock_timeout(Duration::from_millis(20));
+        let mut second = TransactionManager::sharing(&first);
+        first.begin_user_txn().unwrap();
+        first.lock_row("t", &[1]).unwrap();
+        let first_id = first.user_txn_id();
+
+        let (conflicted, wait_for_conflict) = mpsc::channel();
+        let second = thread::spawn(move || {
+            second.begin_user_txn().unwrap();
+            assert_ne!(second.user_txn_id(), first_id);
+            let err = second.lock_row("t", &[1]).unwrap_err();
+            assert!(matches!(err, HorizonError::LockConflict(_)));
+            // Other rows are not affected
+            second.lock_row("t", &[2]).unwrap();
+            conflicted.send(()).unwrap();
+            // Retrying with a longer wait succeeds once the first commits
+            second.set_lock_timeout(Duration::from_secs(10));
+            second.lock_row("t", &[1]).map(|_| second)
+        });
+        wait_for_conflict.recv().unwrap();
+        first.commit_user_txn().unwrap();
+        let mut second = second.join().unwrap().unwrap();
+        assert_eq!(second.row_locks().owner("t", &[1]), second.user_txn_id());
+        second.commit_user_txn().unwrap();
+        assert_eq!(first.row_locks().owner("t", &[2]), None);
+    }
+
+    #[test]
+    fn deferred_lock_wait_is_left_to_the_caller() {
+        let mut first = TransactionManager::new();
+        first.begin_user_txn().unwrap();
+        first.lock_row("t", &[1]).unwrap();
+        let mut second = TransactionManager::sharing(&first);
+        second.set_lock_timeout(Duration::from_secs(10));
+        second.set_defer_lock_waits(true);
+        second.begin_user_txn().unwrap();
+        second.record_undo(insert_undo(9));
+
+        second.begin_statement();
+        second.lock_row("t", &[2]).unwrap();
+        second.record_undo(insert_undo(2));
+        let started = std::time::Instant::now();
+        let err = second.lock_row("t", &[1]).unwrap_err();
+        assert!(matches!(err, HorizonError::LockConflict(_)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // Only the statement's change is taken back
+        assert_eq!(undo_keys(&second.rollback_statement()), vec![2]);
+
+        let wait = second.take_lock_wait().unwrap();
+        assert!(second.take_lock_wait().is_none());
+        first.commit_user_txn().unwrap();
+        wait.wait().unwrap();
+        second.begin_statement();
+        second.lock_row("t", &[1]).unwrap();
+        assert_eq!(undo_keys(&second.rollback_user_txn().unwrap()), vec![9]);
+    }
+
+    #[test]
+    fn snapshots_are_shared_between_connections() {
+        let mut first = TransactionManager::new();
+        let mut second = TransactionManager::sharing(&first);
+        let snapshot = first.begin_snapshot();
+        second.record_undo(update_undo(1, 10));
+        assert_eq!(seen(&first, snapshot), vec![(vec![1], Some(vec![10]))]);
+        second.release_snapshot(snapshot).unwrap();
+        assert!(!first.has_open_snapshots());
+    }
+
+    #[test]
+    fn snapshot_hides_transactions_running_when_it_was_taken() {
+        let mut first = TransactionManager::new();
+        let mut second = TransactionManager::sharing(&first);
+        second.begin_user_txn().unwrap();
+        second.record_undo(update_undo(1, 10));
+        let snapshot = first.begin_snapshot();
+        second.record_undo(update_undo(2, 20));
+        assert_eq!(seen(&first, snapshot), vec![(vec![1], Some(vec![10])), (vec![2], Some(vec![20]))]);
+
+        // Committing does not make the writes visible to the snapshot
+        second.commit_user_txn().unwrap();
+        assert_eq!(seen(&first, snapshot).len(), 2);
+        let later = first.begin_snapshot();
+        assert!(seen(&first, later).is_empty());
+
+        first.release_snapshot(snapshot).unwrap();
+        first.release_snapshot(later).unwrap();
+        assert!(first.snapshots().versions.is_empty());
+        assert!(first.snapshots().running.is_empty());
+    }
+
+    #[test]
+    fn deadlocked_transactions_lose_exactly_one() {
+        use std::sync::{Barrier, Mutex};
+        use std::thread;
+
+        let first = TransactionManager::new();
+        let second = TransactionManager::sharing(&first);
+        let barrier = Arc::new(Barrier::new(2));
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let workers: Vec<_> = [(first, 1u8, 2u8), (second, 2, 1)]
+            .into_iter()
+            .map(|(mut mgr, held, wanted)| {
+                let (barrier, outcomes) = (Arc::clone(&barrier), Arc::clone(&outcomes));
+                thread::spawn(move || {
+                    mgr.set_lock_timeout(Duration::from_secs(10));
+                    mgr.begin_user_txn().unwrap();
+                    mgr.lock_row("t", &[held]).unwrap();
+                    barrier.wait();
+                    let result = mgr.lock_row("t", &[wanted]);
+                    match result {
+                        Ok(()) => mgr.commit_user_txn().unwrap(),
+                        // The victim rolls back, releasing the row the other waits for
+                        Err(_) => drop(mgr.rollback_user_txn().unwrap()),
+                    }
+                    outcomes.lock().unwrap().push(result);
+                    Arc::clone(mgr.row_locks())
+                })
+            })
+            .collect();
+        let locks: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        let outcomes = outcomes.lock().unwrap();
+        assert_eq!(outcomes.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(outcomes.iter().any(|result| matches!(result, Err(HorizonError::Deadlock(_)))));
+        assert_eq!(locks[0].owner("t", &[1]), None);
+        assert_eq!(locks[0].owner("t", &[2]), None);
+    }
 }
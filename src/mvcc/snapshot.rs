@@ -0,0 +1,101 @@
+//! Reading a table as it was when a snapshot was taken.
+//!
+//! A query run with [`Database::query_at_snapshot`](crate::Database::query_at_snapshot)
+//! installs a [`SnapshotView`] for the current thread. Table scans pass
+//! their entries through `visible_entries`, which swaps in the versions
+//! the snapshot saw for rows changed since it was taken. Index and
+//! primary-key seeks are skipped while a view is installed, because the
+//! indexes only describe the current rows.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::btree::BTreeEntry;
+use super::SnapshotId;
+
+thread_local! {
+    static SNAPSHOT: RefCell<Option<SnapshotView>> = const { RefCell::new(None) };
+}
+
+/// The rows a snapshot sees that differ from the current B+Tree contents.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotView {
+    /// The snapshot this view was built for.
+    pub id: SnapshotId,
+    /// Per lowercased table name, the value the snapshot sees for each
+    /// changed key, or `None` if the row did not exist for it.
+    pub(crate) tables: HashMap<String, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+/// Run `f` with `view` as the current thread's snapshot.
+pub(crate) fn with_snapshot<T>(view: SnapshotView, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous view even if `f` panics.
+    struct Restore(Option<SnapshotView>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            SNAPSHOT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let previous = SNAPSHOT.with(|current| current.borrow_mut().replace(view));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Whether the current thread is reading at a snapshot.
+pub(crate) fn is_active() -> bool {
+    SNAPSHOT.with(|current| current.borrow().is_some())
+}
+
+/// Rewrite a full scan of `table` so it shows the rows the current
+/// thread's snapshot sees. Entries are returned unchanged outside a
+/// snapshot.
+pub(crate) fn visible_entries(table: &str, entries: Vec<BTreeEntry>) -> Vec<BTreeEntry> {
+    SNAPSHOT.with(|current| {
+        let current = current.borrow();
+        let changed = match current.as_ref().and_then(|view| view.tables.get(&table.to_lowercase())) {
+            Some(changed) if !changed.is_empty() => changed,
+            _ => return entries,
+        };
+        let mut rows: BTreeMap<Vec<u8>, Vec<u8>> =
+            entries.into_iter().map(|entry| (entry.key, entry.value)).collect();
+        for (key, value) in changed {
+            match value {
+                Some(value) => rows.insert(key.clone(), value.clone()),
+                None => rows.remove(key),
+            };
+        }
+        rows.into_iter().map(|(key, value)| BTreeEntry { key, value }).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u8, value: u8) -> BTreeEntry {
+        BTreeEntry { key: vec![key], value: vec![value] }
+    }
+
+    #[test]
+    fn view_replaces_hides_and_restores_rows() {
+        let mut changed = BTreeMap::new();
+        changed.insert(vec![2], Some(vec![20]));
+        changed.insert(vec![3], None);
+        changed.insert(vec![4], Some(vec![40]));
+        let mut view = SnapshotView { id: 7, ..Default::default() };
+        view.tables.insert("t".into(), changed);
+
+        let scan = || vec![entry(1, 1), entry(2, 2), entry(3, 3)];
+        assert!(!is_active());
+        assert_eq!(visible_entries("t", scan()), scan());
+        let seen = with_snapshot(view, || {
+            assert!(is_active());
+            assert_eq!(visible_entries("other", scan()), scan());
+            visible_entries("T", scan())
+        });
+        assert_eq!(seen, vec![entry(1, 1), entry(2, 20), entry(4, 40)]);
+        assert!(!is_active());
+    }
+}
@@ -0,0 +1,260 @@
+//! The list of free pages.
+//!
+//! Freed pages are recorded on *trunk* pages, which form a singly-linked
+//! list starting at the free-list head in the file header. Each trunk page
+//! holds the IDs of further free pages, its *leaves*:
+//!
+//! | Offset | Size | Field                                           |
+//! |--------|------|-------------------------------------------------|
+//! | 0..4   | 4    | next trunk page (u32 BE, `0` on the last trunk) |
+//! | 4..8   | 4    | number of leaf page IDs (u32 BE)                |
+//! | 8..    | 4 each | leaf page IDs (u32 BE)                        |
+//!
+//! A freed page is appended to the leaves of the first trunk, or becomes
+//! the new first trunk once that one is full. Allocation takes the last
+//! leaf of the first trunk, or the trunk itself once it has no leaves left,
+//! so pages are reused in last-in, first-out order. Leaf pages are never
+//! written while they are free.
+//!
+//! Older files chained every free page through its first four bytes and
+//! zeroed the rest; such a chain reads as a list of trunks without leaves.
+
+use crate::error::{HorizonError, Result};
+use super::PageId;
+
+/// Size of the fixed part of a trunk page: next trunk and leaf count.
+const TRUNK_HEADER_SIZE: usize = 8;
+
+/// Page access for [`FreeList`], provided by the [`Pager`](super::Pager)
+/// or by a buffer pool that caches its pages.
+pub trait FreeListPages {
+    /// The size of every page in bytes.
+    fn page_size(&self) -> usize;
+    /// The number of pages in the database, including the header page.
+    fn page_count(&self) -> u32;
+    /// Read the trunk page `page_id`.
+    fn read_trunk(&mut self, page_id: PageId) -> Result<Vec<u8>>;
+    /// Overwrite the trunk page `page_id` with `data`.
+    fn write_trunk(&mut self, page_id: PageId, data: &[u8]) -> Result<()>;
+}
+
+/// The head of the free list and the number of pages on it, as recorded
+/// in the file header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FreeList {
+    head: PageId,
+    len: u32,
+}
+
+impl FreeList {
+    /// A free list whose first trunk page is `head` (`0` if empty) and
+    /// that holds `len` pages.
+    pub fn new(head: PageId, len: u32) -> Self {
+        FreeList { head, len }
+    }
+
+    /// The first trunk page, or `0` if the list is empty.
+    pub fn head(&self) -> PageId {
+        self.head
+    }
+
+    /// The number of free pages, trunks included.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Whether no page is free.
+    pub fn is_empty(&self) -> bool {
+        self.head == 0
+    }
+
+    /// Count the pages on the list by walking its trunks.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::CorruptDatabase`] if a trunk is malformed
+    /// or the trunks form a cycle.
+    pub fn count(&self, pages: &mut impl FreeListPages) -> Result<u32> {
+        let mut len = 0u32;
+        let mut trunk_id = self.head;
+        while trunk_id != 0 {
+            if len >= pages.page_count() {
+                return Err(HorizonError::CorruptDatabase("free list contains a cycle".into()));
+            }
+            let trunk = read_trunk(pages, trunk_id)?;
+            len += 1 + leaf_count(&trunk);
+            trunk_id = read_u32(&trunk, 0);
+        }
+        Ok(len)
+    }
+
+    /// Take a page off the list, or return `None` if it is empty. The
+    /// page's contents are left as they were.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`HorizonError::CorruptDatabase`] for a malformed trunk, and
+    /// any error reading or writing it.
+    pub fn pop(&mut self, pages: &mut impl FreeListPages) -> Result<Option<PageId>> {
+        if self.head == 0 {
+            return Ok(None);
+        }
+        let mut trunk = read_trunk(pages, self.head)?;
+        let leaves = leaf_count(&trunk);
+        let page_id = if leaves > 0 {
+            let leaf = read_u32(&trunk, leaf_offset(leaves - 1));
+            if leaf == 0 || leaf >= pages.page_count() {
+                return Err(HorizonError::CorruptDatabase(format!(
+                    "free-list trunk {} lists page {}",
+                    self.head, leaf
+                )));
+            }
+            write_u32(&mut trunk, 4, leaves - 1);
+            pages.write_trunk(self.head, &trunk)?;
+            leaf
+        } else {
+            let page_id = self.head;
+            self.head = read_u32(&trunk, 0);
+            page_id
+        };
+        self.len = self.len.saturating_sub(1);
+        Ok(Some(page_id))
+    }
+
+    /// Add `page_id` to the list.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::CorruptDatabase`] -- `page_id` is the header page,
+    ///   or a trunk is malformed.
+    /// * [`HorizonError::PageNotFound`] -- `page_id` is out of range.
+    pub fn push(&mut self, page_id: PageId, pages: &mut impl FreeListPages) -> Result<()> {
+        if page_id == 0 {
+            return Err(HorizonError::CorruptDatabase(
+                "cannot free the header page (page 0)".into(),
+            ));
+        }
+        if page_id >= pages.page_count() {
+            return Err(HorizonError::PageNotFound(page_id));
+        }
+
+        if self.head != 0 {
+            let mut trunk = read_trunk(pages, self.head)?;
+            let leaves = leaf_count(&trunk);
+            if leaves < max_leaves(trunk.len()) {
+                write_u32(&mut trunk, leaf_offset(leaves), page_id);
+                write_u32(&mut trunk, 4, leaves + 1);
+                pages.write_trunk(self.head, &trunk)?;
+                self.len += 1;
+                return Ok(());
+            }
+        }
+
+        // The page becomes the first trunk, with no leaves yet
+        let mut trunk = vec![0u8; pages.page_size()];
+        write_u32(&mut trunk, 0, self.head);
+        pages.write_trunk(page_id, &trunk)?;
+        self.head = page_id;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Read a trunk page and check its leaf count.
+fn read_trunk(pages: &mut impl FreeListPages, page_id: PageId) -> Result<Vec<u8>> {
+    if page_id >= pages.page_count() {
+        return Err(HorizonError::CorruptDatabase(format!(
+            "free-list trunk {} is out of range",
+            page_id
+        )));
+    }
+    let trunk = pages.read_trunk(page_id)?;
+    if leaf_count(&trunk) > max_leaves(trunk.len()) {
+        return Err(HorizonError::CorruptDatabase(format!(
+            "free-list trunk {} has too many leaves",
+            page_id
+        )));
+    }
+    Ok(trunk)
+}
+
+fn max_leaves(page_size: usize) -> u32 {
+    ((page_size - TRUNK_HEADER_SIZE) / 4) as u32
+}
+
+fn leaf_count(trunk: &[u8]) -> u32 {
+    read_u32(trunk, 4)
+}
+
+fn leaf_offset(index: u32) -> usize {
+    TRUNK_HEADER_SIZE + index as usize * 4
+}
+
+fn read_u32(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+fn write_u32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Pages held in memory.
+    struct Pages {
+        page_size: usize,
+        page_count: u32,
+        written: HashMap<PageId, Vec<u8>>,
+    }
+
+    impl FreeListPages for Pages {
+        fn page_size(&self) -> usize {
+            self.page_size
+        }
+        fn page_count(&self) -> u32 {
+            self.page_count
+        }
+        fn read_trunk(&mut self, page_id: PageId) -> Result<Vec<u8>> {
+            Ok(self.written.get(&page_id).cloned().unwrap_or_else(|| vec![0; self.page_size]))
+        }
+        fn write_trunk(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+            self.written.insert(page_id, data.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trunks_fill_up_before_a_new_one_is_started() {
+        let mut pages = Pages { page_size: 512, page_count: 1000, written: HashMap::new() };
+        let per_trunk = max_leaves(512);
+        let mut list = FreeList::default();
+        for page_id in 1..=2 * (per_trunk + 1) {
+            list.push(page_id, &mut pages).unwrap();
+        }
+        assert_eq!(list.len(), 2 * (per_trunk + 1));
+        assert_eq!(list.count(&mut pages).unwrap(), list.len());
+        // Only the two trunk pages were written
+        assert_eq!(pages.written.len(), 2);
+
+        let mut popped = Vec::new();
+        while let Some(page_id) = list.pop(&mut pages).unwrap() {
+            popped.push(page_id);
+        }
+        let expected: Vec<PageId> = (1..=2 * (per_trunk + 1)).rev().collect();
+        assert_eq!(popped, expected);
+        assert!(list.is_empty());
+        assert_eq!(list.len(), 0);
+    }
+
+    #[test]
+    fn push_rejects_pages_outside_the_file() {
+        let mut pages = Pages { page_size: 512, page_count: 10, written: HashMap::new() };
+        let mut list = FreeList::default();
+        assert!(matches!(list.push(0, &mut pages), Err(HorizonError::CorruptDatabase(_))));
+        assert!(matches!(list.push(10, &mut pages), Err(HorizonError::PageNotFound(10))));
+        assert!(list.is_empty());
+    }
+}
@@ -2,11 +2,18 @@
 //!
 //! The [`Pager`] is the lowest-level storage abstraction in the database
 //! engine.  It views the database file as a flat sequence of fixed-size
-//! **pages** ([`PAGE_SIZE`] = 4 096 bytes each) and provides simple
-//! read / write / allocate / free operations on those pages.
+//! **pages** ([`PAGE_SIZE`] = 4 096 bytes each by default) and provides
+//! simple read / write / allocate / free operations on those pages.
+//!
+//! The page size is chosen when the file is created (any power of two
+//! from [`MIN_PAGE_SIZE`] to [`MAX_PAGE_SIZE`]) and recorded in the file
+//! header.
 //!
 //! The pager knows nothing about the *contents* of pages -- it deals
-//! exclusively in raw `[u8; PAGE_SIZE]` buffers.  Higher layers (the
+//! exclusively in raw page-sized byte buffers.
+//!
+//! Pages live in a [`PageStorage`]: normally the database file, or a
+//! [`MemoryPager`] for `:memory:` databases.  Higher layers (the
 //! B-tree module, the buffer pool, the WAL) build their own structure on
 //! top.
 //!
@@ -17,24 +24,51 @@
 //!
 //! # Free list
 //!
-//! Freed pages are chained together in a singly-linked list.  Each free
-//! page stores the [`PageId`] of the next free page in its first four
-//! bytes (big-endian).  [`Pager::allocate_page`] pops the head of this
-//! list; [`Pager::free_page`] pushes onto it.
+//! Freed pages are kept on a [`FreeList`] of trunk pages, each listing
+//! further free pages; see the [`freelist`] module for the layout.
+//! [`Pager::allocate_page`] takes a page off this list before growing the
+//! file; [`Pager::free_page`] adds one to it.
+//!
+//! # Rollback journal
+//!
+//! In the `Delete` and `Memory` journal modes the pager saves the original
+//! image of each page in a [`RollbackJournal`] before overwriting it, and
+//! commits the journal in [`Pager::sync`]. If the sync fails, the pages are
+//! put back from the journal, so the file stays as it was at the last
+//! commit.
 
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::error::{HorizonError, Result};
+use crate::wal::{journal_path, JournalMode, RollbackJournal, SynchronousMode};
+use crate::DatabaseConfig;
+
+pub mod freelist;
+
+pub use freelist::{FreeList, FreeListPages};
 
 // ---------------------------------------------------------------------------
 // Constants
 // ---------------------------------------------------------------------------
 
-/// Every page in the database file is exactly this many bytes.
+/// The default page size, used when a database is created without an
+/// explicit one.
 pub const PAGE_SIZE: usize = 4096;
 
+/// The smallest supported page size.
+pub const MIN_PAGE_SIZE: usize = 512;
+
+/// The largest supported page size.
+pub const MAX_PAGE_SIZE: usize = 65536;
+
+/// Whether `size` is a supported page size: a power of two between
+/// [`MIN_PAGE_SIZE`] and [`MAX_PAGE_SIZE`].
+pub fn is_valid_page_size(size: usize) -> bool {
+    size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&size)
+}
+
 /// The file header occupies the first 100 bytes of page 0.
 pub const HEADER_SIZE: usize = 100;
 
@@ -50,11 +84,111 @@ pub const MAGIC: &[u8; 16] = b"HorizonDB v001\x00\x00";
 /// A zero-based page number.  Page 0 is always the header page.
 pub type PageId = u32;
 
+// ---------------------------------------------------------------------------
+// Page storage
+// ---------------------------------------------------------------------------
+
+/// Byte-addressed storage behind a [`Pager`].
+///
+/// Every access stays within a single page.
+pub trait PageStorage: std::fmt::Debug + Send + Sync {
+    /// Fill `buf` with the bytes starting at `offset`.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+    /// Write `data` starting at `offset`, growing the storage if needed.
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+    /// Make previous writes durable.
+    fn sync(&self) -> Result<()>;
+    /// Cut the storage down to its first `len` bytes.
+    fn truncate(&mut self, len: u64) -> Result<()>;
+}
+
+impl PageStorage for File {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        // `Read` and `Seek` are implemented for `&File`, so reads do not
+        // need `&mut self`.
+        let mut file = self;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)?;
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.sync_all()?;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<()> {
+        self.set_len(len)?;
+        Ok(())
+    }
+}
+
+/// Page storage held on the heap, used for `:memory:` databases. Its
+/// contents are lost when it is dropped.
+#[derive(Debug)]
+pub struct MemoryPager {
+    page_size: usize,
+    pages: Vec<Vec<u8>>,
+}
+
+impl MemoryPager {
+    /// Create empty storage for pages of `page_size` bytes.
+    pub fn new(page_size: usize) -> Self {
+        MemoryPager { page_size, pages: Vec::new() }
+    }
+
+    /// Split a byte offset into a page index and an offset within it.
+    fn locate(&self, offset: u64, len: usize) -> Result<(usize, usize)> {
+        let page = (offset / self.page_size as u64) as usize;
+        let within = (offset % self.page_size as u64) as usize;
+        if within + len > self.page_size {
+            return Err(HorizonError::Internal("memory page access crosses a page boundary".into()));
+        }
+        Ok((page, within))
+    }
+}
+
+impl PageStorage for MemoryPager {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let (page, within) = self.locate(offset, buf.len())?;
+        let data = self.pages.get(page).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "read past end of memory database")
+        })?;
+        buf.copy_from_slice(&data[within..within + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let (page, within) = self.locate(offset, data.len())?;
+        if page >= self.pages.len() {
+            self.pages.resize_with(page + 1, || vec![0u8; self.page_size]);
+        }
+        self.pages[page][within..within + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<()> {
+        // Pages are only ever truncated whole
+        self.pages.truncate((len / self.page_size as u64) as usize);
+        Ok(())
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Pager
 // ---------------------------------------------------------------------------
 
-/// Manages a database file as a flat array of [`PAGE_SIZE`]-byte pages.
+/// Manages a database file as a flat array of fixed-size pages.
 ///
 /// The pager is responsible for:
 ///
@@ -68,21 +202,37 @@ pub type PageId = u32;
 /// pool sitting above it.
 #[derive(Debug)]
 pub struct Pager {
-    /// The underlying database file handle.
-    file: File,
+    /// Where the pages are stored (the database file, or memory).
+    storage: Box<dyn PageStorage>,
+    /// Size of every page in bytes.
+    page_size: usize,
     /// Total number of pages currently in the file (including page 0).
     page_count: u32,
-    /// Head of the singly-linked free-page list (`0` means empty).
-    free_list_head: PageId,
+    /// The pages freed for reuse.
+    free_list: FreeList,
     /// The next transaction ID to hand out.
     next_txn_id: u64,
     /// Root page of the schema table B-tree (`0` means not yet created).
     schema_root: PageId,
     /// Current schema version number.
     schema_version: u32,
+    /// Application-defined version number (`PRAGMA user_version`).
+    user_version: u32,
+    /// Bumped by [`sync`](Self::sync) whenever pages were written, so that
+    /// other handles can tell the file changed under them.
+    change_counter: u32,
+    /// Whether pages were written since the last sync.
+    modified: bool,
     /// When `true`, all mutating operations will return
     /// [`HorizonError::ReadOnly`].
     read_only: bool,
+    /// Path of the database file (`None` for an in-memory database).
+    path: Option<PathBuf>,
+    /// Saves original page images before they are overwritten, in the
+    /// `Delete` and `Memory` journal modes.
+    journal: Option<RollbackJournal>,
+    /// Whether [`sync`](Self::sync) flushes the file to stable storage.
+    synchronous: SynchronousMode,
 }
 
 impl Pager {
@@ -103,6 +253,33 @@ impl Pager {
     /// * [`HorizonError::CorruptDatabase`] -- the file exists but contains
     ///   invalid magic bytes or is shorter than a single page.
     pub fn open(path: &Path, read_only: bool) -> Result<Self> {
+        Self::open_with_page_size(path, read_only, None)
+    }
+
+    /// Like [`open`](Self::open), with an explicit page size.
+    ///
+    /// A new file is created with `page_size` ([`PAGE_SIZE`] if `None`).
+    /// For an existing file the size stored in its header is used, and
+    /// must equal `page_size` when one is given.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::InvalidSql`] -- `page_size` is not supported.
+    /// * [`HorizonError::CorruptDatabase`] -- the stored page size differs
+    ///   from `page_size`, or the header is invalid.
+    pub fn open_with_page_size(
+        path: &Path,
+        read_only: bool,
+        page_size: Option<usize>,
+    ) -> Result<Self> {
+        if let Some(size) = page_size {
+            if !is_valid_page_size(size) {
+                return Err(HorizonError::InvalidSql(format!(
+                    "invalid page size {size}: must be a power of two between {MIN_PAGE_SIZE} and {MAX_PAGE_SIZE}"
+                )));
+            }
+        }
+
         let file = if read_only {
             OpenOptions::new().read(true).open(path)?
         } else {
@@ -110,6 +287,7 @@ impl Pager {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(path)?
         };
 
@@ -123,74 +301,140 @@ impl Pager {
                 ));
             }
 
-            let mut pager = Pager {
-                file,
-                page_count: 1,
-                free_list_head: 0,
-                next_txn_id: 1,
-                schema_root: 0,
-                schema_version: 0,
-                read_only,
-            };
-
-            // Write a zeroed page 0 first, then stamp the header on it.
-            let blank = [0u8; PAGE_SIZE];
-            pager.file.seek(SeekFrom::Start(0))?;
-            pager.file.write_all(&blank)?;
-            pager.flush_header()?;
-            pager.file.sync_all()?;
-
+            let mut pager = Self::create(Box::new(file), page_size.unwrap_or(PAGE_SIZE))?;
+            pager.path = Some(path.to_path_buf());
             Ok(pager)
         } else {
             // ---- existing database ------------------------------------
-            if file_len < PAGE_SIZE as u64 {
+            if file_len < HEADER_SIZE as u64 {
                 return Err(HorizonError::CorruptDatabase(
                     "file is shorter than a single page".into(),
                 ));
             }
 
             let mut pager = Pager {
-                file,
+                storage: Box::new(file),
+                page_size: 0,
                 page_count: 0,
-                free_list_head: 0,
+                free_list: FreeList::default(),
                 next_txn_id: 0,
                 schema_root: 0,
                 schema_version: 0,
+                user_version: 0,
+                change_counter: 0,
+                modified: false,
                 read_only,
+                path: Some(path.to_path_buf()),
+                journal: None,
+                synchronous: SynchronousMode::default(),
             };
 
+            // Undo the unfinished transaction of a writer that crashed
+            if !read_only {
+                RollbackJournal::recover(&journal_path(path), pager.storage.as_mut())?;
+            }
             pager.read_header()?;
 
+            if file_len < pager.page_size as u64 {
+                return Err(HorizonError::CorruptDatabase(
+                    "file is shorter than a single page".into(),
+                ));
+            }
+            if let Some(size) = page_size {
+                if size != pager.page_size {
+                    return Err(HorizonError::CorruptDatabase(format!(
+                        "page size mismatch: database uses {} bytes, {} requested",
+                        pager.page_size, size
+                    )));
+                }
+            }
+
             Ok(pager)
         }
     }
 
+    /// Open the database file at `path` with the page size, read-only
+    /// setting, and journal mode of `config`. See
+    /// [`open_with_page_size`](Self::open_with_page_size).
+    pub fn open_with_config(path: &Path, config: &DatabaseConfig) -> Result<Self> {
+        let page_size = config.page_size.map(|size| size as usize);
+        let mut pager = Self::open_with_page_size(path, config.read_only, page_size)?;
+        pager.synchronous = config.synchronous;
+        if !config.read_only {
+            let mut journal = match config.journal_mode {
+                JournalMode::Delete => RollbackJournal::file(path),
+                JournalMode::Memory => RollbackJournal::memory(),
+                JournalMode::Wal | JournalMode::Off => return Ok(pager),
+            };
+            journal.set_synchronous(config.synchronous);
+            pager.journal = Some(journal);
+        }
+        Ok(pager)
+    }
+
+    /// Create a new database held entirely in memory. Nothing is written to
+    /// disk, and the contents are lost when the pager is dropped.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::InvalidSql`] -- `page_size` is not supported.
+    pub fn open_memory(page_size: Option<usize>) -> Result<Self> {
+        let page_size = page_size.unwrap_or(PAGE_SIZE);
+        if !is_valid_page_size(page_size) {
+            return Err(HorizonError::InvalidSql(format!(
+                "invalid page size {page_size}: must be a power of two between {MIN_PAGE_SIZE} and {MAX_PAGE_SIZE}"
+            )));
+        }
+        Self::create(Box::new(MemoryPager::new(page_size)), page_size)
+    }
+
+    /// Initialize a brand-new database in empty `storage`.
+    fn create(storage: Box<dyn PageStorage>, page_size: usize) -> Result<Self> {
+        let mut pager = Pager {
+            storage,
+            page_size,
+            page_count: 1,
+            free_list: FreeList::default(),
+            next_txn_id: 1,
+            schema_root: 0,
+            schema_version: 0,
+            user_version: 0,
+            change_counter: 0,
+            modified: false,
+            read_only: false,
+            path: None,
+            journal: None,
+            synchronous: SynchronousMode::default(),
+        };
+
+        // Write a zeroed page 0 first, then stamp the header on it.
+        let blank = vec![0u8; page_size];
+        pager.storage.write_at(0, &blank)?;
+        pager.flush_header()?;
+        pager.storage.sync()?;
+
+        Ok(pager)
+    }
+
     // ---------------------------------------------------------------------
     // Page I/O
     // ---------------------------------------------------------------------
 
-    /// Read the page identified by `page_id` into a `[u8; PAGE_SIZE]`
-    /// buffer and return it.
+    /// Read the page identified by `page_id` into a page-sized buffer and
+    /// return it.
     ///
     /// # Errors
     ///
     /// * [`HorizonError::PageNotFound`] -- `page_id` is out of range.
     /// * [`HorizonError::Io`] -- the underlying read failed.
-    pub fn read_page(&self, page_id: PageId) -> Result<[u8; PAGE_SIZE]> {
+    pub fn read_page(&self, page_id: PageId) -> Result<Vec<u8>> {
         if page_id >= self.page_count {
             return Err(HorizonError::PageNotFound(page_id));
         }
 
-        let offset = page_id as u64 * PAGE_SIZE as u64;
-        let mut buf = [0u8; PAGE_SIZE];
-
-        // `File` does not require `&mut self` for `read_at`-style access
-        // when using pread under the hood, but the `Read` trait does.
-        // We use a second reference obtained via `(&self.file)` so that
-        // we can call `seek` + `read_exact` without requiring `&mut self`.
-        let file = &self.file;
-        (&*file).seek(SeekFrom::Start(offset))?;
-        (&*file).read_exact(&mut buf)?;
+        let offset = page_id as u64 * self.page_size as u64;
+        let mut buf = vec![0u8; self.page_size];
+        self.storage.read_at(offset, &mut buf)?;
 
         Ok(buf)
     }
@@ -201,17 +445,24 @@ impl Pager {
     ///
     /// * [`HorizonError::ReadOnly`] -- the pager was opened read-only.
     /// * [`HorizonError::PageNotFound`] -- `page_id` is out of range.
+    /// * [`HorizonError::Internal`] -- `data` is not exactly one page.
     /// * [`HorizonError::Io`] -- the underlying write failed.
-    pub fn write_page(&mut self, page_id: PageId, data: &[u8; PAGE_SIZE]) -> Result<()> {
+    pub fn write_page(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
         self.ensure_writable()?;
 
         if page_id >= self.page_count {
             return Err(HorizonError::PageNotFound(page_id));
         }
+        if data.len() != self.page_size {
+            return Err(HorizonError::Internal(format!(
+                "page buffer is {} bytes, expected {}",
+                data.len(),
+                self.page_size
+            )));
+        }
 
-        let offset = page_id as u64 * PAGE_SIZE as u64;
-        self.file.seek(SeekFrom::Start(offset))?;
-        self.file.write_all(data)?;
+        self.write_at_page(page_id, data)?;
+        self.modified = true;
 
         Ok(())
     }
@@ -222,26 +473,24 @@ impl Pager {
 
     /// Allocate a page and return its [`PageId`].
     ///
-    /// If the free list is non-empty the head page is recycled; otherwise
+    /// If the free list is non-empty a free page is recycled; otherwise
     /// the file is extended by one page.
     ///
     /// # Errors
     ///
     /// * [`HorizonError::ReadOnly`] -- the pager was opened read-only.
+    /// * [`HorizonError::CorruptDatabase`] -- the free list is malformed.
     /// * [`HorizonError::Io`] -- disk I/O failed.
     pub fn allocate_page(&mut self) -> Result<PageId> {
         self.ensure_writable()?;
 
-        if self.free_list_head != 0 {
-            // Pop the head of the free list.
-            let page_id = self.free_list_head;
-            let page = self.read_page(page_id)?;
-            let next = u32::from_be_bytes([page[0], page[1], page[2], page[3]]);
-            self.free_list_head = next;
+        let mut free_list = self.free_list;
+        if let Some(page_id) = free_list.pop(self)? {
+            self.free_list = free_list;
 
             // Zero out the recycled page so callers start with a clean
             // slate.
-            let blank = [0u8; PAGE_SIZE];
+            let blank = vec![0u8; self.page_size];
             self.write_page(page_id, &blank)?;
 
             self.flush_header()?;
@@ -249,13 +498,10 @@ impl Pager {
         } else {
             // Extend the file by one page.
             let page_id = self.page_count;
+            let blank = vec![0u8; self.page_size];
+            self.write_at_page(page_id, &blank)?;
             self.page_count += 1;
 
-            let blank = [0u8; PAGE_SIZE];
-            let offset = page_id as u64 * PAGE_SIZE as u64;
-            self.file.seek(SeekFrom::Start(offset))?;
-            self.file.write_all(&blank)?;
-
             self.flush_header()?;
             Ok(page_id)
         }
@@ -264,43 +510,83 @@ impl Pager {
     /// Return `page_id` to the free list so it can be reused by a future
     /// [`allocate_page`](Self::allocate_page) call.
     ///
-    /// The page is overwritten: the first four bytes store the previous
-    /// free-list head (big-endian), and the remainder is zeroed.
+    /// The page is only overwritten if it becomes a new trunk page of the
+    /// free list.
     ///
     /// # Errors
     ///
     /// * [`HorizonError::ReadOnly`] -- the pager was opened read-only.
     /// * [`HorizonError::PageNotFound`] -- `page_id` is out of range.
-    /// * [`HorizonError::CorruptDatabase`] -- attempt to free page 0.
+    /// * [`HorizonError::CorruptDatabase`] -- attempt to free page 0, or
+    ///   the free list is malformed.
     /// * [`HorizonError::Io`] -- disk I/O failed.
     pub fn free_page(&mut self, page_id: PageId) -> Result<()> {
         self.ensure_writable()?;
 
-        if page_id == 0 {
-            return Err(HorizonError::CorruptDatabase(
-                "cannot free the header page (page 0)".into(),
-            ));
-        }
-        if page_id >= self.page_count {
-            return Err(HorizonError::PageNotFound(page_id));
-        }
+        let mut free_list = self.free_list;
+        free_list.push(page_id, self)?;
+        self.set_free_list(free_list)
+    }
 
-        // Build the free-page payload: [next_free (4 bytes)] ++ [zeros].
-        let mut page = [0u8; PAGE_SIZE];
-        page[0..4].copy_from_slice(&self.free_list_head.to_be_bytes());
+    /// Return the free list as recorded in the file header.
+    #[inline]
+    pub fn free_list(&self) -> FreeList {
+        self.free_list
+    }
 
-        self.write_page(page_id, &page)?;
+    /// Replace the free list and persist it to the file header. Used by a
+    /// buffer pool that updates the trunk pages through its cache.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::ReadOnly`] -- the pager was opened read-only.
+    /// * [`HorizonError::Io`] -- disk I/O failed.
+    pub fn set_free_list(&mut self, free_list: FreeList) -> Result<()> {
+        self.ensure_writable()?;
+        self.free_list = free_list;
+        self.flush_header()
+    }
 
-        self.free_list_head = page_id;
+    /// Shrink the file to its first `page_count` pages and empty the free
+    /// list. The caller must make sure no live page lies past the end.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::ReadOnly`] -- the pager was opened read-only.
+    /// * [`HorizonError::Internal`] -- `page_count` would grow the file or
+    ///   drop the header page.
+    /// * [`HorizonError::Io`] -- disk I/O failed.
+    pub fn truncate(&mut self, page_count: u32) -> Result<()> {
+        self.ensure_writable()?;
+        if page_count == 0 || page_count > self.page_count {
+            return Err(HorizonError::Internal(format!(
+                "cannot truncate {} pages to {}",
+                self.page_count, page_count
+            )));
+        }
+        self.page_count = page_count;
+        self.free_list = FreeList::default();
+        self.modified = true;
         self.flush_header()?;
-
-        Ok(())
+        self.storage.truncate(page_count as u64 * self.page_size as u64)
     }
 
     // ---------------------------------------------------------------------
     // Accessors
     // ---------------------------------------------------------------------
 
+    /// Return the size of every page in bytes.
+    #[inline]
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Return `true` if the file was opened without write access.
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     /// Return the total number of pages in the database file, including the
     /// header page.
     #[inline]
@@ -339,6 +625,26 @@ impl Pager {
         self.flush_header()
     }
 
+    /// Return the application-defined version number stored in the file
+    /// header (`0` for a new database).
+    #[inline]
+    pub fn get_user_version(&self) -> u32 {
+        self.user_version
+    }
+
+    /// Set the application-defined version number and persist it to the
+    /// file header.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::ReadOnly`] -- the pager was opened read-only.
+    /// * [`HorizonError::Io`] -- disk I/O failed.
+    pub fn set_user_version(&mut self, version: u32) -> Result<()> {
+        self.ensure_writable()?;
+        self.user_version = version;
+        self.flush_header()
+    }
+
     // ---------------------------------------------------------------------
     // Header persistence
     // ---------------------------------------------------------------------
@@ -348,7 +654,7 @@ impl Pager {
     /// disk.
     ///
     /// Fields that occupy the "reserved" portion of the header
-    /// ([44..100]) are written as zeros.
+    /// ([56..100]) are written as zeros.
     ///
     /// # Errors
     ///
@@ -358,26 +664,27 @@ impl Pager {
         self.ensure_writable()?;
 
         // Read the current page 0 so that we preserve any data that lives
-        // *after* the header (bytes [100..4096]).
-        let mut page = [0u8; PAGE_SIZE];
-        self.file.seek(SeekFrom::Start(0))?;
-        // It is fine if we read fewer bytes (e.g. during initial creation);
+        // *after* the header (bytes [100..page_size]).
+        let mut page = vec![0u8; self.page_size];
+        // It is fine if the read fails (e.g. during initial creation);
         // the buffer is already zeroed.
-        let _ = (&self.file).read(&mut page);
+        let _ = self.storage.read_at(0, &mut page);
 
         // Stamp the header fields.
         page[0..16].copy_from_slice(MAGIC);
-        page[16..20].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        page[16..20].copy_from_slice(&(self.page_size as u32).to_be_bytes());
         page[20..24].copy_from_slice(&self.page_count.to_be_bytes());
-        page[24..28].copy_from_slice(&self.free_list_head.to_be_bytes());
+        page[24..28].copy_from_slice(&self.free_list.head().to_be_bytes());
         page[28..32].copy_from_slice(&self.schema_version.to_be_bytes());
         page[32..40].copy_from_slice(&self.next_txn_id.to_be_bytes());
         page[40..44].copy_from_slice(&self.schema_root.to_be_bytes());
-        // [44..100] reserved -- ensure they are zeroed.
-        page[44..HEADER_SIZE].fill(0);
+        page[44..48].copy_from_slice(&self.user_version.to_be_bytes());
+        page[48..52].copy_from_slice(&self.change_counter.to_be_bytes());
+        page[52..56].copy_from_slice(&self.free_list.len().to_be_bytes());
+        // [56..100] reserved -- ensure they are zeroed.
+        page[56..HEADER_SIZE].fill(0);
 
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.write_all(&page)?;
+        self.write_at_page(0, &page)?;
 
         Ok(())
     }
@@ -386,11 +693,152 @@ impl Pager {
     /// previously written data has been durably flushed to the underlying
     /// storage device.
     ///
+    /// If pages were written since the last sync, the header's change
+    /// counter is bumped first. Once the file is synced, the rollback
+    /// journal's transaction is committed. With [`SynchronousMode::Off`]
+    /// the file is not actually flushed. If writing the header or syncing
+    /// fails, the transaction is rolled back (see [`rollback`](Self::rollback))
+    /// before the error is returned.
+    ///
     /// # Errors
     ///
     /// * [`HorizonError::Io`] -- the sync failed.
-    pub fn sync(&self) -> Result<()> {
-        self.file.sync_all()?;
+    pub fn sync(&mut self) -> Result<()> {
+        if let Err(e) = self.commit() {
+            // The error that stopped the commit matters more than one
+            // from the rollback; a journal left behind is recovered on open
+            let _ = self.rollback();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.modified {
+            self.change_counter = self.change_counter.wrapping_add(1);
+            self.flush_header()?;
+            self.modified = false;
+        }
+        if self.synchronous != SynchronousMode::Off {
+            self.storage.sync()?;
+        }
+        if let Some(ref mut journal) = self.journal {
+            journal.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Set whether [`sync`](Self::sync) flushes the file, and when the
+    /// rollback journal is synced.
+    pub fn set_synchronous(&mut self, mode: SynchronousMode) {
+        self.synchronous = mode;
+        if let Some(ref mut journal) = self.journal {
+            journal.set_synchronous(mode);
+        }
+    }
+
+    /// Put back every page written since the last [`sync`](Self::sync)
+    /// from the rollback journal, and reload the header. Returns `false`,
+    /// changing nothing, when there is no journal or nothing to undo.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::Io`] -- reading the journal or writing a page
+    ///   failed.
+    pub fn rollback(&mut self) -> Result<bool> {
+        let Pager { ref mut journal, ref mut storage, .. } = *self;
+        let rolled_back = match journal {
+            Some(journal) => journal.rollback(storage.as_mut())?,
+            None => false,
+        };
+        if rolled_back {
+            self.read_header()?;
+            self.modified = false;
+        }
+        Ok(rolled_back)
+    }
+
+    /// Return the rollback journal's mode, or `None` if the pager has no
+    /// rollback journal.
+    pub fn journal_mode(&self) -> Option<JournalMode> {
+        self.journal.as_ref().map(RollbackJournal::mode)
+    }
+
+    /// Replace the rollback journal. Changes saved in the old one are
+    /// committed first.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::ReadOnly`] -- a journal was given to a read-only
+    ///   pager.
+    /// * [`HorizonError::Io`] -- syncing the file failed.
+    pub fn set_journal(&mut self, mut journal: Option<RollbackJournal>) -> Result<()> {
+        if let Some(ref mut journal) = journal {
+            self.ensure_writable()?;
+            journal.set_synchronous(self.synchronous);
+        }
+        if self.journal.as_ref().is_some_and(RollbackJournal::in_transaction) {
+            self.sync()?;
+        }
+        self.journal = journal;
+        Ok(())
+    }
+
+    /// Return the path of the database file, or `None` for an in-memory
+    /// database.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Return `true` if another handle has changed the file since this
+    /// pager last read or wrote its header, so that pages cached above it
+    /// may be out of date.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::Io`] -- reading the header failed.
+    pub fn is_stale(&self) -> Result<bool> {
+        let mut counter = [0u8; 4];
+        self.storage.read_at(48, &mut counter)?;
+        Ok(u32::from_be_bytes(counter) != self.change_counter)
+    }
+
+    /// Copy every page to a new database file at `path`, replacing any
+    /// file already there.
+    ///
+    /// The pages are written to a temporary file beside `path` that is
+    /// synced and then renamed into place, so `path` never holds a partial
+    /// copy. `progress` is called after each page with the number of pages
+    /// copied so far and the total.
+    ///
+    /// The copy is only consistent if nothing writes to this pager while
+    /// it runs; callers must also have flushed any pages they cache.
+    ///
+    /// # Errors
+    ///
+    /// * [`HorizonError::Io`] -- reading a page or writing the copy failed.
+    pub fn copy_to_file<F: FnMut(u32, u32)>(&self, path: &Path, mut progress: F) -> Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = std::path::PathBuf::from(tmp_path);
+
+        if let Err(e) = self.write_pages_to(&tmp_path, &mut progress) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Write every page, in order, to a new file at `path` and sync it.
+    fn write_pages_to<F: FnMut(u32, u32)>(&self, path: &Path, progress: &mut F) -> Result<()> {
+        let mut writer = std::io::BufWriter::new(File::create(path)?);
+        for page_id in 0..self.page_count {
+            writer.write_all(&self.read_page(page_id)?)?;
+            progress(page_id + 1, self.page_count);
+        }
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
         Ok(())
     }
 
@@ -402,8 +850,7 @@ impl Pager {
     /// in-memory metadata fields.
     fn read_header(&mut self) -> Result<()> {
         let mut header = [0u8; HEADER_SIZE];
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.read_exact(&mut header)?;
+        self.storage.read_at(0, &mut header)?;
 
         // Validate magic bytes.
         if &header[0..16] != MAGIC {
@@ -416,16 +863,17 @@ impl Pager {
         let stored_page_size = u32::from_be_bytes([
             header[16], header[17], header[18], header[19],
         ]) as usize;
-        if stored_page_size != PAGE_SIZE {
+        if !is_valid_page_size(stored_page_size) {
             return Err(HorizonError::CorruptDatabase(format!(
-                "unexpected page size {stored_page_size} (expected {PAGE_SIZE})"
+                "unsupported page size {stored_page_size}"
             )));
         }
+        self.page_size = stored_page_size;
 
         self.page_count = u32::from_be_bytes([
             header[20], header[21], header[22], header[23],
         ]);
-        self.free_list_head = u32::from_be_bytes([
+        let free_list_head = u32::from_be_bytes([
             header[24], header[25], header[26], header[27],
         ]);
         self.schema_version = u32::from_be_bytes([
@@ -438,10 +886,36 @@ impl Pager {
         self.schema_root = u32::from_be_bytes([
             header[40], header[41], header[42], header[43],
         ]);
+        self.user_version = u32::from_be_bytes([
+            header[44], header[45], header[46], header[47],
+        ]);
+        self.change_counter = u32::from_be_bytes([
+            header[48], header[49], header[50], header[51],
+        ]);
+        let free_page_count = u32::from_be_bytes([
+            header[52], header[53], header[54], header[55],
+        ]);
+        self.free_list = FreeList::new(free_list_head, free_page_count);
+        if free_list_head != 0 && free_page_count == 0 {
+            // Written before the header recorded the count. A malformed
+            // list is left for the integrity check to report.
+            let free_list = self.free_list;
+            let count = free_list.count(self).unwrap_or(0);
+            self.free_list = FreeList::new(free_list_head, count);
+        }
 
         Ok(())
     }
 
+    /// Write `data` over page `page_id`, first saving the page's current
+    /// image in the rollback journal if there is one.
+    fn write_at_page(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        if let Some(ref mut journal) = self.journal {
+            journal.save(self.storage.as_ref(), self.page_size, self.page_count, page_id)?;
+        }
+        self.storage.write_at(page_id as u64 * self.page_size as u64, data)
+    }
+
     /// Return `Err(HorizonError::ReadOnly(..))` when the pager was opened
     /// in read-only mode.
     #[inline]
@@ -456,6 +930,24 @@ impl Pager {
     }
 }
 
+impl FreeListPages for Pager {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    fn read_trunk(&mut self, page_id: PageId) -> Result<Vec<u8>> {
+        self.read_page(page_id)
+    }
+
+    fn write_trunk(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.write_page(page_id, data)
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -479,6 +971,7 @@ mod tests {
         let (pager, _tmp) = new_pager();
         assert_eq!(pager.page_count(), 1);
         assert_eq!(pager.schema_root(), 0);
+        assert_eq!(pager.get_user_version(), 0);
     }
 
     #[test]
@@ -495,6 +988,7 @@ mod tests {
         {
             let mut pager = Pager::open(tmp.path(), false).unwrap();
             pager.set_schema_root(42).unwrap();
+            pager.set_user_version(0xDEAD_BEEF).unwrap();
             let _id = pager.next_txn_id(); // bumps to 2
             pager.flush_header().unwrap();
             pager.sync().unwrap();
@@ -503,6 +997,7 @@ mod tests {
         // Re-open and verify.
         let pager = Pager::open(tmp.path(), true).unwrap();
         assert_eq!(pager.schema_root(), 42);
+        assert_eq!(pager.get_user_version(), 0xDEAD_BEEF);
         assert_eq!(pager.page_count(), 1);
     }
 
@@ -629,6 +1124,26 @@ mod tests {
         assert_eq!(pager.allocate_page().unwrap(), p1);
     }
 
+    #[test]
+    fn truncate_drops_trailing_pages_and_the_free_list() {
+        let (mut pager, tmp) = new_pager();
+        for _ in 0..4 {
+            pager.allocate_page().unwrap();
+        }
+        pager.free_page(2).unwrap();
+        pager.truncate(3).unwrap();
+        assert!(pager.truncate(4).is_err());
+        assert!(pager.truncate(0).is_err());
+        drop(pager);
+
+        assert_eq!(std::fs::metadata(tmp.path()).unwrap().len(), 3 * PAGE_SIZE as u64);
+        let mut pager = Pager::open(tmp.path(), false).unwrap();
+        assert_eq!(pager.page_count(), 3);
+        assert!(matches!(pager.read_page(3), Err(HorizonError::PageNotFound(3))));
+        // Page 2 is no longer on the free list
+        assert_eq!(pager.allocate_page().unwrap(), 3);
+    }
+
     #[test]
     fn cannot_free_page_zero() {
         let (mut pager, _tmp) = new_pager();
@@ -663,6 +1178,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn free_page_count_survives_reopen() {
+        let tmp = NamedTempFile::new().unwrap();
+        {
+            let mut pager = Pager::open(tmp.path(), false).unwrap();
+            for _ in 0..10 {
+                pager.allocate_page().unwrap();
+            }
+            for page_id in [3, 5, 7] {
+                pager.free_page(page_id).unwrap();
+            }
+            assert_eq!(pager.free_list().len(), 3);
+            pager.sync().unwrap();
+        }
+
+        let mut pager = Pager::open(tmp.path(), false).unwrap();
+        assert_eq!(pager.free_list().len(), 3);
+        assert_eq!(pager.allocate_page().unwrap(), 7);
+        assert_eq!(pager.free_list().len(), 2);
+    }
+
+    #[test]
+    fn free_list_without_a_stored_count_is_counted_on_open() {
+        let tmp = NamedTempFile::new().unwrap();
+        {
+            let mut pager = Pager::open(tmp.path(), false).unwrap();
+            for _ in 0..4 {
+                pager.allocate_page().unwrap();
+            }
+            // A free list in the older format: pages 4 -> 2, chained
+            // through their first four bytes, and no count in the header
+            let mut page = vec![0u8; PAGE_SIZE];
+            page[0..4].copy_from_slice(&2u32.to_be_bytes());
+            pager.write_page(4, &page).unwrap();
+            pager.free_list = FreeList::new(4, 0);
+            pager.flush_header().unwrap();
+            pager.sync().unwrap();
+        }
+
+        let mut pager = Pager::open(tmp.path(), false).unwrap();
+        assert_eq!(pager.free_list().len(), 2);
+        assert_eq!(pager.allocate_page().unwrap(), 4);
+        assert_eq!(pager.allocate_page().unwrap(), 2);
+        assert_eq!(pager.allocate_page().unwrap(), 5);
+    }
+
     // ----- Transaction IDs ---------------------------------------------
 
     #[test]
@@ -713,6 +1274,10 @@ mod tests {
             pager.set_schema_root(5).unwrap_err(),
             HorizonError::ReadOnly(_)
         ));
+        assert!(matches!(
+            pager.set_user_version(5).unwrap_err(),
+            HorizonError::ReadOnly(_)
+        ));
         assert!(matches!(
             pager.flush_header().unwrap_err(),
             HorizonError::ReadOnly(_)
@@ -772,7 +1337,7 @@ mod tests {
 
     #[test]
     fn sync_does_not_error() {
-        let (pager, _tmp) = new_pager();
+        let (mut pager, _tmp) = new_pager();
         pager.sync().unwrap();
     }
 
@@ -786,6 +1351,79 @@ mod tests {
         assert_eq!(stored as usize, PAGE_SIZE);
     }
 
+    #[test]
+    fn custom_page_size_round_trips() {
+        let tmp = NamedTempFile::new().unwrap();
+        std::fs::remove_file(tmp.path()).unwrap();
+
+        {
+            let mut pager = Pager::open_with_page_size(tmp.path(), false, Some(1024)).unwrap();
+            let id = pager.allocate_page().unwrap();
+            pager.write_page(id, &[7u8; 1024]).unwrap();
+            assert!(pager.write_page(id, &[0u8; PAGE_SIZE]).is_err());
+            pager.sync().unwrap();
+        }
+        assert_eq!(std::fs::metadata(tmp.path()).unwrap().len(), 2048);
+
+        // The stored size is adopted, and must match an explicit request
+        let pager = Pager::open(tmp.path(), true).unwrap();
+        assert_eq!(pager.page_size(), 1024);
+        assert_eq!(pager.read_page(1).unwrap(), vec![7u8; 1024]);
+        let err = Pager::open_with_page_size(tmp.path(), true, Some(2048)).unwrap_err();
+        assert!(matches!(err, HorizonError::CorruptDatabase(_)));
+    }
+
+    // ----- In-memory storage -------------------------------------------
+
+    #[test]
+    fn memory_pager_reads_and_writes() {
+        let mut pager = Pager::open_memory(Some(512)).unwrap();
+        assert_eq!(pager.page_size(), 512);
+        assert_eq!(&pager.read_page(0).unwrap()[0..16], MAGIC);
+
+        let a = pager.allocate_page().unwrap();
+        let b = pager.allocate_page().unwrap();
+        pager.write_page(b, &[9u8; 512]).unwrap();
+        assert_eq!(pager.read_page(a).unwrap(), vec![0u8; 512]);
+        assert_eq!(pager.read_page(b).unwrap(), vec![9u8; 512]);
+
+        pager.free_page(a).unwrap();
+        assert_eq!(pager.allocate_page().unwrap(), a);
+        assert!(pager.read_page(10).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_page_size() {
+        let tmp = NamedTempFile::new().unwrap();
+        for size in [256, 1000, 2 * MAX_PAGE_SIZE] {
+            assert!(Pager::open_with_page_size(tmp.path(), false, Some(size)).is_err());
+        }
+    }
+
+    // ----- Copying -----------------------------------------------------
+
+    #[test]
+    fn copy_to_file_produces_an_identical_database() {
+        let mut pager = Pager::open_memory(Some(1024)).unwrap();
+        let page = pager.allocate_page().unwrap();
+        pager.write_page(page, &vec![0x5A; 1024]).unwrap();
+        pager.set_schema_root(page).unwrap();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("copy.hdb");
+        std::fs::write(&path, b"old contents").unwrap();
+        let mut calls = Vec::new();
+        pager.copy_to_file(&path, |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(calls, [(1, 2), (2, 2)]);
+
+        let copy = Pager::open(&path, true).unwrap();
+        assert_eq!(copy.page_size(), 1024);
+        assert_eq!(copy.page_count(), 2);
+        assert_eq!(copy.schema_root(), page);
+        assert_eq!(copy.read_page(page).unwrap(), vec![0x5A; 1024]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
     // ----- Large allocation sequence -----------------------------------
 
     #[test]
@@ -842,4 +1480,197 @@ mod tests {
             assert_eq!(pager.page_count(), 5);
         }
     }
+
+    // ----- Rollback journal ----------------------------------------------
+
+    #[test]
+    fn rollback_journal_undoes_writes_until_sync() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("test.hdb");
+        let config = DatabaseConfig { journal_mode: JournalMode::Delete, ..DatabaseConfig::default() };
+        let mut pager = Pager::open_with_config(&path, &config).unwrap();
+        let p1 = pager.allocate_page().unwrap();
+        pager.write_page(p1, &vec![1u8; pager.page_size()]).unwrap();
+        pager.sync().unwrap();
+        assert!(!journal_path(&path).exists());
+
+        pager.write_page(p1, &vec![2u8; pager.page_size()]).unwrap();
+        let p2 = pager.allocate_page().unwrap();
+        pager.set_user_version(7).unwrap();
+        assert!(journal_path(&path).exists());
+        assert!(pager.rollback().unwrap());
+        assert_eq!(pager.read_page(p1).unwrap()[0], 1);
+        assert_eq!(pager.page_count(), p2);
+        assert_eq!(pager.get_user_version(), 0);
+        assert!(!journal_path(&path).exists());
+
+        // A journal left by a crashed writer is rolled back on open
+        pager.write_page(p1, &vec![3u8; pager.page_size()]).unwrap();
+        drop(pager);
+        let pager = Pager::open(&path, false).unwrap();
+        assert_eq!(pager.read_page(p1).unwrap()[0], 1);
+        assert!(!journal_path(&path).exists());
+    }
+
+    /// In-memory storage whose writes and syncs can be made to fail.
+    #[derive(Debug)]
+    struct FaultyStorage {
+        inner: MemoryPager,
+        /// Fail the write this many writes from now (the next one at 1);
+        /// 0 fails none.
+        fail_write_in: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        /// Fail the next sync.
+        fail_sync: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl PageStorage for FaultyStorage {
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+            self.inner.read_at(offset, buf)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+            use std::sync::atomic::Ordering;
+            let countdown = self.fail_write_in.load(Ordering::SeqCst);
+            if countdown > 0 {
+                self.fail_write_in.store(countdown - 1, Ordering::SeqCst);
+                if countdown == 1 {
+                    return Err(std::io::Error::other("injected write failure").into());
+                }
+            }
+            self.inner.write_at(offset, data)
+        }
+
+        fn sync(&self) -> Result<()> {
+            if self.fail_sync.swap(false, std::sync::atomic::Ordering::SeqCst) {
+                return Err(std::io::Error::other("injected sync failure").into());
+            }
+            Ok(())
+        }
+
+        fn truncate(&mut self, len: u64) -> Result<()> {
+            self.inner.truncate(len)
+        }
+    }
+
+    /// A pager with a memory journal over [`FaultyStorage`], and the
+    /// storage's write countdown and sync switch.
+    fn faulty_pager() -> (
+        Pager,
+        std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        std::sync::Arc<std::sync::atomic::AtomicBool>,
+    ) {
+        let fail_write_in = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let fail_sync = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let storage = FaultyStorage {
+            inner: MemoryPager::new(PAGE_SIZE),
+            fail_write_in: fail_write_in.clone(),
+            fail_sync: fail_sync.clone(),
+        };
+        let mut pager = Pager::create(Box::new(storage), PAGE_SIZE).unwrap();
+        pager.set_journal(Some(RollbackJournal::memory())).unwrap();
+        (pager, fail_write_in, fail_sync)
+    }
+
+    #[test]
+    fn failed_sync_rolls_back_the_transaction() {
+        use std::sync::atomic::Ordering;
+
+        let (mut pager, _, fail_sync) = faulty_pager();
+        let page_id = pager.allocate_page().unwrap();
+        pager.write_page(page_id, &[1u8; PAGE_SIZE]).unwrap();
+        pager.sync().unwrap();
+
+        pager.write_page(page_id, &[2u8; PAGE_SIZE]).unwrap();
+        let grown = pager.allocate_page().unwrap();
+        pager.set_user_version(3).unwrap();
+        fail_sync.store(true, Ordering::SeqCst);
+        assert!(pager.sync().is_err());
+        assert_eq!(pager.read_page(page_id).unwrap()[0], 1);
+        assert_eq!(pager.page_count(), grown);
+        assert_eq!(pager.get_user_version(), 0);
+
+        // The next transaction commits normally
+        pager.write_page(page_id, &[4u8; PAGE_SIZE]).unwrap();
+        pager.sync().unwrap();
+        assert!(!pager.rollback().unwrap());
+        assert_eq!(pager.read_page(page_id).unwrap()[0], 4);
+    }
+
+    #[test]
+    fn failed_flush_rolls_back_and_drops_cached_pages() {
+        use crate::buffer::BufferPool;
+        use std::sync::atomic::Ordering;
+
+        let (pager, fail_write_in, _) = faulty_pager();
+        let mut pool = BufferPool::new(pager, None, 16).unwrap();
+        let first = pool.allocate_page().unwrap();
+        let second = pool.allocate_page().unwrap();
+        for (page_id, byte) in [(first, 1), (second, 1)] {
+            pool.get_page_mut(page_id).unwrap()[0] = byte;
+            pool.unpin(page_id);
+        }
+        pool.flush_all().unwrap();
+
+        for (page_id, byte) in [(first, 2), (second, 2)] {
+            pool.get_page_mut(page_id).unwrap()[0] = byte;
+            pool.unpin(page_id);
+        }
+        // The first page is written, then writing the second fails
+        fail_write_in.store(2, Ordering::SeqCst);
+        assert!(pool.flush_all().is_err());
+        for page_id in [first, second] {
+            assert_eq!(pool.get_page(page_id).unwrap()[0], 1);
+            pool.unpin(page_id);
+        }
+    }
+
+    // ----- Synchronous modes ---------------------------------------------
+
+    /// In-memory storage that counts its syncs.
+    #[derive(Debug)]
+    struct CountingStorage {
+        inner: MemoryPager,
+        syncs: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl PageStorage for CountingStorage {
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+            self.inner.read_at(offset, buf)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+            self.inner.write_at(offset, data)
+        }
+
+        fn sync(&self) -> Result<()> {
+            self.syncs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn truncate(&mut self, len: u64) -> Result<()> {
+            self.inner.truncate(len)
+        }
+    }
+
+    #[test]
+    fn sync_honors_synchronous_mode() {
+        use std::sync::atomic::Ordering;
+
+        let syncs = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let storage = CountingStorage { inner: MemoryPager::new(PAGE_SIZE), syncs: syncs.clone() };
+        let mut pager = Pager::create(Box::new(storage), PAGE_SIZE).unwrap();
+        let created = syncs.load(Ordering::SeqCst);
+
+        pager.set_synchronous(SynchronousMode::Off);
+        let page_id = pager.allocate_page().unwrap();
+        pager.write_page(page_id, &[1u8; PAGE_SIZE]).unwrap();
+        pager.sync().unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), created);
+
+        pager.set_synchronous(SynchronousMode::Full);
+        pager.write_page(page_id, &[2u8; PAGE_SIZE]).unwrap();
+        pager.sync().unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), created + 1);
+        assert_eq!(pager.read_page(page_id).unwrap()[0], 2);
+    }
 }
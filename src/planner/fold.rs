@@ -0,0 +1,263 @@
+//! Constant folding.
+//!
+//! [`fold_constants`] replaces each operator whose operands are all
+//! literals by the literal it evaluates to, so `1 + 2` becomes `3` and
+//! `NOT TRUE` becomes `0`. An operator whose evaluation fails is left for
+//! execution to report.
+//!
+//! [`fold_plan`] folds the predicates of a plan (its filters, join
+//! conditions, and HAVING clauses) and additionally uses the NOT NULL
+//! constraints of the scanned tables: `col IS NULL` is false and
+//! `col = col` is true for a NOT NULL column, unless an outer join may
+//! NULL-pad it. A filter whose predicate folds to true is removed; one
+//! that folds to false or NULL is kept as a `FALSE` filter, which
+//! execution answers without reading its input.
+
+use crate::catalog::{Catalog, ColumnInfo};
+use crate::execution::{eval_binary_op, eval_unary_op, params::value_to_literal};
+use crate::sql::ast::*;
+use crate::types::Value;
+use super::LogicalPlan;
+
+/// Fold the constant subexpressions of `expr`.
+pub fn fold_constants(expr: &Expr) -> Expr {
+    fold(expr, &[])
+}
+
+/// Fold the predicates of `plan` (its filters, join conditions, and HAVING
+/// clauses), using the NOT NULL constraints of the tables it scans, and
+/// drop the filters that are always true.
+pub fn fold_plan(plan: LogicalPlan, catalog: &Catalog) -> LogicalPlan {
+    let fold_input = |input: Box<LogicalPlan>| Box::new(fold_plan(*input, catalog));
+    match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            let predicate = simplify_predicate(fold(&predicate, &scope(&input, catalog, false)));
+            let input = fold_input(input);
+            match literal_truth(&predicate) {
+                Some(true) => *input,
+                Some(false) => LogicalPlan::Filter { input, predicate: Expr::Literal(LiteralValue::False) },
+                None => LogicalPlan::Filter { input, predicate },
+            }
+        }
+        LogicalPlan::Join { left, right, join_type, on, using } => {
+            let mut tables = scope(&left, catalog, false);
+            tables.extend(scope(&right, catalog, false));
+            let on = on
+                .map(|on| simplify_predicate(fold(&on, &tables)))
+                .filter(|on| literal_truth(on) != Some(true));
+            LogicalPlan::Join { left: fold_input(left), right: fold_input(right), join_type, on, using }
+        }
+        LogicalPlan::MergeJoin { left, right, join_type, left_key, right_key } => {
+            LogicalPlan::MergeJoin { left: fold_input(left), right: fold_input(right), join_type, left_key, right_key }
+        }
+        LogicalPlan::Aggregate { input, group_by, modifier, having } => LogicalPlan::Aggregate {
+            input: fold_input(input),
+            group_by,
+            modifier,
+            having: having.map(|having| simplify_predicate(fold_constants(&having))),
+        },
+        LogicalPlan::Project { input, columns } => LogicalPlan::Project { input: fold_input(input), columns },
+        LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort { input: fold_input(input), order_by },
+        LogicalPlan::Limit { input, limit, offset } => LogicalPlan::Limit { input: fold_input(input), limit, offset },
+        LogicalPlan::Distinct { input } => LogicalPlan::Distinct { input: fold_input(input) },
+        other => other,
+    }
+}
+
+/// Whether the literal `expr` is true or false (NULL counting as false),
+/// or `None` if it is not a literal.
+pub(crate) fn literal_truth(expr: &Expr) -> Option<bool> {
+    match expr {
+        Expr::Literal(LiteralValue::Null) => Some(false),
+        Expr::Literal(lit) => Some(literal_value(lit).to_bool()),
+        _ => None,
+    }
+}
+
+/// Drop the conjuncts of `expr` that are always true and the disjuncts
+/// that are always false or NULL, and reduce it to a literal if it is then
+/// known to be true or false. Only the truth of the result is kept, so
+/// this applies to predicates but not to values.
+fn simplify_predicate(expr: Expr) -> Expr {
+    match expr {
+        Expr::BinaryOp { left, op: op @ (BinaryOp::And | BinaryOp::Or), right } => {
+            let (left, right) = (simplify_predicate(*left), simplify_predicate(*right));
+            // The value that decides the operator on its own
+            let decisive = op == BinaryOp::Or;
+            match (literal_truth(&left), literal_truth(&right)) {
+                (Some(l), _) if l == decisive => Expr::Literal(truth(decisive)),
+                (_, Some(r)) if r == decisive => Expr::Literal(truth(decisive)),
+                (Some(_), _) => right,
+                (_, Some(_)) => left,
+                _ => Expr::BinaryOp { left: Box::new(left), op, right: Box::new(right) },
+            }
+        }
+        other => other,
+    }
+}
+
+/// A table scanned by a plan, under the name its columns are qualified
+/// with.
+struct ScopeTable<'a> {
+    name: String,
+    columns: &'a [ColumnInfo],
+    /// Whether an outer join may NULL-pad its rows.
+    padded: bool,
+}
+
+/// The tables scanned by `plan`, marked as padded if `padded` is set or
+/// an outer join in `plan` pads them.
+fn scope<'a>(plan: &LogicalPlan, catalog: &'a Catalog, padded: bool) -> Vec<ScopeTable<'a>> {
+    match plan {
+        LogicalPlan::SeqScan { table, alias, .. } => match catalog.get_table(table) {
+            Ok(info) => vec![ScopeTable {
+                name: alias.clone().unwrap_or_else(|| table.clone()),
+                columns: &info.columns,
+                padded,
+            }],
+            Err(_) => Vec::new(),
+        },
+        LogicalPlan::Filter { input, .. } => scope(input, catalog, padded),
+        LogicalPlan::Join { left, right, join_type, .. } | LogicalPlan::MergeJoin { left, right, join_type, .. } => {
+            let left_padded = matches!(join_type, JoinType::Right | JoinType::Full);
+            let right_padded = matches!(join_type, JoinType::Left | JoinType::Full);
+            let mut tables = scope(left, catalog, padded || left_padded);
+            tables.extend(scope(right, catalog, padded || right_padded));
+            tables
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the column `expr` is known never to be NULL: it resolves to a
+/// single NOT NULL column of a table in `tables` that is not padded.
+fn never_null(expr: &Expr, tables: &[ScopeTable]) -> bool {
+    let (table, name) = match expr {
+        Expr::Column { table, name } => (table, name),
+        _ => return false,
+    };
+    let mut matches = tables
+        .iter()
+        .filter(|t| table.as_ref().map_or(true, |table| t.name.eq_ignore_ascii_case(table)))
+        .filter_map(|t| Some((t, t.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))?)));
+    match (matches.next(), matches.next()) {
+        (Some((t, column)), None) => column.not_null && !t.padded,
+        _ => false,
+    }
+}
+
+fn fold(expr: &Expr, tables: &[ScopeTable]) -> Expr {
+    let boxed = |e: &Expr| Box::new(fold(e, tables));
+    match expr {
+        Expr::BinaryOp { left, op, right } => {
+            let (left, right) = (boxed(left), boxed(right));
+            if let (Expr::Literal(l), Expr::Literal(r)) = (left.as_ref(), right.as_ref()) {
+                if let Ok(value) = eval_binary_op(&literal_value(l), op, &literal_value(r)) {
+                    return Expr::Literal(value_to_literal(&value));
+                }
+            }
+            if *op == BinaryOp::Eq && left == right && never_null(&left, tables) {
+                return Expr::Literal(LiteralValue::True);
+            }
+            Expr::BinaryOp { left, op: op.clone(), right }
+        }
+        Expr::UnaryOp { op, expr } => {
+            let expr = boxed(expr);
+            match expr.as_ref() {
+                Expr::Literal(lit) => Expr::Literal(value_to_literal(&eval_unary_op(op, &literal_value(lit)))),
+                _ => Expr::UnaryOp { op: op.clone(), expr },
+            }
+        }
+        Expr::IsNull { expr, negated } => {
+            let expr = boxed(expr);
+            match expr.as_ref() {
+                Expr::Literal(lit) => Expr::Literal(truth(matches!(lit, LiteralValue::Null) != *negated)),
+                column if never_null(column, tables) => Expr::Literal(truth(*negated)),
+                _ => Expr::IsNull { expr, negated: *negated },
+            }
+        }
+        Expr::Between { expr, low, high, negated } => Expr::Between {
+            expr: boxed(expr),
+            low: boxed(low),
+            high: boxed(high),
+            negated: *negated,
+        },
+        Expr::InList { expr, list, negated } => Expr::InList {
+            expr: boxed(expr),
+            list: list.iter().map(|e| fold(e, tables)).collect(),
+            negated: *negated,
+        },
+        Expr::Like { expr, pattern, negated } => Expr::Like {
+            expr: boxed(expr),
+            pattern: boxed(pattern),
+            negated: *negated,
+        },
+        Expr::Function { name, args, distinct, filter } => Expr::Function {
+            name: name.clone(),
+            args: args.iter().map(|e| fold(e, tables)).collect(),
+            distinct: *distinct,
+            filter: filter.clone(),
+        },
+        Expr::Cast { expr, type_name } => Expr::Cast { expr: boxed(expr), type_name: type_name.clone() },
+        Expr::Case { operand, when_clauses, else_clause } => Expr::Case {
+            operand: operand.as_deref().map(boxed),
+            when_clauses: when_clauses.iter().map(|(w, t)| (fold(w, tables), fold(t, tables))).collect(),
+            else_clause: else_clause.as_deref().map(boxed),
+        },
+        other => other.clone(),
+    }
+}
+
+fn literal_value(lit: &LiteralValue) -> Value {
+    match lit {
+        LiteralValue::Null => Value::Null,
+        LiteralValue::Integer(i) => Value::Integer(*i),
+        LiteralValue::Real(r) => Value::Real(*r),
+        LiteralValue::String(s) => Value::Text(s.clone()),
+        LiteralValue::Blob(b) => Value::Blob(b.clone()),
+        LiteralValue::True => Value::Integer(1),
+        LiteralValue::False => Value::Integer(0),
+    }
+}
+
+fn truth(value: bool) -> LiteralValue {
+    if value { LiteralValue::True } else { LiteralValue::False }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+
+    fn expr(sql: &str) -> Expr {
+        let select = match Parser::parse(&format!("SELECT {}", sql)).unwrap().remove(0) {
+            Statement::Select(select) => select,
+            other => panic!("expected SELECT, got {:?}", other),
+        };
+        match &select.columns[0] {
+            SelectColumn::Expr { expr, .. } => expr.clone(),
+            other => panic!("expected an expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn constant_operators_are_folded() {
+        assert_eq!(fold_constants(&expr("1 + 2 * 3")), expr("7"));
+        assert_eq!(fold_constants(&expr("'a' || 'b'")), expr("'ab'"));
+        assert_eq!(fold_constants(&expr("NOT TRUE")), expr("0"));
+        assert_eq!(fold_constants(&expr("NULL IS NULL")), expr("TRUE"));
+        assert_eq!(fold_constants(&expr("x > 10 / 4")), expr("x > 2"));
+        assert_eq!(fold_constants(&expr("(1 + 2) * x + 4 - 1")), expr("3 * x + 4 - 1"));
+    }
+
+    #[test]
+    fn predicates_reduce_to_their_deciding_terms() {
+        let simplify = |sql: &str| simplify_predicate(fold_constants(&expr(sql)));
+        assert_eq!(simplify("x > 0 AND 1 = 1"), expr("x > 0"));
+        assert_eq!(simplify("x > 0 AND 1 = 0"), expr("FALSE"));
+        assert_eq!(simplify("x > 0 OR 2 > 1"), expr("TRUE"));
+        assert_eq!(simplify("x > 0 OR NULL"), expr("x > 0"));
+        assert_eq!(literal_truth(&simplify("1 = 1 AND 'a' = 'a'")), Some(true));
+        assert_eq!(literal_truth(&simplify("x = x")), None);
+    }
+}
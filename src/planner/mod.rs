@@ -2,19 +2,35 @@
 //!
 //! Transforms parsed SQL AST nodes into logical and physical execution plans.
 //! Currently implements a simple rule-based planner; cost-based optimization
-//! will be added in a later phase.
+//! will be added in a later phase. SELECT plans are rewritten by
+//! [`push_down_predicates`], which moves WHERE conjuncts below joins, and
+//! [`fold_plan`], which folds constants in their predicates. Finally,
+//! [`plan_merge_joins`] turns joins of two tables on their INTEGER PRIMARY
+//! KEYs into merge joins, which need no hash table, and
+//! [`plan_scan_methods`] decides how each scan of a single table reads the
+//! rows its filter keeps: by a seek on the primary key, through an index,
+//! or by reading them all. A scan below an ORDER BY may instead read its
+//! rows through an index whose keys are in the ORDER BY's order, which
+//! does away with the sort. Before a SELECT is planned,
+//! [`rewrite_fulltext_matches`] turns `MATCH`es on tables with a full-text
+//! index into searches of the index.
 
-use crate::catalog::Catalog;
+use crate::catalog::{Catalog, FulltextIndexInfo};
 use crate::error::{HorizonError, Result};
 use crate::sql::ast::*;
 
+mod fold;
+pub use fold::{fold_constants, fold_plan};
+pub(crate) use fold::literal_truth;
+
 /// A logical plan node describing what to compute.
 #[derive(Debug, Clone)]
 pub enum LogicalPlan {
-    /// Scan all rows from a table.
+    /// Scan the rows of a table, reading them as `method` says.
     SeqScan {
         table: String,
         alias: Option<String>,
+        method: ScanMethod,
     },
     /// Filter rows by a predicate.
     Filter {
@@ -41,6 +57,7 @@ pub enum LogicalPlan {
     Aggregate {
         input: Box<LogicalPlan>,
         group_by: Vec<Expr>,
+        modifier: Option<GroupByModifier>,
         having: Option<Expr>,
     },
     /// Join two inputs.
@@ -49,6 +66,19 @@ pub enum LogicalPlan {
         right: Box<LogicalPlan>,
         join_type: JoinType,
         on: Option<Expr>,
+        /// Columns matched by name (from `USING` or `NATURAL`); each is
+        /// emitted only once in the join output.
+        using: Vec<String>,
+    },
+    /// Join two inputs that are read in order of their join keys by
+    /// advancing a cursor over each. The keys are the columns at
+    /// `left_key` and `right_key` of the inputs' rows.
+    MergeJoin {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        join_type: JoinType,
+        left_key: usize,
+        right_key: usize,
     },
     /// Distinct (remove duplicate rows).
     Distinct {
@@ -58,7 +88,7 @@ pub enum LogicalPlan {
     Insert {
         table: String,
         columns: Option<Vec<String>>,
-        values: Vec<Vec<Expr>>,
+        source: InsertSource,
         or_replace: bool,
     },
     /// Update rows in a table.
@@ -90,10 +120,36 @@ pub enum LogicalPlan {
     Empty,
 }
 
+/// How a [`LogicalPlan::SeqScan`] reads the rows of its table. A method
+/// other than `FullScan` may read rows the filter above the scan rejects,
+/// so the filter is applied to them all the same.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanMethod {
+    /// Read every row.
+    FullScan,
+    /// Seek the INTEGER PRIMARY KEY column `col` to the rows where
+    /// `col op ?`, `op` being `=`, `<`, `<=`, `>`, or `>=`.
+    PkSeek { col: String, op: BinaryOp },
+    /// Look up the row whose composite PRIMARY KEY columns `cols` are
+    /// each equal to a value.
+    CompositePkSeek { cols: Vec<String> },
+    /// Read the rows through the index `index_name`, whose leading key
+    /// columns `cols` are each equal to a value, except for the last one
+    /// if `lower` or `upper` is set: that one is bounded from below or
+    /// above instead.
+    IndexScan { index_name: String, cols: Vec<String>, lower: bool, upper: bool },
+    /// Read every row through the index `index_name`, whose keys are in
+    /// the order of the ORDER BY the scan is below.
+    IndexOrder { index_name: String },
+}
+
 /// Plan a parsed SQL statement into a logical plan.
 pub fn plan_statement(stmt: &Statement, catalog: &Catalog) -> Result<LogicalPlan> {
     match stmt {
-        Statement::Select(select) => plan_select(select, catalog),
+        Statement::Select(select) => {
+            let plan = fold_plan(plan_select(select, catalog)?, catalog);
+            Ok(plan_scan_methods(plan_merge_joins(plan, catalog), catalog))
+        }
         Statement::Insert(insert) => plan_insert(insert, catalog),
         Statement::Update(update) => plan_update(update, catalog),
         Statement::Delete(delete) => plan_delete(delete, catalog),
@@ -108,23 +164,30 @@ pub fn plan_statement(stmt: &Statement, catalog: &Catalog) -> Result<LogicalPlan
         Statement::Begin => Ok(LogicalPlan::Begin),
         Statement::Commit => Ok(LogicalPlan::Commit),
         Statement::Rollback => Ok(LogicalPlan::Rollback),
+        Statement::Savepoint(_) => Err(HorizonError::NotImplemented("SAVEPOINT".into())),
+        Statement::ReleaseSavepoint(_) => Err(HorizonError::NotImplemented("RELEASE".into())),
+        Statement::RollbackToSavepoint(_) => Err(HorizonError::NotImplemented("ROLLBACK TO".into())),
         Statement::CreateView(_) => Err(HorizonError::NotImplemented("CREATE VIEW".into())),
         Statement::DropView(_) => Err(HorizonError::NotImplemented("DROP VIEW".into())),
         Statement::CreateTrigger(_) => Err(HorizonError::NotImplemented("CREATE TRIGGER".into())),
         Statement::DropTrigger(_) => Err(HorizonError::NotImplemented("DROP TRIGGER".into())),
         Statement::AttachDatabase(_) => Err(HorizonError::NotImplemented("ATTACH DATABASE".into())),
         Statement::DetachDatabase(_) => Err(HorizonError::NotImplemented("DETACH DATABASE".into())),
-        Statement::Vacuum => Err(HorizonError::NotImplemented("VACUUM".into())),
+        Statement::Vacuum(_) => Err(HorizonError::NotImplemented("VACUUM".into())),
+        Statement::Analyze(_) => Err(HorizonError::NotImplemented("ANALYZE".into())),
         Statement::CreateVirtualTable(_) => Err(HorizonError::NotImplemented("CREATE VIRTUAL TABLE".into())),
     }
 }
 
-fn plan_select(select: &SelectStatement, _catalog: &Catalog) -> Result<LogicalPlan> {
+fn plan_select(select: &SelectStatement, catalog: &Catalog) -> Result<LogicalPlan> {
+    if let Some(rewritten) = rewrite_fulltext_matches(select, catalog) {
+        return plan_select(&rewritten, catalog);
+    }
     // Build the plan bottom-up: scan -> filter -> aggregate -> having -> project -> sort -> distinct -> limit
 
     // 1. FROM clause -> base scan or join
     let mut plan = if let Some(ref from) = select.from {
-        plan_from(from)?
+        plan_from(from, catalog)?
     } else {
         // SELECT without FROM (e.g., SELECT 1+1)
         LogicalPlan::Empty
@@ -143,6 +206,7 @@ fn plan_select(select: &SelectStatement, _catalog: &Catalog) -> Result<LogicalPl
         plan = LogicalPlan::Aggregate {
             input: Box::new(plan),
             group_by: select.group_by.clone(),
+            modifier: select.group_by_modifier,
             having: select.having.clone(),
         };
     }
@@ -177,28 +241,439 @@ fn plan_select(select: &SelectStatement, _catalog: &Catalog) -> Result<LogicalPl
         };
     }
 
-    Ok(plan)
+    Ok(push_down_predicates(plan))
+}
+
+/// `select` with each `MATCH` in its WHERE clause on a table with a
+/// full-text index turned into a search of the index's FTS5 shadow table,
+/// or `None` if there is no such `MATCH`.
+///
+/// `col MATCH 'q'`, where `col` is an indexed column, becomes
+/// `pk IN (SELECT rowid FROM t_fts WHERE col MATCH 'q')`, with `pk` the
+/// table's INTEGER PRIMARY KEY; `t MATCH 'q'` searches every indexed
+/// column. An unqualified column is looked up in the tables of the FROM
+/// clause that have a full-text index, and left alone if more than one
+/// of them indexes it.
+pub fn rewrite_fulltext_matches(select: &SelectStatement, catalog: &Catalog) -> Option<SelectStatement> {
+    fn from_tables(from: &FromClause, tables: &mut Vec<(String, String)>) {
+        match from {
+            FromClause::Table { name, alias } => {
+                tables.push((name.clone(), alias.clone().unwrap_or_else(|| name.clone())));
+            }
+            FromClause::Join { left, right, .. } => {
+                from_tables(left, tables);
+                from_tables(right, tables);
+            }
+            FromClause::Subquery { .. } | FromClause::TableFunction { .. } | FromClause::Values { .. } => {}
+        }
+    }
+
+    // The indexed tables of the FROM clause, by the name they are referred to
+    let mut tables = Vec::new();
+    from_tables(select.from.as_ref()?, &mut tables);
+    let indexed: Vec<(String, &FulltextIndexInfo)> = tables
+        .into_iter()
+        .filter_map(|(name, binding)| Some((binding, catalog.get_fulltext_index(&name)?)))
+        .collect();
+    if indexed.is_empty() {
+        return None;
+    }
+    let mut where_clause = select.where_clause.clone()?;
+    if !rewrite_fulltext_match(&mut where_clause, &indexed) {
+        return None;
+    }
+    Some(SelectStatement { where_clause: Some(where_clause), ..select.clone() })
+}
+
+/// Rewrite the full-text `MATCH`es in `expr` (see
+/// [`rewrite_fulltext_matches`]), returning whether there were any.
+fn rewrite_fulltext_match(expr: &mut Expr, indexed: &[(String, &FulltextIndexInfo)]) -> bool {
+    match expr {
+        Expr::Match { table, pattern } => {
+            let Expr::Column { table: qualifier, name } = table.as_ref() else {
+                return false;
+            };
+            let searches = |binding: &str, index: &FulltextIndexInfo| {
+                if qualifier.as_ref().is_some_and(|q| !q.eq_ignore_ascii_case(binding)) {
+                    return None;
+                }
+                if qualifier.is_none() && name.eq_ignore_ascii_case(binding) {
+                    // `t MATCH 'q'` searches the whole shadow table
+                    return Some(index.fts_table.clone());
+                }
+                index.columns.iter().find(|c| c.eq_ignore_ascii_case(name)).cloned()
+            };
+            let mut matches = indexed
+                .iter()
+                .filter_map(|(binding, index)| Some((binding, *index, searches(binding, index)?)));
+            let (Some((binding, index, searched)), None) = (matches.next(), matches.next()) else {
+                return false;
+            };
+            let search = SelectStatement {
+                ctes: vec![],
+                distinct: false,
+                columns: vec![SelectColumn::Expr {
+                    expr: Expr::Column { table: None, name: "rowid".into() },
+                    alias: None,
+                }],
+                from: Some(FromClause::Table { name: index.fts_table.clone(), alias: None }),
+                where_clause: Some(Expr::Match {
+                    table: Box::new(Expr::Column { table: None, name: searched }),
+                    pattern: pattern.clone(),
+                }),
+                group_by: vec![],
+                group_by_modifier: None,
+                having: None,
+                order_by: vec![],
+                limit: None,
+                offset: None,
+                compound: vec![],
+                windows: vec![],
+            };
+            *expr = Expr::InList {
+                expr: Box::new(Expr::Column { table: Some(binding.clone()), name: index.rowid_column.clone() }),
+                list: vec![Expr::Subquery(Box::new(search))],
+                negated: false,
+            };
+            true
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            let left = rewrite_fulltext_match(left, indexed);
+            rewrite_fulltext_match(right, indexed) || left
+        }
+        Expr::UnaryOp { expr: inner, .. } => rewrite_fulltext_match(inner, indexed),
+        _ => false,
+    }
+}
+
+/// Move the conjuncts of each filter in `plan` below the joins it sits
+/// on, so that rows are dropped before they are joined.
+///
+/// A conjunct goes to the side of a join whose tables it reads all its
+/// columns from, unless that side is NULL-padded by the join (the right
+/// side of a LEFT JOIN, say). Conjuncts are kept in place if they read
+/// columns of both sides, unqualified columns, or no columns at all, or
+/// if they contain a subquery or an aggregate.
+pub fn push_down_predicates(plan: LogicalPlan) -> LogicalPlan {
+    let push = |input: Box<LogicalPlan>| Box::new(push_down_predicates(*input));
+    match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            let mut conjuncts = Vec::new();
+            split_conjuncts(predicate, &mut conjuncts);
+            push_filter(*input, conjuncts)
+        }
+        LogicalPlan::Project { input, columns } => LogicalPlan::Project { input: push(input), columns },
+        LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort { input: push(input), order_by },
+        LogicalPlan::Limit { input, limit, offset } => LogicalPlan::Limit { input: push(input), limit, offset },
+        LogicalPlan::Aggregate { input, group_by, modifier, having } => {
+            LogicalPlan::Aggregate { input: push(input), group_by, modifier, having }
+        }
+        LogicalPlan::Distinct { input } => LogicalPlan::Distinct { input: push(input) },
+        LogicalPlan::Join { left, right, join_type, on, using } => {
+            LogicalPlan::Join { left: push(left), right: push(right), join_type, on, using }
+        }
+        LogicalPlan::MergeJoin { left, right, join_type, left_key, right_key } => {
+            LogicalPlan::MergeJoin { left: push(left), right: push(right), join_type, left_key, right_key }
+        }
+        other => other,
+    }
+}
+
+/// Filter `input` by all of `conjuncts`, pushing each one as far down as
+/// it can go.
+fn push_filter(input: LogicalPlan, mut conjuncts: Vec<Expr>) -> LogicalPlan {
+    match input {
+        LogicalPlan::Filter { input, predicate } => {
+            split_conjuncts(predicate, &mut conjuncts);
+            push_filter(*input, conjuncts)
+        }
+        LogicalPlan::Join { left, right, join_type, on, using } => {
+            let (to_left, to_right) = match join_type {
+                JoinType::Inner | JoinType::Cross => (true, true),
+                JoinType::Left => (true, false),
+                JoinType::Right => (false, true),
+                JoinType::Full => (false, false),
+            };
+            let (left_tables, right_tables) = (plan_tables(&left), plan_tables(&right));
+            let reads_only = |tables: &[String], from: &[String]| {
+                tables.iter().all(|t| from.iter().any(|f| f.eq_ignore_ascii_case(t)))
+            };
+            let (mut left_conjuncts, mut right_conjuncts, mut kept) = (Vec::new(), Vec::new(), Vec::new());
+            for conjunct in conjuncts {
+                match referenced_tables(&conjunct) {
+                    Some(tables) if to_left && reads_only(&tables, &left_tables) => left_conjuncts.push(conjunct),
+                    Some(tables) if to_right && reads_only(&tables, &right_tables) => right_conjuncts.push(conjunct),
+                    _ => kept.push(conjunct),
+                }
+            }
+            let join = LogicalPlan::Join {
+                left: Box::new(push_filter(*left, left_conjuncts)),
+                right: Box::new(push_filter(*right, right_conjuncts)),
+                join_type,
+                on,
+                using,
+            };
+            with_filter(join, kept)
+        }
+        other => with_filter(push_down_predicates(other), conjuncts),
+    }
+}
+
+/// `input` filtered by the conjunction of `conjuncts`, if there are any.
+fn with_filter(input: LogicalPlan, conjuncts: Vec<Expr>) -> LogicalPlan {
+    let predicate = conjuncts.into_iter().reduce(|acc, conjunct| Expr::BinaryOp {
+        left: Box::new(acc),
+        op: BinaryOp::And,
+        right: Box::new(conjunct),
+    });
+    match predicate {
+        Some(predicate) => LogicalPlan::Filter { input: Box::new(input), predicate },
+        None => input,
+    }
+}
+
+/// Split `expr` at its top-level ANDs.
+fn split_conjuncts(expr: Expr, conjuncts: &mut Vec<Expr>) {
+    match expr {
+        Expr::BinaryOp { left, op: BinaryOp::And, right } => {
+            split_conjuncts(*left, conjuncts);
+            split_conjuncts(*right, conjuncts);
+        }
+        other => conjuncts.push(other),
+    }
+}
+
+/// The names under which the tables scanned by `plan` are visible: their
+/// aliases, or their names if they have none.
+fn plan_tables(plan: &LogicalPlan) -> Vec<String> {
+    match plan {
+        LogicalPlan::SeqScan { table, alias, .. } => vec![alias.clone().unwrap_or_else(|| table.clone())],
+        LogicalPlan::Filter { input, .. } => plan_tables(input),
+        LogicalPlan::Join { left, right, .. } | LogicalPlan::MergeJoin { left, right, .. } => {
+            let mut tables = plan_tables(left);
+            tables.extend(plan_tables(right));
+            tables
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The tables whose columns `expr` reads, or `None` if it cannot be moved
+/// below a join: it reads no columns, an unqualified column, or contains a
+/// subquery or an aggregate.
+fn referenced_tables(expr: &Expr) -> Option<Vec<String>> {
+    let mut tables = Vec::new();
+    if collect_tables(expr, &mut tables) && !tables.is_empty() {
+        Some(tables)
+    } else {
+        None
+    }
+}
+
+/// Add the tables of the columns `expr` reads to `tables`, returning
+/// `false` if it cannot be moved (see [`referenced_tables`]).
+fn collect_tables(expr: &Expr, tables: &mut Vec<String>) -> bool {
+    match expr {
+        Expr::Column { table: Some(table), .. } => {
+            tables.push(table.clone());
+            true
+        }
+        Expr::Column { table: None, .. } => false,
+        Expr::Literal(_) | Expr::Placeholder(_) | Expr::NamedPlaceholder(_) => true,
+        Expr::BinaryOp { left, right, .. } => collect_tables(left, tables) && collect_tables(right, tables),
+        Expr::UnaryOp { expr, .. }
+        | Expr::IsNull { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::Collate { expr, .. } => collect_tables(expr, tables),
+        Expr::Between { expr, low, high, .. } => {
+            collect_tables(expr, tables) && collect_tables(low, tables) && collect_tables(high, tables)
+        }
+        Expr::InList { expr, list, .. } => {
+            collect_tables(expr, tables) && list.iter().all(|e| collect_tables(e, tables))
+        }
+        Expr::Like { expr, pattern, .. } => collect_tables(expr, tables) && collect_tables(pattern, tables),
+        Expr::Function { args, .. } => {
+            !expr_has_aggregate(expr) && args.iter().all(|e| collect_tables(e, tables))
+        }
+        Expr::Case { operand, when_clauses, else_clause } => {
+            operand.as_deref().map_or(true, |e| collect_tables(e, tables))
+                && when_clauses.iter().all(|(w, t)| collect_tables(w, tables) && collect_tables(t, tables))
+                && else_clause.as_deref().map_or(true, |e| collect_tables(e, tables))
+        }
+        Expr::Match { .. } | Expr::Subquery(_) | Expr::Exists(_) | Expr::WindowFunction { .. } => false,
+    }
+}
+
+/// Replace each join in `plan` of two scans, each of a table with an
+/// INTEGER PRIMARY KEY and possibly filtered, whose only condition is the
+/// equality of those keys, by a [`LogicalPlan::MergeJoin`].
+///
+/// A table is stored in the order of its INTEGER PRIMARY KEY, so such
+/// scans already produce their rows in join key order. Both columns of the
+/// condition must be qualified with the name they are visible under.
+pub fn plan_merge_joins(plan: LogicalPlan, catalog: &Catalog) -> LogicalPlan {
+    let merge = |input: Box<LogicalPlan>| Box::new(plan_merge_joins(*input, catalog));
+    match plan {
+        LogicalPlan::Join { left, right, join_type, on: Some(on), using }
+            if using.is_empty() && join_type != JoinType::Cross =>
+        {
+            match merge_join_keys(&on, &left, &right, catalog) {
+                Some((left_key, right_key)) => LogicalPlan::MergeJoin { left, right, join_type, left_key, right_key },
+                None => LogicalPlan::Join { left: merge(left), right: merge(right), join_type, on: Some(on), using },
+            }
+        }
+        LogicalPlan::Join { left, right, join_type, on, using } => {
+            LogicalPlan::Join { left: merge(left), right: merge(right), join_type, on, using }
+        }
+        LogicalPlan::Filter { input, predicate } => LogicalPlan::Filter { input: merge(input), predicate },
+        LogicalPlan::Project { input, columns } => LogicalPlan::Project { input: merge(input), columns },
+        LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort { input: merge(input), order_by },
+        LogicalPlan::Limit { input, limit, offset } => LogicalPlan::Limit { input: merge(input), limit, offset },
+        LogicalPlan::Aggregate { input, group_by, modifier, having } => {
+            LogicalPlan::Aggregate { input: merge(input), group_by, modifier, having }
+        }
+        LogicalPlan::Distinct { input } => LogicalPlan::Distinct { input: merge(input) },
+        other => other,
+    }
 }
 
-fn plan_from(from: &FromClause) -> Result<LogicalPlan> {
+/// The positions of the INTEGER PRIMARY KEY columns of `left` and `right`
+/// if `on` is the equality of those columns and both inputs are scans of
+/// their tables, possibly filtered.
+fn merge_join_keys(on: &Expr, left: &LogicalPlan, right: &LogicalPlan, catalog: &Catalog) -> Option<(usize, usize)> {
+    let (a, b) = match on {
+        Expr::BinaryOp { left: a, op: BinaryOp::Eq, right: b } => (a.as_ref(), b.as_ref()),
+        _ => return None,
+    };
+    let (left_name, left_key, left_pk) = integer_pk_scan(left, catalog)?;
+    let (right_name, right_key, right_pk) = integer_pk_scan(right, catalog)?;
+    let is_column = |expr: &Expr, table: &str, column: &str| {
+        matches!(expr, Expr::Column { table: Some(t), name }
+            if t.eq_ignore_ascii_case(table) && name.eq_ignore_ascii_case(column))
+    };
+    let forward = is_column(a, &left_name, left_pk) && is_column(b, &right_name, right_pk);
+    let reversed = is_column(b, &left_name, left_pk) && is_column(a, &right_name, right_pk);
+    (forward || reversed).then_some((left_key, right_key))
+}
+
+/// For a scan, possibly filtered, of a table with an INTEGER PRIMARY KEY:
+/// the name its columns are visible under, and the position and name of
+/// its key column.
+fn integer_pk_scan<'a>(plan: &LogicalPlan, catalog: &'a Catalog) -> Option<(String, usize, &'a str)> {
+    match plan {
+        LogicalPlan::SeqScan { table, alias, .. } => {
+            let info = catalog.get_table(table).ok()?;
+            let pk = info.pk_column?;
+            let column = &info.columns[pk];
+            (column.affinity == crate::types::DataType::Integer)
+                .then(|| (alias.clone().unwrap_or_else(|| table.clone()), pk, column.name.as_str()))
+        }
+        LogicalPlan::Filter { input, .. } => integer_pk_scan(input, catalog),
+        _ => None,
+    }
+}
+
+/// Set the [`ScanMethod`] of each filtered scan in `plan` that is not
+/// below a join to the cheapest way of reading the rows its filter keeps.
+///
+/// The scans of a join read their whole tables: a merge join needs the
+/// rows of each side in key order, and a hash join reads them all anyway.
+pub fn plan_scan_methods(plan: LogicalPlan, catalog: &Catalog) -> LogicalPlan {
+    let annotate = |input: Box<LogicalPlan>| Box::new(plan_scan_methods(*input, catalog));
+    match plan {
+        LogicalPlan::Filter { input, predicate } => match *input {
+            LogicalPlan::SeqScan { table, alias, .. } => {
+                let method = match catalog.get_table(&table) {
+                    Ok(info) => crate::execution::scan_method(&predicate, info, catalog),
+                    Err(_) => ScanMethod::FullScan,
+                };
+                let scan = LogicalPlan::SeqScan { table, alias, method };
+                LogicalPlan::Filter { input: Box::new(scan), predicate }
+            }
+            input => LogicalPlan::Filter { input: annotate(Box::new(input)), predicate },
+        },
+        LogicalPlan::Project { input, columns } => LogicalPlan::Project { input: annotate(input), columns },
+        LogicalPlan::Sort { input, order_by } => match index_ordered(&input, &order_by, catalog) {
+            Some(input) => input,
+            None => LogicalPlan::Sort { input: annotate(input), order_by },
+        },
+        LogicalPlan::Limit { input, limit, offset } => LogicalPlan::Limit { input: annotate(input), limit, offset },
+        LogicalPlan::Aggregate { input, group_by, modifier, having } => {
+            LogicalPlan::Aggregate { input: annotate(input), group_by, modifier, having }
+        }
+        LogicalPlan::Distinct { input } => LogicalPlan::Distinct { input: annotate(input) },
+        other => other,
+    }
+}
+
+/// `input`, the input of a sort by `order_by`, reading its table through
+/// an index whose keys are in that order, so that it needs no sorting; or
+/// `None` if it does not project the rows of a single table, possibly
+/// filtered, or no index has that order.
+fn index_ordered(input: &LogicalPlan, order_by: &[OrderByItem], catalog: &Catalog) -> Option<LogicalPlan> {
+    let scan = |table: &String, alias: &Option<String>, predicate: Option<&Expr>| {
+        let info = catalog.get_table(table).ok()?;
+        let visible = alias.as_deref().unwrap_or(table);
+        let index = crate::execution::order_by_index(predicate, order_by, visible, info, catalog)?;
+        let method = ScanMethod::IndexOrder { index_name: index.name.clone() };
+        Some(LogicalPlan::SeqScan { table: table.clone(), alias: alias.clone(), method })
+    };
+    match input {
+        LogicalPlan::Project { input, columns } => {
+            let input = Box::new(index_ordered(input, order_by, catalog)?);
+            Some(LogicalPlan::Project { input, columns: columns.clone() })
+        }
+        LogicalPlan::Distinct { input } => {
+            Some(LogicalPlan::Distinct { input: Box::new(index_ordered(input, order_by, catalog)?) })
+        }
+        LogicalPlan::Filter { input, predicate } => match input.as_ref() {
+            LogicalPlan::SeqScan { table, alias, .. } => {
+                let input = Box::new(scan(table, alias, Some(predicate))?);
+                Some(LogicalPlan::Filter { input, predicate: predicate.clone() })
+            }
+            _ => None,
+        },
+        LogicalPlan::SeqScan { table, alias, .. } => scan(table, alias, None),
+        _ => None,
+    }
+}
+
+/// The plan producing the rows of the FROM clause `from`, with column names
+/// qualified by table name or alias.
+pub fn plan_from(from: &FromClause, catalog: &Catalog) -> Result<LogicalPlan> {
     match from {
         FromClause::Table { name, alias } => Ok(LogicalPlan::SeqScan {
             table: name.clone(),
             alias: alias.clone(),
+            method: ScanMethod::FullScan,
         }),
         FromClause::Join {
             left,
             join_type,
             right,
-            on,
+            constraint,
         } => {
-            let left_plan = plan_from(left)?;
-            let right_plan = plan_from(right)?;
+            let (on, using) = match constraint {
+                None => (None, Vec::new()),
+                Some(JoinSpec::On(expr)) => (Some(expr.clone()), Vec::new()),
+                Some(JoinSpec::Using(cols)) => (None, cols.clone()),
+                Some(JoinSpec::Natural) => {
+                    let right_cols = from_column_names(right, catalog)?;
+                    let shared = from_column_names(left, catalog)?
+                        .into_iter()
+                        .filter(|l| right_cols.iter().any(|r| r.eq_ignore_ascii_case(l)))
+                        .collect();
+                    (None, shared)
+                }
+            };
+            let left_plan = plan_from(left, catalog)?;
+            let right_plan = plan_from(right, catalog)?;
             Ok(LogicalPlan::Join {
                 left: Box::new(left_plan),
                 right: Box::new(right_plan),
                 join_type: join_type.clone(),
-                on: on.clone(),
+                on,
+                using,
             })
         }
         FromClause::Subquery { query: _, alias: _ } => {
@@ -208,6 +683,32 @@ fn plan_from(from: &FromClause) -> Result<LogicalPlan> {
         FromClause::TableFunction { .. } => {
             Err(HorizonError::NotImplemented("table function in FROM clause".into()))
         }
+        FromClause::Values { .. } => {
+            Err(HorizonError::NotImplemented("VALUES in FROM clause".into()))
+        }
+    }
+}
+
+/// Unqualified column names produced by a FROM item, used to resolve
+/// `NATURAL` joins.
+fn from_column_names(from: &FromClause, catalog: &Catalog) -> Result<Vec<String>> {
+    match from {
+        FromClause::Table { name, .. } => {
+            let table = catalog.get_table(name)?;
+            Ok(table.columns.iter().map(|c| c.name.clone()).collect())
+        }
+        FromClause::Join { left, right, .. } => {
+            let mut names = from_column_names(left, catalog)?;
+            for name in from_column_names(right, catalog)? {
+                if !names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+                    names.push(name);
+                }
+            }
+            Ok(names)
+        }
+        FromClause::Subquery { .. } | FromClause::TableFunction { .. } | FromClause::Values { .. } => Err(
+            HorizonError::NotImplemented("NATURAL JOIN on a subquery or table function".into()),
+        ),
     }
 }
 
@@ -215,7 +716,7 @@ fn plan_insert(insert: &InsertStatement, _catalog: &Catalog) -> Result<LogicalPl
     Ok(LogicalPlan::Insert {
         table: insert.table.clone(),
         columns: insert.columns.clone(),
-        values: insert.values.clone(),
+        source: insert.source.clone(),
         or_replace: insert.or_replace,
     })
 }
@@ -253,7 +754,8 @@ fn expr_has_aggregate(expr: &Expr) -> bool {
             let upper = name.to_uppercase();
             matches!(
                 upper.as_str(),
-                "COUNT" | "SUM" | "AVG" | "MIN" | "MAX" | "GROUP_CONCAT" | "TOTAL"
+                "COUNT" | "SUM" | "AVG" | "MIN" | "MAX" | "GROUP_CONCAT" | "JSON_AGG" | "TOTAL"
+                    | "STDDEV" | "STDDEV_POP" | "STDDEV_SAMP" | "VARIANCE" | "VAR_POP" | "VAR_SAMP"
             )
         }
         Expr::BinaryOp { left, right, .. } => {
@@ -303,6 +805,56 @@ mod tests {
         }
     }
 
+    /// The predicates of the filters directly above each side of the
+    /// join under the projection of `plan`, and of a filter above it.
+    fn join_filters(plan: LogicalPlan) -> (Option<Expr>, Option<Expr>, Option<Expr>) {
+        let filter_of = |plan: &LogicalPlan| match plan {
+            LogicalPlan::Filter { predicate, .. } => Some(predicate.clone()),
+            _ => None,
+        };
+        let mut node = match plan {
+            LogicalPlan::Project { input, .. } => *input,
+            other => panic!("expected Project, got {:?}", other),
+        };
+        let above = filter_of(&node);
+        if let LogicalPlan::Filter { input, .. } = node {
+            node = *input;
+        }
+        match node {
+            LogicalPlan::Join { left, right, .. } => (filter_of(&left), filter_of(&right), above),
+            other => panic!("expected Join, got {:?}", other),
+        }
+    }
+
+    fn expr(sql: &str) -> Expr {
+        let select = match Parser::parse(&format!("SELECT {}", sql)).unwrap().remove(0) {
+            Statement::Select(select) => select,
+            other => panic!("expected SELECT, got {:?}", other),
+        };
+        match &select.columns[0] {
+            SelectColumn::Expr { expr, .. } => expr.clone(),
+            other => panic!("expected an expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn where_conjuncts_are_pushed_below_an_inner_join() {
+        let p = plan("SELECT * FROM t1 JOIN t2 ON t1.id = t2.id WHERE t1.x > 0 AND t2.y < 10 AND t1.x < t2.y");
+        assert_eq!(
+            join_filters(p),
+            (Some(expr("t1.x > 0")), Some(expr("t2.y < 10")), Some(expr("t1.x < t2.y")))
+        );
+    }
+
+    #[test]
+    fn where_conjuncts_stay_above_the_padded_side_of_an_outer_join() {
+        let p = plan("SELECT * FROM t1 LEFT JOIN t2 ON t1.id = t2.id WHERE t1.x > 0 AND t2.y IS NULL");
+        assert_eq!(join_filters(p), (Some(expr("t1.x > 0")), None, Some(expr("t2.y IS NULL"))));
+
+        let p = plan("SELECT * FROM t1 AS a JOIN t2 ON a.id = t2.id WHERE x > 0 AND a.x IN (SELECT 1)");
+        assert_eq!(join_filters(p), (None, None, Some(expr("x > 0 AND a.x IN (SELECT 1)"))));
+    }
+
     #[test]
     fn test_plan_create_table() {
         let p = plan("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)");
@@ -0,0 +1,103 @@
+//! Prepared statements.
+//!
+//! [`Database::prepare`](crate::Database::prepare) parses a SQL statement
+//! once into a [`PreparedStatement`], which can then be executed any number
+//! of times with different bind parameters without being parsed again.
+//!
+//! A prepared statement records the schema generation of the database
+//! (see [`Catalog::schema_generation`](crate::catalog::Catalog::schema_generation))
+//! when it was parsed. Once a CREATE, DROP, or ALTER statement changes the
+//! schema, the statement has expired, and its next execution prepares it
+//! again before running it.
+
+use std::sync::Mutex;
+
+use crate::error::{HorizonError, Result};
+use crate::sql::ast::Statement;
+use crate::types::Value;
+use crate::{execution, sql, Database, QueryResult};
+
+/// A SQL statement parsed once and executed many times.
+///
+/// Created by [`Database::prepare`](crate::Database::prepare):
+///
+/// ```no_run
+/// # use horizon::Value;
+/// # let db = horizon::Database::open(":memory:")?;
+/// let insert = db.prepare("INSERT INTO users (id, name) VALUES (?, ?)")?;
+/// for (id, name) in [(1, "alice"), (2, "bob")] {
+///     insert.execute(&db, &[Value::Integer(id), Value::Text(name.into())])?;
+/// }
+/// # Ok::<(), horizon::HorizonError>(())
+/// ```
+#[derive(Debug)]
+pub struct PreparedStatement {
+    sql: String,
+    /// The parsed statements, replaced when they expire. Executions share
+    /// the statement, so it has a lock of its own.
+    parsed: Mutex<Parsed>,
+}
+
+#[derive(Debug)]
+struct Parsed {
+    stmts: Vec<Statement>,
+    /// The schema generation of the database when `stmts` were parsed.
+    schema_generation: u64,
+}
+
+impl PreparedStatement {
+    /// Parse `sql` for a database whose schema is at `schema_generation`.
+    pub(crate) fn new(sql: &str, schema_generation: u64) -> Result<Self> {
+        let stmts = sql::parser::Parser::parse(sql)?;
+        Ok(PreparedStatement {
+            sql: sql.to_string(),
+            parsed: Mutex::new(Parsed { stmts, schema_generation }),
+        })
+    }
+
+    /// The SQL text the statement was prepared from.
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// Whether the schema of `db` has changed since the statement was
+    /// prepared, so that its next execution prepares it again.
+    pub fn is_expired(&self, db: &Database) -> Result<bool> {
+        Ok(self.lock()?.schema_generation != db.schema_generation()?)
+    }
+
+    /// Execute the statement with positional bind parameters, returning the
+    /// number of rows changed. See [`Database::execute_params`](crate::Database::execute_params).
+    pub fn execute(&self, db: &Database, params: &[Value]) -> Result<usize> {
+        db.with_hooks(&self.sql, |changed| *changed, || {
+            let mut stmts = self.statements(db)?;
+            execution::params::bind_positional(&mut stmts, params)?;
+            db.execute_statements(stmts)
+        })
+    }
+
+    /// Run the statement as a query with positional bind parameters. See
+    /// [`Database::query_params`](crate::Database::query_params).
+    pub fn query(&self, db: &Database, params: &[Value]) -> Result<QueryResult> {
+        db.with_hooks(&self.sql, QueryResult::len, || {
+            let mut stmts = self.statements(db)?;
+            execution::params::bind_positional(&mut stmts, params)?;
+            db.query_statements(stmts)
+        })
+    }
+
+    /// A copy of the parsed statements to bind and run against `db`,
+    /// parsing them again first if they have expired.
+    fn statements(&self, db: &Database) -> Result<Vec<Statement>> {
+        let schema_generation = db.schema_generation()?;
+        let mut parsed = self.lock()?;
+        if parsed.schema_generation != schema_generation {
+            *parsed = Parsed { stmts: sql::parser::Parser::parse(&self.sql)?, schema_generation };
+        }
+        Ok(parsed.stmts.clone())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Parsed>> {
+        self.parsed.lock().map_err(|_| HorizonError::Internal("lock poisoned".into()))
+    }
+}
@@ -0,0 +1,290 @@
+//! Serde support for query results.
+//!
+//! Available with the `serde` Cargo feature. [`Value`], [`Row`], and
+//! [`QueryResult`] implement [`Serialize`]: a row serializes as a map from
+//! column name to value, and a result as a sequence of rows.
+//!
+//! [`from_row`] goes the other way and deserializes a row into any
+//! `Deserialize` type. Struct fields are matched to columns by name,
+//! ignoring ASCII case; tuples and sequences take the values in column order.
+//!
+//! ```ignore
+//! #[derive(serde::Deserialize)]
+//! struct User {
+//!     id: i64,
+//!     name: String,
+//!     email: Option<String>,
+//! }
+//!
+//! let users: Vec<User> = db
+//!     .query("SELECT id, name, email FROM users")?
+//!     .rows
+//!     .iter()
+//!     .map(horizon::serde_horizon::from_row)
+//!     .collect::<horizon::Result<_>>()?;
+//! ```
+
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{HorizonError, Result};
+use crate::types::Value;
+use crate::{QueryResult, Row};
+
+/// Deserialize a row into `T`.
+pub fn from_row<'de, T: Deserialize<'de>>(row: &'de Row) -> Result<T> {
+    T::deserialize(RowDeserializer { row })
+}
+
+impl de::Error for HorizonError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        HorizonError::Deserialization(msg.to_string())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Serialize
+// ---------------------------------------------------------------------------
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_none(),
+            Value::Integer(n) => serializer.serialize_i64(*n),
+            Value::Real(f) => serializer.serialize_f64(*f),
+            Value::Text(s) => serializer.serialize_str(s),
+            Value::Blob(b) => serializer.serialize_bytes(b),
+        }
+    }
+}
+
+impl Serialize for Row {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (name, value) in self.columns.iter().zip(&self.values) {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for QueryResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+        for row in &self.rows {
+            seq.serialize_element(row)?;
+        }
+        seq.end()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Deserialize
+// ---------------------------------------------------------------------------
+
+/// Deserializes a whole row as a map, struct, or sequence.
+struct RowDeserializer<'de> {
+    row: &'de Row,
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer<'de> {
+    type Error = HorizonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let entries = self.row.columns.iter().map(String::as_str).zip(&self.row.values).collect();
+        visitor.visit_map(RowMap { entries, next: 0 })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        // Report each column under the field name it matches, so that a
+        // column `ID` fills the field `id`. Unmatched columns are skipped.
+        let entries = fields
+            .iter()
+            .filter_map(|&field| {
+                let idx = self.row.columns.iter().position(|c| c.eq_ignore_ascii_case(field))?;
+                Some((field, &self.row.values[idx]))
+            })
+            .collect();
+        visitor.visit_map(RowMap { entries, next: 0 })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(RowSeq { values: self.row.values.iter() })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct map enum
+        identifier ignored_any
+    }
+}
+
+struct RowMap<'de> {
+    entries: Vec<(&'de str, &'de Value)>,
+    next: usize,
+}
+
+impl<'de> MapAccess<'de> for RowMap<'de> {
+    type Error = HorizonError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.entries.get(self.next) {
+            Some((name, _)) => seed.deserialize(name.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let (_, value) = self.entries[self.next];
+        self.next += 1;
+        seed.deserialize(ValueDeserializer { value })
+    }
+}
+
+struct RowSeq<'de> {
+    values: std::slice::Iter<'de, Value>,
+}
+
+impl<'de> SeqAccess<'de> for RowSeq<'de> {
+    type Error = HorizonError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.values.next() {
+            Some(value) => seed.deserialize(ValueDeserializer { value }).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Deserializes a single column value.
+struct ValueDeserializer<'de> {
+    value: &'de Value,
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = HorizonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Null => visitor.visit_unit(),
+            Value::Integer(n) => visitor.visit_i64(*n),
+            Value::Real(f) => visitor.visit_f64(*f),
+            Value::Text(s) => visitor.visit_borrowed_str(s),
+            Value::Blob(b) => visitor.visit_borrowed_bytes(b),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Integer(n) => visitor.visit_bool(*n != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn row(columns: &[&str], values: Vec<Value>) -> Row {
+        Row {
+            columns: Arc::new(columns.iter().map(|c| c.to_string()).collect()),
+            values,
+        }
+    }
+
+    #[test]
+    fn struct_fields_match_columns_case_insensitively() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct User<'a> {
+            id: i32,
+            name: &'a str,
+            active: bool,
+            score: f64,
+            email: Option<String>,
+        }
+
+        let r = row(
+            &["ID", "Name", "active", "score", "email", "extra"],
+            vec![
+                Value::Integer(7),
+                Value::Text("ada".into()),
+                Value::Integer(1),
+                Value::Integer(3),
+                Value::Null,
+                Value::Blob(vec![1]),
+            ],
+        );
+        let user: User = from_row(&r).unwrap();
+        assert_eq!(
+            user,
+            User { id: 7, name: "ada", active: true, score: 3.0, email: None }
+        );
+    }
+
+    #[test]
+    fn tuples_take_values_in_order() {
+        let r = row(&["a", "b"], vec![Value::Integer(1), Value::Text("x".into())]);
+        let (a, b): (i64, String) = from_row(&r).unwrap();
+        assert_eq!((a, b.as_str()), (1, "x"));
+    }
+
+    #[test]
+    fn mismatched_types_are_errors() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Small {
+            n: u8,
+        }
+
+        let r = row(&["n"], vec![Value::Integer(300)]);
+        assert!(matches!(from_row::<Small>(&r), Err(HorizonError::Deserialization(_))));
+        let r = row(&["n"], vec![Value::Text("x".into())]);
+        assert!(matches!(from_row::<Small>(&r), Err(HorizonError::Deserialization(_))));
+        let r = row(&["m"], vec![Value::Integer(1)]);
+        assert!(from_row::<Small>(&r).is_err());
+    }
+}
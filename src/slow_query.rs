@@ -0,0 +1,103 @@
+//! The slow query log.
+//!
+//! Once [`Database::set_slow_query_threshold`](crate::Database::set_slow_query_threshold)
+//! has been called, every statement run through
+//! [`Database::execute`](crate::Database::execute),
+//! [`Database::query`](crate::Database::query), their variants, or a
+//! [`PreparedStatement`](crate::PreparedStatement) that succeeds but takes
+//! longer than the threshold is recorded in a [`SlowQueryLog`]. The log
+//! keeps the last [`SLOW_QUERY_LOG_CAPACITY`] entries, dropping the oldest
+//! when it is full, and is read with
+//! [`Database::slow_query_log`](crate::Database::slow_query_log).
+//!
+//! A statement's time includes parsing it and waiting for the database
+//! lock, but not the execute hooks.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+/// The number of entries a [`SlowQueryLog`] keeps.
+pub const SLOW_QUERY_LOG_CAPACITY: usize = 128;
+
+/// A statement recorded in the slow query log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowQueryEntry {
+    /// The SQL text of the statement.
+    pub sql: String,
+    /// How long the statement took to run.
+    pub duration: Duration,
+    /// The number of rows the statement changed, or returned for a query.
+    pub rows: usize,
+    /// When the statement finished.
+    pub timestamp: SystemTime,
+}
+
+/// The most recent statements that took longer than a threshold, oldest
+/// first.
+#[derive(Debug, Clone)]
+pub struct SlowQueryLog {
+    threshold: Duration,
+    entries: VecDeque<SlowQueryEntry>,
+}
+
+impl SlowQueryLog {
+    /// An empty log of the statements taking longer than `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        SlowQueryLog { threshold, entries: VecDeque::with_capacity(SLOW_QUERY_LOG_CAPACITY) }
+    }
+
+    /// The time a statement must take to be recorded.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Change the threshold; the entries recorded so far are kept.
+    pub fn set_threshold(&mut self, threshold: Duration) {
+        self.threshold = threshold;
+    }
+
+    /// Record the statement `sql` if `duration` exceeds the threshold,
+    /// dropping the oldest entry if the log is full.
+    pub fn record(&mut self, sql: &str, duration: Duration, rows: usize) {
+        if duration <= self.threshold {
+            return;
+        }
+        if self.entries.len() == SLOW_QUERY_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(SlowQueryEntry {
+            sql: sql.to_string(),
+            duration,
+            rows,
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    /// The entries in the log, oldest first.
+    pub fn entries(&self) -> Vec<SlowQueryEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_entries_over_the_threshold() {
+        let mut log = SlowQueryLog::new(Duration::from_millis(10));
+        log.record("fast", Duration::from_millis(10), 1);
+        log.record("slow", Duration::from_millis(11), 2);
+        let entries = log.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].sql.as_str(), entries[0].rows), ("slow", 2));
+
+        for i in 0..SLOW_QUERY_LOG_CAPACITY {
+            log.record(&i.to_string(), Duration::from_secs(1), i);
+        }
+        let entries = log.entries();
+        assert_eq!(entries.len(), SLOW_QUERY_LOG_CAPACITY);
+        assert_eq!(entries[0].sql, "0");
+        assert_eq!(entries[SLOW_QUERY_LOG_CAPACITY - 1].sql, (SLOW_QUERY_LOG_CAPACITY - 1).to_string());
+    }
+}
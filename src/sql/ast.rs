@@ -26,9 +26,18 @@ pub enum Statement {
     Begin,
     Commit,
     Rollback,
+    /// `SAVEPOINT name`
+    Savepoint(String),
+    /// `RELEASE [SAVEPOINT] name`
+    ReleaseSavepoint(String),
+    /// `ROLLBACK [TRANSACTION] TO [SAVEPOINT] name`
+    RollbackToSavepoint(String),
     AttachDatabase(AttachDatabaseStatement),
     DetachDatabase(DetachDatabaseStatement),
-    Vacuum,
+    /// `VACUUM [INTO 'path']`
+    Vacuum(Option<String>),
+    /// `ANALYZE [table-or-index]`
+    Analyze(Option<String>),
     CreateVirtualTable(CreateVirtualTableStatement),
 }
 
@@ -41,12 +50,16 @@ pub struct SelectStatement {
     pub from: Option<FromClause>,
     pub where_clause: Option<Expr>,
     pub group_by: Vec<Expr>,
+    /// How `group_by` is grouped by, if not simply as a whole.
+    pub group_by_modifier: Option<GroupByModifier>,
     pub having: Option<Expr>,
     pub order_by: Vec<OrderByItem>,
     pub limit: Option<Expr>,
     pub offset: Option<Expr>,
     /// UNION / INTERSECT / EXCEPT chain (applied after this select body).
     pub compound: Vec<CompoundOp>,
+    /// Named windows from the `WINDOW` clause.
+    pub windows: Vec<NamedWindow>,
 }
 
 /// A Common Table Expression: `name [(col1, col2)] AS (select)`.
@@ -56,6 +69,29 @@ pub struct Cte {
     pub columns: Option<Vec<String>>,
     pub query: SelectStatement,
     pub recursive: bool,
+    /// `AS MATERIALIZED` (`Some(true)`) or `AS NOT MATERIALIZED`
+    /// (`Some(false)`), if given.
+    pub materialized: Option<bool>,
+    /// The `CYCLE` clause of a recursive CTE.
+    pub cycle: Option<CycleClause>,
+}
+
+/// `CYCLE col, ... SET cycle_col [TO mark DEFAULT default] USING path_col`:
+/// cycle detection for a recursive CTE.
+///
+/// Each row gets two more columns: `path_col` lists the values of the
+/// `CYCLE` columns of the rows it was reached through, and `cycle_col` is
+/// `mark` if its own values are already on that path (and `default`
+/// otherwise). Rows on a cycle are returned but not recursed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleClause {
+    pub columns: Vec<String>,
+    pub set_column: String,
+    /// `TRUE` unless given.
+    pub mark: Expr,
+    /// `FALSE` unless given.
+    pub default: Expr,
+    pub path_column: String,
 }
 
 /// A compound SELECT operator with its second operand.
@@ -82,9 +118,18 @@ pub struct SelectBody {
     pub from: Option<FromClause>,
     pub where_clause: Option<Expr>,
     pub group_by: Vec<Expr>,
+    pub group_by_modifier: Option<GroupByModifier>,
     pub having: Option<Expr>,
 }
 
+/// A modifier of a `GROUP BY` list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupByModifier {
+    /// `GROUP BY ROLLUP(a, b, ...)`: group by the whole list, then by each
+    /// shorter prefix of it, down to a grand total over all rows.
+    Rollup,
+}
+
 /// A single item in the SELECT column list.
 #[derive(Debug, Clone, PartialEq)]
 pub enum SelectColumn {
@@ -107,7 +152,8 @@ pub enum FromClause {
         left: Box<FromClause>,
         join_type: JoinType,
         right: Box<FromClause>,
-        on: Option<Expr>,
+        /// The join constraint (`ON`, `USING`, or `NATURAL`), if any.
+        constraint: Option<JoinSpec>,
     },
     Subquery {
         query: Box<SelectStatement>,
@@ -119,6 +165,13 @@ pub enum FromClause {
         args: Vec<Expr>,
         alias: Option<String>,
     },
+    /// An inline table: `(VALUES (1, 'a'), (2, 'b')) AS t(id, name)`.
+    /// Without a column list the columns are named `column1`, `column2`, ...
+    Values {
+        rows: Vec<Vec<Expr>>,
+        alias: Option<String>,
+        columns: Option<Vec<String>>,
+    },
 }
 
 /// The flavour of a `JOIN`.
@@ -127,9 +180,22 @@ pub enum JoinType {
     Inner,
     Left,
     Right,
+    Full,
     Cross,
 }
 
+/// How the rows on the two sides of a `JOIN` are matched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinSpec {
+    /// `NATURAL JOIN`: equality on every column name the two sides share.
+    Natural,
+    /// `USING (col, ...)`: equality on the listed columns, each of which
+    /// appears only once in the join output.
+    Using(Vec<String>),
+    /// `ON expr`.
+    On(Expr),
+}
+
 /// A single item in an `ORDER BY` clause.
 #[derive(Debug, Clone, PartialEq)]
 pub struct OrderByItem {
@@ -142,18 +208,30 @@ pub struct OrderByItem {
 pub struct InsertStatement {
     pub table: String,
     pub columns: Option<Vec<String>>,
-    /// Multiple value rows: `VALUES (a, b), (c, d)`.
-    pub values: Vec<Vec<Expr>>,
+    pub source: InsertSource,
     pub or_replace: bool,
     /// Optional `RETURNING` clause.
     pub returning: Option<Vec<SelectColumn>>,
 }
 
+/// Where an `INSERT` gets its rows from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    /// Multiple value rows: `VALUES (a, b), (c, d)`.
+    Values(Vec<Vec<Expr>>),
+    /// The rows produced by a query: `INSERT INTO t SELECT ...`.
+    Select(Box<SelectStatement>),
+}
+
 /// An `UPDATE` statement.
 #[derive(Debug, Clone, PartialEq)]
 pub struct UpdateStatement {
     pub table: String,
     pub assignments: Vec<(String, Expr)>,
+    /// Optional `FROM` clause of an `UPDATE ... FROM`, whose tables the
+    /// WHERE clause and assignments can refer to alongside the table
+    /// being updated.
+    pub from: Option<FromClause>,
     pub where_clause: Option<Expr>,
     /// Optional `RETURNING` clause.
     pub returning: Option<Vec<SelectColumn>>,
@@ -174,6 +252,75 @@ pub struct CreateTableStatement {
     pub name: String,
     pub if_not_exists: bool,
     pub columns: Vec<ColumnDef>,
+    /// Table-level constraints following the column definitions.
+    pub constraints: Vec<TableConstraint>,
+}
+
+/// A table-level constraint inside `CREATE TABLE`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    /// `PRIMARY KEY (col, ...)`
+    PrimaryKey(Vec<String>),
+    /// `CHECK (expr)`; may reference any column of the table.
+    Check(Expr),
+    /// `FOREIGN KEY (col, ...) REFERENCES parent [(col, ...)] ...`
+    ForeignKey {
+        columns: Vec<String>,
+        references: ForeignKeyClause,
+    },
+}
+
+/// The `REFERENCES parent [(col, ...)] [ON DELETE ...] [ON UPDATE ...]`
+/// part of a foreign key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyClause {
+    pub table: String,
+    /// Referenced parent columns; empty means the parent's primary key.
+    pub columns: Vec<String>,
+    pub on_delete: FkAction,
+    pub on_update: FkAction,
+}
+
+/// What happens to child rows when the parent key they reference is
+/// deleted or changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FkAction {
+    /// Reject the change while child rows still reference the parent.
+    #[default]
+    NoAction,
+    /// Same as [`FkAction::NoAction`].
+    Restrict,
+    /// Delete (or re-key) the child rows along with the parent.
+    Cascade,
+    /// Set the child's foreign key columns to NULL.
+    SetNull,
+    /// Set the child's foreign key columns to their default values.
+    SetDefault,
+}
+
+impl FkAction {
+    /// The SQL spelling of this action.
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            FkAction::NoAction => "NO ACTION",
+            FkAction::Restrict => "RESTRICT",
+            FkAction::Cascade => "CASCADE",
+            FkAction::SetNull => "SET NULL",
+            FkAction::SetDefault => "SET DEFAULT",
+        }
+    }
+
+    /// Parse the SQL spelling produced by [`FkAction::as_sql`].
+    pub fn from_sql(s: &str) -> Option<FkAction> {
+        match s.to_ascii_uppercase().as_str() {
+            "NO ACTION" => Some(FkAction::NoAction),
+            "RESTRICT" => Some(FkAction::Restrict),
+            "CASCADE" => Some(FkAction::Cascade),
+            "SET NULL" => Some(FkAction::SetNull),
+            "SET DEFAULT" => Some(FkAction::SetDefault),
+            _ => None,
+        }
+    }
 }
 
 /// A column definition inside `CREATE TABLE`.
@@ -190,6 +337,11 @@ pub struct ColumnDef {
     /// Generated column: `GENERATED ALWAYS AS (expr) STORED|VIRTUAL`
     /// or shorthand `AS (expr) STORED|VIRTUAL`.
     pub generated: Option<GeneratedColumn>,
+    /// `CHECK (expr)` column constraint. Several CHECKs on one column are
+    /// combined with `AND`.
+    pub check: Option<Expr>,
+    /// Column-level `REFERENCES` clause.
+    pub references: Option<ForeignKeyClause>,
 }
 
 /// Metadata for a generated column definition.
@@ -217,6 +369,8 @@ pub struct CreateIndexStatement {
     pub columns: Vec<OrderByItem>,
     pub unique: bool,
     pub if_not_exists: bool,
+    /// `WHERE` predicate of a partial index.
+    pub where_clause: Option<Expr>,
 }
 
 /// A `DROP INDEX` statement.
@@ -235,6 +389,7 @@ pub struct AlterTableStatement {
 
 /// The specific alteration to apply to a table.
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum AlterTableAction {
     AddColumn(ColumnDef),
     RenameTable(String),
@@ -320,6 +475,8 @@ pub struct CreateVirtualTableStatement {
     pub name: String,
     pub if_not_exists: bool,
     pub module_name: String,
+    /// The module arguments: column names, and options `name = value`
+    /// given as `name=value`.
     pub module_args: Vec<String>,
 }
 
@@ -373,6 +530,8 @@ pub enum Expr {
         name: String,
         args: Vec<Expr>,
         distinct: bool,
+        /// The `FILTER (WHERE ...)` condition of an aggregate call.
+        filter: Option<Box<Expr>>,
     },
     Cast {
         expr: Box<Expr>,
@@ -386,6 +545,8 @@ pub enum Expr {
     Subquery(Box<SelectStatement>),
     Exists(Box<SelectStatement>),
     Placeholder(usize),
+    /// A named bind parameter: `$name`, `:name`, or `@name` (prefix included).
+    NamedPlaceholder(String),
     /// A `COLLATE` expression: `expr COLLATE collation_name`.
     Collate {
         expr: Box<Expr>,
@@ -397,9 +558,28 @@ pub enum Expr {
         partition_by: Vec<Expr>,
         order_by: Vec<OrderByItem>,
         frame: Option<WindowFrame>,
+        /// The named window this call uses or extends (`OVER w`, `OVER (w ...)`).
+        window: Option<String>,
     },
 }
 
+/// A window specification: `[base] [PARTITION BY ...] [ORDER BY ...] [frame]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowDef {
+    /// The named window this specification extends.
+    pub base: Option<String>,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderByItem>,
+    pub frame: Option<WindowFrame>,
+}
+
+/// A window defined in the `WINDOW` clause: `name AS (def)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedWindow {
+    pub name: String,
+    pub def: WindowDef,
+}
+
 /// The frame specification for a window function.
 #[derive(Debug, Clone, PartialEq)]
 pub struct WindowFrame {
@@ -459,6 +639,10 @@ pub enum BinaryOp {
     BitOr,
     ShiftLeft,
     ShiftRight,
+    /// `x REGEXP pattern`
+    Regexp,
+    /// `x GLOB pattern`: case-sensitive match with `*`, `?` and `[...]`.
+    Glob,
 }
 
 /// Unary operators.
@@ -485,11 +669,13 @@ mod tests {
             }),
             where_clause: None,
             group_by: vec![],
+            group_by_modifier: None,
             having: None,
             order_by: vec![],
             limit: None,
             offset: None,
             compound: vec![],
+            windows: vec![],
         };
         assert!(!stmt.distinct);
         assert_eq!(stmt.columns.len(), 1);
@@ -526,6 +712,8 @@ mod tests {
             default: None,
             collation: None,
             generated: None,
+            check: None,
+            references: None,
         };
         assert!(col.primary_key);
         assert!(col.autoincrement);
@@ -97,6 +97,7 @@ pub enum Token {
     Plan,
     Pragma,
     Vacuum,
+    Analyze,
     Attach,
     Detach,
     Database,
@@ -177,6 +178,8 @@ pub enum Token {
     // -----------------------------------------------------------------------
     /// Positional placeholder `?` or numbered `?1`, `?2`, etc.
     Placeholder(usize),
+    /// Named placeholder `$name`, `:name`, or `@name` (prefix included).
+    NamedPlaceholder(String),
     /// End-of-file sentinel.
     Eof,
 }
@@ -273,6 +276,7 @@ fn keyword_token(word: &str) -> Option<Token> {
         "PLAN" => Some(Token::Plan),
         "PRAGMA" => Some(Token::Pragma),
         "VACUUM" => Some(Token::Vacuum),
+        "ANALYZE" => Some(Token::Analyze),
         "ATTACH" => Some(Token::Attach),
         "DETACH" => Some(Token::Detach),
         "DATABASE" => Some(Token::Database),
@@ -323,8 +327,11 @@ fn keyword_token(word: &str) -> Option<Token> {
 pub struct Lexer<'a> {
     input: &'a [u8],
     pos: usize,
-    /// Auto-incrementing counter for bare `?` placeholders.
+    /// The highest parameter index assigned so far; a bare `?` takes the
+    /// next one.
     placeholder_counter: usize,
+    /// Named placeholders seen so far, each of which holds an index.
+    named_placeholders: Vec<String>,
 }
 
 impl<'a> Lexer<'a> {
@@ -334,6 +341,7 @@ impl<'a> Lexer<'a> {
             input: input.as_bytes(),
             pos: 0,
             placeholder_counter: 0,
+            named_placeholders: Vec::new(),
         }
     }
 
@@ -453,7 +461,7 @@ impl<'a> Lexer<'a> {
         }
 
         // ----- dot — could be `.123` float or just a dot -----
-        if ch == b'.' && self.peek_at(1).map_or(false, |c| c.is_ascii_digit()) {
+        if ch == b'.' && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
             return self.read_number();
         }
 
@@ -472,6 +480,13 @@ impl<'a> Lexer<'a> {
             return self.read_placeholder();
         }
 
+        // ----- named placeholder $name / :name / @name -----
+        if matches!(ch, b'$' | b':' | b'@')
+            && self.peek_at(1).is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            return Ok(self.read_named_placeholder());
+        }
+
         // ----- operators & punctuation -----
         self.read_operator()
     }
@@ -480,7 +495,9 @@ impl<'a> Lexer<'a> {
 
     fn read_string_literal(&mut self) -> Result<Token> {
         self.advance(); // consume opening '
-        let mut s = String::new();
+        // Collect raw bytes so multibyte characters survive intact; the
+        // input is valid UTF-8 and the delimiters are ASCII.
+        let mut s = Vec::new();
         loop {
             match self.advance() {
                 None => {
@@ -492,17 +509,17 @@ impl<'a> Lexer<'a> {
                     // Check for escaped quote ('')
                     if self.peek() == Some(b'\'') {
                         self.advance();
-                        s.push('\'');
+                        s.push(b'\'');
                     } else {
                         break;
                     }
                 }
                 Some(c) => {
-                    s.push(c as char);
+                    s.push(c);
                 }
             }
         }
-        Ok(Token::StringLiteral(s))
+        Ok(Token::StringLiteral(String::from_utf8_lossy(&s).into_owned()))
     }
 
     fn read_blob_literal(&mut self) -> Result<Token> {
@@ -543,17 +560,17 @@ impl<'a> Lexer<'a> {
         let mut is_real = false;
 
         // Integer part
-        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
             self.advance();
         }
 
         // Fractional part
         if self.peek() == Some(b'.')
-            && self.peek_at(1).map_or(false, |c| c.is_ascii_digit())
+            && self.peek_at(1).is_some_and(|c| c.is_ascii_digit())
         {
             is_real = true;
             self.advance(); // consume '.'
-            while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
                 self.advance();
             }
         } else if self.peek() == Some(b'.') && self.pos > start {
@@ -561,7 +578,7 @@ impl<'a> Lexer<'a> {
             // Only treat as real if we already consumed at least one digit
             is_real = true;
             self.advance();
-            while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
                 self.advance();
             }
         }
@@ -573,12 +590,12 @@ impl<'a> Lexer<'a> {
             if self.peek() == Some(b'+') || self.peek() == Some(b'-') {
                 self.advance();
             }
-            if !self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
                 return Err(HorizonError::InvalidSql(
                     "invalid numeric literal: expected digit after exponent".into(),
                 ));
             }
-            while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
                 self.advance();
             }
         }
@@ -602,7 +619,7 @@ impl<'a> Lexer<'a> {
         let start = self.pos;
         while self
             .peek()
-            .map_or(false, |c| c.is_ascii_alphanumeric() || c == b'_')
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
         {
             self.advance();
         }
@@ -618,7 +635,7 @@ impl<'a> Lexer<'a> {
 
     fn read_quoted_identifier(&mut self) -> Result<Token> {
         self.advance(); // consume opening "
-        let mut name = String::new();
+        let mut name = Vec::new();
         loop {
             match self.advance() {
                 None => {
@@ -630,21 +647,21 @@ impl<'a> Lexer<'a> {
                     // Doubled quote escapes itself
                     if self.peek() == Some(b'"') {
                         self.advance();
-                        name.push('"');
+                        name.push(b'"');
                     } else {
                         break;
                     }
                 }
-                Some(c) => name.push(c as char),
+                Some(c) => name.push(c),
             }
         }
-        Ok(Token::Identifier(name))
+        Ok(Token::Identifier(String::from_utf8_lossy(&name).into_owned()))
     }
 
     fn read_placeholder(&mut self) -> Result<Token> {
         self.advance(); // consume '?'
         let start = self.pos;
-        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
             self.advance();
         }
         if self.pos > start {
@@ -654,6 +671,7 @@ impl<'a> Lexer<'a> {
                 .map_err(|_| {
                     HorizonError::InvalidSql("invalid placeholder number".into())
                 })?;
+            self.placeholder_counter = self.placeholder_counter.max(num);
             Ok(Token::Placeholder(num))
         } else {
             self.placeholder_counter += 1;
@@ -661,6 +679,25 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn read_named_placeholder(&mut self) -> Token {
+        let start = self.pos;
+        self.advance(); // consume the '$', ':' or '@' prefix
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            self.advance();
+        }
+        let name = std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        // The first appearance of a name takes the next index, so that a
+        // later bare `?` is numbered after it (as in SQLite).
+        if !self.named_placeholders.iter().any(|n| n == name) {
+            self.named_placeholders.push(name.to_string());
+            self.placeholder_counter += 1;
+        }
+        Token::NamedPlaceholder(name.to_string())
+    }
+
     fn read_operator(&mut self) -> Result<Token> {
         let ch = self.advance().unwrap();
         match ch {
@@ -777,6 +814,13 @@ mod tests {
         assert_eq!(tokens[1], Token::StringLiteral("it's".into()));
     }
 
+    #[test]
+    fn multibyte_literals_are_preserved() {
+        let tokens = lex("'こんにちは' \"café\"");
+        assert_eq!(tokens[0], Token::StringLiteral("こんにちは".into()));
+        assert_eq!(tokens[1], Token::Identifier("café".into()));
+    }
+
     #[test]
     fn blob_literal() {
         let tokens = lex("X'DEADBEEF'");
@@ -819,7 +863,23 @@ mod tests {
         assert_eq!(tokens[0], Token::Placeholder(1));
         assert_eq!(tokens[1], Token::Placeholder(1));
         assert_eq!(tokens[2], Token::Placeholder(42));
-        assert_eq!(tokens[3], Token::Placeholder(2));
+        assert_eq!(tokens[3], Token::Placeholder(43));
+    }
+
+    #[test]
+    fn bare_placeholders_follow_named_ones() {
+        let tokens = lex(":a ? :a ?");
+        assert_eq!(tokens[1], Token::Placeholder(2));
+        assert_eq!(tokens[3], Token::Placeholder(3));
+    }
+
+    #[test]
+    fn named_placeholders() {
+        let tokens = lex("$id :name @x_1");
+        assert_eq!(tokens[0], Token::NamedPlaceholder("$id".into()));
+        assert_eq!(tokens[1], Token::NamedPlaceholder(":name".into()));
+        assert_eq!(tokens[2], Token::NamedPlaceholder("@x_1".into()));
+        assert!(Lexer::new("$").tokenize().is_err());
     }
 
     #[test]
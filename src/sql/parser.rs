@@ -36,6 +36,20 @@ impl Parser {
         Ok(stmts)
     }
 
+    /// Parse a SQL string consisting of a single expression.
+    pub fn parse_expression(sql: &str) -> Result<Expr> {
+        let tokens = Lexer::new(sql).tokenize()?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_expr()?;
+        if parser.current() != &Token::Eof {
+            return Err(parser.error(format!(
+                "unexpected token after expression: {:?}",
+                parser.current()
+            )));
+        }
+        Ok(expr)
+    }
+
     // =======================================================================
     // Token helpers
     // =======================================================================
@@ -65,6 +79,18 @@ impl Parser {
         }
     }
 
+    /// Parse a table name, which may be qualified by the schema of an
+    /// attached database as `schema.table`.
+    fn expect_table_name(&mut self) -> Result<String> {
+        let name = self.expect_identifier()?;
+        if self.current() == &Token::Dot {
+            self.advance();
+            let table = self.expect_identifier()?;
+            return Ok(format!("{}.{}", name, table));
+        }
+        Ok(name)
+    }
+
     fn expect_identifier(&mut self) -> Result<String> {
         match self.current().clone() {
             Token::Identifier(name) => {
@@ -126,6 +152,7 @@ impl Parser {
             Token::Release => "release",
             Token::Pragma => "pragma",
             Token::Vacuum => "vacuum",
+            Token::Analyze => "analyze",
             Token::Attach => "attach",
             Token::Detach => "detach",
             Token::Trigger => "trigger",
@@ -159,7 +186,7 @@ impl Parser {
 
     fn parse_statement(&mut self) -> Result<Statement> {
         match self.current() {
-            Token::Select | Token::With => self.parse_select_stmt(),
+            Token::Select | Token::With | Token::Values => self.parse_select_stmt(),
             Token::Insert | Token::Replace => self.parse_insert(),
             Token::Update => self.parse_update(),
             Token::Delete => self.parse_delete(),
@@ -187,8 +214,26 @@ impl Parser {
                 if self.current() == &Token::Transaction {
                     self.advance();
                 }
+                if self.current() == &Token::To {
+                    self.advance();
+                    if self.current() == &Token::Savepoint {
+                        self.advance();
+                    }
+                    return Ok(Statement::RollbackToSavepoint(self.expect_identifier()?));
+                }
                 Ok(Statement::Rollback)
             }
+            Token::Savepoint => {
+                self.advance();
+                Ok(Statement::Savepoint(self.expect_identifier()?))
+            }
+            Token::Release => {
+                self.advance();
+                if self.current() == &Token::Savepoint {
+                    self.advance();
+                }
+                Ok(Statement::ReleaseSavepoint(self.expect_identifier()?))
+            }
             Token::Attach => {
                 self.advance();
                 if self.current() == &Token::Database {
@@ -212,7 +257,26 @@ impl Parser {
             }
             Token::Vacuum => {
                 self.advance();
-                Ok(Statement::Vacuum)
+                if self.current() != &Token::Into {
+                    return Ok(Statement::Vacuum(None));
+                }
+                self.advance();
+                match self.current() {
+                    Token::StringLiteral(s) => {
+                        let path = s.clone();
+                        self.advance();
+                        Ok(Statement::Vacuum(Some(path)))
+                    }
+                    _ => Err(self.error("expected string path after VACUUM INTO".into())),
+                }
+            }
+            Token::Analyze => {
+                self.advance();
+                let target = match self.current() {
+                    Token::Semicolon | Token::Eof => None,
+                    _ => Some(self.expect_identifier()?),
+                };
+                Ok(Statement::Analyze(target))
             }
             _ => Err(self.error(format!(
                 "unexpected token at start of statement: {:?}",
@@ -315,19 +379,78 @@ impl Parser {
             None
         };
         self.expect(&Token::As)?;
+        let materialized = if self.peek_is_word(0, "MATERIALIZED") {
+            self.advance();
+            Some(true)
+        } else if self.current() == &Token::Not && self.peek_is_word(1, "MATERIALIZED") {
+            self.advance();
+            self.advance();
+            Some(false)
+        } else {
+            None
+        };
         self.expect(&Token::LeftParen)?;
         let query = self.parse_select_full()?;
         self.expect(&Token::RightParen)?;
+        let cycle = if self.peek_is_word(0, "CYCLE") {
+            Some(self.parse_cycle_clause()?)
+        } else {
+            None
+        };
         Ok(Cte {
             name,
             columns,
             query,
             recursive,
+            materialized,
+            cycle,
         })
     }
 
+    /// Parse `CYCLE col, ... SET cycle_col [TO mark DEFAULT default] USING path_col`.
+    fn parse_cycle_clause(&mut self) -> Result<CycleClause> {
+        self.advance();
+        let mut columns = vec![self.expect_identifier()?];
+        while self.current() == &Token::Comma {
+            self.advance();
+            columns.push(self.expect_identifier()?);
+        }
+        self.expect(&Token::Set)?;
+        let set_column = self.expect_identifier()?;
+        let (mark, default) = if self.current() == &Token::To {
+            self.advance();
+            let mark = self.parse_unary_expr()?;
+            self.expect(&Token::Default)?;
+            (mark, self.parse_unary_expr()?)
+        } else {
+            (Expr::Literal(LiteralValue::True), Expr::Literal(LiteralValue::False))
+        };
+        self.expect(&Token::Using)?;
+        let path_column = self.expect_identifier()?;
+        Ok(CycleClause { columns, set_column, mark, default, path_column })
+    }
+
     /// Parse the core SELECT body (without CTEs or compound trailing).
     fn parse_select_core(&mut self) -> Result<SelectStatement> {
+        // A bare `VALUES (...), ...` selects every column of the inline table.
+        if self.current() == &Token::Values {
+            let body = self.parse_select_body()?;
+            return Ok(SelectStatement {
+                ctes: vec![],
+                distinct: false,
+                columns: body.columns,
+                from: body.from,
+                where_clause: None,
+                group_by: vec![],
+                group_by_modifier: None,
+                having: None,
+                order_by: vec![],
+                limit: None,
+                offset: None,
+                compound: vec![],
+                windows: vec![],
+            });
+        }
         self.expect(&Token::Select)?;
 
         let distinct = if self.current() == &Token::Distinct {
@@ -353,13 +476,7 @@ impl Parser {
             None
         };
 
-        let group_by = if self.current() == &Token::Group {
-            self.advance();
-            self.expect(&Token::By)?;
-            self.parse_expr_list()?
-        } else {
-            vec![]
-        };
+        let (group_by, group_by_modifier) = self.parse_group_by()?;
 
         let having = if self.current() == &Token::Having {
             self.advance();
@@ -368,6 +485,21 @@ impl Parser {
             None
         };
 
+        let mut windows = Vec::new();
+        if self.current() == &Token::Window {
+            self.advance();
+            loop {
+                let name = self.expect_identifier()?;
+                self.expect(&Token::As)?;
+                let def = self.parse_window_def()?;
+                windows.push(NamedWindow { name, def });
+                if self.current() != &Token::Comma {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
         let order_by = if self.current() == &Token::Order {
             self.advance();
             self.expect(&Token::By)?;
@@ -397,16 +529,30 @@ impl Parser {
             from,
             where_clause,
             group_by,
+            group_by_modifier,
             having,
             order_by,
             limit,
             offset,
             compound: vec![],
+            windows,
         })
     }
 
     /// Parse a SELECT body for compound operators (no CTEs, no trailing ORDER/LIMIT).
     fn parse_select_body(&mut self) -> Result<SelectBody> {
+        if self.current() == &Token::Values {
+            let rows = self.parse_values_rows()?;
+            return Ok(SelectBody {
+                distinct: false,
+                columns: vec![SelectColumn::AllColumns],
+                from: Some(FromClause::Values { rows, alias: None, columns: None }),
+                where_clause: None,
+                group_by: vec![],
+                group_by_modifier: None,
+                having: None,
+            });
+        }
         self.expect(&Token::Select)?;
 
         let distinct = if self.current() == &Token::Distinct {
@@ -432,13 +578,7 @@ impl Parser {
             None
         };
 
-        let group_by = if self.current() == &Token::Group {
-            self.advance();
-            self.expect(&Token::By)?;
-            self.parse_expr_list()?
-        } else {
-            vec![]
-        };
+        let (group_by, group_by_modifier) = self.parse_group_by()?;
 
         let having = if self.current() == &Token::Having {
             self.advance();
@@ -453,10 +593,29 @@ impl Parser {
             from,
             where_clause,
             group_by,
+            group_by_modifier,
             having,
         })
     }
 
+    /// Parse an optional `GROUP BY` clause: a list of expressions, or
+    /// `ROLLUP(...)` of one.
+    fn parse_group_by(&mut self) -> Result<(Vec<Expr>, Option<GroupByModifier>)> {
+        if self.current() != &Token::Group {
+            return Ok((vec![], None));
+        }
+        self.advance();
+        self.expect(&Token::By)?;
+        if self.peek_is_word(0, "ROLLUP") && self.peek_ahead(1) == &Token::LeftParen {
+            self.advance();
+            self.advance();
+            let group_by = self.parse_expr_list()?;
+            self.expect(&Token::RightParen)?;
+            return Ok((group_by, Some(GroupByModifier::Rollup)));
+        }
+        Ok((self.parse_expr_list()?, None))
+    }
+
     fn parse_select_columns(&mut self) -> Result<Vec<SelectColumn>> {
         let mut cols = vec![self.parse_select_column()?];
         while self.current() == &Token::Comma {
@@ -521,6 +680,10 @@ impl Parser {
         let mut left = self.parse_from_item()?;
 
         loop {
+            let natural = self.current() == &Token::Natural;
+            if natural {
+                self.advance();
+            }
             let join_type = match self.current() {
                 Token::Join | Token::Inner => {
                     if self.current() == &Token::Inner {
@@ -545,30 +708,47 @@ impl Parser {
                     self.expect(&Token::Join)?;
                     JoinType::Right
                 }
+                Token::Full => {
+                    self.advance();
+                    if self.current() == &Token::Outer {
+                        self.advance();
+                    }
+                    self.expect(&Token::Join)?;
+                    JoinType::Full
+                }
                 Token::Cross => {
                     self.advance();
                     self.expect(&Token::Join)?;
                     JoinType::Cross
                 }
-                Token::Comma => {
+                Token::Comma if !natural => {
                     self.advance();
                     let right = self.parse_from_item()?;
                     left = FromClause::Join {
                         left: Box::new(left),
                         join_type: JoinType::Cross,
                         right: Box::new(right),
-                        on: None,
+                        constraint: None,
                     };
                     continue;
                 }
+                _ if natural => return Err(self.error("expected JOIN after NATURAL".into())),
                 _ => break,
             };
 
             let right = self.parse_from_item()?;
 
-            let on = if self.current() == &Token::On {
+            let constraint = if natural {
+                if matches!(self.current(), Token::On | Token::Using) {
+                    return Err(self.error("a NATURAL join may not have an ON or USING clause".into()));
+                }
+                Some(JoinSpec::Natural)
+            } else if self.current() == &Token::On {
                 self.advance();
-                Some(self.parse_expr()?)
+                Some(JoinSpec::On(self.parse_expr()?))
+            } else if self.current() == &Token::Using {
+                self.advance();
+                Some(JoinSpec::Using(self.parse_paren_identifier_list()?))
             } else {
                 None
             };
@@ -577,7 +757,7 @@ impl Parser {
                 left: Box::new(left),
                 join_type,
                 right: Box::new(right),
-                on,
+                constraint,
             };
         }
 
@@ -601,9 +781,32 @@ impl Parser {
                     alias,
                 });
             }
+            // Inline table: (VALUES (...), ...) [AS] alias [(col, ...)]
+            if self.peek_ahead(1) == &Token::Values {
+                self.advance(); // consume (
+                let rows = self.parse_values_rows()?;
+                self.expect(&Token::RightParen)?;
+                let alias = if self.current() == &Token::As {
+                    self.advance();
+                    Some(self.expect_identifier()?)
+                } else if let Token::Identifier(_) = self.current() {
+                    Some(self.expect_identifier()?)
+                } else {
+                    None
+                };
+                let columns = if alias.is_some() && self.current() == &Token::LeftParen {
+                    self.advance();
+                    let cols = self.parse_identifier_list()?;
+                    self.expect(&Token::RightParen)?;
+                    Some(cols)
+                } else {
+                    None
+                };
+                return Ok(FromClause::Values { rows, alias, columns });
+            }
         }
 
-        let name = self.expect_identifier()?;
+        let name = self.expect_table_name()?;
 
         // Check for table-valued function call: table_name('args')
         if self.current() == &Token::LeftParen {
@@ -660,7 +863,7 @@ impl Parser {
         };
 
         self.expect(&Token::Into)?;
-        let table = self.expect_identifier()?;
+        let table = self.expect_table_name()?;
 
         let columns = if self.current() == &Token::LeftParen {
             self.advance();
@@ -671,24 +874,34 @@ impl Parser {
             None
         };
 
-        self.expect(&Token::Values)?;
-        let mut values = vec![self.parse_value_row()?];
-        while self.current() == &Token::Comma {
-            self.advance();
-            values.push(self.parse_value_row()?);
-        }
+        let source = if matches!(self.current(), Token::Select | Token::With) {
+            InsertSource::Select(Box::new(self.parse_select_full()?))
+        } else {
+            InsertSource::Values(self.parse_values_rows()?)
+        };
 
         let returning = self.parse_optional_returning()?;
 
         Ok(Statement::Insert(InsertStatement {
             table,
             columns,
-            values,
+            source,
             or_replace,
             returning,
         }))
     }
 
+    /// Parse `VALUES (...), (...), ...`.
+    fn parse_values_rows(&mut self) -> Result<Vec<Vec<Expr>>> {
+        self.expect(&Token::Values)?;
+        let mut rows = vec![self.parse_value_row()?];
+        while self.current() == &Token::Comma {
+            self.advance();
+            rows.push(self.parse_value_row()?);
+        }
+        Ok(rows)
+    }
+
     fn parse_value_row(&mut self) -> Result<Vec<Expr>> {
         self.expect(&Token::LeftParen)?;
         let exprs = self.parse_expr_list()?;
@@ -711,7 +924,7 @@ impl Parser {
 
     fn parse_update(&mut self) -> Result<Statement> {
         self.expect(&Token::Update)?;
-        let table = self.expect_identifier()?;
+        let table = self.expect_table_name()?;
         self.expect(&Token::Set)?;
 
         let mut assignments = vec![self.parse_assignment()?];
@@ -720,6 +933,13 @@ impl Parser {
             assignments.push(self.parse_assignment()?);
         }
 
+        let from = if self.current() == &Token::From {
+            self.advance();
+            Some(self.parse_from_clause()?)
+        } else {
+            None
+        };
+
         let where_clause = if self.current() == &Token::Where {
             self.advance();
             Some(self.parse_expr()?)
@@ -732,13 +952,21 @@ impl Parser {
         Ok(Statement::Update(UpdateStatement {
             table,
             assignments,
+            from,
             where_clause,
             returning,
         }))
     }
 
+    /// A `column = expr` assignment of an UPDATE. The column may be
+    /// qualified by the table name (`t.column`), which is kept for the
+    /// executor to check.
     fn parse_assignment(&mut self) -> Result<(String, Expr)> {
-        let col = self.expect_identifier()?;
+        let mut col = self.expect_identifier()?;
+        if self.current() == &Token::Dot {
+            self.advance();
+            col = format!("{}.{}", col, self.expect_identifier()?);
+        }
         self.expect(&Token::Eq)?;
         let expr = self.parse_expr()?;
         Ok((col, expr))
@@ -751,7 +979,7 @@ impl Parser {
     fn parse_delete(&mut self) -> Result<Statement> {
         self.expect(&Token::Delete)?;
         self.expect(&Token::From)?;
-        let table = self.expect_identifier()?;
+        let table = self.expect_table_name()?;
 
         let where_clause = if self.current() == &Token::Where {
             self.advance();
@@ -810,10 +1038,10 @@ impl Parser {
         self.expect(&Token::LeftParen)?;
         let mut args = Vec::new();
         if self.current() != &Token::RightParen {
-            args.push(self.expect_identifier()?);
+            args.push(self.parse_module_arg()?);
             while self.current() == &Token::Comma {
                 self.advance();
-                args.push(self.expect_identifier()?);
+                args.push(self.parse_module_arg()?);
             }
         }
         self.expect(&Token::RightParen)?;
@@ -825,20 +1053,40 @@ impl Parser {
         }))
     }
 
+    /// Parse a module argument of `CREATE VIRTUAL TABLE`: a column name, or
+    /// an option `name = value` (the value an identifier or a string),
+    /// returned as `name=value`.
+    fn parse_module_arg(&mut self) -> Result<String> {
+        let name = self.expect_identifier()?;
+        if self.current() != &Token::Eq {
+            return Ok(name);
+        }
+        self.advance();
+        let value = match self.current().clone() {
+            Token::StringLiteral(value) => {
+                self.advance();
+                value
+            }
+            _ => self.expect_identifier()?,
+        };
+        Ok(format!("{}={}", name, value))
+    }
+
     fn parse_create_table(&mut self) -> Result<Statement> {
         self.expect(&Token::Table)?;
 
         let if_not_exists = self.parse_if_not_exists()?;
-        let name = self.expect_identifier()?;
+        let name = self.expect_table_name()?;
 
         self.expect(&Token::LeftParen)?;
-        let columns = self.parse_column_defs()?;
+        let (columns, constraints) = self.parse_column_defs()?;
         self.expect(&Token::RightParen)?;
 
         Ok(Statement::CreateTable(CreateTableStatement {
             name,
             if_not_exists,
             columns,
+            constraints,
         }))
     }
 
@@ -863,37 +1111,165 @@ impl Parser {
         }
     }
 
-    fn parse_column_defs(&mut self) -> Result<Vec<ColumnDef>> {
+    fn parse_column_defs(&mut self) -> Result<(Vec<ColumnDef>, Vec<TableConstraint>)> {
         let mut defs = vec![self.parse_column_def()?];
+        let mut constraints = Vec::new();
         while self.current() == &Token::Comma {
-            // Peek ahead to see if the next thing looks like a table
-            // constraint (PRIMARY, UNIQUE, CHECK, FOREIGN) rather than
-            // a column name. If so, stop.
+            self.advance();
+            // A table constraint (PRIMARY, UNIQUE, CHECK, FOREIGN) rather
+            // than a column name.
             if matches!(
-                self.peek_ahead(1),
+                self.current(),
                 Token::Primary | Token::Unique | Token::Check | Token::Foreign
             ) {
-                // Skip table constraints for now.
-                break;
+                if let Some(constraint) = self.parse_table_constraint()? {
+                    constraints.push(constraint);
+                }
+            } else {
+                defs.push(self.parse_column_def()?);
             }
+        }
+        Ok((defs, constraints))
+    }
+
+    /// Parse a table constraint. Returns `None` for constraint kinds that
+    /// are accepted but not yet enforced.
+    fn parse_table_constraint(&mut self) -> Result<Option<TableConstraint>> {
+        if self.current() == &Token::Primary {
             self.advance();
-            defs.push(self.parse_column_def()?);
+            self.expect(&Token::Key)?;
+            let columns = self.parse_paren_identifier_list()?;
+            return Ok(Some(TableConstraint::PrimaryKey(columns)));
         }
-        // Consume any remaining tokens up to the closing paren (table
-        // constraints we don't yet handle).
+        if self.current() == &Token::Check {
+            self.advance();
+            self.expect(&Token::LeftParen)?;
+            let expr = self.parse_expr()?;
+            self.expect(&Token::RightParen)?;
+            return Ok(Some(TableConstraint::Check(expr)));
+        }
+        if self.current() == &Token::Foreign {
+            self.advance();
+            self.expect(&Token::Key)?;
+            let columns = self.parse_paren_identifier_list()?;
+            let references = self.parse_references()?;
+            return Ok(Some(TableConstraint::ForeignKey { columns, references }));
+        }
+        // Skip the remaining constraint kinds up to the next top-level
+        // comma or the closing paren.
         let mut depth = 0i32;
-        while self.current() != &Token::RightParen || depth > 0 {
-            if self.current() == &Token::LeftParen {
-                depth += 1;
-            } else if self.current() == &Token::RightParen {
-                depth -= 1;
+        while depth > 0 || !matches!(self.current(), Token::Comma | Token::RightParen) {
+            match self.current() {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                Token::Eof => {
+                    return Err(self.error("unexpected end of input in column definitions".into()));
+                }
+                _ => {}
             }
-            if self.current() == &Token::Eof {
-                return Err(self.error("unexpected end of input in column definitions".into()));
+            self.advance();
+        }
+        Ok(None)
+    }
+
+    /// Parse `REFERENCES parent [(col, ...)]` followed by any `ON DELETE`,
+    /// `ON UPDATE`, `MATCH`, and `[NOT] DEFERRABLE` clauses.
+    fn parse_references(&mut self) -> Result<ForeignKeyClause> {
+        self.expect(&Token::References)?;
+        let table = self.expect_identifier()?;
+        let columns = if self.current() == &Token::LeftParen {
+            self.parse_paren_identifier_list()?
+        } else {
+            Vec::new()
+        };
+        let mut on_delete = FkAction::NoAction;
+        let mut on_update = FkAction::NoAction;
+        loop {
+            match self.current() {
+                Token::On => {
+                    self.advance();
+                    let is_delete = match self.current() {
+                        Token::Delete => true,
+                        Token::Update => false,
+                        other => {
+                            return Err(self.error(format!(
+                                "expected DELETE or UPDATE after ON, got {:?}",
+                                other
+                            )))
+                        }
+                    };
+                    self.advance();
+                    let action = self.parse_fk_action()?;
+                    if is_delete {
+                        on_delete = action;
+                    } else {
+                        on_update = action;
+                    }
+                }
+                Token::Match => {
+                    self.advance();
+                    self.expect_identifier()?;
+                }
+                Token::Not if self.peek_is_word(1, "DEFERRABLE") => {
+                    self.advance();
+                }
+                _ if self.peek_is_word(0, "DEFERRABLE") => {
+                    self.advance();
+                    if self.peek_is_word(0, "INITIALLY") {
+                        self.advance();
+                        self.expect_identifier()?;
+                    }
+                }
+                _ => break,
             }
+        }
+        Ok(ForeignKeyClause { table, columns, on_delete, on_update })
+    }
+
+    fn parse_fk_action(&mut self) -> Result<FkAction> {
+        if self.current() == &Token::Set {
+            self.advance();
+            return match self.current() {
+                Token::Null => {
+                    self.advance();
+                    Ok(FkAction::SetNull)
+                }
+                Token::Default => {
+                    self.advance();
+                    Ok(FkAction::SetDefault)
+                }
+                other => Err(self.error(format!("expected NULL or DEFAULT after SET, got {:?}", other))),
+            };
+        }
+        let action = if self.peek_is_word(0, "CASCADE") {
+            FkAction::Cascade
+        } else if self.peek_is_word(0, "RESTRICT") {
+            FkAction::Restrict
+        } else if self.peek_is_word(0, "NO") && self.peek_is_word(1, "ACTION") {
+            self.advance();
+            FkAction::NoAction
+        } else {
+            return Err(self.error(format!("expected foreign key action, got {:?}", self.current())));
+        };
+        self.advance();
+        Ok(action)
+    }
+
+    /// Whether the token `offset` places ahead is the bare word `word`.
+    fn peek_is_word(&self, offset: usize, word: &str) -> bool {
+        matches!(self.peek_ahead(offset), Token::Identifier(w) if w.eq_ignore_ascii_case(word))
+    }
+
+    /// Parse `(ident, ident, ...)`.
+    fn parse_paren_identifier_list(&mut self) -> Result<Vec<String>> {
+        self.expect(&Token::LeftParen)?;
+        let mut names = vec![self.expect_identifier()?];
+        while self.current() == &Token::Comma {
             self.advance();
+            names.push(self.expect_identifier()?);
         }
-        Ok(defs)
+        self.expect(&Token::RightParen)?;
+        Ok(names)
     }
 
     fn parse_column_def(&mut self) -> Result<ColumnDef> {
@@ -910,6 +1286,8 @@ impl Parser {
         let mut default = None;
         let mut collation = None;
         let mut generated = None;
+        let mut check = None;
+        let mut references = None;
 
         // Column constraints
         loop {
@@ -943,45 +1321,21 @@ impl Parser {
                     }
                 }
                 Token::Check => {
-                    // Skip CHECK constraints for now.
                     self.advance();
                     self.expect(&Token::LeftParen)?;
-                    let mut depth = 1i32;
-                    while depth > 0 {
-                        match self.current() {
-                            Token::LeftParen => depth += 1,
-                            Token::RightParen => depth -= 1,
-                            Token::Eof => {
-                                return Err(
-                                    self.error("unexpected EOF in CHECK constraint".into())
-                                );
-                            }
-                            _ => {}
-                        }
-                        self.advance();
-                    }
+                    let expr = self.parse_expr()?;
+                    self.expect(&Token::RightParen)?;
+                    check = Some(match check.take() {
+                        Some(prev) => Expr::BinaryOp {
+                            left: Box::new(prev),
+                            op: BinaryOp::And,
+                            right: Box::new(expr),
+                        },
+                        None => expr,
+                    });
                 }
                 Token::References => {
-                    // Skip REFERENCES clause for now.
-                    self.advance();
-                    let _ref_table = self.expect_identifier()?;
-                    if self.current() == &Token::LeftParen {
-                        self.advance();
-                        let mut depth = 1i32;
-                        while depth > 0 {
-                            match self.current() {
-                                Token::LeftParen => depth += 1,
-                                Token::RightParen => depth -= 1,
-                                Token::Eof => {
-                                    return Err(
-                                        self.error("unexpected EOF in REFERENCES".into())
-                                    );
-                                }
-                                _ => {}
-                            }
-                            self.advance();
-                        }
-                    }
+                    references = Some(self.parse_references()?);
                 }
                 Token::Collate => {
                     self.advance();
@@ -1041,6 +1395,8 @@ impl Parser {
             default,
             collation,
             generated,
+            check,
+            references,
         })
     }
 
@@ -1143,12 +1499,20 @@ impl Parser {
         let columns = self.parse_order_by_list()?;
         self.expect(&Token::RightParen)?;
 
+        let where_clause = if self.current() == &Token::Where {
+            self.advance();
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
         Ok(Statement::CreateIndex(CreateIndexStatement {
             name,
             table,
             columns,
             unique,
             if_not_exists,
+            where_clause,
         }))
     }
 
@@ -1296,7 +1660,7 @@ impl Parser {
             Token::Table => {
                 self.advance();
                 let if_exists = self.parse_if_exists()?;
-                let name = self.expect_identifier()?;
+                let name = self.expect_table_name()?;
                 Ok(Statement::DropTable(DropTableStatement { name, if_exists }))
             }
             Token::Index => {
@@ -1476,6 +1840,24 @@ impl Parser {
                         negated: false,
                     };
                 }
+                Token::Glob => {
+                    self.advance();
+                    let pattern = self.parse_bitor_expr()?;
+                    left = Expr::BinaryOp {
+                        left: Box::new(left),
+                        op: BinaryOp::Glob,
+                        right: Box::new(pattern),
+                    };
+                }
+                Token::Identifier(word) if word.eq_ignore_ascii_case("REGEXP") => {
+                    self.advance();
+                    let pattern = self.parse_bitor_expr()?;
+                    left = Expr::BinaryOp {
+                        left: Box::new(left),
+                        op: BinaryOp::Regexp,
+                        right: Box::new(pattern),
+                    };
+                }
                 Token::Match => {
                     self.advance();
                     let pattern = self.parse_bitor_expr()?;
@@ -1485,7 +1867,28 @@ impl Parser {
                     };
                 }
                 Token::Not => {
-                    // NOT BETWEEN, NOT IN, NOT LIKE
+                    // NOT BETWEEN, NOT IN, NOT LIKE, NOT GLOB, NOT REGEXP
+                    let negated_op = match self.peek_ahead(1) {
+                        Token::Glob => Some(BinaryOp::Glob),
+                        Token::Identifier(word) if word.eq_ignore_ascii_case("REGEXP") => {
+                            Some(BinaryOp::Regexp)
+                        }
+                        _ => None,
+                    };
+                    if let Some(op) = negated_op {
+                        self.advance(); // NOT
+                        self.advance(); // GLOB / REGEXP
+                        let pattern = self.parse_bitor_expr()?;
+                        left = Expr::UnaryOp {
+                            op: UnaryOp::Not,
+                            expr: Box::new(Expr::BinaryOp {
+                                left: Box::new(left),
+                                op,
+                                right: Box::new(pattern),
+                            }),
+                        };
+                        continue;
+                    }
                     match self.peek_ahead(1) {
                         Token::Between => {
                             self.advance(); // NOT
@@ -1763,6 +2166,26 @@ impl Parser {
         Ok(expr)
     }
 
+    /// Parse the arguments of `RAISE(action[, message])` after the opening
+    /// parenthesis. The action keyword becomes a bare column reference.
+    fn parse_raise_args(&mut self) -> Result<Expr> {
+        let action = match self.current() {
+            Token::Abort => "ABORT",
+            Token::Fail => "FAIL",
+            Token::Rollback => "ROLLBACK",
+            Token::Ignore => "IGNORE",
+            other => return Err(self.error(format!("expected RAISE action, got {:?}", other))),
+        };
+        self.advance();
+        let mut args = vec![Expr::Column { table: None, name: action.into() }];
+        if self.current() == &Token::Comma {
+            self.advance();
+            args.push(self.parse_expr()?);
+        }
+        self.expect(&Token::RightParen)?;
+        Ok(Expr::Function { name: "RAISE".into(), args, distinct: false, filter: None })
+    }
+
     fn parse_primary_expr(&mut self) -> Result<Expr> {
         match self.current().clone() {
             // Integer literal
@@ -1805,6 +2228,10 @@ impl Parser {
                 self.advance();
                 Ok(Expr::Placeholder(n))
             }
+            Token::NamedPlaceholder(name) => {
+                self.advance();
+                Ok(Expr::NamedPlaceholder(name))
+            }
             // Parenthesised expression or subquery
             Token::LeftParen => {
                 self.advance();
@@ -1874,6 +2301,9 @@ impl Parser {
                 // Function call: name(...)
                 if self.current() == &Token::LeftParen {
                     self.advance();
+                    if name.eq_ignore_ascii_case("raise") {
+                        return self.parse_raise_args();
+                    }
                     let distinct = if self.current() == &Token::Distinct {
                         self.advance();
                         true
@@ -1893,10 +2323,12 @@ impl Parser {
                         self.parse_expr_list()?
                     };
                     self.expect(&Token::RightParen)?;
+                    let filter = self.parse_filter_clause()?;
                     let func = Expr::Function {
                         name: name.to_ascii_uppercase(),
                         args,
                         distinct,
+                        filter,
                     };
                     // Check for OVER (...) to make this a window function
                     if self.current() == &Token::Over {
@@ -1907,6 +2339,11 @@ impl Parser {
                 // Qualified column: table.column
                 if self.current() == &Token::Dot {
                     self.advance();
+                    // All the columns of a table, as in ROW_TO_JSON(t.*)
+                    if self.current() == &Token::Star {
+                        self.advance();
+                        return Ok(Expr::Column { table: Some(name), name: "*".into() });
+                    }
                     let col_name = self.expect_identifier()?;
                     return Ok(Expr::Column {
                         table: Some(name),
@@ -1916,6 +2353,12 @@ impl Parser {
                 // Simple column reference
                 Ok(Expr::Column { table: None, name })
             }
+            // Keywords that can also be column names in expression context,
+            // e.g. the `key` column of json_each
+            Token::Key => {
+                self.advance();
+                Ok(Expr::Column { table: None, name: "key".into() })
+            }
             // Keywords that can also be function names in expression context
             Token::Replace => {
                 self.advance();
@@ -1931,6 +2374,7 @@ impl Parser {
                         name: "REPLACE".to_string(),
                         args,
                         distinct: false,
+                        filter: None,
                     });
                 }
                 Err(self.error("unexpected REPLACE in expression context".to_string()))
@@ -1951,6 +2395,19 @@ impl Parser {
         }
     }
 
+    /// Parse an optional `FILTER (WHERE expr)` after an aggregate call.
+    fn parse_filter_clause(&mut self) -> Result<Option<Box<Expr>>> {
+        if !(self.peek_is_word(0, "FILTER") && self.peek_ahead(1) == &Token::LeftParen) {
+            return Ok(None);
+        }
+        self.advance();
+        self.advance();
+        self.expect(&Token::Where)?;
+        let condition = self.parse_expr()?;
+        self.expect(&Token::RightParen)?;
+        Ok(Some(Box::new(condition)))
+    }
+
     // =======================================================================
     // Window function parsing
     // =======================================================================
@@ -1958,8 +2415,37 @@ impl Parser {
     /// Parse a window specification after a function call: `OVER (...)`.
     fn parse_window_function(&mut self, func: Expr) -> Result<Expr> {
         self.expect(&Token::Over)?;
+        // OVER name refers to a window from the WINDOW clause
+        let def = if let Token::Identifier(name) = self.current() {
+            let base = Some(name.clone());
+            self.advance();
+            WindowDef { base, partition_by: vec![], order_by: vec![], frame: None }
+        } else {
+            self.parse_window_def()?
+        };
+        Ok(Expr::WindowFunction {
+            function: Box::new(func),
+            partition_by: def.partition_by,
+            order_by: def.order_by,
+            frame: def.frame,
+            window: def.base,
+        })
+    }
+
+    /// Parse a parenthesized window specification:
+    /// `([base] [PARTITION BY ...] [ORDER BY ...] [frame])`.
+    fn parse_window_def(&mut self) -> Result<WindowDef> {
         self.expect(&Token::LeftParen)?;
 
+        // The name of a window to extend (optional)
+        let base = if let Token::Identifier(name) = self.current() {
+            let name = name.clone();
+            self.advance();
+            Some(name)
+        } else {
+            None
+        };
+
         // PARTITION BY expr_list (optional)
         let partition_by = if self.current() == &Token::Partition {
             self.advance();
@@ -1987,12 +2473,7 @@ impl Parser {
 
         self.expect(&Token::RightParen)?;
 
-        Ok(Expr::WindowFunction {
-            function: Box::new(func),
-            partition_by,
-            order_by,
-            frame,
-        })
+        Ok(WindowDef { base, partition_by, order_by, frame })
     }
 
     /// Parse a window frame clause: `ROWS|RANGE BETWEEN ... AND ...`
@@ -2171,10 +2652,10 @@ impl Parser {
 
         let value = if self.current() == &Token::Eq {
             self.advance();
-            Some(self.parse_expr()?)
+            Some(self.parse_pragma_value()?)
         } else if self.current() == &Token::LeftParen {
             self.advance();
-            let val = self.parse_expr()?;
+            let val = self.parse_pragma_value()?;
             self.expect(&Token::RightParen)?;
             Some(val)
         } else {
@@ -2183,6 +2664,19 @@ impl Parser {
 
         Ok(Statement::Pragma(PragmaStatement { name, value }))
     }
+
+    /// Parse a PRAGMA argument. `ON`, `FULL`, and `DELETE` are keywords,
+    /// but common PRAGMA values, so they are read as bare words.
+    fn parse_pragma_value(&mut self) -> Result<Expr> {
+        let word = match self.current() {
+            Token::On => "ON",
+            Token::Full => "FULL",
+            Token::Delete => "DELETE",
+            _ => return self.parse_expr(),
+        };
+        self.advance();
+        Ok(Expr::Column { table: None, name: word.into() })
+    }
 }
 
 // ===========================================================================
@@ -2252,9 +2746,9 @@ mod tests {
         );
         if let Statement::Select(sel) = stmt {
             assert_eq!(sel.columns.len(), 2);
-            if let Some(FromClause::Join { join_type, on, .. }) = &sel.from {
+            if let Some(FromClause::Join { join_type, constraint, .. }) = &sel.from {
                 assert_eq!(*join_type, JoinType::Inner);
-                assert!(on.is_some());
+                assert!(matches!(constraint, Some(JoinSpec::On(_))));
             } else {
                 panic!("expected Join");
             }
@@ -2276,6 +2770,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_group_by_rollup() {
+        let stmt = parse_one("SELECT a, b, SUM(c) FROM t GROUP BY ROLLUP(a, b)");
+        if let Statement::Select(sel) = stmt {
+            assert_eq!(sel.group_by.len(), 2);
+            assert_eq!(sel.group_by_modifier, Some(GroupByModifier::Rollup));
+        } else {
+            panic!("expected Select");
+        }
+        // Without parentheses `rollup` is an ordinary column
+        if let Statement::Select(sel) = parse_one("SELECT rollup FROM t GROUP BY rollup") {
+            assert_eq!(sel.group_by_modifier, None);
+        } else {
+            panic!("expected Select");
+        }
+    }
+
     #[test]
     fn parse_select_table_star() {
         let stmt = parse_one("SELECT u.* FROM users u");
@@ -2308,8 +2819,9 @@ mod tests {
         if let Statement::Insert(ins) = stmt {
             assert_eq!(ins.table, "users");
             assert_eq!(ins.columns, Some(vec!["id".into(), "name".into()]));
-            assert_eq!(ins.values.len(), 1);
-            assert_eq!(ins.values[0].len(), 2);
+            let InsertSource::Values(values) = ins.source else { panic!("expected VALUES") };
+            assert_eq!(values.len(), 1);
+            assert_eq!(values[0].len(), 2);
             assert!(!ins.or_replace);
         } else {
             panic!("expected Insert");
@@ -2320,10 +2832,26 @@ mod tests {
     fn parse_insert_multiple_rows() {
         let stmt = parse_one("INSERT INTO t VALUES (1, 2), (3, 4), (5, 6)");
         if let Statement::Insert(ins) = stmt {
-            assert_eq!(ins.values.len(), 3);
+            assert!(matches!(ins.source, InsertSource::Values(ref v) if v.len() == 3));
+        } else {
+            panic!("expected Insert");
+        }
+    }
+
+    #[test]
+    fn parse_insert_select() {
+        let stmt = parse_one("INSERT INTO t2 (a, b) SELECT b, a FROM t1 WHERE a > 1");
+        if let Statement::Insert(ins) = stmt {
+            assert_eq!(ins.columns, Some(vec!["a".into(), "b".into()]));
+            let InsertSource::Select(select) = ins.source else { panic!("expected SELECT") };
+            assert_eq!(select.columns.len(), 2);
+            assert!(select.where_clause.is_some());
         } else {
             panic!("expected Insert");
         }
+
+        let stmt = parse_one("INSERT INTO t WITH x AS (SELECT 1) SELECT * FROM x");
+        assert!(matches!(stmt, Statement::Insert(InsertStatement { source: InsertSource::Select(_), .. })));
     }
 
     #[test]
@@ -2371,6 +2899,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_update_from() {
+        let stmt = parse_one("UPDATE t SET t.a = s.a, b = 2 FROM s JOIN u ON s.id = u.id WHERE t.id = s.id");
+        if let Statement::Update(upd) = stmt {
+            assert_eq!(upd.assignments[0].0, "t.a");
+            assert_eq!(upd.assignments[1].0, "b");
+            assert!(matches!(upd.from, Some(FromClause::Join { .. })));
+            assert!(upd.where_clause.is_some());
+        } else {
+            panic!("expected Update");
+        }
+    }
+
     // -- DELETE tests -------------------------------------------------------
 
     #[test]
@@ -2446,6 +2987,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_foreign_key_clauses() {
+        let stmt = parse_one(
+            "CREATE TABLE c (id INTEGER PRIMARY KEY, \
+             p INTEGER REFERENCES parent ON DELETE CASCADE ON UPDATE SET NULL, \
+             a INTEGER, b INTEGER, \
+             FOREIGN KEY (a, b) REFERENCES other (x, y) ON DELETE NO ACTION DEFERRABLE INITIALLY DEFERRED)",
+        );
+        if let Statement::CreateTable(ct) = stmt {
+            let col_fk = ct.columns[1].references.as_ref().unwrap();
+            assert_eq!(col_fk.table, "parent");
+            assert!(col_fk.columns.is_empty());
+            assert_eq!(col_fk.on_delete, FkAction::Cascade);
+            assert_eq!(col_fk.on_update, FkAction::SetNull);
+            match &ct.constraints[0] {
+                TableConstraint::ForeignKey { columns, references } => {
+                    assert_eq!(columns, &vec!["a".to_string(), "b".to_string()]);
+                    assert_eq!(references.columns, vec!["x".to_string(), "y".to_string()]);
+                    assert_eq!(references.on_delete, FkAction::NoAction);
+                }
+                other => panic!("expected ForeignKey, got {:?}", other),
+            }
+        } else {
+            panic!("expected CreateTable");
+        }
+    }
+
+    #[test]
+    fn parse_create_table_check_constraints() {
+        let stmt = parse_one(
+            "CREATE TABLE t (a INTEGER CHECK (a > 0) CHECK (a < 10), b INTEGER, \
+             UNIQUE (a), CHECK (a < b))",
+        );
+        if let Statement::CreateTable(ct) = stmt {
+            assert_eq!(ct.columns.len(), 2);
+            assert!(matches!(
+                ct.columns[0].check,
+                Some(Expr::BinaryOp { op: BinaryOp::And, .. })
+            ));
+            assert!(ct.columns[1].check.is_none());
+            assert_eq!(ct.constraints.len(), 1);
+            assert!(matches!(
+                ct.constraints[0],
+                TableConstraint::Check(Expr::BinaryOp { op: BinaryOp::Lt, .. })
+            ));
+        } else {
+            panic!("expected CreateTable");
+        }
+    }
+
+    #[test]
+    fn parse_composite_primary_key() {
+        let stmt = parse_one(
+            "CREATE TABLE t (a INTEGER, b TEXT, c REAL, PRIMARY KEY (a, b))",
+        );
+        if let Statement::CreateTable(ct) = stmt {
+            assert!(ct.columns.iter().all(|c| !c.primary_key));
+            assert_eq!(
+                ct.constraints,
+                vec![TableConstraint::PrimaryKey(vec!["a".to_string(), "b".to_string()])]
+            );
+        } else {
+            panic!("expected CreateTable");
+        }
+    }
+
     // -- DROP TABLE / INDEX tests -------------------------------------------
 
     #[test]
@@ -2508,6 +3115,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_partial_index() {
+        let stmt = parse_one("CREATE INDEX idx_active ON users (name) WHERE status = 'active'");
+        if let Statement::CreateIndex(ci) = stmt {
+            assert!(matches!(ci.where_clause, Some(Expr::BinaryOp { op: BinaryOp::Eq, .. })));
+        } else {
+            panic!("expected CreateIndex");
+        }
+    }
+
     // -- Transaction tests --------------------------------------------------
 
     #[test]
@@ -2525,6 +3142,21 @@ mod tests {
         assert_eq!(stmts, vec![Statement::Begin]);
     }
 
+    #[test]
+    fn parse_savepoint_statements() {
+        let stmts = Parser::parse(
+            "SAVEPOINT sp1; RELEASE sp1; RELEASE SAVEPOINT sp2; ROLLBACK TO sp3; ROLLBACK TRANSACTION TO SAVEPOINT sp4",
+        )
+        .unwrap();
+        assert_eq!(stmts, vec![
+            Statement::Savepoint("sp1".into()),
+            Statement::ReleaseSavepoint("sp1".into()),
+            Statement::ReleaseSavepoint("sp2".into()),
+            Statement::RollbackToSavepoint("sp3".into()),
+            Statement::RollbackToSavepoint("sp4".into()),
+        ]);
+    }
+
     // -- Multiple statements ------------------------------------------------
 
     #[test]
@@ -2650,6 +3282,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_glob_and_regexp() {
+        let stmt = parse_one("SELECT * FROM f WHERE name GLOB '*.rs' AND name NOT REGEXP '^x'");
+        if let Statement::Select(sel) = stmt {
+            match &sel.where_clause {
+                Some(Expr::BinaryOp { left, op: BinaryOp::And, right }) => {
+                    assert!(matches!(left.as_ref(), Expr::BinaryOp { op: BinaryOp::Glob, .. }));
+                    match right.as_ref() {
+                        Expr::UnaryOp { op: UnaryOp::Not, expr } => {
+                            assert!(matches!(expr.as_ref(), Expr::BinaryOp { op: BinaryOp::Regexp, .. }));
+                        }
+                        other => panic!("expected NOT REGEXP, got {:?}", other),
+                    }
+                }
+                other => panic!("expected AND, got {:?}", other),
+            }
+        } else {
+            panic!("expected Select");
+        }
+    }
+
     #[test]
     fn parse_is_null_is_not_null() {
         let stmt = parse_one("SELECT * FROM t WHERE a IS NULL AND b IS NOT NULL");
@@ -2688,6 +3341,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_aggregate_filter() {
+        let stmt = parse_one("SELECT COUNT(*) FILTER (WHERE score > 50), filter FROM t");
+        if let Statement::Select(sel) = stmt {
+            if let SelectColumn::Expr { expr: Expr::Function { name, filter, .. }, .. } = &sel.columns[0] {
+                assert_eq!(name, "COUNT");
+                assert!(matches!(filter.as_deref(), Some(Expr::BinaryOp { op: BinaryOp::Gt, .. })));
+            } else {
+                panic!("expected Function");
+            }
+            // Without a following parenthesis, `filter` is an ordinary column
+            assert!(matches!(
+                &sel.columns[1],
+                SelectColumn::Expr { expr: Expr::Column { name, .. }, .. } if name == "filter"
+            ));
+        } else {
+            panic!("expected SELECT");
+        }
+    }
+
+    #[test]
+    fn parse_table_star_argument() {
+        let stmt = parse_one("SELECT ROW_TO_JSON(u.*), ROW_TO_JSON(*) FROM users u");
+        if let Statement::Select(sel) = stmt {
+            for (column, table) in sel.columns.iter().zip([Some("u"), None]) {
+                match column {
+                    SelectColumn::Expr { expr: Expr::Function { args, .. }, .. } => {
+                        assert_eq!(args, &vec![Expr::Column { table: table.map(String::from), name: "*".into() }]);
+                    }
+                    other => panic!("expected Function, got {:?}", other),
+                }
+            }
+        } else {
+            panic!("expected SELECT");
+        }
+    }
+
+    #[test]
+    fn parse_window_clause() {
+        let stmt = parse_one(
+            "SELECT RANK() OVER w, SUM(x) OVER (w ORDER BY y) FROM t WINDOW w AS (PARTITION BY z), v AS (w)",
+        );
+        if let Statement::Select(sel) = stmt {
+            assert_eq!(sel.windows.len(), 2);
+            assert_eq!(sel.windows[0].name, "w");
+            assert_eq!(sel.windows[0].def.partition_by.len(), 1);
+            assert_eq!(sel.windows[1].def.base.as_deref(), Some("w"));
+            match &sel.columns[1] {
+                SelectColumn::Expr { expr: Expr::WindowFunction { window, order_by, partition_by, .. }, .. } => {
+                    assert_eq!(window.as_deref(), Some("w"));
+                    assert_eq!(order_by.len(), 1);
+                    assert!(partition_by.is_empty());
+                }
+                other => panic!("expected WindowFunction, got {:?}", other),
+            }
+        } else {
+            panic!("expected SELECT");
+        }
+    }
+
     #[test]
     fn parse_cast_expression() {
         let stmt = parse_one("SELECT CAST(age AS integer)");
@@ -2798,13 +3511,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_full_outer_join() {
+        for sql in ["SELECT * FROM a FULL OUTER JOIN b ON a.id = b.id", "SELECT * FROM a FULL JOIN b ON a.id = b.id"] {
+            if let Statement::Select(sel) = parse_one(sql) {
+                if let Some(FromClause::Join { join_type, .. }) = &sel.from {
+                    assert_eq!(*join_type, JoinType::Full);
+                } else {
+                    panic!("expected Full Join");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_natural_and_using_joins() {
+        if let Statement::Select(sel) = parse_one("SELECT * FROM a NATURAL LEFT JOIN b") {
+            if let Some(FromClause::Join { join_type, constraint, .. }) = &sel.from {
+                assert_eq!(*join_type, JoinType::Left);
+                assert_eq!(*constraint, Some(JoinSpec::Natural));
+            } else {
+                panic!("expected Join");
+            }
+        }
+        if let Statement::Select(sel) = parse_one("SELECT * FROM a JOIN b USING (x, y)") {
+            if let Some(FromClause::Join { constraint, .. }) = &sel.from {
+                assert_eq!(*constraint, Some(JoinSpec::Using(vec!["x".into(), "y".into()])));
+            } else {
+                panic!("expected Join");
+            }
+        }
+        assert!(Parser::parse("SELECT * FROM a NATURAL JOIN b ON a.x = b.x").is_err());
+        assert!(Parser::parse("SELECT * FROM a NATURAL b").is_err());
+    }
+
     #[test]
     fn parse_cross_join() {
         let stmt = parse_one("SELECT * FROM a CROSS JOIN b");
         if let Statement::Select(sel) = stmt {
-            if let Some(FromClause::Join { join_type, on, .. }) = &sel.from {
+            if let Some(FromClause::Join { join_type, constraint, .. }) = &sel.from {
                 assert_eq!(*join_type, JoinType::Cross);
-                assert!(on.is_none());
+                assert!(constraint.is_none());
             } else {
                 panic!("expected Cross Join");
             }
@@ -2841,6 +3588,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_values_in_from() {
+        let stmt = parse_one("SELECT * FROM (VALUES (1, 'a'), (2, 'b')) AS t(id, name)");
+        if let Statement::Select(sel) = stmt {
+            match &sel.from {
+                Some(FromClause::Values { rows, alias, columns }) => {
+                    assert_eq!(rows.len(), 2);
+                    assert_eq!(alias.as_deref(), Some("t"));
+                    assert_eq!(columns.as_deref(), Some(&["id".to_string(), "name".to_string()][..]));
+                }
+                other => panic!("expected Values from, got {:?}", other),
+            }
+        }
+
+        let stmt = parse_one("SELECT 1 UNION VALUES (2), (3)");
+        if let Statement::Select(sel) = stmt {
+            assert!(matches!(
+                &sel.compound[0].select.from,
+                Some(FromClause::Values { rows, alias: None, .. }) if rows.len() == 2
+            ));
+        }
+    }
+
     #[test]
     fn parse_nested_parens_in_expr() {
         let stmt = parse_one("SELECT (1 + (2 * 3))");
@@ -3044,6 +3814,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_cte_cycle_clause() {
+        let stmt = parse_one(
+            "WITH RECURSIVE t(a, b) AS (SELECT 1, 2 UNION ALL SELECT b, a FROM t) \
+             CYCLE a, b SET seen TO 'y' DEFAULT 'n' USING path SELECT * FROM t"
+        );
+        if let Statement::Select(s) = stmt {
+            let cycle = s.ctes[0].cycle.as_ref().expect("CYCLE clause");
+            assert_eq!(cycle.columns, vec!["a".to_string(), "b".to_string()]);
+            assert_eq!(cycle.set_column, "seen");
+            assert_eq!(cycle.mark, Expr::Literal(LiteralValue::String("y".into())));
+            assert_eq!(cycle.default, Expr::Literal(LiteralValue::String("n".into())));
+            assert_eq!(cycle.path_column, "path");
+        } else {
+            panic!("expected Select");
+        }
+
+        let stmt = parse_one("WITH RECURSIVE t(a) AS (SELECT 1) CYCLE a SET seen USING path SELECT * FROM t");
+        if let Statement::Select(s) = stmt {
+            let cycle = s.ctes[0].cycle.as_ref().expect("CYCLE clause");
+            assert_eq!(cycle.mark, Expr::Literal(LiteralValue::True));
+            assert_eq!(cycle.default, Expr::Literal(LiteralValue::False));
+        } else {
+            panic!("expected Select");
+        }
+    }
+
+    #[test]
+    fn parse_cte_materialization_hints() {
+        let stmt = parse_one(
+            "WITH a AS MATERIALIZED (SELECT 1), b AS NOT MATERIALIZED (SELECT 2), c AS (SELECT 3) SELECT * FROM a"
+        );
+        if let Statement::Select(s) = stmt {
+            let hints: Vec<Option<bool>> = s.ctes.iter().map(|cte| cte.materialized).collect();
+            assert_eq!(hints, vec![Some(true), Some(false), None]);
+        } else {
+            panic!("expected Select");
+        }
+    }
+
     #[test]
     fn parse_multiple_ctes() {
         let stmt = parse_one(
@@ -3196,6 +4006,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_create_trigger_when_with_raise() {
+        let stmt = parse_one(
+            "CREATE TRIGGER trg BEFORE UPDATE ON t WHEN NEW.x < 0 BEGIN SELECT RAISE(ABORT, 'negative'); END"
+        );
+        if let Statement::CreateTrigger(ct) = stmt {
+            assert!(matches!(ct.when, Some(Expr::BinaryOp { op: BinaryOp::Lt, .. })));
+            if let Statement::Select(select) = &ct.body[0] {
+                if let SelectColumn::Expr { expr: Expr::Function { name, args, .. }, .. } = &select.columns[0] {
+                    assert_eq!(name, "RAISE");
+                    assert_eq!(args[0], Expr::Column { table: None, name: "ABORT".into() });
+                    assert_eq!(args.len(), 2);
+                } else {
+                    panic!("expected RAISE call");
+                }
+            } else {
+                panic!("expected Select body");
+            }
+        } else {
+            panic!("expected CreateTrigger");
+        }
+    }
+
     #[test]
     fn parse_drop_trigger() {
         let stmt = parse_one("DROP TRIGGER IF EXISTS trg");
@@ -3206,4 +4039,21 @@ mod tests {
             panic!("expected DropTrigger");
         }
     }
+
+    #[test]
+    fn parse_analyze() {
+        let stmts = Parser::parse("ANALYZE; ANALYZE users; ANALYZE \"idx\"").unwrap();
+        assert_eq!(stmts, vec![
+            Statement::Analyze(None),
+            Statement::Analyze(Some("users".into())),
+            Statement::Analyze(Some("idx".into())),
+        ]);
+    }
+
+    #[test]
+    fn parse_vacuum() {
+        let stmts = Parser::parse("VACUUM; VACUUM INTO 'copy.hdb'").unwrap();
+        assert_eq!(stmts, vec![Statement::Vacuum(None), Statement::Vacuum(Some("copy.hdb".into()))]);
+        assert!(Parser::parse("VACUUM INTO copy").is_err());
+    }
 }
@@ -0,0 +1,490 @@
+//! Reading SQLite database files.
+//!
+//! [`SqliteReader`] parses the SQLite file format directly: the 100-byte
+//! file header, the table B-trees with their overflow pages, and the
+//! records stored in them. It reads one page at a time, so files of any
+//! size can be read. [`Database::import_from_sqlite`](crate::Database::import_from_sqlite)
+//! uses it to copy the tables, rows, indexes, views, and triggers of a
+//! SQLite database into a Horizon one.
+//!
+//! The reader does not replay a write-ahead log: a database in WAL mode
+//! must be checkpointed first, and opening one whose `-wal` file still
+//! holds changes fails. `WITHOUT ROWID` and virtual tables are not
+//! supported.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::catalog::TableInfo;
+use crate::error::{HorizonError, Result};
+use crate::sql::parser::Parser;
+use crate::types::Value;
+use crate::Database;
+
+/// The first 16 bytes of every SQLite database file.
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// Size of the file header at the start of page 1.
+const FILE_HEADER_SIZE: usize = 100;
+
+/// B-tree page types, the first byte of a page's header.
+const INTERIOR_INDEX_PAGE: u8 = 0x02;
+const INTERIOR_TABLE_PAGE: u8 = 0x05;
+const LEAF_INDEX_PAGE: u8 = 0x0a;
+const LEAF_TABLE_PAGE: u8 = 0x0d;
+
+/// The deepest B-tree the reader follows; deeper ones are assumed to be
+/// corrupt (a cycle of child pointers, say). SQLite's own limit is 20.
+const MAX_TREE_DEPTH: usize = 64;
+
+/// How the text in a database is encoded (header offset 56).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// A row of the `sqlite_schema` table: a table, index, view, or trigger.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaEntry {
+    /// `table`, `index`, `view`, or `trigger`.
+    pub kind: String,
+    pub name: String,
+    /// The table an index or trigger belongs to; for tables and views,
+    /// the name itself.
+    pub table_name: String,
+    /// The root page of a table's or index's B-tree, or 0.
+    pub root_page: u32,
+    /// The statement that created the object, or `None` for the indexes
+    /// SQLite creates for UNIQUE and PRIMARY KEY constraints.
+    pub sql: Option<String>,
+}
+
+/// A reader of a SQLite database file.
+pub struct SqliteReader {
+    file: File,
+    page_size: usize,
+    /// Bytes of each page B-trees may use: the page size less the bytes
+    /// reserved at the end of every page.
+    usable_size: usize,
+    page_count: u32,
+    encoding: TextEncoding,
+}
+
+impl SqliteReader {
+    /// Open the SQLite database file at `path` and read its header.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let mut header = [0u8; FILE_HEADER_SIZE];
+        if file.read_exact(&mut header).is_err() || &header[..16] != MAGIC {
+            return Err(corrupt(format!("{} is not a SQLite database", path.display())));
+        }
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        if !page_size.is_power_of_two() || !(512..=65536).contains(&page_size) {
+            return Err(corrupt(format!("invalid SQLite page size: {}", page_size)));
+        }
+        let usable_size = page_size - header[20] as usize;
+        if usable_size < 480 {
+            return Err(corrupt("invalid SQLite reserved space".into()));
+        }
+        let encoding = match u32_at(&header, 56)? {
+            // A database without any content has no encoding yet
+            0 | 1 => TextEncoding::Utf8,
+            2 => TextEncoding::Utf16Le,
+            3 => TextEncoding::Utf16Be,
+            other => return Err(corrupt(format!("invalid SQLite text encoding: {}", other))),
+        };
+        // Read and write version 2 means WAL mode
+        if header[18] == 2 && wal_has_frames(path)? {
+            return Err(HorizonError::NotImplemented(format!(
+                "reading {} with changes in its write-ahead log; checkpoint it first",
+                path.display()
+            )));
+        }
+        // The page count in the header is only valid if the version it was
+        // written by matches the change counter
+        let file_pages = (file.metadata()?.len() / page_size as u64) as u32;
+        let page_count = match u32_at(&header, 28)? {
+            pages if pages > 0 && u32_at(&header, 92)? == u32_at(&header, 24)? => pages.min(file_pages),
+            _ => file_pages,
+        };
+        Ok(SqliteReader { file, page_size, usable_size, page_count, encoding })
+    }
+
+    /// The size of the database's pages in bytes.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// The number of pages in the database.
+    pub fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    /// The rows of the `sqlite_schema` table, in the order the objects
+    /// were created.
+    pub fn schema(&mut self) -> Result<Vec<SchemaEntry>> {
+        let mut entries = Vec::new();
+        self.scan_table(1, |_, values| {
+            let text = |value: Option<&Value>| match value {
+                Some(Value::Text(s)) => Ok(s.clone()),
+                _ => Err(corrupt("malformed sqlite_schema row".into())),
+            };
+            entries.push(SchemaEntry {
+                kind: text(values.first())?,
+                name: text(values.get(1))?,
+                table_name: text(values.get(2))?,
+                root_page: match values.get(3) {
+                    Some(Value::Integer(page)) => u32::try_from(*page).map_err(|_| corrupt("invalid root page".into()))?,
+                    _ => 0,
+                },
+                sql: match values.get(4) {
+                    Some(Value::Text(sql)) => Some(sql.clone()),
+                    _ => None,
+                },
+            });
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    /// Call `f` with the rowid and column values of each row of the table
+    /// whose B-tree is rooted at `root_page`, in rowid order.
+    ///
+    /// The values are those stored in the row's record: a column that is
+    /// an alias of the rowid (an `INTEGER PRIMARY KEY`) is stored as NULL,
+    /// and a row written before columns were added lacks them.
+    pub fn scan_table<F>(&mut self, root_page: u32, mut f: F) -> Result<()>
+    where
+        F: FnMut(i64, Vec<Value>) -> Result<()>,
+    {
+        self.scan_table_page(root_page, 0, &mut f)
+    }
+
+    fn scan_table_page(&mut self, number: u32, depth: usize, f: &mut dyn FnMut(i64, Vec<Value>) -> Result<()>) -> Result<()> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(corrupt("SQLite B-tree is too deep".into()));
+        }
+        let page = self.page(number)?;
+        let header = if number == 1 { FILE_HEADER_SIZE } else { 0 };
+        let page_type = *page.get(header).ok_or_else(|| corrupt("truncated page".into()))?;
+        let cell_count = u16_at(&page, header + 3)? as usize;
+        let cells_start = header + if page_type == LEAF_TABLE_PAGE { 8 } else { 12 };
+        let cell_offset = |i: usize| u16_at(&page, cells_start + 2 * i).map(usize::from);
+        match page_type {
+            LEAF_TABLE_PAGE => {
+                for i in 0..cell_count {
+                    let mut pos = cell_offset(i)?;
+                    let (payload_size, len) = varint(&page, pos)?;
+                    pos += len;
+                    let (rowid, len) = varint(&page, pos)?;
+                    pos += len;
+                    let payload = self.payload(&page, pos, payload_size as usize)?;
+                    f(rowid as i64, self.decode_record(&payload)?)?;
+                }
+                Ok(())
+            }
+            INTERIOR_TABLE_PAGE => {
+                for i in 0..cell_count {
+                    let child = u32_at(&page, cell_offset(i)?)?;
+                    self.scan_table_page(child, depth + 1, f)?;
+                }
+                let rightmost = u32_at(&page, header + 8)?;
+                self.scan_table_page(rightmost, depth + 1, f)
+            }
+            INTERIOR_INDEX_PAGE | LEAF_INDEX_PAGE => {
+                Err(HorizonError::NotImplemented("reading WITHOUT ROWID tables from SQLite databases".into()))
+            }
+            other => Err(corrupt(format!("invalid SQLite page type {:#04x} on page {}", other, number))),
+        }
+    }
+
+    /// The `size`-byte payload of a table leaf cell starting at `offset` in
+    /// `page`, including the part that spilled to overflow pages.
+    fn payload(&mut self, page: &[u8], offset: usize, size: usize) -> Result<Vec<u8>> {
+        let usable = self.usable_size;
+        // How much of the payload is stored on the page itself
+        let max_local = usable - 35;
+        let local = if size <= max_local {
+            size
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let local = min_local + (size - min_local) % (usable - 4);
+            if local <= max_local { local } else { min_local }
+        };
+        let mut payload = slice(page, offset, local)?.to_vec();
+        if local < size {
+            let mut next = u32_at(page, offset + local)?;
+            let mut pages = 0;
+            while payload.len() < size {
+                pages += 1;
+                if next == 0 || pages > self.page_count {
+                    return Err(corrupt("broken SQLite overflow chain".into()));
+                }
+                let overflow = self.page(next)?;
+                next = u32_at(&overflow, 0)?;
+                let take = (usable - 4).min(size - payload.len());
+                payload.extend_from_slice(slice(&overflow, 4, take)?);
+            }
+        }
+        Ok(payload)
+    }
+
+    /// The column values of a record.
+    fn decode_record(&self, record: &[u8]) -> Result<Vec<Value>> {
+        let (header_size, mut pos) = varint(record, 0)?;
+        let header_size = header_size as usize;
+        let mut body = header_size;
+        let mut values = Vec::new();
+        while pos < header_size {
+            let (serial_type, len) = varint(record, pos)?;
+            pos += len;
+            let value = match serial_type {
+                0 => Value::Null,
+                1..=6 => {
+                    let size = [1, 2, 3, 4, 6, 8][serial_type as usize - 1];
+                    let bytes = slice(record, body, size)?;
+                    body += size;
+                    // Big-endian two's complement, sign-extended
+                    let first = bytes[0] as i8 as i64;
+                    Value::Integer(bytes[1..].iter().fold(first, |n, &b| (n << 8) | b as i64))
+                }
+                7 => {
+                    let bytes = slice(record, body, 8)?;
+                    body += 8;
+                    Value::Real(f64::from_be_bytes(bytes.try_into().expect("8 bytes")))
+                }
+                8 => Value::Integer(0),
+                9 => Value::Integer(1),
+                10 | 11 => return Err(corrupt("reserved SQLite serial type".into())),
+                n => {
+                    let size = ((n - 12) / 2) as usize;
+                    let bytes = slice(record, body, size)?;
+                    body += size;
+                    if n % 2 == 0 {
+                        Value::Blob(bytes.to_vec())
+                    } else {
+                        Value::Text(self.decode_text(bytes))
+                    }
+                }
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    fn decode_text(&self, bytes: &[u8]) -> String {
+        let utf16 = |from: fn([u8; 2]) -> u16| {
+            let units: Vec<u16> = bytes.chunks_exact(2).map(|c| from([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        };
+        match self.encoding {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            TextEncoding::Utf16Le => utf16(u16::from_le_bytes),
+            TextEncoding::Utf16Be => utf16(u16::from_be_bytes),
+        }
+    }
+
+    /// Read page `number`, counting from 1.
+    fn page(&mut self, number: u32) -> Result<Vec<u8>> {
+        if number == 0 || number > self.page_count {
+            return Err(corrupt(format!("SQLite page {} is out of range", number)));
+        }
+        let mut page = vec![0; self.page_size];
+        self.file.seek(SeekFrom::Start((number as u64 - 1) * self.page_size as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(page)
+    }
+}
+
+/// Copy the tables, rows, indexes, views, and triggers of the SQLite
+/// database at `path` into `db`.
+///
+/// Tables are created from their SQLite `CREATE TABLE` statements, so
+/// these must be statements Horizon understands; so must the statements of
+/// the indexes, views, and triggers, which are created after the rows are
+/// copied. Every statement is parsed before anything is created. The rows
+/// are copied in a single transaction; if the import fails, it is rolled
+/// back and the objects created so far are dropped again, since ROLLBACK
+/// does not undo them. SQLite's internal tables, such as
+/// `sqlite_sequence`, are not copied.
+pub(crate) fn import(db: &Database, path: &Path) -> Result<()> {
+    let mut reader = SqliteReader::open(path)?;
+    let schema: Vec<SchemaEntry> = reader
+        .schema()?
+        .into_iter()
+        .filter(|entry| !entry.name.to_ascii_lowercase().starts_with("sqlite_"))
+        .collect();
+    check_schema(&schema)?;
+    let mut created = Vec::new();
+    db.execute("BEGIN")?;
+    match import_schema(db, &mut reader, &schema, &mut created).and_then(|_| db.execute("COMMIT")) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            // The transaction may already have ended
+            let _ = db.execute("ROLLBACK");
+            // Dropping a table also drops its indexes and triggers
+            for entry in created.iter().rev() {
+                let _ = db.execute(&format!("DROP {} IF EXISTS {}", entry.kind, quote_identifier(&entry.name)));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Fail if a statement of `schema` is one Horizon cannot run, before any
+/// of them is.
+fn check_schema(schema: &[SchemaEntry]) -> Result<()> {
+    for entry in schema {
+        if entry.kind == "table" {
+            if entry.sql.is_none() {
+                return Err(corrupt(format!("table {} has no schema", entry.name)));
+            }
+            if entry.root_page == 0 {
+                return Err(HorizonError::NotImplemented(format!("importing SQLite virtual table {}", entry.name)));
+            }
+        }
+        if let Some(sql) = &entry.sql {
+            Parser::parse(sql)?;
+        }
+    }
+    Ok(())
+}
+
+/// Create the objects of `schema` in `db` and copy the rows of its tables,
+/// adding each object to `created` once it exists.
+fn import_schema<'a>(
+    db: &Database,
+    reader: &mut SqliteReader,
+    schema: &'a [SchemaEntry],
+    created: &mut Vec<&'a SchemaEntry>,
+) -> Result<()> {
+    for entry in schema.iter().filter(|entry| entry.kind == "table") {
+        db.execute(entry.sql.as_deref().unwrap_or_default())?;
+        created.push(entry);
+        let table = db.read()?.catalog.get_table(&entry.name)?.clone();
+        import_rows(db, reader, entry.root_page, &table)?;
+    }
+    for kind in ["index", "view", "trigger"] {
+        for entry in schema.iter().filter(|entry| entry.kind == kind) {
+            if let Some(sql) = &entry.sql {
+                db.execute(sql)?;
+                created.push(entry);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Insert the rows of the SQLite table rooted at `root_page` into `table`.
+fn import_rows(db: &Database, reader: &mut SqliteReader, root_page: u32, table: &TableInfo) -> Result<()> {
+    // Records hold every column but the virtual generated ones; generated
+    // columns are computed again on insert
+    let stored: Vec<usize> = (0..table.columns.len())
+        .filter(|&i| table.columns[i].generated_expr.is_none() || table.columns[i].is_stored)
+        .collect();
+    let inserted: Vec<usize> = (0..table.columns.len()).filter(|&i| table.columns[i].generated_expr.is_none()).collect();
+    if inserted.is_empty() {
+        return Ok(());
+    }
+    // An INTEGER PRIMARY KEY column is stored as NULL and read from the rowid
+    let rowid_alias = match table.pk_columns.as_slice() {
+        [pk] if table.columns[*pk].type_name.eq_ignore_ascii_case("INTEGER") => Some(*pk),
+        _ => None,
+    };
+    let names: Vec<String> = inserted.iter().map(|&i| quote_identifier(&table.columns[i].name)).collect();
+    let insert = db.prepare(&format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote_identifier(&table.name),
+        names.join(", "),
+        vec!["?"; names.len()].join(", ")
+    ))?;
+    reader.scan_table(root_page, |rowid, record| {
+        let mut row: Vec<Value> = table.columns.iter().map(|c| c.default_value.clone().unwrap_or(Value::Null)).collect();
+        // A row written before columns were added has no values for them
+        for (&column, value) in stored.iter().zip(record) {
+            row[column] = value;
+        }
+        if let Some(pk) = rowid_alias {
+            if row[pk].is_null() {
+                row[pk] = Value::Integer(rowid);
+            }
+        }
+        let params: Vec<Value> = inserted.iter().map(|&i| std::mem::replace(&mut row[i], Value::Null)).collect();
+        insert.execute(db, &params)?;
+        Ok(())
+    })
+}
+
+/// Whether the write-ahead log of the database at `path` holds any frames.
+fn wal_has_frames(path: &Path) -> Result<bool> {
+    let mut wal = PathBuf::from(path);
+    wal.as_mut_os_string().push("-wal");
+    match std::fs::metadata(&wal) {
+        // A log holding only its 32-byte header has no changes
+        Ok(metadata) => Ok(metadata.len() > 32),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn corrupt(message: String) -> HorizonError {
+    HorizonError::CorruptDatabase(message)
+}
+
+/// The `len` bytes of `data` at `offset`.
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| corrupt("SQLite record or page is truncated".into()))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(slice(data, offset, 2)?.try_into().expect("2 bytes")))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(slice(data, offset, 4)?.try_into().expect("4 bytes")))
+}
+
+/// The SQLite varint at `offset` in `data` and its length in bytes: up to
+/// eight bytes of seven bits each, high bit set on all but the last, and a
+/// ninth byte contributing all eight bits.
+fn varint(data: &[u8], offset: usize) -> Result<(u64, usize)> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data.get(offset + i).ok_or_else(|| corrupt("truncated SQLite varint".into()))?;
+        if i == 8 {
+            return Ok(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    unreachable!("a varint has at most nine bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_decode() {
+        assert_eq!(varint(&[0x05], 0).unwrap(), (5, 1));
+        assert_eq!(varint(&[0x81, 0x00], 0).unwrap(), (128, 2));
+        assert_eq!(varint(&[0xff; 9], 0).unwrap(), (u64::MAX, 9));
+        assert!(varint(&[0x81], 0).is_err());
+    }
+}
@@ -14,6 +14,7 @@
 //!   algorithm.
 //! - Serialization and deserialization of values to and from a compact binary
 //!   encoding suitable for on-disk storage and network transport.
+//! - [`FromSql`] and [`ToSql`]: conversions between [`Value`] and Rust types.
 
 use std::cmp::Ordering;
 use std::fmt;
@@ -405,6 +406,100 @@ impl Value {
         }
     }
 
+    /// Appends an order-preserving encoding of this value to `buf`, for use
+    /// in index keys.
+    ///
+    /// Comparing two encodings byte-wise gives the same order as comparing
+    /// the values with [`Ord`], and no encoding is a prefix of another, so
+    /// encodings of several values can be concatenated into a composite key.
+    /// INTEGER and REAL share one numeric encoding (as an order-mapped
+    /// `f64`), so integers beyond 2^53 may encode equal to their neighbours.
+    /// [`sortable_len`](Self::sortable_len) splits a composite key into its
+    /// parts, and [`deserialize_sortable`](Self::deserialize_sortable)
+    /// decodes them up to that loss of type.
+    pub fn serialize_sortable(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => buf.push(0),
+            Value::Integer(i) => {
+                buf.push(1);
+                buf.extend_from_slice(&sortable_f64_bits(*i as f64).to_be_bytes());
+            }
+            Value::Real(r) => {
+                buf.push(1);
+                buf.extend_from_slice(&sortable_f64_bits(*r).to_be_bytes());
+            }
+            Value::Text(s) => {
+                buf.push(2);
+                escape_sortable_bytes(s.as_bytes(), buf);
+            }
+            Value::Blob(b) => {
+                buf.push(3);
+                escape_sortable_bytes(b, buf);
+            }
+        }
+    }
+
+    /// Returns the length of the [`serialize_sortable`](Self::serialize_sortable)
+    /// encoding at the start of `data`, or `None` if `data` does not start
+    /// with a complete encoding.
+    pub fn sortable_len(data: &[u8]) -> Option<usize> {
+        match data.first()? {
+            0 => Some(1),
+            1 if data.len() >= 9 => Some(9),
+            2 | 3 => {
+                // Escaped bytes run up to the `00 00` terminator; a zero
+                // byte inside the value is written as `00 FF`.
+                let mut i = 1;
+                while i < data.len() {
+                    if data[i] == 0 {
+                        match data.get(i + 1)? {
+                            0 => return Some(i + 2),
+                            _ => i += 2,
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes the [`serialize_sortable`](Self::serialize_sortable)
+    /// encoding at the start of `data`, returning the value and the number
+    /// of bytes consumed, or `None` if `data` does not start with a complete
+    /// encoding.
+    ///
+    /// Numbers decode as [`Value::Real`], since the encoding does not record
+    /// whether they were integers, and `-0.0` decodes as `0.0`.
+    pub fn deserialize_sortable(data: &[u8]) -> Option<(Value, usize)> {
+        let len = Value::sortable_len(data)?;
+        let value = match data[0] {
+            0 => Value::Null,
+            1 => {
+                let bits = u64::from_be_bytes(data[1..9].try_into().ok()?);
+                let bits = if bits >> 63 == 1 { bits & !(1 << 63) } else { !bits };
+                Value::Real(f64::from_bits(bits))
+            }
+            tag => {
+                // Drop the terminator and unescape `00 FF` back to `00`.
+                let mut bytes = Vec::with_capacity(len - 3);
+                let mut i = 1;
+                while i < len - 2 {
+                    bytes.push(data[i]);
+                    i += if data[i] == 0 { 2 } else { 1 };
+                }
+                if tag == 2 {
+                    Value::Text(String::from_utf8(bytes).ok()?)
+                } else {
+                    Value::Blob(bytes)
+                }
+            }
+        };
+        Some((value, len))
+    }
+
     /// Deserializes a value from its binary representation.
     ///
     /// Returns the decoded [`Value`] together with the number of bytes
@@ -728,6 +823,30 @@ impl Ord for Value {
     }
 }
 
+/// Map an `f64` to a `u64` whose unsigned order matches the numeric order.
+fn sortable_f64_bits(r: f64) -> u64 {
+    // Fold -0.0 into 0.0 so that equal values encode identically.
+    let r = if r == 0.0 { 0.0 } else { r };
+    let bits = r.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    }
+}
+
+/// Write `data` with each 0x00 byte escaped as `00 FF`, followed by the
+/// terminator `00 00`, keeping byte order and making the result prefix-free.
+fn escape_sortable_bytes(data: &[u8], buf: &mut Vec<u8>) {
+    for &b in data {
+        buf.push(b);
+        if b == 0 {
+            buf.push(0xFF);
+        }
+    }
+    buf.extend_from_slice(&[0, 0]);
+}
+
 /// Compares two `f64` values with a total ordering.
 ///
 /// NaN is treated as equal to NaN and greater than all other values (for a
@@ -837,6 +956,231 @@ impl From<bool> for Value {
     }
 }
 
+// ---------------------------------------------------------------------------
+// FromSql / ToSql
+// ---------------------------------------------------------------------------
+
+/// Conversion from a SQL [`Value`] into a Rust type.
+///
+/// Conversions are strict about storage class: an integer column converts
+/// to the integer types, `bool`, and the float types, but text is never
+/// parsed as a number. NULL converts only into `Option<T>` (as `None`).
+pub trait FromSql: Sized {
+    /// Convert `v`, failing with [`HorizonError::TypeError`] if its storage
+    /// class does not fit `Self`.
+    fn from_sql(v: &Value) -> Result<Self>;
+}
+
+/// Conversion from a Rust type into a SQL [`Value`].
+pub trait ToSql {
+    /// The SQL value representing `self`.
+    fn to_sql(&self) -> Value;
+}
+
+fn type_mismatch(v: &Value, target: &str) -> HorizonError {
+    let found = v.data_type().map_or_else(|| "NULL".to_string(), |t| t.to_string());
+    HorizonError::TypeError(format!("cannot convert {} to {}", found, target))
+}
+
+macro_rules! impl_sql_integer {
+    ($($t:ty),*) => {$(
+        impl FromSql for $t {
+            fn from_sql(v: &Value) -> Result<Self> {
+                match v {
+                    Value::Integer(i) => <$t>::try_from(*i).map_err(|_| {
+                        HorizonError::Overflow(format!("{} does not fit in {}", i, stringify!($t)))
+                    }),
+                    _ => Err(type_mismatch(v, stringify!($t))),
+                }
+            }
+        }
+
+        impl ToSql for $t {
+            fn to_sql(&self) -> Value {
+                Value::Integer(*self as i64)
+            }
+        }
+    )*};
+}
+
+impl_sql_integer!(i8, i16, i32, i64, u8, u16, u32);
+
+impl FromSql for u64 {
+    fn from_sql(v: &Value) -> Result<Self> {
+        match v {
+            Value::Integer(i) => u64::try_from(*i)
+                .map_err(|_| HorizonError::Overflow(format!("{} does not fit in u64", i))),
+            _ => Err(type_mismatch(v, "u64")),
+        }
+    }
+}
+
+impl ToSql for u64 {
+    /// Values above `i64::MAX` do not fit an INTEGER and are stored as REAL.
+    fn to_sql(&self) -> Value {
+        i64::try_from(*self).map_or(Value::Real(*self as f64), Value::Integer)
+    }
+}
+
+impl FromSql for f64 {
+    fn from_sql(v: &Value) -> Result<Self> {
+        match v {
+            Value::Real(r) => Ok(*r),
+            Value::Integer(i) => Ok(*i as f64),
+            _ => Err(type_mismatch(v, "f64")),
+        }
+    }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> Value {
+        Value::Real(*self)
+    }
+}
+
+impl FromSql for f32 {
+    fn from_sql(v: &Value) -> Result<Self> {
+        f64::from_sql(v).map(|r| r as f32).map_err(|_| type_mismatch(v, "f32"))
+    }
+}
+
+impl ToSql for f32 {
+    fn to_sql(&self) -> Value {
+        Value::Real(*self as f64)
+    }
+}
+
+impl FromSql for bool {
+    fn from_sql(v: &Value) -> Result<Self> {
+        match v {
+            Value::Integer(i) => Ok(*i != 0),
+            _ => Err(type_mismatch(v, "bool")),
+        }
+    }
+}
+
+impl ToSql for bool {
+    fn to_sql(&self) -> Value {
+        Value::from(*self)
+    }
+}
+
+impl FromSql for String {
+    fn from_sql(v: &Value) -> Result<Self> {
+        match v {
+            Value::Text(s) => Ok(s.clone()),
+            _ => Err(type_mismatch(v, "String")),
+        }
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl FromSql for Vec<u8> {
+    fn from_sql(v: &Value) -> Result<Self> {
+        match v {
+            Value::Blob(b) => Ok(b.clone()),
+            _ => Err(type_mismatch(v, "Vec<u8>")),
+        }
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> Value {
+        Value::Blob(self.clone())
+    }
+}
+
+impl ToSql for [u8] {
+    fn to_sql(&self) -> Value {
+        Value::Blob(self.to_vec())
+    }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_sql(v: &Value) -> Result<Self> {
+        match v {
+            Value::Null => Ok(None),
+            _ => T::from_sql(v).map(Some),
+        }
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToSql::to_sql)
+    }
+}
+
+impl FromSql for Value {
+    fn from_sql(v: &Value) -> Result<Self> {
+        Ok(v.clone())
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn to_sql(&self) -> Value {
+        (**self).to_sql()
+    }
+}
+
+/// Dates are stored as TEXT in the formats produced by the `date()` and
+/// `datetime()` SQL functions.
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use super::{type_mismatch, FromSql, ToSql, Value};
+    use crate::error::Result;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    impl FromSql for NaiveDate {
+        fn from_sql(v: &Value) -> Result<Self> {
+            v.as_text()
+                .and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+                .ok_or_else(|| type_mismatch(v, "NaiveDate"))
+        }
+    }
+
+    impl ToSql for NaiveDate {
+        fn to_sql(&self) -> Value {
+            Value::Text(self.format("%Y-%m-%d").to_string())
+        }
+    }
+
+    impl FromSql for NaiveDateTime {
+        fn from_sql(v: &Value) -> Result<Self> {
+            v.as_text()
+                .and_then(|s| {
+                    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+                        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f"))
+                        .ok()
+                })
+                .ok_or_else(|| type_mismatch(v, "NaiveDateTime"))
+        }
+    }
+
+    impl ToSql for NaiveDateTime {
+        fn to_sql(&self) -> Value {
+            Value::Text(self.format("%Y-%m-%d %H:%M:%S%.f").to_string())
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -1350,6 +1694,108 @@ mod tests {
         assert_eq!(offset, buffer.len());
     }
 
+    #[test]
+    fn test_serialize_sortable_preserves_order() {
+        let values = vec![
+            Value::Null,
+            Value::Real(f64::NEG_INFINITY),
+            Value::Integer(-1000),
+            Value::Real(-1.5),
+            Value::Integer(-1),
+            Value::Integer(0),
+            Value::Real(0.5),
+            Value::Integer(1),
+            Value::Integer(i64::MAX),
+            Value::Text(String::new()),
+            Value::Text("a".into()),
+            Value::Text("a\0b".into()),
+            Value::Text("aa".into()),
+            Value::Text("b".into()),
+            Value::Blob(vec![]),
+            Value::Blob(vec![0]),
+            Value::Blob(vec![0, 0]),
+            Value::Blob(vec![1]),
+        ];
+        let encode = |v: &Value| {
+            let mut buf = Vec::new();
+            v.serialize_sortable(&mut buf);
+            buf
+        };
+        for pair in values.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(encode(&pair[0]) < encode(&pair[1]), "{:?} vs {:?}", pair[0], pair[1]);
+        }
+        assert_eq!(encode(&Value::Integer(3)), encode(&Value::Real(3.0)));
+        assert_eq!(encode(&Value::Real(-0.0)), encode(&Value::Real(0.0)));
+
+        // Composite keys order by their first differing component
+        let key = |a: &Value, b: &Value| {
+            let mut buf = Vec::new();
+            a.serialize_sortable(&mut buf);
+            b.serialize_sortable(&mut buf);
+            buf
+        };
+        let a = Value::Text("a".into());
+        let aa = Value::Text("aa".into());
+        assert!(key(&a, &Value::Integer(9)) < key(&aa, &Value::Integer(0)));
+    }
+
+    #[test]
+    fn test_sortable_len_splits_composite_keys() {
+        let values = [
+            Value::Null,
+            Value::Integer(7),
+            Value::Text("a\0b".into()),
+            Value::Blob(vec![0, 0xFF, 0]),
+            Value::Text(String::new()),
+        ];
+        let mut key = Vec::new();
+        for v in &values {
+            v.serialize_sortable(&mut key);
+        }
+        let mut rest = key.as_slice();
+        for v in &values {
+            let mut one = Vec::new();
+            v.serialize_sortable(&mut one);
+            let len = Value::sortable_len(rest).unwrap();
+            assert_eq!(&rest[..len], one.as_slice(), "{:?}", v);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(Value::sortable_len(&[2, b'a', 0]), None);
+        assert_eq!(Value::sortable_len(&[1, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_deserialize_sortable_round_trips() {
+        let values = [
+            Value::Null,
+            Value::Real(-2.5),
+            Value::Real(0.0),
+            Value::Real(1e300),
+            Value::Real(f64::NEG_INFINITY),
+            Value::Text("a\0b".into()),
+            Value::Text(String::new()),
+            Value::Blob(vec![0, 0xFF, 0]),
+        ];
+        let mut key = Vec::new();
+        for v in &values {
+            v.serialize_sortable(&mut key);
+        }
+        let mut rest = key.as_slice();
+        for v in &values {
+            let (decoded, len) = Value::deserialize_sortable(rest).unwrap();
+            assert_eq!(&decoded, v);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+
+        let mut int = Vec::new();
+        Value::Integer(-7).serialize_sortable(&mut int);
+        assert_eq!(Value::deserialize_sortable(&int), Some((Value::Real(-7.0), 9)));
+        assert_eq!(Value::deserialize_sortable(&[3, 1, 0]), None);
+    }
+
     // -- DataType Display ---------------------------------------------------
 
     #[test]
@@ -1360,4 +1806,49 @@ mod tests {
         assert_eq!(format!("{}", DataType::Blob), "BLOB");
         assert_eq!(format!("{}", DataType::Numeric), "NUMERIC");
     }
+
+    // -- FromSql / ToSql ----------------------------------------------------
+
+    #[test]
+    fn test_from_sql_conversions() {
+        assert_eq!(i32::from_sql(&Value::Integer(-5)).unwrap(), -5);
+        assert_eq!(f64::from_sql(&Value::Integer(2)).unwrap(), 2.0);
+        assert!(bool::from_sql(&Value::Integer(1)).unwrap());
+        assert_eq!(String::from_sql(&Value::Text("hi".into())).unwrap(), "hi");
+        assert_eq!(Vec::<u8>::from_sql(&Value::Blob(vec![1, 2])).unwrap(), vec![1, 2]);
+        assert_eq!(Option::<i64>::from_sql(&Value::Null).unwrap(), None);
+        assert_eq!(Option::<i64>::from_sql(&Value::Integer(3)).unwrap(), Some(3));
+
+        assert!(matches!(i64::from_sql(&Value::Null), Err(HorizonError::TypeError(_))));
+        assert!(matches!(i64::from_sql(&Value::Text("1".into())), Err(HorizonError::TypeError(_))));
+        assert!(matches!(u8::from_sql(&Value::Integer(256)), Err(HorizonError::Overflow(_))));
+        assert!(matches!(u64::from_sql(&Value::Integer(-1)), Err(HorizonError::Overflow(_))));
+    }
+
+    #[test]
+    fn test_to_sql_conversions() {
+        assert_eq!(7i16.to_sql(), Value::Integer(7));
+        assert_eq!(u64::MAX.to_sql(), Value::Real(u64::MAX as f64));
+        assert_eq!(1.5f32.to_sql(), Value::Real(1.5));
+        assert_eq!(true.to_sql(), Value::Integer(1));
+        assert_eq!("a".to_sql(), Value::Text("a".into()));
+        assert_eq!(b"ab"[..].to_sql(), Value::Blob(vec![b'a', b'b']));
+        assert_eq!(None::<i32>.to_sql(), Value::Null);
+        assert_eq!(Some("x".to_string()).to_sql(), Value::Text("x".into()));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono_conversions() {
+        use chrono::{NaiveDate, NaiveDateTime};
+
+        let date = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(date.to_sql(), Value::Text("2024-02-29".into()));
+        assert_eq!(NaiveDate::from_sql(&date.to_sql()).unwrap(), date);
+
+        let dt = date.and_hms_opt(13, 5, 9).unwrap();
+        assert_eq!(dt.to_sql(), Value::Text("2024-02-29 13:05:09".into()));
+        assert_eq!(NaiveDateTime::from_sql(&Value::Text("2024-02-29T13:05:09".into())).unwrap(), dt);
+        assert!(NaiveDate::from_sql(&Value::Integer(1)).is_err());
+    }
 }
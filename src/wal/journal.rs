@@ -0,0 +1,320 @@
+//! # Rollback Journal
+//!
+//! In the `Delete` and `Memory` journal modes changed pages are written
+//! straight to the database file. Before a page is first overwritten in a
+//! transaction its original image is saved in the rollback journal, so
+//! the file can be put back as it was if the transaction does not finish.
+//!
+//! A transaction starts with the first page write after a commit and
+//! commits when the pager is synced. The `Delete` journal lives in a
+//! `.hdb-journal` file beside the database that is deleted on commit; a
+//! journal still present when the database is opened is "hot" and is
+//! rolled back. The `Memory` journal keeps the images in memory, so it
+//! only protects against failures the process survives.
+//!
+//! ## Journal File Format
+//!
+//! ### Header (24 bytes)
+//! - `[0..16]`: Magic bytes `b"HorizonJrnl v01\x00"`
+//! - `[16..20]`: Page size (u32 big-endian)
+//! - `[20..24]`: Page count of the database before the transaction (u32 big-endian)
+//!
+//! ### Record (page size + 4 bytes each)
+//! - `[0..4]`: Page number (u32 big-endian)
+//! - `[4..4+page size]`: Original page data
+
+use super::{sync_directory, JournalMode, SynchronousMode};
+use crate::error::{HorizonError, Result};
+use crate::pager::{PageId, PageStorage};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_MAGIC: &[u8; 16] = b"HorizonJrnl v01\x00";
+const JOURNAL_HEADER_SIZE: usize = 24;
+
+/// Path of the rollback journal for the database at `db_path`.
+pub fn journal_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("hdb-journal")
+}
+
+/// Where the original page images are kept.
+#[derive(Debug)]
+enum Store {
+    /// A journal file, created when a transaction starts.
+    File { path: PathBuf, file: Option<File> },
+    /// Page images held in memory.
+    Memory(Vec<(PageId, Vec<u8>)>),
+}
+
+/// The rollback journal of a database in `Delete` or `Memory` mode.
+#[derive(Debug)]
+pub struct RollbackJournal {
+    store: Store,
+    /// Page count of the database when the open transaction started, or
+    /// `None` outside a transaction.
+    original_page_count: Option<u32>,
+    /// Pages whose original image is already saved.
+    saved: HashSet<PageId>,
+    /// Whether journal writes are synced before the page they protect is
+    /// overwritten.
+    synchronous: SynchronousMode,
+}
+
+impl RollbackJournal {
+    /// A journal kept in the `.hdb-journal` file beside `db_path`.
+    pub fn file(db_path: &Path) -> Self {
+        Self::with_store(Store::File { path: journal_path(db_path), file: None })
+    }
+
+    /// A journal kept in memory.
+    pub fn memory() -> Self {
+        Self::with_store(Store::Memory(Vec::new()))
+    }
+
+    fn with_store(store: Store) -> Self {
+        RollbackJournal {
+            store,
+            original_page_count: None,
+            saved: HashSet::new(),
+            synchronous: SynchronousMode::default(),
+        }
+    }
+
+    /// The journal mode this journal implements.
+    pub fn mode(&self) -> JournalMode {
+        match self.store {
+            Store::File { .. } => JournalMode::Delete,
+            Store::Memory(_) => JournalMode::Memory,
+        }
+    }
+
+    /// Set when journal writes are synced; only `Off` skips syncing.
+    pub fn set_synchronous(&mut self, mode: SynchronousMode) {
+        self.synchronous = mode;
+    }
+
+    /// Whether a transaction has saved pages since the last commit.
+    pub fn in_transaction(&self) -> bool {
+        self.original_page_count.is_some()
+    }
+
+    /// Save the current image of `page_id` from `storage` before it is
+    /// overwritten, starting a transaction if none is open.
+    ///
+    /// Pages past the end of the database at the start of the transaction
+    /// have no original image and are not saved.
+    pub fn save(
+        &mut self,
+        storage: &dyn PageStorage,
+        page_size: usize,
+        page_count: u32,
+        page_id: PageId,
+    ) -> Result<()> {
+        let original_page_count = match self.original_page_count {
+            Some(count) => count,
+            None => {
+                self.begin(page_size, page_count)?;
+                page_count
+            }
+        };
+        if page_id >= original_page_count || !self.saved.insert(page_id) {
+            return Ok(());
+        }
+
+        let mut data = vec![0u8; page_size];
+        storage.read_at(page_id as u64 * page_size as u64, &mut data)?;
+        match self.store {
+            Store::File { ref mut file, .. } => {
+                let file = file
+                    .as_mut()
+                    .ok_or_else(|| HorizonError::Internal("journal file not open".into()))?;
+                file.write_all(&page_id.to_be_bytes())?;
+                file.write_all(&data)?;
+                if self.synchronous != SynchronousMode::Off {
+                    file.sync_data()?;
+                }
+            }
+            Store::Memory(ref mut pages) => pages.push((page_id, data)),
+        }
+        Ok(())
+    }
+
+    /// Start a transaction over a database of `page_count` pages.
+    fn begin(&mut self, page_size: usize, page_count: u32) -> Result<()> {
+        if let Store::File { ref path, ref mut file } = self.store {
+            let mut journal = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            let mut header = [0u8; JOURNAL_HEADER_SIZE];
+            header[0..16].copy_from_slice(JOURNAL_MAGIC);
+            header[16..20].copy_from_slice(&(page_size as u32).to_be_bytes());
+            header[20..24].copy_from_slice(&page_count.to_be_bytes());
+            journal.write_all(&header)?;
+            if self.synchronous != SynchronousMode::Off {
+                journal.sync_all()?;
+            }
+            if self.synchronous == SynchronousMode::Extra {
+                sync_directory(path)?;
+            }
+            *file = Some(journal);
+        }
+        self.original_page_count = Some(page_count);
+        Ok(())
+    }
+
+    /// Commit the open transaction by discarding the saved pages. The
+    /// database file must already be synced.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.original_page_count.take().is_none() {
+            return Ok(());
+        }
+        self.saved.clear();
+        match self.store {
+            Store::File { ref path, ref mut file } => {
+                drop(file.take());
+                std::fs::remove_file(path)?;
+                if self.synchronous == SynchronousMode::Extra {
+                    sync_directory(path)?;
+                }
+            }
+            Store::Memory(ref mut pages) => pages.clear(),
+        }
+        Ok(())
+    }
+
+    /// Write the saved pages back to `storage` and end the transaction.
+    /// Returns `false` if no transaction was open.
+    pub fn rollback(&mut self, storage: &mut dyn PageStorage) -> Result<bool> {
+        if self.original_page_count.is_none() {
+            return Ok(false);
+        }
+        match self.store {
+            Store::File { ref path, .. } => {
+                restore_file(path, storage)?;
+            }
+            Store::Memory(ref pages) => {
+                for (page_id, data) in pages {
+                    storage.write_at(*page_id as u64 * data.len() as u64, data)?;
+                }
+                storage.sync()?;
+            }
+        }
+        self.commit()?;
+        Ok(true)
+    }
+
+    /// Roll back a hot journal left at `path` by a writer that did not
+    /// commit, and delete it. Returns whether there was one.
+    pub fn recover(path: &Path, storage: &mut dyn PageStorage) -> Result<bool> {
+        if !path.exists() {
+            return Ok(false);
+        }
+        restore_file(path, storage)?;
+        std::fs::remove_file(path)?;
+        Ok(true)
+    }
+}
+
+/// Copy every complete record of the journal file at `path` back into
+/// `storage` and sync it. A journal whose header was never completely
+/// written protects no pages and is ignored.
+fn restore_file(path: &Path, storage: &mut dyn PageStorage) -> Result<()> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes.len() < JOURNAL_HEADER_SIZE {
+        return Ok(());
+    }
+    if &bytes[0..16] != JOURNAL_MAGIC {
+        return Err(HorizonError::CorruptDatabase("invalid journal magic bytes".into()));
+    }
+    let page_size = u32::from_be_bytes(bytes[16..20].try_into().unwrap()) as usize;
+    // Only records synced before their page was overwritten matter; a
+    // torn last record never reached the database file
+    for record in bytes[JOURNAL_HEADER_SIZE..].chunks_exact(4 + page_size) {
+        let page_id = u32::from_be_bytes(record[0..4].try_into().unwrap());
+        storage.write_at(page_id as u64 * page_size as u64, &record[4..])?;
+    }
+    storage.sync()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PAGE: usize = 512;
+
+    /// A three-page database file whose pages are filled with 1, 2, and 3.
+    fn setup() -> (TempDir, PathBuf, File) {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.hdb");
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&db_path).unwrap();
+        for fill in 1..=3u8 {
+            file.write_all(&[fill; PAGE]).unwrap();
+        }
+        (dir, db_path, file)
+    }
+
+    fn page(storage: &File, page_id: PageId) -> Vec<u8> {
+        let mut data = vec![0u8; PAGE];
+        storage.read_at(page_id as u64 * PAGE as u64, &mut data).unwrap();
+        data
+    }
+
+    fn overwrite(journal: &mut RollbackJournal, storage: &mut File, page_id: PageId, fill: u8) {
+        journal.save(&*storage, PAGE, 3, page_id).unwrap();
+        storage.write_at(page_id as u64 * PAGE as u64, &[fill; PAGE]).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_original_pages() {
+        for in_memory in [true, false] {
+            let (_dir, db_path, mut storage) = setup();
+            let mut journal = match in_memory {
+                true => RollbackJournal::memory(),
+                false => RollbackJournal::file(&db_path),
+            };
+            overwrite(&mut journal, &mut storage, 1, 9);
+            overwrite(&mut journal, &mut storage, 1, 8);
+            overwrite(&mut journal, &mut storage, 3, 7);
+            assert!(journal.in_transaction());
+            assert!(journal.rollback(&mut storage).unwrap());
+            assert_eq!(page(&storage, 1), vec![2u8; PAGE]);
+            assert!(!journal.in_transaction());
+            assert!(!journal_path(&db_path).exists());
+        }
+    }
+
+    #[test]
+    fn commit_deletes_the_journal_file() {
+        let (_dir, db_path, mut storage) = setup();
+        let mut journal = RollbackJournal::file(&db_path);
+        overwrite(&mut journal, &mut storage, 0, 9);
+        assert!(journal_path(&db_path).exists());
+        journal.commit().unwrap();
+        assert!(!journal_path(&db_path).exists());
+        assert!(!journal.rollback(&mut storage).unwrap());
+        assert_eq!(page(&storage, 0), vec![9u8; PAGE]);
+    }
+
+    #[test]
+    fn hot_journal_is_rolled_back() {
+        let (_dir, db_path, mut storage) = setup();
+        let mut journal = RollbackJournal::file(&db_path);
+        overwrite(&mut journal, &mut storage, 0, 9);
+        overwrite(&mut journal, &mut storage, 2, 9);
+        // The writer dies without committing
+        drop(journal);
+
+        let path = journal_path(&db_path);
+        assert!(RollbackJournal::recover(&path, &mut storage).unwrap());
+        assert_eq!(page(&storage, 0), vec![1u8; PAGE]);
+        assert_eq!(page(&storage, 2), vec![3u8; PAGE]);
+        assert!(!path.exists());
+        assert!(!RollbackJournal::recover(&path, &mut storage).unwrap());
+    }
+}
@@ -8,30 +8,173 @@
 //! The WAL file (`.hdb-wal`) consists of a header followed by frames.
 //!
 //! ### WAL Header (32 bytes)
-//! - [0..16]: Magic bytes `b"HorizonWAL v01\x00\x00"`
-//! - [16..20]: Page size (u32 big-endian)
-//! - [20..24]: Checkpoint sequence number (u32 big-endian)
-//! - [24..32]: Salt (two u32 values for integrity checking)
+//! - `[0..16]`: Magic bytes `b"HorizonWAL v01\x00\x00"`
+//! - `[16..20]`: Page size (u32 big-endian)
+//! - `[20..24]`: Checkpoint sequence number (u32 big-endian)
+//! - `[24..32]`: Salt (two u32 values for integrity checking)
 //!
-//! ### WAL Frame (PAGE_SIZE + 24 bytes each)
-//! - [0..4]: Page number (u32 big-endian)
-//! - [4..8]: Size of database in pages after commit (u32 big-endian, 0 if not a commit frame)
-//! - [8..16]: Transaction ID (u64 big-endian)
-//! - [16..20]: Checksum part 1 (u32)
-//! - [20..24]: Checksum part 2 (u32)
-//! - [24..24+PAGE_SIZE]: Page data
+//! ### WAL Frame (page size + 24 bytes each)
+//! - `[0..4]`: Page number (u32 big-endian)
+//! - `[4..8]`: Size of database in pages after commit (u32 big-endian, 0 if not a commit frame)
+//! - `[8..16]`: Transaction ID (u64 big-endian)
+//! - `[16..20]`: Checksum part 1 (u32)
+//! - `[20..24]`: Checksum part 2 (u32)
+//! - `[24..24+page size]`: Page data
 
 use crate::error::{HorizonError, Result};
 use crate::pager::{PageId, PAGE_SIZE};
+use crate::DatabaseConfig;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+mod journal;
+
+pub use journal::{journal_path, RollbackJournal};
 
 const WAL_MAGIC: &[u8; 16] = b"HorizonWAL v01\x00\x00";
 const WAL_HEADER_SIZE: usize = 32;
 const FRAME_HEADER_SIZE: usize = 24;
-const FRAME_SIZE: usize = FRAME_HEADER_SIZE + PAGE_SIZE;
+
+/// How much work a checkpoint does, mirroring SQLite's
+/// `PRAGMA wal_checkpoint` modes.
+///
+/// A database handle is the only user of its WAL, so there are never
+/// readers or writers to wait for: `Full` and `Restart` both leave the log
+/// empty once every frame has been copied back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Copy frames into the database file but keep the log.
+    Passive,
+    /// Copy every frame, sync the database file, and restart the log.
+    Full,
+    /// Same as `Full`.
+    Restart,
+    /// Like `Restart`, but also truncate the WAL file to zero bytes.
+    Truncate,
+}
+
+impl CheckpointMode {
+    /// Parse a mode name as accepted by `PRAGMA wal_checkpoint(mode)`.
+    pub fn from_sql(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "PASSIVE" => Some(CheckpointMode::Passive),
+            "FULL" => Some(CheckpointMode::Full),
+            "RESTART" => Some(CheckpointMode::Restart),
+            "TRUNCATE" => Some(CheckpointMode::Truncate),
+            _ => None,
+        }
+    }
+}
+
+/// How changes reach the database file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JournalMode {
+    /// Changed pages are appended to the `.hdb-wal` log and copied into
+    /// the database file at checkpoints.
+    #[default]
+    Wal,
+    /// Changed pages are written straight to the database file after
+    /// their original images are saved in a `.hdb-journal` rollback
+    /// journal, which is deleted when the changes are committed.
+    Delete,
+    /// Like `Delete`, but the rollback journal is kept in memory, so a
+    /// crash in the middle of a write can leave the file corrupt.
+    Memory,
+    /// No journal: changed pages are written straight to the database
+    /// file, so a crash in the middle of a write can leave it corrupt.
+    Off,
+}
+
+impl JournalMode {
+    /// Parse a mode name as accepted by `PRAGMA journal_mode = mode`.
+    pub fn from_sql(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "WAL" => Some(JournalMode::Wal),
+            "DELETE" => Some(JournalMode::Delete),
+            "MEMORY" => Some(JournalMode::Memory),
+            "OFF" => Some(JournalMode::Off),
+            _ => None,
+        }
+    }
+
+    /// The name `PRAGMA journal_mode` reports for the mode.
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            JournalMode::Wal => "wal",
+            JournalMode::Delete => "delete",
+            JournalMode::Memory => "memory",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+/// How often changes are flushed to stable storage, mirroring SQLite's
+/// `PRAGMA synchronous` levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SynchronousMode {
+    /// Never sync; the operating system decides when writes reach the
+    /// disk.
+    Off,
+    /// Sync the WAL at commits and checkpoints, and the database file
+    /// when it is written.
+    #[default]
+    Normal,
+    /// Like `Normal`, but also sync the WAL after every frame written.
+    Full,
+    /// Like `Full`, but also sync the directory after a WAL or journal
+    /// file is created or deleted.
+    Extra,
+}
+
+impl SynchronousMode {
+    /// Parse a level as accepted by `PRAGMA synchronous = level`: a name
+    /// or its number from 0 (`OFF`) to 3 (`EXTRA`).
+    pub fn from_sql(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "OFF" | "0" => Some(SynchronousMode::Off),
+            "NORMAL" | "1" => Some(SynchronousMode::Normal),
+            "FULL" | "2" => Some(SynchronousMode::Full),
+            "EXTRA" | "3" => Some(SynchronousMode::Extra),
+            _ => None,
+        }
+    }
+
+    /// The number `PRAGMA synchronous` reports for the level.
+    pub fn level(self) -> i64 {
+        match self {
+            SynchronousMode::Off => 0,
+            SynchronousMode::Normal => 1,
+            SynchronousMode::Full => 2,
+            SynchronousMode::Extra => 3,
+        }
+    }
+}
+
+/// Sync the directory holding `path`, so that a file created or deleted
+/// there survives a crash. Directories cannot be synced on every platform;
+/// failing to open one is ignored.
+pub(crate) fn sync_directory(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        dir.sync_all()?;
+    }
+    Ok(())
+}
+
+/// The outcome of a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CheckpointResult {
+    /// Number of frames in the WAL when the checkpoint ran.
+    pub wal_frames: usize,
+    /// Number of those frames that are now in the database file.
+    pub checkpointed_frames: usize,
+}
 
 /// A WAL frame containing a page image.
 #[derive(Debug, Clone)]
@@ -44,7 +187,7 @@ struct WalFrame {
     /// Transaction ID that wrote this frame.
     txn_id: u64,
     /// The page data.
-    data: Box<[u8; PAGE_SIZE]>,
+    data: Box<[u8]>,
 }
 
 /// Manages the Write-Ahead Log for crash recovery and durability.
@@ -53,27 +196,75 @@ pub struct WalManager {
     path: PathBuf,
     /// WAL file handle.
     file: Option<File>,
+    /// Size of the page image in each frame.
+    page_size: usize,
     /// Number of frames in the WAL.
     frame_count: u32,
     /// Checkpoint sequence number.
     checkpoint_seq: u32,
+    /// Number of leading frames already copied to the database file.
+    backfilled: u32,
+    /// Whether the header must be rewritten before the next frame, after
+    /// a `Truncate` checkpoint emptied the file.
+    needs_header: bool,
     /// Index mapping page_id -> most recent frame index (0-based).
     /// Used for reading the latest version of a page from the WAL.
     page_index: HashMap<PageId, u32>,
     /// Salt values for integrity.
     salt: [u32; 2],
+    /// When the WAL file is synced.
+    synchronous: SynchronousMode,
+    /// Whether the file was opened without write access.
+    read_only: bool,
 }
 
 impl WalManager {
-    /// Open or create a WAL file.
+    /// Open or create a WAL file for the default page size.
     pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with_page_size(path, PAGE_SIZE)
+    }
+
+    /// Open or create a WAL file whose frames hold `page_size`-byte pages.
+    pub fn open_with_page_size(path: &Path, page_size: usize) -> Result<Self> {
+        Self::open_inner(path, page_size, false)
+    }
+
+    /// Open the WAL for a database opened with `config`.
+    ///
+    /// In read-only mode an existing log is opened for reading, so that
+    /// pages not yet checkpointed are still seen, and `None` is returned
+    /// when there is no log; the file is never created.
+    pub fn open_with_config(
+        path: &Path,
+        page_size: usize,
+        config: &DatabaseConfig,
+    ) -> Result<Option<Self>> {
         let exists = path.exists();
+        if config.read_only && !exists {
+            return Ok(None);
+        }
+        let mut wal = Self::open_inner(path, page_size, config.read_only)?;
+        wal.synchronous = config.synchronous;
+        if !exists && config.synchronous == SynchronousMode::Extra {
+            sync_directory(path)?;
+        }
+        Ok(Some(wal))
+    }
 
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
+    fn open_inner(path: &Path, page_size: usize, read_only: bool) -> Result<Self> {
+        let frame_size = FRAME_HEADER_SIZE + page_size;
+        let exists = path.exists();
+
+        let mut file = if read_only {
+            OpenOptions::new().read(true).open(path)?
+        } else {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?
+        };
 
         if exists && file.metadata()?.len() >= WAL_HEADER_SIZE as u64 {
             // Read existing WAL
@@ -88,11 +279,11 @@ impl WalManager {
                 ));
             }
 
-            let page_size = u32::from_be_bytes(header[16..20].try_into().unwrap());
-            if page_size != PAGE_SIZE as u32 {
+            let stored_page_size = u32::from_be_bytes(header[16..20].try_into().unwrap());
+            if stored_page_size != page_size as u32 {
                 return Err(HorizonError::CorruptDatabase(format!(
                     "WAL page size mismatch: expected {}, got {}",
-                    PAGE_SIZE, page_size
+                    page_size, stored_page_size
                 )));
             }
 
@@ -105,11 +296,11 @@ impl WalManager {
             // Build page index by scanning all frames
             let file_len = file.metadata()?.len();
             let data_len = file_len as usize - WAL_HEADER_SIZE;
-            let frame_count = (data_len / FRAME_SIZE) as u32;
+            let frame_count = (data_len / frame_size) as u32;
 
             let mut page_index = HashMap::new();
             for i in 0..frame_count {
-                let offset = WAL_HEADER_SIZE as u64 + (i as u64) * FRAME_SIZE as u64;
+                let offset = WAL_HEADER_SIZE as u64 + (i as u64) * frame_size as u64;
                 file.seek(SeekFrom::Start(offset))?;
                 let mut frame_header = [0u8; FRAME_HEADER_SIZE];
                 file.read_exact(&mut frame_header)?;
@@ -121,32 +312,36 @@ impl WalManager {
             Ok(WalManager {
                 path: path.to_path_buf(),
                 file: Some(file),
+                page_size,
                 frame_count,
                 checkpoint_seq,
+                backfilled: 0,
+                needs_header: false,
                 page_index,
                 salt,
+                synchronous: SynchronousMode::default(),
+                read_only,
             })
         } else {
             // Initialize new WAL
             let salt = [0x12345678u32, 0x9ABCDEF0u32];
-            let mut header = [0u8; WAL_HEADER_SIZE];
-            header[0..16].copy_from_slice(WAL_MAGIC);
-            header[16..20].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
-            header[20..24].copy_from_slice(&0u32.to_be_bytes());
-            header[24..28].copy_from_slice(&salt[0].to_be_bytes());
-            header[28..32].copy_from_slice(&salt[1].to_be_bytes());
-
-            file.seek(SeekFrom::Start(0))?;
-            file.write_all(&header)?;
-            file.sync_all()?;
+            if !read_only {
+                write_header(&mut file, page_size, 0, salt)?;
+                file.sync_all()?;
+            }
 
             Ok(WalManager {
                 path: path.to_path_buf(),
                 file: Some(file),
+                page_size,
                 frame_count: 0,
                 checkpoint_seq: 0,
+                backfilled: 0,
+                needs_header: read_only,
                 page_index: HashMap::new(),
                 salt,
+                synchronous: SynchronousMode::default(),
+                read_only,
             })
         }
     }
@@ -158,20 +353,35 @@ impl WalManager {
     pub fn write_frame(
         &mut self,
         page_id: PageId,
-        data: &[u8; PAGE_SIZE],
+        data: &[u8],
         txn_id: u64,
         is_commit: bool,
         db_size: u32,
     ) -> Result<()> {
+        self.ensure_writable()?;
+        if data.len() != self.page_size {
+            return Err(HorizonError::Internal(format!(
+                "WAL frame is {} bytes, expected {}",
+                data.len(),
+                self.page_size
+            )));
+        }
+
         // Compute checksum before borrowing file mutably
         let checksum = self.compute_checksum(data);
+        let frame_size = self.frame_size();
 
         let file = self
             .file
             .as_mut()
             .ok_or_else(|| HorizonError::Internal("WAL file not open".into()))?;
 
-        let offset = WAL_HEADER_SIZE as u64 + (self.frame_count as u64) * FRAME_SIZE as u64;
+        if self.needs_header {
+            write_header(file, self.page_size, self.checkpoint_seq, self.salt)?;
+            self.needs_header = false;
+        }
+
+        let offset = WAL_HEADER_SIZE as u64 + (self.frame_count as u64) * frame_size as u64;
         file.seek(SeekFrom::Start(offset))?;
 
         // Write frame header
@@ -190,7 +400,12 @@ impl WalManager {
         self.page_index.insert(page_id, self.frame_count);
         self.frame_count += 1;
 
-        if is_commit {
+        let sync = match self.synchronous {
+            SynchronousMode::Off => false,
+            SynchronousMode::Normal => is_commit,
+            SynchronousMode::Full | SynchronousMode::Extra => true,
+        };
+        if sync {
             file.sync_data()?;
         }
 
@@ -200,7 +415,7 @@ impl WalManager {
     /// Read the most recent version of a page from the WAL.
     ///
     /// Returns `None` if the page is not in the WAL.
-    pub fn read_page(&self, page_id: PageId) -> Result<Option<[u8; PAGE_SIZE]>> {
+    pub fn read_page(&self, page_id: PageId) -> Result<Option<Vec<u8>>> {
         let frame_index = match self.page_index.get(&page_id) {
             Some(&idx) => idx,
             None => return Ok(None),
@@ -212,10 +427,10 @@ impl WalManager {
             .ok_or_else(|| HorizonError::Internal("WAL file not open".into()))?;
 
         let offset = WAL_HEADER_SIZE as u64
-            + (frame_index as u64) * FRAME_SIZE as u64
+            + (frame_index as u64) * self.frame_size() as u64
             + FRAME_HEADER_SIZE as u64;
 
-        let mut data = [0u8; PAGE_SIZE];
+        let mut data = vec![0u8; self.page_size];
         // Use a separate reader to avoid needing &mut self
         let mut reader = File::open(&self.path)?;
         reader.seek(SeekFrom::Start(offset))?;
@@ -229,60 +444,97 @@ impl WalManager {
         self.page_index.contains_key(&page_id)
     }
 
-    /// Checkpoint: write all WAL pages back to the main database file.
-    ///
-    /// This transfers all committed frames from the WAL into the main database
-    /// file, then resets the WAL.
-    pub fn checkpoint(&mut self, pager: &mut crate::pager::Pager) -> Result<()> {
-        if self.frame_count == 0 {
+    /// Make every frame written so far durable, unless syncing is off.
+    /// Called once the frames of a transaction are all written.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.synchronous == SynchronousMode::Off || self.read_only {
             return Ok(());
         }
+        if let Some(ref file) = self.file {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoint: write WAL pages back to the main database file.
+    ///
+    /// Frames not yet copied are transferred into the main database file.
+    /// Every mode except `Passive` then resets the WAL.
+    pub fn checkpoint(
+        &mut self,
+        pager: &mut crate::pager::Pager,
+        mode: CheckpointMode,
+    ) -> Result<CheckpointResult> {
+        if self.read_only {
+            // Nothing can be copied; the log stays as it is
+            return Ok(CheckpointResult {
+                wal_frames: self.frame_count as usize,
+                checkpointed_frames: 0,
+            });
+        }
+        let result = CheckpointResult {
+            wal_frames: self.frame_count as usize,
+            checkpointed_frames: self.frame_count as usize,
+        };
 
+        let frame_size = self.frame_size();
         let file = self
             .file
             .as_mut()
             .ok_or_else(|| HorizonError::Internal("WAL file not open".into()))?;
 
-        // Read each frame and write it to the pager
-        for frame_idx in 0..self.frame_count {
-            let offset = WAL_HEADER_SIZE as u64 + (frame_idx as u64) * FRAME_SIZE as u64;
-            file.seek(SeekFrom::Start(offset))?;
+        if self.backfilled < self.frame_count {
+            // Read each frame and write it to the pager
+            for frame_idx in self.backfilled..self.frame_count {
+                let offset = WAL_HEADER_SIZE as u64 + (frame_idx as u64) * frame_size as u64;
+                file.seek(SeekFrom::Start(offset))?;
 
-            // Read frame header
-            let mut frame_header = [0u8; FRAME_HEADER_SIZE];
-            file.read_exact(&mut frame_header)?;
-            let page_id = u32::from_be_bytes(frame_header[0..4].try_into().unwrap());
+                // Read frame header
+                let mut frame_header = [0u8; FRAME_HEADER_SIZE];
+                file.read_exact(&mut frame_header)?;
+                let page_id = u32::from_be_bytes(frame_header[0..4].try_into().unwrap());
 
-            // Read page data
-            let mut data = [0u8; PAGE_SIZE];
-            file.read_exact(&mut data)?;
+                // Read page data
+                let mut data = vec![0u8; self.page_size];
+                file.read_exact(&mut data)?;
 
-            // Write to main database
-            pager.write_page(page_id, &data)?;
+                // Write to main database
+                pager.write_page(page_id, &data)?;
+            }
+
+            pager.sync()?;
+            self.backfilled = self.frame_count;
         }
 
-        pager.sync()?;
+        if mode == CheckpointMode::Passive || (self.frame_count == 0 && mode != CheckpointMode::Truncate) {
+            return Ok(result);
+        }
 
         // Reset WAL
         self.frame_count = 0;
+        self.backfilled = 0;
         self.checkpoint_seq += 1;
         self.page_index.clear();
 
-        // Rewrite WAL header with new checkpoint sequence
-        file.seek(SeekFrom::Start(0))?;
-        let mut header = [0u8; WAL_HEADER_SIZE];
-        header[0..16].copy_from_slice(WAL_MAGIC);
-        header[16..20].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
-        header[20..24].copy_from_slice(&self.checkpoint_seq.to_be_bytes());
-        header[24..28].copy_from_slice(&self.salt[0].to_be_bytes());
-        header[28..32].copy_from_slice(&self.salt[1].to_be_bytes());
-        file.write_all(&header)?;
+        if mode == CheckpointMode::Truncate {
+            file.set_len(0)?;
+            self.needs_header = true;
+        } else {
+            // Rewrite WAL header with new checkpoint sequence and truncate
+            // the file to just the header
+            write_header(file, self.page_size, self.checkpoint_seq, self.salt)?;
+            file.set_len(WAL_HEADER_SIZE as u64)?;
+        }
+        if self.synchronous != SynchronousMode::Off {
+            file.sync_all()?;
+        }
 
-        // Truncate WAL file to just the header
-        file.set_len(WAL_HEADER_SIZE as u64)?;
-        file.sync_all()?;
+        Ok(result)
+    }
 
-        Ok(())
+    /// Set when the WAL file is synced.
+    pub fn set_synchronous(&mut self, mode: SynchronousMode) {
+        self.synchronous = mode;
     }
 
     /// Get the number of frames in the WAL.
@@ -290,8 +542,38 @@ impl WalManager {
         self.frame_count
     }
 
+    /// Whether another handle has written to or checkpointed the log since
+    /// this manager last did, so that its page index is out of date.
+    pub fn is_stale(&self) -> Result<bool> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+        let expected_len = if self.needs_header {
+            0
+        } else {
+            WAL_HEADER_SIZE as u64 + self.frame_count as u64 * self.frame_size() as u64
+        };
+        if file.metadata()?.len() != expected_len {
+            return Ok(true);
+        }
+        if expected_len == 0 {
+            return Ok(false);
+        }
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        let checkpoint_seq = u32::from_be_bytes(header[20..24].try_into().unwrap());
+        Ok(checkpoint_seq != self.checkpoint_seq)
+    }
+
     /// Compute a simple checksum for integrity checking.
-    fn compute_checksum(&self, data: &[u8; PAGE_SIZE]) -> (u32, u32) {
+    /// Size of one frame: its header plus a page image.
+    fn frame_size(&self) -> usize {
+        FRAME_HEADER_SIZE + self.page_size
+    }
+
+    fn compute_checksum(&self, data: &[u8]) -> (u32, u32) {
         let mut s1: u32 = self.salt[0];
         let mut s2: u32 = self.salt[1];
         for chunk in data.chunks(4) {
@@ -310,7 +592,9 @@ impl WalManager {
 
     /// Reset the WAL, discarding all uncommitted changes.
     pub fn reset(&mut self) -> Result<()> {
+        self.ensure_writable()?;
         self.frame_count = 0;
+        self.backfilled = 0;
         self.page_index.clear();
 
         if let Some(ref mut file) = self.file {
@@ -320,6 +604,116 @@ impl WalManager {
 
         Ok(())
     }
+
+    /// Return [`HorizonError::ReadOnly`] if the log was opened read-only.
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(HorizonError::ReadOnly("cannot write to a read-only WAL".into()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Write the WAL header at the start of `file`.
+fn write_header(file: &mut File, page_size: usize, checkpoint_seq: u32, salt: [u32; 2]) -> Result<()> {
+    let mut header = [0u8; WAL_HEADER_SIZE];
+    header[0..16].copy_from_slice(WAL_MAGIC);
+    header[16..20].copy_from_slice(&(page_size as u32).to_be_bytes());
+    header[20..24].copy_from_slice(&checkpoint_seq.to_be_bytes());
+    header[24..28].copy_from_slice(&salt[0].to_be_bytes());
+    header[28..32].copy_from_slice(&salt[1].to_be_bytes());
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    Ok(())
+}
+
+/// The exclusive right to write to a database, shared by every handle and
+/// process that opens it.
+///
+/// The lock is a `.hdb-lock` file beside the database, created atomically
+/// and holding the owner's process ID; dropping the lock deletes it. A lock
+/// file whose owner is known to have exited (checked on Linux only) is
+/// taken over rather than waited on.
+#[derive(Debug)]
+pub struct WriteLock {
+    path: PathBuf,
+}
+
+impl WriteLock {
+    /// Take the write lock for the database at `db_path`, or return `None`
+    /// at once if someone else holds it.
+    pub fn try_acquire(db_path: &Path) -> Result<Option<Self>> {
+        let path = db_path.with_extension("hdb-lock");
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(std::process::id().to_string().as_bytes())?;
+                    return Ok(Some(WriteLock { path }));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if !lock_owner_exited(&path) {
+                        return Ok(None);
+                    }
+                    // Left behind by a process that died holding it
+                    match std::fs::remove_file(&path) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Take the write lock for the database at `db_path`, waiting while
+    /// someone else holds it.
+    ///
+    /// Retries after 1 ms, then after twice as long each time, until
+    /// `timeout` has passed; `on_busy` is called before each wait. Fails
+    /// with [`HorizonError::Busy`] if the lock is still held by then.
+    pub fn acquire(db_path: &Path, timeout: Duration, on_busy: Option<fn()>) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = Duration::from_millis(1);
+        loop {
+            if let Some(lock) = Self::try_acquire(db_path)? {
+                return Ok(lock);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(HorizonError::Busy(format!(
+                    "{} is locked by another writer",
+                    db_path.display()
+                )));
+            }
+            if let Some(handler) = on_busy {
+                handler();
+            }
+            std::thread::sleep(delay.min(deadline - now));
+            delay *= 2;
+        }
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Whether the process recorded in the lock file at `path` has exited.
+/// Only answered on Linux; elsewhere, and when the file cannot be read,
+/// the owner is assumed to be alive.
+fn lock_owner_exited(path: &Path) -> bool {
+    let pid = match std::fs::read_to_string(path).ok().and_then(|s| s.trim().parse::<u32>().ok()) {
+        Some(pid) => pid,
+        None => return false,
+    };
+    if pid == std::process::id() {
+        return false;
+    }
+    cfg!(target_os = "linux") && !Path::new("/proc").join(pid.to_string()).exists()
 }
 
 impl Drop for WalManager {
@@ -424,4 +818,84 @@ mod tests {
         assert_eq!(wal.frame_count(), 0);
         assert!(!wal.contains_page(1));
     }
+
+    #[test]
+    fn test_checkpoint_modes() {
+        let dir = TempDir::new().unwrap();
+        let mut pager = crate::pager::Pager::open(&dir.path().join("test.hdb"), false).unwrap();
+        let page_id = pager.allocate_page().unwrap();
+        let path = dir.path().join("test.hdb-wal");
+        let mut wal = WalManager::open(&path).unwrap();
+
+        let mut data = [0u8; PAGE_SIZE];
+        data[0] = 7;
+        wal.write_frame(page_id, &data, 1, true, 2).unwrap();
+
+        // Passive copies the frame but keeps it in the log
+        let result = wal.checkpoint(&mut pager, CheckpointMode::Passive).unwrap();
+        assert_eq!(result, CheckpointResult { wal_frames: 1, checkpointed_frames: 1 });
+        assert_eq!(pager.read_page(page_id).unwrap()[0], 7);
+        assert!(wal.contains_page(page_id));
+
+        wal.checkpoint(&mut pager, CheckpointMode::Full).unwrap();
+        assert_eq!(wal.frame_count(), 0);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), WAL_HEADER_SIZE as u64);
+
+        // Truncate empties the file; the header is restored on the next write
+        wal.checkpoint(&mut pager, CheckpointMode::Truncate).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        wal.write_frame(page_id, &data, 2, true, 2).unwrap();
+        drop(wal);
+        let wal = WalManager::open(&path).unwrap();
+        assert_eq!(wal.frame_count(), 1);
+    }
+
+    #[test]
+    fn test_is_stale_after_another_writer() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.hdb-wal");
+        let mut ours = WalManager::open(&path).unwrap();
+        let mut theirs = WalManager::open(&path).unwrap();
+        assert!(!ours.is_stale().unwrap());
+
+        ours.write_frame(1, &[0u8; PAGE_SIZE], 1, true, 2).unwrap();
+        assert!(!ours.is_stale().unwrap());
+        assert!(theirs.is_stale().unwrap());
+        theirs.reset().unwrap();
+        assert!(ours.is_stale().unwrap());
+    }
+
+    #[test]
+    fn test_write_lock_waits_for_holder() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.hdb");
+        let lock = WriteLock::try_acquire(&db_path).unwrap().unwrap();
+        assert!(WriteLock::try_acquire(&db_path).unwrap().is_none());
+
+        let holder = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(lock);
+        });
+        let start = Instant::now();
+        let lock = WriteLock::acquire(&db_path, Duration::from_secs(5), None).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        holder.join().unwrap();
+
+        // Still held: give up after the timeout
+        let start = Instant::now();
+        let err = WriteLock::acquire(&db_path, Duration::from_millis(20), None).unwrap_err();
+        assert!(matches!(err, HorizonError::Busy(_)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        drop(lock);
+        assert!(!db_path.with_extension("hdb-lock").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_write_lock_of_exited_process_is_taken_over() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.hdb");
+        std::fs::write(db_path.with_extension("hdb-lock"), u32::MAX.to_string()).unwrap();
+        assert!(WriteLock::try_acquire(&db_path).unwrap().is_some());
+    }
 }
@@ -0,0 +1,58 @@
+#![cfg(feature = "tokio")]
+
+use horizon::async_db::AsyncDatabase;
+use horizon::Value;
+use tempfile::TempDir;
+
+async fn open_db() -> (TempDir, AsyncDatabase) {
+    let dir = TempDir::new().unwrap();
+    let db = AsyncDatabase::open(dir.path().join("test.hdb")).await.unwrap();
+    (dir, db)
+}
+
+#[tokio::test]
+async fn async_execute_and_query() {
+    let (_dir, db) = open_db().await;
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)").await.unwrap();
+    let affected = db.execute("INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob')").await.unwrap();
+    assert_eq!(affected, 2);
+
+    let result = db.query("SELECT name FROM users ORDER BY id").await.unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[0], Value::Text("Alice".into()));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn async_concurrent_selects() {
+    let (_dir, db) = open_db().await;
+    db.execute("CREATE TABLE nums (id INTEGER PRIMARY KEY, val INTEGER)").await.unwrap();
+    for i in 1..=50 {
+        db.execute(&format!("INSERT INTO nums VALUES ({}, {})", i, i * 10)).await.unwrap();
+    }
+
+    let mut handles = Vec::new();
+    for i in 1..=16i64 {
+        let db = db.clone();
+        handles.push(tokio::spawn(async move {
+            let result = db
+                .query(&format!("SELECT val FROM nums WHERE id = {}", i))
+                .await
+                .unwrap();
+            (i, result.rows[0].values[0].clone())
+        }));
+    }
+    for handle in handles {
+        let (i, val) = handle.await.unwrap();
+        assert_eq!(val, Value::Integer(i * 10));
+    }
+
+    let count = db.query("SELECT COUNT(*) FROM nums").await.unwrap();
+    assert_eq!(count.rows[0].values[0], Value::Integer(50));
+}
+
+#[tokio::test]
+async fn async_errors_propagate() {
+    let (_dir, db) = open_db().await;
+    assert!(db.query("SELECT * FROM missing").await.is_err());
+    assert!(db.execute("NOT VALID SQL").await.is_err());
+}
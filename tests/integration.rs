@@ -1,4 +1,7 @@
-use horizon::{Database, HorizonError, Value};
+#![allow(clippy::approx_constant)]
+
+use horizon::execution::fts5::{self, Token};
+use horizon::{CdcOperation, CheckpointMode, Database, DatabaseConfig, Fts5Tokenizer, HorizonError, JournalMode, QueryHandle, Row, Value};
 use tempfile::TempDir;
 
 fn open_db() -> (TempDir, Database) {
@@ -212,6 +215,43 @@ fn large_insert_and_scan() {
     assert_eq!(result.len(), 500);
 }
 
+// ---- In-Memory Database Tests ----
+
+#[test]
+fn memory_database_round_trip() {
+    let db = Database::open(":memory:").unwrap();
+    assert!(db.is_memory());
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
+    db.execute("BEGIN").unwrap();
+    for i in 0..1000 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'value {}')", i, i)).unwrap();
+    }
+    db.execute("COMMIT").unwrap();
+
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 1000);
+    let result = db.query("SELECT val FROM t WHERE id = 777").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("value 777".into()));
+    assert_eq!(db.query("PRAGMA journal_mode").unwrap().rows[0].values[0], Value::Text("memory".into()));
+    db.close().unwrap();
+
+    assert!(!std::path::Path::new(":memory:").exists());
+    assert!(!std::path::Path::new(":memory:.hdb-wal").exists());
+}
+
+#[test]
+fn memory_databases_are_independent() {
+    let a = Database::open(":memory:").unwrap();
+    let b = Database::open(":memory:").unwrap();
+    a.execute("CREATE TABLE t (x INTEGER)").unwrap();
+    a.execute("INSERT INTO t VALUES (1)").unwrap();
+    assert!(b.query("SELECT * FROM t").is_err());
+    drop(a);
+
+    // A new handle starts empty
+    let c = Database::open(":memory:").unwrap();
+    assert!(c.query("SELECT * FROM t").is_err());
+}
+
 // ---- CREATE INDEX / DROP INDEX / Index Scan Tests ----
 
 #[test]
@@ -474,6 +514,108 @@ fn select_without_where_unaffected_by_index() {
     assert_eq!(result.len(), 3);
 }
 
+#[test]
+fn multi_column_index_equality_on_both_columns() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, customer TEXT, status TEXT, total REAL)").unwrap();
+    db.execute("CREATE INDEX idx_cust_status ON orders (customer, status)").unwrap();
+    db.execute("INSERT INTO orders VALUES (1, 'ann', 'open', 10.0), (2, 'ann', 'paid', 20.0), \
+                (3, 'bob', 'open', 30.0), (4, 'ann', 'open', 40.0)").unwrap();
+
+    let result = db.query("SELECT id FROM orders WHERE status = 'open' AND customer = 'ann' ORDER BY id").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+    assert_eq!(result.rows[1].values[0], Value::Integer(4));
+
+    // The index follows updates and deletes
+    db.execute("UPDATE orders SET status = 'paid' WHERE id = 4").unwrap();
+    db.execute("DELETE FROM orders WHERE id = 2").unwrap();
+    let result = db.query("SELECT id FROM orders WHERE customer = 'ann' AND status = 'paid'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(4));
+}
+
+#[test]
+fn multi_column_index_leading_column_range() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (score INTEGER, name TEXT)").unwrap();
+    db.execute("CREATE INDEX idx_score_name ON t (score, name)").unwrap();
+    db.execute("INSERT INTO t VALUES (-20, 'a'), (-5, 'b'), (0, 'c'), (7, 'd'), (100, 'e'), (NULL, 'f')").unwrap();
+
+    let result = db.query("SELECT name FROM t WHERE score > -10 AND score < 50 ORDER BY score").unwrap();
+    let names: Vec<&Value> = result.rows.iter().map(|r| &r.values[0]).collect();
+    assert_eq!(names, vec![&Value::Text("b".into()), &Value::Text("c".into()), &Value::Text("d".into())]);
+
+    assert_eq!(db.query("SELECT * FROM t WHERE score <= -5").unwrap().len(), 2);
+    assert_eq!(db.query("SELECT * FROM t WHERE score >= 100").unwrap().len(), 1);
+}
+
+#[test]
+fn multi_column_index_trailing_column_filtered_after_scan() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (a TEXT, b TEXT, c INTEGER)").unwrap();
+    db.execute("CREATE INDEX idx_a_b ON t (a, b)").unwrap();
+    db.execute("INSERT INTO t VALUES ('x', 'aa', 1), ('x', 'b', 2), ('x', 'c', 3), ('y', 'aa', 4)").unwrap();
+
+    // Equality on `a` drives the scan; the `c` filter is applied afterwards
+    let result = db.query("SELECT c FROM t WHERE a = 'x' AND c > 1 ORDER BY c").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[0], Value::Integer(2));
+
+    // Text range on the second column orders 'aa' before 'b'
+    let result = db.query("SELECT c FROM t WHERE a = 'x' AND b < 'b'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+
+    // Only the trailing column: a full scan still finds both rows
+    assert_eq!(db.query("SELECT * FROM t WHERE b = 'aa'").unwrap().len(), 2);
+}
+
+#[test]
+fn partial_index_queries_match_full_scan() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, status TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'ann', 'active'), (2, 'ann', 'inactive'), (3, 'bob', 'active')").unwrap();
+    db.execute("CREATE INDEX idx_active ON users (name) WHERE status = 'active'").unwrap();
+    db.execute("INSERT INTO users VALUES (4, 'ann', 'active')").unwrap();
+    db.execute("UPDATE users SET status = 'inactive' WHERE id = 3").unwrap();
+
+    let result = db.query("SELECT id FROM users WHERE name = 'ann' AND status = 'active' ORDER BY id").unwrap();
+    let ids: Vec<&Value> = result.rows.iter().map(|r| &r.values[0]).collect();
+    assert_eq!(ids, vec![&Value::Integer(1), &Value::Integer(4)]);
+    assert_eq!(db.query("SELECT * FROM users WHERE name = 'bob' AND status = 'active'").unwrap().len(), 0);
+
+    // Without the index predicate, inactive rows are still found
+    assert_eq!(db.query("SELECT * FROM users WHERE name = 'ann'").unwrap().len(), 3);
+
+    let result = db.query("PRAGMA index_list(users)").unwrap();
+    assert_eq!(result.rows[0].values[4], Value::Integer(1));
+}
+
+#[test]
+fn expression_index_lookup_and_maintenance() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'Bob@x.com'), (2, 'alice@x.com')").unwrap();
+    db.execute("CREATE INDEX idx_email_lower ON users (lower(email))").unwrap();
+    db.execute("INSERT INTO users VALUES (3, 'BOB@X.COM')").unwrap();
+    db.execute("UPDATE users SET email = 'carol@x.com' WHERE id = 1").unwrap();
+    db.execute("DELETE FROM users WHERE id = 2").unwrap();
+
+    let result = db.query("SELECT id FROM users WHERE lower(email) = 'bob@x.com'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(3));
+    assert_eq!(db.query("SELECT * FROM users WHERE lower(email) = 'carol@x.com'").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM users WHERE lower(email) = 'alice@x.com'").unwrap().len(), 0);
+}
+
+#[test]
+fn expression_index_rejects_aggregates() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (a INTEGER)").unwrap();
+    assert!(db.execute("CREATE INDEX idx_bad ON t (max(a))").is_err());
+}
+
 // ---- Transaction Tests (BEGIN / COMMIT / ROLLBACK) ----
 
 #[test]
@@ -609,6 +751,388 @@ fn rollback_mixed_operations() {
     assert_eq!(result.rows[2].get("val"), Some(&Value::Integer(30)));
 }
 
+#[test]
+fn rollback_to_savepoint_keeps_outer_changes() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 10)").unwrap();
+
+    db.execute("BEGIN").unwrap();
+    db.execute("INSERT INTO t VALUES (2, 20)").unwrap();
+    db.execute("SAVEPOINT sp").unwrap();
+    db.execute("INSERT INTO t VALUES (3, 30)").unwrap();
+    db.execute("UPDATE t SET val = 99 WHERE id = 1").unwrap();
+    db.execute("DELETE FROM t WHERE id = 2").unwrap();
+    db.execute("ROLLBACK TO sp").unwrap();
+
+    // Only the work after the savepoint is undone; the transaction is still open
+    let result = db.query("SELECT id, val FROM t ORDER BY id").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].get("val"), Some(&Value::Integer(10)));
+    assert_eq!(result.rows[1].get("id"), Some(&Value::Integer(2)));
+
+    db.execute("INSERT INTO t VALUES (4, 40)").unwrap();
+    db.execute("COMMIT").unwrap();
+    let result = db.query("SELECT id FROM t ORDER BY id").unwrap();
+    let ids: Vec<_> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(1), Value::Integer(2), Value::Integer(4)]);
+}
+
+#[test]
+fn released_savepoint_is_undone_by_outer_rollback() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+
+    db.execute("BEGIN").unwrap();
+    db.execute("SAVEPOINT a").unwrap();
+    db.execute("INSERT INTO t VALUES (1)").unwrap();
+    db.execute("SAVEPOINT b").unwrap();
+    db.execute("INSERT INTO t VALUES (2)").unwrap();
+    db.execute("RELEASE b").unwrap();
+    db.execute("ROLLBACK TO SAVEPOINT a").unwrap();
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 0);
+
+    db.execute("INSERT INTO t VALUES (3)").unwrap();
+    db.execute("RELEASE SAVEPOINT a").unwrap();
+    db.execute("ROLLBACK").unwrap();
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 0);
+}
+
+#[test]
+fn savepoint_without_begin_starts_transaction() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+
+    db.execute("SAVEPOINT outer_sp").unwrap();
+    db.execute("INSERT INTO t VALUES (1)").unwrap();
+    db.execute("RELEASE outer_sp").unwrap();
+
+    // Releasing the outermost savepoint committed the implicit transaction
+    let err = db.execute("ROLLBACK").unwrap_err();
+    assert!(matches!(err, HorizonError::TransactionError(_)));
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 1);
+
+    let err = db.execute("ROLLBACK TO missing").unwrap_err();
+    assert!(matches!(err, HorizonError::TransactionError(_)));
+}
+
+// ---- Composite PRIMARY KEY Tests ----
+
+#[test]
+fn composite_primary_key_rejects_duplicates() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE enrollments (student INTEGER, course TEXT, grade TEXT, PRIMARY KEY (student, course))").unwrap();
+    db.execute("INSERT INTO enrollments VALUES (1, 'math', 'A'), (1, 'art', 'B'), (2, 'math', 'C')").unwrap();
+
+    let err = db.execute("INSERT INTO enrollments VALUES (1, 'math', 'F')").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(ref m) if m.contains("enrollments.course")));
+    assert!(db.execute("INSERT INTO enrollments VALUES (NULL, 'math', 'F')").is_err());
+
+    let result = db.query("SELECT grade FROM enrollments WHERE student = 1 AND course = 'math'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("A".into()));
+    assert_eq!(db.query("SELECT * FROM enrollments WHERE student = 1").unwrap().len(), 2);
+}
+
+#[test]
+fn composite_primary_key_replace_and_update() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE kv (ns TEXT, k TEXT, v INTEGER, PRIMARY KEY (ns, k))").unwrap();
+    db.execute("INSERT INTO kv VALUES ('a', 'x', 1), ('a', 'y', 2)").unwrap();
+
+    db.execute("INSERT OR REPLACE INTO kv VALUES ('a', 'x', 10)").unwrap();
+    assert_eq!(db.query("SELECT * FROM kv").unwrap().len(), 2);
+
+    // Changing a key column moves the row to its new key
+    let err = db.execute("UPDATE kv SET k = 'y' WHERE k = 'x'").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+    db.execute("UPDATE kv SET k = 'z' WHERE k = 'x'").unwrap();
+    let result = db.query("SELECT v FROM kv WHERE ns = 'a' AND k = 'z'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(10));
+    assert!(db.query("SELECT v FROM kv WHERE ns = 'a' AND k = 'x'").unwrap().is_empty());
+
+    db.execute("BEGIN").unwrap();
+    db.execute("UPDATE kv SET k = 'w' WHERE k = 'z'").unwrap();
+    db.execute("ROLLBACK").unwrap();
+    assert_eq!(db.query("SELECT v FROM kv WHERE ns = 'a' AND k = 'z'").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM kv").unwrap().len(), 2);
+}
+
+#[test]
+fn composite_primary_key_survives_reopen() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("pk.hdb");
+    {
+        let db = Database::open(&path).unwrap();
+        db.execute("CREATE TABLE t (a INTEGER, b INTEGER, PRIMARY KEY (a, b))").unwrap();
+        db.execute("INSERT INTO t VALUES (1, 2)").unwrap();
+        db.close().unwrap();
+    }
+    let db = Database::open(&path).unwrap();
+    assert!(db.execute("INSERT INTO t VALUES (1, 2)").is_err());
+    db.execute("INSERT INTO t VALUES (2, 1)").unwrap();
+    assert_eq!(db.query("SELECT * FROM t WHERE a = 2 AND b = 1").unwrap().len(), 1);
+}
+
+// ---- CHECK Constraint Tests ----
+
+#[test]
+fn check_constraint_single_column_range() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, qty INTEGER CHECK (qty > 0 AND qty <= 100))").unwrap();
+    db.execute("INSERT INTO items VALUES (1, 5)").unwrap();
+
+    let err = db.execute("INSERT INTO items VALUES (2, 0)").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+    let err = db.execute("UPDATE items SET qty = 101 WHERE id = 1").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+
+    let result = db.query("SELECT qty FROM items").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(5));
+}
+
+#[test]
+fn check_constraint_table_level_multi_column() {
+    let (_dir, db) = open_db();
+    db.execute(
+        "CREATE TABLE ranges (id INTEGER PRIMARY KEY, lo INTEGER, hi INTEGER, CHECK (lo <= hi))",
+    ).unwrap();
+    db.execute("INSERT INTO ranges VALUES (1, 1, 10)").unwrap();
+    db.execute("UPDATE ranges SET hi = 1 WHERE id = 1").unwrap();
+
+    let err = db.execute("INSERT INTO ranges VALUES (2, 5, 4)").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+    let err = db.execute("UPDATE ranges SET lo = 2 WHERE id = 1").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+}
+
+#[test]
+fn check_constraint_passes_on_null() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, price REAL CHECK (price >= 0))").unwrap();
+    db.execute("INSERT INTO t VALUES (1, NULL)").unwrap();
+    db.execute("INSERT INTO t (id) VALUES (2)").unwrap();
+    assert_eq!(db.query("SELECT * FROM t WHERE price IS NULL").unwrap().len(), 2);
+}
+
+#[test]
+fn check_constraint_survives_reopen() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("check.hdb");
+    {
+        let db = Database::open(&path).unwrap();
+        db.execute("CREATE TABLE t (a INTEGER CHECK (a <> 3), b TEXT, CHECK (length(b) < 4))").unwrap();
+        db.close().unwrap();
+    }
+    let db = Database::open(&path).unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'abc')").unwrap();
+    assert!(db.execute("INSERT INTO t VALUES (3, 'abc')").is_err());
+    assert!(db.execute("INSERT INTO t VALUES (1, 'abcd')").is_err());
+}
+
+#[test]
+fn multi_line_check_constraint_survives_reopen() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("check.hdb");
+    {
+        let db = Database::open(&path).unwrap();
+        db.execute(
+            "CREATE TABLE t (\n  a TEXT CHECK (a <> 'line one\nline two'),\n  b TEXT DEFAULT 'it''s\\n',\n  CHECK (b <> 'x\r\ny' AND b <> 'back\\slash')\n)",
+        )
+        .unwrap();
+        db.close().unwrap();
+    }
+    let db = Database::open(&path).unwrap();
+    db.execute("INSERT INTO t (a) VALUES ('ok')").unwrap();
+    assert!(db.execute("INSERT INTO t (a) VALUES ('line one\nline two')").is_err());
+    assert!(db.execute("INSERT INTO t VALUES ('ok', 'x\r\ny')").is_err());
+    assert!(db.execute("INSERT INTO t VALUES ('ok', 'back\\slash')").is_err());
+    db.execute("INSERT INTO t VALUES ('ok', 'back\\\\slash')").unwrap();
+    let b = db.query("SELECT b FROM t WHERE a = 'ok' LIMIT 1").unwrap();
+    assert_eq!(b.rows[0].values[0], Value::Text("it's\\n".into()));
+}
+
+// ---- UNIQUE Constraint Tests ----
+
+#[test]
+fn unique_text_column_rejects_duplicate() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'a@example.com')").unwrap();
+
+    let err = db.execute("INSERT INTO users VALUES (2, 'a@example.com')").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(ref m) if m.contains("users.email")));
+
+    // NULLs never conflict with each other
+    db.execute("INSERT INTO users VALUES (3, NULL)").unwrap();
+    db.execute("INSERT INTO users VALUES (4, NULL)").unwrap();
+    assert_eq!(db.query("SELECT * FROM users").unwrap().len(), 3);
+}
+
+#[test]
+fn unique_update_to_duplicate_fails() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE)").unwrap();
+    db.execute("CREATE INDEX idx_email ON users (email)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'a'), (2, 'b')").unwrap();
+
+    let err = db.execute("UPDATE users SET email = 'a' WHERE id = 2").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+
+    // Setting a row to its own value is not a conflict, and the index
+    // follows the change so the old value becomes free again.
+    db.execute("UPDATE users SET email = 'a' WHERE id = 1").unwrap();
+    db.execute("UPDATE users SET email = 'c' WHERE id = 1").unwrap();
+    db.execute("UPDATE users SET email = 'a' WHERE id = 2").unwrap();
+    let result = db.query("SELECT id FROM users WHERE email = 'c'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+}
+
+#[test]
+fn unique_insert_or_replace_overwrites_conflicting_row() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE, name TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'a', 'old')").unwrap();
+    db.execute("INSERT INTO users VALUES (2, 'b', 'other')").unwrap();
+
+    db.execute("INSERT OR REPLACE INTO users VALUES (3, 'a', 'new')").unwrap();
+
+    let result = db.query("SELECT id, name FROM users ORDER BY id").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[0], Value::Integer(2));
+    assert_eq!(result.rows[1].values[0], Value::Integer(3));
+    assert_eq!(result.rows[1].values[1], Value::Text("new".into()));
+}
+
+#[test]
+fn unique_replace_is_undone_by_rollback() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE)").unwrap();
+    db.execute("CREATE INDEX idx_email ON users (email)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'a')").unwrap();
+
+    db.execute("BEGIN").unwrap();
+    db.execute("REPLACE INTO users VALUES (2, 'a')").unwrap();
+    db.execute("ROLLBACK").unwrap();
+
+    let result = db.query("SELECT id FROM users WHERE email = 'a'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+    assert!(db.execute("INSERT INTO users VALUES (3, 'a')").is_err());
+}
+
+// ---- FOREIGN KEY Tests ----
+
+fn open_fk_db(on_delete: &str) -> (TempDir, Database) {
+    let (dir, db) = open_db();
+    db.execute("PRAGMA foreign_keys = ON").unwrap();
+    db.execute("CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute(&format!(
+        "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, \
+         author_id INTEGER REFERENCES authors (id) ON DELETE {})",
+        on_delete
+    )).unwrap();
+    db.execute("INSERT INTO authors VALUES (1, 'le guin'), (2, 'herbert')").unwrap();
+    db.execute("INSERT INTO books VALUES (10, 'earthsea', 1), (11, 'dispossessed', 1), (12, 'dune', 2)").unwrap();
+    (dir, db)
+}
+
+#[test]
+fn foreign_key_insert_without_parent_fails() {
+    let (_dir, db) = open_fk_db("NO ACTION");
+    let err = db.execute("INSERT INTO books VALUES (13, 'orphan', 99)").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+    let err = db.execute("UPDATE books SET author_id = 99 WHERE id = 12").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+
+    // A NULL key references nothing and is always allowed
+    db.execute("INSERT INTO books VALUES (13, 'anonymous', NULL)").unwrap();
+    assert_eq!(db.query("SELECT * FROM books").unwrap().len(), 4);
+}
+
+#[test]
+fn foreign_key_restricts_parent_delete() {
+    let (_dir, db) = open_fk_db("RESTRICT");
+    let err = db.execute("DELETE FROM authors WHERE id = 1").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+
+    db.execute("DELETE FROM books WHERE author_id = 1").unwrap();
+    db.execute("DELETE FROM authors WHERE id = 1").unwrap();
+    assert_eq!(db.query("SELECT * FROM authors").unwrap().len(), 1);
+}
+
+#[test]
+fn foreign_key_restricts_parent_key_update() {
+    let (_dir, db) = open_db();
+    db.execute("PRAGMA foreign_keys = ON").unwrap();
+    db.execute("CREATE TABLE p (code TEXT PRIMARY KEY, label TEXT)").unwrap();
+    db.execute("CREATE TABLE c (id INTEGER PRIMARY KEY, code TEXT REFERENCES p (code) ON UPDATE RESTRICT)").unwrap();
+    db.execute("INSERT INTO p VALUES ('a', 'first'), ('b', 'second')").unwrap();
+    db.execute("INSERT INTO c VALUES (1, 'a')").unwrap();
+
+    let err = db.execute("UPDATE p SET code = 'z', label = 'changed' WHERE code = 'a'").unwrap_err();
+    assert!(matches!(err, HorizonError::ConstraintViolation(_)));
+    let parent = db.query("SELECT code, label FROM p ORDER BY code").unwrap();
+    assert_eq!(parent.len(), 2);
+    assert_eq!(parent.rows[0].values, vec![Value::Text("a".into()), Value::Text("first".into())]);
+    assert_eq!(db.query("SELECT * FROM p WHERE code = 'z'").unwrap().len(), 0);
+
+    // An unreferenced parent key may still change
+    db.execute("UPDATE p SET code = 'y' WHERE code = 'b'").unwrap();
+    assert_eq!(db.query("SELECT * FROM p WHERE code = 'y'").unwrap().len(), 1);
+}
+
+#[test]
+fn foreign_key_cascade_delete_removes_children() {
+    let (_dir, db) = open_fk_db("CASCADE");
+    db.execute("DELETE FROM authors WHERE id = 1").unwrap();
+
+    let result = db.query("SELECT title FROM books").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("dune".into()));
+}
+
+#[test]
+fn foreign_key_set_null_on_delete() {
+    let (_dir, db) = open_fk_db("SET NULL");
+    db.execute("DELETE FROM authors WHERE id = 1").unwrap();
+
+    let result = db.query("SELECT id FROM books WHERE author_id IS NULL ORDER BY id").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[0], Value::Integer(10));
+    assert_eq!(db.query("SELECT * FROM books WHERE author_id = 2").unwrap().len(), 1);
+}
+
+#[test]
+fn foreign_key_on_update_cascade() {
+    let (_dir, db) = open_db();
+    db.execute("PRAGMA foreign_keys = ON").unwrap();
+    db.execute("CREATE TABLE p (code TEXT PRIMARY KEY)").unwrap();
+    db.execute("CREATE TABLE c (id INTEGER PRIMARY KEY, code TEXT, FOREIGN KEY (code) REFERENCES p (code) ON UPDATE CASCADE)").unwrap();
+    db.execute("INSERT INTO p VALUES ('a')").unwrap();
+    db.execute("INSERT INTO c VALUES (1, 'a')").unwrap();
+
+    db.execute("UPDATE p SET code = 'b' WHERE code = 'a'").unwrap();
+    let result = db.query("SELECT code FROM c").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("b".into()));
+}
+
+#[test]
+fn foreign_keys_pragma_toggles_enforcement() {
+    let (_dir, db) = open_fk_db("NO ACTION");
+    let result = db.query("PRAGMA foreign_keys").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+
+    db.execute("PRAGMA foreign_keys = OFF").unwrap();
+    db.execute("INSERT INTO books VALUES (13, 'orphan', 99)").unwrap();
+    db.execute("DELETE FROM authors WHERE id = 2").unwrap();
+    let result = db.query("PRAGMA foreign_keys").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(0));
+}
+
 // ---- INSERT OR REPLACE Tests ----
 
 #[test]
@@ -684,6 +1208,52 @@ fn insert_duplicate_pk_without_replace_fails() {
     assert!(err.is_err());
 }
 
+// ---- INSERT ... SELECT Tests ----
+
+#[test]
+fn insert_select_copies_matching_rows() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE live (id INTEGER PRIMARY KEY, date TEXT, note TEXT)").unwrap();
+    db.execute("CREATE TABLE archive (id INTEGER PRIMARY KEY, date TEXT, note TEXT)").unwrap();
+    db.execute(
+        "INSERT INTO live VALUES (1, '2019-05-01', 'old'), (2, '2021-01-01', 'new'), (3, '2018-12-31', 'older')",
+    )
+    .unwrap();
+
+    let n = db.execute("INSERT INTO archive SELECT * FROM live WHERE date < '2020-01-01'").unwrap();
+    assert_eq!(n, 2);
+    let ids: Vec<Value> = db
+        .query("SELECT id FROM archive ORDER BY id")
+        .unwrap()
+        .rows
+        .into_iter()
+        .map(|r| r.values[0].clone())
+        .collect();
+    assert_eq!(ids, vec![Value::Integer(1), Value::Integer(3)]);
+
+    // An empty result inserts nothing
+    let n = db.execute("INSERT INTO archive SELECT * FROM live WHERE id > 100").unwrap();
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn insert_select_respects_column_list() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t1 (a INTEGER, b TEXT)").unwrap();
+    db.execute("CREATE TABLE t2 (a TEXT, b INTEGER, c INTEGER DEFAULT 9)").unwrap();
+    db.execute("INSERT INTO t1 VALUES (1, 'x'), (2, 'y')").unwrap();
+
+    assert_eq!(db.execute("INSERT INTO t2 (a, b) SELECT b, a FROM t1").unwrap(), 2);
+    let rows = db.query("SELECT a, b, c FROM t2 ORDER BY b").unwrap();
+    assert_eq!(rows.rows[1].values, vec![Value::Text("y".into()), Value::Integer(2), Value::Integer(9)]);
+
+    // The source query is evaluated before any row is inserted
+    assert_eq!(db.execute("INSERT INTO t1 SELECT a + 10, b FROM t1").unwrap(), 2);
+    assert_eq!(db.query("SELECT * FROM t1").unwrap().len(), 4);
+
+    assert!(db.execute("INSERT INTO t2 (a, b) SELECT a FROM t1").is_err());
+}
+
 // ---- BETWEEN Expression Tests ----
 
 #[test]
@@ -965,7 +1535,104 @@ fn inner_join_with_where_clause() {
     assert_eq!(result.len(), 2);
 }
 
-// ---- Aggregate Tests ----
+#[test]
+fn full_outer_join_both_sides_unmatched() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE l (id INTEGER PRIMARY KEY, k INTEGER, lv TEXT)").unwrap();
+    db.execute("CREATE TABLE r (id INTEGER PRIMARY KEY, k INTEGER, rv TEXT)").unwrap();
+
+    db.execute("INSERT INTO l VALUES (1, 1, 'l1'), (2, 2, 'l2'), (3, 3, 'l3')").unwrap();
+    db.execute("INSERT INTO r VALUES (1, 2, 'r2'), (2, 3, 'r3'), (3, 4, 'r4')").unwrap();
+
+    // Equi-join (hash join path)
+    let result = db.query("SELECT l.lv, r.rv FROM l FULL OUTER JOIN r ON l.k = r.k").unwrap();
+    let mut pairs: Vec<(Value, Value)> = result.rows.iter()
+        .map(|row| (row.values[0].clone(), row.values[1].clone()))
+        .collect();
+    pairs.sort();
+    assert_eq!(pairs, vec![
+        (Value::Null, Value::Text("r4".into())),
+        (Value::Text("l1".into()), Value::Null),
+        (Value::Text("l2".into()), Value::Text("r2".into())),
+        (Value::Text("l3".into()), Value::Text("r3".into())),
+    ]);
+
+    // Non-equi condition (nested-loop path) without the OUTER keyword
+    let result = db.query("SELECT l.lv, r.rv FROM l FULL JOIN r ON l.k = r.k AND r.k < 3").unwrap();
+    // l2/r2 match; l1, l3 unmatched on the left; r3, r4 unmatched on the right
+    assert_eq!(result.len(), 5);
+    let unmatched_right = result.rows.iter().filter(|row| row.values[0].is_null()).count();
+    assert_eq!(unmatched_right, 2);
+}
+
+#[test]
+fn full_outer_join_with_empty_side() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE l (id INTEGER PRIMARY KEY, k INTEGER)").unwrap();
+    db.execute("CREATE TABLE r (id INTEGER PRIMARY KEY, k INTEGER)").unwrap();
+    db.execute("INSERT INTO r VALUES (1, 10), (2, 20)").unwrap();
+
+    let result = db.query("SELECT l.k, r.k FROM l FULL OUTER JOIN r ON l.k = r.k").unwrap();
+    assert_eq!(result.len(), 2);
+    assert!(result.rows.iter().all(|row| row.values[0].is_null()));
+
+    let result = db.query("SELECT l.k, r.k FROM r FULL OUTER JOIN l ON l.k = r.k").unwrap();
+    assert_eq!(result.len(), 2);
+    assert!(result.rows.iter().all(|row| row.values[0].is_null() && !row.values[1].is_null()));
+}
+
+#[test]
+fn join_using_deduplicates_join_column() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE emp (id INTEGER PRIMARY KEY, dept_id INTEGER, name TEXT)").unwrap();
+    db.execute("CREATE TABLE dept (dept_id INTEGER PRIMARY KEY, title TEXT)").unwrap();
+    db.execute("INSERT INTO emp VALUES (1, 10, 'ann'), (2, 20, 'bob'), (3, 30, 'cy')").unwrap();
+    db.execute("INSERT INTO dept VALUES (10, 'eng'), (20, 'ops')").unwrap();
+
+    let result = db.query("SELECT * FROM emp JOIN dept USING (dept_id) ORDER BY id").unwrap();
+    assert_eq!(result.columns.len(), 4);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values, vec![
+        Value::Integer(1), Value::Integer(10), Value::Text("ann".into()), Value::Text("eng".into()),
+    ]);
+
+    // The merged column is unambiguous and takes the right value for unmatched right rows
+    db.execute("INSERT INTO dept VALUES (40, 'hr')").unwrap();
+    let result = db.query("SELECT dept_id, title FROM emp RIGHT JOIN dept USING (dept_id) ORDER BY dept_id").unwrap();
+    assert_eq!(result.len(), 3);
+    assert_eq!(result.rows[2].values, vec![Value::Integer(40), Value::Text("hr".into())]);
+}
+
+#[test]
+fn natural_join_matches_shared_columns() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE a (x INTEGER, y INTEGER, av TEXT)").unwrap();
+    db.execute("CREATE TABLE b (y INTEGER, x INTEGER, bv TEXT)").unwrap();
+    db.execute("INSERT INTO a VALUES (1, 1, 'a11'), (1, 2, 'a12'), (2, 2, 'a22')").unwrap();
+    db.execute("INSERT INTO b VALUES (1, 1, 'b11'), (2, 1, 'b12'), (9, 9, 'b99')").unwrap();
+
+    let result = db.query("SELECT * FROM a NATURAL JOIN b ORDER BY av").unwrap();
+    assert_eq!(result.columns.len(), 4);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[2], Value::Text("a11".into()));
+    assert_eq!(result.rows[0].values[3], Value::Text("b11".into()));
+    assert_eq!(result.rows[1].values[2], Value::Text("a12".into()));
+    assert_eq!(result.rows[1].values[3], Value::Text("b12".into()));
+
+    let result = db.query("SELECT av, bv FROM a NATURAL LEFT JOIN b WHERE bv IS NULL").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("a22".into()));
+}
+
+#[test]
+fn join_using_unknown_column_errors() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE a (x INTEGER)").unwrap();
+    db.execute("CREATE TABLE b (y INTEGER)").unwrap();
+    assert!(db.query("SELECT * FROM a JOIN b USING (x)").is_err());
+}
+
+// ---- Aggregate Tests ----
 
 #[test]
 fn count_star_basic() {
@@ -1176,6 +1843,95 @@ fn group_by_multiple_columns() {
     ]);
 }
 
+#[test]
+fn aggregate_filter_clause() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE scores (id INTEGER PRIMARY KEY, team TEXT, category TEXT, score INTEGER)").unwrap();
+    db.execute(
+        "INSERT INTO scores VALUES (1, 'red', 'A', 40), (2, 'red', 'B', 60), (3, 'red', 'A', 90), \
+         (4, 'blue', 'A', 10), (5, 'blue', 'B', NULL)",
+    )
+    .unwrap();
+
+    let result = db
+        .query("SELECT team, COUNT(*) FILTER (WHERE score > 50), COUNT(*) FROM scores GROUP BY team ORDER BY team")
+        .unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Text("blue".into()), Value::Integer(0), Value::Integer(2)]);
+    assert_eq!(result.rows[1].values, vec![Value::Text("red".into()), Value::Integer(2), Value::Integer(3)]);
+
+    let result = db
+        .query("SELECT SUM(score) FILTER (WHERE category = 'A'), SUM(score), COUNT(score) FROM scores")
+        .unwrap();
+    // The NULL score is skipped by SUM and COUNT with or without a filter
+    assert_eq!(result.rows[0].values, vec![Value::Integer(140), Value::Integer(200), Value::Integer(4)]);
+}
+
+#[test]
+fn aggregate_distinct_variants() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, grp TEXT, name TEXT, val INTEGER)").unwrap();
+    db.execute(
+        "INSERT INTO t VALUES (1, 'x', 'ann', 10), (2, 'x', 'bob', 10), (3, 'x', 'ann', 20), \
+         (4, 'x', NULL, 30), (5, 'y', 'cat', 5), (6, 'y', 'cat', 5)",
+    )
+    .unwrap();
+
+    let result = db
+        .query(
+            "SELECT COUNT(DISTINCT name), SUM(DISTINCT val), AVG(DISTINCT val), COUNT(name), SUM(val) \
+             FROM t WHERE grp = 'x'",
+        )
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values,
+        vec![Value::Integer(2), Value::Integer(60), Value::Real(20.0), Value::Integer(3), Value::Integer(70)]
+    );
+
+    let result = db
+        .query("SELECT grp, GROUP_CONCAT(DISTINCT name), COUNT(DISTINCT val) FROM t GROUP BY grp ORDER BY grp")
+        .unwrap();
+    assert_eq!(result.rows[0].values[1], Value::Text("ann,bob".into()));
+    assert_eq!(result.rows[0].values[2], Value::Integer(3));
+    assert_eq!(result.rows[1].values[1], Value::Text("cat".into()));
+    assert_eq!(result.rows[1].values[2], Value::Integer(1));
+}
+
+#[test]
+fn aggregate_stddev_and_variance() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, grp TEXT, val)").unwrap();
+    db.execute(
+        "INSERT INTO t VALUES (1, 'a', 2), (2, 'a', 4), (3, 'a', 4), (4, 'a', 4), \
+         (5, 'a', 5), (6, 'a', 5), (7, 'a', 7), (8, 'a', 9), (9, 'b', 3), (10, 'c', NULL), \
+         (11, 'm', 1), (12, 'm', 2.5), (13, 'm', 4)",
+    )
+    .unwrap();
+
+    // Mean 5, squared deviations sum to 32
+    let result = db
+        .query(
+            "SELECT STDDEV(val), STDDEV_POP(val), VARIANCE(val), VAR_POP(val), VAR_SAMP(val), \
+             STDDEV_SAMP(val) FROM t WHERE grp = 'a'",
+        )
+        .unwrap();
+    let v = &result.rows[0].values;
+    assert_eq!(v[..4], [Value::Real(2.0), Value::Real(2.0), Value::Real(4.0), Value::Real(4.0)]);
+    assert_eq!(v[4], Value::Real(32.0 / 7.0));
+    assert_eq!(v[5], Value::Real((32.0f64 / 7.0).sqrt()));
+
+    // Single row: zero spread for the population, undefined for a sample
+    let result = db
+        .query("SELECT grp, VAR_POP(val), VAR_SAMP(val), STDDEV_SAMP(val) FROM t GROUP BY grp ORDER BY grp")
+        .unwrap();
+    assert_eq!(result.rows[1].values[1..], [Value::Real(0.0), Value::Null, Value::Null]);
+    // Only NULLs, and mixed integer and real values
+    assert_eq!(result.rows[2].values[1..], [Value::Null, Value::Null, Value::Null]);
+    assert_eq!(result.rows[3].values[1], Value::Real(1.5));
+
+    let result = db.query("SELECT STDDEV(val), VAR_SAMP(val) FROM t WHERE grp = 'none'").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Null, Value::Null]);
+}
+
 // ---- CASE Expression Tests ----
 
 #[test]
@@ -1307,6 +2063,56 @@ fn cast_text_to_real() {
     assert_eq!(result.rows[0].values[0], Value::Real(3.14));
 }
 
+// ---- GLOB / REGEXP Tests ----
+
+#[test]
+fn glob_operator_matches_wildcards() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE files (name TEXT)").unwrap();
+    db.execute("INSERT INTO files VALUES ('main.rs'), ('lib.RS'), ('notes.txt'), ('a1.rs')").unwrap();
+
+    let result = db.query("SELECT name FROM files WHERE name GLOB '*.rs' ORDER BY name").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[0], Value::Text("a1.rs".into()));
+    assert_eq!(result.rows[1].values[0], Value::Text("main.rs".into()));
+
+    assert_eq!(db.query("SELECT * FROM files WHERE name GLOB '[a-c][0-9].??'").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM files WHERE name NOT GLOB '*.rs'").unwrap().len(), 2);
+
+    let result = db.query("SELECT NULL GLOB '*'").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Null);
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn regexp_operator_and_function() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (email TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES ('alice@example.com'), ('Bob@example.com'), ('carol@example.org')").unwrap();
+
+    let result = db.query(r"SELECT email FROM users WHERE email REGEXP '^[a-z]+@example\.com$'").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("alice@example.com".into()));
+    assert_eq!(db.query("SELECT * FROM users WHERE email NOT REGEXP 'example'").unwrap().len(), 0);
+
+    let result = db.query("SELECT regexp('^b', 'bob'), NULL REGEXP 'x', 'x' REGEXP NULL").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+    assert_eq!(result.rows[0].values[1], Value::Null);
+    assert_eq!(result.rows[0].values[2], Value::Null);
+
+    assert!(db.query("SELECT * FROM users WHERE email REGEXP '('").is_err());
+}
+
+#[cfg(not(feature = "regex"))]
+#[test]
+fn regexp_requires_regex_feature() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (s TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES ('abc')").unwrap();
+    let result = db.query("SELECT * FROM t WHERE s REGEXP 'b'");
+    assert!(matches!(result, Err(HorizonError::NotImplemented(_))));
+}
+
 // ---- Built-in String Function Tests ----
 
 #[test]
@@ -1398,6 +2204,120 @@ fn hex_function() {
     assert_eq!(result.rows[0].values[0], Value::Text("4142".to_string()));
 }
 
+#[test]
+fn pad_repeat_reverse_functions() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT LPAD('5', 3, '0'), RPAD('ab', 5, '.'), REPEAT('ab', 3), REVERSE('hello'), REVERSE('こんにちは')")
+        .unwrap();
+    let expected: Vec<Value> = ["005", "ab...", "ababab", "olleh", "はちにんこ"]
+        .iter()
+        .map(|&s| s.into())
+        .collect();
+    assert_eq!(result.rows[0].values, expected);
+
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, code INTEGER, name TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 42, 'ünï'), (2, 7, NULL)").unwrap();
+    let result = db
+        .query("SELECT id, LPAD(code, 5, '0'), RPAD(name, 5, '*'), REPEAT(name, 2), REPEAT('x', -1) FROM t ORDER BY id")
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values[1..],
+        [
+            Value::Text("00042".into()),
+            Value::Text("ünï**".into()),
+            Value::Text("ünïünï".into()),
+            Value::Text("".into()),
+        ]
+    );
+    assert_eq!(result.rows[1].values[2], Value::Null);
+    assert_eq!(result.rows[1].values[3], Value::Null);
+}
+
+#[test]
+fn split_part_function() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT SPLIT_PART('a,b,c', ',', 2), SPLIT_PART('a,b,c', ',', 4), SPLIT_PART('a,b,c', ',', -1), SPLIT_PART('abc', ';', 1)")
+        .unwrap();
+    let expected: Vec<Value> = ["b", "", "c", "abc"].iter().map(|&s| s.into()).collect();
+    assert_eq!(result.rows[0].values, expected);
+
+    db.execute("CREATE TABLE emails (id INTEGER PRIMARY KEY, addr TEXT)").unwrap();
+    db.execute("INSERT INTO emails VALUES (1, 'ann@example.com'), (2, 'bob@test.org'), (3, NULL)").unwrap();
+    let result = db
+        .query("SELECT id, SPLIT_PART(addr, '@', 2) FROM emails ORDER BY id")
+        .unwrap();
+    assert_eq!(result.rows[0].values[1], Value::Text("example.com".into()));
+    assert_eq!(result.rows[2].values[1], Value::Null);
+
+    assert!(db.query("SELECT SPLIT_PART(addr, '', 1) FROM emails").is_err());
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn hash_functions() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT SHA256(''), MD5('hello'), MD5(X'68656C6C6F'), SHA256(NULL), HMAC_SHA256('key', NULL)")
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values,
+        vec![
+            Value::Text("E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855".into()),
+            Value::Text("5D41402ABC4B2A76B9719D911017C592".into()),
+            Value::Text("5D41402ABC4B2A76B9719D911017C592".into()),
+            Value::Null,
+            Value::Null,
+        ]
+    );
+
+    let result = db
+        .query("SELECT HMAC_SHA256('key', 'The quick brown fox jumps over the lazy dog')")
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values[0],
+        Value::Text("F7BC83F430538424B13298E6AA6FB143EF4D59A14946175997479DBC2D1A3CD8".into())
+    );
+}
+
+#[cfg(not(feature = "crypto"))]
+#[test]
+fn hash_functions_require_crypto_feature() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (s TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES ('abc')").unwrap();
+    let result = db.query("SELECT SHA256(s) FROM t");
+    assert!(matches!(result, Err(HorizonError::NotImplemented(_))));
+}
+
+#[test]
+fn uuid_functions() {
+    let (_dir, db) = open_db();
+    let result = db.query("SELECT UUID(), UUID(), LENGTH(UUID_BLOB()), TYPEOF(UUID_BLOB())").unwrap();
+    let values = &result.rows[0].values;
+    let (a, b) = match (&values[0], &values[1]) {
+        (Value::Text(a), Value::Text(b)) => (a.clone(), b.clone()),
+        _ => panic!("UUID() should return text"),
+    };
+    assert_ne!(a, b);
+    assert_eq!(values[2], Value::Integer(16));
+    assert_eq!(values[3], Value::Text("blob".into()));
+
+    // xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx, with y one of 8, 9, a, b
+    let groups: Vec<&str> = a.split('-').collect();
+    assert_eq!(groups.iter().map(|g| g.len()).collect::<Vec<_>>(), [8, 4, 4, 4, 12]);
+    assert!(a.chars().all(|c| c == '-' || c.is_ascii_digit() || ('a'..='f').contains(&c)));
+    assert!(groups[2].starts_with('4'));
+    assert!(groups[3].starts_with(['8', '9', 'a', 'b']));
+
+    db.execute("CREATE TABLE t (id TEXT PRIMARY KEY, n INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (UUID(), 1)").unwrap();
+    db.execute("INSERT INTO t VALUES (UUID(), 2)").unwrap();
+    assert_eq!(db.query("SELECT DISTINCT id FROM t").unwrap().len(), 2);
+    assert!(db.query("SELECT UUID(n) FROM t").is_err());
+}
+
 // ---- Built-in Math Function Tests ----
 
 #[test]
@@ -1478,6 +2398,75 @@ fn zeroblob_function() {
     assert_eq!(result.rows[0].values[0], Value::Blob(vec![0, 0, 0, 0]));
 }
 
+#[test]
+fn math_functions() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT CEIL(1.1), FLOOR(-1.1), TRUNC(-2.7), SQRT(16), POWER(2, 10), LOG(1), LOG10(1000), LOG2(8)")
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values,
+        vec![
+            Value::Real(2.0),
+            Value::Real(-2.0),
+            Value::Real(-2.0),
+            Value::Real(4.0),
+            Value::Real(1024.0),
+            Value::Real(0.0),
+            Value::Real(3.0),
+            Value::Real(3.0),
+        ]
+    );
+
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER, b REAL)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, -7, 5.5), (2, 0, NULL)").unwrap();
+    let result = db
+        .query("SELECT id, SIGN(a), MOD(a, 3), MOD(b, 2), CEIL(a), SQRT(a), POW(b, 2) FROM t ORDER BY id")
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values[1..],
+        [
+            Value::Integer(-1),
+            Value::Integer(-1),
+            Value::Real(1.5),
+            Value::Integer(-7),
+            Value::Null,
+            Value::Real(30.25),
+        ]
+    );
+    // NULL propagates
+    assert_eq!(result.rows[1].values[1], Value::Integer(0));
+    assert_eq!(result.rows[1].values[3], Value::Null);
+    assert_eq!(result.rows[1].values[6], Value::Null);
+
+    // Math functions work in WHERE on a table scan too
+    let result = db.query("SELECT id FROM t WHERE FLOOR(b) = 5").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert!(db.query("SELECT POW(2) FROM t").is_err());
+}
+
+#[test]
+fn trigonometric_functions() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT SIN(0), COS(0), TAN(0), ATAN2(1, 1), DEGREES(PI()), ASIN(2), ACOS(1), ATAN(0)")
+        .unwrap();
+    let v = &result.rows[0].values;
+    assert_eq!(v[..3], [Value::Real(0.0), Value::Real(1.0), Value::Real(0.0)]);
+    let close = |val: &Value, expected: f64| matches!(val, Value::Real(r) if (r - expected).abs() < 1e-12);
+    assert!(close(&v[3], std::f64::consts::FRAC_PI_4));
+    assert!(close(&v[4], 180.0));
+    assert_eq!(v[5], Value::Null);
+    assert_eq!(v[6..], [Value::Real(0.0), Value::Real(0.0)]);
+
+    // Integer columns are coerced to real
+    db.execute("CREATE TABLE angles (id INTEGER PRIMARY KEY, deg INTEGER)").unwrap();
+    db.execute("INSERT INTO angles VALUES (1, 90), (2, 180)").unwrap();
+    let result = db.query("SELECT id, SIN(RADIANS(deg)) FROM angles ORDER BY id").unwrap();
+    assert!(close(&result.rows[0].values[1], 1.0));
+    assert!(matches!(result.rows[1].values[1], Value::Real(r) if r.abs() < 1e-12));
+}
+
 // ---- NULL Handling Function Tests ----
 
 #[test]
@@ -1601,10 +2590,92 @@ fn exists_subquery_in_where() {
     let result = db.query(
         "SELECT name FROM users WHERE EXISTS (SELECT 1 FROM orders WHERE orders.user_id = users.id)"
     ).unwrap();
-    // Note: the subquery doesn't have correlated support yet, so it just checks if orders exist at all.
-    // Since orders table is non-empty, EXISTS returns true for all rows.
-    // This tests that EXISTS works syntactically and returns the correct boolean.
-    assert!(result.len() > 0);
+    // The subquery is correlated with the outer row, so Charlie is excluded.
+    assert_eq!(result.len(), 2);
+}
+
+#[test]
+fn exists_and_not_exists_correlated() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, customer TEXT)").unwrap();
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, order_id INTEGER, sku TEXT)").unwrap();
+    db.execute("INSERT INTO orders VALUES (1, 'ann'), (2, 'bob'), (3, 'cat')").unwrap();
+    db.execute("INSERT INTO items VALUES (1, 1, 'a'), (2, 1, 'b'), (3, 3, 'c')").unwrap();
+
+    let ids = |sql: &str| -> Vec<Value> {
+        db.query(sql).unwrap().rows.iter().map(|r| r.values[0].clone()).collect()
+    };
+    assert_eq!(
+        ids("SELECT * FROM orders WHERE EXISTS (SELECT 1 FROM items WHERE items.order_id = orders.id) ORDER BY id"),
+        vec![Value::Integer(1), Value::Integer(3)]
+    );
+    assert_eq!(
+        ids("SELECT id FROM orders WHERE NOT EXISTS (SELECT 1 FROM items WHERE items.order_id = orders.id)"),
+        vec![Value::Integer(2)]
+    );
+    // NOT EXISTS over an empty subquery keeps every row
+    assert_eq!(
+        ids("SELECT id FROM orders WHERE NOT EXISTS (SELECT 1 FROM items WHERE sku = 'zzz') ORDER BY id"),
+        vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]
+    );
+    // EXISTS in the select list
+    assert_eq!(
+        db.query("SELECT EXISTS (SELECT 1 FROM items WHERE items.order_id = o.id) FROM orders o WHERE id = 2")
+            .unwrap()
+            .rows[0]
+            .values[0],
+        Value::Integer(0)
+    );
+}
+
+#[test]
+fn correlated_subquery_in_where() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, dept TEXT)").unwrap();
+    db.execute("CREATE TABLE t2 (id INTEGER PRIMARY KEY, dept TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'ann', 'eng'), (2, 'bob', 'ops'), (3, 'cat', 'eng')").unwrap();
+    db.execute("INSERT INTO t2 VALUES (1, 'eng'), (2, 'eng'), (3, 'ops')").unwrap();
+
+    // ids 1 and 3 have an eng row in t2; id 2 (ops) only matches t2.id 3
+    let result = db
+        .query("SELECT name FROM t WHERE id IN (SELECT id FROM t2 WHERE t2.dept = t.dept) ORDER BY id")
+        .unwrap();
+    let names: Vec<Value> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(names, vec![Value::Text("ann".into())]);
+
+    // An aliased outer table, with the same table name inside the subquery
+    let result = db
+        .query(
+            "SELECT name FROM t AS o WHERE id = (SELECT MAX(id) FROM t WHERE t.dept = o.dept) ORDER BY id",
+        )
+        .unwrap();
+    let names: Vec<Value> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(names, vec![Value::Text("bob".into()), Value::Text("cat".into())]);
+}
+
+#[test]
+fn correlated_subquery_in_select_list() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE dept (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE TABLE emp (id INTEGER PRIMARY KEY, dept_id INTEGER, salary INTEGER)").unwrap();
+    db.execute("INSERT INTO dept VALUES (1, 'eng'), (2, 'ops'), (3, 'hr')").unwrap();
+    db.execute("INSERT INTO emp VALUES (1, 1, 100), (2, 1, 200), (3, 2, 50)").unwrap();
+
+    let result = db
+        .query("SELECT id, name, (SELECT SUM(salary) FROM emp WHERE emp.dept_id = dept.id) FROM dept ORDER BY id")
+        .unwrap();
+    let totals: Vec<Value> = result.rows.iter().map(|r| r.values[2].clone()).collect();
+    assert_eq!(totals, vec![Value::Integer(300), Value::Integer(50), Value::Null]);
+
+    // Correlated with one side of a join
+    let result = db
+        .query(
+            "SELECT d.name, e.id FROM dept d JOIN emp e ON e.dept_id = d.id \
+             WHERE e.salary = (SELECT MAX(salary) FROM emp x WHERE x.dept_id = d.id) ORDER BY e.id",
+        )
+        .unwrap();
+    let ids: Vec<Value> = result.rows.iter().map(|r| r.values[1].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(2), Value::Integer(3)]);
 }
 
 #[test]
@@ -1623,49 +2694,323 @@ fn scalar_subquery_returns_null_for_empty() {
     assert_eq!(result.rows[0].values[1], Value::Null);
 }
 
-// ---- Combined / Complex Tests ----
-
 #[test]
-fn case_with_cast() {
+fn scalar_subquery_in_projection_and_where() {
     let (_dir, db) = open_db();
-    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
-    db.execute("INSERT INTO t VALUES (1, '42')").unwrap();
-
-    let result = db.query(
-        "SELECT CASE WHEN CAST(val AS INTEGER) > 40 THEN 'high' ELSE 'low' END FROM t"
-    ).unwrap();
-    assert_eq!(result.rows[0].values[0], Value::Text("high".to_string()));
-}
+    db.execute("CREATE TABLE emp (id INTEGER PRIMARY KEY, name TEXT, salary INTEGER)").unwrap();
+    db.execute("CREATE TABLE mgr (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO emp VALUES (1, 'ann', 100), (2, 'bob', 200), (3, 'cat', 600)").unwrap();
+    db.execute("INSERT INTO mgr VALUES (1, 'dee')").unwrap();
 
-#[test]
-fn nested_functions() {
-    let (_dir, db) = open_db();
-    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
-    db.execute("INSERT INTO t VALUES (1, '  Hello World  ')").unwrap();
+    let result = db.query("SELECT (SELECT MAX(salary) FROM emp) AS max_sal, name FROM mgr").unwrap();
+    assert_eq!(result.columns[0], "max_sal");
+    assert_eq!(result.rows[0].values, vec![Value::Integer(600), Value::Text("dee".into())]);
 
-    let result = db.query("SELECT UPPER(TRIM(val)) FROM t").unwrap();
-    assert_eq!(result.rows[0].values[0], Value::Text("HELLO WORLD".to_string()));
+    let result = db.query("SELECT name FROM emp WHERE salary > (SELECT AVG(salary) FROM emp)").unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("cat".into()));
 }
 
 #[test]
-fn iif_with_null_handling() {
+fn scalar_subquery_must_return_one_row_and_column() {
     let (_dir, db) = open_db();
     db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val INTEGER)").unwrap();
-    db.execute("INSERT INTO t VALUES (1, NULL)").unwrap();
-    db.execute("INSERT INTO t VALUES (2, 5)").unwrap();
-
-    let result = db.query("SELECT id, IIF(val IS NULL, 'missing', CAST(val AS TEXT)) FROM t ORDER BY id").unwrap();
-    assert_eq!(result.len(), 2);
-    assert_eq!(result.rows[0].values[1], Value::Text("missing".to_string()));
-    assert_eq!(result.rows[1].values[1], Value::Text("5".to_string()));
+    db.execute("INSERT INTO t VALUES (1, 10), (2, 20)").unwrap();
+
+    assert!(matches!(
+        db.query("SELECT (SELECT val FROM t) FROM t"),
+        Err(HorizonError::TooManyRows)
+    ));
+    assert!(matches!(
+        db.query("SELECT id FROM t WHERE val = (SELECT id, val FROM t WHERE id = 1)"),
+        Err(HorizonError::InvalidSql(_))
+    ));
+    // A single matching row is fine
+    let result = db.query("SELECT (SELECT val FROM t WHERE id = 2)").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(20));
 }
 
 #[test]
-fn functions_in_where_clause() {
+fn from_subquery_nested_and_aggregated() {
     let (_dir, db) = open_db();
-    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
-    db.execute("INSERT INTO t VALUES (1, 'Alice')").unwrap();
-    db.execute("INSERT INTO t VALUES (2, 'Bob')").unwrap();
+    db.execute("CREATE TABLE emp (id INTEGER PRIMARY KEY, dept TEXT, salary INTEGER)").unwrap();
+    db.execute("INSERT INTO emp VALUES (1, 'eng', 100), (2, 'eng', 200), (3, 'ops', 50), (4, 'ops', 70)").unwrap();
+
+    // Two levels of nesting, with qualified and unqualified references
+    let result = db
+        .query(
+            "SELECT outer_sub.id FROM (SELECT id, salary FROM (SELECT * FROM emp WHERE salary > 60) AS inner_sub \
+             WHERE inner_sub.salary < 150) AS outer_sub ORDER BY id",
+        )
+        .unwrap();
+    let ids: Vec<Value> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(1), Value::Integer(4)]);
+
+    // Aggregation inside the subquery, filtered and ordered outside
+    let result = db
+        .query(
+            "SELECT s.dept, total FROM (SELECT dept, SUM(salary) AS total FROM emp GROUP BY dept) AS s \
+             WHERE total > 100 ORDER BY total DESC",
+        )
+        .unwrap();
+    assert_eq!(result.columns.as_slice(), ["dept".to_string(), "total".to_string()]);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values, vec![Value::Text("eng".into()), Value::Integer(300)]);
+
+    // Aggregation over the subquery
+    let result = db.query("SELECT COUNT(*), MAX(salary) FROM (SELECT * FROM emp WHERE dept = 'ops') t").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Integer(2), Value::Integer(70)]);
+}
+
+#[test]
+fn from_subquery_joined_with_table() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER, amount INTEGER)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice'), (2, 'bob'), (3, 'carol')").unwrap();
+    db.execute("INSERT INTO orders VALUES (1, 1, 10), (2, 1, 5), (3, 2, 7)").unwrap();
+
+    let result = db
+        .query(
+            "SELECT u.name, o.spent FROM users u \
+             JOIN (SELECT user_id, SUM(amount) AS spent FROM orders GROUP BY user_id) AS o \
+             ON o.user_id = u.id ORDER BY name",
+        )
+        .unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values, vec![Value::Text("alice".into()), Value::Integer(15)]);
+    assert_eq!(result.rows[1].values, vec![Value::Text("bob".into()), Value::Integer(7)]);
+
+    // A reference to the outer query is rejected with a clear error
+    let err = db
+        .query("SELECT * FROM users u JOIN (SELECT * FROM orders WHERE orders.user_id = u.id) AS o ON 1 = 1")
+        .err()
+        .unwrap();
+    assert!(matches!(err, HorizonError::InvalidSql(ref msg) if msg.contains("correlated")), "{}", err);
+}
+
+// ---- VALUES Table Tests ----
+
+#[test]
+fn values_in_from_with_column_aliases() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT * FROM (VALUES (1, 'hello'), (2, 'world')) AS v(n, s)")
+        .unwrap();
+    assert_eq!(result.columns.as_ref(), &["n".to_string(), "s".to_string()]);
+    assert_eq!(result.rows.len(), 2);
+    assert_eq!(result.rows[1].values, vec![Value::Integer(2), Value::Text("world".into())]);
+
+    let result = db
+        .query("SELECT s FROM (VALUES (1, 'a'), (2 + 1, 'b')) v(n, s) WHERE n > 2")
+        .unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("b".into()));
+
+    assert!(db.query("SELECT * FROM (VALUES (1, 2), (3)) AS v").is_err());
+    assert!(db.query("SELECT * FROM (VALUES (1, 2)) AS v(a)").is_err());
+}
+
+#[test]
+fn values_joined_with_table() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice'), (2, 'bob'), (3, 'carol')").unwrap();
+
+    let result = db
+        .query(
+            "SELECT users.name, r.role FROM users \
+             JOIN (VALUES (1, 'admin'), (3, 'editor')) AS r(uid, role) ON r.uid = users.id \
+             ORDER BY users.name",
+        )
+        .unwrap();
+    let pairs: Vec<(Value, Value)> = result
+        .rows
+        .iter()
+        .map(|r| (r.values[0].clone(), r.values[1].clone()))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![
+            (Value::Text("alice".into()), Value::Text("admin".into())),
+            (Value::Text("carol".into()), Value::Text("editor".into())),
+        ]
+    );
+}
+
+#[test]
+fn values_as_standalone_select_and_compound_arm() {
+    let (_dir, db) = open_db();
+    let result = db.query("VALUES (1, 'x'), (2, 'y')").unwrap();
+    assert_eq!(result.columns.as_ref(), &["column1".to_string(), "column2".to_string()]);
+    assert_eq!(result.rows.len(), 2);
+
+    let result = db.query("SELECT 1 UNION VALUES (2), (3) ORDER BY 1").unwrap();
+    let values: Vec<Value> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(values, vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]);
+
+    let result = db.query("VALUES (1) UNION ALL SELECT 1").unwrap();
+    assert_eq!(result.rows.len(), 2);
+}
+
+// ---- Table-Valued Function Tests ----
+
+#[test]
+fn generate_series_integers() {
+    let (_dir, db) = open_db();
+    let values = |sql: &str| -> Vec<Value> {
+        db.query(sql).unwrap().rows.iter().map(|r| r.values[0].clone()).collect()
+    };
+
+    assert_eq!(values("SELECT value FROM generate_series(1, 10)"), (1..=10).map(Value::Integer).collect::<Vec<_>>());
+    assert_eq!(
+        values("SELECT value FROM generate_series(10, 1, -3)"),
+        vec![Value::Integer(10), Value::Integer(7), Value::Integer(4), Value::Integer(1)]
+    );
+    assert_eq!(values("SELECT SUM(value) FROM generate_series(1, 100) WHERE value % 2 = 0"), vec![Value::Integer(2550)]);
+    assert!(db.query("SELECT value FROM generate_series(1, 5, 0)").is_err());
+}
+
+#[test]
+fn generate_series_dates() {
+    let (_dir, db) = open_db();
+    let result = db.query("SELECT value FROM generate_series('2024-12-30', '2025-01-02')").unwrap();
+    let dates: Vec<Value> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    let expected: Vec<Value> = ["2024-12-30", "2024-12-31", "2025-01-01", "2025-01-02"]
+        .iter()
+        .map(|&d| Value::Text(d.into()))
+        .collect();
+    assert_eq!(dates, expected);
+}
+
+#[test]
+fn generate_series_joined_with_table() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE sales (id INTEGER PRIMARY KEY, day INTEGER, amount INTEGER)").unwrap();
+    db.execute("INSERT INTO sales VALUES (1, 1, 10), (2, 1, 5), (3, 3, 7)").unwrap();
+
+    // Days without sales still appear, with a NULL total
+    let result = db
+        .query(
+            "SELECT s.value, SUM(sales.amount) FROM generate_series(1, 4) AS s \
+             LEFT JOIN sales ON sales.day = s.value GROUP BY s.value ORDER BY s.value",
+        )
+        .unwrap();
+    let totals: Vec<Value> = result.rows.iter().map(|r| r.values[1].clone()).collect();
+    assert_eq!(totals, vec![Value::Integer(15), Value::Null, Value::Integer(7), Value::Null]);
+}
+
+#[test]
+fn json_each_expands_arrays_and_objects() {
+    let (_dir, db) = open_db();
+
+    let result = db.query("SELECT key, value FROM json_each('[1,2,3]')").unwrap();
+    let pairs: Vec<(Value, Value)> = result
+        .rows
+        .iter()
+        .map(|r| (r.values[0].clone(), r.values[1].clone()))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![
+            (Value::Integer(0), Value::Integer(1)),
+            (Value::Integer(1), Value::Integer(2)),
+            (Value::Integer(2), Value::Integer(3)),
+        ]
+    );
+
+    let result = db.query("SELECT key, value, type, fullkey FROM json_each('{\"a\":1}')").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(
+        result.rows[0].values,
+        vec![
+            Value::Text("a".into()),
+            Value::Integer(1),
+            Value::Text("integer".into()),
+            Value::Text("$.a".into()),
+        ]
+    );
+
+    // A path selects the container to expand
+    let result = db
+        .query("SELECT value FROM json_each('{\"tags\":[\"x\",\"y\"]}', '$.tags') WHERE value <> 'x'")
+        .unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("y".into()));
+}
+
+#[test]
+fn json_tree_walks_every_node() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT fullkey, type, atom FROM json_tree('{\"a\":[1,{\"b\":null}],\"c\":true}')")
+        .unwrap();
+    let nodes: Vec<Value> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    let expected: Vec<Value> = ["$", "$.a", "$.a[0]", "$.a[1]", "$.a[1].b", "$.c"]
+        .iter()
+        .map(|&k| k.into())
+        .collect();
+    assert_eq!(nodes, expected);
+    assert_eq!(result.rows[1].values[1], Value::Text("array".into()));
+    assert_eq!(result.rows[1].values[2], Value::Null);
+    assert_eq!(result.rows[5].values[2], Value::Integer(1));
+
+    // Leaves joined back to their parent container through id / parent
+    let result = db
+        .query(
+            "SELECT c.key, p.fullkey FROM json_tree('[[10,20]]') AS c \
+             JOIN json_tree('[[10,20]]') AS p ON c.parent = p.id WHERE c.atom IS NOT NULL",
+        )
+        .unwrap();
+    assert_eq!(result.rows.len(), 2);
+    assert_eq!(result.rows[0].values[1], Value::Text("$[0]".into()));
+
+    assert!(db.query("SELECT * FROM json_each('not json')").is_err());
+}
+
+// ---- Combined / Complex Tests ----
+
+#[test]
+fn case_with_cast() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, '42')").unwrap();
+
+    let result = db.query(
+        "SELECT CASE WHEN CAST(val AS INTEGER) > 40 THEN 'high' ELSE 'low' END FROM t"
+    ).unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("high".to_string()));
+}
+
+#[test]
+fn nested_functions() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, '  Hello World  ')").unwrap();
+
+    let result = db.query("SELECT UPPER(TRIM(val)) FROM t").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("HELLO WORLD".to_string()));
+}
+
+#[test]
+fn iif_with_null_handling() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, NULL)").unwrap();
+    db.execute("INSERT INTO t VALUES (2, 5)").unwrap();
+
+    let result = db.query("SELECT id, IIF(val IS NULL, 'missing', CAST(val AS TEXT)) FROM t ORDER BY id").unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.rows[0].values[1], Value::Text("missing".to_string()));
+    assert_eq!(result.rows[1].values[1], Value::Text("5".to_string()));
+}
+
+#[test]
+fn functions_in_where_clause() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'Alice')").unwrap();
+    db.execute("INSERT INTO t VALUES (2, 'Bob')").unwrap();
     db.execute("INSERT INTO t VALUES (3, 'Charlie')").unwrap();
 
     let result = db.query("SELECT * FROM t WHERE LENGTH(name) > 4").unwrap();
@@ -1768,6 +3113,77 @@ fn alter_table_drop_column() {
     assert_eq!(result.columns[1], "name");
 }
 
+#[test]
+fn alter_table_drop_middle_column_rewrites_rows() {
+    let (dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT, b INTEGER, c REAL)").unwrap();
+    db.execute("CREATE INDEX idx_c ON t (c)").unwrap();
+    for i in 0..300 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'a{}', {}, {}.5)", i, i, i * 10, i)).unwrap();
+    }
+    // Rows written before ADD COLUMN are shorter than the table
+    db.execute("ALTER TABLE t ADD COLUMN d TEXT DEFAULT 'dflt'").unwrap();
+    db.execute("INSERT INTO t VALUES (300, 'a300', 3000, 300.5, 'new')").unwrap();
+
+    db.execute("ALTER TABLE t DROP COLUMN b").unwrap();
+    let result = db.query("SELECT * FROM t WHERE id IN (7, 300) ORDER BY id").unwrap();
+    assert_eq!(*result.columns, vec!["id", "a", "c", "d"]);
+    assert_eq!(result.rows[0].values, vec![
+        Value::Integer(7), Value::Text("a7".into()), Value::Real(7.5), Value::Null,
+    ]);
+    assert_eq!(result.rows[1].values, vec![
+        Value::Integer(300), Value::Text("a300".into()), Value::Real(300.5), Value::Text("new".into()),
+    ]);
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 301);
+    assert!(db.query("SELECT b FROM t").is_err());
+
+    // The index on a later column still finds its rows
+    let result = db.query("SELECT id FROM t WHERE c = 42.5").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(42));
+
+    db.execute("INSERT INTO t VALUES (301, 'a301', 1.0, 'x')").unwrap();
+    db.close().unwrap();
+    let db = Database::open(dir.path().join("test.hdb")).unwrap();
+    let result = db.query("SELECT a, c, d FROM t WHERE id = 150").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Text("a150".into()), Value::Real(150.5), Value::Null]);
+    let result = db.query("PRAGMA integrity_check").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("ok".into()));
+}
+
+#[test]
+fn alter_table_drop_column_rejects_dependencies() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE p (id INTEGER PRIMARY KEY, code TEXT)").unwrap();
+    db.execute(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, u TEXT UNIQUE, i INTEGER, e TEXT, w INTEGER, \
+         k INTEGER, g INTEGER GENERATED ALWAYS AS (k * 2) VIRTUAL, lo INTEGER, hi INTEGER CHECK (hi >= lo), \
+         pid INTEGER, x INTEGER, FOREIGN KEY (pid) REFERENCES p (id))",
+    )
+    .unwrap();
+    db.execute("CREATE TABLE child (code TEXT, FOREIGN KEY (code) REFERENCES p (code))").unwrap();
+    db.execute("CREATE INDEX idx_i ON t (i)").unwrap();
+    db.execute("CREATE INDEX idx_e ON t (lower(e))").unwrap();
+    db.execute("CREATE INDEX idx_x ON t (x) WHERE w > 0").unwrap();
+    db.execute("INSERT INTO t (id, u, i, e, w, k, lo, hi, x) VALUES (1, 'u', 2, 'E', 1, 2, 1, 2, 9)").unwrap();
+
+    for col in ["id", "u", "i", "e", "w", "k", "lo", "pid"] {
+        let sql = format!("ALTER TABLE t DROP COLUMN {}", col);
+        assert!(db.execute(&sql).is_err(), "{}", sql);
+    }
+    assert!(db.execute("ALTER TABLE p DROP COLUMN code").is_err());
+    assert!(db.execute("ALTER TABLE t DROP COLUMN nosuch").is_err());
+
+    // Nothing was changed by the rejected drops
+    let result = db.query("SELECT g, x FROM t").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Integer(4), Value::Integer(9)]);
+    // A column that only has its own CHECK can go
+    db.execute("ALTER TABLE t DROP COLUMN hi").unwrap();
+    db.execute("ALTER TABLE t DROP COLUMN g").unwrap();
+    let result = db.query("SELECT * FROM t").unwrap();
+    assert_eq!(result.columns.len(), 9);
+    assert_eq!(result.rows[0].values[8], Value::Integer(9));
+}
+
 #[test]
 fn alter_table_drop_pk_column_fails() {
     let (_dir, db) = open_db();
@@ -1851,6 +3267,49 @@ fn pragma_page_size() {
     assert_eq!(result.rows[0].values[0], Value::Integer(4096));
 }
 
+#[test]
+fn small_page_size_survives_splits_and_reopen() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let config = DatabaseConfig::new().page_size(512);
+    let db = Database::open_with_config(&path, config.clone()).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, score REAL)").unwrap();
+    db.execute("CREATE INDEX idx_name ON t (name)").unwrap();
+    for i in 0..300 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'name-{:04}', {}.5)", i, i, i)).unwrap();
+    }
+    assert!(db.query("PRAGMA page_count").unwrap().rows[0].values[0].as_integer().unwrap() > 20);
+    db.close().unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len() % 512, 0);
+
+    let db = Database::open_with_config(&path, config).unwrap();
+    assert_eq!(db.query("PRAGMA page_size").unwrap().rows[0].values[0], Value::Integer(512));
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 300);
+    let result = db.query("SELECT id FROM t WHERE name = 'name-0123'").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(123));
+    db.close().unwrap();
+
+    // Without an explicit size the stored one is used; a different one is rejected
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(300));
+    db.close().unwrap();
+    assert!(Database::open_with_config(&path, DatabaseConfig::new().page_size(1024)).is_err());
+}
+
+#[test]
+fn invalid_page_size_is_rejected() {
+    let dir = TempDir::new().unwrap();
+    for size in [0, 256, 1000, 131072] {
+        let config = DatabaseConfig::new().page_size(size);
+        assert!(Database::open_with_config(dir.path().join("t.hdb"), config).is_err());
+    }
+    let config = DatabaseConfig::new().page_size(65536);
+    let db = Database::open_with_config(dir.path().join("big.hdb"), config).unwrap();
+    db.execute("CREATE TABLE t (v TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES ('x')").unwrap();
+    assert_eq!(db.query("PRAGMA page_size").unwrap().rows[0].values[0], Value::Integer(65536));
+}
+
 #[test]
 fn pragma_page_count() {
     let (_dir, db) = open_db();
@@ -1859,6 +3318,58 @@ fn pragma_page_count() {
     assert!(result.rows[0].values[0].as_integer().unwrap() >= 1);
 }
 
+#[test]
+fn checkpoint_full_shrinks_wal() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let wal_path = dir.path().join("test.hdb-wal");
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'row {}')", i, i)).unwrap();
+    }
+
+    // A passive checkpoint copies frames back but keeps the log
+    let passive = db.checkpoint(CheckpointMode::Passive).unwrap();
+    assert!(passive.wal_frames > 0);
+    assert_eq!(passive.checkpointed_frames, passive.wal_frames);
+    let before = std::fs::metadata(&wal_path).unwrap().len();
+
+    let full = db.checkpoint(CheckpointMode::Full).unwrap();
+    assert_eq!(full.checkpointed_frames, full.wal_frames);
+    let after = std::fs::metadata(&wal_path).unwrap().len();
+    assert!(after < before);
+
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 200);
+    db.execute("INSERT INTO t VALUES (200, 'after')").unwrap();
+    db.close().unwrap();
+
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 201);
+}
+
+#[test]
+fn pragma_wal_checkpoint() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute("INSERT INTO t VALUES (1), (2)").unwrap();
+
+    let result = db.query("PRAGMA wal_checkpoint(TRUNCATE)").unwrap();
+    assert_eq!(result.columns.as_slice(), ["busy", "log", "checkpointed"]);
+    assert_eq!(std::fs::metadata(dir.path().join("test.hdb-wal")).unwrap().len(), 0);
+    assert_eq!(db.query("PRAGMA wal_checkpoint(FULL)").unwrap().len(), 1);
+    assert!(db.query("PRAGMA wal_checkpoint(sometimes)").is_err());
+
+    // The emptied log is reinitialized on the next write
+    db.execute("INSERT INTO t VALUES (3)").unwrap();
+    db.checkpoint(CheckpointMode::Passive).unwrap();
+    db.close().unwrap();
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 3);
+}
+
 #[test]
 fn pragma_unknown_returns_empty() {
     let (_dir, db) = open_db();
@@ -2082,6 +3593,74 @@ fn json_minify() {
     );
 }
 
+#[test]
+fn json_set_insert_replace() {
+    let (_dir, db) = open_db();
+    let doc = r#"'{"a":1,"b":[1,2]}'"#;
+    let result = db
+        .query(&format!(
+            "SELECT JSON_SET({0}, '$.a', 10, '$.c', 'x'), \
+             JSON_INSERT({0}, '$.a', 10, '$.b[2]', 3), \
+             JSON_REPLACE({0}, '$.a', 10, '$.c', 'x')",
+            doc
+        ))
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values,
+        vec![
+            Value::Text(r#"{"a":10,"b":[1,2],"c":"x"}"#.into()),
+            Value::Text(r#"{"a":1,"b":[1,2,3]}"#.into()),
+            Value::Text(r#"{"a":10,"b":[1,2]}"#.into()),
+        ]
+    );
+
+    // Missing objects along the path are created
+    let result = db.query("SELECT JSON_SET('{}', '$.x.y', 1)").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text(r#"{"x":{"y":1}}"#.into()));
+
+    db.execute("CREATE TABLE jdata (id INTEGER PRIMARY KEY, doc TEXT)").unwrap();
+    db.execute("INSERT INTO jdata VALUES (1, '{}')").unwrap();
+    assert!(db.query("SELECT JSON_SET(doc, '$.a') FROM jdata").is_err());
+    assert!(db.query("SELECT JSON_SET(doc, 'a', 1) FROM jdata").is_err());
+    assert!(db.query("SELECT JSON_SET('{bad', '$.a', id) FROM jdata").is_err());
+}
+
+#[test]
+fn json_set_updates_stored_documents() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE jdata (id INTEGER PRIMARY KEY, doc TEXT)").unwrap();
+    db.execute(r#"INSERT INTO jdata VALUES (1, '{"n":1}')"#).unwrap();
+    db.execute("UPDATE jdata SET doc = JSON_SET(doc, '$.n', JSON_EXTRACT(doc, '$.n') + 1)").unwrap();
+
+    let result = db.query("SELECT doc FROM jdata").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text(r#"{"n":2}"#.into()));
+}
+
+#[test]
+fn json_remove_paths() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query(r#"SELECT JSON_REMOVE('{"a":[1,2,3],"b":2,"c":3}', '$.a[0]', '$.b', '$.missing')"#)
+        .unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text(r#"{"a":[2,3],"c":3}"#.into()));
+
+    let result = db.query("SELECT JSON_REMOVE('[1,2]', '$'), JSON_REMOVE(NULL, '$[0]')").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Null, Value::Null]);
+}
+
+#[test]
+fn json_patch_merges_objects() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query(r#"SELECT JSON_PATCH('{"a":1,"b":{"c":2,"d":3}}', '{"a":null,"b":{"c":20},"e":5}')"#)
+        .unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text(r#"{"b":{"c":20,"d":3},"e":5}"#.into()));
+
+    // A non-object patch replaces the document
+    let result = db.query(r#"SELECT JSON_PATCH('{"a":1}', '[1]')"#).unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("[1]".into()));
+}
+
 // ====================================================================
 // RETURNING clause
 // ====================================================================
@@ -2659,30 +4238,132 @@ fn window_dense_rank_with_partition() {
     assert_eq!(name_dr["Diana"], 3);
 }
 
-// ---------------------------------------------------------------------------
-// CTE (Common Table Expression) tests
-// ---------------------------------------------------------------------------
-
 #[test]
-fn cte_simple() {
+fn window_ntile_percent_rank_cume_dist() {
     let (_dir, db) = open_db();
-    db.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT, price REAL)")
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, grp TEXT, v INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'a', 10), (2, 'a', 20), (3, 'a', 30), (4, 'a', 40), (5, 'a', 50), (6, 'a', 60), (7, 'a', 70)")
         .unwrap();
-    db.execute("INSERT INTO products VALUES (1, 'Apple', 1.50)").unwrap();
-    db.execute("INSERT INTO products VALUES (2, 'Banana', 0.75)").unwrap();
-    db.execute("INSERT INTO products VALUES (3, 'Cherry', 3.00)").unwrap();
+    db.execute("INSERT INTO t VALUES (8, 'b', 5), (9, 'b', 5), (10, 'b', 5)").unwrap();
 
-    let result = db
-        .query("WITH expensive AS (SELECT * FROM products WHERE price > 1.0) SELECT * FROM expensive")
-        .unwrap();
-    assert_eq!(result.len(), 2);
+    let column = |sql: &str| -> Vec<Value> {
+        db.query(sql).unwrap().rows.iter().map(|r| r.values[1].clone()).collect()
+    };
 
-    let mut names: Vec<String> = result
-        .rows
-        .iter()
-        .map(|r| r.values[1].as_text().unwrap().to_string())
-        .collect();
-    names.sort();
+    // 7 rows in 4 buckets: sizes 2, 2, 2, 1
+    let buckets = column("SELECT id, NTILE(4) OVER (ORDER BY v) FROM t WHERE grp = 'a' ORDER BY id");
+    let expected: Vec<Value> = [1, 1, 2, 2, 3, 3, 4].iter().map(|&b| Value::Integer(b)).collect();
+    assert_eq!(buckets, expected);
+
+    // Tied rows all rank first
+    let ranks = column("SELECT id, PERCENT_RANK() OVER (PARTITION BY grp ORDER BY v) FROM t WHERE grp = 'b'");
+    assert_eq!(ranks, vec![Value::Real(0.0); 3]);
+    let ranks = column("SELECT id, PERCENT_RANK() OVER (ORDER BY v) FROM t WHERE grp = 'a' ORDER BY id");
+    assert_eq!(ranks[0], Value::Real(0.0));
+    assert_eq!(ranks[6], Value::Real(1.0));
+
+    // Distinct values give a strictly increasing distribution ending at 1
+    let dist = column("SELECT id, CUME_DIST() OVER (PARTITION BY grp ORDER BY v) FROM t WHERE grp = 'a' ORDER BY id");
+    assert!(dist.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(dist[6], Value::Real(1.0));
+    let dist = column("SELECT id, CUME_DIST() OVER (ORDER BY v) FROM t WHERE grp = 'b'");
+    assert_eq!(dist, vec![Value::Real(1.0); 3]);
+
+    assert!(db.query("SELECT NTILE(0) OVER (ORDER BY v) FROM t").is_err());
+}
+
+#[test]
+fn window_nth_value_respects_frame() {
+    let (_dir, db) = open_db();
+    setup_window_test(&db);
+
+    let column = |sql: &str| -> Vec<Value> {
+        db.query(sql).unwrap().rows.iter().map(|r| r.values[1].clone()).collect()
+    };
+
+    // Default frame grows from the partition start, so only the lowest
+    // salary (Diana, id 4) has no second value
+    let second = column("SELECT id, NTH_VALUE(salary, 2) OVER (ORDER BY salary) FROM employees ORDER BY id");
+    let expected: Vec<Value> = [Some(72000), Some(72000), Some(72000), None, Some(72000), Some(72000)]
+        .iter()
+        .map(|v| v.map_or(Value::Null, Value::Integer))
+        .collect();
+    assert_eq!(second, expected);
+
+    // A 3-row sliding frame: the 2nd value is the current row, except for
+    // the lowest salary, whose frame starts at itself
+    let middle = column(
+        "SELECT id, NTH_VALUE(salary, 2) OVER (ORDER BY salary ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) \
+         FROM employees ORDER BY id",
+    );
+    let expected: Vec<Value> = [90000, 85000, 92000, 72000, 75000, 72000].iter().map(|&v| Value::Integer(v)).collect();
+    assert_eq!(middle, expected);
+
+    assert!(db.query("SELECT NTH_VALUE(salary, 0) OVER (ORDER BY salary) FROM employees").is_err());
+}
+
+#[test]
+fn window_clause_named_windows() {
+    let (_dir, db) = open_db();
+    setup_window_test(&db);
+
+    let result = db
+        .query(
+            "SELECT id, ROW_NUMBER() OVER w, RANK() OVER w, SUM(salary) OVER w FROM employees \
+             WINDOW w AS (PARTITION BY dept ORDER BY salary) ORDER BY id",
+        )
+        .unwrap();
+    // Bob (id 2) is the lowest-paid engineer; Charlie (id 3) the highest
+    assert_eq!(
+        result.rows[1].values,
+        vec![Value::Integer(2), Value::Integer(1), Value::Integer(1), Value::Integer(85000)]
+    );
+    assert_eq!(
+        result.rows[2].values,
+        vec![Value::Integer(3), Value::Integer(3), Value::Integer(3), Value::Integer(267000)]
+    );
+
+    // A named window extended inline with an ORDER BY
+    let result = db
+        .query(
+            "SELECT id, ROW_NUMBER() OVER (d ORDER BY salary DESC) FROM employees \
+             WINDOW d AS (PARTITION BY dept) ORDER BY id",
+        )
+        .unwrap();
+    let rn: Vec<Value> = result.rows.iter().map(|r| r.values[1].clone()).collect();
+    let expected: Vec<Value> = [2, 3, 1, 3, 1, 2].iter().map(|&v| Value::Integer(v)).collect();
+    assert_eq!(rn, expected);
+
+    match db.query("SELECT RANK() OVER missing FROM employees") {
+        Err(HorizonError::InvalidSql(msg)) => assert!(msg.contains("missing")),
+        _ => panic!("expected an unknown window error"),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// CTE (Common Table Expression) tests
+// ---------------------------------------------------------------------------
+
+#[test]
+fn cte_simple() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT, price REAL)")
+        .unwrap();
+    db.execute("INSERT INTO products VALUES (1, 'Apple', 1.50)").unwrap();
+    db.execute("INSERT INTO products VALUES (2, 'Banana', 0.75)").unwrap();
+    db.execute("INSERT INTO products VALUES (3, 'Cherry', 3.00)").unwrap();
+
+    let result = db
+        .query("WITH expensive AS (SELECT * FROM products WHERE price > 1.0) SELECT * FROM expensive")
+        .unwrap();
+    assert_eq!(result.len(), 2);
+
+    let mut names: Vec<String> = result
+        .rows
+        .iter()
+        .map(|r| r.values[1].as_text().unwrap().to_string())
+        .collect();
+    names.sort();
     assert_eq!(names, vec!["Apple", "Cherry"]);
 }
 
@@ -3336,6 +5017,80 @@ fn date_functions_in_table_context() {
     assert_eq!(result.rows[1].values[1], Value::Text("2024-07-20".to_string()));
 }
 
+#[test]
+fn unixepoch_function() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query("SELECT UNIXEPOCH('1970-01-01'), UNIXEPOCH('2024-01-15 10:20:30'), UNIXEPOCH('2024-01-15', '+1 days')")
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values,
+        vec![Value::Integer(0), Value::Integer(1_705_314_030), Value::Integer(1_705_363_200)]
+    );
+
+    let result = db.query("SELECT UNIXEPOCH('now'), UNIXEPOCH()").unwrap();
+    for value in &result.rows[0].values {
+        // Some time after 2024-01-01 and before 2100-01-01
+        assert!(matches!(value, Value::Integer(t) if *t > 1_704_067_200 && *t < 4_102_444_800));
+    }
+    assert_eq!(db.query("SELECT UNIXEPOCH('garbage')").unwrap().rows[0].values[0], Value::Null);
+}
+
+#[test]
+fn unixepoch_modifier_converts_timestamps() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query(
+            "SELECT DATETIME(UNIXEPOCH('2024-01-15'), 'unixepoch'), \
+             DATE(86400 * 365, 'unixepoch', '+1 days'), \
+             STRFTIME('%H:%M', '3600', 'unixepoch'), DATETIME(-1, 'unixepoch')",
+        )
+        .unwrap();
+    let expected: Vec<Value> = ["2024-01-15 00:00:00", "1971-01-02", "01:00", "1969-12-31 23:59:59"]
+        .iter()
+        .map(|&s| s.into())
+        .collect();
+    assert_eq!(result.rows[0].values, expected);
+
+    db.execute("CREATE TABLE logins (id INTEGER PRIMARY KEY, ts INTEGER)").unwrap();
+    db.execute("INSERT INTO logins VALUES (1, 1700000000)").unwrap();
+    let result = db.query("SELECT DATETIME(ts, 'unixepoch') FROM logins").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("2023-11-14 22:13:20".into()));
+    // 'unixepoch' only applies directly after the time value
+    let result = db.query("SELECT DATETIME(0, '+1 days', 'unixepoch')").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Null);
+}
+
+#[test]
+fn datetime_offset_modifiers() {
+    let (_dir, db) = open_db();
+    let result = db
+        .query(
+            "SELECT DATETIME('2024-01-01 12:00:00', '+05:30'), \
+             DATETIME('2024-01-01 12:00:00', '+05:00', '-05:00'), \
+             DATETIME('2024-01-01 02:00:00', '-03:15'), \
+             DATETIME('2024-01-01 23:59:50', '+00:00:15'), \
+             DATETIME('2024-01-01 12:00:00', '+5:30')",
+        )
+        .unwrap();
+    assert_eq!(
+        result.rows[0].values,
+        vec![
+            Value::Text("2024-01-01 17:30:00".into()),
+            Value::Text("2024-01-01 12:00:00".into()),
+            Value::Text("2023-12-31 22:45:00".into()),
+            Value::Text("2024-01-02 00:00:05".into()),
+            Value::Null,
+        ]
+    );
+
+    // Converting to local time and back is lossless in any time zone
+    let result = db
+        .query("SELECT DATETIME('2024-06-01 08:00:00', 'localtime', 'utc')")
+        .unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("2024-06-01 08:00:00".into()));
+}
+
 // =====================================================================
 // ATTACH / DETACH DATABASE
 // =====================================================================
@@ -4510,3 +6265,3370 @@ fn fts5_case_insensitive_search() {
     let result = db.query("SELECT * FROM fts5_ci_test WHERE fts5_ci_test MATCH 'HELLO'").unwrap();
     assert_eq!(result.len(), 2);
 }
+
+// ---- Parameter Binding Tests ----
+
+#[test]
+fn params_positional_binding() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, age INTEGER)").unwrap();
+    db.execute_params(
+        "INSERT INTO users VALUES (?, ?, ?)",
+        &[Value::Integer(1), Value::Text("O'Brien".into()), Value::Integer(40)],
+    )
+    .unwrap();
+    db.execute_params(
+        "INSERT INTO users VALUES (?1, ?2, ?3)",
+        &[Value::Integer(2), Value::Text("Bob".into()), Value::Integer(25)],
+    )
+    .unwrap();
+
+    let result = db
+        .query_params("SELECT name FROM users WHERE age > ? ORDER BY id", &[Value::Integer(30)])
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Text("O'Brien".into()));
+}
+
+#[test]
+fn params_named_binding() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE kv (k TEXT PRIMARY KEY, v INTEGER)").unwrap();
+    db.execute_named(
+        "INSERT INTO kv VALUES (:key, $value)",
+        &[("key", Value::Text("a".into())), ("$value", Value::Integer(1))],
+    )
+    .unwrap();
+
+    // Named placeholders can also be bound by position; repeats share a slot.
+    let result = db
+        .query_params("SELECT v FROM kv WHERE k = @k OR k = @k", &[Value::Text("a".into())])
+        .unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(1));
+}
+
+#[test]
+fn params_blob_round_trip() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE files (id INTEGER PRIMARY KEY, data BLOB)").unwrap();
+    let blob = vec![0u8, 159, 146, 150, 255];
+    db.execute_params(
+        "INSERT INTO files VALUES (?, ?)",
+        &[Value::Integer(1), Value::Blob(blob.clone())],
+    )
+    .unwrap();
+
+    let result = db.query_params("SELECT data FROM files WHERE id = ?", &[Value::Integer(1)]).unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Blob(blob));
+}
+
+#[test]
+fn params_count_mismatch_errors() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (a INTEGER, b INTEGER)").unwrap();
+    let err = db.execute_params("INSERT INTO t VALUES (?, ?)", &[Value::Integer(1)]).unwrap_err();
+    assert!(matches!(err, HorizonError::InvalidSql(_)));
+
+    let result = db.query_named("SELECT * FROM t WHERE a = :a", &[("b", Value::Integer(1))]);
+    assert!(matches!(result, Err(HorizonError::InvalidSql(_))));
+
+    let result = db.query_named("SELECT * FROM t WHERE a = ?", &[("a", Value::Integer(1))]);
+    assert!(matches!(result, Err(HorizonError::InvalidSql(_))));
+}
+
+// ---- Single-Row Query Tests ----
+
+#[test]
+fn query_row_returns_at_most_one_row() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, team INTEGER)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice', 1), (2, 'bob', 1), (3, 'carol', 2)").unwrap();
+
+    assert!(db.query_row("SELECT * FROM users WHERE id = 99").unwrap().is_none());
+
+    let row = db.query_row("SELECT name FROM users WHERE id = 2").unwrap().unwrap();
+    assert_eq!(row.get("name"), Some(&Value::Text("bob".into())));
+
+    let err = db.query_row("SELECT * FROM users WHERE team = 1");
+    assert!(matches!(err, Err(HorizonError::TooManyRows)));
+
+    let row = db
+        .query_row("SELECT name FROM users WHERE id = (SELECT MAX(id) FROM users WHERE team = 1)")
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values[0], Value::Text("bob".into()));
+}
+
+#[test]
+fn query_row_params_binds_values() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice'), (2, 'bob')").unwrap();
+
+    let row = db
+        .query_row_params("SELECT name FROM users WHERE id = ? LIMIT 1", &[Value::Integer(1)])
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values[0], Value::Text("alice".into()));
+
+    let none = db
+        .query_row_params("SELECT name FROM users WHERE id = ?", &[Value::Integer(7)])
+        .unwrap();
+    assert!(none.is_none());
+
+    let err = db.query_row_params("SELECT name FROM users WHERE id > ?", &[Value::Integer(0)]);
+    assert!(matches!(err, Err(HorizonError::TooManyRows)));
+}
+
+#[test]
+fn row_get_typed_converts_values() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, age INTEGER)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice', NULL)").unwrap();
+
+    let row = db.query_row("SELECT * FROM users").unwrap().unwrap();
+    assert_eq!(row.get_typed::<i32>("id").unwrap(), 1);
+    assert_eq!(row.get_typed::<String>("NAME").unwrap(), "alice");
+    assert_eq!(row.get_typed::<Option<i64>>("age").unwrap(), None);
+    assert!(matches!(row.get_typed::<i64>("name"), Err(HorizonError::TypeError(_))));
+    assert!(matches!(row.get_typed::<i64>("age"), Err(HorizonError::TypeError(_))));
+    assert!(matches!(row.get_typed::<i64>("missing"), Err(HorizonError::ColumnNotFound(_))));
+}
+
+// ---- Streaming Query Tests ----
+
+#[test]
+fn query_iter_streams_table_scan() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE nums (id INTEGER PRIMARY KEY, val INTEGER)").unwrap();
+    for i in 1..=500 {
+        db.execute(&format!("INSERT INTO nums VALUES ({}, {})", i, i * 2)).unwrap();
+    }
+
+    let iter = db.query_iter("SELECT val FROM nums WHERE val % 3 = 0").unwrap();
+    assert_eq!(iter.columns().as_slice(), ["val".to_string()]);
+    let vals: Vec<i64> = iter
+        .map(|row| row.unwrap().values[0].as_integer().unwrap())
+        .collect();
+    let expected = db.query("SELECT val FROM nums WHERE val % 3 = 0").unwrap();
+    assert_eq!(vals.len(), expected.len());
+    assert_eq!(vals[0], 6);
+}
+
+#[test]
+fn query_iter_limit_offset_and_early_drop() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    for i in 1..=20 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'n{}')", i, i)).unwrap();
+    }
+
+    let ids: Vec<Value> = db
+        .query_iter("SELECT id FROM t LIMIT 3 OFFSET 5")
+        .unwrap()
+        .map(|row| row.unwrap().values[0].clone())
+        .collect();
+    assert_eq!(ids, vec![Value::Integer(6), Value::Integer(7), Value::Integer(8)]);
+
+    // Dropping a partially consumed iterator releases the database lock.
+    let mut iter = db.query_iter("SELECT * FROM t").unwrap();
+    assert!(iter.next().is_some());
+    drop(iter);
+    assert_eq!(db.query("SELECT * FROM t WHERE id = 1").unwrap().len(), 1);
+}
+
+#[test]
+fn query_iter_falls_back_for_complex_queries() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, grp TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'a')").unwrap();
+
+    let rows: Vec<Row> = db
+        .query_iter("SELECT grp, COUNT(*) FROM t GROUP BY grp ORDER BY grp")
+        .unwrap()
+        .collect::<horizon::Result<_>>()
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].values[1], Value::Integer(2));
+
+    assert!(db.query_iter("SELECT * FROM missing").is_err());
+}
+
+// ---- Concurrent Reader Tests ----
+
+#[test]
+fn concurrent_queries_from_many_threads() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, grp INTEGER, val INTEGER)").unwrap();
+    for i in 1..=300 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, {}, {})", i, i % 8, i * 10)).unwrap();
+    }
+    db.execute("CREATE INDEX idx_grp ON t (grp)").unwrap();
+
+    std::thread::scope(|s| {
+        for worker in 0..8i64 {
+            let db = &db;
+            s.spawn(move || {
+                for _ in 0..25 {
+                    let total = db.query("SELECT SUM(val) FROM t").unwrap();
+                    assert_eq!(total.rows[0].values[0], Value::Integer(451_500));
+
+                    let rows = db
+                        .query(&format!("SELECT id FROM t WHERE grp = {}", worker))
+                        .unwrap();
+                    let expected = (1..=300).filter(|i| i % 8 == worker).count();
+                    assert_eq!(rows.len(), expected);
+                }
+            });
+        }
+    });
+}
+
+// ---- SQL Dump Tests ----
+
+#[test]
+fn dump_empty_database() {
+    let db = Database::open(":memory:").unwrap();
+    assert_eq!(db.dump_sql().unwrap(), "BEGIN TRANSACTION;\nCOMMIT;\n");
+}
+
+#[test]
+fn dump_and_restore_round_trip() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL, price REAL DEFAULT 1.5)").unwrap();
+    for i in 1..=100 {
+        db.execute(&format!("INSERT INTO items VALUES ({}, 'item {}', {}.25)", i, i, i)).unwrap();
+    }
+    db.execute("CREATE INDEX idx_name ON items (name)").unwrap();
+    db.execute(
+        r#"CREATE TABLE "odd ""name""" (k TEXT, n INTEGER, r REAL, b BLOB, PRIMARY KEY (k, n), CHECK (n >= 0))"#,
+    )
+    .unwrap();
+    db.execute(r#"INSERT INTO "odd ""name""" VALUES ('it''s', 1, 2.0, X'00FF'), ('x', 0, NULL, NULL)"#).unwrap();
+    db.execute("CREATE VIEW cheap AS SELECT name FROM items WHERE price < 10").unwrap();
+    db.execute("CREATE TABLE audit (item_id INTEGER, note TEXT)").unwrap();
+    db.execute(
+        "CREATE TRIGGER log_price AFTER UPDATE ON items FOR EACH ROW WHEN NEW.price > OLD.price \
+         BEGIN INSERT INTO audit VALUES (NEW.id, 'raised'); END",
+    )
+    .unwrap();
+    db.execute("PRAGMA user_version = 42").unwrap();
+
+    let dump = db.dump_sql().unwrap();
+    assert!(dump.starts_with("BEGIN TRANSACTION;\n"));
+    assert!(dump.ends_with("COMMIT;\n"));
+    assert!(dump.contains(r#"CREATE INDEX "idx_name" ON "items" ("name");"#));
+    assert!(dump.contains(r#"('it''s', 1, 2.0, X'00FF')"#));
+    // Tables are dumped in name order
+    assert!(dump.find(r#"CREATE TABLE "items""#) < dump.find(r#"CREATE TABLE "odd"#));
+
+    let copy = Database::open(":memory:").unwrap();
+    copy.restore_sql(&dump).unwrap();
+    assert_eq!(copy.dump_sql().unwrap(), dump);
+
+    let count = copy.query("SELECT COUNT(*), SUM(price) FROM items").unwrap();
+    assert_eq!(count.rows[0].values, vec![Value::Integer(100), Value::Real(5075.0)]);
+    let odd = copy.query(r#"SELECT r, b FROM "odd ""name""" WHERE k = 'it''s'"#).unwrap();
+    assert_eq!(odd.rows[0].values, vec![Value::Real(2.0), Value::Blob(vec![0x00, 0xFF])]);
+    assert_eq!(copy.query("SELECT * FROM cheap").unwrap().len(), 9);
+    assert_eq!(copy.query("PRAGMA index_list(items)").unwrap().len(), 1);
+    let version = copy.query("PRAGMA user_version").unwrap();
+    assert_eq!(version.rows[0].values[0], Value::Integer(42));
+    copy.execute("UPDATE items SET price = price + 1 WHERE id IN (50, 51)").unwrap();
+    copy.execute("UPDATE items SET price = price - 1 WHERE id = 52").unwrap();
+    let audit = copy.query("SELECT item_id, note FROM audit ORDER BY item_id").unwrap();
+    assert_eq!(audit.len(), 2);
+    assert_eq!(audit.rows[1].values, vec![Value::Integer(51), Value::Text("raised".into())]);
+
+    // Constraints survive the round trip
+    assert!(copy.execute("INSERT INTO items (id) VALUES (101)").is_err());
+    assert!(copy.execute(r#"INSERT INTO "odd ""name""" VALUES ('y', -1, NULL, NULL)"#).is_err());
+    let row = copy.query_row("SELECT price FROM items WHERE id = 1").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Real(1.25));
+}
+
+#[test]
+fn restore_failure_rolls_back() {
+    let db = Database::open(":memory:").unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    let script = "BEGIN TRANSACTION;\nINSERT INTO t VALUES (1);\n\
+                  INSERT INTO missing VALUES (1);\nCOMMIT;\n";
+    assert!(db.restore_sql(script).is_err());
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 0);
+    // No transaction is left open
+    db.restore_sql("BEGIN; INSERT INTO t VALUES (2); COMMIT;").unwrap();
+    assert_eq!(db.query("SELECT * FROM t").unwrap().len(), 1);
+}
+
+// ---- Backup Tests ----
+
+#[test]
+fn backup_copies_every_table() {
+    let (dir, db) = open_db();
+    for t in 0..10 {
+        db.execute(&format!("CREATE TABLE t{} (id INTEGER PRIMARY KEY, val TEXT)", t)).unwrap();
+        for i in 0..50 {
+            db.execute(&format!("INSERT INTO t{} VALUES ({}, 'row {} of t{}')", t, i, i, t)).unwrap();
+        }
+    }
+    db.execute("CREATE INDEX idx_val ON t3 (val)").unwrap();
+
+    let backup_path = dir.path().join("backup.hdb");
+    db.backup_to(&backup_path).unwrap();
+    // Writes after the backup do not reach it
+    db.execute("INSERT INTO t0 VALUES (1000, 'late')").unwrap();
+
+    let backup = Database::open(&backup_path).unwrap();
+    for t in 0..10 {
+        let result = backup.query(&format!("SELECT COUNT(*), MAX(val) FROM t{}", t)).unwrap();
+        assert_eq!(
+            result.rows[0].values,
+            vec![Value::Integer(50), Value::Text(format!("row 9 of t{}", t))]
+        );
+    }
+    let row = backup.query_row("SELECT id FROM t3 WHERE val = 'row 7 of t3'").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Integer(7));
+    assert_eq!(db.query("SELECT * FROM t0").unwrap().len(), 51);
+}
+
+#[test]
+fn backup_of_memory_database_with_progress() {
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(":memory:").unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, data TEXT)").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, '{}')", i, "x".repeat(100))).unwrap();
+    }
+
+    let path = dir.path().join("from_memory.hdb");
+    let mut last = (0, 0);
+    db.backup_to_with_progress(&path, |done, total| last = (done, total)).unwrap();
+    assert!(last.1 > 1);
+    assert_eq!(last.0, last.1);
+
+    let backup = Database::open(&path).unwrap();
+    let result = backup.query("SELECT COUNT(*) FROM t").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(200));
+
+    // Backing up again replaces the earlier copy
+    db.execute("DELETE FROM t WHERE id >= 100").unwrap();
+    drop(backup);
+    db.backup_to(&path).unwrap();
+    let backup = Database::open(&path).unwrap();
+    let result = backup.query("SELECT COUNT(*) FROM t").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(100));
+}
+
+// ---- Integrity Check Tests ----
+
+fn check_rows(db: &Database, sql: &str) -> Vec<Value> {
+    db.query(sql).unwrap().rows.into_iter().map(|r| r.values[0].clone()).collect()
+}
+
+#[test]
+fn integrity_check_of_sound_database_is_ok() {
+    let (dir, db) = open_db();
+    let ok = vec![Value::Text("ok".into())];
+    assert_eq!(check_rows(&db, "PRAGMA integrity_check"), ok);
+
+    for sql in [
+        "CREATE TABLE a (id INTEGER PRIMARY KEY, name TEXT UNIQUE, n INTEGER)",
+        "CREATE INDEX a_n ON a (n)",
+        "CREATE INDEX a_lower ON a (lower(name))",
+        "CREATE INDEX a_even ON a (n) WHERE n % 2 = 0",
+        "CREATE TABLE b (x INTEGER, y TEXT, v REAL, PRIMARY KEY (x, y))",
+    ] {
+        db.execute(sql).unwrap();
+    }
+    for i in 0..500 {
+        db.execute(&format!("INSERT INTO a (name, n) VALUES ('name {}', {})", i, i % 7)).unwrap();
+        db.execute(&format!("INSERT INTO b VALUES ({}, 'k{}', {}.5)", i % 10, i, i)).unwrap();
+    }
+    db.execute("INSERT INTO a (name, n) VALUES (NULL, NULL)").unwrap();
+    db.execute("DELETE FROM a WHERE id % 3 = 0").unwrap();
+    db.execute("UPDATE b SET v = v * 2 WHERE x = 4").unwrap();
+
+    let result = db.query("PRAGMA integrity_check").unwrap();
+    assert_eq!(result.columns[0], "integrity_check");
+    assert_eq!(check_rows(&db, "PRAGMA integrity_check"), ok);
+    let result = db.query("PRAGMA quick_check").unwrap();
+    assert_eq!(result.columns[0], "quick_check");
+    assert_eq!(check_rows(&db, "PRAGMA quick_check"), ok);
+
+    // Still sound after a reopen
+    db.close().unwrap();
+    let db = Database::open(dir.path().join("test.hdb")).unwrap();
+    assert_eq!(check_rows(&db, "PRAGMA integrity_check"), ok);
+}
+
+#[test]
+fn integrity_check_rejects_a_bad_limit() {
+    let (_dir, db) = open_db();
+    assert!(db.query("PRAGMA integrity_check(0)").is_err());
+    assert!(db.query("PRAGMA quick_check('x')").is_err());
+    assert_eq!(check_rows(&db, "PRAGMA integrity_check(5)"), vec![Value::Text("ok".into())]);
+}
+
+// ---- User Version Tests ----
+
+fn user_version(db: &Database) -> Value {
+    db.query("PRAGMA user_version").unwrap().rows[0].values[0].clone()
+}
+
+/// Bring the schema up to date, one step per version, the way an
+/// application would on startup.
+fn migrate(db: &Database) {
+    let steps = [
+        "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)",
+        "ALTER TABLE notes ADD COLUMN pinned INTEGER DEFAULT 0",
+    ];
+    let current = match user_version(db) {
+        Value::Integer(v) => v as usize,
+        other => panic!("unexpected user_version {:?}", other),
+    };
+    for (i, step) in steps.iter().enumerate().skip(current) {
+        db.execute(step).unwrap();
+        db.execute(&format!("PRAGMA user_version = {}", i + 1)).unwrap();
+    }
+}
+
+#[test]
+fn user_version_persists_across_reopen() {
+    let (dir, db) = open_db();
+    assert_eq!(user_version(&db), Value::Integer(0));
+
+    db.execute("PRAGMA user_version = 42").unwrap();
+    assert_eq!(user_version(&db), Value::Integer(42));
+    let result = db.query("PRAGMA user_version = 43").unwrap();
+    assert_eq!(result.columns[0], "user_version");
+    assert_eq!(result.rows[0].values[0], Value::Integer(43));
+    db.execute("PRAGMA user_version = 42").unwrap();
+    db.close().unwrap();
+
+    let db = Database::open(dir.path().join("test.hdb")).unwrap();
+    assert_eq!(user_version(&db), Value::Integer(42));
+    assert!(db.execute("PRAGMA user_version = -1").is_err());
+    assert!(db.execute("PRAGMA user_version = 4294967296").is_err());
+    assert!(db.execute("PRAGMA user_version = 'x'").is_err());
+    assert_eq!(user_version(&db), Value::Integer(42));
+}
+
+#[test]
+fn user_version_drives_migrations() {
+    let (dir, db) = open_db();
+    migrate(&db);
+    assert_eq!(user_version(&db), Value::Integer(2));
+    db.execute("INSERT INTO notes (body) VALUES ('hello')").unwrap();
+    db.close().unwrap();
+
+    // Already up to date: running the migrations again changes nothing
+    let db = Database::open(dir.path().join("test.hdb")).unwrap();
+    migrate(&db);
+    assert_eq!(user_version(&db), Value::Integer(2));
+    let result = db.query("SELECT body, pinned FROM notes").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Text("hello".into()), Value::Integer(0)]);
+}
+
+// ---- ANALYZE Tests ----
+
+#[test]
+fn analyze_gathers_statistics_and_keeps_results_correct() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, lo INTEGER, hi INTEGER, tag TEXT)").unwrap();
+    db.execute("INSERT INTO t (lo, hi, tag) SELECT value % 2, value, 'x' FROM generate_series(1, 10000)").unwrap();
+    db.execute("CREATE INDEX idx_lo ON t (lo)").unwrap();
+    db.execute("CREATE INDEX idx_hi ON t (hi)").unwrap();
+    db.execute("CREATE INDEX idx_tag ON t (tag) WHERE tag IS NOT NULL").unwrap();
+
+    db.execute("ANALYZE").unwrap();
+    db.execute("ANALYZE t").unwrap();
+    db.execute("ANALYZE idx_hi").unwrap();
+    assert!(db.execute("ANALYZE nosuch").is_err());
+
+    let result = db.query("SELECT id FROM t WHERE lo = 1 AND hi = 77").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(result.rows[0].values[0], Value::Integer(77));
+    let result = db.query("SELECT COUNT(*) FROM t WHERE lo = 0 AND hi > 9990").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(5));
+}
+
+// ---- Database Builder Tests ----
+
+#[test]
+fn builder_read_only_rejects_writes() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'a')").unwrap();
+    db.close().unwrap();
+
+    let db = Database::builder().read_only(true).open(&path).unwrap();
+    assert_eq!(db.query("SELECT name FROM t").unwrap().rows[0].values[0], Value::Text("a".into()));
+    for sql in [
+        "INSERT INTO t VALUES (2, 'b')",
+        "UPDATE t SET name = 'z'",
+        "DELETE FROM t",
+        "CREATE TABLE u (x)",
+        "DROP TABLE t",
+        "PRAGMA user_version = 3",
+    ] {
+        assert!(matches!(db.execute(sql), Err(HorizonError::ReadOnly(_))), "{}", sql);
+    }
+    assert!(matches!(
+        db.query("INSERT INTO t VALUES (2, 'b') RETURNING id"),
+        Err(HorizonError::ReadOnly(_))
+    ));
+    assert_eq!(db.query("PRAGMA user_version").unwrap().rows[0].values[0], Value::Integer(0));
+    db.execute("BEGIN").unwrap();
+    db.execute("COMMIT").unwrap();
+    db.close().unwrap();
+
+    assert_eq!(Database::open(&path).unwrap().query("SELECT * FROM t").unwrap().len(), 1);
+    // A read-only open never creates the file
+    let missing = dir.path().join("missing.hdb");
+    assert!(Database::builder().read_only(true).open(&missing).is_err());
+    assert!(!missing.with_extension("hdb-wal").exists());
+}
+
+#[test]
+fn builder_small_cache_evicts_and_reads_back() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::builder().cache_size(2).page_size(512).open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE INDEX idx_name ON t (name)").unwrap();
+    for i in 0..500 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'name-{:04}')", i, i)).unwrap();
+    }
+    assert!(db.query("PRAGMA page_count").unwrap().rows[0].values[0].as_integer().unwrap() > 20);
+    assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(500));
+    let result = db.query("SELECT id FROM t WHERE name = 'name-0321'").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(321));
+    db.close().unwrap();
+
+    let db = Database::builder().cache_size(2).open(&path).unwrap();
+    let result = db.query("SELECT name FROM t WHERE id = 499").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("name-0499".into()));
+    assert!(Database::builder().cache_size(0).open(&path).is_err());
+}
+
+#[test]
+fn builder_applies_foreign_keys_and_journal_mode() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE p (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute("CREATE TABLE c (pid INTEGER REFERENCES p (id))").unwrap();
+    db.execute("INSERT INTO p VALUES (1)").unwrap();
+    db.close().unwrap();
+
+    let db = Database::builder()
+        .foreign_keys(true)
+        .journal_mode(JournalMode::Off)
+        .open(&path)
+        .unwrap();
+    assert!(!path.with_extension("hdb-wal").exists());
+    assert!(db.execute("INSERT INTO c VALUES (2)").is_err());
+    db.execute("INSERT INTO c VALUES (1)").unwrap();
+    db.close().unwrap();
+
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.query("SELECT * FROM c").unwrap().len(), 1);
+    assert_eq!(db.query("PRAGMA foreign_keys").unwrap().rows[0].values[0], Value::Integer(0));
+}
+
+// ---- Read-Only Tests ----
+
+#[test]
+fn open_readonly_allows_queries_only() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')").unwrap();
+    db.close().unwrap();
+    std::fs::remove_file(path.with_extension("hdb-wal")).unwrap();
+
+    let db = Database::open_readonly(&path).unwrap();
+    assert!(db.is_read_only());
+    assert!(!path.with_extension("hdb-wal").exists());
+    let result = db.query("SELECT id FROM t WHERE name = 'b'").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(2));
+    assert_eq!(db.query("PRAGMA table_info(t)").unwrap().len(), 2);
+    assert_eq!(db.query("PRAGMA table_list").unwrap().len(), 1);
+    assert_eq!(db.query("PRAGMA integrity_check").unwrap().rows[0].values[0], Value::Text("ok".into()));
+
+    assert!(matches!(db.execute("INSERT INTO t VALUES (3, 'c')"), Err(HorizonError::ReadOnly(_))));
+    assert!(matches!(db.execute("CREATE INDEX idx_id ON t (id)"), Err(HorizonError::ReadOnly(_))));
+    assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(2));
+    db.close().unwrap();
+    assert!(!path.with_extension("hdb-wal").exists());
+
+    assert!(!Database::open(&path).unwrap().is_read_only());
+}
+
+// ---- Busy Timeout Tests ----
+
+#[test]
+fn second_writer_waits_for_the_first() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, who TEXT)").unwrap();
+    db.close().unwrap();
+
+    let first = Database::open(&path).unwrap();
+    let second = Database::open(&path).unwrap();
+    first.execute("INSERT INTO t VALUES (1, 'first')").unwrap();
+    let writer = std::thread::spawn(move || {
+        // Blocks until the first handle is closed
+        second.execute("INSERT INTO t VALUES (2, 'second')").unwrap();
+        second.close().unwrap();
+    });
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    first.close().unwrap();
+    writer.join().unwrap();
+
+    let db = Database::open(&path).unwrap();
+    let result = db.query("SELECT who FROM t ORDER BY id").unwrap();
+    let who: Vec<Value> = result.rows.into_iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(who, vec![Value::Text("first".into()), Value::Text("second".into())]);
+}
+
+#[test]
+fn busy_timeout_expires_while_the_lock_is_held() {
+    static WAITS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    fn count_wait() {
+        WAITS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    let (dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    // Make the table visible to the second handle; `db` keeps the lock
+    db.checkpoint(CheckpointMode::Full).unwrap();
+    let other = Database::builder()
+        .busy_timeout(std::time::Duration::from_millis(30))
+        .on_busy(count_wait)
+        .open(dir.path().join("test.hdb"))
+        .unwrap();
+    assert!(matches!(other.execute("INSERT INTO t VALUES (1)"), Err(HorizonError::Busy(_))));
+    assert!(WAITS.load(std::sync::atomic::Ordering::SeqCst) >= 3);
+    drop(other);
+    db.execute("INSERT INTO t VALUES (1)").unwrap();
+}
+
+// ---- Row Lock Tests ----
+
+fn counters(db: &Database) -> Vec<Value> {
+    let result = db.query("SELECT n FROM counters ORDER BY id").unwrap();
+    result.rows.into_iter().map(|r| r.values[0].clone()).collect()
+}
+
+#[test]
+fn connections_writing_one_row_wait_for_each_other() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE counters (id INTEGER PRIMARY KEY, n INTEGER)").unwrap();
+    db.execute("INSERT INTO counters VALUES (1, 0), (2, 0)").unwrap();
+    let other = db.connect().unwrap();
+
+    db.execute("BEGIN").unwrap();
+    db.execute("UPDATE counters SET n = n + 1 WHERE id = 1").unwrap();
+    let (updated, wait_for_update) = std::sync::mpsc::channel();
+    let writer = std::thread::spawn(move || {
+        other.execute("BEGIN").unwrap();
+        // Rows the first transaction did not write are free
+        other.execute("UPDATE counters SET n = n + 10 WHERE id = 2").unwrap();
+        updated.send(()).unwrap();
+        // Waits for the first transaction to commit
+        other.execute("UPDATE counters SET n = n + 10 WHERE id = 1").unwrap();
+        other.execute("COMMIT").unwrap();
+    });
+    wait_for_update.recv().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    // The waiting connection does not hold up this one
+    db.execute("UPDATE counters SET n = n + 1 WHERE id = 1").unwrap();
+    db.execute("COMMIT").unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(counters(&db), vec![Value::Integer(12), Value::Integer(10)]);
+}
+
+#[test]
+fn connection_gives_up_on_a_row_that_stays_locked() {
+    let dir = TempDir::new().unwrap();
+    let db = Database::builder()
+        .busy_timeout(std::time::Duration::from_millis(50))
+        .open(dir.path().join("test.hdb"))
+        .unwrap();
+    db.execute("CREATE TABLE counters (id INTEGER PRIMARY KEY, n INTEGER)").unwrap();
+    db.execute("INSERT INTO counters VALUES (1, 0), (2, 0)").unwrap();
+    db.execute("BEGIN").unwrap();
+    db.execute("UPDATE counters SET n = 1 WHERE id = 2").unwrap();
+
+    let other = db.connect().unwrap();
+    let writer = std::thread::spawn(move || {
+        other.execute("BEGIN").unwrap();
+        let result = other.execute("UPDATE counters SET n = n + 10");
+        // The failed statement is undone, and the transaction goes on
+        other.execute("COMMIT").unwrap();
+        result
+    });
+    let result = writer.join().unwrap();
+    assert!(matches!(result, Err(HorizonError::LockConflict(_))), "{:?}", result);
+    db.execute("COMMIT").unwrap();
+
+    assert_eq!(counters(&db), vec![Value::Integer(0), Value::Integer(1)]);
+}
+
+#[test]
+fn deadlocked_connections_roll_back_one_transaction() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE counters (id INTEGER PRIMARY KEY, n INTEGER)").unwrap();
+    db.execute("INSERT INTO counters VALUES (1, 0), (2, 0)").unwrap();
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+    let workers: Vec<_> = [(1, 2, 1), (2, 1, 10)]
+        .into_iter()
+        .map(|(held, wanted, step)| {
+            let conn = db.connect().unwrap();
+            let barrier = std::sync::Arc::clone(&barrier);
+            std::thread::spawn(move || {
+                let add = |id: i64| conn.execute(&format!("UPDATE counters SET n = n + {} WHERE id = {}", step, id));
+                conn.execute("BEGIN").unwrap();
+                add(held).unwrap();
+                barrier.wait();
+                // Each connection now waits for the row the other holds
+                let result = add(wanted);
+                if result.is_ok() {
+                    conn.execute("COMMIT").unwrap();
+                } else {
+                    // The victim's transaction was rolled back
+                    assert!(conn.execute("COMMIT").is_err());
+                }
+                result
+            })
+        })
+        .collect();
+    let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1, "{:?}", results);
+    assert!(results.iter().any(|r| matches!(r, Err(HorizonError::Deadlock(_)))), "{:?}", results);
+    // Only the survivor's changes remain, on both rows
+    let n = counters(&db);
+    assert_eq!(n[0], n[1]);
+    assert!(n[0] == Value::Integer(1) || n[0] == Value::Integer(10), "{:?}", n);
+}
+
+#[test]
+fn connections_read_uncommitted_rows_of_each_other() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE counters (id INTEGER PRIMARY KEY, n INTEGER)").unwrap();
+    db.execute("INSERT INTO counters VALUES (1, 0)").unwrap();
+    let other = db.connect().unwrap();
+    other.execute("BEGIN").unwrap();
+    other.execute("UPDATE counters SET n = 5").unwrap();
+
+    // Plain queries read the shared pages as they are
+    assert_eq!(counters(&db), vec![Value::Integer(5)]);
+    // A snapshot only sees committed rows
+    let snapshot = db.begin_snapshot().unwrap();
+    let row = db.query_at_snapshot(snapshot, "SELECT n FROM counters").unwrap();
+    assert_eq!(row.rows[0].values[0], Value::Integer(0));
+    db.release_snapshot(snapshot).unwrap();
+
+    other.execute("ROLLBACK").unwrap();
+    assert_eq!(counters(&db), vec![Value::Integer(0)]);
+}
+
+#[test]
+fn closing_a_connection_rolls_back_its_transaction() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE counters (id INTEGER PRIMARY KEY, n INTEGER)").unwrap();
+    db.execute("INSERT INTO counters VALUES (1, 0)").unwrap();
+    let other = db.connect().unwrap();
+    other.execute("BEGIN").unwrap();
+    other.execute("UPDATE counters SET n = 5").unwrap();
+    other.close().unwrap();
+
+    // The row is released at once
+    db.execute("UPDATE counters SET n = n + 1").unwrap();
+    assert_eq!(counters(&db), vec![Value::Integer(1)]);
+}
+
+// ---- Query Cancellation Tests ----
+
+#[test]
+fn cancelled_query_stops_early() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute("INSERT INTO t SELECT value FROM generate_series(1, 1000)").unwrap();
+
+    let handle = QueryHandle::new();
+    let canceller = handle.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        canceller.cancel();
+    });
+    // A million-row cross join takes far longer than 10ms
+    let result = db.query_cancellable("SELECT a.id, b.id FROM t a, t b", handle.flag());
+    assert!(matches!(result, Err(HorizonError::Cancelled)));
+    assert!(handle.is_cancelled());
+
+    // The flag only applies to the cancelled query
+    let result = db.query("SELECT COUNT(*) FROM t").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(1000));
+    let fresh = QueryHandle::new();
+    let result = db.query_cancellable("SELECT id FROM t WHERE id = 7", fresh.flag()).unwrap();
+    assert_eq!(result.rows.len(), 1);
+}
+
+// ---- Cache Size Tests ----
+
+#[test]
+fn pragma_cache_size_resizes_the_pool() {
+    let dir = TempDir::new().unwrap();
+    let db = Database::builder().cache_size(10).open(dir.path().join("test.hdb")).unwrap();
+    let cache_size = |db: &Database| db.query("PRAGMA cache_size").unwrap().rows[0].values[0].clone();
+    assert_eq!(cache_size(&db), Value::Integer(10));
+
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    for i in 0..200 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'row {}')", i, i)).unwrap();
+    }
+    let result = db.query("PRAGMA cache_size = 5").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Integer(5));
+    assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(200));
+
+    // A negative size is in KiB: 64 KiB of 4096-byte pages
+    db.execute("PRAGMA cache_size = -64").unwrap();
+    assert_eq!(cache_size(&db), Value::Integer(16));
+
+    db.execute("PRAGMA cache_size = 100").unwrap();
+    for i in 200..2000 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, 'row {}')", i, i)).unwrap();
+    }
+    assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(2000));
+    let result = db.query("SELECT name FROM t WHERE id = 1234").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("row 1234".into()));
+
+    assert!(db.execute("PRAGMA cache_size = 0").is_err());
+    assert_eq!(cache_size(&db), Value::Integer(100));
+}
+
+#[test]
+fn small_cache_evicts_to_the_wal_and_reads_back() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let db = Database::builder().cache_size(4).page_size(512).open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, payload TEXT)").unwrap();
+    for i in 0..20 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, '{}')", i, "x".repeat(150))).unwrap();
+    }
+    assert!(db.query("PRAGMA page_count").unwrap().rows[0].values[0].as_integer().unwrap() >= 8);
+    // Dirty pages pushed out of the 4-page cache went to the WAL
+    let wal_len = std::fs::metadata(dir.path().join("test.hdb-wal")).unwrap().len();
+    assert!(wal_len > 4 * 512, "WAL holds {} bytes", wal_len);
+
+    for _ in 0..3 {
+        for i in (0..20).rev() {
+            let result = db.query(&format!("SELECT length(payload) FROM t WHERE id = {}", i)).unwrap();
+            assert_eq!(result.rows[0].values[0], Value::Integer(150));
+        }
+    }
+    assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(20));
+}
+
+// ---- Journal Mode Tests ----
+
+#[test]
+fn pragma_journal_mode_switches_between_wal_and_delete() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.hdb");
+    let mode = |db: &Database| db.query("PRAGMA journal_mode").unwrap().rows[0].values[0].clone();
+
+    let db = Database::open(&path).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'wal')").unwrap();
+    let result = db.query("PRAGMA journal_mode = delete").unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("delete".into()));
+    assert!(!path.with_extension("hdb-wal").exists());
+    db.execute("INSERT INTO t VALUES (2, 'delete')").unwrap();
+    db.close().unwrap();
+    assert!(!path.with_extension("hdb-journal").exists());
+
+    let db = Database::builder().journal_mode(JournalMode::Delete).open(&path).unwrap();
+    assert_eq!(mode(&db), Value::Text("delete".into()));
+    db.execute("INSERT INTO t VALUES (3, 'reopened')").unwrap();
+    db.execute("PRAGMA journal_mode = WAL").unwrap();
+    assert_eq!(mode(&db), Value::Text("wal".into()));
+    db.execute("INSERT INTO t VALUES (4, 'wal again')").unwrap();
+    db.execute("PRAGMA journal_mode = memory").unwrap();
+    db.execute("INSERT INTO t VALUES (5, 'memory')").unwrap();
+    assert!(db.execute("PRAGMA journal_mode = sideways").is_err());
+    db.close().unwrap();
+
+    let db = Database::open(&path).unwrap();
+    assert_eq!(mode(&db), Value::Text("wal".into()));
+    let result = db.query("SELECT id, name FROM t ORDER BY id").unwrap();
+    let names: Vec<Value> = result.rows.into_iter().map(|r| r.values[1].clone()).collect();
+    assert_eq!(
+        names,
+        ["wal", "delete", "reopened", "wal again", "memory"].map(|n| Value::Text(n.into())).to_vec()
+    );
+
+    let memory = Database::open(":memory:").unwrap();
+    assert_eq!(memory.query("PRAGMA journal_mode = delete").unwrap().rows[0].values[0], Value::Text("memory".into()));
+}
+
+// ---- Synchronous Tests ----
+
+#[test]
+fn pragma_synchronous_modes_survive_a_clean_shutdown() {
+    let dir = TempDir::new().unwrap();
+    let levels = [("OFF", 0), ("normal", 1), ("FULL", 2), ("extra", 3)];
+    for (i, (name, level)) in levels.into_iter().enumerate() {
+        let path = dir.path().join(format!("{}.hdb", i));
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.query("PRAGMA synchronous").unwrap().rows[0].values[0], Value::Integer(1));
+        let result = db.query(&format!("PRAGMA synchronous = {}", name)).unwrap();
+        assert_eq!(result.rows[0].values[0], Value::Integer(level));
+        db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)").unwrap();
+        for j in 0..50 {
+            db.execute(&format!("INSERT INTO t VALUES ({}, 'value {}')", j, j)).unwrap();
+        }
+        db.execute("PRAGMA journal_mode = delete").unwrap();
+        db.execute("INSERT INTO t VALUES (50, 'journaled')").unwrap();
+        db.close().unwrap();
+
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.query("SELECT COUNT(*) FROM t").unwrap().rows[0].values[0], Value::Integer(51));
+        assert_eq!(db.query("PRAGMA integrity_check").unwrap().rows[0].values[0], Value::Text("ok".into()));
+    }
+
+    let db = Database::open(dir.path().join("levels.hdb")).unwrap();
+    db.execute("PRAGMA synchronous = 2").unwrap();
+    assert_eq!(db.query("PRAGMA synchronous").unwrap().rows[0].values[0], Value::Integer(2));
+    assert!(db.execute("PRAGMA synchronous = 4").is_err());
+}
+
+// ---- Snapshot Tests ----
+
+#[test]
+fn snapshot_reader_keeps_seeing_rows_from_before_later_commits() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE acct (id INTEGER PRIMARY KEY, balance INTEGER)").unwrap();
+    db.execute("CREATE INDEX idx_balance ON acct (balance)").unwrap();
+    db.execute("INSERT INTO acct VALUES (1, 100), (2, 200), (3, 300)").unwrap();
+
+    let snapshot = db.begin_snapshot().unwrap();
+    db.execute("UPDATE acct SET balance = 150 WHERE id = 1").unwrap();
+    db.execute("DELETE FROM acct WHERE id = 2").unwrap();
+    db.execute("BEGIN").unwrap();
+    db.execute("INSERT INTO acct VALUES (4, 400)").unwrap();
+    db.execute("COMMIT").unwrap();
+
+    let balances = |result: horizon::QueryResult| -> Vec<i64> {
+        result.rows.iter().map(|r| r.values[1].as_integer().unwrap()).collect()
+    };
+    let old = db.query_at_snapshot(snapshot, "SELECT id, balance FROM acct ORDER BY id").unwrap();
+    assert_eq!(balances(old), vec![100, 200, 300]);
+    let seek = db.query_at_snapshot(snapshot, "SELECT id, balance FROM acct WHERE balance = 200").unwrap();
+    assert_eq!(balances(seek), vec![200]);
+    let count = db.query_at_snapshot(snapshot, "SELECT COUNT(*) FROM acct").unwrap();
+    assert_eq!(count.rows[0].values[0], Value::Integer(3));
+    let current = db.query("SELECT id, balance FROM acct ORDER BY id").unwrap();
+    assert_eq!(balances(current), vec![150, 300, 400]);
+
+    db.release_snapshot(snapshot).unwrap();
+    assert!(db.query_at_snapshot(snapshot, "SELECT * FROM acct").is_err());
+    let snapshot = db.begin_snapshot().unwrap();
+    let fresh = db.query_at_snapshot(snapshot, "SELECT id, balance FROM acct ORDER BY id").unwrap();
+    assert_eq!(balances(fresh), vec![150, 300, 400]);
+    assert!(db.query_at_snapshot(snapshot, "DELETE FROM acct").is_err());
+    db.release_snapshot(snapshot).unwrap();
+}
+
+#[test]
+fn snapshot_hides_transactions_open_on_other_connections() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 11), (2, 22)").unwrap();
+    let other = db.connect().unwrap();
+    let values = |snapshot| -> Vec<Vec<Value>> {
+        let result = db.query_at_snapshot(snapshot, "SELECT id, v FROM t ORDER BY id").unwrap();
+        result.rows.into_iter().map(|r| r.values).collect()
+    };
+    let before = vec![
+        vec![Value::Integer(1), Value::Integer(11)],
+        vec![Value::Integer(2), Value::Integer(22)],
+    ];
+
+    other.execute("BEGIN").unwrap();
+    other.execute("UPDATE t SET v = 99 WHERE id = 1").unwrap();
+    let snapshot = db.begin_snapshot().unwrap();
+    other.execute("INSERT INTO t VALUES (3, 33)").unwrap();
+    assert_eq!(values(snapshot), before);
+    other.execute("ROLLBACK").unwrap();
+    assert_eq!(values(snapshot), before);
+    db.release_snapshot(snapshot).unwrap();
+
+    // A transaction that commits after the snapshot stays hidden from it
+    other.execute("BEGIN").unwrap();
+    other.execute("DELETE FROM t WHERE id = 2").unwrap();
+    let snapshot = db.begin_snapshot().unwrap();
+    other.execute("COMMIT").unwrap();
+    assert_eq!(values(snapshot), before);
+    db.release_snapshot(snapshot).unwrap();
+    let snapshot = db.begin_snapshot().unwrap();
+    assert_eq!(values(snapshot), vec![vec![Value::Integer(1), Value::Integer(11)]]);
+    db.release_snapshot(snapshot).unwrap();
+}
+
+// ---- INSTEAD OF Trigger Tests ----
+
+#[test]
+fn instead_of_insert_trigger_writes_through_a_view() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE TABLE emails (person_id INTEGER PRIMARY KEY, email TEXT)").unwrap();
+    db.execute(
+        "CREATE VIEW contacts AS SELECT people.id, people.name, emails.email \
+         FROM people JOIN emails ON people.id = emails.person_id",
+    )
+    .unwrap();
+
+    let err = db.execute("INSERT INTO contacts VALUES (1, 'Ann', 'ann@example.com')").unwrap_err();
+    assert!(err.to_string().contains("because it is a view"), "{err}");
+
+    db.execute(
+        "CREATE TRIGGER contacts_insert INSTEAD OF INSERT ON contacts BEGIN \
+         INSERT INTO people VALUES (NEW.id, NEW.name); \
+         INSERT INTO emails VALUES (NEW.id, NEW.email); END",
+    )
+    .unwrap();
+    let inserted = db
+        .execute("INSERT INTO contacts VALUES (1, 'Ann', 'ann@example.com'), (2, 'Bob', 'bob@example.com')")
+        .unwrap();
+    assert_eq!(inserted, 2);
+    let people = db.query("SELECT id, name FROM people ORDER BY id").unwrap();
+    assert_eq!(people.len(), 2);
+    assert_eq!(people.rows[1].values[1], Value::Text("Bob".into()));
+    let emails = db.query("SELECT person_id, email FROM emails ORDER BY person_id").unwrap();
+    assert_eq!(emails.rows[0].values[1], Value::Text("ann@example.com".into()));
+    let view = db.query("SELECT * FROM contacts").unwrap();
+    assert_eq!(view.len(), 2);
+
+    // UPDATE and DELETE see OLD and NEW for each matching view row
+    db.execute(
+        "CREATE TRIGGER contacts_update INSTEAD OF UPDATE ON contacts BEGIN \
+         UPDATE emails SET email = NEW.email WHERE person_id = OLD.id; END",
+    )
+    .unwrap();
+    db.execute(
+        "CREATE TRIGGER contacts_delete INSTEAD OF DELETE ON contacts BEGIN \
+         DELETE FROM emails WHERE person_id = OLD.id; DELETE FROM people WHERE id = OLD.id; END",
+    )
+    .unwrap();
+    assert_eq!(db.execute("UPDATE contacts SET email = 'ann@new.example' WHERE name = 'Ann'").unwrap(), 1);
+    let email = db.query_row("SELECT email FROM emails WHERE person_id = 1").unwrap().unwrap();
+    assert_eq!(email.values[0], Value::Text("ann@new.example".into()));
+    assert_eq!(db.execute("DELETE FROM contacts WHERE id = 2").unwrap(), 1);
+    assert_eq!(db.query("SELECT * FROM people").unwrap().len(), 1);
+    assert_eq!(db.query("SELECT * FROM emails").unwrap().len(), 1);
+
+    assert!(db
+        .execute("CREATE TRIGGER bad INSTEAD OF INSERT ON people BEGIN DELETE FROM emails; END")
+        .is_err());
+    assert!(db
+        .execute("CREATE TRIGGER bad BEFORE INSERT ON contacts BEGIN DELETE FROM emails; END")
+        .is_err());
+}
+
+// ---- Trigger Row Reference Tests ----
+
+#[test]
+fn before_update_trigger_validates_new_row() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE employees (id INTEGER PRIMARY KEY, name TEXT, salary INTEGER)").unwrap();
+    db.execute("INSERT INTO employees VALUES (1, 'Ann', 100), (2, 'Bob', 200)").unwrap();
+    db.execute(
+        "CREATE TRIGGER check_salary BEFORE UPDATE ON employees WHEN NEW.salary <= 0 BEGIN \
+         SELECT RAISE(ABORT, 'salary must be positive'); END",
+    )
+    .unwrap();
+
+    let err = db.execute("UPDATE employees SET salary = -5 WHERE id = 1").unwrap_err();
+    assert!(err.to_string().contains("salary must be positive"));
+    let salary = db.query_row("SELECT salary FROM employees WHERE id = 1").unwrap().unwrap();
+    assert_eq!(salary.values[0], Value::Integer(100));
+
+    // OLD holds the row before the change
+    db.execute("CREATE TABLE raises (employee_id INTEGER, amount INTEGER)").unwrap();
+    db.execute(
+        "CREATE TRIGGER log_raise AFTER UPDATE ON employees BEGIN \
+         INSERT INTO raises VALUES (OLD.id, NEW.salary - OLD.salary); END",
+    )
+    .unwrap();
+    assert_eq!(db.execute("UPDATE employees SET salary = salary + 50").unwrap(), 2);
+    let raises = db.query("SELECT employee_id, amount FROM raises ORDER BY employee_id").unwrap();
+    assert_eq!(raises.len(), 2);
+    assert_eq!(raises.rows[1].values, vec![Value::Integer(2), Value::Integer(50)]);
+}
+
+#[test]
+fn after_insert_trigger_maintains_a_count_from_new_row() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT, book_count INTEGER DEFAULT 0)").unwrap();
+    db.execute("CREATE TABLE books (id INTEGER PRIMARY KEY, author_id INTEGER, title TEXT)").unwrap();
+    db.execute("INSERT INTO authors (id, name) VALUES (1, 'Le Guin'), (2, 'Herbert')").unwrap();
+    db.execute(
+        "CREATE TRIGGER count_books AFTER INSERT ON books BEGIN \
+         UPDATE authors SET book_count = book_count + 1 WHERE id = NEW.author_id; END",
+    )
+    .unwrap();
+    db.execute(
+        "CREATE TRIGGER uncount_books AFTER DELETE ON books BEGIN \
+         UPDATE authors SET book_count = book_count - 1 WHERE id = OLD.author_id; END",
+    )
+    .unwrap();
+
+    db.execute("INSERT INTO books (author_id, title) VALUES (1, 'Earthsea'), (1, 'The Dispossessed'), (2, 'Dune')")
+        .unwrap();
+    let counts = db.query("SELECT id, book_count FROM authors ORDER BY id").unwrap();
+    assert_eq!(counts.rows[0].values[1], Value::Integer(2));
+    assert_eq!(counts.rows[1].values[1], Value::Integer(1));
+
+    // NEW.id is the rowid the insert assigned
+    db.execute("CREATE TABLE latest (book_id INTEGER)").unwrap();
+    db.execute("CREATE TRIGGER track_latest AFTER INSERT ON books BEGIN INSERT INTO latest VALUES (NEW.id); END")
+        .unwrap();
+    db.execute("INSERT INTO books (author_id, title) VALUES (2, 'Children of Dune')").unwrap();
+    let latest = db.query_row("SELECT book_id FROM latest").unwrap().unwrap();
+    assert_eq!(latest.values[0], Value::Integer(4));
+
+    db.execute("DELETE FROM books WHERE author_id = 1").unwrap();
+    let count = db.query_row("SELECT book_count FROM authors WHERE id = 1").unwrap().unwrap();
+    assert_eq!(count.values[0], Value::Integer(0));
+}
+
+// ---- PRAGMA table_xinfo Tests ----
+
+#[test]
+fn pragma_table_xinfo_lists_generated_columns() {
+    fn check(db: &Database) {
+        let xinfo = db.query("PRAGMA table_xinfo(items)").unwrap();
+        assert_eq!(xinfo.columns.last().map(String::as_str), Some("hidden"));
+        let hidden: Vec<(&Value, &Value)> =
+            xinfo.rows.iter().map(|r| (r.get("name").unwrap(), r.get("hidden").unwrap())).collect();
+        assert_eq!(hidden, vec![
+            (&Value::Text("id".into()), &Value::Integer(0)),
+            (&Value::Text("price".into()), &Value::Integer(0)),
+            (&Value::Text("label".into()), &Value::Integer(0)),
+            (&Value::Text("doubled".into()), &Value::Integer(1)),
+            (&Value::Text("taxed".into()), &Value::Integer(2)),
+        ]);
+
+        let info = db.query("PRAGMA table_info(items)").unwrap();
+        assert_eq!(info.len(), 3);
+        assert_eq!(info.columns.len(), 6);
+        assert_eq!(info.rows[0].get("dflt_value"), Some(&Value::Null));
+        assert_eq!(info.rows[1].get("dflt_value"), Some(&Value::Text("10".into())));
+        assert_eq!(info.rows[2].get("dflt_value"), Some(&Value::Text("'n/a'".into())));
+    }
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("xinfo.hdb");
+    let db = Database::open(&path).unwrap();
+    db.execute(
+        "CREATE TABLE items (id INTEGER PRIMARY KEY, price INTEGER DEFAULT 10, label TEXT DEFAULT 'n/a', \
+         doubled INTEGER GENERATED ALWAYS AS (price * 2) VIRTUAL, \
+         taxed INTEGER GENERATED ALWAYS AS (price + 1) STORED)",
+    )
+    .unwrap();
+    check(&db);
+    db.close().unwrap();
+
+    // Defaults and generated columns survive a reopen
+    let db = Database::open(&path).unwrap();
+    check(&db);
+    db.execute("INSERT INTO items (id) VALUES (1)").unwrap();
+    let row = db.query_row("SELECT price, label, doubled, taxed FROM items").unwrap().unwrap();
+    assert_eq!(row.values, vec![
+        Value::Integer(10), Value::Text("n/a".into()), Value::Integer(20), Value::Integer(11),
+    ]);
+}
+
+// ---- Attached Database Tests ----
+
+#[test]
+fn attached_database_supports_cross_schema_queries() {
+    let (dir, db) = open_db();
+    let other_path = dir.path().join("other.db");
+    db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY, user_id INTEGER, kind TEXT)").unwrap();
+    db.execute("INSERT INTO events VALUES (1, 1, 'login'), (2, 2, 'login'), (3, 1, 'logout')").unwrap();
+
+    db.execute(&format!("ATTACH DATABASE '{}' AS other", other_path.display())).unwrap();
+    db.execute("CREATE TABLE other.users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE TABLE other.log (id INTEGER PRIMARY KEY, user_id INTEGER, kind TEXT)").unwrap();
+    db.execute("INSERT INTO other.users VALUES (1, 'alice'), (2, 'bob')").unwrap();
+    assert!(db.query("SELECT * FROM users").is_err());
+
+    let users = db.query("SELECT * FROM other.users ORDER BY id").unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!(users.rows[1].get("name"), Some(&Value::Text("bob".into())));
+
+    assert_eq!(db.execute("INSERT INTO other.log SELECT * FROM main.events").unwrap(), 3);
+    db.execute("UPDATE other.log SET kind = 'signin' WHERE kind = 'login'").unwrap();
+    db.execute("DELETE FROM other.log WHERE id = 2").unwrap();
+    let log = db.query("SELECT id, kind FROM other.log ORDER BY id").unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log.rows[0].get("kind"), Some(&Value::Text("signin".into())));
+
+    let joined = db
+        .query(
+            "SELECT u.name, e.kind FROM main.events e JOIN other.users u ON u.id = e.user_id \
+             ORDER BY e.id",
+        )
+        .unwrap();
+    let names: Vec<_> = joined.rows.iter().map(|r| r.get("name").cloned().unwrap()).collect();
+    assert_eq!(names, vec![
+        Value::Text("alice".into()), Value::Text("bob".into()), Value::Text("alice".into()),
+    ]);
+
+    let list = db.query("PRAGMA database_list").unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list.rows[1].get("name"), Some(&Value::Text("other".into())));
+
+    db.execute("DETACH DATABASE other").unwrap();
+    assert!(matches!(
+        db.query("SELECT * FROM other.users"),
+        Err(HorizonError::TableNotFound(_))
+    ));
+    db.close().unwrap();
+
+    // Changes made through the attachment were written to its own file
+    let other = Database::open(&other_path).unwrap();
+    assert_eq!(other.query("SELECT * FROM log").unwrap().len(), 2);
+    assert!(other.query("SELECT * FROM events").is_err());
+    other.close().unwrap();
+}
+
+#[test]
+fn attached_database_is_not_written_inside_a_transaction() {
+    let (dir, db) = open_db();
+    db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute(&format!("ATTACH DATABASE '{}' AS other", dir.path().join("other.db").display())).unwrap();
+    db.execute("CREATE TABLE other.users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO other.users VALUES (1, 'alice'), (2, 'bob')").unwrap();
+
+    db.execute("BEGIN").unwrap();
+    db.execute("INSERT INTO events VALUES (1)").unwrap();
+    let err = db.execute("DELETE FROM other.users").unwrap_err();
+    assert!(matches!(err, HorizonError::TransactionError(_)), "{:?}", err);
+    assert!(db.query("DELETE FROM other.users RETURNING id").is_err());
+    // Reading the attached database is still allowed
+    assert_eq!(db.query("SELECT * FROM other.users").unwrap().len(), 2);
+    db.execute("ROLLBACK").unwrap();
+    assert_eq!(db.query("SELECT * FROM other.users").unwrap().len(), 2);
+    assert!(db.query("SELECT * FROM events").unwrap().is_empty());
+
+    db.execute("SAVEPOINT sp").unwrap();
+    assert!(db.execute("UPDATE other.users SET name = 'carol'").is_err());
+    db.execute("RELEASE sp").unwrap();
+
+    // Outside a transaction the change commits at once
+    assert_eq!(db.execute("DELETE FROM other.users WHERE id = 2").unwrap(), 1);
+    assert_eq!(db.query("SELECT * FROM other.users").unwrap().len(), 1);
+}
+
+// ---- VACUUM INTO Tests ----
+
+#[test]
+fn vacuum_into_writes_a_compacted_copy() {
+    let (dir, db) = open_db();
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("BEGIN").unwrap();
+    for i in 1..=10000 {
+        db.execute(&format!("INSERT INTO items VALUES ({}, 'item number {}')", i, i)).unwrap();
+    }
+    db.execute("COMMIT").unwrap();
+    db.execute("DELETE FROM items WHERE id % 2 = 0").unwrap();
+    db.execute("PRAGMA user_version = 7").unwrap();
+
+    let copy_path = dir.path().join("copy.hdb");
+    db.execute(&format!("VACUUM INTO '{}'", copy_path.display())).unwrap();
+    let err = db.execute(&format!("VACUUM INTO '{}'", copy_path.display())).unwrap_err();
+    assert!(matches!(err, HorizonError::InvalidSql(_)));
+    db.close().unwrap();
+
+    let copy = Database::open(&copy_path).unwrap();
+    let rows = copy.query("SELECT id, name FROM items ORDER BY id").unwrap();
+    assert_eq!(rows.len(), 5000);
+    for (row, id) in rows.rows.iter().zip((1..=10000).step_by(2)) {
+        assert_eq!(row.values, vec![Value::Integer(id), Value::Text(format!("item number {}", id))]);
+    }
+    let version = copy.query_row("PRAGMA user_version").unwrap().unwrap();
+    assert_eq!(version.values, vec![Value::Integer(7)]);
+    let check = copy.query_row("PRAGMA integrity_check").unwrap().unwrap();
+    assert_eq!(check.values, vec![Value::Text("ok".into())]);
+    copy.execute("INSERT INTO items VALUES (10001, 'new')").unwrap();
+    copy.close().unwrap();
+
+    let original = std::fs::metadata(dir.path().join("test.hdb")).unwrap().len();
+    let compacted = std::fs::metadata(&copy_path).unwrap().len();
+    assert!(compacted < original, "copy is {} bytes, original {}", compacted, original);
+}
+
+#[test]
+fn vacuum_rewrites_the_database_in_place() {
+    let (dir, db) = open_db();
+    let path = dir.path().join("test.hdb");
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE TABLE dropped (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE INDEX idx_name ON items (name)").unwrap();
+    db.execute("BEGIN").unwrap();
+    for i in 1..=3000 {
+        db.execute(&format!("INSERT INTO items VALUES ({}, 'name {}')", i, i)).unwrap();
+        db.execute(&format!("INSERT INTO dropped VALUES ({}, 'name {}')", i, i)).unwrap();
+    }
+    db.execute("COMMIT").unwrap();
+    db.execute("DELETE FROM items WHERE id > 1000").unwrap();
+    db.execute("DROP TABLE dropped").unwrap();
+    let pages_before = db.query_row("PRAGMA page_count").unwrap().unwrap().values[0].clone();
+
+    db.execute("VACUUM").unwrap();
+    let pages_after = db.query_row("PRAGMA page_count").unwrap().unwrap().values[0].clone();
+    match (pages_before, pages_after) {
+        (Value::Integer(before), Value::Integer(after)) => assert!(after * 2 < before, "{} -> {}", before, after),
+        other => panic!("unexpected page counts {:?}", other),
+    }
+
+    // The index still works and the database accepts new writes
+    let row = db.query_row("SELECT id FROM items WHERE name = 'name 500'").unwrap().unwrap();
+    assert_eq!(row.values, vec![Value::Integer(500)]);
+    db.execute("INSERT INTO items VALUES (5000, 'late')").unwrap();
+    db.close().unwrap();
+    assert!(!dir.path().join("test.hdb-vacuum").exists());
+
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.query("SELECT * FROM items").unwrap().len(), 1001);
+    let check = db.query_row("PRAGMA integrity_check").unwrap().unwrap();
+    assert_eq!(check.values, vec![Value::Text("ok".into())]);
+    db.close().unwrap();
+
+    let memory = Database::open(":memory:").unwrap();
+    memory.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    memory.execute("INSERT INTO t VALUES (1), (2), (3)").unwrap();
+    memory.execute("DELETE FROM t WHERE id = 2").unwrap();
+    memory.execute("VACUUM").unwrap();
+    assert_eq!(memory.query("SELECT * FROM t").unwrap().len(), 2);
+}
+
+// ---- Overflow Page Tests ----
+
+#[test]
+fn large_blobs_round_trip_through_overflow_pages() {
+    let (dir, db) = open_db();
+    let path = dir.path().join("test.hdb");
+    let page_count = |db: &Database| match db.query_row("PRAGMA page_count").unwrap().unwrap().values[0] {
+        Value::Integer(n) => n,
+        ref other => panic!("unexpected page count {:?}", other),
+    };
+    db.execute("CREATE TABLE files (id INTEGER PRIMARY KEY, data BLOB, note TEXT)").unwrap();
+
+    let blob: Vec<u8> = (0..1_048_576u32).map(|i| (i * 7 % 256) as u8).collect();
+    db.execute_params("INSERT INTO files VALUES (1, ?, 'one')", &[Value::Blob(blob.clone())]).unwrap();
+    let row = db.query_row("SELECT data, note FROM files WHERE id = 1").unwrap().unwrap();
+    assert_eq!(row.values, vec![Value::Blob(blob.clone()), Value::Text("one".into())]);
+
+    let text = "horizon ".repeat(40_000);
+    db.execute_params("UPDATE files SET data = ?, note = ? WHERE id = 1", &[
+        Value::Blob(blob[..300_000].to_vec()),
+        Value::Text(text.clone()),
+    ])
+    .unwrap();
+    let row = db.query_row("SELECT length(data), note FROM files").unwrap().unwrap();
+    assert_eq!(row.values, vec![Value::Integer(300_000), Value::Text(text.clone())]);
+    db.close().unwrap();
+
+    let db = Database::open(&path).unwrap();
+    let row = db.query_row("SELECT data, note FROM files").unwrap().unwrap();
+    assert_eq!(row.values, vec![Value::Blob(blob[..300_000].to_vec()), Value::Text(text)]);
+
+    // The pages of a deleted row are reused by the next large value
+    db.execute("DELETE FROM files WHERE id = 1").unwrap();
+    let pages = page_count(&db);
+    db.execute_params("INSERT INTO files VALUES (2, ?, 'two')", &[Value::Blob(blob[..500_000].to_vec())]).unwrap();
+    assert_eq!(page_count(&db), pages);
+    let check = db.query_row("PRAGMA integrity_check").unwrap().unwrap();
+    assert_eq!(check.values, vec![Value::Text("ok".into())]);
+    db.close().unwrap();
+}
+
+#[test]
+fn create_index_refuses_keys_larger_than_a_quarter_page() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, body TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'short'), (2, REPEAT('x', 50000))").unwrap();
+
+    let err = db.execute("CREATE INDEX t_body ON t (body)").unwrap_err();
+    assert!(matches!(err, HorizonError::InvalidSql(ref msg) if msg.contains("too large")), "{err}");
+    assert!(db.query("PRAGMA index_list(t)").unwrap().is_empty());
+    assert_eq!(db.query("SELECT id FROM t").unwrap().len(), 2);
+}
+
+#[test]
+fn insert_refuses_index_keys_larger_than_a_quarter_page() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, body TEXT)").unwrap();
+    db.execute("CREATE INDEX t_body ON t (body)").unwrap();
+    for id in 1..=5 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, REPEAT('{}', 500))", id, id)).unwrap();
+    }
+
+    let err = db.execute("INSERT INTO t VALUES (6, REPEAT('f', 5000))").unwrap_err();
+    assert!(matches!(err, HorizonError::InvalidSql(ref msg) if msg.contains("too large")), "{err}");
+    assert!(db.query("SELECT id FROM t WHERE id = 6").unwrap().is_empty());
+    let err = db.execute("UPDATE t SET body = REPEAT('f', 5000) WHERE id = 2").unwrap_err();
+    assert!(matches!(err, HorizonError::InvalidSql(ref msg) if msg.contains("too large")), "{err}");
+    let row = db.query_row("SELECT body FROM t WHERE id = 2").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Text("2".repeat(500)));
+    let row = db.query_row("SELECT id FROM t WHERE body = REPEAT('3', 500)").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Integer(3));
+    let check = db.query_row("PRAGMA integrity_check").unwrap().unwrap();
+    assert_eq!(check.values, vec![Value::Text("ok".into())]);
+}
+
+// ---- Free List Tests ----
+
+#[test]
+fn deleted_rows_free_pages_for_new_rows() {
+    let (dir, db) = open_db();
+    let path = dir.path().join("test.hdb");
+    let pragma = |db: &Database, name: &str| match db.query_row(&format!("PRAGMA {}", name)).unwrap().unwrap().values[0] {
+        Value::Integer(n) => n,
+        ref other => panic!("unexpected {} {:?}", name, other),
+    };
+    let insert = |db: &Database, ids: std::ops::Range<i64>| {
+        let rows: Vec<String> = ids.map(|id| format!("({}, 'row {:05} {}')", id, id, "x".repeat(100))).collect();
+        db.execute(&format!("INSERT INTO t VALUES {}", rows.join(", "))).unwrap();
+    };
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, body TEXT)").unwrap();
+    db.execute("CREATE INDEX t_body ON t (body)").unwrap();
+    assert_eq!(pragma(&db, "freelist_count"), 0);
+
+    insert(&db, 1..1001);
+    let pages = pragma(&db, "page_count");
+    db.execute("DELETE FROM t").unwrap();
+    let free = pragma(&db, "freelist_count");
+    assert!(free > 20, "only {} free pages", free);
+
+    insert(&db, 1001..2001);
+    assert_eq!(pragma(&db, "page_count"), pages);
+    assert!(pragma(&db, "freelist_count") < free);
+    let check = db.query_row("PRAGMA integrity_check").unwrap().unwrap();
+    assert_eq!(check.values, vec![Value::Text("ok".into())]);
+    db.close().unwrap();
+
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.query("SELECT * FROM t WHERE id > 1000").unwrap().len(), 1000);
+    assert_eq!(pragma(&db, "page_count"), pages);
+    db.close().unwrap();
+}
+
+// ---- External Sort Tests ----
+
+#[test]
+fn order_by_spills_to_disk_past_the_threshold() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, grp INTEGER, name TEXT)").unwrap();
+    db.execute("BEGIN").unwrap();
+    for id in 0..20_000 {
+        db.execute(&format!("INSERT INTO t VALUES ({}, {}, 'name {}')", id, id * 7919 % 97, id % 1000)).unwrap();
+    }
+    db.execute("COMMIT").unwrap();
+
+    let queries = [
+        "SELECT id, grp FROM t ORDER BY grp",
+        "SELECT id, grp, name FROM t ORDER BY grp DESC, name",
+        "SELECT grp, count(*) FROM t GROUP BY grp ORDER BY count(*) DESC, grp",
+    ];
+    let in_memory: Vec<_> = queries.iter().map(|sql| db.query(sql).unwrap()).collect();
+    assert_eq!(db.query_row("PRAGMA sort_spill_threshold = 1000").unwrap().unwrap().values, vec![Value::Integer(1000)]);
+    for (sql, expected) in queries.iter().zip(&in_memory) {
+        let spilled = db.query(sql).unwrap();
+        assert_eq!(spilled.rows.len(), expected.rows.len());
+        assert!(spilled.rows.iter().zip(&expected.rows).all(|(a, b)| a.values == b.values), "{}", sql);
+    }
+    let first = db.query_row("SELECT id, grp FROM t ORDER BY grp DESC, id DESC LIMIT 1").unwrap().unwrap();
+    let expected = (0..20_000i64).rev().find(|id| id * 7919 % 97 == 96).unwrap();
+    assert_eq!(first.values, vec![Value::Integer(expected), Value::Integer(96)]);
+}
+
+// ---- Grace Hash Join Tests ----
+
+#[test]
+fn hash_join_partitions_to_disk_past_the_threshold() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE a (id INTEGER PRIMARY KEY, k INTEGER, label TEXT)").unwrap();
+    db.execute("CREATE TABLE b (id INTEGER PRIMARY KEY, k INTEGER, label TEXT)").unwrap();
+    db.execute("BEGIN").unwrap();
+    for table in ["a", "b"] {
+        for chunk in (0..50_000i64).collect::<Vec<_>>().chunks(1000) {
+            let values: Vec<String> = chunk
+                .iter()
+                .map(|id| format!("({}, {}, '{} {}')", id, id * 7919 % 40_000, table, id))
+                .collect();
+            db.execute(&format!("INSERT INTO {} VALUES {}", table, values.join(", "))).unwrap();
+        }
+    }
+    db.execute("COMMIT").unwrap();
+
+    let queries = [
+        "SELECT a.id, b.id FROM a JOIN b ON a.k = b.k",
+        "SELECT a.id, b.label FROM a LEFT JOIN b ON a.k = b.k WHERE a.id < 20000",
+        "SELECT count(*), sum(a.id), sum(b.id) FROM a JOIN b ON a.k = b.k",
+    ];
+    let in_memory: Vec<_> = queries.iter().map(|sql| db.query(sql).unwrap()).collect();
+    // 10 000 keys are taken by two rows of each table, the rest by one
+    assert_eq!(in_memory[0].rows.len(), 10_000 * 4 + 30_000);
+
+    let spill_files = || {
+        let prefix = format!("horizon-join-{}-", std::process::id());
+        std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix))
+            .count()
+    };
+    assert_eq!(db.query_row("PRAGMA join_spill_threshold = 1000").unwrap().unwrap().values, vec![Value::Integer(1000)]);
+    for (sql, expected) in queries.iter().zip(&in_memory) {
+        let spilled = db.query(sql).unwrap();
+        assert_eq!(spilled.rows.len(), expected.rows.len(), "{}", sql);
+        assert!(spilled.rows.iter().zip(&expected.rows).all(|(a, b)| a.values == b.values), "{}", sql);
+    }
+    assert_eq!(spill_files(), 0);
+}
+
+// ---- Predicate Pushdown Tests ----
+
+#[test]
+fn where_predicates_are_pushed_below_joins() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t1 (id INTEGER PRIMARY KEY, x INTEGER)").unwrap();
+    db.execute("CREATE TABLE t2 (id INTEGER PRIMARY KEY, y INTEGER)").unwrap();
+    for id in 0..20 {
+        db.execute(&format!("INSERT INTO t1 VALUES ({}, {})", id, id - 5)).unwrap();
+        db.execute(&format!("INSERT INTO t2 VALUES ({}, {})", id, id)).unwrap();
+    }
+
+    let sql = "SELECT * FROM t1 JOIN t2 ON t1.id=t2.id WHERE t1.x > 0 AND t2.y < 10";
+    let details: Vec<String> = db
+        .query(&format!("EXPLAIN QUERY PLAN {}", sql))
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| row.get("detail").unwrap().to_string())
+        .collect();
+    assert_eq!(
+        details,
+        vec!["SCAN TABLE t1 WHERE (t1.x > 0)", "SCAN TABLE t2 WHERE (t2.y < 10)", "MERGE JOIN ON INTEGER PRIMARY KEY"]
+    );
+
+    let ids: Vec<Value> = db.query(sql).unwrap().rows.iter().map(|row| row.values[0].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(6), Value::Integer(7), Value::Integer(8), Value::Integer(9)]);
+
+    // Filtering the NULL-padded side of a LEFT JOIN happens after the join
+    let sql = "SELECT t1.id FROM t1 LEFT JOIN t2 ON t1.id = t2.id + 15 WHERE t1.x > 8 AND t2.y IS NULL";
+    let details: Vec<String> = db
+        .query(&format!("EXPLAIN QUERY PLAN {}", sql))
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| row.get("detail").unwrap().to_string())
+        .collect();
+    assert_eq!(details, vec!["SCAN TABLE t1 WHERE (t1.x > 8)", "SCAN TABLE t2", "FILTER (t2.y IS NULL)"]);
+    let ids: Vec<Value> = db.query(sql).unwrap().rows.iter().map(|row| row.values[0].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(14)]);
+}
+
+// ---- Constant Folding Tests ----
+
+/// The detail column of `EXPLAIN QUERY PLAN sql`.
+fn query_plan_details(db: &Database, sql: &str) -> Vec<String> {
+    db.query(&format!("EXPLAIN QUERY PLAN {}", sql))
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| row.get("detail").unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn trivially_true_predicates_are_folded_away() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER NOT NULL, y INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 10, NULL), (2, 20, 5)").unwrap();
+
+    let opcodes: Vec<String> = db
+        .query("EXPLAIN SELECT * FROM t WHERE 1=1")
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| row.get("opcode").unwrap().to_string())
+        .collect();
+    assert!(!opcodes.iter().any(|op| op == "Filter"), "{:?}", opcodes);
+    assert_eq!(query_plan_details(&db, "SELECT * FROM t WHERE 1=1"), vec!["SCAN TABLE t"]);
+    assert_eq!(db.query("SELECT * FROM t WHERE 1=1").unwrap().rows.len(), 2);
+
+    // NOT NULL columns are never NULL and always equal to themselves
+    assert_eq!(query_plan_details(&db, "SELECT * FROM t WHERE x = x AND x IS NOT NULL"), vec!["SCAN TABLE t"]);
+    assert_eq!(
+        query_plan_details(&db, "SELECT * FROM t WHERE y = y AND y IS NOT NULL"),
+        vec!["SCAN TABLE t WHERE ((y = y) AND (y IS NOT NULL))"]
+    );
+    assert_eq!(db.query("SELECT id FROM t WHERE y = y").unwrap().rows.len(), 1);
+}
+
+#[test]
+fn trivially_false_predicates_return_no_rows() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER NOT NULL)").unwrap();
+    db.execute("CREATE TABLE u (id INTEGER PRIMARY KEY, t_id INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 10), (2, 20)").unwrap();
+    db.execute("INSERT INTO u VALUES (1, 1), (2, 3)").unwrap();
+
+    let result = db.query("SELECT * FROM t WHERE 1=0").unwrap();
+    assert_eq!(result.columns.as_ref(), &vec!["id".to_string(), "x".to_string()]);
+    assert!(result.rows.is_empty());
+    assert_eq!(query_plan_details(&db, "SELECT * FROM t WHERE 1=0"), vec!["SCAN TABLE t WHERE FALSE"]);
+
+    let result = db.query("SELECT count(*) FROM t JOIN u ON t.id = u.t_id WHERE t.x IS NULL").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Integer(0)]);
+    let result = db.query("SELECT t.id, u.id FROM t JOIN u ON t.id = u.t_id WHERE 'a' || 'b' = 'ba'").unwrap();
+    assert!(result.rows.is_empty());
+
+    // The NOT NULL column of the padded side of a LEFT JOIN can be NULL
+    db.execute("CREATE TABLE v (id INTEGER PRIMARY KEY, n INTEGER NOT NULL)").unwrap();
+    db.execute("INSERT INTO v VALUES (1, 7)").unwrap();
+    let result = db.query("SELECT t.id FROM t LEFT JOIN v ON t.id = v.id WHERE v.n IS NULL").unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(result.rows[0].values, vec![Value::Integer(2)]);
+}
+
+#[test]
+fn constant_arithmetic_folds_to_the_evaluated_value() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 7), (2, 8)").unwrap();
+
+    assert_eq!(
+        query_plan_details(&db, "SELECT id FROM t WHERE x > 1 + 2 * 3 AND NOT FALSE"),
+        vec!["SCAN TABLE t WHERE (x > 7)"]
+    );
+    let rows = db.query("SELECT id FROM t WHERE x > 1 + 2 * 3 AND NOT FALSE").unwrap().rows;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values, vec![Value::Integer(2)]);
+    let rows = db.query("SELECT id FROM t WHERE x = 16 / 2 AND 'x' || 'y' = 'xy'").unwrap().rows;
+    assert_eq!(rows[0].values, vec![Value::Integer(2)]);
+}
+
+// ---- Covering Index Tests ----
+
+#[test]
+fn covering_index_answers_queries_without_reading_the_table() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER, b TEXT, c TEXT)").unwrap();
+    db.execute(
+        "INSERT INTO t (a, b, c) SELECT value % 100, 'b' || (value % 7), 'padding ' || value FROM generate_series(1, 5000)",
+    )
+    .unwrap();
+    db.execute("INSERT INTO t (a, b, c) VALUES (5, NULL, 'no b')").unwrap();
+    db.execute("CREATE INDEX idx_ab ON t (a, b)").unwrap();
+
+    let accesses = |sql: &str| {
+        let before = db.page_accesses();
+        let rows: Vec<Vec<Value>> = db.query(sql).unwrap().rows.into_iter().map(|row| row.values).collect();
+        (rows, db.page_accesses() - before)
+    };
+    let (covered, covering_accesses) = accesses("SELECT a, b FROM t WHERE a = 5");
+    let (looked_up, lookup_accesses) = accesses("SELECT a, b, c FROM t WHERE a = 5");
+    assert_eq!(covered.len(), 51);
+    assert_eq!(covered[0], vec![Value::Integer(5), Value::Null]);
+    let expected: Vec<Vec<Value>> = looked_up.into_iter().map(|row| row[..2].to_vec()).collect();
+    assert_eq!(covered, expected);
+    // The index tree is a few pages deep; looking up 51 rows reads more
+    assert!(covering_accesses < 10, "{}", covering_accesses);
+    assert!(lookup_accesses > covering_accesses + 51, "{} vs {}", lookup_accesses, covering_accesses);
+
+    // Columns of the WHERE clause and ORDER BY must be in the index too
+    let (rows, _) = accesses("SELECT a FROM t WHERE a = 5 AND b = 'b3' ORDER BY b DESC");
+    assert_eq!(rows.len(), 7);
+    let (rows, _) = accesses("SELECT a FROM t WHERE a = 5 AND c = 'padding 5'");
+    assert_eq!(rows, vec![vec![Value::Integer(5)]]);
+}
+
+// ---- Query Cache Tests ----
+
+#[test]
+fn query_cache_serves_repeated_queries_until_a_table_is_written() {
+    let dir = TempDir::new().unwrap();
+    let db = Database::builder().query_cache(1 << 20).open(dir.path().join("test.hdb")).unwrap();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)").unwrap();
+    db.execute("CREATE TABLE u (id INTEGER PRIMARY KEY, y INTEGER)").unwrap();
+    db.execute("INSERT INTO t (x) SELECT value FROM generate_series(1, 1000)").unwrap();
+    db.execute("INSERT INTO u VALUES (1, 1)").unwrap();
+
+    let sql = "SELECT count(*), sum(x) FROM t WHERE x > 10";
+    let query = |sql: &str| {
+        let before = db.page_accesses();
+        let values = db.query(sql).unwrap().rows[0].values.clone();
+        (values, db.page_accesses() - before)
+    };
+    let (first, reads) = query(sql);
+    assert_eq!(first, vec![Value::Integer(990), Value::Integer(500445)]);
+    assert!(reads > 0);
+    assert_eq!(query(sql), (first.clone(), 0));
+
+    // A write to another table leaves the result cached
+    db.execute("INSERT INTO u VALUES (2, 2)").unwrap();
+    assert_eq!(query(sql), (first, 0));
+
+    db.execute("INSERT INTO t (x) VALUES (2000)").unwrap();
+    let (updated, reads) = query(sql);
+    assert_eq!(updated, vec![Value::Integer(991), Value::Integer(502445)]);
+    assert!(reads > 0);
+    db.execute("UPDATE t SET x = 0 WHERE x = 2000").unwrap();
+    assert_eq!(query(sql).0, vec![Value::Integer(990), Value::Integer(500445)]);
+
+    // Rolled back writes are not seen either
+    db.execute("BEGIN").unwrap();
+    db.execute("DELETE FROM t WHERE x > 500").unwrap();
+    assert_eq!(query(sql).0, vec![Value::Integer(490), Value::Integer(125195)]);
+    db.execute("ROLLBACK").unwrap();
+    assert_eq!(query(sql).0, vec![Value::Integer(990), Value::Integer(500445)]);
+
+    // Nondeterministic queries always run
+    let (_, reads) = query("SELECT random(), count(*) FROM t");
+    assert!(reads > 0);
+    let (_, reads) = query("SELECT random(), count(*) FROM t");
+    assert!(reads > 0);
+}
+
+// ---- Merge Join Tests ----
+
+#[test]
+fn primary_key_joins_are_merge_joins() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE a (id INTEGER PRIMARY KEY, k INTEGER, x TEXT)").unwrap();
+    db.execute("CREATE TABLE b (id INTEGER PRIMARY KEY, y INTEGER)").unwrap();
+    // a holds the multiples of 2 and b the multiples of 3, some negative
+    db.execute("INSERT INTO a SELECT value * 2, value * 2, 'x' || value FROM generate_series(-50, 3000)").unwrap();
+    db.execute("INSERT INTO b SELECT value * 3, value FROM generate_series(-50, 2000)").unwrap();
+
+    let details = |sql: &str| -> Vec<String> {
+        db.query(&format!("EXPLAIN QUERY PLAN {}", sql))
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| row.get("detail").unwrap().to_string())
+            .collect()
+    };
+    let rows = |sql: &str| -> Vec<Vec<Value>> {
+        let mut rows: Vec<Vec<Value>> = db.query(sql).unwrap().rows.into_iter().map(|row| row.values).collect();
+        rows.sort();
+        rows
+    };
+
+    for join in ["JOIN", "LEFT JOIN", "RIGHT JOIN", "FULL JOIN"] {
+        // a.k is a copy of a.id that is not a key, so joining on it is a hash join
+        let merged = format!("SELECT * FROM a {} b ON a.id = b.id", join);
+        let hashed = format!("SELECT * FROM a {} b ON a.k = b.id", join);
+        assert_eq!(details(&merged), vec!["SCAN TABLE a", "SCAN TABLE b", "MERGE JOIN ON INTEGER PRIMARY KEY"]);
+        assert_eq!(details(&hashed), vec!["SCAN TABLE a", "SCAN TABLE b"]);
+        assert_eq!(rows(&merged), rows(&hashed), "{}", join);
+    }
+    // The multiples of 6 from -96 to 6000
+    assert_eq!(rows("SELECT a.id FROM a JOIN b ON a.id = b.id").len(), 1017);
+
+    // Inner join rows come out in key order, filters included
+    let sql = "SELECT b.id, p.x FROM a AS p JOIN b ON b.id = p.id WHERE p.id BETWEEN 0 AND 30 AND b.y < 9";
+    assert_eq!(details(sql).last().unwrap(), "MERGE JOIN ON INTEGER PRIMARY KEY");
+    let ids: Vec<Value> = db.query(sql).unwrap().rows.iter().map(|row| row.values[0].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(0), Value::Integer(6), Value::Integer(12), Value::Integer(18), Value::Integer(24)]);
+}
+
+// ---- ROLLUP Tests ----
+
+#[test]
+fn group_by_rollup_adds_subtotals_and_a_grand_total() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE sales (region TEXT, product TEXT, year INTEGER, amount INTEGER)").unwrap();
+    db.execute(
+        "INSERT INTO sales VALUES \
+         ('east', 'apple', 2023, 10), ('east', 'apple', 2024, 20), ('east', 'pear', 2024, 5), \
+         ('west', 'apple', 2023, 7), ('west', 'pear', 2023, 3), ('west', 'pear', 2024, 4)",
+    )
+    .unwrap();
+
+    let rows = db
+        .query(
+            "SELECT region, product, year, sum(amount), grouping(region, product, year) \
+             FROM sales GROUP BY ROLLUP(region, product, year)",
+        )
+        .unwrap()
+        .rows;
+    let sets = |mask: i64| rows.iter().filter(|row| row.values[4] == Value::Integer(mask)).count();
+    // 6 (region, product, year) groups, 4 (region, product), 2 (region), 1 total
+    assert_eq!((sets(0), sets(1), sets(3), sets(7)), (6, 4, 2, 1));
+    assert_eq!(rows.len(), 13);
+    let total = rows.iter().find(|row| row.values[4] == Value::Integer(7)).unwrap();
+    assert_eq!(total.values, vec![Value::Null, Value::Null, Value::Null, Value::Integer(49), Value::Integer(7)]);
+    let west_pear = rows
+        .iter()
+        .find(|row| row.values[4] == Value::Integer(1) && row.values[0] == Value::Text("west".into())
+            && row.values[1] == Value::Text("pear".into()))
+        .unwrap();
+    assert_eq!(west_pear.values[3], Value::Integer(7));
+
+    // HAVING sees the rolled-up columns as NULL
+    let rows = db
+        .query("SELECT region, sum(amount) FROM sales GROUP BY ROLLUP(region) HAVING region IS NULL")
+        .unwrap()
+        .rows;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values, vec![Value::Null, Value::Integer(49)]);
+}
+
+#[test]
+fn grouping_tells_rolled_up_nulls_from_null_data() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (k TEXT, v INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES ('a', 1), ('a', 2), (NULL, 10)").unwrap();
+
+    let rows: Vec<Vec<Value>> = db
+        .query("SELECT k, grouping(k), sum(v) FROM t GROUP BY ROLLUP(k) ORDER BY grouping(k), sum(v)")
+        .unwrap()
+        .rows
+        .into_iter()
+        .map(|row| row.values)
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec![Value::Text("a".into()), Value::Integer(0), Value::Integer(3)],
+            vec![Value::Null, Value::Integer(0), Value::Integer(10)],
+            vec![Value::Null, Value::Integer(1), Value::Integer(13)],
+        ]
+    );
+
+    // Without ROLLUP every group is a full grouping
+    let rows = db.query("SELECT k, grouping(k) FROM t GROUP BY k").unwrap().rows;
+    assert!(rows.iter().all(|row| row.values[1] == Value::Integer(0)));
+
+    // A view keeps its ROLLUP
+    db.execute("CREATE VIEW totals AS SELECT k, sum(v) AS s FROM t GROUP BY ROLLUP(k)").unwrap();
+    assert_eq!(db.query("SELECT * FROM totals").unwrap().rows.len(), 3);
+}
+
+// ---- Recursive CTE CYCLE Tests ----
+
+/// Every node reachable from node 1 over `edges`, with its cycle mark and path.
+const REACHABLE: &str = "WITH RECURSIVE walk(node) AS ( \
+         SELECT 1 \
+         UNION ALL \
+         SELECT e.dst FROM edges e JOIN walk w ON e.src = w.node \
+     ) CYCLE node SET is_cycle USING path \
+     SELECT node, is_cycle, path FROM walk";
+
+#[test]
+fn cycle_clause_stops_recursion_at_a_self_loop() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE edges (src INTEGER, dst INTEGER)").unwrap();
+    db.execute("INSERT INTO edges VALUES (1, 2), (2, 2)").unwrap();
+
+    let rows: Vec<Vec<Value>> = db.query(REACHABLE).unwrap().rows.into_iter().map(|row| row.values).collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec![Value::Integer(1), Value::Integer(0), Value::Text("[1]".into())],
+            vec![Value::Integer(2), Value::Integer(0), Value::Text("[1,2]".into())],
+            vec![Value::Integer(2), Value::Integer(1), Value::Text("[1,2,2]".into())],
+        ]
+    );
+}
+
+#[test]
+fn cycle_clause_leaves_trees_unmarked() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE edges (src INTEGER, dst INTEGER)").unwrap();
+    db.execute("INSERT INTO edges VALUES (1, 2), (1, 3), (2, 4), (2, 5), (3, 6)").unwrap();
+
+    let rows = db.query(REACHABLE).unwrap().rows;
+    assert_eq!(rows.len(), 6);
+    assert!(rows.iter().all(|row| row.values[1] == Value::Integer(0)));
+    let path_of_5 = rows.iter().find(|row| row.values[0] == Value::Integer(5)).unwrap();
+    assert_eq!(path_of_5.values[2], Value::Text("[1,2,5]".into()));
+}
+
+#[test]
+fn cycle_column_marks_the_rows_that_close_a_cycle() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE edges (src INTEGER, dst INTEGER, label TEXT)").unwrap();
+    // 1 -> 2 -> 3 -> 1 is a cycle; 3 -> 4 leads out of it
+    db.execute("INSERT INTO edges VALUES (1, 2, 'a'), (2, 3, 'b'), (3, 1, 'c'), (3, 4, 'd')").unwrap();
+
+    let rows: Vec<Vec<Value>> = db
+        .query(
+            "WITH RECURSIVE walk(node, label) AS ( \
+                 SELECT 1, 'start' \
+                 UNION ALL \
+                 SELECT e.dst, e.label FROM edges e JOIN walk ON e.src = walk.node \
+             ) CYCLE node, label SET looped TO 'Y' DEFAULT 'N' USING trail \
+             SELECT node, label, looped FROM walk WHERE looped = 'Y'",
+        )
+        .unwrap()
+        .rows
+        .into_iter()
+        .map(|row| row.values)
+        .collect();
+    // (1, 'c') was not seen before, so the walk goes around once more
+    // before (2, 'a') repeats
+    assert_eq!(rows, vec![vec![Value::Integer(2), Value::Text("a".into()), Value::Text("Y".into())]]);
+
+    let count = db
+        .query(
+            "WITH RECURSIVE walk(node) AS ( \
+                 SELECT 1 UNION ALL SELECT e.dst FROM edges e JOIN walk ON e.src = walk.node \
+             ) CYCLE node SET looped USING trail \
+             SELECT count(*), sum(looped) FROM walk",
+        )
+        .unwrap();
+    // 1, 2, 3, then 1 (cycle) and 4
+    assert_eq!(count.rows[0].values, vec![Value::Integer(5), Value::Integer(1)]);
+}
+
+// ---- CTE Materialization Tests ----
+
+#[test]
+fn not_materialized_ctes_run_once_per_use() {
+    let (_dir, db) = open_db();
+    let same = |sql: &str| db.query(sql).unwrap().rows[0].values[0].clone();
+
+    // Each use of an inlined CTE draws its own random number
+    let inlined = same(
+        "WITH r AS NOT MATERIALIZED (SELECT random() AS x) \
+         SELECT a.x = b.x FROM r AS a CROSS JOIN r AS b",
+    );
+    assert_eq!(inlined, Value::Integer(0));
+
+    for hint in ["MATERIALIZED", ""] {
+        let materialized = same(&format!(
+            "WITH r AS {} (SELECT random() AS x) SELECT a.x = b.x FROM r AS a CROSS JOIN r AS b",
+            hint
+        ));
+        assert_eq!(materialized, Value::Integer(1), "{}", hint);
+    }
+}
+
+#[test]
+fn not_materialized_ctes_read_like_materialized_ones() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 10), (2, 20), (3, 30)").unwrap();
+
+    let rows = |hint: &str| -> Vec<Vec<Value>> {
+        let sql = format!(
+            "WITH big(k, w) AS {hint} (SELECT id, v FROM t WHERE v > 10), \
+                  bigger AS {hint} (SELECT k FROM big WHERE w > 20) \
+             SELECT big.k, bigger.k FROM big LEFT JOIN bigger ON big.k = bigger.k ORDER BY big.k",
+            hint = hint
+        );
+        db.query(&sql).unwrap().rows.into_iter().map(|row| row.values).collect()
+    };
+    let expected = vec![
+        vec![Value::Integer(2), Value::Null],
+        vec![Value::Integer(3), Value::Integer(3)],
+    ];
+    assert_eq!(rows("NOT MATERIALIZED"), expected);
+    assert_eq!(rows("MATERIALIZED"), expected);
+}
+
+// ---- Prepared Statement Tests ----
+
+#[test]
+fn prepared_statements_skip_parsing() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)").unwrap();
+    // Long to parse, quick to run: no row has id 0
+    let branches: String = (0..100).map(|i| format!(" WHEN v = {} THEN {}", i, i * 2)).collect();
+    let sql = format!("UPDATE t SET v = CASE{} ELSE ? END WHERE id = 0", branches);
+
+    let prepared = db.prepare(&sql).unwrap();
+    let params = [Value::Integer(1)];
+    let start = std::time::Instant::now();
+    for _ in 0..1000 {
+        assert_eq!(prepared.execute(&db, &params).unwrap(), 0);
+    }
+    let prepared_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    for _ in 0..1000 {
+        assert_eq!(db.execute_params(&sql, &params).unwrap(), 0);
+    }
+    let unprepared_time = start.elapsed();
+    assert!(prepared_time < unprepared_time, "prepared {:?}, unprepared {:?}", prepared_time, unprepared_time);
+}
+
+#[test]
+fn prepared_statements_run_with_new_parameters() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    let insert = db.prepare("INSERT INTO t VALUES (?, ?)").unwrap();
+    for (id, name) in [(1, "a"), (2, "b"), (3, "c")] {
+        assert_eq!(insert.execute(&db, &[Value::Integer(id), Value::Text(name.into())]).unwrap(), 1);
+    }
+
+    let select = db.prepare("SELECT name FROM t WHERE id >= ? ORDER BY id").unwrap();
+    let names = |min: i64| -> Vec<Value> {
+        select.query(&db, &[Value::Integer(min)]).unwrap().rows.into_iter().map(|row| row.values[0].clone()).collect()
+    };
+    assert_eq!(names(2), vec![Value::Text("b".into()), Value::Text("c".into())]);
+    assert_eq!(names(4), vec![]);
+    assert_eq!(select.sql(), "SELECT name FROM t WHERE id >= ? ORDER BY id");
+    assert!(db.prepare("SELEC name FROM t").is_err());
+}
+
+#[test]
+fn schema_changes_expire_prepared_statements() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute("INSERT INTO t VALUES (1)").unwrap();
+    let select = db.prepare("SELECT * FROM t WHERE id = ?").unwrap();
+    assert_eq!(select.query(&db, &[Value::Integer(1)]).unwrap().columns.len(), 1);
+
+    // Writing rows leaves the schema as it was
+    db.execute("INSERT INTO t VALUES (2)").unwrap();
+    assert!(!select.is_expired(&db).unwrap());
+
+    db.execute("ALTER TABLE t ADD COLUMN name TEXT").unwrap();
+    assert!(select.is_expired(&db).unwrap());
+    let result = select.query(&db, &[Value::Integer(1)]).unwrap();
+    assert_eq!(result.columns.len(), 2);
+    assert!(!select.is_expired(&db).unwrap());
+
+    db.execute("DROP TABLE t").unwrap();
+    assert!(select.is_expired(&db).unwrap());
+    assert!(matches!(select.query(&db, &[Value::Integer(1)]), Err(HorizonError::TableNotFound(_))));
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT, b TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'x', 'y')").unwrap();
+    assert_eq!(select.query(&db, &[Value::Integer(1)]).unwrap().columns.len(), 3);
+}
+
+// ---- Execute Hook Tests ----
+
+#[test]
+fn pre_execute_hooks_see_every_statement() {
+    let (_dir, db) = open_db();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+    let log = seen.clone();
+    db.set_pre_execute_hook(move |sql| log.lock().unwrap().push(sql.to_string())).unwrap();
+
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute_params("INSERT INTO t VALUES (?)", &[Value::Integer(1)]).unwrap();
+    db.query("SELECT * FROM t").unwrap();
+    db.query_row_params("SELECT * FROM t WHERE id = ?", &[Value::Integer(1)]).unwrap();
+    db.prepare("SELECT count(*) FROM t").unwrap().query(&db, &[]).unwrap();
+    assert!(db.execute("SELEC 1").is_err());
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            "CREATE TABLE t (id INTEGER PRIMARY KEY)",
+            "INSERT INTO t VALUES (?)",
+            "SELECT * FROM t",
+            "SELECT * FROM t WHERE id = ?",
+            "SELECT count(*) FROM t",
+            "SELEC 1",
+        ]
+    );
+}
+
+#[test]
+fn post_execute_hooks_see_results_and_errors() {
+    let (_dir, db) = open_db();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = seen.clone();
+    db.set_post_execute_hook(move |sql, result| {
+        let outcome = match result {
+            Ok(rows) => Ok(*rows),
+            Err(e) => Err(e.to_string()),
+        };
+        log.lock().unwrap().push((sql.to_string(), outcome));
+    })
+    .unwrap();
+
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    db.execute("INSERT INTO t VALUES (1), (2), (3)").unwrap();
+    assert_eq!(db.query("SELECT * FROM t WHERE id > 1").unwrap().len(), 2);
+    let error = db.execute("INSERT INTO t VALUES (1)").unwrap_err();
+    assert!(db.query("SELECT * FROM missing").is_err());
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 5);
+    assert_eq!(seen[1], ("INSERT INTO t VALUES (1), (2), (3)".to_string(), Ok(3)));
+    assert_eq!(seen[2], ("SELECT * FROM t WHERE id > 1".to_string(), Ok(2)));
+    assert_eq!(seen[3], ("INSERT INTO t VALUES (1)".to_string(), Err(error.to_string())));
+    assert!(seen[4].1.is_err());
+}
+
+#[test]
+fn execute_hooks_may_use_the_database() {
+    let (_dir, db) = open_db();
+    let db = std::sync::Arc::new(db);
+    db.execute("CREATE TABLE audit (sql TEXT)").unwrap();
+    db.execute("CREATE TABLE t (id INTEGER)").unwrap();
+    let audit = std::sync::Arc::downgrade(&db);
+    db.set_post_execute_hook(move |sql, _| {
+        if let (Some(db), false) = (audit.upgrade(), sql.contains("audit")) {
+            db.execute_params("INSERT INTO audit VALUES (?)", &[Value::Text(sql.to_string())]).unwrap();
+        }
+    })
+    .unwrap();
+
+    db.execute("INSERT INTO t VALUES (1)").unwrap();
+    db.query("SELECT * FROM t").unwrap();
+    let logged = db.query("SELECT sql FROM audit").unwrap();
+    assert_eq!(logged.len(), 2);
+    assert_eq!(logged.rows[0].values[0], Value::Text("INSERT INTO t VALUES (1)".into()));
+}
+
+// ---- Collation Tests ----
+
+/// A case-insensitive German collation: umlauts sort with their base
+/// letters and ß as "ss".
+fn german(a: &str, b: &str) -> std::cmp::Ordering {
+    let fold = |s: &str| -> String {
+        s.to_lowercase()
+            .chars()
+            .flat_map(|c| match c {
+                'ä' => vec!['a'],
+                'ö' => vec!['o'],
+                'ü' => vec!['u'],
+                'ß' => vec!['s', 's'],
+                c => vec![c],
+            })
+            .collect()
+    };
+    fold(a).cmp(&fold(b))
+}
+
+fn german_words(db: &Database) {
+    db.execute("CREATE TABLE words (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO words (name) VALUES ('Zebra'), ('Äpfel'), ('Affe'), ('birne'), ('Öl'), ('Ofen')").unwrap();
+}
+
+fn names(db: &Database, sql: &str) -> Vec<String> {
+    db.query(sql).unwrap().rows.iter().map(|row| row.get_typed::<String>("name").unwrap()).collect()
+}
+
+#[test]
+fn order_by_uses_registered_collations() {
+    let (_dir, db) = open_db();
+    german_words(&db);
+    db.create_collation("DE", german).unwrap();
+
+    assert_eq!(
+        names(&db, "SELECT name FROM words ORDER BY name COLLATE DE"),
+        vec!["Affe", "Äpfel", "birne", "Ofen", "Öl", "Zebra"]
+    );
+    assert_eq!(
+        names(&db, "SELECT name FROM words ORDER BY name COLLATE de DESC"),
+        vec!["Zebra", "Öl", "Ofen", "birne", "Äpfel", "Affe"]
+    );
+    // Without the collation, umlauts sort after every ASCII letter
+    assert_eq!(
+        names(&db, "SELECT name FROM words ORDER BY name"),
+        vec!["Affe", "Ofen", "Zebra", "birne", "Äpfel", "Öl"]
+    );
+}
+
+#[test]
+fn registered_collations_replace_earlier_ones() {
+    let (_dir, db) = open_db();
+    german_words(&db);
+    db.create_collation("NOCASE", |a, b| b.cmp(a)).unwrap();
+    assert_eq!(names(&db, "SELECT name FROM words ORDER BY name COLLATE NOCASE LIMIT 2"), vec!["Öl", "Äpfel"]);
+    db.create_collation("NOCASE", |a, b| a.len().cmp(&b.len()).then(a.cmp(b))).unwrap();
+    assert_eq!(names(&db, "SELECT name FROM words ORDER BY name COLLATE NOCASE LIMIT 2"), vec!["Öl", "Affe"]);
+}
+
+#[test]
+fn indexes_may_use_registered_collations() {
+    let (_dir, db) = open_db();
+    german_words(&db);
+    db.create_collation("DE", german).unwrap();
+    db.execute("CREATE INDEX words_de ON words (name COLLATE DE)").unwrap();
+    db.execute("INSERT INTO words (name) VALUES ('Öfen')").unwrap();
+
+    assert_eq!(
+        names(&db, "SELECT name FROM words ORDER BY name COLLATE DE"),
+        vec!["Affe", "Äpfel", "birne", "Ofen", "Öfen", "Öl", "Zebra"]
+    );
+    assert_eq!(names(&db, "SELECT name FROM words WHERE name = 'Öl'"), vec!["Öl"]);
+    let integrity = db.query("PRAGMA integrity_check").unwrap();
+    assert_eq!(integrity.rows[0].values[0], Value::Text("ok".into()));
+
+    // The index holds the rows in DE order, so the ORDER BY needs no sort
+    assert_eq!(
+        query_plan_details(&db, "SELECT name FROM words ORDER BY name COLLATE de"),
+        vec!["SCAN TABLE words USING INDEX words_de"]
+    );
+    assert_eq!(
+        query_plan_details(&db, "SELECT name FROM words ORDER BY name"),
+        vec!["SCAN TABLE words", "USE TEMP B-TREE FOR ORDER BY"]
+    );
+}
+
+#[test]
+fn collated_index_keeps_its_entries_in_collation_order() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE INDEX t_name ON t (name COLLATE NOCASE)").unwrap();
+    let prefixes = ["apple", "Banana", "cherry", "APPLE", "banana"];
+    let mut expected = Vec::new();
+    for id in 1..=600i64 {
+        let name = format!("{}{:03}", prefixes[id as usize % 5], (id * 37) % 150);
+        db.execute(&format!("INSERT INTO t VALUES ({}, '{}')", id, name)).unwrap();
+        expected.push((id, name));
+    }
+    db.execute("DELETE FROM t WHERE id % 7 = 0").unwrap();
+    expected.retain(|(id, _)| id % 7 != 0);
+    // Names equal ignoring case stay in rowid order
+    expected.sort_by_key(|(id, name)| (name.to_lowercase(), *id));
+
+    let sql = "SELECT id, name FROM t ORDER BY name COLLATE NOCASE";
+    assert_eq!(query_plan_details(&db, sql), vec!["SCAN TABLE t USING INDEX t_name"]);
+    let rows: Vec<(i64, String)> = db
+        .query(sql)
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| (row.get_typed("id").unwrap(), row.get_typed("name").unwrap()))
+        .collect();
+    assert_eq!(rows, expected);
+
+    let sql = "SELECT id FROM t WHERE id % 2 = 0 ORDER BY name COLLATE NOCASE";
+    assert_eq!(query_plan_details(&db, sql), vec!["SCAN TABLE t USING INDEX t_name WHERE ((id % 2) = 0)"]);
+    assert_eq!(db.query(sql).unwrap().len(), expected.iter().filter(|(id, _)| id % 2 == 0).count());
+    // A descending order, or a seek on the primary key, still sorts
+    assert_eq!(
+        query_plan_details(&db, "SELECT id FROM t ORDER BY name COLLATE NOCASE DESC"),
+        vec!["SCAN TABLE t", "USE TEMP B-TREE FOR ORDER BY"]
+    );
+    assert_eq!(
+        query_plan_details(&db, "SELECT id FROM t WHERE id > 500 ORDER BY name COLLATE NOCASE"),
+        vec!["SEARCH TABLE t USING INTEGER PRIMARY KEY (rowid>?) WHERE (id > 500)", "USE TEMP B-TREE FOR ORDER BY"]
+    );
+    let integrity = db.query("PRAGMA integrity_check").unwrap();
+    assert_eq!(integrity.rows[0].values[0], Value::Text("ok".into()));
+}
+
+#[test]
+fn unknown_collations_are_rejected() {
+    let (_dir, db) = open_db();
+    german_words(&db);
+    let err = db.query("SELECT name FROM words ORDER BY name COLLATE DE").err().unwrap();
+    assert!(err.to_string().contains("no such collation sequence: DE"), "{}", err);
+    let err = db.execute("CREATE INDEX words_de ON words (name COLLATE DE)").unwrap_err();
+    assert!(err.to_string().contains("no such collation sequence: DE"), "{}", err);
+
+    db.create_collation("DE", german).unwrap();
+    db.execute("CREATE INDEX words_de ON words (name COLLATE DE)").unwrap();
+    assert_eq!(names(&db, "SELECT name FROM words ORDER BY name COLLATE DE LIMIT 2"), vec!["Affe", "Äpfel"]);
+}
+
+#[test]
+fn order_by_terms_resolve_against_table_and_output_columns() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, score INTEGER)").unwrap();
+    db.execute("INSERT INTO t (name, score) VALUES ('b', 2), ('c', 1), ('a', 3)").unwrap();
+    assert_eq!(names(&db, "SELECT name FROM t ORDER BY name"), vec!["a", "b", "c"]);
+    assert_eq!(names(&db, "SELECT name FROM t ORDER BY score"), vec!["c", "b", "a"]);
+    assert_eq!(names(&db, "SELECT upper(name) AS name FROM t ORDER BY 1 DESC"), vec!["C", "B", "A"]);
+    assert_eq!(names(&db, "SELECT name, score * -1 AS neg FROM t ORDER BY neg"), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn unicode_collations_compare_case_and_accent_insensitively() {
+    let (_dir, db) = open_db();
+    let value = |sql: &str| db.query(sql).unwrap().rows[0].values[0].clone();
+    assert_eq!(value("SELECT 'Ö' = 'ö' COLLATE UNICODE"), Value::Integer(1));
+    assert_eq!(value("SELECT 'Ö' = 'ö'"), Value::Integer(0));
+    assert_eq!(value("SELECT 'Ö' = 'ö' COLLATE NOCASE"), Value::Integer(1));
+    assert_eq!(value("SELECT 'café' = 'cafe' COLLATE UNICODENOACCENT"), Value::Integer(1));
+    assert_eq!(value("SELECT 'CAFÉ' COLLATE unicodenoaccent = 'cafe'"), Value::Integer(1));
+    assert_eq!(value("SELECT 'café' = 'cafe' COLLATE UNICODE"), Value::Integer(0));
+    assert_eq!(value("SELECT 'é' < 'f' COLLATE UNICODE"), Value::Integer(1));
+
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE INDEX t_name ON t (name)").unwrap();
+    db.execute("CREATE INDEX t_name_unicode ON t (name COLLATE UNICODE)").unwrap();
+    db.execute("INSERT INTO t (name) VALUES ('Café'), ('cafe'), ('CAFÉ'), ('caff')").unwrap();
+    let count = |sql: &str| db.query(sql).unwrap().len();
+    assert_eq!(count("SELECT id FROM t WHERE name = 'café' COLLATE UNICODE"), 2);
+    assert_eq!(count("SELECT id FROM t WHERE name COLLATE UNICODE = 'café'"), 2);
+    assert_eq!(count("SELECT id FROM t WHERE name = 'cafe' COLLATE UNICODENOACCENT"), 3);
+    assert_eq!(count("SELECT id FROM t WHERE name = 'cafe'"), 1);
+}
+
+#[test]
+fn unicode_collations_sort_accented_letters_with_their_base_letters() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE words (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute(
+        "INSERT INTO words (name) VALUES ('zebra'), ('Éclair'), ('eclair'), ('apple'), ('Ölig'), ('offen'), ('ebony')",
+    )
+    .unwrap();
+    assert_eq!(
+        names(&db, "SELECT name FROM words ORDER BY name COLLATE UNICODE"),
+        vec!["apple", "ebony", "eclair", "Éclair", "offen", "Ölig", "zebra"]
+    );
+    // Binary order puts every accented capital after the ASCII letters
+    assert_eq!(names(&db, "SELECT name FROM words ORDER BY name LIMIT 1 OFFSET 5"), vec!["Éclair"]);
+}
+
+// ---- Multibyte String Function Tests ----
+
+#[test]
+fn string_functions_count_characters_not_bytes() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, val TEXT)").unwrap();
+    db.execute("INSERT INTO t VALUES (1, 'こんにちは'), (2, 'café'), (3, 'naïve'), (4, '🎉 party 🎉')").unwrap();
+
+    let row = db
+        .query_row("SELECT LENGTH(val), OCTET_LENGTH(val), REVERSE(val) FROM t WHERE id = 1")
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values, vec![Value::Integer(5), Value::Integer(15), Value::Text("はちにんこ".into())]);
+
+    let row = db.query_row("SELECT SUBSTR(val, 3), LPAD(val, 6, 'é') FROM t WHERE id = 2").unwrap().unwrap();
+    assert_eq!(row.values, vec![Value::Text("fé".into()), Value::Text("éécafé".into())]);
+
+    let row = db.query_row("SELECT INSTR(val, 'ï'), SUBSTR(val, INSTR(val, 'ï')) FROM t WHERE id = 3").unwrap().unwrap();
+    assert_eq!(row.values, vec![Value::Integer(3), Value::Text("ïve".into())]);
+
+    let row = db
+        .query_row("SELECT LENGTH(val), TRIM(val, '🎉 '), INSTR(val, 'p'), RTRIM(val, '🎉') FROM t WHERE id = 4")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        row.values,
+        vec![
+            Value::Integer(9),
+            Value::Text("party".into()),
+            Value::Integer(3),
+            Value::Text("🎉 party ".into()),
+        ]
+    );
+
+    let result = db.query("SELECT id FROM t WHERE LENGTH(val) = 5 ORDER BY id").unwrap();
+    let ids: Vec<_> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(ids, vec![Value::Integer(1), Value::Integer(3)]);
+}
+
+// ---- Row To JSON Tests ----
+
+#[test]
+fn row_to_json_serializes_the_current_row() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice', 9.5), (2, 'bob', NULL)").unwrap();
+
+    let result = db.query("SELECT ROW_TO_JSON(*) AS doc FROM users ORDER BY id LIMIT 1").unwrap();
+    assert_eq!(result.columns.as_ref(), &["doc".to_string()]);
+    assert_eq!(result.rows[0].values[0], Value::Text(r#"{"id":1,"name":"alice","score":9.5}"#.into()));
+
+    let row = db.query_row("SELECT JSON_EXTRACT(ROW_TO_JSON(u.*), '$.name') FROM users u WHERE id = 2").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Text("bob".into()));
+    let row = db.query_row("SELECT ROW_TO_JSON(*) FROM users WHERE id = 2").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Text(r#"{"id":2,"name":"bob","score":null}"#.into()));
+
+    assert!(db.query("SELECT ROW_TO_JSON(name) FROM users").is_err());
+}
+
+#[test]
+fn row_to_json_of_one_side_of_a_join() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER, total INTEGER)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'alice')").unwrap();
+    db.execute("INSERT INTO orders VALUES (10, 1, 30)").unwrap();
+
+    let row = db
+        .query_row("SELECT ROW_TO_JSON(o.*) FROM users u JOIN orders o ON o.user_id = u.id")
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values[0], Value::Text(r#"{"id":10,"user_id":1,"total":30}"#.into()));
+}
+
+#[test]
+fn row_to_json_qualifies_names_shared_across_a_join() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE u (id INTEGER PRIMARY KEY, name TEXT, n INTEGER)").unwrap();
+    db.execute("INSERT INTO u VALUES (1, 'alice', 3)").unwrap();
+    db.execute("CREATE TABLE tags (user_id INTEGER, tag TEXT)").unwrap();
+    db.execute("INSERT INTO tags VALUES (1, 'admin')").unwrap();
+
+    let row = db.query_row("SELECT ROW_TO_JSON(*) FROM u a JOIN u b ON a.id = b.id").unwrap().unwrap();
+    assert_eq!(
+        row.values[0],
+        Value::Text(r#"{"a.id":1,"a.name":"alice","a.n":3,"b.id":1,"b.name":"alice","b.n":3}"#.into())
+    );
+    // Names only one table has stay unqualified
+    let row = db.query_row("SELECT ROW_TO_JSON(*) FROM u JOIN tags ON tags.user_id = u.id").unwrap().unwrap();
+    assert_eq!(
+        row.values[0],
+        Value::Text(r#"{"id":1,"name":"alice","n":3,"user_id":1,"tag":"admin"}"#.into())
+    );
+}
+
+#[test]
+fn json_agg_collects_a_group_into_an_array() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, team TEXT, name TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'red', 'alice'), (2, 'red', 'bob'), (3, 'blue', NULL), (4, 'blue', 'carol')")
+        .unwrap();
+
+    let result = db.query("SELECT team, JSON_AGG(name) FROM users GROUP BY team ORDER BY team").unwrap();
+    assert_eq!(result.rows[0].values, vec![Value::Text("blue".into()), Value::Text(r#"[null,"carol"]"#.into())]);
+    assert_eq!(result.rows[1].values, vec![Value::Text("red".into()), Value::Text(r#"["alice","bob"]"#.into())]);
+
+    let row = db.query_row("SELECT JSON_AGG(id) FROM users WHERE id > 2").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Text("[3,4]".into()));
+    let row = db.query_row("SELECT JSON_AGG(id) FROM users WHERE id > 9").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Null);
+}
+
+// ---- Change Data Capture Tests ----
+
+#[test]
+fn cdc_records_inserts_updates_and_deletes() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'before cdc')").unwrap();
+    assert!(db.cdc_events_since(0).unwrap().is_empty());
+
+    db.enable_cdc("users").unwrap();
+    db.execute("INSERT INTO users VALUES (2, 'alice'), (3, 'bob')").unwrap();
+    db.execute("UPDATE users SET name = 'ALICE' WHERE id = 2").unwrap();
+    db.execute("DELETE FROM users WHERE id = 3").unwrap();
+
+    let events = db.cdc_events_since(0).unwrap();
+    let operations: Vec<_> = events.iter().map(|e| e.operation).collect();
+    assert_eq!(
+        operations,
+        vec![CdcOperation::Insert, CdcOperation::Insert, CdcOperation::Update, CdcOperation::Delete]
+    );
+    assert!(events.iter().all(|e| e.table_name == "users" && e.changed_at.len() == 19));
+    assert!(events.windows(2).all(|w| w[0].id < w[1].id));
+
+    assert_eq!(events[0].old_row_json, None);
+    assert_eq!(events[0].new_row_json.as_deref(), Some(r#"{"id":2,"name":"alice"}"#));
+    assert_eq!(events[2].old_row_json.as_deref(), Some(r#"{"id":2,"name":"alice"}"#));
+    assert_eq!(events[2].new_row_json.as_deref(), Some(r#"{"id":2,"name":"ALICE"}"#));
+    assert_eq!(events[3].old_row_json.as_deref(), Some(r#"{"id":3,"name":"bob"}"#));
+    assert_eq!(events[3].new_row_json, None);
+
+    // Polling from the last event seen returns only newer ones
+    let cursor = events[3].id;
+    assert!(db.cdc_events_since(cursor).unwrap().is_empty());
+    db.execute("INSERT INTO users VALUES (4, 'carol')").unwrap();
+    let newer = db.cdc_events_since(cursor).unwrap();
+    assert_eq!(newer.len(), 1);
+    assert_eq!(newer[0].new_row_json.as_deref(), Some(r#"{"id":4,"name":"carol"}"#));
+}
+
+#[test]
+fn cdc_can_be_enabled_again_after_schema_changes() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, label TEXT)").unwrap();
+    db.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)").unwrap();
+    db.enable_cdc("items").unwrap();
+    db.enable_cdc("items").unwrap();
+    db.execute("INSERT INTO items VALUES (1, 'a')").unwrap();
+    db.execute("INSERT INTO other VALUES (1)").unwrap();
+    assert_eq!(db.cdc_events_since(0).unwrap().len(), 1);
+
+    db.execute("ALTER TABLE items ADD COLUMN price INTEGER").unwrap();
+    db.enable_cdc("items").unwrap();
+    db.execute("INSERT INTO items VALUES (2, 'b', 5)").unwrap();
+    let events = db.cdc_events_since(1).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].new_row_json.as_deref(), Some(r#"{"id":2,"label":"b","price":5}"#));
+
+    assert!(db.enable_cdc("missing").is_err());
+    assert!(db.enable_cdc("__cdc_log").is_err());
+}
+
+// ---- Schema Migration Tests ----
+
+fn migrations() -> Vec<horizon::migration::Migration> {
+    use horizon::migration::Migration;
+    vec![
+        Migration::new("001_users", "CREATE TABLE users (id INTEGER PRIMARY KEY)", "DROP TABLE users"),
+        Migration::new(
+            "002_users_name",
+            "ALTER TABLE users ADD COLUMN name TEXT; INSERT INTO users VALUES (1, 'alice');",
+            "DELETE FROM users WHERE id = 1; ALTER TABLE users DROP COLUMN name;",
+        ),
+        Migration::new(
+            "003_posts",
+            "CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER); CREATE INDEX posts_user ON posts (user_id);",
+            "DROP INDEX posts_user; DROP TABLE posts;",
+        ),
+    ]
+}
+
+#[test]
+fn migrations_are_applied_once_in_order() {
+    use horizon::migration::Migrator;
+    let (_dir, db) = open_db();
+    let migrator = Migrator::new(&db);
+    assert!(migrator.applied().unwrap().is_empty());
+
+    migrator.run(&migrations()).unwrap();
+    assert_eq!(migrator.applied().unwrap(), vec!["001_users", "002_users_name", "003_posts"]);
+    let row = db.query_row("SELECT name FROM users WHERE id = 1").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Text("alice".into()));
+    db.execute("INSERT INTO posts VALUES (1, 1)").unwrap();
+
+    // Running them again applies none
+    migrator.run(&migrations()).unwrap();
+    assert_eq!(migrator.applied().unwrap().len(), 3);
+    assert_eq!(db.query("SELECT * FROM users").unwrap().len(), 1);
+}
+
+#[test]
+fn rolled_back_migrations_are_reverted_and_can_be_applied_again() {
+    use horizon::migration::Migrator;
+    let (_dir, db) = open_db();
+    let migrator = Migrator::new(&db);
+    migrator.run(&migrations()).unwrap();
+
+    migrator.rollback(2).unwrap();
+    assert_eq!(migrator.applied().unwrap(), vec!["001_users"]);
+    assert!(matches!(db.query("SELECT * FROM posts"), Err(HorizonError::TableNotFound(_))));
+    assert_eq!(db.query("SELECT * FROM users").unwrap().columns.as_ref(), &["id".to_string()]);
+
+    migrator.run(&migrations()).unwrap();
+    assert_eq!(migrator.applied().unwrap().len(), 3);
+    db.query("SELECT name FROM posts JOIN users ON users.id = posts.user_id").unwrap();
+
+    // Rolling back more than were applied undoes them all
+    migrator.rollback(10).unwrap();
+    assert!(migrator.applied().unwrap().is_empty());
+    assert!(db.query("SELECT * FROM users").is_err());
+}
+
+#[test]
+fn failed_and_changed_migrations_are_reported() {
+    use horizon::migration::{Migration, Migrator};
+    let (_dir, db) = open_db();
+    let migrator = Migrator::new(&db);
+    let mut list = migrations();
+    list.truncate(1);
+    list.push(Migration::new("002_seed", "INSERT INTO users VALUES (1); INSERT INTO missing VALUES (1);", ""));
+    assert!(matches!(migrator.run(&list), Err(HorizonError::TableNotFound(_))));
+    // The failed migration's rows are rolled back and it is not recorded
+    assert_eq!(migrator.applied().unwrap(), vec!["001_users"]);
+    assert!(db.query("SELECT * FROM users").unwrap().is_empty());
+
+    let mut changed = migrations();
+    changed[0].up = "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT)".into();
+    assert!(matches!(migrator.run(&changed), Err(HorizonError::Migration(_))));
+    assert_eq!(migrator.applied().unwrap(), vec!["001_users"]);
+}
+
+// ---- SQLite Import Tests ----
+
+/// A SQLite database at `path` created by running `sql` with rusqlite.
+fn create_sqlite_db(path: &std::path::Path, sql: &str) {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute_batch(sql).unwrap();
+}
+
+#[test]
+fn import_from_sqlite_copies_tables_and_rows() {
+    let (dir, db) = open_db();
+    let sqlite_path = dir.path().join("source.db");
+    let conn = rusqlite::Connection::open(&sqlite_path).unwrap();
+    // Small pages, so the table's B-tree has interior pages
+    conn.execute_batch(
+        "PRAGMA page_size = 512;
+         CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL, price REAL, data BLOB, qty INTEGER);
+         CREATE INDEX items_qty ON items (qty);
+         CREATE VIEW cheap AS SELECT name FROM items WHERE price < 5;",
+    )
+    .unwrap();
+    for i in 1..=100i64 {
+        conn.execute(
+            "INSERT INTO items VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![i * 10, format!("item {}", i), i as f64 / 4.0, vec![i as u8, 0, 255], i - 50],
+        )
+        .unwrap();
+    }
+    // A NULL in every column type, and large values spilling to overflow pages
+    conn.execute("INSERT INTO items VALUES (5000, 'nulls', NULL, NULL, NULL)", []).unwrap();
+    conn.execute("INSERT INTO items VALUES (5001, ?1, 1e300, zeroblob(20000), 9007199254740993)", [
+        "é".repeat(10_000),
+    ])
+    .unwrap();
+    drop(conn);
+
+    db.import_from_sqlite(&sqlite_path).unwrap();
+
+    let result = db.query("SELECT id, name, price, data, qty FROM items ORDER BY id").unwrap();
+    assert_eq!(result.len(), 102);
+    for (i, row) in result.rows.iter().take(100).enumerate() {
+        let i = i as i64 + 1;
+        assert_eq!(
+            row.values,
+            vec![
+                Value::Integer(i * 10),
+                Value::Text(format!("item {}", i)),
+                Value::Real(i as f64 / 4.0),
+                Value::Blob(vec![i as u8, 0, 255]),
+                Value::Integer(i - 50),
+            ]
+        );
+    }
+    assert_eq!(
+        result.rows[100].values,
+        vec![Value::Integer(5000), Value::Text("nulls".into()), Value::Null, Value::Null, Value::Null]
+    );
+    let large = &result.rows[101].values;
+    assert_eq!(large[1], Value::Text("é".repeat(10_000)));
+    assert_eq!(large[2], Value::Real(1e300));
+    assert_eq!(large[3], Value::Blob(vec![0; 20000]));
+    assert_eq!(large[4], Value::Integer(9007199254740993));
+
+    // Indexes and views come along
+    let indexes = db.query("PRAGMA index_list(items)").unwrap();
+    assert_eq!(indexes.rows[0].get("name"), Some(&Value::Text("items_qty".into())));
+    let row = db.query_row("SELECT id FROM items WHERE qty = 7").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Integer(570));
+    assert_eq!(db.query("SELECT * FROM cheap").unwrap().len(), 19);
+    // The rowid alias keeps counting from the imported rows
+    db.execute("INSERT INTO items (name) VALUES ('new')").unwrap();
+    let row = db.query_row("SELECT id FROM items WHERE name = 'new'").unwrap().unwrap();
+    assert_eq!(row.values[0], Value::Integer(5002));
+}
+
+#[test]
+fn import_from_sqlite_handles_added_columns_and_utf16() {
+    let (dir, db) = open_db();
+    let sqlite_path = dir.path().join("source.db");
+    create_sqlite_db(
+        &sqlite_path,
+        "PRAGMA encoding = 'UTF-16le';
+         CREATE TABLE notes (body TEXT);
+         INSERT INTO notes VALUES ('こんにちは'), ('hello');
+         ALTER TABLE notes ADD COLUMN lang TEXT DEFAULT 'en';
+         INSERT INTO notes VALUES ('bonjour', 'fr');
+         CREATE TABLE log (at INTEGER, msg TEXT);
+         CREATE TRIGGER notes_log AFTER INSERT ON notes BEGIN INSERT INTO log VALUES (1, NEW.body); END;",
+    );
+
+    db.import_from_sqlite(&sqlite_path).unwrap();
+    let result = db.query("SELECT body, lang FROM notes").unwrap();
+    let rows: Vec<Vec<Value>> = result.rows.into_iter().map(|r| r.values).collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec![Value::Text("こんにちは".into()), Value::Text("en".into())],
+            vec![Value::Text("hello".into()), Value::Text("en".into())],
+            vec![Value::Text("bonjour".into()), Value::Text("fr".into())],
+        ]
+    );
+    // Triggers are created after the rows are copied, so they fire only
+    // for later changes
+    assert!(db.query("SELECT * FROM log").unwrap().is_empty());
+    db.execute("INSERT INTO notes (body) VALUES ('hallo')").unwrap();
+    assert_eq!(db.query("SELECT * FROM log").unwrap().len(), 1);
+}
+
+#[test]
+fn import_from_sqlite_rejects_other_files_and_rolls_back() {
+    let (dir, db) = open_db();
+    let not_sqlite = dir.path().join("not.db");
+    std::fs::write(&not_sqlite, b"just some text, not a database file at all").unwrap();
+    assert!(matches!(db.import_from_sqlite(&not_sqlite), Err(HorizonError::CorruptDatabase(_))));
+
+    let sqlite_path = dir.path().join("source.db");
+    create_sqlite_db(
+        &sqlite_path,
+        "CREATE TABLE a (x INTEGER); INSERT INTO a VALUES (1);
+         CREATE TABLE b (x INTEGER); INSERT INTO b VALUES (2);",
+    );
+    db.execute("CREATE TABLE b (y TEXT)").unwrap();
+    assert!(matches!(db.import_from_sqlite(&sqlite_path), Err(HorizonError::DuplicateTable(_))));
+    // The table created before the failure is dropped again, and the
+    // existing one is left alone
+    assert!(matches!(db.query("SELECT * FROM a"), Err(HorizonError::TableNotFound(_))));
+    assert!(db.query("SELECT y FROM b").unwrap().is_empty());
+}
+
+#[test]
+fn import_from_sqlite_checks_every_statement_before_creating_tables() {
+    let (dir, db) = open_db();
+    let sqlite_path = dir.path().join("source.db");
+    create_sqlite_db(
+        &sqlite_path,
+        "CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1);
+         CREATE TABLE \"we\"\"ird\" (y TEXT); INSERT INTO \"we\"\"ird\" VALUES ('a');
+         CREATE TABLE kv (k TEXT PRIMARY KEY, v TEXT) WITHOUT ROWID;",
+    );
+
+    assert!(matches!(db.import_from_sqlite(&sqlite_path), Err(HorizonError::InvalidSql(_))));
+    let tables = db.query("PRAGMA table_list").unwrap();
+    assert!(tables.is_empty(), "{:?}", tables.rows);
+}
+
+#[test]
+fn import_from_sqlite_refuses_index_keys_larger_than_a_quarter_page() {
+    let (dir, db) = open_db();
+    let sqlite_path = dir.path().join("source.db");
+    let conn = rusqlite::Connection::open(&sqlite_path).unwrap();
+    conn.execute_batch("CREATE TABLE docs (id INTEGER PRIMARY KEY, body TEXT); CREATE INDEX docs_body ON docs (body);")
+        .unwrap();
+    conn.execute("INSERT INTO docs VALUES (1, ?1)", ["d".repeat(50_000)]).unwrap();
+    drop(conn);
+
+    let err = db.import_from_sqlite(&sqlite_path).unwrap_err();
+    assert!(matches!(err, HorizonError::InvalidSql(ref msg) if msg.contains("too large")), "{err}");
+    assert!(matches!(db.query("SELECT * FROM docs"), Err(HorizonError::TableNotFound(_))));
+}
+
+// ---- Query Plan Index Usage Tests ----
+
+#[test]
+fn explain_query_plan_shows_primary_key_and_index_searches() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, qty INTEGER, price REAL, name TEXT)").unwrap();
+    db.execute("CREATE INDEX idx_qty ON t (qty)").unwrap();
+    db.execute("CREATE INDEX idx_qty_price ON t (qty, price)").unwrap();
+    db.execute("INSERT INTO t SELECT value, value % 10, value * 1.5, 'n' || value FROM generate_series(1, 100)").unwrap();
+
+    assert_eq!(
+        query_plan_details(&db, "SELECT * FROM t WHERE id = 5"),
+        vec!["SEARCH TABLE t USING INTEGER PRIMARY KEY (rowid=?) WHERE (id = 5)"]
+    );
+    assert_eq!(
+        query_plan_details(&db, "SELECT * FROM t WHERE id > 90"),
+        vec!["SEARCH TABLE t USING INTEGER PRIMARY KEY (rowid>?) WHERE (id > 90)"]
+    );
+    assert_eq!(
+        query_plan_details(&db, "SELECT name FROM t WHERE qty = 7"),
+        vec!["SEARCH TABLE t USING INDEX idx_qty (qty=?) WHERE (qty = 7)"]
+    );
+    assert_eq!(
+        query_plan_details(&db, "SELECT name FROM t WHERE qty = 7 AND price > 50 AND price < 100"),
+        vec!["SEARCH TABLE t USING INDEX idx_qty_price (qty=? AND price>? AND price<?) \
+              WHERE (((qty = 7) AND (price > 50)) AND (price < 100))"]
+    );
+    assert_eq!(query_plan_details(&db, "SELECT * FROM t WHERE name = 'n5'"), vec!["SCAN TABLE t WHERE (name = 'n5')"]);
+    assert_eq!(query_plan_details(&db, "SELECT * FROM t"), vec!["SCAN TABLE t"]);
+
+    // Aggregates read through the index too, with the same results
+    assert_eq!(
+        query_plan_details(&db, "SELECT count(*), sum(id) FROM t WHERE qty = 3"),
+        vec!["SEARCH TABLE t USING INDEX idx_qty (qty=?) WHERE (qty = 3)"]
+    );
+    let row = &db.query("SELECT count(*), sum(id) FROM t WHERE qty = 3").unwrap().rows[0];
+    assert_eq!(row.values, vec![Value::Integer(10), Value::Integer(480)]);
+
+    // The scans of a join read their whole tables
+    db.execute("CREATE TABLE u (id INTEGER PRIMARY KEY, t_id INTEGER)").unwrap();
+    assert_eq!(
+        query_plan_details(&db, "SELECT * FROM t JOIN u ON t.id = u.t_id WHERE t.qty = 1"),
+        vec!["SCAN TABLE t WHERE (t.qty = 1)", "SCAN TABLE u"]
+    );
+
+    let opcodes: Vec<String> = db
+        .query("EXPLAIN SELECT * FROM t WHERE qty = 7")
+        .unwrap()
+        .rows
+        .iter()
+        .map(|row| format!("{} {}", row.get("opcode").unwrap(), row.get("p4").unwrap()))
+        .collect();
+    assert_eq!(opcodes[..3], ["OpenRead t", "OpenRead idx_qty", "SeekGE index idx_qty"]);
+}
+
+#[test]
+fn explain_query_plan_shows_composite_primary_key_searches() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE pairs (a INTEGER, b TEXT, v INTEGER, PRIMARY KEY (a, b))").unwrap();
+    db.execute("INSERT INTO pairs VALUES (1, 'x', 10), (1, 'y', 20), (2, 'x', 30)").unwrap();
+
+    assert_eq!(
+        query_plan_details(&db, "SELECT v FROM pairs AS p WHERE b = 'y' AND a = 1"),
+        vec!["SEARCH TABLE pairs AS p USING PRIMARY KEY (a=? AND b=?) WHERE ((b = 'y') AND (a = 1))"]
+    );
+    assert_eq!(query_plan_details(&db, "SELECT v FROM pairs WHERE a = 1"), vec!["SCAN TABLE pairs WHERE (a = 1)"]);
+    let rows = db.query("SELECT v FROM pairs WHERE b = 'y' AND a = 1").unwrap().rows;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values, vec![Value::Integer(20)]);
+}
+
+// ---- Slow Query Log Tests ----
+
+#[test]
+fn slow_queries_are_logged_with_their_row_counts() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)").unwrap();
+    let inserted = db.execute("INSERT INTO t SELECT value, value % 1000 FROM generate_series(1, 100000)").unwrap();
+    assert_eq!(inserted, 100_000);
+    assert!(db.slow_query_log().unwrap().is_empty());
+
+    db.set_slow_query_threshold(std::time::Duration::from_millis(5)).unwrap();
+    // Scans all 100,000 rows
+    let slow = "SELECT id FROM t WHERE CAST(x AS TEXT) LIKE '7' ORDER BY id DESC";
+    assert_eq!(db.query(slow).unwrap().len(), 100);
+    db.query("SELECT 1").unwrap();
+    db.query("SELECT x FROM t WHERE id = 5").unwrap();
+
+    let log = db.slow_query_log().unwrap();
+    assert_eq!(log.len(), 1, "{:?}", log);
+    assert_eq!(log[0].sql, slow);
+    assert_eq!(log[0].rows, 100);
+    assert!(log[0].duration > std::time::Duration::from_millis(5));
+    assert!(log[0].timestamp <= std::time::SystemTime::now());
+
+    // Statements that fail are not logged
+    assert!(db.query("SELECT * FROM missing").is_err());
+    assert_eq!(db.slow_query_log().unwrap().len(), 1);
+}
+
+#[test]
+fn the_slow_query_log_keeps_the_latest_entries() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").unwrap();
+    // Every statement takes longer than no time at all
+    db.set_slow_query_threshold(std::time::Duration::ZERO).unwrap();
+    let capacity = horizon::slow_query::SLOW_QUERY_LOG_CAPACITY;
+    for i in 0..capacity + 10 {
+        db.execute(&format!("INSERT INTO t VALUES ({})", i)).unwrap();
+    }
+    let log = db.slow_query_log().unwrap();
+    assert_eq!(log.len(), capacity);
+    assert_eq!(log[0].sql, "INSERT INTO t VALUES (10)");
+    assert_eq!(log[0].rows, 1);
+    assert_eq!(log.last().unwrap().sql, format!("INSERT INTO t VALUES ({})", capacity + 9));
+
+    // Raising the threshold keeps the entries but logs no more
+    db.set_slow_query_threshold(std::time::Duration::from_secs(3600)).unwrap();
+    db.execute("DELETE FROM t").unwrap();
+    assert_eq!(db.slow_query_log().unwrap(), log);
+}
+
+// ---- PRAGMA optimize Tests ----
+
+#[test]
+fn pragma_optimize_analyzes_frequently_queried_tables() {
+    let (_dir, db) = open_db();
+    for table in ["hot", "cold"] {
+        db.execute(&format!("CREATE TABLE {} (id INTEGER PRIMARY KEY, lo INTEGER, tag TEXT, hi INTEGER)", table)).unwrap();
+        db.execute(&format!("CREATE INDEX {0}_lo_tag ON {0} (lo, tag)", table)).unwrap();
+        db.execute(&format!("CREATE INDEX {0}_hi ON {0} (hi)", table)).unwrap();
+        db.execute(&format!("INSERT INTO {} SELECT value, value % 2, 'x', value FROM generate_series(1, 50)", table))
+            .unwrap();
+    }
+    db.execute("CREATE TABLE unindexed (id INTEGER PRIMARY KEY)").unwrap();
+    // Without statistics the index with more columns constrained wins;
+    // with them, the index on the unique column
+    let index_used = |table: &str| {
+        let sql = format!("SELECT id FROM {} WHERE lo = 1 AND tag = 'x' AND hi = 7", table);
+        let detail = query_plan_details(&db, &sql).remove(0);
+        detail.split(' ').nth(5).unwrap().to_string()
+    };
+    assert_eq!(index_used("hot"), "hot_lo_tag");
+
+    for i in 0..100 {
+        db.query(&format!("SELECT tag FROM hot WHERE id = {}", i)).unwrap();
+        db.query("SELECT * FROM unindexed").unwrap();
+    }
+    let analyzed: Vec<Value> = db.query("PRAGMA optimize").unwrap().rows.into_iter().map(|r| r.values[0].clone()).collect();
+    assert_eq!(analyzed, vec![Value::Text("hot".into())]);
+    assert_eq!(index_used("hot"), "hot_hi");
+    assert_eq!(index_used("cold"), "cold_lo_tag");
+
+    // An analyzed table is analyzed again once it has been scanned more
+    // than twice as often
+    assert!(db.query("PRAGMA optimize").unwrap().is_empty());
+    for i in 0..100 {
+        db.query(&format!("SELECT tag FROM hot WHERE id = {}", i)).unwrap();
+    }
+    assert!(db.query("PRAGMA optimize").unwrap().is_empty());
+    db.query("SELECT tag FROM hot WHERE id = 1").unwrap();
+    assert_eq!(db.query("PRAGMA optimize").unwrap().len(), 1);
+
+    // ANALYZE resets the count as well
+    db.query("SELECT * FROM cold").unwrap();
+    db.execute("ANALYZE cold").unwrap();
+    assert!(db.query("PRAGMA optimize").unwrap().is_empty());
+    assert_eq!(index_used("cold"), "cold_hi");
+}
+
+// ---- Full-Text Index Tests ----
+
+/// The ids of the rows `sql` returns, in order.
+fn ids(db: &Database, sql: &str) -> Vec<i64> {
+    db.query(sql).unwrap().rows.iter().map(|r| r.get_typed::<i64>("id").unwrap()).collect()
+}
+
+#[test]
+fn match_on_a_table_with_a_fulltext_index_searches_it() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE articles (id INTEGER PRIMARY KEY, title TEXT, body TEXT, views INTEGER)").unwrap();
+    let articles = [
+        ("Learning Rust", "Ownership and borrowing explained"),
+        ("Python tips", "List comprehensions in depth"),
+        ("Rust async", "Futures and executors"),
+        ("Databases", "Why rust is used for storage engines"),
+        ("Go concurrency", "Goroutines and channels"),
+        ("Rust macros", "Declarative and procedural"),
+        ("Java streams", "Functional pipelines"),
+        ("Kotlin coroutines", "Structured concurrency"),
+        ("C pointers", "Memory and arrays"),
+        ("Zig comptime", "Compile-time evaluation"),
+    ];
+    let insert = |id: usize| {
+        let (title, body) = articles[id - 1];
+        let params = [Value::Integer(id as i64), Value::Text(title.into()), Value::Text(body.into())];
+        db.execute_params("INSERT INTO articles VALUES (?1, ?2, ?3, ?1)", &params).unwrap();
+    };
+    // Rows inserted before the index is created are indexed too
+    (1..=5).for_each(insert);
+    db.create_fulltext_index("articles", &["title", "body"]).unwrap();
+    (6..=10).for_each(insert);
+
+    let result = db.query("SELECT * FROM articles WHERE title MATCH 'rust' ORDER BY id").unwrap();
+    assert_eq!(result.columns.as_ref(), &["id", "title", "body", "views"]);
+    let titles: Vec<String> = result.rows.iter().map(|r| r.get_typed("title").unwrap()).collect();
+    assert_eq!(titles, vec!["Learning Rust", "Rust async", "Rust macros"]);
+
+    // The table name searches every indexed column
+    assert_eq!(ids(&db, "SELECT id FROM articles WHERE articles MATCH 'rust' ORDER BY id"), vec![1, 3, 4, 6]);
+    assert_eq!(ids(&db, "SELECT id FROM articles WHERE body MATCH 'concurrency' ORDER BY id"), vec![8]);
+    // Combined with other conditions, aliases, and aggregates
+    assert_eq!(ids(&db, "SELECT id FROM articles a WHERE a.title MATCH 'rust' AND views > 2 ORDER BY id"), vec![3, 6]);
+    let count = db.query("SELECT COUNT(*) FROM articles WHERE title MATCH 'rust' OR body MATCH 'memory'").unwrap();
+    assert_eq!(count.rows[0].values[0], Value::Integer(4));
+
+    let err = db.create_fulltext_index("articles", &["title"]).unwrap_err();
+    assert!(matches!(err, HorizonError::DuplicateIndex(_)));
+}
+
+#[test]
+fn fulltext_index_triggers_keep_it_in_sync() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE posts (id INTEGER PRIMARY KEY, title TEXT, body TEXT)").unwrap();
+    db.create_fulltext_index("posts", &["title"]).unwrap();
+    db.execute("INSERT INTO posts VALUES (1, 'Rust basics', 'x'), (2, 'Python basics', 'y'), (3, 'Rust advanced', 'z')")
+        .unwrap();
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE title MATCH 'rust' ORDER BY id"), vec![1, 3]);
+
+    db.execute("UPDATE posts SET title = 'Rust for Pythonistas' WHERE id = 2").unwrap();
+    db.execute("UPDATE posts SET title = 'Go advanced' WHERE id = 3").unwrap();
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE title MATCH 'rust' ORDER BY id"), vec![1, 2]);
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE title MATCH 'advanced'"), vec![3]);
+    assert!(ids(&db, "SELECT id FROM posts WHERE title MATCH 'python'").is_empty());
+
+    db.execute("DELETE FROM posts WHERE id = 1").unwrap();
+    assert_eq!(ids(&db, "SELECT id FROM posts WHERE title MATCH 'rust'"), vec![2]);
+
+    // Only indexed columns can be searched, and the table needs an INTEGER PRIMARY KEY
+    db.execute("CREATE TABLE tags (name TEXT PRIMARY KEY)").unwrap();
+    assert!(db.create_fulltext_index("tags", &["name"]).is_err());
+    assert!(matches!(db.create_fulltext_index("posts_missing", &["title"]), Err(HorizonError::TableNotFound(_))));
+}
+
+// ---- FTS5 Tokenizer Tests ----
+
+/// Splits on whitespace, folding case and dropping punctuation inside
+/// words, so that `E-mail` is the term `email`.
+struct SquashingTokenizer;
+
+impl Fts5Tokenizer for SquashingTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut offset = 0;
+        for word in text.split(' ') {
+            let squashed: String = word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+            if !squashed.is_empty() {
+                let position = tokens.len();
+                tokens.push(Token { text: squashed, start: offset, end: offset + word.len(), position });
+            }
+            offset += word.len() + 1;
+        }
+        tokens
+    }
+}
+
+#[test]
+fn fts5_tables_use_registered_tokenizers() {
+    let (_dir, db) = open_db();
+    db.register_fts5_tokenizer("Squash", Box::new(SquashingTokenizer)).unwrap();
+    db.execute("CREATE VIRTUAL TABLE fts5_contacts USING fts5(note, tokenize=\"squash\")").unwrap();
+    db.execute("INSERT INTO fts5_contacts VALUES ('Prefers E-mail, not phone.'), ('Call on the phone')").unwrap();
+
+    let notes = |query: &str| -> Vec<String> {
+        let sql = format!("SELECT note FROM fts5_contacts WHERE fts5_contacts MATCH '{}' ORDER BY note", query);
+        db.query(&sql).unwrap().rows.iter().map(|r| r.get_typed("note").unwrap()).collect()
+    };
+    assert_eq!(notes("EMAIL"), vec!["Prefers E-mail, not phone."]);
+    assert_eq!(notes("e.mail phone"), vec!["Prefers E-mail, not phone."]);
+    assert_eq!(notes("phone"), vec!["Call on the phone", "Prefers E-mail, not phone."]);
+    // The tokenizer's offsets decide what is highlighted
+    let result = db
+        .query("SELECT highlight(fts5_contacts, 0, '<', '>') FROM fts5_contacts WHERE fts5_contacts MATCH 'email'")
+        .unwrap();
+    assert_eq!(result.rows[0].values[0], Value::Text("Prefers <E-mail,> not phone.".into()));
+
+    let err = db.execute("CREATE VIRTUAL TABLE fts5_nowhere USING fts5(note, tokenize = 'missing')").unwrap_err();
+    assert!(err.to_string().contains("no such FTS5 tokenizer"), "{}", err);
+}
+
+#[test]
+fn fts5_porter_tokenizer_matches_inflected_forms() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE VIRTUAL TABLE fts5_stemmed USING fts5(body, tokenize = 'porter')").unwrap();
+    db.execute(
+        "INSERT INTO fts5_stemmed VALUES ('The server connected quickly'), ('Connections were dropped'), \
+         ('Connecting flights'), ('Unrelated text')",
+    )
+    .unwrap();
+    let count = |table: &str, query: &str| {
+        db.query(&format!("SELECT rowid FROM {0} WHERE {0} MATCH '{1}'", table, query)).unwrap().len()
+    };
+    assert_eq!(count("fts5_stemmed", "connect"), 3);
+    assert_eq!(count("fts5_stemmed", "connection"), 3);
+    assert_eq!(count("fts5_stemmed", "dropping"), 1);
+
+    // The default tokenizer matches words exactly
+    db.execute("CREATE VIRTUAL TABLE fts5_unstemmed USING fts5(body)").unwrap();
+    db.execute("INSERT INTO fts5_unstemmed VALUES ('The server connected quickly')").unwrap();
+    assert_eq!(count("fts5_unstemmed", "connect"), 0);
+    assert_eq!(count("fts5_unstemmed", "connected"), 1);
+}
+
+// ---- FTS5 Phrase and Proximity Tests ----
+
+#[test]
+fn fts5_phrase_and_near_queries() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE VIRTUAL TABLE fts5_phrases USING fts5(body)").unwrap();
+    db.execute(
+        "INSERT INTO fts5_phrases VALUES ('The quick brown fox jumps over the lazy dog'), \
+         ('A brown fox is very quick'), ('The quick dog chased a brown and very sleepy fox'), ('Nothing here')",
+    )
+    .unwrap();
+    let rowids = |query: &str| -> Vec<i64> {
+        let sql = format!("SELECT rowid FROM fts5_phrases WHERE fts5_phrases MATCH '{}' ORDER BY rowid", query);
+        db.query(&sql).unwrap().rows.iter().map(|r| r.get_typed::<i64>("rowid").unwrap()).collect()
+    };
+
+    // A phrase needs its words in sequence
+    assert_eq!(rowids("\"quick brown fox\""), vec![1]);
+    assert_eq!(rowids("\"brown fox\""), vec![1, 2]);
+    assert!(rowids("\"fox brown\"").is_empty());
+    // NEAR needs the terms within the distance of each other
+    assert_eq!(rowids("NEAR(quick fox, 2)"), vec![1]);
+    assert_eq!(rowids("NEAR(quick fox, 3)"), vec![1, 2]);
+    assert_eq!(rowids("NEAR(quick fox, 8)"), vec![1, 2, 3]);
+    // Phrases, NEAR groups, and terms combine with AND
+    assert_eq!(rowids("\"brown fox\" lazy"), vec![1]);
+    assert_eq!(rowids("NEAR(quick fox, 3) \"very quick\""), vec![2]);
+    // Single terms still work
+    assert_eq!(rowids("fox"), vec![1, 2, 3]);
+    assert_eq!(rowids("sleepy"), vec![3]);
+
+    let phrase = fts5::fts5_query_phrase("fts5_phrases", "quick brown fox").unwrap();
+    assert_eq!(phrase.iter().map(|(rowid, _, _)| *rowid).collect::<Vec<_>>(), vec![1]);
+    let mut near: Vec<i64> =
+        fts5::fts5_query_near("fts5_phrases", &["dog", "quick"], 1).unwrap().into_iter().map(|(rowid, _, _)| rowid).collect();
+    near.sort_unstable();
+    assert_eq!(near, vec![3]);
+}
+
+// ---- R-tree Nearest-Neighbour Tests ----
+
+#[test]
+fn rtree_nearest_neighbours_match_brute_force() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE VIRTUAL TABLE places USING rtree(id, minX, maxX, minY, maxY)").unwrap();
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed >> 11) as f64 / (1u64 << 53) as f64 * 1000.0
+    };
+    let mut boxes = Vec::new();
+    for id in 1..=1000i64 {
+        let (x, y) = (next(), next());
+        boxes.push((id, [x, x + next() / 50.0, y, y + next() / 50.0]));
+    }
+    let values: Vec<String> = boxes
+        .iter()
+        .map(|(id, c)| format!("({}, {}, {}, {}, {})", id, c[0], c[1], c[2], c[3]))
+        .collect();
+    db.execute(&format!("INSERT INTO places VALUES {}", values.join(", "))).unwrap();
+
+    let distance = |c: &[f64; 4], (qx, qy): (f64, f64)| {
+        let dx = (c[0] - qx).max(qx - c[1]).max(0.0);
+        let dy = (c[2] - qy).max(qy - c[3]).max(0.0);
+        (dx * dx + dy * dy).sqrt()
+    };
+    for _ in 0..10 {
+        let point = (next(), next());
+        let mut expected: Vec<f64> = boxes.iter().map(|(_, c)| distance(c, point)).collect();
+        expected.sort_by(f64::total_cmp);
+        expected.truncate(5);
+
+        let sql = format!(
+            "SELECT id FROM places ORDER BY rtree_distance(minX, minY, maxX, maxY, {}, {}) LIMIT 5",
+            point.0, point.1
+        );
+        let from_sql = ids(&db, &sql);
+        let from_api: Vec<i64> = db
+            .knn_query("places", &[point.0, point.1], 5)
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| row.get_typed("id").unwrap())
+            .collect();
+        for found in [from_sql, from_api] {
+            let distances: Vec<f64> = found.iter().map(|&id| distance(&boxes[id as usize - 1].1, point)).collect();
+            assert_eq!(distances, expected);
+        }
+    }
+}
+
+#[test]
+fn rtree_distance_orders_without_a_limit_and_respects_where() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE VIRTUAL TABLE spots USING rtree(id, minX, maxX, minY, maxY)").unwrap();
+    db.execute(
+        "INSERT INTO spots VALUES (1, 0, 1, 0, 1), (2, 10, 11, 10, 11), (3, 4, 5, 4, 5), (4, 3, 4, 0, 1)",
+    )
+    .unwrap();
+    let by_distance = "ORDER BY rtree_distance(minX, minY, maxX, maxY, 0, 0)";
+    assert_eq!(ids(&db, &format!("SELECT id FROM spots {}", by_distance)), vec![1, 4, 3, 2]);
+    assert_eq!(ids(&db, &format!("SELECT id FROM spots WHERE id > 1 {} LIMIT 2", by_distance)), vec![4, 3]);
+    assert_eq!(ids(&db, &format!("SELECT id FROM spots {} LIMIT 2 OFFSET 1", by_distance)), vec![4, 3]);
+
+    let result = db.query("SELECT rtree_distance(minX, minY, maxX, maxY, 7, 5) AS d FROM spots WHERE id = 3").unwrap();
+    assert_eq!(result.rows[0].get("d"), Some(&Value::Real(2.0)));
+    assert!(db.knn_query("spots", &[0.0], 1).is_err());
+}
+
+// ---- JSONPath Tests ----
+
+fn json_extract(db: &Database, json: &str, path: &str) -> Value {
+    let row = db.query_row_params("SELECT JSON_EXTRACT(?, ?)", &[Value::from(json), Value::from(path)]).unwrap();
+    row.unwrap().values[0].clone()
+}
+
+#[test]
+fn json_extract_wildcards_and_recursive_descent_return_arrays() {
+    let (_dir, db) = open_db();
+    assert_eq!(json_extract(&db, "[1,2,3]", "$[*]"), Value::Text("[1,2,3]".into()));
+    assert_eq!(json_extract(&db, r#"{"a":{"b":1}}"#, "$..b"), Value::Text("[1]".into()));
+    assert_eq!(json_extract(&db, r#"{"a":{"b":1},"c":[{"b":2}]}"#, "$..b"), Value::Text("[1,2]".into()));
+    assert_eq!(json_extract(&db, r#"{"x":1,"y":[2]}"#, "$.*"), Value::Text("[1,[2]]".into()));
+    // Paths selecting at most one value still return it as is.
+    assert_eq!(json_extract(&db, r#"{"a":{"b":1}}"#, "$.a.b"), Value::Integer(1));
+}
+
+#[test]
+fn json_extract_slices_and_filters() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE groups (id INTEGER PRIMARY KEY, doc TEXT)").unwrap();
+    db.execute(
+        r#"INSERT INTO groups VALUES (1, '{"members":[{"name":"Ann","age":34},{"name":"Bob","age":12},{"name":"Cy","age":19},{"name":"Di","age":70}]}')"#,
+    )
+    .unwrap();
+    let extract = |path: &str| {
+        let row = db.query_row_params("SELECT JSON_EXTRACT(doc, ?) FROM groups WHERE id = 1", &[Value::from(path)]);
+        row.unwrap().unwrap().values[0].clone()
+    };
+    assert_eq!(extract("$.members[0:3].name"), Value::Text(r#"["Ann","Bob","Cy"]"#.into()));
+    assert_eq!(extract("$.members[-2:].age"), Value::Text("[19,70]".into()));
+    assert_eq!(extract("$.members[?(@.age > 18)].name"), Value::Text(r#"["Ann","Cy","Di"]"#.into()));
+    assert_eq!(
+        extract("$.members[?(@.age > 18 && @.age < 65)]"),
+        Value::Text(r#"[{"name":"Ann","age":34},{"name":"Cy","age":19}]"#.into())
+    );
+    assert_eq!(extract("$.members[?(@.name == 'Zed')]"), Value::Text("[]".into()));
+
+    let with_minors = db.query("SELECT id FROM groups WHERE JSON_EXTRACT(doc, '$.members[?(@.age < 18)].name') = '[\"Bob\"]'");
+    assert_eq!(with_minors.unwrap().len(), 1);
+}
+
+// ---- INSERT ... SELECT RETURNING Tests ----
+
+#[test]
+fn insert_select_returning_returns_every_inserted_row() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT, created_at TEXT)").unwrap();
+    db.execute(
+        "INSERT INTO events VALUES (1, 'login', '2024-01-01'), (2, 'logout', '2024-01-02'), (3, 'login', '2024-01-03')",
+    )
+    .unwrap();
+    db.execute("CREATE TABLE log (id INTEGER PRIMARY KEY, name TEXT, created_at TEXT, day TEXT AS (SUBSTR(created_at, 9)))")
+        .unwrap();
+
+    let result = db.query("INSERT INTO log SELECT * FROM events RETURNING id, created_at, day").unwrap();
+    assert_eq!(*result.columns, ["id", "created_at", "day"]);
+    let returned: Vec<(i64, String, String)> = result
+        .rows
+        .iter()
+        .map(|row| (row.get_typed("id").unwrap(), row.get_typed("created_at").unwrap(), row.get_typed("day").unwrap()))
+        .collect();
+    assert_eq!(
+        returned,
+        [
+            (1, "2024-01-01".to_string(), "01".to_string()),
+            (2, "2024-01-02".to_string(), "02".to_string()),
+            (3, "2024-01-03".to_string(), "03".to_string()),
+        ]
+    );
+    assert_eq!(ids(&db, "SELECT id FROM log ORDER BY id"), vec![1, 2, 3]);
+
+    let result = db.query("INSERT INTO log SELECT id + 10, name, created_at FROM events WHERE name = 'nothing' RETURNING *");
+    assert!(result.unwrap().is_empty());
+}
+
+#[test]
+fn insert_select_returning_shows_generated_ids() {
+    let (_dir, db) = open_db();
+    db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+    db.execute("INSERT INTO events VALUES (7, 'a'), (3, 'b'), (9, 'c'), (4, 'd')").unwrap();
+    db.execute("CREATE TABLE log (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, upper_name TEXT AS (UPPER(name)))")
+        .unwrap();
+    db.execute("INSERT INTO log (name) VALUES ('first')").unwrap();
+    db.execute("CREATE TABLE audit (log_id INTEGER)").unwrap();
+    db.execute("CREATE TRIGGER log_audit AFTER INSERT ON log BEGIN INSERT INTO audit VALUES (NEW.id); END").unwrap();
+
+    let result = db.query("INSERT INTO log (name) SELECT name FROM events ORDER BY id RETURNING id, name, upper_name").unwrap();
+    let returned: Vec<(i64, String, String)> = result
+        .rows
+        .iter()
+        .map(|row| (row.get_typed("id").unwrap(), row.get_typed("name").unwrap(), row.get_typed("upper_name").unwrap()))
+        .collect();
+    assert_eq!(
+        returned,
+        [
+            (2, "b".to_string(), "B".to_string()),
+            (3, "d".to_string(), "D".to_string()),
+            (4, "a".to_string(), "A".to_string()),
+            (5, "c".to_string(), "C".to_string()),
+        ]
+    );
+    assert!(returned.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(ids(&db, "SELECT id FROM log ORDER BY id"), vec![1, 2, 3, 4, 5]);
+    assert_eq!(ids(&db, "SELECT log_id AS id FROM audit ORDER BY log_id"), vec![2, 3, 4, 5]);
+}
+
+// ---- UPDATE ... FROM Tests ----
+
+fn employees_and_departments(db: &Database) {
+    db.execute("CREATE TABLE departments (id INTEGER PRIMARY KEY, name TEXT, budget INTEGER)").unwrap();
+    db.execute("INSERT INTO departments VALUES (1, 'Engineering', 100), (2, 'Sales', 50)").unwrap();
+    db.execute("CREATE TABLE employees (id INTEGER PRIMARY KEY, name TEXT, dept_id INTEGER, dept_name TEXT)").unwrap();
+    db.execute(
+        "INSERT INTO employees VALUES (1, 'Ann', 1, NULL), (2, 'Bob', 2, NULL), (3, 'Cy', 1, NULL), (4, 'Di', 9, 'none')",
+    )
+    .unwrap();
+}
+
+fn dept_names(db: &Database) -> Vec<Value> {
+    db.query("SELECT dept_name FROM employees ORDER BY id").unwrap().rows.iter().map(|r| r.values[0].clone()).collect()
+}
+
+#[test]
+fn update_from_sets_columns_from_the_joined_table() {
+    let (_dir, db) = open_db();
+    employees_and_departments(&db);
+    let updated = db
+        .execute("UPDATE employees SET dept_name = departments.name FROM departments WHERE employees.dept_id = departments.id")
+        .unwrap();
+    assert_eq!(updated, 3);
+    assert_eq!(
+        dept_names(&db),
+        [Value::from("Engineering"), Value::from("Sales"), Value::from("Engineering"), Value::from("none")]
+    );
+}
+
+#[test]
+fn update_from_with_aliases_and_extra_conditions() {
+    let (_dir, db) = open_db();
+    employees_and_departments(&db);
+    let updated = db
+        .execute(
+            "UPDATE employees SET employees.dept_name = d.name || ' (' || d.budget || ')' \
+             FROM departments d WHERE employees.dept_id = d.id AND d.budget > 60",
+        )
+        .unwrap();
+    assert_eq!(updated, 2);
+    assert_eq!(
+        dept_names(&db),
+        [Value::from("Engineering (100)"), Value::Null, Value::from("Engineering (100)"), Value::from("none")]
+    );
+
+    let result = db
+        .query("UPDATE employees SET dept_name = d.name FROM departments d WHERE dept_id = d.id AND d.name = 'Sales' RETURNING id, dept_name")
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values, [Value::Integer(2), Value::from("Sales")]);
+
+    assert!(db.execute("UPDATE employees SET d.name = 'x' FROM departments d WHERE dept_id = d.id").is_err());
+}
+
+// ---- DELETE ... RETURNING Aggregate Tests ----
+
+fn queue(db: &Database) {
+    db.execute("CREATE TABLE queue (id INTEGER PRIMARY KEY, task TEXT, priority INTEGER)").unwrap();
+    db.execute("INSERT INTO queue VALUES (1, 'a', 1), (2, 'b', 4), (3, 'c', 2), (4, 'd', 5)").unwrap();
+}
+
+#[test]
+fn delete_returning_aggregates_summarize_the_deleted_rows() {
+    let (_dir, db) = open_db();
+    queue(&db);
+    let result = db
+        .query("DELETE FROM queue WHERE priority < 5 RETURNING COUNT(*) AS deleted, AVG(priority) AS avg_prio, MAX(task)")
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].get("deleted"), Some(&Value::Integer(3)));
+    assert_eq!(result.rows[0].get("avg_prio"), Some(&Value::Real(7.0 / 3.0)));
+    assert_eq!(result.rows[0].values[2], Value::from("c"));
+    assert_eq!(ids(&db, "SELECT id FROM queue"), [4]);
+
+    let result = db.query("DELETE FROM queue RETURNING COUNT(*) AS deleted, AVG(priority) AS avg_prio").unwrap();
+    assert_eq!(result.rows[0].values, [Value::Integer(1), Value::Real(5.0)]);
+    assert!(ids(&db, "SELECT id FROM queue").is_empty());
+}
+
+#[test]
+fn delete_returning_aggregates_over_no_rows_return_one_row() {
+    let (_dir, db) = open_db();
+    queue(&db);
+    let result = db
+        .query("DELETE FROM queue WHERE priority > 10 RETURNING COUNT(*) AS deleted, AVG(priority) AS avg_prio")
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result.rows[0].values, [Value::Integer(0), Value::Null]);
+    assert_eq!(ids(&db, "SELECT id FROM queue"), [1, 2, 3, 4]);
+}
@@ -0,0 +1,34 @@
+//! The 'localtime' and 'utc' date/time modifiers depend on the process time
+//! zone, so they are tested in their own binary where setting `TZ` cannot
+//! race with other tests.
+#![cfg(unix)]
+
+use horizon::{Database, Value};
+
+fn query_value(db: &Database, sql: &str) -> Value {
+    db.query(sql).unwrap().rows[0].values[0].clone()
+}
+
+#[test]
+fn localtime_and_utc_modifiers_follow_tz() {
+    // POSIX zone five and a half hours east of UTC, with no daylight saving
+    std::env::set_var("TZ", "XST-05:30");
+    let db = Database::open(":memory:").unwrap();
+
+    assert_eq!(
+        query_value(&db, "SELECT DATETIME('2024-01-01 12:00:00', 'localtime')"),
+        Value::Text("2024-01-01 17:30:00".into())
+    );
+    assert_eq!(
+        query_value(&db, "SELECT DATETIME('2024-01-01 12:00:00', 'localtime', 'utc')"),
+        Value::Text("2024-01-01 12:00:00".into())
+    );
+    // 'utc' alone treats its input as local time
+    assert_eq!(
+        query_value(&db, "SELECT DATETIME('2024-01-01 03:00:00', 'utc')"),
+        Value::Text("2023-12-31 21:30:00".into())
+    );
+
+    let diff = query_value(&db, "SELECT UNIXEPOCH(DATETIME('now', 'localtime')) - UNIXEPOCH('now')");
+    assert!(matches!(diff, Value::Integer(d) if (19_799..=19_800).contains(&d)));
+}
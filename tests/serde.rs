@@ -0,0 +1,41 @@
+#![cfg(feature = "serde")]
+
+use horizon::serde_horizon::from_row;
+use horizon::Database;
+use serde::Deserialize;
+use tempfile::TempDir;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct User {
+    id: i64,
+    name: String,
+    email: Option<String>,
+}
+
+#[test]
+fn query_rows_into_structs() {
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("test.hdb")).unwrap();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT)").unwrap();
+    db.execute("INSERT INTO users VALUES (1, 'Alice', 'alice@x.com'), (2, 'Bob', NULL)").unwrap();
+
+    let users: Vec<User> = db
+        .query("SELECT ID, NAME, EMAIL FROM users ORDER BY id")
+        .unwrap()
+        .rows
+        .iter()
+        .map(from_row)
+        .collect::<horizon::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        users,
+        vec![
+            User { id: 1, name: "Alice".into(), email: Some("alice@x.com".into()) },
+            User { id: 2, name: "Bob".into(), email: None },
+        ]
+    );
+
+    let (count, max_id): (i32, i64) =
+        from_row(&db.query("SELECT COUNT(*), MAX(id) FROM users").unwrap().rows[0]).unwrap();
+    assert_eq!((count, max_id), (2, 2));
+}